@@ -0,0 +1,341 @@
+//! C API: `arib2bdnxml_convert` runs one conversion in-process through the arib2bdnxml library's
+//! `Converter`, for pipelines that are not written in Rust. The header is include/arib2bdnxml.h,
+//! generated by cbindgen (cbindgen.toml).
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use arib2bdnxml::{
+    with_handler, ConversionReport, ConvertOptions, Converter, DiagnosticHandler, Level,
+};
+
+/// The conversion succeeded.
+pub const ARIB2BDNXML_OK: i32 = 0;
+/// The conversion failed; see `arib2bdnxml_last_error`.
+pub const ARIB2BDNXML_FAILED: i32 = 1;
+/// The options were rejected before anything was opened.
+pub const ARIB2BDNXML_INVALID: i32 = 2;
+/// The library panicked; the panic was caught and the message kept as the last error.
+pub const ARIB2BDNXML_PANIC: i32 = 3;
+
+/// Message levels passed to `MessageCallback`.
+pub const ARIB2BDNXML_LEVEL_INFO: i32 = 0;
+pub const ARIB2BDNXML_LEVEL_WARNING: i32 = 1;
+pub const ARIB2BDNXML_LEVEL_ERROR: i32 = 2;
+
+/// Progress callback: the number of events written so far and the in-TC of the latest one
+/// (valid only during the call).
+///
+/// It is called synchronously from the thread doing the work, which today is the thread that
+/// called `arib2bdnxml_convert`; do not rely on that, and do not call back into this library
+/// from it.
+pub type ProgressCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, events: u64, in_tc: *const c_char)>;
+
+/// Message callback: an `ARIB2BDNXML_LEVEL_*` and one log line or warning, without a
+/// `Warning: ` label (valid only during the call). Called from the threads doing the work, as
+/// `ProgressCallback` is; with --jobs, from several at once.
+pub type MessageCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, level: i32, message: *const c_char)>;
+
+/// Options of one conversion, as the library's `ConvertOptions`; every other option keeps its
+/// command-line default. Strings are NUL-terminated UTF-8; NULL leaves an option unset.
+#[repr(C)]
+pub struct Options {
+    /// Input file (.ts, .m2ts, .mkv, .mks); required.
+    pub input: *const c_char,
+    /// Output directory (--output); required.
+    pub output: *const c_char,
+    /// --anamorphic.
+    pub anamorphic: bool,
+    /// libaribcaption options, `key=value,...` (--arib-params).
+    pub arib_params: *const c_char,
+    /// --debug: log the statistics and every output written.
+    pub debug: bool,
+    pub progress: ProgressCallback,
+    /// Passed back to `progress` and `message` unchanged.
+    pub user_data: *mut c_void,
+    /// Receives the log lines and warnings; NULL prints them to stderr as the command line does.
+    pub message: MessageCallback,
+}
+
+/// What a conversion did; filled in whatever the result.
+#[repr(C)]
+pub struct Report {
+    /// Events in the BDN XML, as the library's `ConversionReport::events`; when the conversion
+    /// failed, the events written before it did.
+    pub events: u64,
+    pub elapsed_secs: f64,
+}
+
+/// `Options::user_data`, passed back to the callbacks from whichever thread does the work.
+struct UserData(*mut c_void);
+
+// SAFETY: the pointer is never dereferenced here, only handed to the caller's callback, which
+// the `ProgressCallback` documentation tells not to rely on the calling thread.
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Hands the messages of a conversion to `Options::message`.
+struct CallbackHandler {
+    callback: unsafe extern "C" fn(user_data: *mut c_void, level: i32, message: *const c_char),
+    user_data: UserData,
+}
+
+impl DiagnosticHandler for CallbackHandler {
+    fn message(&self, level: Level, text: &str) {
+        let level = match level {
+            Level::Info => ARIB2BDNXML_LEVEL_INFO,
+            Level::Warning => ARIB2BDNXML_LEVEL_WARNING,
+            Level::Error => ARIB2BDNXML_LEVEL_ERROR,
+        };
+        let text = CString::new(text.replace('\0', " ")).unwrap_or_default();
+        // SAFETY: the caller of `arib2bdnxml_convert` vouches for the callback.
+        unsafe { (self.callback)(self.user_data.get(), level, text.as_ptr()) };
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// A string option; `what` names it in the error.
+///
+/// # Safety
+/// `s` is NULL or a NUL-terminated string.
+unsafe fn c_str(s: *const c_char, what: &str) -> Result<Option<String>, String> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(|s| Some(s.to_string()))
+        .map_err(|_| format!("{} is not valid UTF-8", what))
+}
+
+/// The input, output directory and library options `options` stand for.
+///
+/// # Safety
+/// See `arib2bdnxml_convert`.
+unsafe fn conversion(options: &Options) -> Result<(String, String, ConvertOptions), String> {
+    let input = c_str(options.input, "input")?.unwrap_or_default();
+    if input.is_empty() {
+        return Err("input is not set".to_string());
+    }
+    let Some(output) = c_str(options.output, "output")? else {
+        return Err("output is not set".to_string());
+    };
+    let options = ConvertOptions {
+        anamorphic: options.anamorphic,
+        arib_params: c_str(options.arib_params, "arib_params")?,
+        debug: options.debug,
+    };
+    Ok((input, output, options))
+}
+
+/// Runs the conversion `options` stand for; an `ARIB2BDNXML_*` code when it fails, with the
+/// last error set. `written` counts the events written so far.
+///
+/// # Safety
+/// See `arib2bdnxml_convert`.
+unsafe fn convert(
+    options: *const Options,
+    written: &Arc<AtomicU64>,
+) -> Result<ConversionReport, i32> {
+    let Some(options) = options.as_ref() else {
+        set_last_error("options is NULL");
+        return Err(ARIB2BDNXML_INVALID);
+    };
+    let converter = match conversion(options) {
+        Ok((input, output, convert_options)) => Converter::new(input, output, convert_options),
+        Err(e) => {
+            set_last_error(&e);
+            return Err(ARIB2BDNXML_INVALID);
+        }
+    };
+    let (callback, user_data) = (options.progress, UserData(options.user_data));
+    let counter = Arc::clone(written);
+    let converter = converter.on_progress(move |written, in_tc| {
+        counter.store(written, Ordering::Relaxed);
+        if let Some(callback) = callback {
+            let in_tc = CString::new(in_tc).unwrap_or_default();
+            callback(user_data.get(), written, in_tc.as_ptr());
+        }
+    });
+    let handler = options.message.map(|callback| {
+        Arc::new(CallbackHandler {
+            callback,
+            user_data: UserData(options.user_data),
+        }) as Arc<dyn DiagnosticHandler>
+    });
+    with_handler(handler, || converter.convert()).map_err(|e| {
+        set_last_error(&e.to_string());
+        ARIB2BDNXML_FAILED
+    })
+}
+
+/// Converts one input, writing the BDN XML and images as the command line does. Returns one of
+/// the `ARIB2BDNXML_*` codes; on failure `arib2bdnxml_last_error` describes it. Messages and
+/// warnings go to `Options::message`, or to stderr without one. Panics never cross this boundary.
+///
+/// # Safety
+/// `options` is NULL or points to a valid `Options` whose strings stay valid for the call; `report` is NULL or points to a writable `Report`.
+#[no_mangle]
+pub unsafe extern "C" fn arib2bdnxml_convert(options: *const Options, report: *mut Report) -> i32 {
+    let started = Instant::now();
+    LAST_ERROR.with(|e| *e.borrow_mut() = None);
+    let written = Arc::new(AtomicU64::new(0));
+    let result =
+        catch_unwind(AssertUnwindSafe(|| convert(options, &written))).unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(&format!("internal error: {}", message));
+            Err(ARIB2BDNXML_PANIC)
+        });
+    let (code, events) = match result {
+        Ok(converted) => (ARIB2BDNXML_OK, converted.events as u64),
+        Err(code) => (code, written.load(Ordering::Relaxed)),
+    };
+    if let Some(report) = report.as_mut() {
+        report.events = events;
+        report.elapsed_secs = started.elapsed().as_secs_f64();
+    }
+    code
+}
+
+/// The error of the last failed `arib2bdnxml_convert` on this thread, or NULL. The string is
+/// owned by the library and valid until the next `arib2bdnxml_convert` on this thread.
+#[no_mangle]
+pub extern "C" fn arib2bdnxml_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(std::ptr::null(), |s| s.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(input: &CStr) -> Options {
+        Options {
+            input: input.as_ptr(),
+            output: c"out".as_ptr(),
+            anamorphic: true,
+            arib_params: c"outline_width=2.0".as_ptr(),
+            debug: false,
+            progress: None,
+            user_data: std::ptr::null_mut(),
+            message: None,
+        }
+    }
+
+    fn last_error() -> String {
+        let error = arib2bdnxml_last_error();
+        assert!(!error.is_null());
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_conversion() {
+        let expected = ConvertOptions {
+            anamorphic: true,
+            arib_params: Some("outline_width=2.0".to_string()),
+            debug: false,
+        };
+        assert_eq!(
+            unsafe { conversion(&options(c"rec/-a.ts")) },
+            Ok(("rec/-a.ts".to_string(), "out".to_string(), expected))
+        );
+        assert_eq!(
+            unsafe { conversion(&options(c"")) },
+            Err("input is not set".to_string())
+        );
+        let mut no_output = options(c"a.ts");
+        no_output.output = std::ptr::null();
+        assert_eq!(
+            unsafe { conversion(&no_output) },
+            Err("output is not set".to_string())
+        );
+    }
+
+    #[test]
+    fn test_convert_errors() {
+        let mut report = Report {
+            events: 7,
+            elapsed_secs: -1.0,
+        };
+        assert_eq!(
+            unsafe { arib2bdnxml_convert(std::ptr::null(), &mut report) },
+            ARIB2BDNXML_INVALID
+        );
+        assert_eq!(last_error(), "options is NULL");
+        assert_eq!(report.events, 0);
+        assert!(report.elapsed_secs >= 0.0);
+
+        let code = unsafe { arib2bdnxml_convert(&options(c""), std::ptr::null_mut()) };
+        assert_eq!(code, ARIB2BDNXML_INVALID);
+        assert_eq!(last_error(), "input is not set");
+
+        let missing = c"/nonexistent/arib2bdnxml-capi.ts";
+        let code = unsafe { arib2bdnxml_convert(&options(missing), std::ptr::null_mut()) };
+        assert_eq!(code, ARIB2BDNXML_FAILED);
+        assert_eq!(
+            last_error(),
+            "Input file does not exist: /nonexistent/arib2bdnxml-capi.ts"
+        );
+    }
+
+    unsafe extern "C" fn collect(user_data: *mut c_void, level: i32, message: *const c_char) {
+        let messages = &mut *(user_data as *mut Vec<(i32, String)>);
+        let message = CStr::from_ptr(message).to_string_lossy().into_owned();
+        messages.push((level, message));
+    }
+
+    #[test]
+    fn test_message_callback() {
+        // An empty input: the options are read before opening it fails.
+        let dir = std::env::temp_dir().join(format!("arib2bdnxml-capi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("empty.ts");
+        std::fs::write(&input, b"").unwrap();
+        let input = CString::new(input.to_str().unwrap()).unwrap();
+
+        let mut messages: Vec<(i32, String)> = Vec::new();
+        let mut options = options(&input);
+        let output = CString::new(dir.join("out").to_str().unwrap()).unwrap();
+        options.output = output.as_ptr();
+        options.arib_params = c"bold".as_ptr();
+        options.user_data = &mut messages as *mut _ as *mut c_void;
+        options.message = Some(collect);
+        let code = unsafe { arib2bdnxml_convert(&options, std::ptr::null_mut()) };
+        std::fs::remove_dir_all(&dir).ok();
+        assert_ne!(code, ARIB2BDNXML_OK);
+        assert_eq!(
+            messages.first(),
+            Some(&(
+                ARIB2BDNXML_LEVEL_WARNING,
+                "libaribcaption option 'bold' is not key=value format, skipping".to_string()
+            ))
+        );
+        // The thread is back to the default handler, whatever the outcome.
+        assert!(arib2bdnxml::handler().is_none());
+    }
+}
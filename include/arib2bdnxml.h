@@ -0,0 +1,93 @@
+#ifndef ARIB2BDNXML_H
+#define ARIB2BDNXML_H
+
+/* Generated by cbindgen from capi/src/lib.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The conversion succeeded.
+#define ARIB2BDNXML_OK 0
+
+// The conversion failed; see `arib2bdnxml_last_error`.
+#define ARIB2BDNXML_FAILED 1
+
+// The options were rejected before anything was opened.
+#define ARIB2BDNXML_INVALID 2
+
+// The library panicked; the panic was caught and the message kept as the last error.
+#define ARIB2BDNXML_PANIC 3
+
+// Message levels passed to `MessageCallback`.
+#define ARIB2BDNXML_LEVEL_INFO 0
+
+#define ARIB2BDNXML_LEVEL_WARNING 1
+
+#define ARIB2BDNXML_LEVEL_ERROR 2
+
+// Progress callback: the number of events written so far and the in-TC of the latest one
+// (valid only during the call).
+//
+// It is called synchronously from the thread doing the work, which today is the thread that
+// called `arib2bdnxml_convert`; do not rely on that, and do not call back into this library
+// from it.
+typedef void (*Arib2bdnxmlProgressCallback)(void *user_data, uint64_t events, const char *in_tc);
+
+// Message callback: an `ARIB2BDNXML_LEVEL_*` and one log line or warning, without a
+// `Warning: ` label (valid only during the call). Called from the threads doing the work, as
+// `ProgressCallback` is; with --jobs, from several at once.
+typedef void (*Arib2bdnxmlMessageCallback)(void *user_data, int32_t level, const char *message);
+
+// Options of one conversion, as the library's `ConvertOptions`; every other option keeps its
+// command-line default. Strings are NUL-terminated UTF-8; NULL leaves an option unset.
+typedef struct Arib2bdnxmlOptions {
+  // Input file (.ts, .m2ts, .mkv, .mks); required.
+  const char *input;
+  // Output directory (--output); required.
+  const char *output;
+  // --anamorphic.
+  bool anamorphic;
+  // libaribcaption options, `key=value,...` (--arib-params).
+  const char *arib_params;
+  // --debug: log the statistics and every output written.
+  bool debug;
+  Arib2bdnxmlProgressCallback progress;
+  // Passed back to `progress` and `message` unchanged.
+  void *user_data;
+  // Receives the log lines and warnings; NULL prints them to stderr as the command line does.
+  Arib2bdnxmlMessageCallback message;
+} Arib2bdnxmlOptions;
+
+// What a conversion did; filled in whatever the result.
+typedef struct Arib2bdnxmlReport {
+  // Events in the BDN XML, as the library's `ConversionReport::events`; when the conversion
+  // failed, the events written before it did.
+  uint64_t events;
+  double elapsed_secs;
+} Arib2bdnxmlReport;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Converts one input, writing the BDN XML and images as the command line does. Returns one of
+// the `ARIB2BDNXML_*` codes; on failure `arib2bdnxml_last_error` describes it. Messages and
+// warnings go to `Options::message`, or to stderr without one. Panics never cross this boundary.
+//
+// # Safety
+// `options` is NULL or points to a valid `Options` whose strings stay valid for the call;
+// `report` is NULL or points to a writable `Report`.
+int32_t arib2bdnxml_convert(const Arib2bdnxmlOptions *options, Arib2bdnxmlReport *report);
+
+// The error of the last failed `arib2bdnxml_convert` on this thread, or NULL. The string is
+// owned by the library and valid until the next `arib2bdnxml_convert` on this thread.
+const char *arib2bdnxml_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ARIB2BDNXML_H */
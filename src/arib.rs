@@ -0,0 +1,241 @@
+//! Minimal ARIB STD-B24 caption PES parsing (data groups and data units), used for diagnostics
+//! that libaribcaption does not expose through AVSubtitle, such as DRCS (custom glyph) usage.
+
+use std::fmt;
+
+/// Data unit parameter of 1-byte DRCS pattern data.
+const DATA_UNIT_DRCS_1BYTE: u8 = 0x30;
+/// Data unit parameter of 2-byte DRCS pattern data.
+const DATA_UNIT_DRCS_2BYTE: u8 = 0x31;
+const UNIT_SEPARATOR: u8 = 0x1F;
+/// data_identifier of synchronized PES (captions, 字幕).
+const DATA_ID_SYNCHRONIZED: u8 = 0x80;
+/// data_identifier of asynchronous PES (superimpose, 文字スーパー).
+const DATA_ID_ASYNCHRONOUS: u8 = 0x81;
+
+/// Kind of ARIB caption stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionType {
+    /// Program captions, timed to the video (synchronized PES).
+    Caption,
+    /// Superimposed text such as news flashes (asynchronous PES).
+    Superimpose,
+}
+
+impl fmt::Display for CaptionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CaptionType::Caption => "caption",
+            CaptionType::Superimpose => "superimpose",
+        })
+    }
+}
+
+/// Caption type of a caption PES payload, from its data_identifier; None when it is not ARIB
+/// caption data.
+pub fn caption_type(payload: &[u8]) -> Option<CaptionType> {
+    if *payload.get(1)? != 0xFF {
+        return None;
+    }
+    match *payload.first()? {
+        DATA_ID_SYNCHRONIZED => Some(CaptionType::Caption),
+        DATA_ID_ASYNCHRONOUS => Some(CaptionType::Superimpose),
+        _ => None,
+    }
+}
+
+/// DRCS usage counters for one input.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DrcsStats {
+    /// Caption packets that carried at least one DRCS data unit.
+    pub packets_with_drcs: usize,
+    /// DRCS glyph definitions (character codes) transmitted.
+    pub glyphs: usize,
+}
+
+impl DrcsStats {
+    /// Counts DRCS glyph definitions in one caption packet payload. Malformed data is ignored.
+    pub fn add_packet(&mut self, payload: &[u8]) {
+        let glyphs = count_drcs_glyphs(payload);
+        if glyphs > 0 {
+            self.packets_with_drcs += 1;
+            self.glyphs += glyphs;
+        }
+    }
+}
+
+fn read_u16(b: &[u8], pos: usize) -> Option<usize> {
+    Some(((*b.get(pos)? as usize) << 8) | *b.get(pos + 1)? as usize)
+}
+
+fn read_u24(b: &[u8], pos: usize) -> Option<usize> {
+    Some(((*b.get(pos)? as usize) << 16) | read_u16(b, pos + 1)?)
+}
+
+/// Returns the data unit loop (bytes after data_unit_loop_length) of a caption data group.
+fn data_units(payload: &[u8]) -> Option<&[u8]> {
+    // Synchronized PES: data_identifier 0x80 (or asynchronous 0x81), private_stream_id 0xFF.
+    caption_type(payload)?;
+    let header_len = (*payload.get(2)? & 0x0F) as usize;
+    let group = payload.get(3 + header_len..)?;
+    let group_id = group.first()? >> 2;
+    let group_size = read_u16(group, 3)?;
+    let data = group.get(5..5 + group_size)?;
+
+    let management = group_id & 0x0F == 0;
+    let tmd = data.first()? >> 6;
+    let mut pos = 1;
+    // OTM (caption management) follows TMD 10 only; STM (caption statement) TMD 01 and 10.
+    if tmd == 0b10 || (tmd == 0b01 && !management) {
+        pos += 5;
+    }
+    if management {
+        // Caption management: skip the per-language table.
+        let num_languages = *data.get(pos)? as usize;
+        pos += 1;
+        for _ in 0..num_languages {
+            let dmf = *data.get(pos)? & 0x0F;
+            pos += 1;
+            if (0b1100..=0b1110).contains(&dmf) {
+                pos += 1;
+            }
+            // ISO_639_language_code (3) + Format/TCS/rollup_mode (1).
+            pos += 4;
+        }
+    }
+    let loop_len = read_u24(data, pos)?;
+    data.get(pos + 3..pos + 3 + loop_len)
+}
+
+/// Counts DRCS glyph definitions (NumberOfCode summed over DRCS data units) in a caption PES payload.
+pub fn count_drcs_glyphs(payload: &[u8]) -> usize {
+    let Some(units) = data_units(payload) else {
+        return 0;
+    };
+    let mut glyphs = 0;
+    let mut pos = 0;
+    while pos + 5 <= units.len() {
+        if units[pos] != UNIT_SEPARATOR {
+            break;
+        }
+        let parameter = units[pos + 1];
+        let Some(size) = read_u24(units, pos + 2) else {
+            break;
+        };
+        let body_start = pos + 5;
+        if body_start + size > units.len() {
+            break;
+        }
+        if (parameter == DATA_UNIT_DRCS_1BYTE || parameter == DATA_UNIT_DRCS_2BYTE) && size > 0 {
+            glyphs += units[body_start] as usize;
+        }
+        pos = body_start + size;
+    }
+    glyphs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a caption statement PES payload (data group id 1, TMD free) from data units.
+    fn statement_payload(units: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut loop_bytes = Vec::new();
+        for (param, body) in units {
+            loop_bytes.push(UNIT_SEPARATOR);
+            loop_bytes.push(*param);
+            let n = body.len();
+            loop_bytes.extend_from_slice(&[(n >> 16) as u8, (n >> 8) as u8, n as u8]);
+            loop_bytes.extend_from_slice(body);
+        }
+        let mut data = vec![0x00];
+        let n = loop_bytes.len();
+        data.extend_from_slice(&[(n >> 16) as u8, (n >> 8) as u8, n as u8]);
+        data.extend_from_slice(&loop_bytes);
+        let mut out = vec![0x80, 0xFF, 0xF0];
+        out.push(0x01 << 2);
+        out.push(0x00);
+        out.push(0x00);
+        out.extend_from_slice(&[(data.len() >> 8) as u8, data.len() as u8]);
+        out.extend_from_slice(&data);
+        // CRC_16 (not checked)
+        out.extend_from_slice(&[0, 0]);
+        out
+    }
+
+    #[test]
+    fn test_count_drcs_glyphs() {
+        let text = (0x20, vec![0x41, 0x42]);
+        let drcs2 = (DATA_UNIT_DRCS_2BYTE, vec![3, 0x41, 0x41]);
+        let drcs1 = (DATA_UNIT_DRCS_1BYTE, vec![1, 0x21]);
+        assert_eq!(count_drcs_glyphs(&statement_payload(std::slice::from_ref(&text))), 0);
+        assert_eq!(
+            count_drcs_glyphs(&statement_payload(&[drcs2, text.clone(), drcs1])),
+            4
+        );
+    }
+
+    /// Sets the TMD of a statement payload and inserts the 5-byte STM after it.
+    fn with_stm(mut p: Vec<u8>, tmd: u8) -> Vec<u8> {
+        p[8] = tmd << 6;
+        p.splice(9..9, [0x01, 0x23, 0x45, 0x67, 0x80]);
+        let size = read_u16(&p, 6).unwrap() + 5;
+        p[6..8].copy_from_slice(&[(size >> 8) as u8, size as u8]);
+        p
+    }
+
+    #[test]
+    fn test_count_drcs_glyphs_stm() {
+        let drcs = (DATA_UNIT_DRCS_1BYTE, vec![2, 0x21]);
+        for tmd in [0b01, 0b10] {
+            let p = with_stm(statement_payload(std::slice::from_ref(&drcs)), tmd);
+            assert_eq!(count_drcs_glyphs(&p), 2, "TMD {:02b}", tmd);
+        }
+        // Management groups carry no time for TMD 01.
+        let mut p = statement_payload(&[]);
+        p[3] = 0;
+        p[8] = 0b01 << 6;
+        p.splice(9..9, [0]);
+        let size = read_u16(&p, 6).unwrap() + 1;
+        p[6..8].copy_from_slice(&[(size >> 8) as u8, size as u8]);
+        assert_eq!(data_units(&p), Some(&[][..]));
+    }
+
+    #[test]
+    fn test_count_drcs_glyphs_malformed() {
+        assert_eq!(count_drcs_glyphs(&[]), 0);
+        assert_eq!(count_drcs_glyphs(&[0x80, 0xFF]), 0);
+        let mut p = statement_payload(&[(DATA_UNIT_DRCS_1BYTE, vec![2, 0x21])]);
+        p.truncate(p.len() - 6);
+        assert_eq!(count_drcs_glyphs(&p), 0);
+        let mut p = statement_payload(&[(DATA_UNIT_DRCS_1BYTE, vec![2, 0x21])]);
+        p[0] = 0x00;
+        assert_eq!(count_drcs_glyphs(&p), 0);
+    }
+
+    #[test]
+    fn test_caption_type() {
+        let mut p = statement_payload(&[(0x20, vec![0x41])]);
+        assert_eq!(caption_type(&p), Some(CaptionType::Caption));
+        p[0] = DATA_ID_ASYNCHRONOUS;
+        assert_eq!(caption_type(&p), Some(CaptionType::Superimpose));
+        assert_eq!(caption_type(&[0x80]), None);
+        assert_eq!(caption_type(&[0x80, 0x00]), None);
+        assert_eq!(caption_type(&[0x82, 0xFF]), None);
+        assert_eq!(CaptionType::Superimpose.to_string(), "superimpose");
+    }
+
+    #[test]
+    fn test_drcs_stats() {
+        let mut s = DrcsStats::default();
+        s.add_packet(&statement_payload(&[(0x20, vec![0x41])]));
+        s.add_packet(&statement_payload(&[(DATA_UNIT_DRCS_2BYTE, vec![2, 0x41])]));
+        assert_eq!(
+            s,
+            DrcsStats {
+                packets_with_drcs: 1,
+                glyphs: 2
+            }
+        );
+    }
+}
@@ -1,15 +1,273 @@
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bitmap::second_image_filename;
+use crate::stl::civil_from_days;
+use crate::validate::{attr, child, is_xml_char, parse, validate_bdn};
 
 /// BDN metadata (frame rate, format). Written to BDN XML Description/Format.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BdnInfo {
     pub fps: f64,
     pub video_format: String,
+    /// Default fades, written as DefaultFadeIn/DefaultFadeOut on the Description Events element
+    /// (--bdn-extensions; not part of strict 0.93).
+    pub fade: Option<Fade>,
+    /// Canvas (width, height) for --emit-normalized: written as a Canvas comment in the
+    /// Description and a Normalized comment before each Graphic. None writes neither.
+    pub normalized: Option<(i32, i32)>,
+    /// Text of the --provenance comment written after the XML declaration; kept as read so
+    /// rewriting a file preserves it.
+    pub provenance: Option<String>,
+    /// Line endings and indentation of the written XML; also kept as read.
+    pub xml_style: XmlStyle,
+}
+
+/// Line ending of the written XML (--xml-line-endings).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+/// One level of indentation of the written XML (--xml-indent).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(u8),
+    Tab,
+}
+
+/// Layout of the written XML. Attributes are always double-quoted and in the same order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XmlStyle {
+    pub line_ending: LineEnding,
+    pub indent: Indent,
+}
+
+impl Default for XmlStyle {
+    fn default() -> Self {
+        XmlStyle {
+            line_ending: LineEnding::Lf,
+            indent: Indent::Spaces(2),
+        }
+    }
+}
+
+impl XmlStyle {
+    /// The style of a BDN XML document: its line endings and the indentation of `<Description>`.
+    fn detect(xml: &str) -> Self {
+        let line_ending = match xml.contains("\r\n") {
+            true => LineEnding::Crlf,
+            false => LineEnding::Lf,
+        };
+        let indent = xml
+            .lines()
+            .find_map(|line| {
+                let text = line.trim_start();
+                let indent = &line[..line.len() - text.len()];
+                text.starts_with("<Description").then_some(indent)
+            })
+            .and_then(|lead| match lead.bytes().next() {
+                Some(b'\t') => Some(Indent::Tab),
+                _ if lead.bytes().all(|b| b == b' ') => {
+                    Some(Indent::Spaces(lead.len().min(8) as u8))
+                }
+                _ => None,
+            })
+            .unwrap_or(XmlStyle::default().indent);
+        XmlStyle {
+            line_ending,
+            indent,
+        }
+    }
+}
+
+pub fn parse_line_ending(s: &str) -> Result<LineEnding, String> {
+    match s.trim() {
+        "lf" => Ok(LineEnding::Lf),
+        "crlf" => Ok(LineEnding::Crlf),
+        _ => Err(format!("invalid line ending: {} (expected lf or crlf)", s)),
+    }
+}
+
+/// `tab`, or a number of spaces from 0 to 8.
+pub fn parse_indent(s: &str) -> Result<Indent, String> {
+    let s = s.trim();
+    if s == "tab" {
+        return Ok(Indent::Tab);
+    }
+    match s.parse::<u8>() {
+        Ok(n) if n <= 8 => Ok(Indent::Spaces(n)),
+        _ => Err(format!(
+            "invalid indent: {} (expected tab or 0 to 8 spaces)",
+            s
+        )),
+    }
+}
+
+/// Builds an XML document line by line in an `XmlStyle`.
+struct XmlLines {
+    out: String,
+    style: XmlStyle,
+}
+
+impl XmlLines {
+    fn new(style: XmlStyle) -> Self {
+        XmlLines {
+            out: String::new(),
+            style,
+        }
+    }
+
+    /// Writes one line, indented `depth` levels.
+    fn line(&mut self, depth: usize, text: &str) {
+        for _ in 0..depth {
+            match self.style.indent {
+                Indent::Spaces(n) => self.out.push_str(&" ".repeat(n as usize)),
+                Indent::Tab => self.out.push('\t'),
+            }
+        }
+        self.out.push_str(text);
+        self.out.push_str(match self.style.line_ending {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        });
+    }
+}
+
+/// Where a BDN XML came from (--provenance).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// Tool name and version.
+    pub tool: String,
+    pub input: String,
+    pub created: SystemTime,
+    pub fps: f64,
+    /// Canvas the captions were decoded on.
+    pub canvas: String,
+    /// Effective libaribcaption options.
+    pub decoder_options: Vec<(String, String)>,
+    /// --brightness and --gamma; written only when they change the captions.
+    pub tone: (f64, f64),
+}
+
+impl Provenance {
+    /// Text of the comment: a `Provenance` line, then one `Key: value` line per field and per
+    /// decoder option (sorted by name). Values are made safe for a comment.
+    pub fn comment_text(&self) -> String {
+        let mut options = self.decoder_options.clone();
+        options.sort();
+        let mut lines = vec![
+            "Provenance".to_string(),
+            format!("  Tool: {}", xml_comment_text(&self.tool)),
+            format!("  Input: {}", xml_comment_text(&self.input)),
+            format!("  Created: {}", utc_timestamp(self.created)),
+            format!("  FrameRate: {}", format_fps(self.fps)),
+            format!("  Canvas: {}", xml_comment_text(&self.canvas)),
+        ];
+        if self.tone != (1.0, 1.0) {
+            lines.push(format!(
+                "  Tone: brightness={} gamma={}",
+                self.tone.0, self.tone.1
+            ));
+        }
+        lines.extend(options.iter().map(|(k, v)| {
+            format!(
+                "  libaribcaption: {}={}",
+                xml_comment_text(k),
+                xml_comment_text(v)
+            )
+        }));
+        lines.join("\n")
+    }
+}
+
+/// `time` as an ISO 8601 UTC timestamp to the second.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64;
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let seconds = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The text of the --provenance comment before the root element of `xml`, if any.
+fn parse_provenance(xml: &str) -> Option<String> {
+    let prolog = &xml[..xml.find("<BDN")?];
+    prolog.split("<!--").skip(1).find_map(|c| {
+        let text = c.split_once("-->")?.0.trim();
+        let lines: Vec<&str> = text.lines().collect();
+        text.starts_with("Provenance").then(|| lines.join("\n"))
+    })
+}
+
+/// Position and size of a Graphic as fractions of the canvas (--emit-normalized).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normalized {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Normalized {
+    pub fn of(x: i32, y: i32, width: i32, height: i32, canvas: (i32, i32)) -> Self {
+        let (cw, ch) = (canvas.0.max(1) as f64, canvas.1.max(1) as f64);
+        Normalized {
+            x: x as f64 / cw,
+            y: y as f64 / ch,
+            width: width as f64 / cw,
+            height: height as f64 / ch,
+        }
+    }
+
+    fn comment(&self) -> String {
+        format!(
+            "Normalized X=\"{:.6}\" Y=\"{:.6}\" Width=\"{:.6}\" Height=\"{:.6}\"",
+            self.x, self.y, self.width, self.height
+        )
+    }
+
+    /// Reads the text of a comment written by `comment`.
+    pub fn parse_comment(text: &str) -> Option<Self> {
+        let [x, y, width, height] =
+            comment_fields(text, "Normalized", ["X", "Y", "Width", "Height"])?;
+        Some(Normalized {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+/// Values of `names` from a comment of the form `<keyword> Name="value" ...`.
+fn comment_fields<const N: usize>(text: &str, keyword: &str, names: [&str; N]) -> Option<[f64; N]> {
+    let fields = text.trim().strip_prefix(keyword)?;
+    let mut values = [0.0; N];
+    for (value, name) in values.iter_mut().zip(names) {
+        let (_, rest) = fields.split_once(&format!(" {}=\"", name))?;
+        *value = rest.split('"').next()?.parse().ok()?;
+    }
+    Some(values)
+}
+
+/// Fade-in and fade-out lengths in frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fade {
+    pub in_frames: u32,
+    pub out_frames: u32,
 }
 
 /// A single subtitle event (one graphic with InTC/OutTC and PNG reference).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SubtitleEvent {
     pub in_tc: String,
     pub out_tc: String,
@@ -18,6 +276,27 @@ pub struct SubtitleEvent {
     pub y: i32,
     pub width: i32,
     pub height: i32,
+    /// Written as Forced="True" (--forced-ranges).
+    pub forced: bool,
+    /// Right-hand part of a caption split at a transparent gap (--split-horizontal), or a
+    /// colliding caption merged into this event (--merge-colliding).
+    pub second: Option<Graphic>,
+}
+
+/// An additional `<Graphic>` of an event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Graphic {
+    pub png_file: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// True if the event's midpoint falls inside any of the ranges (bounds inclusive).
+pub fn midpoint_in_ranges(start: f64, end: f64, ranges: &[(f64, f64)]) -> bool {
+    let mid = (start + end) / 2.0;
+    ranges.iter().any(|&(s, e)| s <= mid && mid <= e)
 }
 
 /// Converts seconds to BDN timecode HH:MM:SS:FF (frame index 0..fps_int-1).
@@ -47,6 +326,313 @@ pub fn time_to_tc(seconds: f64, fps: f64) -> String {
     format_tc(hours, minutes, secs, frames)
 }
 
+/// Converts a BDN timecode HH:MM:SS:FF back to seconds (inverse of `time_to_tc`).
+pub fn tc_to_seconds(tc: &str, fps: f64) -> anyhow::Result<f64> {
+    let parts: Vec<&str> = tc.split(':').collect();
+    if parts.len() != 4 {
+        anyhow::bail!("invalid timecode: {}", tc);
+    }
+    let mut fields = [0i64; 4];
+    for (f, p) in fields.iter_mut().zip(&parts) {
+        *f = p
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid timecode: {}", tc))?;
+    }
+    let fps_int = fps.round() as i64;
+    if fps_int <= 0 {
+        anyhow::bail!("invalid frame rate: {}", fps);
+    }
+    let [h, m, s, f] = fields;
+    let total_frames = ((h * 60 + m) * 60 + s) * fps_int + f;
+    Ok(total_frames as f64 / fps)
+}
+
+/// What OutTC means (--outtc-semantics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutTcSemantics {
+    /// First frame the caption is gone (BDN spec).
+    #[default]
+    Exclusive,
+    /// Last frame the caption is shown.
+    Inclusive,
+}
+
+pub fn parse_outtc_semantics(s: &str) -> Result<OutTcSemantics, String> {
+    match s.trim() {
+        "exclusive" => Ok(OutTcSemantics::Exclusive),
+        "inclusive" => Ok(OutTcSemantics::Inclusive),
+        _ => Err(format!(
+            "invalid OutTC semantics: {} (expected exclusive or inclusive)",
+            s
+        )),
+    }
+}
+
+/// Converts a timecode HH:MM:SS:FF to a frame count at the nominal (rounded) frame rate.
+pub fn tc_to_frames(tc: &str, fps: f64) -> Option<i64> {
+    let fields: Vec<i64> = tc.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let [h, m, s, f] = fields[..] else {
+        return None;
+    };
+    Some(((h * 60 + m) * 60 + s) * fps.round() as i64 + f)
+}
+
+/// Converts a frame count back to HH:MM:SS:FF (inverse of `tc_to_frames`).
+pub fn frames_to_tc(frames: i64, fps: f64) -> String {
+    let fps_int = (fps.round() as i64).max(1);
+    let secs = frames.max(0) / fps_int;
+    format_tc(
+        (secs / 3600) as i32,
+        (secs / 60 % 60) as i32,
+        (secs % 60) as i32,
+        (frames.max(0) % fps_int) as i32,
+    )
+}
+
+/// Start of the program timeline (--tc-offset), such as 01:00:00:00.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcOffset {
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    pub frames: u32,
+}
+
+/// Parses --tc-offset: HH:MM:SS:FF. The frame field is checked against the frame rate later.
+pub fn parse_tc_offset(s: &str) -> Result<TcOffset, String> {
+    let fields: Option<Vec<u32>> = s.trim().split(':').map(|p| p.parse().ok()).collect();
+    match fields.as_deref() {
+        Some(&[hours, minutes, seconds, frames]) if minutes < 60 && seconds < 60 => Ok(TcOffset {
+            hours,
+            minutes,
+            seconds,
+            frames,
+        }),
+        _ => Err(format!(
+            "invalid timecode offset: {} (expected HH:MM:SS:FF)",
+            s
+        )),
+    }
+}
+
+impl TcOffset {
+    /// The offset in frames at the nominal rate of `fps`, as timecodes count them.
+    pub fn to_frames(self, fps: f64) -> anyhow::Result<i64> {
+        let fps_int = fps.round() as i64;
+        if self.frames as i64 >= fps_int {
+            anyhow::bail!(
+                "--tc-offset frame field {} is not below the frame rate ({} fps).",
+                self.frames,
+                fps_int
+            );
+        }
+        let seconds = (self.hours as i64 * 60 + self.minutes as i64) * 60 + self.seconds as i64;
+        Ok(seconds * fps_int + self.frames as i64)
+    }
+}
+
+/// Moves a timecode by `frames`. Whole frames are added to the frame count, so a long program
+/// does not drift at 23.976 or 29.97.
+pub fn offset_tc(tc: &str, frames: i64, fps: f64) -> String {
+    match tc_to_frames(tc, fps) {
+        Some(n) => frames_to_tc(n + frames, fps),
+        None => tc.to_string(),
+    }
+}
+
+/// Moves the InTC and OutTC of `event` by `frames` (`offset_tc`).
+pub fn offset_event(event: &SubtitleEvent, frames: i64, fps: f64) -> SubtitleEvent {
+    SubtitleEvent {
+        in_tc: offset_tc(&event.in_tc, frames, fps),
+        out_tc: offset_tc(&event.out_tc, frames, fps),
+        ..event.clone()
+    }
+}
+
+/// Rewrites OutTCs for `semantics` once the events are rounded to frames. Inclusive OutTC is
+/// one frame earlier than exclusive, so back-to-back events no longer share their boundary
+/// frame. Every event keeps at least one displayed frame: exclusive OutTC stays after InTC,
+/// inclusive OutTC not before it.
+pub fn apply_outtc_semantics(events: &mut [SubtitleEvent], semantics: OutTcSemantics, fps: f64) {
+    for event in events {
+        if let Some(out_tc) = outtc_with_semantics(&event.in_tc, &event.out_tc, semantics, fps) {
+            event.out_tc = out_tc;
+        }
+    }
+}
+
+/// The OutTC of one in/out pair under `semantics`; None when a timecode does not parse.
+pub fn outtc_with_semantics(
+    in_tc: &str,
+    out_tc: &str,
+    semantics: OutTcSemantics,
+    fps: f64,
+) -> Option<String> {
+    let (in_frame, out_frame) = (tc_to_frames(in_tc, fps)?, tc_to_frames(out_tc, fps)?);
+    let out_frame = match semantics {
+        OutTcSemantics::Exclusive => out_frame.max(in_frame + 1),
+        OutTcSemantics::Inclusive => (out_frame - 1).max(in_frame),
+    };
+    Some(frames_to_tc(out_frame, fps))
+}
+
+/// Default --snap-gaps: one frame, the error of rounding millisecond display times to frames.
+pub const DEFAULT_SNAP_GAPS: u32 = 1;
+
+/// Makes transitions of at most `max_frames` frames seamless (--snap-gaps): a gap between
+/// consecutive events is closed by extending the earlier OutTC to the next InTC, an overlap by
+/// ending the earlier event there instead. Works on exclusive OutTCs, before
+/// `apply_outtc_semantics`; 0 disables. Returns the number of transitions changed.
+pub fn snap_gaps(events: &mut [SubtitleEvent], max_frames: u32, fps: f64) -> usize {
+    let mut snapped = 0;
+    for i in 1..events.len() {
+        let (Some(start), Some(end), Some(next)) = (
+            tc_to_frames(&events[i - 1].in_tc, fps),
+            tc_to_frames(&events[i - 1].out_tc, fps),
+            tc_to_frames(&events[i].in_tc, fps),
+        ) else {
+            continue;
+        };
+        let distance = (next - end).unsigned_abs();
+        // An overlap is never trimmed to nothing.
+        if distance == 0 || distance > max_frames as u64 || next <= start {
+            continue;
+        }
+        events[i - 1].out_tc = events[i].in_tc.clone();
+        snapped += 1;
+    }
+    snapped
+}
+
+/// `event` cut to the `start`..`end` seconds of the timeline (--start/--end); None when it is not
+/// shown within them. With `rebase`, moved so `start` becomes 00:00:00:00. OutTCs are read and
+/// written with `semantics`; an event whose timecodes do not parse is kept as it is.
+pub fn clip_event(
+    event: &SubtitleEvent,
+    (start, end): (f64, f64),
+    rebase: bool,
+    semantics: OutTcSemantics,
+    fps: f64,
+) -> Option<SubtitleEvent> {
+    let (Some(in_frame), Some(out_frame)) = (
+        tc_to_frames(&event.in_tc, fps),
+        tc_to_frames(&event.out_tc, fps),
+    ) else {
+        return Some(event.clone());
+    };
+    // Exclusive from here on.
+    let inclusive = i64::from(semantics == OutTcSemantics::Inclusive);
+    let out_frame = out_frame + inclusive;
+    let start_frame = (start * fps).round() as i64;
+    let end_frame = if end.is_finite() {
+        (end * fps).round() as i64
+    } else {
+        i64::MAX
+    };
+    if out_frame <= start_frame || in_frame >= end_frame {
+        return None;
+    }
+    let shift = if rebase { start_frame } else { 0 };
+    let mut clipped = event.clone();
+    clipped.in_tc = frames_to_tc(in_frame.max(start_frame) - shift, fps);
+    clipped.out_tc = frames_to_tc(out_frame.min(end_frame) - inclusive - shift, fps);
+    Some(clipped)
+}
+
+/// Two consecutive events that rounded to the same InTC and OutTC; players show only one of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collision {
+    /// Index of the later event before the collisions were resolved.
+    pub index: usize,
+    pub in_tc: String,
+    pub out_tc: String,
+    /// Images of the earlier and the later event.
+    pub first: String,
+    pub second: String,
+    /// InTC the later event was moved to; None when it was merged into the earlier one.
+    pub shifted_to: Option<String>,
+}
+
+/// Whether `second` can become the second graphic of `first`: both have a single graphic that
+/// no other event shares (--dedup-png), and the two do not overlap on screen.
+fn can_merge(first: &SubtitleEvent, second: &SubtitleEvent, events: &[SubtitleEvent]) -> bool {
+    let shared = |file: &str| events.iter().filter(|e| e.png_file == file).count() > 1;
+    let apart = first.x + first.width <= second.x
+        || second.x + second.width <= first.x
+        || first.y + first.height <= second.y
+        || second.y + second.height <= first.y;
+    first.second.is_none()
+        && second.second.is_none()
+        && apart
+        && !shared(&first.png_file)
+        && !shared(&second.png_file)
+}
+
+/// Tells apart consecutive events with the same InTC and OutTC. Works on exclusive OutTCs,
+/// after `snap_gaps` and before `apply_outtc_semantics`. The later event starts one frame later
+/// and the earlier one ends there, so InTCs keep increasing: a run of colliding events starts on
+/// consecutive frames, and an event moved onto its OutTC keeps one frame. With `merge` the later
+/// event becomes the second graphic of the earlier one instead when `can_merge` allows; its image
+/// is to be renamed to `second_image_filename` of the earlier one. Returns every collision.
+pub fn resolve_collisions(
+    events: &mut Vec<SubtitleEvent>,
+    merge: bool,
+    fps: f64,
+) -> Vec<Collision> {
+    let input = std::mem::take(events);
+    let mut collisions = Vec::new();
+    // Timing of the previous event as it was built, before it was moved.
+    let mut previous: Option<(&str, &str)> = None;
+    for (index, original) in input.iter().enumerate() {
+        let timing = (original.in_tc.as_str(), original.out_tc.as_str());
+        let mut event = original.clone();
+        let end = tc_to_frames(&event.out_tc, fps);
+        if let (true, Some(last), Some(end)) = (previous == Some(timing), events.last_mut(), end) {
+            let mut collision = Collision {
+                index,
+                in_tc: event.in_tc.clone(),
+                out_tc: event.out_tc.clone(),
+                first: last.png_file.clone(),
+                second: event.png_file.clone(),
+                shifted_to: None,
+            };
+            if merge && can_merge(last, &event, &input) {
+                last.second = Some(Graphic {
+                    png_file: second_image_filename(&last.png_file),
+                    x: event.x,
+                    y: event.y,
+                    width: event.width,
+                    height: event.height,
+                });
+                // The previous timing stays the same.
+                last.forced |= event.forced;
+                collisions.push(collision);
+                continue;
+            }
+            if let Some(start) = tc_to_frames(&last.in_tc, fps) {
+                let start = start + 1;
+                event.in_tc = frames_to_tc(start, fps);
+                event.out_tc = frames_to_tc(end.max(start + 1), fps);
+                last.out_tc = event.in_tc.clone();
+                collision.shifted_to = Some(event.in_tc.clone());
+                collisions.push(collision);
+            }
+        }
+        previous = Some(timing);
+        events.push(event);
+    }
+    collisions
+}
+
+/// `{in_tc} {out_tc}` per event, one per line (--tc-list). Uses the event timecodes verbatim so
+/// the list always agrees with the BDN XML.
+pub fn render_tc_list(events: &[SubtitleEvent]) -> String {
+    events
+        .iter()
+        .map(|e| format!("{} {}\n", e.in_tc, e.out_tc))
+        .collect()
+}
+
 /// Adjusts timestamp so that start_time is treated as 00:00:00.000.
 pub fn adjust_timestamp(timestamp: f64, start_time: f64) -> f64 {
     timestamp - start_time
@@ -84,11 +670,46 @@ fn xml_escape(s: &str) -> String {
     out
 }
 
+/// Escapes `s` for XML, rejecting characters XML cannot represent (e.g. control characters).
+fn xml_value(s: &str) -> anyhow::Result<String> {
+    if let Some(c) = s.chars().find(|&c| !is_xml_char(c)) {
+        anyhow::bail!(
+            "{:?} contains U+{:04X}, which is not allowed in XML",
+            s,
+            c as u32
+        );
+    }
+    Ok(xml_escape(s))
+}
+
+/// `s` made safe inside an XML comment on one line: newlines become " / ", "--" is broken up and
+/// characters XML does not allow are dropped.
+fn xml_comment_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.lines().collect::<Vec<_>>().join(" / ").chars() {
+        if c == '-' && out.ends_with('-') {
+            out.push(' ');
+        }
+        if is_xml_char(c) {
+            out.push(c);
+        }
+    }
+    if out.ends_with('-') {
+        out.push(' ');
+    }
+    out
+}
+
 /// BDN XML format conforms to [BDSup2Sub Supported Formats](https://github.com/mjuhasz/BDSup2Sub/wiki/Supported-Formats#sony-bdn-xml-format).
 /// Writes BDN 0.93 XML to a file.
 pub struct BdnXmlGenerator {
     info: BdnInfo,
     events: Vec<SubtitleEvent>,
+    /// Per event; written as an OCR comment inside the Event when not empty (--ocr-comments).
+    comments: Vec<String>,
+    /// Per event; written as a Source comment inside the Event when not empty
+    /// (--source-time-comments).
+    source_comments: Vec<String>,
 }
 
 impl BdnXmlGenerator {
@@ -96,69 +717,261 @@ impl BdnXmlGenerator {
         BdnXmlGenerator {
             info,
             events: Vec::new(),
+            comments: Vec::new(),
+            source_comments: Vec::new(),
         }
     }
 
     pub fn add_event(&mut self, event: &SubtitleEvent) {
+        self.add_event_with_comment(event, "");
+    }
+
+    /// Adds an event with recognized caption text (--ocr-comments).
+    pub fn add_event_with_comment(&mut self, event: &SubtitleEvent, comment: &str) {
+        self.add_event_with_comments(event, comment, "");
+    }
+
+    /// Adds an event with recognized caption text and the text of its Source comment
+    /// (--source-time-comments); either may be empty.
+    pub fn add_event_with_comments(&mut self, event: &SubtitleEvent, ocr: &str, source: &str) {
         self.events.push(event.clone());
+        self.comments.push(ocr.to_string());
+        self.source_comments.push(source.to_string());
     }
 
-    pub fn write_to_file(&self, path: &str) -> anyhow::Result<()> {
-        let f = File::create(path).map_err(|e| anyhow::anyhow!("Failed to open file: {}: {}", path, e))?;
-        let mut w = BufWriter::new(f);
-
-        writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
-        writeln!(
-            w,
-            "<BDN Version=\"0.93\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:noNamespaceSchemaLocation=\"BD-03-006-0093b BDN File Format.xsd\">"
-        )?;
-        writeln!(w, "  <Description>")?;
-        writeln!(w, "    <Name Title=\"BDN Subtitle\" Content=\"\"/>")?;
-        writeln!(w, "    <Language Code=\"und\"/>")?;
-        writeln!(
-            w,
-            "    <Format VideoFormat=\"{}\" FrameRate=\"{}\" DropFrame=\"False\"/>",
-            self.info.video_format,
-            format_fps(self.info.fps)
-        )?;
+    pub fn write_to_file(&self, path: &Path) -> anyhow::Result<()> {
+        crate::sink::write(path, self.to_xml()?)
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))
+    }
+
+    /// Renders the BDN XML document.
+    pub fn to_xml(&self) -> anyhow::Result<String> {
+        let mut w = XmlLines::new(self.info.xml_style);
+
+        w.line(0, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        if let Some(text) = &self.info.provenance {
+            // Each line escaped again, in case the text was read from a hand-edited file.
+            for (i, line) in text.lines().map(xml_comment_text).enumerate() {
+                w.line(0, &format!("{}{}", if i == 0 { "<!-- " } else { "" }, line));
+            }
+            w.line(0, "-->");
+        }
+        w.line(
+            0,
+            "<BDN Version=\"0.93\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:noNamespaceSchemaLocation=\"BD-03-006-0093b BDN File Format.xsd\">",
+        );
+        w.line(1, "<Description>");
+        w.line(2, "<Name Title=\"BDN Subtitle\" Content=\"\"/>");
+        w.line(2, "<Language Code=\"und\"/>");
+        w.line(
+            2,
+            &format!(
+                "<Format VideoFormat=\"{}\" FrameRate=\"{}\" DropFrame=\"False\"/>",
+                self.info.video_format,
+                format_fps(self.info.fps)
+            ),
+        );
+        if let Some((width, height)) = self.info.normalized {
+            w.line(
+                2,
+                &format!("<!-- Canvas Width=\"{}\" Height=\"{}\" -->", width, height),
+            );
+        }
         let (first_tc, last_tc) = if let (Some(first), Some(last)) = (self.events.first(), self.events.last()) {
             (first.in_tc.as_str(), last.out_tc.as_str())
         } else {
             ("00:00:00:00", "00:00:00:00")
         };
-        writeln!(
-            w,
-            "    <Events Type=\"Graphic\" FirstEventInTC=\"{}\" LastEventOutTC=\"{}\" NumberofEvents=\"{}\"/>",
-            xml_escape(first_tc),
-            xml_escape(last_tc),
-            self.events.len()
-        )?;
-        writeln!(w, "  </Description>")?;
-        writeln!(w, "  <Events>")?;
-
-        for event in &self.events {
-            writeln!(
-                w,
-                "    <Event InTC=\"{}\" OutTC=\"{}\" Forced=\"False\">",
-                xml_escape(&event.in_tc),
-                xml_escape(&event.out_tc)
-            )?;
-            writeln!(
-                w,
-                "      <Graphic Width=\"{}\" Height=\"{}\" X=\"{}\" Y=\"{}\">{}</Graphic>",
-                event.width,
-                event.height,
-                event.x,
-                event.y,
-                xml_escape(&event.png_file)
-            )?;
-            writeln!(w, "    </Event>")?;
-        }
-
-        writeln!(w, "  </Events>")?;
-        writeln!(w, "</BDN>")?;
-        w.flush()?;
-        Ok(())
+        let fade = self.info.fade.map_or(String::new(), |f| {
+            format!(
+                " DefaultFadeIn=\"{}\" DefaultFadeOut=\"{}\"",
+                f.in_frames, f.out_frames
+            )
+        });
+        w.line(
+            2,
+            &format!(
+                "<Events Type=\"Graphic\" FirstEventInTC=\"{}\" LastEventOutTC=\"{}\" NumberofEvents=\"{}\"{}/>",
+                xml_value(first_tc)?,
+                xml_value(last_tc)?,
+                self.events.len(),
+                fade
+            ),
+        );
+        w.line(1, "</Description>");
+        w.line(1, "<Events>");
+
+        let comments = self.comments.iter().zip(&self.source_comments);
+        for (event, (comment, source)) in self.events.iter().zip(comments) {
+            w.line(
+                2,
+                &format!(
+                    "<Event InTC=\"{}\" OutTC=\"{}\" Forced=\"{}\">",
+                    xml_value(&event.in_tc)?,
+                    xml_value(&event.out_tc)?,
+                    if event.forced { "True" } else { "False" }
+                ),
+            );
+            if !source.is_empty() {
+                w.line(3, &format!("<!-- {} -->", xml_comment_text(source)));
+            }
+            if !comment.is_empty() {
+                w.line(3, &format!("<!-- OCR: {} -->", xml_comment_text(comment)));
+            }
+            let first = Graphic {
+                png_file: event.png_file.clone(),
+                x: event.x,
+                y: event.y,
+                width: event.width,
+                height: event.height,
+            };
+            for g in std::iter::once(&first).chain(&event.second) {
+                if let Some(canvas) = self.info.normalized {
+                    let normalized = Normalized::of(g.x, g.y, g.width, g.height, canvas);
+                    w.line(3, &format!("<!-- {} -->", normalized.comment()));
+                }
+                w.line(
+                    3,
+                    &format!(
+                        "<Graphic Width=\"{}\" Height=\"{}\" X=\"{}\" Y=\"{}\">{}</Graphic>",
+                        g.width,
+                        g.height,
+                        g.x,
+                        g.y,
+                        xml_value(&g.png_file)?
+                    ),
+                );
+            }
+            w.line(2, "</Event>");
+        }
+
+        w.line(1, "</Events>");
+        w.line(0, "</BDN>");
+        Ok(w.out)
+    }
+}
+
+/// Reads a BDN XML document written by `BdnXmlGenerator` (or another 0.93 writer) back into
+/// its info and events, keeping --bdn-extensions fades and the --emit-normalized canvas. Events
+/// keep their first two Graphics.
+pub fn parse_bdn(xml: &str) -> anyhow::Result<(BdnInfo, Vec<SubtitleEvent>)> {
+    let problems = validate_bdn(xml, true);
+    if !problems.is_empty() {
+        anyhow::bail!("Invalid BDN XML:\n  {}", problems.join("\n  "));
+    }
+    let root = parse(xml).map_err(|e| anyhow::anyhow!("Invalid BDN XML: {}", e))?;
+    // Validation guarantees the elements and the lexical form of every attribute used below.
+    let value = |element, name| attr(element, name).unwrap_or_default();
+    let number = |element, name| value(element, name).parse::<i32>().unwrap_or(0);
+    let description = child(&root, "Description").unwrap();
+    let format = child(description, "Format").unwrap();
+    let summary = child(description, "Events").unwrap();
+    let fade_in = attr(summary, "DefaultFadeIn").and_then(|v| v.parse().ok());
+    let fade_out = attr(summary, "DefaultFadeOut").and_then(|v| v.parse().ok());
+    let canvas = description.comments.iter().find_map(|c| {
+        comment_fields(c, "Canvas", ["Width", "Height"]).map(|[w, h]| (w as i32, h as i32))
+    });
+    let info = BdnInfo {
+        fps: value(format, "FrameRate").parse().unwrap_or(0.0),
+        video_format: value(format, "VideoFormat").to_string(),
+        fade: (fade_in.is_some() || fade_out.is_some()).then(|| Fade {
+            in_frames: fade_in.unwrap_or(0),
+            out_frames: fade_out.unwrap_or(0),
+        }),
+        normalized: canvas,
+        provenance: parse_provenance(xml),
+        xml_style: XmlStyle::detect(xml),
+    };
+    let events = child(&root, "Events")
+        .unwrap()
+        .children
+        .iter()
+        .map(|event| {
+            let graphic = &event.children[0];
+            let second = event.children.get(1).map(|g| Graphic {
+                png_file: g.text.trim().to_string(),
+                x: number(g, "X"),
+                y: number(g, "Y"),
+                width: number(g, "Width"),
+                height: number(g, "Height"),
+            });
+            SubtitleEvent {
+                in_tc: value(event, "InTC").to_string(),
+                out_tc: value(event, "OutTC").to_string(),
+                png_file: graphic.text.trim().to_string(),
+                x: number(graphic, "X"),
+                y: number(graphic, "Y"),
+                width: number(graphic, "Width"),
+                height: number(graphic, "Height"),
+                forced: value(event, "Forced") == "True",
+                second,
+            }
+        })
+        .collect();
+    Ok((info, events))
+}
+
+/// The --emit-normalized comments of each event of a valid BDN XML document, in Graphic order;
+/// empty for events without them.
+pub fn parse_normalized(xml: &str) -> anyhow::Result<Vec<Vec<Normalized>>> {
+    let root = parse(xml).map_err(|e| anyhow::anyhow!("Invalid BDN XML: {}", e))?;
+    let events = child(&root, "Events").ok_or_else(|| anyhow::anyhow!("Invalid BDN XML"))?;
+    Ok(events
+        .children
+        .iter()
+        .map(|event| {
+            event
+                .comments
+                .iter()
+                .filter_map(|c| Normalized::parse_comment(c))
+                .collect()
+        })
+        .collect())
+}
+
+/// Where a Graphic reference of a BDN XML file points, relative to the XML's directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphicRef {
+    /// Inside the directory: the path below it, normalized.
+    Inside(PathBuf),
+    /// An absolute path (Unix, Windows drive or UNC) or one leaving the directory.
+    External,
+}
+
+/// Classifies a Graphic reference without touching the file system. `/` and `\` both separate
+/// components and drive letters count as absolute on every platform, since the XML may come
+/// from a tool on another one.
+pub fn graphic_ref(file: &str) -> GraphicRef {
+    let bytes = file.as_bytes();
+    let drive = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    if drive || file.starts_with(['/', '\\']) {
+        return GraphicRef::External;
+    }
+    let mut parts = Vec::new();
+    for part in file.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    return GraphicRef::External;
+                }
+            }
+            _ => parts.push(part),
+        }
+    }
+    GraphicRef::Inside(parts.iter().collect())
+}
+
+/// The file a Graphic reference of an XML in `dir` names. External references are an error
+/// unless `allow_external` (--allow-external-refs); callers only ever read those.
+pub fn resolve_graphic(dir: &Path, file: &str, allow_external: bool) -> Result<PathBuf, String> {
+    match graphic_ref(file) {
+        GraphicRef::Inside(path) => Ok(dir.join(path)),
+        GraphicRef::External if allow_external => Ok(dir.join(file)),
+        GraphicRef::External => Err(format!(
+            "graphic {} is outside the XML's directory (--allow-external-refs reads it)",
+            file
+        )),
     }
 }
 
@@ -172,6 +985,707 @@ mod tests {
         assert_eq!(time_to_tc(1.0, 30.0), "00:00:01:00");
     }
 
+    #[test]
+    fn test_tc_to_seconds_round_trip() {
+        for fps in [23.976, 24.0, 25.0, 29.97, 59.94] {
+            for secs in [0.0, 1.0, 61.5, 3599.9, 7200.0] {
+                let tc = time_to_tc(secs, fps);
+                let back = tc_to_seconds(&tc, fps).unwrap();
+                assert_eq!(time_to_tc(back, fps), tc);
+            }
+        }
+        assert!(tc_to_seconds("00:00:01", 30.0).is_err());
+        assert!(tc_to_seconds("00:00:xx:00", 30.0).is_err());
+    }
+
+    #[test]
+    fn test_frames_to_tc_round_trip() {
+        for fps in [23.976, 25.0, 29.97, 59.94] {
+            for frames in [0, 1, 29, 30, 1799, 1800, 107_999, 108_000, 1_000_000] {
+                let tc = frames_to_tc(frames, fps);
+                assert_eq!(tc_to_frames(&tc, fps), Some(frames), "{} at {}", tc, fps);
+            }
+        }
+        assert_eq!(frames_to_tc(108_000, 29.97), "01:00:00:00");
+        assert_eq!(tc_to_frames("00:00:01", 30.0), None);
+    }
+
+    #[test]
+    fn test_tc_offset() {
+        let hour = parse_tc_offset("01:00:00:00").unwrap();
+        assert_eq!(hour.to_frames(23.976).unwrap(), 86_400);
+        assert_eq!(hour.to_frames(25.0).unwrap(), 90_000);
+        assert_eq!(hour.to_frames(29.97).unwrap(), 108_000);
+        assert!(parse_tc_offset("01:00:00").is_err());
+        assert!(parse_tc_offset("00:60:00:00").is_err());
+        assert!(parse_tc_offset("-01:00:00:00").is_err());
+        // The frame field has to fit the rate.
+        let frames = |tc: &str, fps: f64| parse_tc_offset(tc).unwrap().to_frames(fps).ok();
+        assert_eq!(frames("00:00:10:23", 23.976), Some(263));
+        assert_eq!(frames("00:00:00:24", 23.976), None);
+        assert_eq!(frames("00:00:00:24", 25.0), Some(24));
+        assert_eq!(frames("00:00:00:25", 25.0), None);
+        assert_eq!(frames("00:00:00:29", 29.97), Some(29));
+        assert_eq!(frames("00:00:00:30", 29.97), None);
+    }
+
+    #[test]
+    fn test_offset_event() {
+        let hour = parse_tc_offset("01:00:00:00").unwrap();
+        let shift = |fps: f64, in_tc: &str, out_tc: &str| {
+            let shifted = offset_event(&timed(in_tc, out_tc), hour.to_frames(fps).unwrap(), fps);
+            (shifted.in_tc, shifted.out_tc)
+        };
+        // The end of a two-hour program: no drift at 29.97.
+        let end = shift(29.97, "01:59:59:29", "02:00:00:00");
+        assert_eq!(end, ("02:59:59:29".into(), "03:00:00:00".into()));
+        let film = shift(23.976, "00:00:00:23", "01:59:59:23");
+        assert_eq!(film, ("01:00:00:23".into(), "02:59:59:23".into()));
+        let pal = shift(25.0, "00:00:00:00", "00:00:01:24");
+        assert_eq!(pal, ("01:00:00:00".into(), "01:00:01:24".into()));
+    }
+
+    fn timed(in_tc: &str, out_tc: &str) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: String::new(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            forced: false,
+            second: None,
+        }
+    }
+
+    fn out_tcs(events: &[(&str, &str)], semantics: OutTcSemantics) -> Vec<String> {
+        let mut events: Vec<SubtitleEvent> = events.iter().map(|(i, o)| timed(i, o)).collect();
+        apply_outtc_semantics(&mut events, semantics, 29.97);
+        events.into_iter().map(|e| e.out_tc).collect()
+    }
+
+    #[test]
+    fn test_outtc_semantics_frame_boundaries() {
+        let events = [
+            // Crosses a second and a minute boundary when moved back one frame.
+            ("00:00:00:10", "00:00:01:00"),
+            ("00:00:59:00", "00:01:00:00"),
+            ("00:59:00:00", "01:00:00:00"),
+        ];
+        assert_eq!(
+            out_tcs(&events, OutTcSemantics::Inclusive),
+            ["00:00:00:29", "00:00:59:29", "00:59:59:29"]
+        );
+        assert_eq!(
+            out_tcs(&events, OutTcSemantics::Exclusive),
+            ["00:00:01:00", "00:01:00:00", "01:00:00:00"]
+        );
+    }
+
+    #[test]
+    fn test_outtc_semantics_back_to_back() {
+        // The first event's exclusive OutTC is the second event's InTC.
+        let events = [
+            ("00:00:01:00", "00:00:02:15"),
+            ("00:00:02:15", "00:00:04:00"),
+        ];
+        let inclusive = out_tcs(&events, OutTcSemantics::Inclusive);
+        assert_eq!(inclusive, ["00:00:02:14", "00:00:03:29"]);
+        assert!(inclusive[0].as_str() < events[1].0);
+    }
+
+    #[test]
+    fn test_outtc_semantics_keeps_one_frame() {
+        let events = [
+            // One frame long.
+            ("00:00:01:00", "00:00:01:01"),
+            // Zero length after rounding.
+            ("00:00:02:00", "00:00:02:00"),
+        ];
+        assert_eq!(
+            out_tcs(&events, OutTcSemantics::Inclusive),
+            ["00:00:01:00", "00:00:02:00"]
+        );
+        assert_eq!(
+            out_tcs(&events, OutTcSemantics::Exclusive),
+            ["00:00:01:01", "00:00:02:01"]
+        );
+    }
+
+    #[test]
+    fn test_snap_gaps() {
+        // (previous end, next start) in seconds, and whether the transition is snapped.
+        let transitions = [
+            ((1.000, 1.040), true),  // one frame apart
+            ((1.000, 1.070), false), // two frames apart
+            ((1.040, 1.000), true),  // one frame of overlap
+            ((1.000, 1.010), false), // the same frame once rounded
+        ];
+        for fps in [24000.0 / 1001.0, 30000.0 / 1001.0] {
+            for ((end, next), expected) in transitions {
+                let mut events = vec![
+                    timed(&time_to_tc(0.5, fps), &time_to_tc(end, fps)),
+                    timed(&time_to_tc(next, fps), &time_to_tc(2.0, fps)),
+                ];
+                let snapped = snap_gaps(&mut events, DEFAULT_SNAP_GAPS, fps);
+                assert_eq!(snapped == 1, expected, "{} {} {}", fps, end, next);
+                if expected {
+                    assert_eq!(events[0].out_tc, events[1].in_tc);
+                }
+            }
+        }
+        // Disabled, and an overlap that would leave the earlier event empty.
+        let fps = 30000.0 / 1001.0;
+        let mut events = vec![
+            timed("00:00:01:00", "00:00:01:01"),
+            timed("00:00:01:02", "00:00:02:00"),
+        ];
+        assert_eq!(snap_gaps(&mut events, 0, fps), 0);
+        let mut events = vec![
+            timed("00:00:01:00", "00:00:01:01"),
+            timed("00:00:01:00", "00:00:02:00"),
+        ];
+        assert_eq!(snap_gaps(&mut events, 1, fps), 0);
+        assert_eq!(events[0].out_tc, "00:00:01:01");
+    }
+
+    /// An event at 29.97 fps from display times in seconds, `width` pixels wide at `x`.
+    fn caption(start: f64, end: f64, png_file: &str, x: i32) -> SubtitleEvent {
+        let fps = 30000.0 / 1001.0;
+        SubtitleEvent {
+            png_file: png_file.to_string(),
+            x,
+            width: 10,
+            ..timed(&time_to_tc(start, fps), &time_to_tc(end, fps))
+        }
+    }
+
+    fn timings(events: &[SubtitleEvent]) -> Vec<(&str, &str)> {
+        events
+            .iter()
+            .map(|e| (e.in_tc.as_str(), e.out_tc.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_collisions_shift() {
+        let fps = 30000.0 / 1001.0;
+        // Three captions 10 ms apart round to the same frames; a one-frame caption collides too.
+        let mut events = vec![
+            caption(10.000, 12.000, "a00000.png", 0),
+            caption(10.010, 12.010, "a00001.png", 0),
+            caption(10.020, 12.005, "a00002.png", 0),
+            caption(15.000, 15.040, "a00003.png", 0),
+            caption(15.010, 15.045, "a00004.png", 0),
+            caption(20.100, 21.100, "a00005.png", 0),
+        ];
+        let collisions = resolve_collisions(&mut events, false, fps);
+        assert_eq!(
+            timings(&events),
+            [
+                ("00:00:10:00", "00:00:10:01"),
+                ("00:00:10:01", "00:00:10:02"),
+                ("00:00:10:02", "00:00:12:00"),
+                ("00:00:15:00", "00:00:15:01"),
+                ("00:00:15:01", "00:00:15:02"),
+                ("00:00:20:02", "00:00:21:02"),
+            ]
+        );
+        let shifted: Vec<(usize, &str, Option<&str>)> = collisions
+            .iter()
+            .map(|c| (c.index, c.first.as_str(), c.shifted_to.as_deref()))
+            .collect();
+        assert_eq!(
+            shifted,
+            [
+                (1, "a00000.png", Some("00:00:10:01")),
+                (2, "a00001.png", Some("00:00:10:02")),
+                (4, "a00003.png", Some("00:00:15:01")),
+            ]
+        );
+        assert_eq!(collisions[0].in_tc, "00:00:10:00");
+        assert_eq!(collisions[0].out_tc, "00:00:12:00");
+
+        // Every event keeps a frame and starts after the previous one, inclusive OutTCs too.
+        apply_outtc_semantics(&mut events, OutTcSemantics::Inclusive, fps);
+        for pair in events.windows(2) {
+            assert!(pair[0].in_tc < pair[1].in_tc);
+            assert!(pair[0].out_tc < pair[1].in_tc);
+        }
+        assert!(events.iter().all(|e| e.in_tc <= e.out_tc));
+    }
+
+    #[test]
+    fn test_resolve_collisions_merge() {
+        let fps = 30000.0 / 1001.0;
+        let mut forced = caption(10.010, 12.010, "a00001.png", 20);
+        forced.forced = true;
+        let mut events = vec![
+            // Side by side: merged.
+            caption(10.000, 12.000, "a00000.png", 0),
+            forced,
+            // Overlapping on screen: shifted.
+            caption(30.000, 31.000, "a00002.png", 0),
+            caption(30.010, 31.010, "a00003.png", 5),
+            // The later image is shared with another event (--dedup-png): shifted.
+            caption(40.000, 41.000, "a00004.png", 0),
+            caption(40.010, 41.010, "a00006.png", 20),
+            caption(45.000, 46.000, "a00006.png", 20),
+        ];
+        let collisions = resolve_collisions(&mut events, true, fps);
+        let resolved: Vec<(usize, bool)> = collisions
+            .iter()
+            .map(|c| (c.index, c.shifted_to.is_some()))
+            .collect();
+        assert_eq!(resolved, [(1, false), (3, true), (5, true)]);
+        assert_eq!(events.len(), 6);
+        assert_eq!(timings(&events)[0], ("00:00:10:00", "00:00:12:00"));
+        assert!(events[0].forced);
+        assert_eq!(
+            events[0].second,
+            Some(Graphic {
+                png_file: "a00000_2.png".to_string(),
+                x: 20,
+                y: 0,
+                width: 10,
+                height: 1,
+            })
+        );
+        assert_eq!(events[2].in_tc, "00:00:30:00");
+        assert_eq!(events[4].in_tc, "00:00:40:00");
+    }
+
+    #[test]
+    fn test_parse_outtc_semantics() {
+        assert_eq!(parse_outtc_semantics("inclusive"), Ok(OutTcSemantics::Inclusive));
+        assert_eq!(parse_outtc_semantics("exclusive"), Ok(OutTcSemantics::Exclusive));
+        assert!(parse_outtc_semantics("both").is_err());
+    }
+
+    #[test]
+    fn test_render_tc_list() {
+        let event = |in_tc: &str, out_tc: &str| SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: String::new(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            forced: false,
+            second: None,
+        };
+        let events = [
+            event("00:00:01:00", "00:00:03:15"),
+            event("01:02:03:04", "01:02:05:00"),
+        ];
+        assert_eq!(
+            render_tc_list(&events),
+            "00:00:01:00 00:00:03:15\n01:02:03:04 01:02:05:00\n"
+        );
+        assert_eq!(render_tc_list(&[]), "");
+    }
+
+    #[test]
+    fn test_midpoint_in_ranges() {
+        let ranges = [(10.0, 20.0), (15.0, 30.0)];
+        assert!(midpoint_in_ranges(12.0, 14.0, &ranges));
+        // Overlapping ranges behave like their union.
+        assert!(midpoint_in_ranges(24.0, 26.0, &ranges));
+        // Midpoint exactly on a boundary counts as inside.
+        assert!(midpoint_in_ranges(8.0, 12.0, &ranges));
+        assert!(midpoint_in_ranges(29.0, 31.0, &ranges));
+        // Overlaps a range but the midpoint is outside.
+        assert!(!midpoint_in_ranges(5.0, 12.0, &ranges));
+        assert!(!midpoint_in_ranges(31.0, 32.0, &ranges));
+        assert!(!midpoint_in_ranges(12.0, 14.0, &[]));
+    }
+
+    fn info(fade: Option<Fade>) -> BdnInfo {
+        BdnInfo {
+            fps: 29.97,
+            video_format: "1080p".to_string(),
+            fade,
+            normalized: None,
+            provenance: None,
+            xml_style: XmlStyle::default(),
+        }
+    }
+
+    fn generator(events: &[SubtitleEvent]) -> BdnXmlGenerator {
+        let mut g = BdnXmlGenerator::new(info(None));
+        for e in events {
+            g.add_event(e);
+        }
+        g
+    }
+
+    fn event(in_tc: &str, out_tc: &str, png_file: &str, forced: bool) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: png_file.to_string(),
+            x: 0,
+            y: 900,
+            width: 1920,
+            height: 120,
+            forced,
+            second: None,
+        }
+    }
+
+    #[test]
+    fn test_generated_xml_is_valid() {
+        let empty = generator(&[]).to_xml().unwrap();
+        assert_eq!(validate_bdn(&empty, false), Vec::<String>::new());
+        let events = [
+            event("00:00:01:00", "00:00:02:00", "a&b<1>00001.png", false),
+            event("00:10:00:29", "01:00:00:00", "字幕00002.png", true),
+        ];
+        let xml = generator(&events).to_xml().unwrap();
+        assert_eq!(validate_bdn(&xml, false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_fade_attributes() {
+        let events = [event("00:00:01:00", "00:00:02:00", "a00001.png", false)];
+        let strict = generator(&events).to_xml().unwrap();
+        assert!(!strict.contains("Fade"), "{}", strict);
+
+        let mut g = BdnXmlGenerator::new(info(Some(Fade {
+            in_frames: 2,
+            out_frames: 3,
+        })));
+        g.add_event(&events[0]);
+        let xml = g.to_xml().unwrap();
+        assert!(xml.contains(
+            r#"<Events Type="Graphic" FirstEventInTC="00:00:01:00" LastEventOutTC="00:00:02:00" NumberofEvents="1" DefaultFadeIn="2" DefaultFadeOut="3"/>"#
+        ), "{}", xml);
+        assert_eq!(validate_bdn(&xml, true), Vec::<String>::new());
+        assert_eq!(validate_bdn(&xml, false).len(), 2);
+    }
+
+    #[test]
+    fn test_normalized_comments() {
+        let mut e = event("00:00:01:00", "00:00:02:00", "a00001.png", false);
+        e.second = Some(Graphic {
+            png_file: "a00001_2.png".to_string(),
+            x: 1440,
+            y: 540,
+            width: 480,
+            height: 540,
+        });
+        let mut info = info(None);
+        info.normalized = Some((1920, 1080));
+        let mut g = BdnXmlGenerator::new(info.clone());
+        g.add_event(&e);
+        let xml = g.to_xml().unwrap();
+        assert!(xml.contains(r#"<!-- Canvas Width="1920" Height="1080" -->"#), "{}", xml);
+        assert!(xml.contains(
+            r#"<!-- Normalized X="0.000000" Y="0.833333" Width="1.000000" Height="0.111111" -->"#
+        ));
+        // Strict 0.93 still validates: comments are not elements.
+        assert_eq!(validate_bdn(&xml, false), Vec::<String>::new());
+
+        let (read, _) = parse_bdn(&xml).unwrap();
+        assert_eq!(read, info);
+        let normalized = parse_normalized(&xml).unwrap();
+        assert_eq!(normalized.len(), 1);
+        assert_eq!(
+            normalized[0][1],
+            Normalized {
+                x: 0.75,
+                y: 0.5,
+                width: 0.25,
+                height: 0.5,
+            }
+        );
+        assert_eq!(normalized[0][0].height, 0.111111);
+
+        let plain = generator(&[e]).to_xml().unwrap();
+        assert!(!plain.contains("<!--"));
+        assert_eq!(parse_normalized(&plain).unwrap(), [Vec::<Normalized>::new()]);
+        assert_eq!(parse_bdn(&plain).unwrap().0.normalized, None);
+        assert_eq!(Normalized::parse_comment("Normalized X=\"1\""), None);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let provenance = Provenance {
+            tool: "arib2bdnxml 0.2.2".to_string(),
+            input: "ep--01.ts".to_string(),
+            created: UNIX_EPOCH + std::time::Duration::from_secs(1_792_152_245),
+            fps: 30000.0 / 1001.0,
+            canvas: "1920x1080".to_string(),
+            decoder_options: vec![
+                ("outline_width".to_string(), "1.5".to_string()),
+                ("font".to_string(), "Rounded M+ 1m\n-".to_string()),
+            ],
+            tone: (1.0, 1.0),
+        };
+        let text = provenance.comment_text();
+        assert_eq!(
+            text,
+            "Provenance\n  Tool: arib2bdnxml 0.2.2\n  Input: ep- -01.ts\n  Created: 2026-10-16T12:04:05Z\n  FrameRate: 29.97\n  Canvas: 1920x1080\n  libaribcaption: font=Rounded M+ 1m / - \n  libaribcaption: outline_width=1.5"
+        );
+        let with = BdnInfo {
+            provenance: Some(text),
+            ..info(None)
+        };
+        let mut g = BdnXmlGenerator::new(with.clone());
+        g.add_event(&event("00:00:01:00", "00:00:02:00", "a00001.png", false));
+        let xml = g.to_xml().unwrap();
+        assert!(xml.starts_with(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- Provenance\n  Tool: arib2bdnxml 0.2.2\n"
+        ));
+        assert!(xml.contains("outline_width=1.5\n-->\n<BDN "), "{}", xml);
+        let comment = parse_provenance(&xml).unwrap();
+        assert!(!comment.contains("--"), "{}", comment);
+        assert_eq!(validate_bdn(&xml, false), Vec::<String>::new());
+        // Read back and rewritten unchanged.
+        let (read, _) = parse_bdn(&xml).unwrap();
+        assert_eq!(read, with);
+        let mut again = BdnXmlGenerator::new(read);
+        again.add_event(&event("00:00:01:00", "00:00:02:00", "a00001.png", false));
+        assert_eq!(again.to_xml().unwrap(), xml);
+
+        // Off by default.
+        let capped = Provenance {
+            tone: (0.9, 1.0),
+            ..provenance
+        };
+        assert!(capped
+            .comment_text()
+            .contains("\n  Canvas: 1920x1080\n  Tone: brightness=0.9 gamma=1\n"));
+
+        let plain = generator(&[]).to_xml().unwrap();
+        assert!(!plain.contains("Provenance"));
+        assert_eq!(parse_bdn(&plain).unwrap().0.provenance, None);
+    }
+
+    #[test]
+    fn test_clip_event() {
+        let fps = 30.0;
+        let exclusive = event("00:00:58:00", "00:01:03:15", "a00001.png", false);
+        let inclusive = event("00:00:58:00", "00:01:03:14", "a00001.png", false);
+        let clip = |range, rebase, semantics| {
+            let e = match semantics {
+                OutTcSemantics::Exclusive => &exclusive,
+                OutTcSemantics::Inclusive => &inclusive,
+            };
+            let clipped = clip_event(e, range, rebase, semantics, fps);
+            clipped.map(|c| format!("{}-{}", c.in_tc, c.out_tc))
+        };
+        let (ex, inc) = (OutTcSemantics::Exclusive, OutTcSemantics::Inclusive);
+        let tcs = |s: &str| Some(s.to_string());
+        // Straddling the start, and the end.
+        let minute = (60.0, 120.0);
+        assert_eq!(clip(minute, false, ex), tcs("00:01:00:00-00:01:03:15"));
+        assert_eq!(clip((0.0, 61.5), false, ex), tcs("00:00:58:00-00:01:01:15"));
+        let open = (60.0, f64::INFINITY);
+        assert_eq!(clip(open, true, ex), tcs("00:00:00:00-00:00:03:15"));
+        // Ends where the range starts, and starts where it ends.
+        assert_eq!(clip((63.5, 120.0), false, ex), None);
+        assert_eq!(clip((0.0, 58.0), false, ex), None);
+        // An inclusive OutTC is the last frame shown.
+        assert_eq!(clip((0.0, 60.0), true, inc), tcs("00:00:58:00-00:00:59:29"));
+        let last_frame = (63.0 + 14.0 / 30.0, 120.0);
+        assert_eq!(clip(last_frame, false, inc), tcs("00:01:03:14-00:01:03:14"));
+    }
+
+    #[test]
+    fn test_ocr_comments() {
+        let events = [
+            event("00:00:01:00", "00:00:02:00", "a00001.png", false),
+            event("00:00:03:00", "00:00:04:00", "a00002.png", false),
+        ];
+        let mut g = BdnXmlGenerator::new(info(None));
+        g.add_event_with_comment(&events[0], "「次は--」\n<終点>-");
+        g.add_event_with_comment(&events[1], "");
+        let xml = g.to_xml().unwrap();
+        assert!(xml.contains(
+            "    <Event InTC=\"00:00:01:00\" OutTC=\"00:00:02:00\" Forced=\"False\">\n      <!-- OCR: 「次は- -」 / <終点>-  -->\n"
+        ), "{}", xml);
+        assert_eq!(xml.matches("<!--").count(), 1);
+        assert_eq!(validate_bdn(&xml, false), Vec::<String>::new());
+        assert_eq!(parse_bdn(&xml).unwrap().1, events);
+
+        // A Source comment (--source-time-comments) goes first, escaped the same way.
+        let mut g = BdnXmlGenerator::new(info(None));
+        g.add_event_with_comments(&events[0], "次は", "Source PTS=\"1.100\" --x");
+        let xml = g.to_xml().unwrap();
+        assert!(xml.contains(
+            "Forced=\"False\">\n      <!-- Source PTS=\"1.100\" - -x -->\n      <!-- OCR: 次は -->\n"
+        ), "{}", xml);
+        assert_eq!(parse_bdn(&xml).unwrap().1, events[..1]);
+    }
+
+    #[test]
+    fn test_xml_style() {
+        let mut e = event("00:00:01:00", "00:00:02:00", "a00001.png", false);
+        e.second = Some(Graphic {
+            png_file: "a00001_2.png".to_string(),
+            x: 1440,
+            y: 900,
+            width: 480,
+            height: 120,
+        });
+        let plain = generator(std::slice::from_ref(&e)).to_xml().unwrap();
+        for line_ending in [LineEnding::Lf, LineEnding::Crlf] {
+            for indent in [Indent::Spaces(2), Indent::Spaces(4), Indent::Tab] {
+                let mut info = info(None);
+                info.xml_style = XmlStyle {
+                    line_ending,
+                    indent,
+                };
+                info.provenance = Some("Provenance\n  Tool: arib2bdnxml".to_string());
+                let mut g = BdnXmlGenerator::new(info.clone());
+                g.add_event_with_comment(&e, "次は");
+                let xml = g.to_xml().unwrap();
+                let lf = xml.replace("\r\n", "\n");
+                // Every line, the provenance comment's included.
+                let crlf = xml.matches("\r\n").count();
+                match line_ending {
+                    LineEnding::Lf => assert_eq!(crlf, 0),
+                    LineEnding::Crlf => assert_eq!(crlf, xml.matches('\n').count()),
+                }
+                let unit = match indent {
+                    Indent::Spaces(n) => " ".repeat(n as usize),
+                    Indent::Tab => "\t".to_string(),
+                };
+                assert!(lf.contains(&format!(
+                    "\n{0}{0}{0}<Graphic Width=\"480\" Height=\"120\" X=\"1440\" Y=\"900\">a00001_2.png</Graphic>\n{0}{0}</Event>\n",
+                    unit
+                )), "{}", xml);
+                assert_eq!(validate_bdn(&xml, false), Vec::<String>::new());
+                // Read back with its style and rewritten byte for byte.
+                let (read, events) = parse_bdn(&xml).unwrap();
+                assert_eq!(read, info);
+                let mut again = BdnXmlGenerator::new(read);
+                again.add_event_with_comment(&events[0], "次は");
+                assert_eq!(again.to_xml().unwrap(), xml);
+            }
+        }
+        // The default style is the one written so far.
+        assert!(plain.contains("\n  <Description>\n    <Name "));
+        assert!(!plain.contains('\r') && !plain.contains('\t'));
+    }
+
+    #[test]
+    fn test_parse_xml_style() {
+        assert_eq!(parse_line_ending("crlf"), Ok(LineEnding::Crlf));
+        assert_eq!(parse_line_ending("lf"), Ok(LineEnding::Lf));
+        assert!(parse_line_ending("cr").is_err());
+        assert_eq!(parse_indent("tab"), Ok(Indent::Tab));
+        assert_eq!(parse_indent("4"), Ok(Indent::Spaces(4)));
+        assert_eq!(parse_indent("0"), Ok(Indent::Spaces(0)));
+        assert!(parse_indent("9").is_err());
+        assert!(parse_indent("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_bdn_round_trip() {
+        let mut split = event("00:10:00:29", "01:00:00:00", "字幕00002.png", true);
+        split.width = 600;
+        split.second = Some(Graphic {
+            png_file: "字幕00002_2.png".to_string(),
+            x: 1500,
+            y: 900,
+            width: 420,
+            height: 120,
+        });
+        let events = [
+            event("00:00:01:00", "00:00:02:00", "a&b<1>00001.png", false),
+            split,
+        ];
+        let fade_in_only = Fade {
+            in_frames: 2,
+            out_frames: 0,
+        };
+        for fade in [None, Some(fade_in_only)] {
+            let mut g = BdnXmlGenerator::new(info(fade));
+            for e in &events {
+                g.add_event(e);
+            }
+            let xml = g.to_xml().unwrap();
+            assert!(xml.contains(
+                r#"<Graphic Width="420" Height="120" X="1500" Y="900">字幕00002_2.png</Graphic>"#
+            ));
+            let (parsed_info, parsed_events) = parse_bdn(&xml).unwrap();
+            assert_eq!(parsed_info, info(fade));
+            assert_eq!(parsed_events, events);
+        }
+        let err = parse_bdn("<BDN/>").unwrap_err().to_string();
+        assert!(err.starts_with("Invalid BDN XML"), "{}", err);
+    }
+
+    #[test]
+    fn test_graphic_ref() {
+        let inside = |parts: &[&str]| GraphicRef::Inside(parts.iter().collect());
+        assert_eq!(graphic_ref("a00001.png"), inside(&["a00001.png"]));
+        assert_eq!(graphic_ref("./png//a.png"), inside(&["png", "a.png"]));
+        assert_eq!(graphic_ref("png/../a.png"), inside(&["a.png"]));
+        assert_eq!(graphic_ref("png\\a.png"), inside(&["png", "a.png"]));
+        for external in [
+            "../a.png",
+            "png/../../a.png",
+            "..\\png\\a.png",
+            "/tmp/a.png",
+            "\\a.png",
+            "C:\\subs\\a.png",
+            "c:/subs/a.png",
+            "D:a.png",
+            "\\\\server\\share\\a.png",
+            "//server/share/a.png",
+            "\\\\?\\C:\\a.png",
+        ] {
+            assert_eq!(graphic_ref(external), GraphicRef::External, "{}", external);
+        }
+    }
+
+    #[test]
+    fn test_resolve_graphic() {
+        let dir = Path::new("out").join("ep01");
+        assert_eq!(
+            resolve_graphic(&dir, "png/./a.png", false),
+            Ok(dir.join("png").join("a.png"))
+        );
+        assert_eq!(
+            resolve_graphic(&dir, "../a.png", false),
+            Err(
+                "graphic ../a.png is outside the XML's directory (--allow-external-refs reads it)"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            resolve_graphic(&dir, "../a.png", true),
+            Ok(dir.join("../a.png"))
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_resolve_graphic_windows() {
+        let dir = Path::new(r"D:\out");
+        assert_eq!(
+            resolve_graphic(dir, r"C:\subs\a.png", true),
+            Ok(PathBuf::from(r"C:\subs\a.png"))
+        );
+        assert_eq!(
+            resolve_graphic(dir, r"\\server\share\a.png", true),
+            Ok(PathBuf::from(r"\\server\share\a.png"))
+        );
+        assert!(resolve_graphic(dir, r"C:\subs\a.png", false).is_err());
+    }
+
+    #[test]
+    fn test_control_characters_rejected() {
+        let bad = event("00:00:01:00", "00:00:02:00", "a\u{1b}b00001.png", false);
+        let err = generator(&[bad]).to_xml().unwrap_err().to_string();
+        assert!(err.contains("U+001B"), "{}", err);
+    }
+
     #[test]
     fn test_xml_escape() {
         assert_eq!(xml_escape("a<b"), "a&lt;b");
@@ -0,0 +1,281 @@
+//! --emit-bdsup2sub-cmd / --run-bdsup2sub: BDSup2Sub (BDN XML + PNG to .sup) with the resolution
+//! and frame rate this run wrote, so the .sup is neither rescaled nor silently retimed.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::bdn::BdnInfo;
+
+/// Tool the scripts run when --run-bdsup2sub does not name one; overridden by $BDSUP2SUB.
+pub const DEFAULT_BDSUP2SUB: &str = "BDSup2Sub.jar";
+
+/// BDSup2Sub `--resolution` for a BDN VideoFormat; `keep` for one it has no name for.
+fn resolution(video_format: &str) -> &'static str {
+    match video_format {
+        "ntsc" | "480i" | "480p" => "ntsc",
+        "pal" | "576i" | "576p" => "pal",
+        "720p" => "720p",
+        "1440x1080" => "1440x1080",
+        "1080p" | "1080i" => "1080p",
+        _ => "keep",
+    }
+}
+
+/// BDSup2Sub `--fps-target` for a frame rate: up to three decimals, trailing zeros dropped
+/// (23.976, 24, 29.97, 59.94).
+fn fps_target(fps: f64) -> String {
+    let s = format!("{:.3}", fps);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// BDSup2Sub arguments converting `<base_name>.xml` to `<base_name>.sup` at the canvas and frame
+/// rate of `info`. The target frame rate equals the XML's, so nothing is retimed.
+pub fn bdsup2sub_args(info: &BdnInfo, base_name: &str) -> Vec<String> {
+    vec![
+        "--resolution".to_string(),
+        resolution(&info.video_format).to_string(),
+        "--fps-target".to_string(),
+        fps_target(info.fps),
+        "--output".to_string(),
+        format!("{}.sup", base_name),
+        format!("{}.xml", base_name),
+    ]
+}
+
+/// Whether `tool` is the BDSup2Sub jar (run with `java -jar`) rather than an executable.
+fn is_jar(tool: &str) -> bool {
+    tool.to_ascii_lowercase().ends_with(".jar")
+}
+
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn bat_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('%', "%%"))
+}
+
+/// POSIX shell script running BDSup2Sub from the directory it is in.
+pub fn render_sh_script(info: &BdnInfo, base_name: &str, tool: &str) -> String {
+    let args: Vec<String> = bdsup2sub_args(info, base_name)
+        .iter()
+        .map(|a| sh_quote(a))
+        .collect();
+    let args = args.join(" ");
+    format!(
+        "#!/bin/sh\n\
+         # Converts {base_name}.xml to .sup with the resolution and frame rate arib2bdnxml wrote.\n\
+         # Set BDSUP2SUB to the BDSup2Sub jar or executable.\n\
+         cd \"$(dirname \"$0\")\" || exit 1\n\
+         tool=\"${{BDSUP2SUB:-{tool}}}\"\n\
+         case \"$tool\" in\n\
+         \x20 *.jar|*.JAR) exec java -jar \"$tool\" {args} ;;\n\
+         \x20 *) exec \"$tool\" {args} ;;\n\
+         esac\n",
+        base_name = base_name,
+        tool = tool.replace(['"', '$', '`', '\\'], ""),
+        args = args
+    )
+}
+
+/// Windows batch file running BDSup2Sub from the directory it is in (CRLF line ends).
+pub fn render_bat_script(info: &BdnInfo, base_name: &str, tool: &str) -> String {
+    let args: Vec<String> = bdsup2sub_args(info, base_name)
+        .iter()
+        .map(|a| bat_quote(a))
+        .collect();
+    let args = args.join(" ");
+    let lines = [
+        "@echo off".to_string(),
+        format!(
+            "rem Converts {}.xml to .sup with the resolution and frame rate arib2bdnxml wrote.",
+            base_name.replace('%', "%%")
+        ),
+        "rem Set BDSUP2SUB to the BDSup2Sub jar or executable.".to_string(),
+        "setlocal".to_string(),
+        "cd /d \"%~dp0\"".to_string(),
+        format!(
+            "if not defined BDSUP2SUB set \"BDSUP2SUB={}\"",
+            tool.replace(['"', '%'], "")
+        ),
+        "if /i \"%BDSUP2SUB:~-4%\"==\".jar\" (".to_string(),
+        format!("  java -jar \"%BDSUP2SUB%\" {}", args),
+        ") else (".to_string(),
+        format!("  \"%BDSUP2SUB%\" {}", args),
+        ")".to_string(),
+        "exit /b %ERRORLEVEL%".to_string(),
+    ];
+    lines.iter().map(|l| format!("{}\r\n", l)).collect()
+}
+
+/// Program and arguments running BDSup2Sub `tool` with `args`.
+fn command_line(tool: &Path, args: &[String]) -> Vec<OsString> {
+    let mut line: Vec<OsString> = if is_jar(&tool.to_string_lossy()) {
+        vec!["java".into(), "-jar".into(), tool.as_os_str().to_owned()]
+    } else {
+        vec![tool.as_os_str().to_owned()]
+    };
+    line.extend(args.iter().map(OsString::from));
+    line
+}
+
+/// Runs BDSup2Sub in `dir` on `<base_name>.xml` and returns the .sup it wrote. Fails when it
+/// cannot be started, exits with an error, or leaves no .sup.
+pub fn run_bdsup2sub(
+    tool: &Path,
+    info: &BdnInfo,
+    dir: &Path,
+    base_name: &str,
+) -> anyhow::Result<PathBuf> {
+    // The tool runs in `dir`; a relative path names a file from here, a bare name is looked up
+    // on PATH.
+    let tool = if tool.components().count() > 1 {
+        std::path::absolute(tool).unwrap_or_else(|_| tool.to_path_buf())
+    } else {
+        tool.to_path_buf()
+    };
+    let sup = dir.join(format!("{}.sup", base_name));
+    // A .sup of an earlier run must not pass for this one.
+    match std::fs::remove_file(&sup) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            anyhow::bail!("Failed to remove file: {}: {}", sup.display(), e)
+        }
+        _ => {}
+    }
+    let line = command_line(&tool, &bdsup2sub_args(info, base_name));
+    let status = Command::new(&line[0])
+        .args(&line[1..])
+        .current_dir(dir)
+        .status()
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to run BDSup2Sub: {}: {}",
+                line[0].to_string_lossy(),
+                e
+            )
+        })?;
+    if !status.success() {
+        anyhow::bail!("BDSup2Sub failed ({}): {}", status, tool.display());
+    }
+    match std::fs::metadata(&sup) {
+        Ok(m) if m.len() > 0 => Ok(sup),
+        _ => anyhow::bail!("BDSup2Sub did not write {}", sup.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdn::XmlStyle;
+
+    fn info(video_format: &str, fps: f64) -> BdnInfo {
+        BdnInfo {
+            fps,
+            video_format: video_format.to_string(),
+            fade: None,
+            normalized: None,
+            provenance: None,
+            xml_style: XmlStyle::default(),
+        }
+    }
+
+    #[test]
+    fn test_bdsup2sub_args() {
+        assert_eq!(
+            bdsup2sub_args(&info("1080p", 30000.0 / 1001.0), "ep01"),
+            [
+                "--resolution",
+                "1080p",
+                "--fps-target",
+                "29.97",
+                "--output",
+                "ep01.sup",
+                "ep01.xml"
+            ]
+        );
+        let flags = |format: &str, fps: f64| {
+            let args = bdsup2sub_args(&info(format, fps), "a");
+            (args[1].clone(), args[3].clone())
+        };
+        assert_eq!(
+            flags("1440x1080", 24000.0 / 1001.0),
+            ("1440x1080".into(), "23.976".into())
+        );
+        assert_eq!(
+            flags("720p", 60000.0 / 1001.0),
+            ("720p".into(), "59.94".into())
+        );
+        assert_eq!(flags("ntsc", 25.0), ("ntsc".into(), "25".into()));
+        assert_eq!(flags("1080i", 24.0), ("1080p".into(), "24".into()));
+        assert_eq!(flags("4k", 50.0), ("keep".into(), "50".into()));
+    }
+
+    #[test]
+    fn test_scripts() {
+        let info = info("1440x1080", 30000.0 / 1001.0);
+        let sh = render_sh_script(&info, "it's", DEFAULT_BDSUP2SUB);
+        assert!(sh.starts_with("#!/bin/sh\n"));
+        assert!(sh.contains("tool=\"${BDSUP2SUB:-BDSup2Sub.jar}\"\n"));
+        assert!(sh.contains(
+            "  *.jar|*.JAR) exec java -jar \"$tool\" '--resolution' '1440x1080' '--fps-target' '29.97' '--output' 'it'\\''s.sup' 'it'\\''s.xml' ;;\n"
+        ));
+        let bat = render_bat_script(&info, "100%", "C:\\tools\\bdsup2sub.exe");
+        assert!(bat.split_inclusive('\n').all(|l| l.ends_with("\r\n")));
+        assert!(bat.contains("set \"BDSUP2SUB=C:\\tools\\bdsup2sub.exe\"\r\n"));
+        assert!(bat.contains(
+            "  \"%BDSUP2SUB%\" \"--resolution\" \"1440x1080\" \"--fps-target\" \"29.97\" \"--output\" \"100%%.sup\" \"100%%.xml\"\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_command_line() {
+        let args = vec!["--output".to_string(), "a.sup".to_string()];
+        assert_eq!(
+            command_line(Path::new("/opt/BDSup2Sub.JAR"), &args),
+            ["java", "-jar", "/opt/BDSup2Sub.JAR", "--output", "a.sup"]
+        );
+        assert_eq!(
+            command_line(Path::new("bdsup2sub"), &args),
+            ["bdsup2sub", "--output", "a.sup"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_bdsup2sub() {
+        let dir =
+            std::env::temp_dir().join(format!("arib2bdnxml-bdsup2sub-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // All written before any runs, so none is open for writing when one is executed.
+        let fake = |name: &str, script: &str| {
+            use std::os::unix::fs::PermissionsExt;
+            let tool = dir.join(name);
+            std::fs::write(&tool, script).unwrap();
+            std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+            tool
+        };
+        // Writes the file named after --output.
+        let writes = fake(
+            "writes",
+            "#!/bin/sh\nwhile [ \"$1\" != --output ]; do shift; done\necho sup > \"$2\"\n",
+        );
+        let silent = fake("silent", "#!/bin/sh\nexit 0\n");
+        let fails = fake("fails", "#!/bin/sh\nexit 2\n");
+        let info = info("1080p", 29.97);
+        assert_eq!(
+            run_bdsup2sub(&writes, &info, &dir, "a").unwrap(),
+            dir.join("a.sup")
+        );
+        // The .sup of the run before does not count.
+        let err = run_bdsup2sub(&silent, &info, &dir, "a")
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("BDSup2Sub did not write"), "{}", err);
+        let err = run_bdsup2sub(&fails, &info, &dir, "a")
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("BDSup2Sub failed"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
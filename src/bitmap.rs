@@ -1,7 +1,8 @@
-//! RGBA bitmap to PNG output (using the png crate).
+//! RGBA bitmap to PNG/TIFF output (using the png and tiff crates).
 
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
 /// RGBA bitmap (stride bytes per row).
 #[derive(Debug, Clone)]
@@ -12,55 +13,822 @@ pub struct BitmapData {
     pub stride: i32,
 }
 
-/// Save bitmap as PNG.
-pub fn save_bitmap_as_png(bitmap: &BitmapData, path: &str) -> anyhow::Result<()> {
+/// Tightly packed straight-alpha RGBA image, as stored in a PNG (`optimize`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaImage {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl RgbaImage {
+    /// Smallest rectangle `(x, y, width, height)` holding every non-transparent pixel; None when
+    /// the image is fully transparent.
+    pub fn content_bounds(&self) -> Option<(u32, u32, u32, u32)> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let opaque = |x: usize, y: usize| self.data[(y * w + x) * 4 + 3] > 0;
+        let top = (0..h).find(|&y| (0..w).any(|x| opaque(x, y)))?;
+        let bottom = (0..h).rev().find(|&y| (0..w).any(|x| opaque(x, y)))?;
+        let left = (0..w).find(|&x| (top..=bottom).any(|y| opaque(x, y)))?;
+        let right = (0..w).rev().find(|&x| (top..=bottom).any(|y| opaque(x, y)))?;
+        Some((
+            left as u32,
+            top as u32,
+            (right - left + 1) as u32,
+            (bottom - top + 1) as u32,
+        ))
+    }
+
+    /// Premultiplied copy, as decoded captions are held.
+    pub fn premultiplied(&self) -> BitmapData {
+        let mut data = self.data.clone();
+        for px in data.chunks_exact_mut(4) {
+            let a = px[3] as u16;
+            for c in &mut px[..3] {
+                *c = ((*c as u16 * a + 127) / 255) as u8;
+            }
+        }
+        BitmapData {
+            data,
+            width: self.width as i32,
+            height: self.height as i32,
+            stride: self.width as i32 * 4,
+        }
+    }
+
+    /// The `width` x `height` rectangle at (x, y); it must lie inside the image.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> RgbaImage {
+        let row = self.width as usize * 4;
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for r in y as usize..(y + height) as usize {
+            let start = r * row + x as usize * 4;
+            data.extend_from_slice(&self.data[start..start + width as usize * 4]);
+        }
+        RgbaImage {
+            data,
+            width,
+            height,
+        }
+    }
+}
+
+/// Image file format for caption bitmaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    /// RGBA TIFF, LZW-compressed, unassociated alpha.
+    Tiff,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 2] = [ImageFormat::Png, ImageFormat::Tiff];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Tiff => "tif",
+        }
+    }
+}
+
+/// Save bitmap in the given format and return the file size in bytes. With `png_auto_gray`,
+/// monochrome PNGs are written as grayscale + alpha.
+pub fn save_bitmap(
+    bitmap: &BitmapData,
+    path: &Path,
+    format: ImageFormat,
+    png_auto_gray: bool,
+) -> anyhow::Result<u64> {
+    match format {
+        ImageFormat::Png => write_png(bitmap, path, png_auto_gray),
+        ImageFormat::Tiff => save_bitmap_as_tiff(bitmap, path),
+    }
+}
+
+/// Tightly packed straight-alpha RGBA rows from a (premultiplied) bitmap.
+fn straight_alpha_rgba(bitmap: &BitmapData) -> anyhow::Result<Vec<u8>> {
     if bitmap.data.is_empty() || bitmap.width <= 0 || bitmap.height <= 0 {
         anyhow::bail!("Invalid bitmap data.");
     }
-    let w = bitmap.width as u32;
-    let h = bitmap.height as u32;
     let stride = bitmap.stride as usize;
     let row_bytes = (bitmap.width as usize) * 4;
 
-    let file = File::create(path)
-        .map_err(|e| anyhow::anyhow!("Failed to open file: {}: {}", path, e))?;
-    let mut out = BufWriter::new(file);
-
-    let mut encoder = png::Encoder::new(&mut out, w, h);
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
-    let mut writer = encoder
-        .write_header()
-        .map_err(|e| anyhow::anyhow!("PNG header write failed: {}", e))?;
-
     let mut image_data = Vec::with_capacity(row_bytes * (bitmap.height as usize));
     for y in 0..(bitmap.height as usize) {
         image_data.extend_from_slice(&bitmap.data[y * stride..y * stride + row_bytes]);
     }
-    // Convert from premultiplied (from compositing) to straight alpha for PNG.
-    // Transparent pixels: ensure R=G=B=0. Opaque/semi: R = R*255/A (and clamp).
     for px in image_data.chunks_exact_mut(4) {
-        let a = px[3];
-        if a == 0 {
-            px[0] = 0;
-            px[1] = 0;
-            px[2] = 0;
+        let straight = straight_pixel([px[0], px[1], px[2], px[3]]);
+        px.copy_from_slice(&straight);
+    }
+    Ok(image_data)
+}
+
+/// Converts a premultiplied (from compositing) pixel to straight alpha.
+/// Transparent pixels: R=G=B=0. Opaque/semi: R = R*255/A (and clamp).
+pub fn straight_pixel(px: [u8; 4]) -> [u8; 4] {
+    let a = px[3];
+    if a == 0 {
+        return [0, 0, 0, 0];
+    }
+    let a16 = a as u16;
+    let unpremultiply = |c: u8| ((c as u16 * 255 + a16 / 2) / a16).min(255) as u8;
+    [unpremultiply(px[0]), unpremultiply(px[1]), unpremultiply(px[2]), a]
+}
+
+/// True when every pixel of straight-alpha RGBA is neutral (R == G == B), so grayscale + alpha
+/// holds it exactly. Stops at the first colored pixel.
+pub fn is_grayscale(rgba: &[u8]) -> bool {
+    rgba.chunks_exact(4).all(|p| p[0] == p[1] && p[1] == p[2])
+}
+
+/// Save bitmap as PNG.
+pub fn save_bitmap_as_png(bitmap: &BitmapData, path: &Path) -> anyhow::Result<u64> {
+    write_png(bitmap, path, false)
+}
+
+/// Writes RGBA, or GrayscaleAlpha when `auto_gray` is set and the bitmap is monochrome
+/// (--png-auto-gray).
+fn write_png(bitmap: &BitmapData, path: &Path, auto_gray: bool) -> anyhow::Result<u64> {
+    write_rgba_png(&straight_rgba_image(bitmap)?, path, auto_gray, false)
+}
+
+/// The straight-alpha image a bitmap is stored as.
+pub fn straight_rgba_image(bitmap: &BitmapData) -> anyhow::Result<RgbaImage> {
+    Ok(RgbaImage {
+        data: straight_alpha_rgba(bitmap)?,
+        width: bitmap.width as u32,
+        height: bitmap.height as u32,
+    })
+}
+
+/// Writes a straight-alpha image as PNG like `write_png`; `best` trades encoding time for the
+/// smallest file (maximum deflate level, adaptive row filters). Returns the file size in bytes.
+pub fn write_rgba_png(
+    image: &RgbaImage,
+    path: &Path,
+    auto_gray: bool,
+    best: bool,
+) -> anyhow::Result<u64> {
+    let png = PngPixels::of(image, auto_gray).encode(|encoder| {
+        if best {
+            encoder.set_compression(png::Compression::Best);
+            encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+        }
+    })?;
+    write_png_bytes(&png, path)
+}
+
+/// Pixel rows in the form they are stored in a PNG.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PngPixels {
+    /// Rows packed at `depth`, without filter bytes.
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub color: png::ColorType,
+    pub depth: png::BitDepth,
+    /// PLTE and tRNS chunks of an indexed image (tRNS may stop before the opaque entries).
+    pub palette: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl PngPixels {
+    /// RGBA, or grayscale + alpha when `auto_gray` is set and the image is monochrome.
+    pub fn of(image: &RgbaImage, auto_gray: bool) -> Self {
+        let (data, color) = if auto_gray && is_grayscale(&image.data) {
+            let gray = image.data.chunks_exact(4).flat_map(|p| [p[0], p[3]]);
+            (gray.collect(), png::ColorType::GrayscaleAlpha)
         } else {
-            let a16 = a as u16;
-            px[0] = ((px[0] as u16 * 255 + a16 / 2) / a16).min(255) as u8;
-            px[1] = ((px[1] as u16 * 255 + a16 / 2) / a16).min(255) as u8;
-            px[2] = ((px[2] as u16 * 255 + a16 / 2) / a16).min(255) as u8;
+            (image.data.clone(), png::ColorType::Rgba)
+        };
+        PngPixels {
+            data,
+            width: image.width,
+            height: image.height,
+            color,
+            depth: png::BitDepth::Eight,
+            palette: None,
         }
     }
-    writer
-        .write_image_data(&image_data)
-        .map_err(|e| anyhow::anyhow!("PNG write failed: {}", e))?;
-    writer.finish().map_err(|e| anyhow::anyhow!("PNG finish: {}", e))?;
-    Ok(())
+
+    /// Encodes the pixels; `configure` chooses compression and row filters.
+    pub fn encode(
+        &self,
+        configure: impl FnOnce(&mut png::Encoder<&mut Vec<u8>>),
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width, self.height);
+        encoder.set_color(self.color);
+        encoder.set_depth(self.depth);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        if let Some((plte, trns)) = &self.palette {
+            encoder.set_palette(plte.as_slice());
+            if !trns.is_empty() {
+                encoder.set_trns(trns.as_slice());
+            }
+        }
+        configure(&mut encoder);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| anyhow::anyhow!("PNG header write failed: {}", e))?;
+        writer
+            .write_image_data(&self.data)
+            .map_err(|e| anyhow::anyhow!("PNG write failed: {}", e))?;
+        writer.finish().map_err(|e| anyhow::anyhow!("PNG finish: {}", e))?;
+        Ok(out)
+    }
+}
+
+/// Writes an encoded PNG to `path` and returns its size in bytes.
+pub fn write_png_bytes(png: &[u8], path: &Path) -> anyhow::Result<u64> {
+    let file = crate::sink::create(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open file: {}: {}", path.display(), e))?;
+    let mut out = BufWriter::new(file);
+    out.write_all(png)
+        .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+    written_size(out, path)
+}
+
+/// Flushes `out` and returns the size of the file it wrote.
+fn written_size(mut out: BufWriter<File>, path: &Path) -> anyhow::Result<u64> {
+    let write_err =
+        |e: std::io::Error| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e);
+    out.flush().map_err(write_err)?;
+    Ok(out.get_ref().metadata().map_err(write_err)?.len())
+}
+
+/// Reads an 8-bit PNG of any color type into straight-alpha RGBA.
+pub fn load_png(path: &Path) -> anyhow::Result<RgbaImage> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open file: {}: {}", path.display(), e))?;
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let png_err =
+        |e: png::DecodingError| anyhow::anyhow!("PNG read failed: {}: {}", path.display(), e);
+    let mut reader = decoder.read_info().map_err(png_err)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(png_err)?;
+    buf.truncate(info.buffer_size());
+    let data = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        // normalize_to_color8 expands palettes.
+        png::ColorType::Indexed => unreachable!(),
+    };
+    Ok(RgbaImage {
+        data,
+        width: info.width,
+        height: info.height,
+    })
+}
+
+/// Save bitmap as an LZW-compressed RGBA TIFF (same straight-alpha conversion as PNG).
+pub fn save_bitmap_as_tiff(bitmap: &BitmapData, path: &Path) -> anyhow::Result<u64> {
+    use tiff::encoder::{colortype::RGBA8, compression::Lzw, TiffEncoder};
+    use tiff::tags::Tag;
+
+    let image_data = straight_alpha_rgba(bitmap)?;
+
+    let file = crate::sink::create(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open file: {}: {}", path.display(), e))?;
+    let mut out = BufWriter::new(file);
+    let tiff_err = |e: tiff::TiffError| anyhow::anyhow!("TIFF write failed: {}", e);
+
+    let mut encoder = TiffEncoder::new(&mut out).map_err(tiff_err)?;
+    let mut image = encoder
+        .new_image_with_compression::<RGBA8, _>(
+            bitmap.width as u32,
+            bitmap.height as u32,
+            Lzw,
+        )
+        .map_err(tiff_err)?;
+    // 2 = unassociated (straight) alpha; without it readers treat the 4th sample as unknown.
+    image
+        .encoder()
+        .write_tag(Tag::ExtraSamples, 2u16)
+        .map_err(tiff_err)?;
+    image.write_data(&image_data).map_err(tiff_err)?;
+    written_size(out, path)
 }
 
-/// Format: base_name + zero-padded 5-digit index + ".png"
-pub fn generate_png_filename(index: usize, base_name: &str) -> String {
-    format!("{}{:05}.png", base_name, index)
+/// Resample a (premultiplied) RGBA bitmap to new_width x new_height with a box filter.
+/// Averaging premultiplied values keeps transparent pixels from darkening edges.
+pub fn scale_bitmap(bitmap: &BitmapData, new_width: i32, new_height: i32) -> BitmapData {
+    let new_width = new_width.max(1);
+    let new_height = new_height.max(1);
+    let (sw, sh) = (bitmap.width.max(0) as usize, bitmap.height.max(0) as usize);
+    let (dw, dh) = (new_width as usize, new_height as usize);
+    let src_stride = bitmap.stride as usize;
+    let mut data = vec![0u8; dw * dh * 4];
+    if sw == 0 || sh == 0 {
+        return BitmapData {
+            data,
+            width: new_width,
+            height: new_height,
+            stride: new_width * 4,
+        };
+    }
+    for dy in 0..dh {
+        let y0 = dy * sh / dh;
+        let y1 = ((dy + 1) * sh / dh).max(y0 + 1).min(sh);
+        for dx in 0..dw {
+            let x0 = dx * sw / dw;
+            let x1 = ((dx + 1) * sw / dw).max(x0 + 1).min(sw);
+            let mut sum = [0u32; 4];
+            for y in y0..y1 {
+                let row = &bitmap.data[y * src_stride..];
+                for x in x0..x1 {
+                    for c in 0..4 {
+                        sum[c] += row[x * 4 + c] as u32;
+                    }
+                }
+            }
+            let n = ((y1 - y0) * (x1 - x0)) as u32;
+            let offset = (dy * dw + dx) * 4;
+            for c in 0..4 {
+                data[offset + c] = ((sum[c] + n / 2) / n) as u8;
+            }
+        }
+    }
+    BitmapData {
+        data,
+        width: new_width,
+        height: new_height,
+        stride: new_width * 4,
+    }
+}
+
+/// Thumbnail of at most max_width pixels wide, keeping aspect ratio.
+/// Returns None when the bitmap is already narrower than max_width.
+pub fn thumbnail(bitmap: &BitmapData, max_width: i32) -> Option<BitmapData> {
+    if max_width <= 0 || bitmap.width <= max_width {
+        return None;
+    }
+    let height = ((bitmap.height as i64 * max_width as i64 + bitmap.width as i64 / 2)
+        / bitmap.width as i64)
+        .max(1) as i32;
+    Some(scale_bitmap(bitmap, max_width, height))
+}
+
+/// Flatten a (premultiplied) RGBA bitmap over an opaque background color.
+pub fn flatten_over(bitmap: &BitmapData, background: [u8; 3]) -> BitmapData {
+    let width = bitmap.width.max(0) as usize;
+    let stride = bitmap.stride as usize;
+    let mut data = Vec::with_capacity(width * bitmap.height.max(0) as usize * 4);
+    for y in 0..bitmap.height.max(0) as usize {
+        for px in bitmap.data[y * stride..y * stride + width * 4].chunks_exact(4) {
+            let inv = 255 - px[3] as u32;
+            for c in 0..3 {
+                let v = px[c] as u32 + (background[c] as u32 * inv + 127) / 255;
+                data.push(v.min(255) as u8);
+            }
+            data.push(255);
+        }
+    }
+    BitmapData {
+        data,
+        width: bitmap.width,
+        height: bitmap.height,
+        stride: bitmap.width * 4,
+    }
+}
+
+/// --brightness/--gamma lookup table for straight-alpha color values:
+/// `255 * brightness * (v / 255)^(1 / gamma)`, so a gamma above 1 lifts mid-tones and a
+/// brightness below 1 caps white.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToneLut {
+    table: [u8; 256],
+}
+
+impl ToneLut {
+    pub fn new(brightness: f64, gamma: f64) -> Self {
+        let mut table = [0u8; 256];
+        for (v, out) in table.iter_mut().enumerate() {
+            let x = (v as f64 / 255.0).powf(1.0 / gamma) * brightness;
+            *out = (x * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        ToneLut { table }
+    }
+
+    /// True when the table maps every value to itself.
+    pub fn is_identity(&self) -> bool {
+        self.table
+            .iter()
+            .enumerate()
+            .all(|(v, &out)| v == out as usize)
+    }
+}
+
+/// Applies `lut` to the color channels of a (premultiplied) bitmap; alpha is left as is.
+/// Opaque pixels are looked up directly, translucent ones through straight alpha. An identity
+/// table leaves the bitmap untouched.
+pub fn adjust_tone(bitmap: &mut BitmapData, lut: &ToneLut) {
+    if lut.is_identity() {
+        return;
+    }
+    let width = bitmap.width.max(0) as usize;
+    let stride = bitmap.stride as usize;
+    for y in 0..bitmap.height.max(0) as usize {
+        for px in bitmap.data[y * stride..y * stride + width * 4].chunks_exact_mut(4) {
+            match px[3] {
+                0 => {}
+                255 => {
+                    for c in &mut px[..3] {
+                        *c = lut.table[*c as usize];
+                    }
+                }
+                a => {
+                    let straight = straight_pixel([px[0], px[1], px[2], a]);
+                    for (c, s) in px[..3].iter_mut().zip(straight) {
+                        *c = ((lut.table[s as usize] as u16 * a as u16 + 127) / 255) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Number of pixels with non-zero alpha.
+pub fn count_opaque_pixels(bitmap: &BitmapData) -> usize {
+    let width = bitmap.width.max(0) as usize;
+    let stride = bitmap.stride as usize;
+    (0..bitmap.height.max(0) as usize)
+        .map(|y| {
+            bitmap.data[y * stride..y * stride + width * 4]
+                .chunks_exact(4)
+                .filter(|px| px[3] > 0)
+                .count()
+        })
+        .sum()
+}
+
+/// Widest run of fully transparent columns with content on both sides, at least `min_gap` wide
+/// (--split-horizontal). Returns the gap as columns `start..end`.
+pub fn find_vertical_gap(bitmap: &BitmapData, min_gap: usize) -> Option<(i32, i32)> {
+    let width = bitmap.width.max(0) as usize;
+    let stride = bitmap.stride as usize;
+    let mut alpha = vec![0u32; width];
+    for y in 0..bitmap.height.max(0) as usize {
+        let row = &bitmap.data[y * stride..y * stride + width * 4];
+        for (sum, px) in alpha.iter_mut().zip(row.chunks_exact(4)) {
+            *sum += px[3] as u32;
+        }
+    }
+    let first = alpha.iter().position(|&a| a > 0)?;
+    let last = alpha.iter().rposition(|&a| a > 0)?;
+    let mut best: Option<(usize, usize)> = None;
+    let mut x = first;
+    while x < last {
+        if alpha[x] > 0 {
+            x += 1;
+            continue;
+        }
+        let start = x;
+        while alpha[x] == 0 {
+            x += 1;
+        }
+        if best.is_none_or(|(s, e)| x - start > e - s) {
+            best = Some((start, x));
+        }
+    }
+    best.filter(|(s, e)| e - s >= min_gap.max(1))
+        .map(|(s, e)| (s as i32, e as i32))
+}
+
+/// Columns `from..to` of a bitmap, clamped to its width.
+pub fn crop_columns(bitmap: &BitmapData, from: i32, to: i32) -> BitmapData {
+    let from = from.clamp(0, bitmap.width.max(0));
+    let to = to.clamp(from, bitmap.width.max(0));
+    let stride = bitmap.stride as usize;
+    let (start, end) = (from as usize * 4, to as usize * 4);
+    let mut data = Vec::with_capacity((end - start) * bitmap.height.max(0) as usize);
+    for y in 0..bitmap.height.max(0) as usize {
+        data.extend_from_slice(&bitmap.data[y * stride + start..y * stride + end]);
+    }
+    BitmapData {
+        data,
+        width: to - from,
+        height: bitmap.height,
+        stride: (to - from) * 4,
+    }
+}
+
+/// `bitmap` placed at (x, y) on a transparent `width` x `height` canvas, clipped to the canvas.
+pub fn place_on_canvas(bitmap: &BitmapData, x: i32, y: i32, width: i32, height: i32) -> BitmapData {
+    let (width, height) = (width.max(1), height.max(1));
+    let mut data = vec![0u8; (width * height * 4) as usize];
+    let (from_x, to_x) = (x.max(0), (x + bitmap.width).min(width));
+    for row in y.max(0)..(y + bitmap.height).min(height) {
+        if from_x >= to_x {
+            break;
+        }
+        let src = ((row - y) * bitmap.stride + (from_x - x) * 4) as usize;
+        let dst = ((row * width + from_x) * 4) as usize;
+        let len = ((to_x - from_x) * 4) as usize;
+        data[dst..dst + len].copy_from_slice(&bitmap.data[src..src + len]);
+    }
+    BitmapData {
+        data,
+        width,
+        height,
+        stride: width * 4,
+    }
+}
+
+/// Largest image number that fits the 5 digits of `generate_image_filename`.
+pub const MAX_IMAGE_INDEX: usize = 99_999;
+
+/// Parses --start-index: the number of the first image, at most `MAX_IMAGE_INDEX`.
+pub fn parse_start_index(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if n <= MAX_IMAGE_INDEX => Ok(n),
+        _ => Err(format!(
+            "invalid start index: {} (expected 0-{})",
+            s, MAX_IMAGE_INDEX
+        )),
+    }
+}
+
+/// Format: base_name + zero-padded 5-digit index + the format's extension
+pub fn generate_image_filename(index: usize, base_name: &str, format: ImageFormat) -> String {
+    format!("{}{:05}.{}", base_name, index, format.extension())
+}
+
+/// File name of the second graphic of a split event: `_2` before the extension of the first.
+pub fn second_image_filename(first: &str) -> String {
+    match first.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}_2.{}", stem, ext),
+        None => format!("{}_2", first),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: i32, height: i32, px: [u8; 4]) -> BitmapData {
+        BitmapData {
+            data: px.repeat((width * height) as usize),
+            width,
+            height,
+            stride: width * 4,
+        }
+    }
+
+    #[test]
+    fn test_scale_bitmap_preserves_uniform_color_and_alpha() {
+        let b = solid(8, 4, [100, 50, 25, 128]);
+        let s = scale_bitmap(&b, 3, 2);
+        assert_eq!((s.width, s.height, s.stride), (3, 2, 12));
+        assert!(s.data.chunks_exact(4).all(|p| p == [100, 50, 25, 128]));
+    }
+
+    #[test]
+    fn test_scale_bitmap_averages_transparency() {
+        // Left half opaque white, right half transparent -> 2x1 keeps both.
+        let mut b = solid(4, 1, [0, 0, 0, 0]);
+        b.data[..8].copy_from_slice(&[255, 255, 255, 255, 255, 255, 255, 255]);
+        let s = scale_bitmap(&b, 2, 1);
+        assert_eq!(s.data, vec![255, 255, 255, 255, 0, 0, 0, 0]);
+        let s = scale_bitmap(&b, 1, 1);
+        assert_eq!(s.data, vec![128, 128, 128, 128]);
+    }
+
+    #[test]
+    fn test_thumbnail_keeps_aspect_and_skips_small() {
+        let b = solid(1920, 270, [255, 255, 255, 255]);
+        let t = thumbnail(&b, 320).unwrap();
+        assert_eq!((t.width, t.height), (320, 45));
+        assert!(thumbnail(&solid(300, 40, [0, 0, 0, 0]), 320).is_none());
+        assert!(thumbnail(&solid(320, 40, [0, 0, 0, 0]), 320).is_none());
+        let t = thumbnail(&solid(1000, 1, [0, 0, 0, 0]), 320).unwrap();
+        assert_eq!(t.height, 1);
+    }
+
+    #[test]
+    fn test_flatten_over() {
+        let mut b = solid(2, 1, [0, 0, 0, 0]);
+        // Opaque red, then 50% white (premultiplied).
+        b.data.copy_from_slice(&[255, 0, 0, 255, 128, 128, 128, 128]);
+        let f = flatten_over(&b, [0, 0, 255]);
+        assert_eq!(f.data, vec![255, 0, 0, 255, 128, 128, 255, 255]);
+        let f = flatten_over(&solid(1, 1, [0, 0, 0, 0]), [10, 20, 30]);
+        assert_eq!(f.data, vec![10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn test_tone_lut() {
+        assert!(ToneLut::new(1.0, 1.0).is_identity());
+        let cap = ToneLut::new(0.9, 1.0);
+        assert!(!cap.is_identity());
+        assert_eq!(
+            (cap.table[0], cap.table[128], cap.table[255]),
+            (0, 115, 230)
+        );
+        // Gamma above 1 lifts mid-tones and keeps the ends.
+        let lift = ToneLut::new(1.0, 2.2);
+        assert_eq!((lift.table[0], lift.table[255]), (0, 255));
+        assert!(lift.table[64] > 64);
+        assert!(lift.table.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_adjust_tone() {
+        let mut b = solid(4, 1, [0, 0, 0, 0]);
+        // Opaque white, 50% white (premultiplied), transparent and opaque dark red.
+        b.data.copy_from_slice(&[
+            255, 255, 255, 255, 128, 128, 128, 128, 0, 0, 0, 0, 100, 0, 0, 255,
+        ]);
+        let original = b.data.clone();
+        adjust_tone(&mut b, &ToneLut::new(1.0, 1.0));
+        assert_eq!(b.data, original);
+
+        adjust_tone(&mut b, &ToneLut::new(0.9, 1.0));
+        assert_eq!(
+            b.data,
+            [230, 230, 230, 255, 115, 115, 115, 128, 0, 0, 0, 0, 90, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn test_count_opaque_pixels() {
+        let mut b = solid(3, 2, [0, 0, 0, 0]);
+        b.data[3] = 1;
+        b.data[23] = 255;
+        assert_eq!(count_opaque_pixels(&b), 2);
+        assert_eq!(count_opaque_pixels(&solid(3, 2, [1, 1, 1, 255])), 6);
+    }
+
+    #[test]
+    fn test_save_bitmap_as_tiff_round_trip() {
+        use tiff::decoder::{Decoder, DecodingResult};
+        use tiff::tags::Tag;
+
+        let dir = crate::output::tests::scratch_dir("tiff");
+        let path = dir.join("a.tif");
+        // Premultiplied half-transparent red next to a transparent pixel.
+        let mut b = solid(2, 1, [0, 0, 0, 0]);
+        b.data[..4].copy_from_slice(&[128, 0, 0, 128]);
+        save_bitmap_as_tiff(&b, &path).unwrap();
+
+        let mut decoder = Decoder::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (2, 1));
+        assert_eq!(decoder.get_tag_u32(Tag::ExtraSamples).unwrap(), 2);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![255, 0, 0, 128, 0, 0, 0, 0]),
+            other => panic!("unexpected sample type: {:?}", std::mem::discriminant(&other)),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Decodes a PNG written by `write_png` back to straight RGBA.
+    fn read_png_rgba(path: &Path) -> (png::ColorType, Vec<u8>) {
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        buf.truncate(info.buffer_size());
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::GrayscaleAlpha => {
+                buf.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect()
+            }
+            other => panic!("unexpected color type: {:?}", other),
+        };
+        (info.color_type, rgba)
+    }
+
+    #[test]
+    fn test_is_grayscale() {
+        assert!(is_grayscale(&[255, 255, 255, 255, 0, 0, 0, 0, 7, 7, 7, 128]));
+        assert!(!is_grayscale(&[255, 255, 255, 255, 255, 255, 254, 255]));
+        assert!(is_grayscale(&[]));
+    }
+
+    #[test]
+    fn test_png_auto_gray_round_trip() {
+        let dir = crate::output::tests::scratch_dir("png-gray");
+        // White text with a black, half-transparent edge (premultiplied) and a transparent pixel.
+        let mut mono = solid(3, 1, [255, 255, 255, 255]);
+        mono.data[4..8].copy_from_slice(&[0, 0, 0, 128]);
+        mono.data[8..12].copy_from_slice(&[0, 0, 0, 0]);
+        // Same, with one pixel off-neutral by a single level.
+        let mut colored = mono.clone();
+        colored.data[..4].copy_from_slice(&[255, 255, 254, 255]);
+
+        for (bitmap, expected) in [
+            (&mono, png::ColorType::GrayscaleAlpha),
+            (&colored, png::ColorType::Rgba),
+        ] {
+            let gray_path = dir.join("gray.png");
+            let rgba_path = dir.join("rgba.png");
+            let size = save_bitmap(bitmap, &gray_path, ImageFormat::Png, true).unwrap();
+            assert_eq!(size, std::fs::metadata(&gray_path).unwrap().len());
+            save_bitmap(bitmap, &rgba_path, ImageFormat::Png, false).unwrap();
+            let (color, pixels) = read_png_rgba(&gray_path);
+            let (rgba_color, rgba_pixels) = read_png_rgba(&rgba_path);
+            assert_eq!(color, expected);
+            assert_eq!(rgba_color, png::ColorType::Rgba);
+            assert_eq!(pixels, rgba_pixels);
+            assert_eq!(pixels, straight_alpha_rgba(bitmap).unwrap());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Opaque `cols` ranges on a transparent canvas, with a semi-transparent pixel in each.
+    fn clusters(width: i32, height: i32, cols: &[(i32, i32)]) -> BitmapData {
+        let mut b = solid(width, height, [0, 0, 0, 0]);
+        for &(from, to) in cols {
+            for y in 0..height {
+                for x in from..to {
+                    let o = ((y * width + x) * 4) as usize;
+                    b.data[o..o + 4].copy_from_slice(&[200, 200, 200, 200]);
+                }
+            }
+            let o = (from * 4) as usize;
+            b.data[o..o + 4].copy_from_slice(&[1, 1, 1, 1]);
+        }
+        b
+    }
+
+    #[test]
+    fn test_find_vertical_gap() {
+        // Speaker on the left, sound effect on the right.
+        let b = clusters(100, 3, &[(0, 20), (70, 100)]);
+        assert_eq!(find_vertical_gap(&b, 10), Some((20, 70)));
+        assert_eq!(find_vertical_gap(&b, 50), Some((20, 70)));
+        assert_eq!(find_vertical_gap(&b, 51), None);
+        // The widest of several gaps; margins outside the content are not gaps.
+        let b = clusters(100, 2, &[(5, 10), (15, 30), (60, 90)]);
+        assert_eq!(find_vertical_gap(&b, 10), Some((30, 60)));
+        // A single faint pixel in a column breaks the gap.
+        let mut b = clusters(100, 3, &[(0, 20), (70, 100)]);
+        b.data[(100 + 45) * 4 + 3] = 1;
+        assert_eq!(find_vertical_gap(&b, 30), None);
+        assert_eq!(find_vertical_gap(&b, 20), Some((20, 45)));
+        assert_eq!(find_vertical_gap(&clusters(10, 1, &[(0, 10)]), 1), None);
+        assert_eq!(find_vertical_gap(&solid(10, 1, [0, 0, 0, 0]), 1), None);
+    }
+
+    #[test]
+    fn test_crop_columns() {
+        let b = clusters(100, 3, &[(0, 20), (70, 100)]);
+        let left = crop_columns(&b, 0, 20);
+        let right = crop_columns(&b, 70, 100);
+        assert_eq!((left.width, left.height, left.data.len()), (20, 3, 240));
+        assert_eq!((right.width, right.stride), (30, 120));
+        assert_eq!(&right.data[..4], &[1, 1, 1, 1]);
+        assert_eq!(count_opaque_pixels(&left) + count_opaque_pixels(&right), 150);
+        assert_eq!(crop_columns(&b, 90, 200).width, 10);
+    }
+
+    #[test]
+    fn test_place_on_canvas() {
+        let b = BitmapData {
+            data: (1..=16).collect(),
+            width: 2,
+            height: 2,
+            stride: 8,
+        };
+        let placed = place_on_canvas(&b, 1, 2, 4, 5);
+        assert_eq!((placed.width, placed.height, placed.stride), (4, 5, 16));
+        assert_eq!(&placed.data[2 * 16 + 4..2 * 16 + 12], &b.data[..8]);
+        assert_eq!(&placed.data[3 * 16 + 4..3 * 16 + 12], &b.data[8..]);
+        assert_eq!(count_opaque_pixels(&placed), count_opaque_pixels(&b));
+        // Clipped at the right and bottom edges, and at negative positions.
+        let clipped = place_on_canvas(&b, 3, 4, 4, 5);
+        assert_eq!(&clipped.data[4 * 16 + 12..], &b.data[..4]);
+        let clipped = place_on_canvas(&b, -1, -1, 4, 5);
+        assert_eq!(&clipped.data[..4], &b.data[12..]);
+        assert!(place_on_canvas(&b, 9, 9, 4, 5).data.iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn test_second_image_filename() {
+        assert_eq!(second_image_filename("a00001.png"), "a00001_2.png");
+        assert_eq!(second_image_filename("x.y00002.tif"), "x.y00002_2.tif");
+    }
+
+    #[test]
+    fn test_generate_image_filename() {
+        assert_eq!(generate_image_filename(7, "ep01", ImageFormat::Png), "ep0100007.png");
+        assert_eq!(generate_image_filename(7, "ep01", ImageFormat::Tiff), "ep0100007.tif");
+    }
+
+    #[test]
+    fn test_parse_start_index() {
+        assert_eq!(parse_start_index("120"), Ok(120));
+        assert_eq!(parse_start_index("99999"), Ok(MAX_IMAGE_INDEX));
+        assert!(parse_start_index("100000").is_err());
+        assert!(parse_start_index("-1").is_err());
+    }
 }
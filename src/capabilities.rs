@@ -0,0 +1,147 @@
+//! --capabilities: what this build and the FFmpeg it runs against can do, as JSON, so frontends
+//! can check before building a command line. The feature list here is also what the long
+//! --version shows.
+
+use crate::style::json_string;
+
+/// Version of the --capabilities document; raised when a key changes meaning or goes away.
+pub const CAPABILITIES_VERSION: u64 = 1;
+
+/// A cargo feature of this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Feature {
+    pub name: &'static str,
+    /// Compiled into this build.
+    pub enabled: bool,
+}
+
+/// Every cargo feature, in Cargo.toml order.
+pub const FEATURES: [Feature; 1] = [Feature {
+    name: "preview-video",
+    enabled: cfg!(feature = "preview-video"),
+}];
+
+/// What this build supports: its version, features and the values its options take.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub features: &'static [Feature],
+    /// --format values.
+    pub formats: Vec<String>,
+    /// Caption image formats, by file extension.
+    pub image_formats: Vec<&'static str>,
+}
+
+/// What the FFmpeg libraries found at run time provide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfmpegSupport {
+    /// The libaribcaption decoder, needed by every conversion.
+    pub arib_decoder: bool,
+    /// --preview-video encoders present, in order of preference.
+    pub preview_encoders: Vec<&'static str>,
+}
+
+impl FfmpegSupport {
+    pub fn probe() -> Self {
+        FfmpegSupport {
+            arib_decoder: crate::ffmpeg::has_arib_decoder(),
+            preview_encoders: crate::ffmpeg::PREVIEW_ENCODERS
+                .into_iter()
+                .filter(|name| crate::ffmpeg::has_encoder(name))
+                .collect(),
+        }
+    }
+}
+
+fn json_list<S: AsRef<str>>(items: &[S]) -> String {
+    let items: Vec<String> = items.iter().map(|s| json_string(s.as_ref())).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// The --capabilities document.
+pub fn render_capabilities_json(build: &BuildInfo, ffmpeg: &FfmpegSupport) -> String {
+    let features: Vec<String> = build
+        .features
+        .iter()
+        .map(|f| format!("{}: {}", json_string(f.name), f.enabled))
+        .collect();
+    format!(
+        "{{\n  \"schema_version\": {},\n  \"version\": {},\n  \"features\": {{{}}},\n  \"formats\": {},\n  \"image_formats\": {},\n  \"ffmpeg\": {{\"libaribcaption\": {}, \"preview_encoders\": {}}}\n}}\n",
+        CAPABILITIES_VERSION,
+        json_string(build.version),
+        features.join(", "),
+        json_list(&build.formats),
+        json_list(&build.image_formats),
+        ffmpeg.arib_decoder,
+        json_list(&ffmpeg.preview_encoders)
+    )
+}
+
+/// The long --version text (after the program name).
+pub fn long_version(build: &BuildInfo) -> String {
+    let features: Vec<String> = build
+        .features
+        .iter()
+        .map(|f| format!("{}{}", if f.enabled { '+' } else { '-' }, f.name))
+        .collect();
+    format!(
+        "{}\nfeatures: {}\nformats: {}\nimage formats: {}",
+        build.version,
+        features.join(" "),
+        build.formats.join(", "),
+        build.image_formats.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::Json;
+
+    fn build() -> BuildInfo {
+        BuildInfo {
+            version: "0.2.2",
+            features: &FEATURES,
+            formats: ["bdn", "chapters", "tiff", "stl", "srt"]
+                .map(String::from)
+                .to_vec(),
+            image_formats: vec!["png", "tif"],
+        }
+    }
+
+    #[test]
+    fn test_capabilities_json() {
+        let ffmpeg = FfmpegSupport {
+            arib_decoder: true,
+            preview_encoders: vec!["mpeg4"],
+        };
+        let json = Json::parse(&render_capabilities_json(&build(), &ffmpeg)).unwrap();
+        assert_eq!(
+            json.get("schema_version").unwrap().as_u64(),
+            Ok(CAPABILITIES_VERSION)
+        );
+        assert_eq!(json.get("version").unwrap().as_str(), Ok("0.2.2"));
+        let features = json.get("features").unwrap();
+        for feature in FEATURES {
+            assert_eq!(
+                features.get(feature.name).unwrap().as_bool(),
+                Ok(feature.enabled)
+            );
+        }
+        assert_eq!(json.get("formats").unwrap().as_array().unwrap().len(), 5);
+        let image_formats = json.get("image_formats").unwrap().as_array().unwrap();
+        assert_eq!(image_formats[1].as_str(), Ok("tif"));
+        let ffmpeg = json.get("ffmpeg").unwrap();
+        assert_eq!(ffmpeg.get("libaribcaption").unwrap().as_bool(), Ok(true));
+        let encoders = ffmpeg.get("preview_encoders").unwrap().as_array().unwrap();
+        assert_eq!(encoders, [Json::String("mpeg4".to_string())]);
+    }
+
+    #[test]
+    fn test_long_version() {
+        let text = long_version(&build());
+        assert!(text.starts_with("0.2.2\nfeatures: "));
+        assert!(text.contains("preview-video"));
+        assert!(text.ends_with("\nformats: bdn, chapters, tiff, stl, srt\nimage formats: png, tif"));
+    }
+}
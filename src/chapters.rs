@@ -0,0 +1,104 @@
+//! OGM-style chapter file (CHAPTERNN=HH:MM:SS.mmm / CHAPTERNNNAME=...) from subtitle events,
+//! for use with mkvmerge --chapters.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::bdn::{tc_to_seconds, BdnInfo, SubtitleEvent};
+
+/// Formats seconds as HH:MM:SS.mmm.
+fn format_chapter_time(seconds: f64) -> String {
+    let total_ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        total_ms / 3_600_000,
+        (total_ms / 60_000) % 60,
+        (total_ms / 1000) % 60,
+        total_ms % 1000
+    )
+}
+
+/// Writes one chapter per event at its InTC, named by the event's timecode.
+pub struct ChapterGenerator {
+    info: BdnInfo,
+    events: Vec<SubtitleEvent>,
+}
+
+impl ChapterGenerator {
+    pub fn new(info: BdnInfo) -> Self {
+        ChapterGenerator {
+            info,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn add_event(&mut self, event: &SubtitleEvent) {
+        self.events.push(event.clone());
+    }
+
+    fn render(&self) -> anyhow::Result<String> {
+        let mut out = String::new();
+        for (i, event) in self.events.iter().enumerate() {
+            let n = i + 1;
+            let start = tc_to_seconds(&event.in_tc, self.info.fps)?;
+            out.push_str(&format!("CHAPTER{:02}={}\n", n, format_chapter_time(start)));
+            out.push_str(&format!("CHAPTER{:02}NAME={}\n", n, event.in_tc));
+        }
+        Ok(out)
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> anyhow::Result<()> {
+        let f = crate::sink::create(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open file: {}: {}", path.display(), e))?;
+        let mut w = BufWriter::new(f);
+        w.write_all(self.render()?.as_bytes())?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdn::XmlStyle;
+
+    fn event(in_tc: &str) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: in_tc.to_string(),
+            png_file: String::new(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            forced: false,
+            second: None,
+        }
+    }
+
+    #[test]
+    fn test_format_chapter_time() {
+        assert_eq!(format_chapter_time(0.0), "00:00:00.000");
+        assert_eq!(format_chapter_time(3723.5), "01:02:03.500");
+        assert_eq!(format_chapter_time(-1.0), "00:00:00.000");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut g = ChapterGenerator::new(BdnInfo {
+            fps: 25.0,
+            video_format: "1080p".to_string(),
+            fade: None,
+            normalized: None,
+            provenance: None,
+            xml_style: XmlStyle::default(),
+        });
+        g.add_event(&event("00:00:01:00"));
+        g.add_event(&event("00:01:00:12"));
+        assert_eq!(
+            g.render().unwrap(),
+            "CHAPTER01=00:00:01.000\nCHAPTER01NAME=00:00:01:00\n\
+             CHAPTER02=00:01:00.480\nCHAPTER02NAME=00:01:00:12\n"
+        );
+    }
+}
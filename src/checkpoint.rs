@@ -0,0 +1,479 @@
+//! --checkpoint: periodically saves how far a conversion got, so a long run that dies can resume
+//! instead of starting over. The file is versioned JSON and only valid for the same input file
+//! (size and modification time) and the same output settings.
+
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::bdn::{Graphic, SubtitleEvent};
+use crate::style::json_string;
+
+/// Version written to and required from checkpoint files.
+pub const CHECKPOINT_VERSION: u64 = 1;
+/// Default number of new events between saves (--checkpoint-every).
+pub const DEFAULT_CHECKPOINT_EVERY: u32 = 100;
+
+/// Identifies the input a checkpoint was made for; any change invalidates it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputStamp {
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub mtime_ns: u64,
+}
+
+impl InputStamp {
+    pub fn of(path: &Path) -> anyhow::Result<Self> {
+        let meta = std::fs::metadata(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))?;
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Ok(InputStamp {
+            size: meta.len(),
+            mtime_ns: mtime.as_nanos() as u64,
+        })
+    }
+}
+
+/// A conversion in progress: the events whose timing is final and where decoding resumes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub input: InputStamp,
+    pub base_name: String,
+    pub fps: f64,
+    /// Seconds treated as 00:00:00:00 (kept so the resumed part is timed the same).
+    pub base_time: f64,
+    /// Timestamp of the first subtitle frame to decode again; earlier frames are skipped.
+    pub resume_at: f64,
+    /// Events already built; their images are written. The next event gets index `events.len()`.
+    pub events: Vec<SubtitleEvent>,
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> String {
+        let events: Vec<String> = self.events.iter().map(event_json).collect();
+        format!(
+            "{{\"version\":{},\"input\":{{\"size\":{},\"mtime_ns\":{}}},\"base_name\":{},\"fps\":{},\"base_time\":{},\"resume_at\":{},\"next_index\":{},\"events\":[\n{}\n]}}\n",
+            CHECKPOINT_VERSION,
+            self.input.size,
+            self.input.mtime_ns,
+            json_string(&self.base_name),
+            self.fps,
+            self.base_time,
+            self.resume_at,
+            self.events.len(),
+            events.join(",\n")
+        )
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let root = Json::parse(contents)?;
+        let version = root.get("version")?.as_u64()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "unsupported version {} (expected {})",
+                version, CHECKPOINT_VERSION
+            ));
+        }
+        let input = root.get("input")?;
+        let events = root
+            .get("events")?
+            .as_array()?
+            .iter()
+            .map(parse_event)
+            .collect::<Result<Vec<_>, _>>()?;
+        if root.get("next_index")?.as_u64()? != events.len() as u64 {
+            return Err("next_index does not match the event count".to_string());
+        }
+        Ok(Checkpoint {
+            input: InputStamp {
+                size: input.get("size")?.as_u64()?,
+                mtime_ns: input.get("mtime_ns")?.as_u64()?,
+            },
+            base_name: root.get("base_name")?.as_str()?.to_string(),
+            fps: root.get("fps")?.as_f64()?,
+            base_time: root.get("base_time")?.as_f64()?,
+            resume_at: root.get("resume_at")?.as_f64()?,
+            events,
+        })
+    }
+
+    /// Why this checkpoint cannot be resumed with the given input and settings, if it cannot.
+    pub fn mismatch(&self, input: InputStamp, base_name: &str, fps: f64) -> Option<&'static str> {
+        if self.input != input {
+            Some("the input file changed")
+        } else if self.base_name != base_name {
+            Some("the output base name differs")
+        } else if self.fps != fps {
+            Some("the frame rate differs")
+        } else {
+            None
+        }
+    }
+
+    /// Reads the checkpoint at `path`; None when there is none.
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => anyhow::bail!("Failed to read file: {}: {}", path.display(), e),
+        };
+        Checkpoint::parse(&contents)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid checkpoint: {}: {}", path.display(), e))
+    }
+
+    /// Writes the checkpoint (via a temporary file, so a crash never leaves it half-written).
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp = path.with_extension("tmp");
+        crate::sink::write(&tmp, self.to_json())
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))
+    }
+}
+
+fn graphic_fields(png_file: &str, x: i32, y: i32, width: i32, height: i32) -> String {
+    format!(
+        "\"png_file\":{},\"x\":{},\"y\":{},\"width\":{},\"height\":{}",
+        json_string(png_file),
+        x,
+        y,
+        width,
+        height
+    )
+}
+
+fn event_json(e: &SubtitleEvent) -> String {
+    let second = e.second.as_ref().map_or("null".to_string(), |g| {
+        format!(
+            "{{{}}}",
+            graphic_fields(&g.png_file, g.x, g.y, g.width, g.height)
+        )
+    });
+    format!(
+        "{{\"in_tc\":{},\"out_tc\":{},{},\"forced\":{},\"second\":{}}}",
+        json_string(&e.in_tc),
+        json_string(&e.out_tc),
+        graphic_fields(&e.png_file, e.x, e.y, e.width, e.height),
+        e.forced,
+        second
+    )
+}
+
+fn parse_graphic(v: &Json) -> Result<Graphic, String> {
+    Ok(Graphic {
+        png_file: v.get("png_file")?.as_str()?.to_string(),
+        x: v.get("x")?.as_i32()?,
+        y: v.get("y")?.as_i32()?,
+        width: v.get("width")?.as_i32()?,
+        height: v.get("height")?.as_i32()?,
+    })
+}
+
+fn parse_event(v: &Json) -> Result<SubtitleEvent, String> {
+    let first = parse_graphic(v)?;
+    let second = match v.get("second")? {
+        Json::Null => None,
+        g => Some(parse_graphic(g)?),
+    };
+    Ok(SubtitleEvent {
+        in_tc: v.get("in_tc")?.as_str()?.to_string(),
+        out_tc: v.get("out_tc")?.as_str()?.to_string(),
+        png_file: first.png_file,
+        x: first.x,
+        y: first.y,
+        width: first.width,
+        height: first.height,
+        forced: v.get("forced")?.as_bool()?,
+        second,
+    })
+}
+
+/// Just enough JSON to read checkpoints (and the `corpus` baseline) back. Numbers keep their
+/// text so 64-bit integers survive.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn parse(s: &str) -> Result<Json, String> {
+        let mut chars = s.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        skip_ws(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("unexpected {:?} after the document", c)),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Result<&Json, String> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .ok_or_else(|| format!("missing {}", key)),
+            _ => Err(format!("expected an object with {}", key)),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Result<&str, String> {
+        match self {
+            Json::String(s) => Ok(s),
+            _ => Err("expected a string".to_string()),
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Result<bool, String> {
+        match self {
+            Json::Bool(b) => Ok(*b),
+            _ => Err("expected true or false".to_string()),
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Result<&[Json], String> {
+        match self {
+            Json::Array(items) => Ok(items),
+            _ => Err("expected an array".to_string()),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&self) -> Result<T, String> {
+        match self {
+            Json::Number(n) => n.parse().map_err(|_| format!("invalid number {}", n)),
+            _ => Err("expected a number".to_string()),
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Result<u64, String> {
+        self.number()
+    }
+
+    pub(crate) fn as_i64(&self) -> Result<i64, String> {
+        self.number()
+    }
+
+    pub(crate) fn as_i32(&self) -> Result<i32, String> {
+        self.number()
+    }
+
+    pub(crate) fn as_f64(&self) -> Result<f64, String> {
+        self.number()
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_ws(chars: &mut Chars) {
+    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Chars, word: &str) -> Result<(), String> {
+    for expected in word.chars() {
+        if chars.next() != Some(expected) {
+            return Err(format!("expected {}", word));
+        }
+    }
+    Ok(())
+}
+
+fn parse_value(chars: &mut Chars) -> Result<Json, String> {
+    skip_ws(chars);
+    match chars.peek().copied() {
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_ws(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                skip_ws(chars);
+                let key = parse_string(chars)?;
+                skip_ws(chars);
+                expect(chars, ":")?;
+                fields.push((key, parse_value(chars)?));
+                skip_ws(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => return Ok(Json::Object(fields)),
+                    _ => return Err("expected , or } in object".to_string()),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_ws(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_value(chars)?);
+                skip_ws(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Json::Array(items)),
+                    _ => return Err("expected , or ] in array".to_string()),
+                }
+            }
+        }
+        Some('"') => parse_string(chars).map(Json::String),
+        Some('t') => expect(chars, "true").map(|_| Json::Bool(true)),
+        Some('f') => expect(chars, "false").map(|_| Json::Bool(false)),
+        Some('n') => expect(chars, "null").map(|_| Json::Null),
+        Some(c) if c == '-' || c.is_ascii_digit() => {
+            let mut n = String::new();
+            while let Some(c) = chars.next_if(|c| "+-.eE".contains(*c) || c.is_ascii_digit()) {
+                n.push(c);
+            }
+            Ok(Json::Number(n))
+        }
+        Some(c) => Err(format!("unexpected {:?}", c)),
+        None => Err("unexpected end of file".to_string()),
+    }
+}
+
+fn parse_string(chars: &mut Chars) -> Result<String, String> {
+    expect(chars, "\"")?;
+    let mut out = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(out),
+            Some('\\') => match chars.next() {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('/') => out.push('/'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('t') => out.push('\t'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid escape \\u{}", hex))?;
+                    out.push(c);
+                }
+                _ => return Err("invalid escape in string".to_string()),
+            },
+            Some(c) => out.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint() -> Checkpoint {
+        let event = |n: i32| SubtitleEvent {
+            in_tc: format!("00:00:0{}:00", n),
+            out_tc: format!("00:00:0{}:15", n),
+            png_file: format!("rec \"{}\"{:05}.png", n, n),
+            x: 100 * n,
+            y: -4,
+            width: 320,
+            height: 60,
+            forced: n == 1,
+            second: None,
+        };
+        let mut split = event(2);
+        split.second = Some(Graphic {
+            png_file: "rec00002_2.png".to_string(),
+            x: 900,
+            y: 10,
+            width: 40,
+            height: 60,
+        });
+        Checkpoint {
+            input: InputStamp {
+                size: 12_345_678_901,
+                mtime_ns: 1_760_000_000_123_456_789,
+            },
+            base_name: "rec\\".to_string(),
+            fps: 29.97,
+            base_time: 0.1 + 0.2,
+            resume_at: 3723.456789012,
+            events: vec![event(0), event(1), split],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let c = checkpoint();
+        let json = c.to_json();
+        assert!(json.starts_with("{\"version\":1,"), "{}", json);
+        assert_eq!(Checkpoint::parse(&json).unwrap(), c);
+        let empty = Checkpoint {
+            events: Vec::new(),
+            ..checkpoint()
+        };
+        assert_eq!(Checkpoint::parse(&empty.to_json()).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_files() {
+        let json = checkpoint().to_json();
+        let err = |s: &str| Checkpoint::parse(s).unwrap_err();
+        assert_eq!(
+            err(&json.replace("\"version\":1", "\"version\":2")),
+            "unsupported version 2 (expected 1)"
+        );
+        assert_eq!(
+            err(&json.replace("\"next_index\":3", "\"next_index\":4")),
+            "next_index does not match the event count"
+        );
+        assert_eq!(err(&json.replace("\"fps\"", "\"rate\"")), "missing fps");
+        assert!(Checkpoint::parse(&json[..json.len() / 2]).is_err());
+        assert_eq!(err(""), "unexpected end of file");
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let c = checkpoint();
+        assert_eq!(c.mismatch(c.input, "rec\\", 29.97), None);
+        let touched = InputStamp {
+            mtime_ns: c.input.mtime_ns + 1,
+            ..c.input
+        };
+        assert_eq!(
+            c.mismatch(touched, "rec\\", 29.97),
+            Some("the input file changed")
+        );
+        assert_eq!(
+            c.mismatch(c.input, "other", 29.97),
+            Some("the output base name differs")
+        );
+        assert_eq!(
+            c.mismatch(c.input, "rec\\", 25.0),
+            Some("the frame rate differs")
+        );
+    }
+
+    #[test]
+    fn test_load_and_save() {
+        let dir = crate::output::tests::scratch_dir("checkpoint");
+        let path = dir.join("rec.checkpoint.json");
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+        checkpoint().save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(checkpoint()));
+        assert!(!dir.join("rec.checkpoint.tmp").exists());
+        let stamp = InputStamp::of(&path).unwrap();
+        assert_eq!(stamp.size, checkpoint().to_json().len() as u64);
+
+        std::fs::write(&path, "{").unwrap();
+        let err = Checkpoint::load(&path).unwrap_err().to_string();
+        assert!(err.starts_with("Invalid checkpoint: "), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
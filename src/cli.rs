@@ -0,0 +1,4009 @@
+//! The command line: `Cli`, its subcommands, and the conversion of each input it names.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+
+use crate::arib::CaptionType;
+use crate::bdn::{
+    clip_event, offset_event, offset_tc, parse_bdn, parse_indent, parse_line_ending,
+    parse_outtc_semantics, parse_tc_offset, render_tc_list, tc_to_seconds, BdnInfo,
+    BdnXmlGenerator, Fade, Indent, LineEnding, OutTcSemantics, Provenance, SubtitleEvent, TcOffset,
+    XmlStyle, DEFAULT_SNAP_GAPS,
+};
+use crate::bdsup2sub::{render_bat_script, render_sh_script, run_bdsup2sub, DEFAULT_BDSUP2SUB};
+use crate::bitmap::{
+    adjust_tone, crop_columns, flatten_over, generate_image_filename, parse_start_index,
+    save_bitmap, save_bitmap_as_png, second_image_filename, thumbnail, BitmapData, ImageFormat,
+    ToneLut, MAX_IMAGE_INDEX,
+};
+use crate::capabilities::{render_capabilities_json, BuildInfo, FfmpegSupport, FEATURES};
+use crate::chapters::ChapterGenerator;
+use crate::checkpoint::{Checkpoint, InputStamp, DEFAULT_CHECKPOINT_EVERY};
+use crate::config::{
+    apply_style, dar_correction, determine_canvas_size, parse_canvas_size, parse_region,
+    parse_video_size, setup_libaribcaption_defaults, video_format_from_canvas, DarCorrection,
+    Region, StyleSet, DEFAULT_CANVAS,
+};
+use crate::corpus::{
+    parse_expectations, render_corpus_table, Baseline, CorpusRow, SampleResult, DEFAULT_BASELINE,
+    DEFAULT_EXPECTATIONS,
+};
+use crate::dedup::DedupIndex;
+use crate::diagnostics::{with_handler, Level};
+use crate::dry_run::{render_plan_json, render_plan_table, PlanRow, PlannedInput};
+use crate::dual::{widen_bitmap, widen_event, ANAMORPHIC_CANVAS, SQUARE_CANVAS};
+use crate::edl::CutList;
+use crate::event_map::{renumber_images, DropReason, EventMap};
+use crate::ffmpeg::{
+    probe_video_resolution, FfmpegWrapper, StreamSelection, VideoInfo, VideoProbeProblem,
+};
+use crate::ffprobe::{ffprobe_video_stream, FFPROBE_TIMEOUT};
+use crate::fit::{apply_fit, fit_graphic, parse_max_graphic_size, GraphicFit};
+use crate::font_coverage::{font_coverage, primary_font, FcMatch};
+use crate::fonts::{
+    attachment_font_dir, extract_fonts, is_font_attachment, prepend_fonts, AttachmentFonts,
+};
+use crate::forced::{
+    forced_signals, forced_summary, parse_forced_heuristic, EventTraits, ForcedHeuristic,
+};
+use crate::jobs::{
+    in_parallel_job, job_threads, run_parallel, set_job, thread_share, JobContext, ThreadBudget,
+};
+use crate::limits::{check_limits, parse_target_tool, tool_limits, TargetTool};
+use crate::margin::{apply_margins, Margins};
+use crate::min_gap::{parse_gap_side, parse_min_gap, GapSide, MinGap};
+use crate::ocr::{ocr_summary, parse_ocr_command, render_ocr_json, run_ocr, DEFAULT_OCR_TIMEOUT_SECS};
+use crate::optimize::{optimize_dir, OptimizeOptions};
+use crate::options::{
+    parse_brightness, parse_frame_rate, parse_gamma, parse_libaribcaption_opts, parse_pid,
+    parse_rgb_color, parse_time_ranges, parse_time_string,
+};
+use crate::output::{
+    existing_outputs, link_or_copy, output_base_name, parse_path_template, plan_outputs,
+    prepare_output_dir, prepare_shared_dir, ExistingOutputPolicy, ImageReference, Layout,
+    OutputPlan,
+};
+use crate::pipeline::{build_events_from, build_text_events, EventSettings};
+use crate::plate::trim_background_plate;
+use crate::png_optimize::{save_bitmap_optimized, PNG_OPTIMIZE_BUDGET};
+use crate::position::{classify, parse_position_thresholds, render_positions, PositionThresholds};
+use crate::preflight::{explain, probe_dir, RealFs};
+use crate::preview::{composite_over, scale_nearest, EvenSampler, PreviewSample, PREVIEW_STILLS};
+use crate::preview_video::PreviewTimeline;
+use crate::probe_cache::ProbeCache;
+use crate::reference::{compare_with_reference, load_reference};
+use crate::retarget::retarget_dir;
+use crate::run_config::{given_arguments, redact_path, RunConfig};
+use crate::shard::{
+    combine_dirs, parse_shard, Shard, ShardManifest, MANIFEST_SUFFIX, SHARD_INDEX_STRIDE,
+    SHARD_PREROLL,
+};
+use crate::sink::{parse_output_mode, set_output_mode};
+use crate::source_time::{read_broadcast_clock, SourceTimes, SCAN_LIMIT};
+use crate::srt::SrtGenerator;
+use crate::stats::{event_image_sizes, warnings_printed, Stats};
+use crate::stl::{render_stl, TextEvent};
+use crate::style::{dominant_color, render_color_analysis, Rgb, StyleReport};
+use crate::throttle::{lower_priority, parse_rate_limit, RateLimit, TokenBucket};
+use crate::timing::{
+    parse_correction_window, parse_default_duration, parse_global_delay,
+    parse_max_display_duration, parse_rate_preference, parse_timestamp_base, select_timestamp_base,
+    DefaultDuration, FrameRateSource, RatePreference, TcMap, TimestampBase,
+    TimestampBaseCandidates, DEFAULT_CORRECTION_WINDOW, DEFAULT_FPS, DEFAULT_MAX_DISPLAY_DURATION,
+    DEFAULT_MAX_EVENTS_PER_SECOND,
+};
+use crate::validate::verify_file;
+use crate::watch::{
+    install_interrupt_handler, interrupted, sleep_unless_interrupted, watch_candidates,
+    StabilityTracker, WatchState, MAX_WATCH_ATTEMPTS, WATCH_POLL_INTERVAL, WATCH_STATE_FILE,
+};
+use crate::workspace::{sweep_stale, TempWorkspace, STALE_WORKSPACE_AGE};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// This build's version, features, --format values and image formats.
+fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        features: &FEATURES,
+        formats: OutputFormat::value_variants()
+            .iter()
+            .filter_map(|f| f.to_possible_value())
+            .map(|v| v.get_name().to_string())
+            .collect(),
+        image_formats: ImageFormat::ALL.iter().map(|f| f.extension()).collect(),
+    }
+}
+
+/// --version (not -V): the version with the features and formats of this build.
+fn long_version() -> &'static str {
+    static LONG_VERSION: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    LONG_VERSION.get_or_init(|| crate::capabilities::long_version(&build_info()))
+}
+
+/// Derives candidate base names for companion .mkv from .mks stem.
+/// Strips from the right: .forced, .jpn/.eng, then .NN (track number).
+/// e.g. "MOVIE.01.jpn.forced" -> ["MOVIE.01.jpn.forced", "MOVIE.01.jpn", "MOVIE.01", "MOVIE"]
+/// so that we try MOVIE.mkv, MOVIE.01.mkv, ... and match MOVIE.mkv.
+fn companion_mkv_base_candidates(stem: &str) -> Vec<String> {
+    if stem.is_empty() {
+        return vec![];
+    }
+    let mut out = vec![stem.to_string()];
+    let mut rest = stem;
+    while let Some(trimmed) = rest
+        .strip_suffix(".forced")
+        .or_else(|| rest.strip_suffix(".jpn"))
+        .or_else(|| rest.strip_suffix(".eng"))
+        .or_else(|| rest.strip_suffix(".japanese"))
+        .or_else(|| rest.strip_suffix(".english"))
+    {
+        rest = trimmed;
+        if !rest.is_empty() {
+            out.push(rest.to_string());
+        }
+    }
+    while let Some(trimmed) = strip_trailing_digits(rest) {
+        rest = trimmed;
+        if !rest.is_empty() {
+            out.push(rest.to_string());
+        }
+    }
+    out.dedup();
+    out
+}
+
+/// Strips trailing .NN (e.g. .01, .001) from the end of s.
+fn strip_trailing_digits(s: &str) -> Option<&str> {
+    let t = s.trim_end_matches(|c: char| c.is_ascii_digit());
+    (t.len() < s.len() && t.ends_with('.')).then(|| t.strip_suffix('.').unwrap_or(t))
+}
+
+/// Resolve effective video resolution: from video_info if present, else from companion .mkv when anamorphic.
+fn resolve_effective_resolution(
+    input_file: &Path,
+    video_width: i32,
+    video_height: i32,
+    anamorphic: bool,
+    open_retries: u32,
+    debug: bool,
+) -> (i32, i32) {
+    if video_width != 0 || video_height != 0 {
+        return (video_width, video_height);
+    }
+    if !anamorphic {
+        return (0, 0);
+    }
+    let stem = input_file.file_stem().unwrap_or_default();
+    let parent = input_file.parent().unwrap_or(Path::new("."));
+    let base_names: Vec<OsString> = match stem.to_str() {
+        Some(stem) => companion_mkv_base_candidates(stem)
+            .into_iter()
+            .map(OsString::from)
+            .collect(),
+        // A stem that is not UTF-8 is only tried as it is.
+        None => vec![stem.to_os_string()],
+    };
+    let mut mkv_candidates: Vec<PathBuf> = Vec::new();
+    for base in &base_names {
+        let mut name = base.clone();
+        name.push(".mkv");
+        mkv_candidates.push(parent.join(&name));
+        if let Some(gp) = parent.parent() {
+            mkv_candidates.push(gp.join(&name));
+        }
+    }
+    for path in &mkv_candidates {
+        if path.exists() {
+            if let Some((w, h)) = probe_companion(path, open_retries, debug) {
+                if (w, h) == (1440, 1080) || (w, h) == (1280, 720) || (w, h) == (720, 480) {
+                    if debug {
+                        log_line!("Companion .mkv resolution: {}x{} ({})", w, h, path.display());
+                    }
+                    return (w, h);
+                }
+            }
+        }
+    }
+    (0, 0)
+}
+
+/// Size of a companion .mkv's video; through ffprobe when our FFmpeg cannot open the file, as
+/// when a newer FFmpeg muxed it.
+fn probe_companion(path: &Path, open_retries: u32, debug: bool) -> Option<(i32, i32)> {
+    let e = match probe_video_resolution(path, open_retries) {
+        Ok(size) => return Some(size),
+        Err(e) => e,
+    };
+    if debug {
+        log_line!("Companion .mkv: {}; trying ffprobe", e);
+    }
+    match ffprobe_video_stream(path, FFPROBE_TIMEOUT) {
+        Ok(s) => {
+            if debug {
+                log_line!("ffprobe: {}x{}, {:?} fps", s.width, s.height, s.fps);
+            }
+            Some((s.width, s.height))
+        }
+        Err(e) => {
+            if debug {
+                log_line!("ffprobe: {}", e);
+            }
+            None
+        }
+    }
+}
+
+/// Output layouts selectable with --layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LayoutArg {
+    /// `<base>_bdnxml/` next to the input (or --output)
+    Default,
+    /// XML and images directly in the input's directory (or --output)
+    Flat,
+    /// `<dir>/<base>.xml` with the images in `<dir>/<base>/`
+    Siblings,
+    /// --xml-path and --png-dir templates
+    Custom,
+}
+
+/// Output formats selectable with --format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// BDN XML + PNG
+    Bdn,
+    /// OGM-style chapter file (one chapter per event)
+    Chapters,
+    /// BDN XML + RGBA TIFF (LZW)
+    Tiff,
+    /// EBU STL (Tech 3264) from a second, text decode pass
+    Stl,
+    /// SRT naming each event's image, for previewing the timeline
+    Srt,
+}
+
+impl OutputFormat {
+    /// Image format of the BDN set requested by `formats`, if any. bdn and tiff both write
+    /// `{base}.xml`, so at most one of them may be given. srt names the images, so without
+    /// either it still writes PNGs.
+    fn bdn_images(formats: &[OutputFormat]) -> anyhow::Result<Option<ImageFormat>> {
+        match (
+            formats.contains(&OutputFormat::Bdn),
+            formats.contains(&OutputFormat::Tiff),
+        ) {
+            (true, true) => anyhow::bail!("--format bdn and tiff both write the BDN XML; use one."),
+            (true, false) => Ok(Some(ImageFormat::Png)),
+            (false, true) => Ok(Some(ImageFormat::Tiff)),
+            (false, false) => Ok(formats
+                .contains(&OutputFormat::Srt)
+                .then_some(ImageFormat::Png)),
+        }
+    }
+}
+
+/// Stream types --caption-type selects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CaptionTypeArg {
+    /// Program captions (字幕)
+    Caption,
+    /// Superimposed text (文字スーパー)
+    Superimpose,
+    /// First ARIB subtitle stream, whatever its type
+    Any,
+}
+
+impl CaptionTypeArg {
+    fn filter(self) -> Option<CaptionType> {
+        match self {
+            CaptionTypeArg::Caption => Some(CaptionType::Caption),
+            CaptionTypeArg::Superimpose => Some(CaptionType::Superimpose),
+            CaptionTypeArg::Any => None,
+        }
+    }
+}
+
+/// Operations on existing output instead of converting inputs.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Re-encode the images of existing BDN output in place.
+    Optimize {
+        /// Crop fully transparent borders of each image.
+        #[arg(long)]
+        trim: bool,
+        /// Directory holding the BDN XML and its images.
+        dir: PathBuf,
+    },
+    /// Scale existing BDN output onto another canvas, writing it to --output.
+    Retarget {
+        /// Target canvas, e.g. 1280x720.
+        #[arg(long, value_name = "WxH")]
+        canvas: String,
+        /// Directory holding the BDN XML and its images.
+        dir: PathBuf,
+    },
+    /// Convert a directory of sample recordings and check each against its expectations.
+    Corpus {
+        /// Expectations file (default: corpus.txt in DIR).
+        #[arg(long, value_name = "FILE")]
+        expect: Option<PathBuf>,
+        /// Baseline timings (default: corpus-baseline.json in DIR).
+        #[arg(long, value_name = "FILE")]
+        baseline: Option<PathBuf>,
+        /// Store this run's timings in the baseline.
+        #[arg(long = "update-baseline")]
+        update_baseline: bool,
+        /// Decode and time the samples only; write no output.
+        #[arg(long = "timing-only")]
+        timing_only: bool,
+        /// Directory holding the samples.
+        dir: PathBuf,
+    },
+    /// Join the outputs of --shard runs into one BDN XML, writing it to --output.
+    Combine {
+        /// Output directories of the shards.
+        #[arg(required = true)]
+        dirs: Vec<PathBuf>,
+    },
+}
+
+/// Which event --poster exports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PosterSelect {
+    /// First event
+    First,
+    /// Event with the longest display duration
+    Longest,
+    /// Event with the largest bitmap area
+    Largest,
+}
+
+impl PosterSelect {
+    /// Score of an event under this criterion; a strictly higher score replaces the current pick.
+    fn score(self, duration: f64, bitmap: &BitmapData) -> f64 {
+        match self {
+            PosterSelect::First => 0.0,
+            PosterSelect::Longest => duration,
+            PosterSelect::Largest => bitmap.width as f64 * bitmap.height as f64,
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "arib2bdnxml")]
+#[command(version = VERSION, long_version = long_version(), disable_version_flag = true)]
+#[command(about = "Extract ARIB subtitles from .ts/.m2ts/.mkv/.mks and generate BDN XML + PNG using libaribcaption (via FFmpeg)")]
+#[command(override_usage = "arib2bdnxml [OPTIONS] <INPUT_FILE>...
+       arib2bdnxml [OPTIONS] optimize [--trim] <DIR>
+       arib2bdnxml [OPTIONS] --output <OUT_DIR> retarget --canvas <WxH> <DIR>
+       arib2bdnxml [OPTIONS] [--output <OUT_DIR>] corpus [--timing-only] <DIR>
+       arib2bdnxml [OPTIONS] --output <OUT_DIR> combine <DIR>...
+       arib2bdnxml [OPTIONS] --watch <DIR>")]
+pub struct Cli {
+    /// Use anamorphic output for 1440x1080 (→ 1440x1080)
+    #[arg(short, long)]
+    pub(crate) anamorphic: bool,
+
+    /// For 1440x1080 sources, decode once and write both the anamorphic set and a 1920x1080 set
+    /// (<name>_1920)
+    #[arg(long = "dual-output", conflicts_with_all = ["dar_correct", "split_forced", "checkpoint"])]
+    dual_output: bool,
+
+    /// libaribcaption options (key=value,key=value)
+    #[arg(long = "arib-params", value_name = "OPTIONS")]
+    pub(crate) arib_params: Vec<String>,
+
+    /// Named libaribcaption option bundle (e.g. nhk, ntv, tbs, cx, ex, tx); --arib-params overrides
+    /// its options
+    #[arg(long, value_name = "NAME")]
+    style: Option<String>,
+
+    /// Add or adjust styles: "name key=value,key=value" lines
+    #[arg(long = "style-file", value_name = "FILE")]
+    style_file: Option<PathBuf>,
+
+    /// List the styles and their options, then exit
+    #[arg(long = "list-styles")]
+    list_styles: bool,
+
+    /// Open every input and print the plan (stream, video, canvas, fps, output, previous files)
+    /// without decoding or writing anything
+    #[arg(long = "dry-run", conflicts_with_all = ["watch", "list_streams"])]
+    dry_run: bool,
+
+    /// With --dry-run, print the plan as JSON
+    #[arg(long, requires = "dry_run")]
+    json: bool,
+
+    /// Output directory (one subdirectory per input when several are given)
+    #[arg(short, long, value_name = "DIR")]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    pub(crate) debug: bool,
+
+    /// Use the subtitle stream at container index N
+    #[arg(long = "stream-index", value_name = "N", conflicts_with = "pid")]
+    stream_index: Option<u32>,
+
+    /// Use the subtitle stream with this PID (decimal or 0x hex)
+    #[arg(long, value_name = "PID", value_parser = parse_pid)]
+    pid: Option<i32>,
+
+    /// Reduce probing when --stream-index/--pid is given
+    #[arg(long = "fast-probe")]
+    fast_probe: bool,
+
+    /// Without an ARIB subtitle stream, also try data streams that look like ARIB captions
+    #[arg(long = "aggressive-stream-detect")]
+    aggressive_stream_detect: bool,
+
+    /// Type of subtitle stream to use
+    #[arg(long = "caption-type", value_name = "TYPE", value_enum, default_value = "any")]
+    caption_type: CaptionTypeArg,
+
+    /// List the ARIB subtitle streams and their types, then exit
+    #[arg(long = "list-streams")]
+    list_streams: bool,
+
+    /// Retry transient I/O errors opening an input N times
+    #[arg(long = "open-retries", value_name = "N", default_value_t = 0)]
+    open_retries: u32,
+
+    /// Remember the chosen PID per directory/service across runs
+    #[arg(long = "probe-cache", value_name = "FILE")]
+    probe_cache: Option<PathBuf>,
+
+    /// Report how many DRCS (custom glyph) definitions the captions carry
+    #[arg(long = "drcs-report")]
+    drcs_report: bool,
+
+    /// Do not use fonts attached to the input (MKV/MKS) for rendering
+    #[arg(long = "no-attachment-fonts")]
+    no_attachment_fonts: bool,
+
+    /// Write every subtitle packet (pts, size, decoded) to FILE as CSV (NDJSON for .ndjson/.jsonl)
+    /// and print packet and gap statistics
+    #[arg(long = "packet-log", value_name = "FILE")]
+    packet_log: Option<PathBuf>,
+
+    /// Output formats, comma-separated: bdn, chapters, tiff (BDN XML + RGBA TIFF instead of PNG),
+    /// stl (EBU STL text subtitles), srt (cues naming each event's image, for previewing)
+    #[arg(
+        long = "format",
+        value_name = "FORMAT",
+        value_enum,
+        hide_possible_values = true,
+        value_delimiter = ',',
+        default_value = "bdn"
+    )]
+    formats: Vec<OutputFormat>,
+
+    /// Write monochrome captions as grayscale + alpha PNGs (smaller)
+    #[arg(long = "png-auto-gray")]
+    png_auto_gray: bool,
+
+    /// Try several PNG row filters, with and without a palette, per caption and keep the smallest
+    /// file (slower)
+    #[arg(long = "png-optimize")]
+    png_optimize: bool,
+
+    /// Write <base>.bdsup2sub.sh/.bat running BDSup2Sub with the resolution and frame rate of the
+    /// BDN XML
+    #[arg(long = "emit-bdsup2sub-cmd", conflicts_with = "benchmark")]
+    emit_bdsup2sub_cmd: bool,
+
+    /// Run BDSup2Sub (jar or executable) on the BDN XML after writing and fail if it does not
+    /// produce <base>.sup
+    #[arg(long = "run-bdsup2sub", value_name = "PATH", conflicts_with = "benchmark")]
+    run_bdsup2sub: Option<PathBuf>,
+
+    /// Warn when the events exceed the practical limits of tsmuxer, scenarist or generic
+    /// and suggest where to split
+    #[arg(long = "target-tool", value_name = "TOOL", value_parser = parse_target_tool, default_value = "generic")]
+    target_tool: TargetTool,
+
+    /// Fail instead of warning when the event count or total length exceeds the --target-tool
+    /// limits, and when a graphic would have to be scaled down
+    #[arg(long)]
+    strict: bool,
+
+    /// Split a caption at a fully transparent vertical gap of at least MIN_GAP pixels (default 200)
+    /// into two graphics
+    #[arg(long = "split-horizontal", value_name = "MIN_GAP", num_args = 0..=1, require_equals = true, default_missing_value = "200", value_parser = clap::value_parser!(u32).range(1..))]
+    split_horizontal: Option<u32>,
+
+    /// Write every image at the full canvas size with the caption at its position (Graphic X/Y 0,
+    /// canvas Width/Height)
+    #[arg(long = "full-frame-pngs", conflicts_with = "split_horizontal")]
+    full_frame_pngs: bool,
+
+    /// Time treated as 00:00:00:00: container, video, subtitle, or absolute:<TIME>
+    #[arg(long = "timestamp-base", value_name = "BASE", value_parser = parse_timestamp_base, default_value = "container")]
+    timestamp_base: TimestampBase,
+
+    /// Also write downscaled preview PNGs (default 320px) to thumbs/
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1, require_equals = true, default_missing_value = "320", value_parser = clap::value_parser!(i32).range(16..))]
+    thumbnails: Option<i32>,
+
+    /// Piecewise-linear timestamp correction table ("source target" lines)
+    #[arg(long = "tc-map", value_name = "FILE")]
+    tc_map: Option<PathBuf>,
+
+    /// Write one representative caption PNG to PATH
+    #[arg(long, value_name = "PATH")]
+    poster: Option<PathBuf>,
+
+    /// Which event makes the poster
+    #[arg(long = "poster-select", value_name = "MODE", value_enum, default_value = "first")]
+    poster_select: PosterSelect,
+
+    /// Flatten the poster over this background color
+    #[arg(long = "bg-color", value_name = "#RRGGBB", value_parser = parse_rgb_color)]
+    bg_color: Option<[u8; 3]>,
+
+    /// Scale caption colors, e.g. 0.9 caps white at 90%
+    #[arg(long, value_name = "0.0-1.0", value_parser = parse_brightness, default_value_t = 1.0)]
+    brightness: f64,
+
+    /// Apply gamma to caption colors; above 1 lifts mid-tones
+    #[arg(long, value_name = "GAMMA", value_parser = parse_gamma, default_value_t = 1.0)]
+    gamma: f64,
+
+    /// Clear a background fill covering the whole caption rect except around the text, and crop to
+    /// the text
+    #[arg(long = "trim-background-plate")]
+    trim_background_plate: bool,
+
+    /// Decode the captions as text too and list the characters the first font has no glyph for,
+    /// with their in-TCs (needs fc-match)
+    #[arg(long = "font-coverage")]
+    font_coverage: bool,
+
+    /// Scale down graphics larger than this (never above the canvas); parts off the canvas are
+    /// cropped first
+    #[arg(long = "max-graphic-size", value_name = "WxH", value_parser = parse_max_graphic_size)]
+    max_graphic_size: Option<(i32, i32)>,
+
+    /// Like --bottom-margin, for the top edge
+    #[arg(long = "top-margin", value_name = "PX", default_value_t = 0, value_parser = clap::value_parser!(i32).range(0..), conflicts_with = "full_frame_pngs")]
+    top_margin: i32,
+
+    /// Move captions out of the bottom PX lines, where players show their OSD, keeping captions
+    /// shown together apart
+    #[arg(long = "bottom-margin", value_name = "PX", default_value_t = 0, value_parser = clap::value_parser!(i32).range(0..), conflicts_with = "full_frame_pngs")]
+    bottom_margin: i32,
+
+    /// Like --bottom-margin, for the left edge
+    #[arg(long = "left-margin", value_name = "PX", default_value_t = 0, value_parser = clap::value_parser!(i32).range(0..), conflicts_with = "full_frame_pngs")]
+    left_margin: i32,
+
+    /// Like --bottom-margin, for the right edge
+    #[arg(long = "right-margin", value_name = "PX", default_value_t = 0, value_parser = clap::value_parser!(i32).range(0..), conflicts_with = "full_frame_pngs")]
+    right_margin: i32,
+
+    /// Duration of a last caption without display time; auto estimates reading time from the
+    /// caption size, 1-10s
+    #[arg(long = "default-duration", value_name = "TIME|auto", value_parser = parse_default_duration, default_value = "1.0")]
+    default_duration: DefaultDuration,
+
+    /// A different caption starting this soon after the previous one is a corrected re-send and
+    /// replaces it (0 disables)
+    #[arg(long = "correction-window", value_name = "TIME", value_parser = parse_correction_window, default_value_t = DEFAULT_CORRECTION_WINDOW)]
+    correction_window: f64,
+
+    /// Move every caption by MS milliseconds (negative: earlier) before timecodes are computed
+    #[arg(long = "global-delay", value_name = "MS", value_parser = parse_global_delay, allow_hyphen_values = true, default_value = "0")]
+    global_delay: f64,
+
+    /// Move every caption by TIME ([-][HH:]MM:SS[.mmm] or seconds) after --tc-map and --edl.
+    /// Captions ending before 0 are dropped
+    #[arg(long, value_name = "TIME", value_parser = parse_time_string, allow_hyphen_values = true, default_value = "0")]
+    offset: f64,
+
+    /// Ignore a caption's own display time when it is longer than TIME, as from a broken encoder
+    /// (0 disables)
+    #[arg(long = "max-display-duration", value_name = "TIME", value_parser = parse_max_display_duration, default_value_t = DEFAULT_MAX_DISPLAY_DURATION)]
+    max_display_duration: f64,
+
+    /// End the corrected caption where the correction starts instead of replacing it
+    #[arg(long = "keep-corrections")]
+    keep_corrections: bool,
+
+    /// Beyond N captions starting within one second, coalesce the rest into the newest event (0
+    /// disables)
+    #[arg(long = "max-events-per-second", value_name = "N", default_value_t = DEFAULT_MAX_EVENTS_PER_SECOND)]
+    max_events_per_second: usize,
+
+    /// Close gaps and overlaps of at most FRAMES frames between consecutive events (0 disables)
+    #[arg(long = "snap-gaps", value_name = "FRAMES", default_value_t = DEFAULT_SNAP_GAPS)]
+    snap_gaps: u32,
+
+    /// Make a caption with the same InTC and OutTC as the previous one that event's second graphic
+    /// instead of starting it a frame later
+    #[arg(long = "merge-colliding")]
+    merge_colliding: bool,
+
+    /// Widen shorter gaps between consecutive captions to FRAMES, or with auto to the time a PG
+    /// decoder needs for the next caption's images
+    #[arg(long = "min-gap", value_name = "FRAMES|auto", value_parser = parse_min_gap)]
+    min_gap: Option<MinGap>,
+
+    /// Take the frames from the next caption's start (next) or from the previous caption's end
+    /// (previous)
+    #[arg(long = "min-gap-side", value_name = "SIDE", value_parser = parse_gap_side, default_value = "next", requires = "min_gap")]
+    min_gap_side: GapSide,
+
+    /// Cut list ("start end" lines, MPlayer EDL or Avidemux script): drop captions in cuts and
+    /// shift later ones earlier
+    #[arg(long, value_name = "FILE")]
+    edl: Option<PathBuf>,
+
+    /// Compare caption timing with the cues of a timed .srt or .ass of the same program and report
+    /// offsets and captions with no cue near
+    #[arg(long = "reference-subs", value_name = "FILE")]
+    reference_subs: Option<PathBuf>,
+
+    /// Move InTCs and OutTCs within MS milliseconds of their reference cue onto it
+    #[arg(
+        long = "snap-to-reference",
+        value_name = "MS",
+        requires = "reference_subs"
+    )]
+    snap_to_reference: Option<u32>,
+
+    /// Remove this input's previous PNG/XML outputs before writing
+    #[arg(long, conflicts_with = "no_clobber")]
+    clean: bool,
+
+    /// Fail if the output directory is not empty
+    #[arg(long = "no-clobber")]
+    no_clobber: bool,
+
+    /// Run decode/composite only, write nothing, report throughput
+    #[arg(long)]
+    benchmark: bool,
+
+    /// Pace the run: captions per second (e.g. 5) or input MB/s (20MB/s)
+    #[arg(long = "limit-rate", value_name = "RATE", value_parser = parse_rate_limit)]
+    limit_rate: Option<RateLimit>,
+
+    /// Decoder threads (1 = single-threaded); with --jobs, shared by all jobs
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+
+    /// Convert up to N inputs of a batch at once
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: u32,
+
+    /// Lower CPU priority and, on Linux, use idle I/O priority
+    #[arg(long)]
+    nice: bool,
+
+    /// Mark events whose midpoint is in a "start end" range as Forced
+    #[arg(long = "forced-ranges", value_name = "FILE")]
+    forced_ranges: Option<PathBuf>,
+
+    /// Frame rate for timecodes (e.g. 23.976 or 24000/1001); required when the video stream reports
+    /// no credible rate
+    #[arg(long, value_name = "RATE", value_parser = parse_frame_rate)]
+    fps: Option<f64>,
+
+    /// Video rate to use: avg, r, or guess (default: first credible of avg, r and FFmpeg's guess)
+    #[arg(long = "rate-source", value_name = "SOURCE", value_parser = parse_rate_preference, default_value = "guess", hide_default_value = true)]
+    rate_source: RatePreference,
+
+    /// Use WxH as the video resolution instead of the video stream's (1920x1080, 1440x1080,
+    /// 1280x720 or 720x480)
+    #[arg(long, value_name = "WxH", value_parser = parse_video_size)]
+    canvas: Option<(i32, i32)>,
+
+    /// When the video stream's size or frame rate cannot be read, assume 1920x1080 and 29.97 fps
+    /// instead of stopping
+    #[arg(long = "best-effort")]
+    best_effort: bool,
+
+    /// OutTC is the first frame without the caption (exclusive) or the last frame with it
+    /// (inclusive)
+    #[arg(long = "outtc-semantics", value_name = "MODE", value_parser = parse_outtc_semantics, default_value = "exclusive")]
+    outtc_semantics: OutTcSemantics,
+
+    /// Start the timecodes of the written outputs at this timecode (e.g. 01:00:00:00), added in
+    /// whole frames
+    #[arg(long = "tc-offset", value_name = "HH:MM:SS:FF", value_parser = parse_tc_offset, conflicts_with = "shard")]
+    tc_offset: Option<TcOffset>,
+
+    /// Line endings of the BDN XML
+    #[arg(long = "xml-line-endings", value_name = "lf|crlf", value_parser = parse_line_ending, default_value = "lf")]
+    xml_line_endings: LineEnding,
+
+    /// Indentation of the BDN XML: 0-8 spaces or a tab
+    #[arg(long = "xml-indent", value_name = "N|tab", value_parser = parse_indent, default_value = "2")]
+    xml_indent: Indent,
+
+    /// Scale captions to the source display aspect on a square-pixel frame (1920x1080 or 1280x720)
+    #[arg(long = "dar-correct")]
+    dar_correct: bool,
+
+    /// Render captions into this part of the caption canvas (an inset picture), placed at X,Y
+    #[arg(long, value_name = "WxH+X+Y", value_parser = parse_region)]
+    region: Option<Region>,
+
+    /// Also write forced events only (needs --forced-ranges) to <stem>.forced_bdnxml (or
+    /// <DIR>.forced with -o)
+    #[arg(long = "split-forced", requires = "forced_ranges")]
+    split_forced: bool,
+
+    /// Permissions of the files written, e.g. 0644; directories created also get execute permission
+    /// (Unix only; default: the umask)
+    #[arg(long = "output-mode", value_name = "OCTAL", value_parser = parse_output_mode)]
+    output_mode: Option<u32>,
+
+    /// Directory for the per-run temporary workspace (default: output dir)
+    #[arg(long = "tmp-dir", value_name = "DIR")]
+    tmp_dir: Option<PathBuf>,
+
+    /// Keep the temporary workspace when the run fails
+    #[arg(long = "keep-partial")]
+    keep_partial: bool,
+
+    /// Also write "<in_tc> <out_tc>" lines for every event to PATH
+    #[arg(long = "tc-list", value_name = "PATH")]
+    tc_list: Option<PathBuf>,
+
+    /// Also write each event's position class (top/middle/bottom, left/center/right) and normalized
+    /// geometry to PATH
+    #[arg(long, value_name = "PATH")]
+    positions: Option<PathBuf>,
+
+    /// Class boundaries as canvas fractions (default: 1/3, 2/3)
+    #[arg(long = "position-thresholds", value_name = "V1,V2[,H1,H2]", value_parser = parse_position_thresholds)]
+    position_thresholds: Option<PositionThresholds>,
+
+    /// Keep only sign/lyrics-like events (top or side, short, other color), marked forced; OPTS:
+    /// short=SECS,color=DIST,min=N
+    #[arg(long = "forced-heuristic", value_name = "OPTS", num_args = 0..=1, require_equals = true, default_missing_value = "", value_parser = parse_forced_heuristic, conflicts_with_all = ["forced_ranges", "checkpoint"])]
+    forced_heuristic: Option<ForcedHeuristic>,
+
+    /// Report text/background/outline colors of the first N captions
+    #[arg(long = "style-report", value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "20", value_parser = clap::value_parser!(u32).range(1..))]
+    style_report: Option<u32>,
+
+    /// Number of the first image, to continue the numbering of an earlier part
+    #[arg(long = "start-index", value_name = "N", value_parser = parse_start_index, default_value_t = 0)]
+    start_index: usize,
+
+    /// Reuse one image file for identical captions
+    #[arg(long = "dedup-png")]
+    dedup_png: bool,
+
+    /// Report unique images, repeats, bytes saved and the top repeat (default 20) as JSON
+    #[arg(long = "dedup-report")]
+    dedup_report: bool,
+
+    /// Write each event's dominant (most frequent opaque) color as JSON
+    #[arg(long = "color-analysis", value_name = "PATH")]
+    color_analysis: Option<PathBuf>,
+
+    /// Write the run statistics as JSON, with each event's encoded and decoded image size, the
+    /// adjacent events with the largest decoded size and the run's configuration
+    #[arg(long = "stats-json", value_name = "PATH")]
+    stats_json: Option<PathBuf>,
+
+    /// Name files by their file name only in the --stats-json config
+    #[arg(long = "redact-paths", requires = "stats_json")]
+    redact_paths: bool,
+
+    /// Write <name>.eventmap.json: each event's source frame (decode index, timestamp), what ended
+    /// it, and the frames dropped, with the reason
+    #[arg(long = "event-map")]
+    event_map: bool,
+
+    /// Also list the clear frames in the event map, with their timecodes
+    #[arg(long = "include-clear-events", requires = "event_map")]
+    include_clear_events: bool,
+
+    /// Also write each event's broadcast wall-clock time (from the TDT/TOT) to the event map
+    #[arg(long = "emit-source-times", requires = "event_map")]
+    emit_source_times: bool,
+
+    /// With --emit-source-times, also write each event's packet time and wall-clock time as a
+    /// comment in the BDN XML
+    #[arg(long = "source-time-comments", requires = "emit_source_times")]
+    source_time_comments: bool,
+
+    /// Write a few stills of captions composited over their video frames
+    #[arg(long = "preview-on-video", value_name = "DIR")]
+    preview_on_video: Option<PathBuf>,
+
+    /// Write a 480p QC video of the captions over gray at their times (e.g. out.mkv; needs a build
+    /// with --features preview-video)
+    #[arg(long = "preview-video", value_name = "FILE")]
+    preview_video: Option<PathBuf>,
+
+    /// Run an OCR command on every written image ({png} is the image; e.g. "tesseract {png} - -l
+    /// jpn") and write <name>.ocr.json
+    #[arg(long = "ocr-cmd", value_name = "TEMPLATE")]
+    ocr_cmd: Option<String>,
+
+    /// OCR commands run at once (default: number of CPUs)
+    #[arg(long = "ocr-jobs", value_name = "N", requires = "ocr_cmd", value_parser = clap::value_parser!(u32).range(1..))]
+    ocr_jobs: Option<u32>,
+
+    /// Give up on one image after SECS
+    #[arg(long = "ocr-timeout", value_name = "SECS", requires = "ocr_cmd", default_value_t = DEFAULT_OCR_TIMEOUT_SECS, value_parser = clap::value_parser!(u64).range(1..))]
+    ocr_timeout: u64,
+
+    /// Also write the recognized text as comments in the BDN XML
+    #[arg(long = "ocr-comments", requires = "ocr_cmd")]
+    ocr_comments: bool,
+
+    /// With several inputs, write all into --output itself; fails up front if two inputs share a
+    /// base name
+    #[arg(long = "flat-output", requires = "output")]
+    flat_output: bool,
+
+    /// Where the XML and images go: default (<base>_bdnxml/), flat (all in the input's directory),
+    /// siblings (<dir>/<base>.xml, images in <dir>/<base>/), custom
+    #[arg(
+        long,
+        value_name = "LAYOUT",
+        value_enum,
+        hide_possible_values = true,
+        default_value = "default"
+    )]
+    layout: LayoutArg,
+
+    /// --layout custom: the XML file, with {base}, {dir} and {lang}
+    #[arg(long = "xml-path", value_name = "TEMPLATE", value_parser = parse_path_template)]
+    xml_path: Option<String>,
+
+    /// --layout custom: the image directory, with the same placeholders
+    #[arg(long = "png-dir", value_name = "TEMPLATE", value_parser = parse_path_template)]
+    png_dir: Option<String>,
+
+    /// Check the written BDN XML against the BDN 0.93 structure and that its graphics exist
+    #[arg(long)]
+    verify: bool,
+
+    /// Let optimize/retarget --verify read graphics given by absolute path or outside the XML's
+    /// directory (optimize leaves them as they are)
+    #[arg(long = "allow-external-refs")]
+    allow_external_refs: bool,
+
+    /// Allow attributes outside strict BDN 0.93 (needed by --fade-in/out)
+    #[arg(long = "bdn-extensions")]
+    bdn_extensions: bool,
+
+    /// Declare a default fade-in (DefaultFadeIn) in the BDN XML
+    #[arg(long = "fade-in", value_name = "FRAMES", requires = "bdn_extensions")]
+    fade_in: Option<u32>,
+
+    /// Declare a default fade-out (DefaultFadeOut) in the BDN XML
+    #[arg(long = "fade-out", value_name = "FRAMES", requires = "bdn_extensions")]
+    fade_out: Option<u32>,
+
+    #[arg(help = "Input files (.ts, .m2ts, .mkv, .mks)")]
+    input_files: Vec<PathBuf>,
+
+    /// Record the canvas and each graphic's canvas-relative position and size as XML comments (used
+    /// by retarget)
+    #[arg(long = "emit-normalized")]
+    emit_normalized: bool,
+
+    /// Write a comment with the tool version, input, time, frame rate, canvas and libaribcaption
+    /// options after the XML declaration
+    #[arg(long)]
+    provenance: bool,
+
+    /// Name the input by its full path in the --provenance comment
+    #[arg(long = "full-paths", requires = "provenance")]
+    full_paths: bool,
+
+    /// Convert each .ts/.m2ts/.mkv/.mks appearing in DIR once it stops growing, until Ctrl-C (done
+    /// files are kept in DIR/.arib2bdnxml-watch)
+    #[arg(long, value_name = "DIR")]
+    watch: Option<PathBuf>,
+
+    /// Seconds a file's size must stay unchanged under --watch
+    #[arg(long = "watch-stable", value_name = "SECS", default_value_t = 10)]
+    watch_stable: u64,
+
+    /// Convert only slice N (from 0) of M equal time slices of the input and write
+    /// <base>.shard.json for combine
+    #[arg(long, value_name = "N/M", value_parser = parse_shard, conflicts_with_all = ["watch", "checkpoint", "start_index"])]
+    shard: Option<Shard>,
+
+    /// Convert only captions shown from TIME on (seconds, MM:SS or HH:MM:SS); events crossing it
+    /// are clipped
+    #[arg(long, value_name = "TIME", value_parser = parse_time_string, conflicts_with_all = ["shard", "checkpoint"])]
+    start: Option<f64>,
+
+    /// Convert only captions shown before TIME; events crossing it are clipped
+    #[arg(long, value_name = "TIME", value_parser = parse_time_string, conflicts_with_all = ["shard", "checkpoint"])]
+    end: Option<f64>,
+
+    /// Make --start 00:00:00:00 in the BDN timecodes instead of keeping the times of the whole
+    /// input
+    #[arg(long, requires = "start")]
+    rebase: bool,
+
+    /// Save progress to FILE while converting and resume from it after a crash (same input and
+    /// settings); removed when the run completes
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "watch", "benchmark", "clean", "no_clobber", "poster", "style_report",
+            "dedup_report", "color_analysis", "preview_on_video", "preview_video",
+        ]
+    )]
+    checkpoint: Option<PathBuf>,
+
+    /// New events between checkpoint saves
+    #[arg(long = "checkpoint-every", value_name = "N", default_value_t = DEFAULT_CHECKPOINT_EVERY, value_parser = clap::value_parser!(u32).range(1..), requires = "checkpoint")]
+    checkpoint_every: u32,
+
+    /// Print the features of this build, the --format values and whether FFmpeg has the decoder and
+    /// encoders needed, as JSON
+    #[arg(long)]
+    capabilities: bool,
+
+    /// Show the version (--version: with the features and formats)
+    #[arg(short = 'V', short_alias = 'v', long, action = clap::ArgAction::Version)]
+    version: (),
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Set by `Converter::on_progress`: called after each written event.
+    #[arg(skip)]
+    pub(crate) progress: Option<ProgressHook>,
+
+    /// Set by `parse_cli`: the options given, as --stats-json records them.
+    #[arg(skip)]
+    arguments: Vec<String>,
+}
+
+/// Parses a command line into `Cli`, keeping the options given (`given_arguments`).
+pub fn parse_cli<I, T>(args: I) -> Result<Cli, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let mut args: Vec<std::ffi::OsString> = args.into_iter().map(Into::into).collect();
+    // Nothing at all shows the help, as --help does.
+    if args.len() == 1 {
+        args.push("--help".into());
+    }
+    let matches = Cli::command().try_get_matches_from(args)?;
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    cli.arguments = given_arguments(&Cli::command(), &matches, cli.redact_paths);
+    Ok(cli)
+}
+
+impl Cli {
+    /// The settings of a command line without options: clap's defaults, for `Converter`.
+    pub(crate) fn defaults() -> Cli {
+        Cli::try_parse_from(["arib2bdnxml"]).expect("the defaults parse")
+    }
+}
+
+/// Receives the number of events written so far and the in-TC of the latest one, on the thread
+/// running the conversion (a worker thread with --jobs).
+pub(crate) type ProgressHook = Box<dyn Fn(u64, &str) + Send + Sync>;
+
+/// Runs a parsed command line: a subcommand, or the conversion of its inputs.
+pub fn run_with(mut cli: Cli) -> anyhow::Result<()> {
+    if cfg!(windows) && cli.output_mode.is_some() {
+        warning!("--output-mode has no effect on Windows.");
+    }
+    set_output_mode(cli.output_mode);
+    match cli.command.take() {
+        Some(Command::Optimize { trim, dir }) => return optimize(&cli, &dir, trim),
+        Some(Command::Retarget { canvas, dir }) => return retarget(&cli, &dir, &canvas),
+        Some(Command::Combine { dirs }) => return combine(&cli, &dirs),
+        Some(Command::Corpus {
+            expect,
+            baseline,
+            update_baseline,
+            timing_only,
+            dir,
+        }) => {
+            let expect = expect.unwrap_or_else(|| dir.join(DEFAULT_EXPECTATIONS));
+            let baseline = baseline.unwrap_or_else(|| dir.join(DEFAULT_BASELINE));
+            if timing_only {
+                cli.benchmark = true;
+            }
+            return corpus(cli, &dir, &expect, &baseline, update_baseline);
+        }
+        None => {}
+    }
+    if cli.list_styles {
+        for line in load_styles(&cli)?.list_lines() {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    if let Some(name) = &cli.style {
+        load_styles(&cli)?.get(name)?;
+    }
+    if cli.capabilities {
+        print!(
+            "{}",
+            render_capabilities_json(&build_info(), &FfmpegSupport::probe())
+        );
+        return Ok(());
+    }
+    if let Some(dir) = &cli.watch {
+        if !cli.input_files.is_empty() {
+            anyhow::bail!("--watch takes its inputs from the directory; do not pass input files.");
+        }
+        OutputFormat::bdn_images(&cli.formats)?;
+        return watch(&cli, dir);
+    }
+
+    if cli
+        .input_files
+        .first()
+        .is_none_or(|f| f.as_os_str().is_empty())
+    {
+        anyhow::bail!("Input file not specified (see --help).");
+    }
+
+    for input_file in &cli.input_files {
+        if !input_file.exists() {
+            anyhow::bail!("Input file does not exist: {}", input_file.display());
+        }
+    }
+    if cli.list_streams {
+        for input_file in &cli.input_files {
+            list_streams(input_file, cli.open_retries)?;
+        }
+        return Ok(());
+    }
+    OutputFormat::bdn_images(&cli.formats)?;
+    if cli.nice {
+        for failure in lower_priority() {
+            warning!("--nice: {}", failure);
+        }
+    }
+    if cli.input_files.len() > 1
+        && (cli.poster.is_some()
+            || cli.tc_list.is_some()
+            || cli.positions.is_some()
+            || cli.color_analysis.is_some()
+            || cli.stats_json.is_some()
+            || cli.packet_log.is_some()
+            || cli.preview_video.is_some()
+            || cli.checkpoint.is_some())
+    {
+        anyhow::bail!(
+            "--poster, --tc-list, --positions, --color-analysis, --stats-json, --packet-log, --preview-video and --checkpoint write one file; use them with a single input."
+        );
+    }
+    if (cli.emit_bdsup2sub_cmd || cli.run_bdsup2sub.is_some())
+        && !cli.formats.contains(&OutputFormat::Bdn)
+    {
+        anyhow::bail!(
+            "--emit-bdsup2sub-cmd and --run-bdsup2sub need the PNG BDN output (--format bdn)."
+        );
+    }
+    if let Some(template) = &cli.ocr_cmd {
+        parse_ocr_command(template).map_err(|e| anyhow::anyhow!("Invalid --ocr-cmd: {}", e))?;
+    }
+    if cli.preview_video.is_some() && !cfg!(feature = "preview-video") {
+        anyhow::bail!(
+            "--preview-video needs a build with the preview-video feature (cargo build --release --features preview-video)."
+        );
+    }
+
+    // Plan and prepare every output directory before the first input is opened, so collisions
+    // and --no-clobber failures stop the batch before any work is done.
+    let layout = output_layout(&cli)?;
+    let plans = plan_outputs(
+        &cli.input_files,
+        cli.output.as_deref(),
+        cli.flat_output,
+        &layout,
+    )?;
+    if cli.dry_run {
+        return dry_run(&cli, &plans);
+    }
+    prepare_outputs(&cli, &plans)?;
+    if cli.jobs > 1 && plans.len() > 1 {
+        return convert_parallel(&cli, &plans);
+    }
+
+    let mut failed = 0;
+    for (input_file, plan) in cli.input_files.iter().zip(&plans) {
+        if let Err(e) = convert(&cli, input_file, plan) {
+            if plans.len() == 1 {
+                return Err(e);
+            }
+            log_line!("Error: {}: {}", input_file.display(), e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} inputs failed.", failed, plans.len());
+    }
+    Ok(())
+}
+
+/// The encoded size of an image written to `path`, or a warning when it could not be.
+fn saved_or_warn(saved: anyhow::Result<u64>, path: &Path) -> Option<u64> {
+    match saved {
+        Ok(size) => Some(size),
+        Err(_) => {
+            warning!("failed to save image: {}", path.display());
+            None
+        }
+    }
+}
+
+/// --jobs: converts the inputs with up to --jobs at once, sharing --threads (default: one per
+/// CPU) among them. Failures are listed once all inputs are done, as the batch rules say.
+fn convert_parallel(cli: &Cli, plans: &[OutputPlan]) -> anyhow::Result<()> {
+    let jobs = (cli.jobs as usize).min(plans.len());
+    let total = cli
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get() as u32));
+    let budget = ThreadBudget::new(total);
+    let share = thread_share(total, jobs);
+    let started = Instant::now();
+    let inputs: Vec<(&PathBuf, &OutputPlan)> = cli.input_files.iter().zip(plans).collect();
+    let handler = crate::diagnostics::handler();
+    let results = run_parallel(&inputs, jobs, |&(input_file, plan)| {
+        let lease = budget.acquire(share);
+        set_job(JobContext {
+            prefix: format!("[{}] ", plan.base_name),
+            threads: Some(lease.threads),
+        });
+        let result = with_handler(handler.clone(), || {
+            convert(cli, input_file, plan).map(|_| ())
+        });
+        set_job(JobContext::default());
+        result
+    });
+    let mut failed = 0;
+    for ((input_file, _), result) in inputs.iter().zip(&results) {
+        if let Err(e) = result {
+            crate::diagnostics::emit(
+                Level::Error,
+                format_args!("{}: {}", input_file.display(), e),
+            );
+            failed += 1;
+        }
+    }
+    log_line!(
+        "Batch: {} of {} inputs converted in {:.1}s ({} jobs, {} decoder threads)",
+        plans.len() - failed,
+        plans.len(),
+        started.elapsed().as_secs_f64(),
+        jobs,
+        total
+    );
+    if failed > 0 {
+        anyhow::bail!("{} of {} inputs failed.", failed, plans.len());
+    }
+    Ok(())
+}
+
+/// The --layout of the outputs, with the --xml-path/--png-dir templates of `custom`.
+fn output_layout(cli: &Cli) -> anyhow::Result<Layout> {
+    if cli.flat_output && cli.layout != LayoutArg::Default {
+        anyhow::bail!("--flat-output applies only to --layout default.");
+    }
+    Ok(match (cli.layout, &cli.xml_path, &cli.png_dir) {
+        (LayoutArg::Custom, Some(xml_path), Some(png_dir)) => Layout::Custom {
+            xml_path: xml_path.clone(),
+            png_dir: png_dir.clone(),
+        },
+        (LayoutArg::Custom, _, _) => {
+            anyhow::bail!("--layout custom needs --xml-path and --png-dir.")
+        }
+        (_, None, None) => match cli.layout {
+            LayoutArg::Flat => Layout::Flat,
+            LayoutArg::Siblings => Layout::Siblings,
+            _ => Layout::Default,
+        },
+        _ => anyhow::bail!("--xml-path and --png-dir need --layout custom."),
+    })
+}
+
+/// --dry-run: runs the setup phase for every input and prints the plan; fails if any input
+/// would fail.
+fn dry_run(cli: &Cli, plans: &[OutputPlan]) -> anyhow::Result<()> {
+    let rows: Vec<PlanRow> = cli
+        .input_files
+        .iter()
+        .zip(plans)
+        .map(|(input_file, plan)| PlanRow {
+            input: input_file.display().to_string(),
+            plan: plan_input(cli, input_file, plan).map_err(|e| e.to_string()),
+        })
+        .collect();
+    if cli.json {
+        print!("{}", render_plan_json(&rows));
+    } else {
+        print!("{}", render_plan_table(&rows));
+    }
+    let failed = rows.iter().filter(|r| r.plan.is_err()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} inputs would fail.", failed, rows.len());
+    }
+    Ok(())
+}
+
+/// The --dry-run plan of one input. Nothing is decoded or written.
+fn plan_input(cli: &Cli, input_file: &Path, plan: &OutputPlan) -> anyhow::Result<PlannedInput> {
+    let setup = setup_input(cli, input_file)?;
+    let dir = &plan.output_dir;
+    let existing = if dir.is_dir() {
+        if cli.no_clobber && plan.exclusive && std::fs::read_dir(dir)?.next().is_some() {
+            anyhow::bail!(
+                "Output directory is not empty: {} (--no-clobber)",
+                dir.display()
+            );
+        }
+        existing_outputs(dir, &plan.base_name)?.len()
+    } else {
+        0
+    };
+    if cli.no_clobber && !plan.exclusive && existing > 0 {
+        anyhow::bail!("Output files exist in {} (--no-clobber)", dir.display());
+    }
+    Ok(PlannedInput {
+        pid: setup.ffmpeg.subtitle_stream_pid(),
+        caption_type: setup.ffmpeg.caption_type().map(|t| t.to_string()),
+        video: (setup.video_info.width, setup.video_info.height),
+        canvas: setup.output_canvas,
+        fps: setup.fps,
+        fps_source: setup.fps_source.to_string(),
+        output_dir: plan.output_dir.clone(),
+        base_name: plan.base_name.clone(),
+        existing,
+    })
+}
+
+/// Creates an output directory, naming what the filesystem refused.
+fn create_output_dir(dir: &Path) -> anyhow::Result<()> {
+    crate::sink::create_dir_all(dir).map_err(|e| {
+        let reason = explain(&e);
+        anyhow::anyhow!("Failed to create directory: {}: {}", dir.display(), reason)
+    })
+}
+
+/// Probes each output directory of `plans` with a sample of the file names written there, and
+/// fails before anything is decoded when one cannot take them.
+fn preflight_outputs(cli: &Cli, plans: &[OutputPlan]) -> anyhow::Result<()> {
+    let image_format = OutputFormat::bdn_images(&cli.formats)?;
+    let mut dirs: BTreeMap<&Path, Vec<String>> = BTreeMap::new();
+    for plan in plans {
+        let base = &plan.base_name;
+        let image = image_format.map(|f| generate_image_filename(cli.start_index, base, f));
+        let mut image_dirs = vec![&plan.output_dir];
+        image_dirs.extend(cli.split_forced.then_some(&plan.forced_dir));
+        image_dirs.extend(cli.dual_output.then_some(&plan.square_dir));
+        for dir in image_dirs {
+            dirs.entry(dir).or_default().extend(image.clone());
+        }
+        let names = dirs.entry(&plan.xml_dir).or_default();
+        names.push(format!("{}.xml", plan.xml_base));
+    }
+    let mut problems = Vec::new();
+    for (dir, mut names) in dirs {
+        names.dedup();
+        for limitation in probe_dir(&RealFs, dir, &names) {
+            if limitation.is_fatal() {
+                problems.push(format!("{}: {}", dir.display(), limitation.message()));
+            } else {
+                warning!("{}: {}.", dir.display(), limitation.message());
+            }
+        }
+    }
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "The output location cannot take this run's files:\n  {}",
+            problems.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Creates and prepares the output directories of `plans` (per --clean / --no-clobber).
+fn prepare_outputs(cli: &Cli, plans: &[OutputPlan]) -> anyhow::Result<()> {
+    if cli.benchmark {
+        return Ok(());
+    }
+    let existing_policy = if cli.clean {
+        ExistingOutputPolicy::Clean
+    } else if cli.no_clobber {
+        ExistingOutputPolicy::NoClobber
+    } else {
+        ExistingOutputPolicy::Warn
+    };
+    for plan in plans {
+        create_output_dir(&plan.output_dir)?;
+        if plan.exclusive {
+            prepare_output_dir(&plan.output_dir, &plan.base_name, existing_policy)?;
+        } else {
+            prepare_shared_dir(&plan.output_dir, &plan.base_name, existing_policy)?;
+            create_output_dir(&plan.xml_dir)?;
+            prepare_shared_dir(&plan.xml_dir, &plan.xml_base, existing_policy)?;
+        }
+        if cli.split_forced {
+            create_output_dir(&plan.forced_dir)?;
+            prepare_output_dir(&plan.forced_dir, &plan.base_name, existing_policy)?;
+        }
+        if cli.dual_output {
+            create_output_dir(&plan.square_dir)?;
+            prepare_output_dir(&plan.square_dir, &plan.base_name, existing_policy)?;
+        }
+    }
+    preflight_outputs(cli, plans)
+}
+
+/// --watch: convert each input that appears in `dir` once its size is stable, until Ctrl-C.
+/// With --output, each input gets a subdirectory named after it (unless --flat-output).
+fn watch(cli: &Cli, dir: &Path) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("Directory does not exist: {}", dir.display());
+    }
+    install_interrupt_handler();
+    let mut state = WatchState::load(&dir.join(WATCH_STATE_FILE))?;
+    let mut tracker = StabilityTracker::new(std::time::Duration::from_secs(cli.watch_stable));
+    log_line!(
+        "Watching {} (Ctrl-C stops after the current conversion; press it twice to abort)",
+        dir.display()
+    );
+    while !interrupted() {
+        for path in watch_candidates(dir)? {
+            let name = path
+                .file_name()
+                .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+            if interrupted() || !state.pending(&name) {
+                continue;
+            }
+            let Ok(meta) = std::fs::metadata(&path) else {
+                continue;
+            };
+            if !tracker.observe(&path, meta.len(), Instant::now()) {
+                continue;
+            }
+            log_line!("Converting: {}", path.display());
+            let output = match (&cli.output, cli.flat_output) {
+                (Some(out), false) => Some(out.join(output_base_name(&path))),
+                (out, _) => out.clone(),
+            };
+            let converted = output_layout(cli)
+                .and_then(|layout| plan_outputs(&[&path], output.as_deref(), true, &layout))
+                .and_then(|plans| {
+                    prepare_outputs(cli, &plans)?;
+                    convert(cli, &path, &plans[0])
+                });
+            match converted {
+                Ok(_) => state.mark_done(&name),
+                Err(e) => {
+                    let attempts = state.mark_failed(&name);
+                    log_line!(
+                        "Error: {}: {} (attempt {} of {})",
+                        path.display(),
+                        e,
+                        attempts,
+                        MAX_WATCH_ATTEMPTS
+                    );
+                    if attempts >= MAX_WATCH_ATTEMPTS {
+                        warning!("giving up on {}", path.display());
+                    }
+                }
+            }
+            state.save()?;
+        }
+        sleep_unless_interrupted(WATCH_POLL_INTERVAL);
+    }
+    log_line!("Watch stopped.");
+    Ok(())
+}
+
+/// Converts one input into the directories chosen by the planner, in three stages: setup (the
+/// option files, outputs and opened input), decode (the events, with their images) and write.
+fn convert(cli: &Cli, input_file: &Path, plan: &OutputPlan) -> anyhow::Result<Converted> {
+    let (setup, mut ffmpeg) = setup_conversion(cli, input_file, plan)?;
+    match decode_conversion(cli, input_file, &setup, &mut ffmpeg)? {
+        Some(decoded) => write_conversion(cli, input_file, setup, &mut ffmpeg, decoded),
+        None => write_empty_conversion(cli, input_file, setup, &ffmpeg),
+    }
+}
+
+/// Converts one input as a batch of one would, without the checks of the whole command line:
+/// the entry point of `Converter`.
+pub(crate) fn convert_input(cli: &Cli, input_file: &Path) -> anyhow::Result<Converted> {
+    if !input_file.exists() {
+        anyhow::bail!("Input file does not exist: {}", input_file.display());
+    }
+    OutputFormat::bdn_images(&cli.formats)?;
+    let layout = output_layout(cli)?;
+    let plans = plan_outputs(
+        &[input_file],
+        cli.output.as_deref(),
+        cli.flat_output,
+        &layout,
+    )?;
+    prepare_outputs(cli, &plans)?;
+    convert(cli, input_file, &plans[0])
+}
+
+/// What a conversion wrote: the files, and the events as the BDN XML references them.
+pub(crate) struct Converted {
+    pub(crate) written: Vec<PathBuf>,
+    pub(crate) events: Vec<SubtitleEvent>,
+}
+
+/// What the setup stage decides for one input before anything is decoded. The opened input is
+/// kept apart, as the decode stage reads it while this is borrowed.
+struct ConversionSetup<'a> {
+    bdn_images: Option<ImageFormat>,
+    /// --start/--end.
+    range: Option<(f64, f64)>,
+    tc_map: Option<TcMap>,
+    cut_list: Option<CutList>,
+    forced_ranges: Option<Vec<(f64, f64)>>,
+    libaribcaption_opts: BTreeMap<String, String>,
+    base_name: String,
+    output_dir: PathBuf,
+    xml_dir: PathBuf,
+    xml_base: String,
+    images: ImageReference,
+    /// --split-forced.
+    forced_dir: Option<PathBuf>,
+    /// --dual-output.
+    square_dir: Option<PathBuf>,
+    thumbnails: Option<i32>,
+    thumbs_dir: PathBuf,
+    poster_path: Option<&'a PathBuf>,
+    color_path: Option<&'a PathBuf>,
+    preview_dir: Option<&'a PathBuf>,
+    /// None with --benchmark, which writes nothing.
+    workspace: Option<TempWorkspace>,
+    video_info: VideoInfo,
+    dar: Option<DarCorrection>,
+    fps_source: FrameRateSource,
+    video_params_assumed: bool,
+    probe_time: Duration,
+    /// The canvas --region places captions on.
+    caption_canvas: (i32, i32),
+    canvas_w: i32,
+    canvas_h: i32,
+    bdn_info: BdnInfo,
+    /// --tc-offset, in frames.
+    tc_offset: Option<i64>,
+    resume: Option<Checkpoint>,
+    /// --shard, with the stream times of its slice.
+    shard: Option<(Shard, f64, f64)>,
+    start_index: usize,
+    /// Frames before it are skipped (--shard).
+    shard_start: Option<f64>,
+    /// Decoding stops at the first frame from it (--end).
+    range_end: Option<f64>,
+    write_bdn: bool,
+    canvas_area: usize,
+}
+
+impl ConversionSetup<'_> {
+    /// How frames become events, on the timeline starting at `base_time`.
+    fn event_settings<'s>(&'s self, cli: &Cli, base_time: f64) -> EventSettings<'s> {
+        EventSettings {
+            fps: self.bdn_info.fps,
+            base_time,
+            default_duration: cli.default_duration,
+            canvas_area: self.canvas_area,
+            tc_map: self.tc_map.as_ref(),
+            cut_list: self.cut_list.as_ref(),
+            forced_ranges: self.forced_ranges.as_deref(),
+            base_name: &self.base_name,
+            image_format: self.bdn_images.unwrap_or(ImageFormat::Png),
+            start_index: self.start_index,
+            outtc_semantics: cli.outtc_semantics,
+            split_min_gap: cli.split_horizontal.map(|g| g as usize),
+            full_frame: cli
+                .full_frame_pngs
+                .then_some((self.canvas_w, self.canvas_h)),
+            correction_window: cli.correction_window,
+            keep_corrections: cli.keep_corrections,
+            max_events_per_second: cli.max_events_per_second,
+            snap_gaps: cli.snap_gaps,
+            merge_colliding: cli.merge_colliding,
+            min_gap: cli.min_gap,
+            min_gap_side: cli.min_gap_side,
+            global_delay: cli.global_delay,
+            offset: cli.offset,
+            max_display_duration: cli.max_display_duration,
+            debug: cli.debug,
+        }
+    }
+}
+
+/// What the decode stage hands to the write stage: the events, with their images written, and
+/// what was gathered from them on the way.
+struct DecodedEvents {
+    events: Vec<SubtitleEvent>,
+    base_time: f64,
+    stats: Stats,
+    poster: Option<(f64, BitmapData)>,
+    style: Option<StyleReport>,
+    dedup: Option<DedupIndex>,
+    colors: Vec<Option<Rgb>>,
+    preview: Option<EvenSampler<PreviewSample>>,
+    #[cfg(feature = "preview-video")]
+    video_timeline: Option<PreviewTimeline>,
+}
+
+/// The setup stage: loads the option files, prepares the workspace and opens the input, seeking
+/// to where --checkpoint, --shard or --start begin.
+fn setup_conversion<'a>(
+    cli: &'a Cli,
+    input_file: &Path,
+    plan: &OutputPlan,
+) -> anyhow::Result<(ConversionSetup<'a>, FfmpegWrapper)> {
+    let bdn_images = OutputFormat::bdn_images(&cli.formats)?;
+    let range = time_range(cli)?;
+    let bdn_xml =
+        cli.formats.contains(&OutputFormat::Bdn) || cli.formats.contains(&OutputFormat::Tiff);
+    if cli.shard.is_some() && !bdn_xml {
+        anyhow::bail!("--shard writes a BDN XML for `combine`; use --format bdn or tiff.");
+    }
+    if cli.shard.is_some() && cli.timestamp_base == TimestampBase::Subtitle {
+        anyhow::bail!("--timestamp-base subtitle differs between shards; use another base.");
+    }
+    let tc_map = cli.tc_map.as_deref().map(TcMap::load).transpose()?;
+    let cut_list = cli.edl.as_deref().map(CutList::load).transpose()?;
+    let forced_ranges = match &cli.forced_ranges {
+        Some(path) => {
+            let contents = std::fs::read_to_string(path).map_err(|e| {
+                anyhow::anyhow!("Failed to read forced ranges: {}: {}", path.display(), e)
+            })?;
+            Some(parse_time_ranges(&contents).map_err(|e| {
+                anyhow::anyhow!("Invalid forced ranges: {}: {}", path.display(), e)
+            })?)
+        }
+        None => None,
+    };
+
+    let mut libaribcaption_opts = BTreeMap::new();
+    for s in &cli.arib_params {
+        for (k, v) in parse_libaribcaption_opts(s) {
+            libaribcaption_opts.insert(k, v);
+        }
+    }
+    if let Some(name) = &cli.style {
+        apply_style(load_styles(cli)?.get(name)?, &mut libaribcaption_opts);
+    }
+
+    let base_name = plan.base_name.clone();
+    let output_dir = plan.output_dir.clone();
+    // The BDN XML and the files next to it; the images' directory unless --layout separates them.
+    let (xml_dir, xml_base) = (plan.xml_dir.clone(), plan.xml_base.clone());
+    let images = ImageReference::new(&xml_dir, &output_dir);
+    if let ImageReference::Absolute(dir) = &images {
+        warning!(
+            "{} has no relative path from {}; the BDN XML names its images by absolute path.",
+            dir.display(),
+            xml_dir.display()
+        );
+    }
+    // --split-forced: forced-only set next to the main output.
+    let forced_dir = (cli.split_forced && !cli.benchmark).then(|| plan.forced_dir.clone());
+
+    // --benchmark runs the decode/composite loop without touching the disk.
+    let thumbnails = cli.thumbnails.filter(|_| !cli.benchmark);
+    let poster_path = cli.poster.as_ref().filter(|_| !cli.benchmark);
+    let color_path = cli.color_analysis.as_ref().filter(|_| !cli.benchmark);
+    let preview_dir = cli.preview_on_video.as_ref().filter(|_| !cli.benchmark);
+    let thumbs_dir = output_dir.join("thumbs");
+    let mut workspace = None;
+    if !cli.benchmark {
+        let tmp_parent = cli.tmp_dir.as_ref().unwrap_or(&output_dir);
+        for stale in sweep_stale(tmp_parent, STALE_WORKSPACE_AGE, std::time::SystemTime::now()) {
+            if cli.debug {
+                log_line!("Removed stale temporary directory: {}", stale.display());
+            }
+        }
+        if thumbnails.is_some() {
+            crate::sink::create_dir_all(&thumbs_dir)?;
+        }
+        let ws = TempWorkspace::create(tmp_parent, cli.keep_partial)?;
+        if cli.debug {
+            log_line!("Temporary workspace: {}", ws.path().display());
+        }
+        workspace = Some(ws);
+    }
+
+    let InputSetup {
+        mut ffmpeg,
+        video_info,
+        canvas_size,
+        dar,
+        output_canvas,
+        fps,
+        fps_source,
+        video_params_assumed,
+        probe_time,
+    } = setup_input(cli, input_file)?;
+    if cli.drcs_report {
+        ffmpeg.enable_drcs_report();
+    }
+    if cli.packet_log.is_some() {
+        ffmpeg.enable_packet_log();
+    }
+    // --dual-output: the widened 1920x1080 set, written next to the anamorphic one.
+    let square_dir = match (cli.dual_output, cli.benchmark) {
+        (true, false) if canvas_size == ANAMORPHIC_CANVAS => Some(plan.square_dir.clone()),
+        (true, false) => {
+            warning!(
+                "--dual-output needs a 1440x1080 source (canvas {}); writing one set.",
+                canvas_size
+            );
+            let _ = std::fs::remove_dir(&plan.square_dir);
+            None
+        }
+        _ => None,
+    };
+    // --region: libaribcaption renders into the region; frames are moved onto the full canvas.
+    let caption_canvas = parse_canvas_size(&canvas_size)?;
+    let decoder_canvas = cli.region.map_or(canvas_size, |r| r.canvas_size());
+    libaribcaption_opts.insert("canvas_size".to_string(), decoder_canvas);
+    setup_libaribcaption_defaults(&mut libaribcaption_opts);
+    if !cli.no_attachment_fonts && workspace.is_some() {
+        // One font directory serves the process: decoders of parallel jobs would load each
+        // other's fonts while they are written.
+        if !in_parallel_job() {
+            use_attachment_fonts(&ffmpeg, &mut libaribcaption_opts)?;
+        } else if ffmpeg
+            .attachments()
+            .iter()
+            .any(|a| is_font_attachment(&a.mimetype, &a.filename))
+        {
+            warning!(
+                "font attachments are not used with --jobs; convert this input alone to use them."
+            );
+        }
+    }
+    let (canvas_w, canvas_h) = parse_canvas_size(&output_canvas)?;
+    let bdn_info = BdnInfo {
+        fps,
+        video_format: video_format_from_canvas(&output_canvas).to_string(),
+        fade: (cli.fade_in.is_some() || cli.fade_out.is_some()).then(|| Fade {
+            in_frames: cli.fade_in.unwrap_or(0),
+            out_frames: cli.fade_out.unwrap_or(0),
+        }),
+        normalized: cli.emit_normalized.then_some((canvas_w, canvas_h)),
+        provenance: cli
+            .provenance
+            .then(|| provenance(cli, input_file, fps, &libaribcaption_opts).comment_text()),
+        xml_style: XmlStyle {
+            line_ending: cli.xml_line_endings,
+            indent: cli.xml_indent,
+        },
+    };
+
+    let tc_offset = cli.tc_offset.map(|o| o.to_frames(fps)).transpose()?;
+
+    ffmpeg.init_decoder(&libaribcaption_opts)?;
+    let resume = match &cli.checkpoint {
+        Some(path) => load_checkpoint(path, input_file, &base_name, bdn_info.fps)?,
+        None => None,
+    };
+    if let Some(c) = &resume {
+        ffmpeg.seek_subtitles(c.resume_at)?;
+        log_line!(
+            "Resuming from checkpoint: {} event(s), at {:.3}s",
+            c.events.len(),
+            c.resume_at
+        );
+    }
+    // --shard: decode from SHARD_PREROLL before the slice; frames before it are skipped.
+    let shard = match cli.shard {
+        Some(shard) => {
+            let Some(duration) = video_info.duration else {
+                anyhow::bail!("--shard needs the duration of the input, which is unknown.");
+            };
+            let (start, end) = shard.range(video_info.start_time, duration);
+            if shard.index > 0 {
+                ffmpeg.seek_subtitles((start - SHARD_PREROLL).max(video_info.start_time))?;
+            }
+            log_line!(
+                "Shard {}/{}: {:.3}s to {}",
+                shard.index,
+                shard.count,
+                start,
+                if end.is_finite() {
+                    format!("{:.3}s", end)
+                } else {
+                    "the end".to_string()
+                }
+            );
+            Some((shard, start, end))
+        }
+        None => None,
+    };
+    let start_index = shard.map_or(cli.start_index, |(s, _, _)| s.start_index());
+    let shard_start = shard
+        .filter(|(s, _, _)| s.index > 0)
+        .map(|(_, start, _)| start);
+    // --start/--end: decode from before the range up to its end. The range is in output time,
+    // which --tc-map and --edl move, so then everything is decoded and clipped. A caption still
+    // shown at --start began at most --max-display-duration before it.
+    let range_end = match range {
+        Some((start, end)) if tc_map.is_none() && cut_list.is_none() => {
+            let candidates = TimestampBaseCandidates {
+                container: Some(video_info.start_time),
+                video: video_info.video_start_time,
+                subtitle: None,
+            };
+            // --start/--end are on the output timeline, --offset included.
+            let base = select_timestamp_base(cli.timestamp_base, &candidates)?
+                - (cli.global_delay + cli.offset);
+            if start > 0.0 {
+                let preroll = SHARD_PREROLL.max(cli.max_display_duration);
+                ffmpeg.seek_subtitles((base + start - preroll).max(video_info.start_time))?;
+            }
+            Some(base + end)
+        }
+        _ => None,
+    };
+
+    let setup = ConversionSetup {
+        bdn_images,
+        range,
+        tc_map,
+        cut_list,
+        forced_ranges,
+        libaribcaption_opts,
+        base_name,
+        output_dir,
+        xml_dir,
+        xml_base,
+        images,
+        forced_dir,
+        square_dir,
+        thumbnails,
+        thumbs_dir,
+        poster_path,
+        color_path,
+        preview_dir,
+        workspace,
+        video_info,
+        dar,
+        fps_source,
+        video_params_assumed,
+        probe_time,
+        caption_canvas,
+        canvas_w,
+        canvas_h,
+        bdn_info,
+        tc_offset,
+        resume,
+        shard,
+        start_index,
+        shard_start,
+        range_end,
+        write_bdn: !cli.benchmark && bdn_images.is_some(),
+        canvas_area: (canvas_w.max(0) as usize) * (canvas_h.max(0) as usize),
+    };
+    Ok((setup, ffmpeg))
+}
+
+/// The decode stage: builds the events from the subtitle frames, writing their images, and
+/// applies the options that drop, rename or move them. None when the input has no subtitle
+/// frames.
+fn decode_conversion(
+    cli: &Cli,
+    input_file: &Path,
+    setup: &ConversionSetup,
+    ffmpeg: &mut FfmpegWrapper,
+) -> anyhow::Result<Option<DecodedEvents>> {
+    let ConversionSetup {
+        range,
+        ref libaribcaption_opts,
+        ref base_name,
+        ref output_dir,
+        ref square_dir,
+        thumbnails,
+        ref thumbs_dir,
+        poster_path,
+        color_path,
+        preview_dir,
+        ref video_info,
+        dar,
+        fps_source,
+        video_params_assumed,
+        probe_time,
+        caption_canvas,
+        canvas_w,
+        canvas_h,
+        ref bdn_info,
+        ref resume,
+        shard,
+        start_index,
+        shard_start,
+        range_end,
+        write_bdn,
+        ..
+    } = *setup;
+    let resume_at = resume.as_ref().map(|c| c.resume_at);
+    let mut past_end = false;
+
+    let mut stats = Stats {
+        frame_rate: Some((bdn_info.fps, fps_source)),
+        video_params_assumed,
+        probe_time: Some(probe_time),
+        config: cli.stats_json.as_ref().map(|_| {
+            let canvas = (canvas_w, canvas_h);
+            run_config(cli, input_file, bdn_info.fps, canvas, libaribcaption_opts)
+        }),
+        ..Default::default()
+    };
+    let mut poster: Option<(f64, BitmapData)> = None;
+    let mut style = cli.style_report.map(|n| StyleReport::new(n as usize));
+    let mut dedup = (cli.dedup_png || cli.dedup_report).then(DedupIndex::new);
+    let mut colors = Vec::new();
+    let mut encoded_sizes: HashMap<String, u64> = HashMap::new();
+    let (mut png_bytes_saved, mut png_optimize_timeouts) = (0, 0);
+    let (mut graphics_cropped, mut graphics_scaled) = (0, 0);
+    let mut rejected_graphic: Option<String> = None;
+    let mut event_colors: HashMap<String, Option<Rgb>> = HashMap::new();
+    let mut preview = preview_dir.map(|_| EvenSampler::new(PREVIEW_STILLS));
+    let mut video_timeline = cli
+        .preview_video
+        .as_ref()
+        .filter(|_| !cli.benchmark)
+        .map(|_| PreviewTimeline::new((canvas_w, canvas_h)));
+    let decode_started = Instant::now();
+
+    let mut event_limit = match cli.limit_rate {
+        Some(RateLimit::EventsPerSecond(rate)) => Some(TokenBucket::new(rate, Instant::now())),
+        _ => None,
+    };
+    let tone = Some(ToneLut::new(cli.brightness, cli.gamma)).filter(|lut| !lut.is_identity());
+    let mut frames = std::iter::from_fn(|| ffmpeg.get_next_subtitle_frame())
+        .skip_while(|frame| resume_at.is_some_and(|t| frame.timestamp < t))
+        .skip_while(|frame| shard_start.is_some_and(|t| frame.timestamp < t))
+        .take_while(|frame| {
+            // Up to the first frame after the slice, which ends its last event; `combine` keeps
+            // the event it starts from the next shard.
+            let inside = !past_end;
+            past_end |= shard.is_some_and(|(_, _, end)| frame.timestamp >= end)
+                || range_end.is_some_and(|end| frame.timestamp >= end);
+            inside
+        })
+        .inspect(|frame| {
+            if let (Some(limit), true) = (event_limit.as_mut(), frame.bitmap.is_some()) {
+                limit.pace(1.0);
+            }
+        })
+        .map(|mut frame| {
+            // Before --region, which places captions by their size.
+            let plate = frame.bitmap.as_ref().filter(|_| cli.trim_background_plate);
+            if let Some(trimmed) = plate.and_then(trim_background_plate) {
+                if cli.debug {
+                    log_line!(
+                        "Background plate {:?} trimmed at {:.3}s: {}x{} at ({}, {})",
+                        trimmed.color,
+                        frame.timestamp,
+                        trimmed.bitmap.width,
+                        trimmed.bitmap.height,
+                        frame.x + trimmed.x,
+                        frame.y + trimmed.y
+                    );
+                }
+                frame.x += trimmed.x;
+                frame.y += trimmed.y;
+                frame.bitmap = Some(trimmed.bitmap);
+            }
+            // --region first: --dar-correct and the placement options after it work on
+            // positions on the full caption canvas.
+            if let (Some(region), Some(bitmap)) = (&cli.region, &frame.bitmap) {
+                let size = (bitmap.width, bitmap.height);
+                (frame.x, frame.y) = region.place((frame.x, frame.y), size, caption_canvas);
+            }
+            // Before events are built, so dedup and hashes see the adjusted pixels.
+            if let (Some(lut), Some(bitmap)) = (&tone, &mut frame.bitmap) {
+                adjust_tone(bitmap, lut);
+            }
+            if let (Some(c), Some(bitmap)) = (&dar, &frame.bitmap) {
+                let (scaled, x, y) = c.apply(bitmap, frame.x, frame.y);
+                frame.bitmap = Some(scaled);
+                frame.x = x;
+                frame.y = y;
+            }
+            frame
+        })
+        .map_while(|mut frame| {
+            // Last, on the output canvas: libaribcaption rects may run off it.
+            let Some(bitmap) = &frame.bitmap else {
+                return Some(frame);
+            };
+            let (pos, size) = ((frame.x, frame.y), (bitmap.width, bitmap.height));
+            let canvas = (canvas_w, canvas_h);
+            let fit = fit_graphic(pos, size, canvas, cli.max_graphic_size, cli.strict);
+            if fit == GraphicFit::Reject {
+                rejected_graphic = Some(format!(
+                    "Graphic at {:.3}s is {}x{}, over the canvas or --max-graphic-size (--strict).",
+                    frame.timestamp, size.0, size.1
+                ));
+                return None;
+            }
+            if let Some((fitted, (x, y))) = apply_fit(bitmap, pos, fit) {
+                let action = match fit {
+                    GraphicFit::Scale { .. } => {
+                        graphics_scaled += 1;
+                        "scaled"
+                    }
+                    _ => {
+                        graphics_cropped += 1;
+                        "cropped"
+                    }
+                };
+                if cli.debug {
+                    log_line!(
+                        "Graphic at {:.3}s {}: {}x{} at ({}, {}) -> {}x{} at ({}, {})",
+                        frame.timestamp,
+                        action,
+                        size.0,
+                        size.1,
+                        pos.0,
+                        pos.1,
+                        fitted.width,
+                        fitted.height,
+                        x,
+                        y
+                    );
+                }
+                (frame.x, frame.y) = (x, y);
+                frame.bitmap = Some(fitted);
+            }
+            Some(frame)
+        })
+        .peekable();
+    let first_timestamp = frames.peek().map(|f| f.timestamp);
+    if first_timestamp.is_none() && resume.is_none() {
+        drop(frames);
+        if let Some(message) = rejected_graphic {
+            anyhow::bail!(message);
+        }
+        if cli.debug {
+            log_line!("No subtitle frames found.");
+        }
+        if cli.benchmark {
+            report_benchmark(&stats, 0, decode_started);
+        }
+        return Ok(None);
+    }
+
+    let candidates = TimestampBaseCandidates {
+        container: Some(video_info.start_time),
+        video: video_info.video_start_time,
+        subtitle: first_timestamp,
+    };
+    if cli.debug {
+        log_line!("Timestamp base candidates: {}", candidates.describe());
+    }
+    // A resumed run keeps the base of the run that wrote the checkpoint.
+    let base_time = match &resume {
+        Some(c) => c.base_time,
+        None => select_timestamp_base(cli.timestamp_base, &candidates)?,
+    };
+    if cli.debug {
+        log_line!("Timestamp base: {:?} ({:.3}s)", cli.timestamp_base, base_time);
+    }
+    let settings = setup.event_settings(cli, base_time);
+    let checkpoint_input = match &cli.checkpoint {
+        Some(_) => Some(InputStamp::of(Path::new(input_file))?),
+        None => None,
+    };
+    let mut since_checkpoint = 0;
+    let save_checkpoint = |settled: &[SubtitleEvent], resume_at: f64| {
+        let (Some(path), Some(input)) = (&cli.checkpoint, checkpoint_input) else {
+            return;
+        };
+        since_checkpoint += 1;
+        if since_checkpoint < cli.checkpoint_every {
+            return;
+        }
+        since_checkpoint = 0;
+        let checkpoint = Checkpoint {
+            input,
+            base_name: base_name.clone(),
+            fps: bdn_info.fps,
+            base_time,
+            resume_at,
+            events: settled.to_vec(),
+        };
+        if let Err(e) = checkpoint.save(path) {
+            warning!("{}", e);
+        }
+    };
+    let resumed = resume
+        .as_ref()
+        .map(|c| c.events.clone())
+        .unwrap_or_default();
+    let mut written = 0u64;
+    let on_event = |event: &mut SubtitleEvent, bitmap: &BitmapData, duration: f64| {
+        let mut reused = false;
+        if let Some(index) = &mut dedup {
+            // A corrected caption rewrites the image of the event it replaces.
+            index.forget(&event.png_file);
+            if let Some(file) = index.record(bitmap, &event.png_file) {
+                if cli.dedup_png {
+                    if let Some(second) = &mut event.second {
+                        second.png_file = second_image_filename(&file);
+                    }
+                    event.png_file = file;
+                    reused = true;
+                }
+            }
+        }
+        let mut save_image = |image: &BitmapData, path: &Path| {
+            if !cli.png_optimize || settings.image_format != ImageFormat::Png {
+                return save_bitmap(image, path, settings.image_format, cli.png_auto_gray);
+            }
+            let optimized =
+                save_bitmap_optimized(image, path, cli.png_auto_gray, PNG_OPTIMIZE_BUDGET)?;
+            png_bytes_saved += optimized.saved();
+            png_optimize_timeouts += optimized.timed_out as usize;
+            Ok(optimized.png.len() as u64)
+        };
+        let mut save = |image: &BitmapData, file: &str| {
+            let path = output_dir.join(file);
+            let Some(size) = saved_or_warn(save_image(image, &path), &path) else {
+                return false;
+            };
+            encoded_sizes.insert(file.to_string(), size);
+            let Some(dir) = &square_dir else {
+                return true;
+            };
+            let path = dir.join(file);
+            let wide = widen_bitmap(image);
+            saved_or_warn(save_image(&wide, &path), &path).is_some()
+        };
+        let saved = if !write_bdn || reused {
+            true
+        } else if let Some(second) = &event.second {
+            // --split-horizontal: each graphic gets its own columns of the bitmap.
+            let right = crop_columns(bitmap, second.x - event.x, bitmap.width);
+            save(&crop_columns(bitmap, 0, event.width), &event.png_file)
+                && save(&right, &second.png_file)
+        } else {
+            save(bitmap, &event.png_file)
+        };
+        if !saved {
+            if let Some(index) = &mut dedup {
+                index.forget(&event.png_file);
+            }
+            return false;
+        }
+
+        if let Some(thumb) = thumbnails.filter(|_| !reused).and_then(|w| thumbnail(bitmap, w)) {
+            let thumb_path = thumbs_dir.join(Path::new(&event.png_file).with_extension("png"));
+            if save_bitmap_as_png(&thumb, &thumb_path).is_err() {
+                warning!("failed to save thumbnail: {}", thumb_path.display());
+            }
+        }
+
+        if let Some(report) = &mut style {
+            report.add(bitmap);
+        }
+        if let Some(timeline) = &mut video_timeline {
+            timeline.add(&event.png_file, bitmap);
+        }
+        if let Some(sampler) = &mut preview {
+            sampler.offer(|| PreviewSample {
+                image_file: event.png_file.clone(),
+                in_tc: event.in_tc.clone(),
+                bitmap: bitmap.clone(),
+                x: event.x,
+                y: event.y,
+            });
+        }
+
+        if poster_path.is_some() {
+            let score = cli.poster_select.score(duration, bitmap);
+            if poster.as_ref().is_none_or(|(best, _)| score > *best) {
+                poster = Some((score, bitmap.clone()));
+            }
+        }
+        if color_path.is_some() {
+            colors.push(dominant_color(bitmap));
+        }
+        if cli.forced_heuristic.is_some() {
+            event_colors.insert(event.png_file.clone(), dominant_color(bitmap));
+        }
+        written += 1;
+        if let Some(progress) = &cli.progress {
+            progress(written, &event.in_tc);
+        }
+        true
+    };
+    let mut events = build_events_from(
+        resumed,
+        &mut frames,
+        &settings,
+        &mut stats,
+        on_event,
+        save_checkpoint,
+    );
+    drop(frames);
+    if let Some(message) = rejected_graphic {
+        anyhow::bail!(message);
+    }
+    stats.graphics_cropped = graphics_cropped;
+    stats.graphics_scaled = graphics_scaled;
+    let mut image_dirs = vec![output_dir.as_path()];
+    image_dirs.extend(square_dir.as_deref());
+    let thumbs = thumbnails.map(|_| thumbs_dir.as_path());
+    if write_bdn && !stats.merged_images.is_empty() {
+        // --merge-colliding: a merged image is now the second graphic of the event it joined;
+        // the images after it are numbered without the gap.
+        let merged = &stats.merged_images;
+        rename_event_images(merged, &image_dirs, thumbs, video_timeline.as_mut());
+        let renames = renumber_images(&mut events, start_index, base_name, settings.image_format);
+        rename_event_images(&renames, &image_dirs, thumbs, video_timeline.as_mut());
+        encoded_sizes.clear();
+    }
+    if let Some(range) = range {
+        let fps = bdn_info.fps;
+        let semantics = cli.outtc_semantics;
+        let clipped: Vec<Option<SubtitleEvent>> = events
+            .iter()
+            .map(|e| clip_event(e, range, cli.rebase, semantics, fps))
+            .collect();
+        let keep: Vec<bool> = clipped.iter().map(Option::is_some).collect();
+        stats.event_map.retain(&keep, DropReason::OutOfRange);
+        let dropped: Vec<SubtitleEvent> = events
+            .into_iter()
+            .zip(&keep)
+            .filter(|(_, &keep)| !keep)
+            .map(|(e, _)| e)
+            .collect();
+        events = clipped.into_iter().flatten().collect();
+        if write_bdn && !dropped.is_empty() {
+            let mut dirs = image_dirs.clone();
+            dirs.extend(thumbs);
+            remove_unused_images(&dropped, &events, &dirs);
+            let format = settings.image_format;
+            let renames = renumber_images(&mut events, start_index, base_name, format);
+            rename_event_images(&renames, &image_dirs, thumbs, video_timeline.as_mut());
+            encoded_sizes.clear();
+        }
+    }
+    let mut events = match &cli.forced_heuristic {
+        Some(h) => {
+            let mut dirs = image_dirs.clone();
+            dirs.extend(thumbs);
+            let canvas = (canvas_w, canvas_h);
+            let map = &mut stats.event_map;
+            let (mut kept, dropped) =
+                keep_forced(cli, h, events, &event_colors, canvas, bdn_info.fps, map);
+            if write_bdn {
+                remove_unused_images(&dropped, &kept, &dirs);
+                // Number the kept images without the gaps of the dropped ones.
+                let renames =
+                    renumber_images(&mut kept, start_index, base_name, settings.image_format);
+                rename_event_images(&renames, &image_dirs, thumbs, video_timeline.as_mut());
+                if !renames.is_empty() {
+                    encoded_sizes.clear();
+                }
+            }
+            kept
+        }
+        None => events,
+    };
+    let margins = Margins {
+        top: cli.top_margin,
+        bottom: cli.bottom_margin,
+        left: cli.left_margin,
+        right: cli.right_margin,
+    };
+    if !margins.is_empty() {
+        let canvas = (canvas_w, canvas_h);
+        stats.margin_shifts = apply_margins(&mut events, margins, canvas, bdn_info.fps)?;
+    }
+    if let Some(path) = &cli.reference_subs {
+        let cues = load_reference(path)?;
+        let snap = cli.snap_to_reference.map(|ms| ms as f64 / 1000.0);
+        let (semantics, fps) = (cli.outtc_semantics, bdn_info.fps);
+        let report = compare_with_reference(&mut events, &cues, snap, semantics, fps)?;
+        for line in report.lines() {
+            log_line!("{}", line);
+        }
+        stats.reference_snaps = report.snapped;
+    }
+    if write_bdn && shard.is_some() && events.len() > SHARD_INDEX_STRIDE {
+        anyhow::bail!(
+            "More than {} images in one shard; use more shards.",
+            SHARD_INDEX_STRIDE
+        );
+    }
+    if write_bdn && cli.start_index + events.len() > MAX_IMAGE_INDEX + 1 {
+        warning!(
+            "Image numbers from --start-index {} run past {}; later file names have 6 digits.",
+            cli.start_index,
+            MAX_IMAGE_INDEX
+        );
+    }
+    stats.png_bytes_saved = png_bytes_saved;
+    stats.png_optimize_timeouts = png_optimize_timeouts;
+    // Images of resumed events were written by the earlier run.
+    stats.image_sizes = event_image_sizes(&events, |file| match encoded_sizes.get(file) {
+        Some(&size) => size,
+        None => std::fs::metadata(output_dir.join(file)).map_or(0, |m| m.len()),
+    });
+
+    if cli.benchmark {
+        report_benchmark(&stats, events.len(), decode_started);
+    }
+    Ok(Some(DecodedEvents {
+        events,
+        base_time,
+        stats,
+        poster,
+        style,
+        dedup,
+        colors,
+        preview,
+        #[cfg(feature = "preview-video")]
+        video_timeline,
+    }))
+}
+
+/// The write stage: the outputs of the decoded events, then the checks and reports.
+fn write_conversion(
+    cli: &Cli,
+    input_file: &Path,
+    mut setup: ConversionSetup,
+    ffmpeg: &mut FfmpegWrapper,
+    decoded: DecodedEvents,
+) -> anyhow::Result<Converted> {
+    let workspace = setup.workspace.take();
+    let DecodedEvents {
+        events,
+        base_time,
+        mut stats,
+        poster,
+        style,
+        dedup,
+        colors,
+        preview,
+        #[cfg(feature = "preview-video")]
+        video_timeline,
+    } = decoded;
+    let settings = setup.event_settings(cli, base_time);
+    let ConversionSetup {
+        ref tc_map,
+        ref cut_list,
+        ref forced_ranges,
+        ref libaribcaption_opts,
+        ref base_name,
+        ref output_dir,
+        ref xml_dir,
+        ref xml_base,
+        ref images,
+        ref forced_dir,
+        ref square_dir,
+        poster_path,
+        color_path,
+        preview_dir,
+        canvas_w,
+        canvas_h,
+        ref bdn_info,
+        tc_offset,
+        shard,
+        write_bdn,
+        ..
+    } = setup;
+
+    let ocr_texts = match (&cli.ocr_cmd, &workspace) {
+        (Some(template), Some(_)) => Some(recognize_events(cli, template, &events, output_dir)?),
+        _ => None,
+    };
+    if cli.emit_source_times {
+        stats.event_map.source_times = Some(source_times(cli, input_file));
+    }
+    let source_comments: Option<Vec<String>> = match &stats.event_map.source_times {
+        Some(times) if cli.source_time_comments => Some(
+            (stats.event_map.sources.iter())
+                .map(|s| s.map_or(String::new(), |f| times.comment(f.timestamp)))
+                .collect(),
+        ),
+        _ => None,
+    };
+    let comments = EventComments {
+        ocr: ocr_texts.as_deref().filter(|_| cli.ocr_comments),
+        source: source_comments.as_deref(),
+    };
+    // --tc-offset moves the written timecodes only; the checks above work on the decoded ones.
+    let program_events: Vec<SubtitleEvent> = match tc_offset {
+        Some(frames) => events
+            .iter()
+            .map(|e| offset_event(e, frames, bdn_info.fps))
+            .collect(),
+        None => events.clone(),
+    };
+    let xml_events: Vec<SubtitleEvent> = program_events
+        .iter()
+        .map(|e| reference_images(e, images))
+        .collect();
+    let mut written = match &workspace {
+        Some(ws) => write_outputs(
+            ws,
+            &cli.formats,
+            bdn_info,
+            &xml_events,
+            comments,
+            xml_dir,
+            xml_base,
+        )?,
+        None => Vec::new(),
+    };
+    if let (true, Some(ws)) = (cli.event_map, &workspace) {
+        let name = format!("{}.eventmap.json", xml_base);
+        let path = xml_dir.join(&name);
+        let json = stats.event_map.to_json(&events, cli.include_clear_events);
+        std::fs::write(ws.file(&name), json)
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+        ws.persist(&name, &path)?;
+        written.push(path);
+    }
+    if let (Some((shard, start, end)), Some(ws)) = (shard, &workspace) {
+        let pts = (stats.event_map.sources.iter())
+            .map(|s| s.and_then(|f| f.pts))
+            .collect();
+        let manifest = shard_manifest(shard, start, end, xml_base, base_name, pts);
+        written.push(write_shard_manifest(ws, &manifest, xml_dir, xml_base)?);
+    }
+    if let (Some(texts), Some(ws)) = (&ocr_texts, &workspace) {
+        let name = format!("{}.ocr.json", xml_base);
+        let path = xml_dir.join(&name);
+        std::fs::write(ws.file(&name), render_ocr_json(&events, texts))
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+        ws.persist(&name, &path)?;
+        written.push(path);
+    }
+    if let (Some(dir), Some(ws)) = (&square_dir, &workspace) {
+        let square_events: Vec<SubtitleEvent> = program_events.iter().map(widen_event).collect();
+        written.extend(write_outputs(
+            ws,
+            &cli.formats,
+            &square_bdn_info(bdn_info),
+            &square_events,
+            comments,
+            dir,
+            base_name,
+        )?);
+    }
+    if let (true, Some(ws)) = (cli.emit_bdsup2sub_cmd, &workspace) {
+        let tool = cli.run_bdsup2sub.as_deref().map(|p| p.to_string_lossy());
+        let tool = tool.as_deref().unwrap_or(DEFAULT_BDSUP2SUB);
+        written.extend(write_bdsup2sub_scripts(
+            ws, bdn_info, xml_dir, xml_base, tool,
+        )?);
+        if let Some(dir) = &square_dir {
+            let info = square_bdn_info(bdn_info);
+            written.extend(write_bdsup2sub_scripts(ws, &info, dir, base_name, tool)?);
+        }
+    }
+    if let (Some(dir), Some(ws)) = (&forced_dir, &workspace) {
+        written.extend(write_forced_outputs(
+            ws,
+            &cli.formats,
+            bdn_info,
+            &program_events,
+            output_dir,
+            dir,
+            base_name,
+        )?);
+    }
+
+    // One text decode pass serves --format stl and --font-coverage.
+    let stl = cli.formats.contains(&OutputFormat::Stl) && workspace.is_some();
+    let text_events = if stl || (cli.font_coverage && !cli.benchmark) {
+        let opts = &libaribcaption_opts;
+        Some(decode_text_events(cli, input_file, opts, &settings)?)
+    } else {
+        None
+    };
+    if let (true, Some(ws), Some(text)) = (stl, &workspace, &text_events) {
+        let text: Vec<TextEvent> = match tc_offset {
+            Some(frames) => (text.iter())
+                .map(|e| TextEvent {
+                    in_tc: offset_tc(&e.in_tc, frames, bdn_info.fps),
+                    out_tc: offset_tc(&e.out_tc, frames, bdn_info.fps),
+                    ..e.clone()
+                })
+                .collect(),
+            None => text.clone(),
+        };
+        written.push(write_stl(ws, cli, &text, &settings, xml_dir, xml_base)?);
+    }
+    if let (true, Some(text)) = (cli.font_coverage, &text_events) {
+        report_font_coverage(text, libaribcaption_opts);
+    }
+
+    if let Some(path) = poster_path {
+        match &poster {
+            Some((_, bitmap)) => {
+                let image = match cli.bg_color {
+                    Some(bg) => flatten_over(bitmap, bg),
+                    None => bitmap.clone(),
+                };
+                save_bitmap_as_png(&image, path)?;
+            }
+            None => warning!("no events; poster not written: {}", path.display()),
+        }
+    }
+    if let Some(path) = cli.tc_list.as_ref().filter(|_| !cli.benchmark) {
+        crate::sink::write(path, render_tc_list(&events))
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+        written.push(path.clone());
+    }
+    if let Some(path) = color_path {
+        crate::sink::write(path, render_color_analysis(&events, &colors))
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+        written.push(path.clone());
+    }
+    if let Some(path) = &cli.stats_json {
+        crate::sink::write(path, stats.to_json())
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+        written.push(path.clone());
+    }
+    if let Some(path) = cli.positions.as_ref().filter(|_| !cli.benchmark) {
+        let thresholds = cli.position_thresholds.unwrap_or_default();
+        let positions = render_positions(&events, canvas_w, canvas_h, &thresholds);
+        crate::sink::write(path, positions)
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+        written.push(path.clone());
+    }
+    if let Some(ws) = workspace {
+        ws.finish()?;
+    }
+    if let Some(path) = &cli.checkpoint {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                warning!("failed to remove checkpoint: {}: {}", path.display(), e)
+            }
+            _ => {}
+        }
+    }
+    if cli.verify {
+        // The references are this run's own; --layout siblings/custom point outside the XML's
+        // directory on purpose.
+        verify_outputs(&written, bdn_info, cli.bdn_extensions, true)?;
+        if stats.implausible_durations > 0 {
+            warning!(
+                "{} caption(s) carried a display time over --max-display-duration ({}s); it was ignored.",
+                stats.implausible_durations,
+                cli.max_display_duration
+            );
+        }
+    }
+    if write_bdn {
+        check_target_tool(cli, &events, bdn_info.fps)?;
+    }
+    if let Some(tool) = cli.run_bdsup2sub.as_ref().filter(|_| write_bdn) {
+        let sup = run_bdsup2sub(tool, bdn_info, xml_dir, xml_base)?;
+        crate::sink::apply_file_mode(&sup)
+            .map_err(|e| anyhow::anyhow!("Failed to set permissions: {}: {}", sup.display(), e))?;
+        log_line!("BDSup2Sub: {}", sup.display());
+        written.push(sup);
+    }
+
+    if let (Some(dir), Some(sampler)) = (preview_dir, preview) {
+        if tc_map.is_some() || cut_list.is_some() {
+            warning!(
+                "--preview-on-video looks frames up on the output timeline; with --tc-map/--edl they may be off."
+            );
+        }
+        let samples = sampler.into_items();
+        written.extend(write_previews(
+            ffmpeg,
+            dir,
+            &samples,
+            bdn_info.fps,
+            base_time,
+            (canvas_w, canvas_h),
+        )?);
+    }
+
+    #[cfg(feature = "preview-video")]
+    if let (Some(path), Some(timeline)) = (&cli.preview_video, &video_timeline) {
+        crate::preview_video::write_preview_video(path, timeline, &events, bdn_info.fps)?;
+    }
+
+    report_drcs(ffmpeg, input_file);
+    report_packet_log(cli, ffmpeg)?;
+    report_style(style.as_ref());
+    if let Some(index) = dedup.as_ref().filter(|_| cli.dedup_report) {
+        for line in index.report_lines(output_dir, cli.dedup_png) {
+            log_line!("{}", line);
+        }
+    }
+    if forced_ranges.is_some() {
+        log_line!(
+            "Forced: {} of {} event(s) marked by --forced-ranges",
+            events.iter().filter(|e| e.forced).count(),
+            events.len()
+        );
+    }
+
+    if cli.debug {
+        log_line!("Done: processed {} subtitle events.", events.len());
+        for line in stats.summary_lines() {
+            log_line!("  {}", line);
+        }
+        for path in &written {
+            log_line!("Output: {}", path.display());
+        }
+    }
+
+    Ok(Converted {
+        written,
+        events: xml_events,
+    })
+}
+
+/// The write stage of an input without subtitle frames: empty outputs, so that every input of
+/// a batch has its set.
+fn write_empty_conversion(
+    cli: &Cli,
+    input_file: &Path,
+    setup: ConversionSetup,
+    ffmpeg: &FfmpegWrapper,
+) -> anyhow::Result<Converted> {
+    let ConversionSetup {
+        base_name,
+        output_dir,
+        xml_dir,
+        xml_base,
+        forced_dir,
+        square_dir,
+        workspace,
+        bdn_info,
+        shard,
+        ..
+    } = setup;
+    let mut written = Vec::new();
+    if let Some(ws) = workspace {
+        let none = EventComments::default();
+        written = write_outputs(&ws, &cli.formats, &bdn_info, &[], none, &xml_dir, &xml_base)?;
+        if let Some((shard, start, end)) = shard {
+            let manifest = shard_manifest(shard, start, end, &xml_base, &base_name, Vec::new());
+            written.push(write_shard_manifest(&ws, &manifest, &xml_dir, &xml_base)?);
+        }
+        if let Some(dir) = &square_dir {
+            let info = square_bdn_info(&bdn_info);
+            written.extend(write_outputs(
+                &ws,
+                &cli.formats,
+                &info,
+                &[],
+                none,
+                dir,
+                &base_name,
+            )?);
+        }
+        if let Some(dir) = &forced_dir {
+            written.extend(write_forced_outputs(
+                &ws,
+                &cli.formats,
+                &bdn_info,
+                &[],
+                &output_dir,
+                dir,
+                &base_name,
+            )?);
+        }
+        ws.finish()?;
+    }
+    report_drcs(ffmpeg, input_file);
+    report_packet_log(cli, ffmpeg)?;
+    let style = cli.style_report.map(|n| StyleReport::new(n as usize));
+    report_style(style.as_ref());
+    Ok(Converted {
+        written,
+        events: Vec::new(),
+    })
+}
+
+/// What the setup phase decides for one input before anything is decoded: the selected stream
+/// (in the opened wrapper), canvas, DAR correction and frame rate. Shared by conversion and
+/// --dry-run.
+struct InputSetup {
+    ffmpeg: FfmpegWrapper,
+    video_info: VideoInfo,
+    /// libaribcaption canvas ("WxH").
+    canvas_size: String,
+    dar: Option<DarCorrection>,
+    /// Frame the events are placed on: the canvas, or the DAR-corrected target.
+    output_canvas: String,
+    fps: f64,
+    fps_source: FrameRateSource,
+    /// --best-effort replaced parameters the video stream lacks with defaults.
+    video_params_assumed: bool,
+    /// Time `open_input` took (--benchmark).
+    probe_time: Duration,
+}
+
+/// Video parameters the stream lacks (see `VideoInfo::probe_problems`) must come from --canvas
+/// and --fps; with --best-effort the defaults are assumed instead, with a warning. Returns
+/// whether they were.
+fn check_video_params(cli: &Cli, video_info: &VideoInfo) -> anyhow::Result<bool> {
+    let missing: Vec<(&str, String)> = video_info
+        .probe_problems()
+        .into_iter()
+        .filter_map(|problem| match problem {
+            VideoProbeProblem::NoSize if cli.canvas.is_none() => {
+                Some(("--canvas WxH", format!("a {} video", DEFAULT_CANVAS)))
+            }
+            VideoProbeProblem::NoFrameRate if cli.fps.is_none() => {
+                Some(("--fps RATE", format!("{} fps", DEFAULT_FPS)))
+            }
+            _ => None,
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(false);
+    }
+    let options: Vec<&str> = missing.iter().map(|(option, _)| *option).collect();
+    let defaults: Vec<&str> = missing
+        .iter()
+        .map(|(_, default)| default.as_str())
+        .collect();
+    let failed = format!(
+        "Video stream parameters could not be read ({}, --rate-source {}); the video stream may be damaged.",
+        video_info.describe(),
+        cli.rate_source
+    );
+    if !cli.best_effort {
+        anyhow::bail!(
+            "{} Specify {}, or pass --best-effort to assume {}.",
+            failed,
+            options.join(" and "),
+            defaults.join(" at ")
+        );
+    }
+    warning!(
+        "{} Assuming {} (--best-effort); specify {} if that is wrong.",
+        failed,
+        defaults.join(" at "),
+        options.join(" and ")
+    );
+    Ok(true)
+}
+
+/// Opens `input_file` and runs the setup phase (stream selection, resolution, canvas, frame
+/// rate).
+fn setup_input(cli: &Cli, input_file: &Path) -> anyhow::Result<InputSetup> {
+    let mut ffmpeg = new_ffmpeg(cli);
+    let probe_started = Instant::now();
+    open_input(&mut ffmpeg, cli, input_file)?;
+    let probe_time = probe_started.elapsed();
+
+    let video_info = ffmpeg.get_video_info();
+    let video_params_assumed = check_video_params(cli, &video_info)?;
+    // --dual-output decodes on the anamorphic canvas and widens the second set itself.
+    let anamorphic = cli.anamorphic || cli.dual_output;
+    let (effective_width, effective_height) = match cli.canvas {
+        Some(size) => size,
+        None => resolve_effective_resolution(
+            input_file,
+            video_info.width,
+            video_info.height,
+            anamorphic,
+            cli.open_retries,
+            cli.debug,
+        ),
+    };
+    let canvas_size =
+        determine_canvas_size(effective_width, effective_height, anamorphic, cli.debug)?;
+    let (canvas_w, canvas_h) = parse_canvas_size(&canvas_size)?;
+    if let Some(region) = &cli.region {
+        region.check(canvas_w, canvas_h)?;
+    }
+
+    let dar = if cli.dar_correct {
+        let display_aspect = if video_info.height > 0 {
+            video_info.width as f64 * video_info.sample_aspect_ratio / video_info.height as f64
+        } else {
+            0.0
+        };
+        let correction = dar_correction(canvas_w, canvas_h, display_aspect);
+        match &correction {
+            Some(c) if cli.debug => log_line!(
+                "DAR correction: display aspect {:.4}, scale {:.4}x{:.4}, offset {:.1},{:.1}, target {}",
+                display_aspect,
+                c.scale_x,
+                c.scale_y,
+                c.offset_x,
+                c.offset_y,
+                c.target_canvas()
+            ),
+            Some(_) => {}
+            None => warning!("video sample aspect ratio unknown; --dar-correct ignored."),
+        }
+        correction
+    } else {
+        None
+    };
+    let output_canvas = dar.map_or(canvas_size.clone(), |c| c.target_canvas());
+
+    let (fps, fps_source) = match (cli.fps, video_info.fps_source) {
+        (Some(fps), _) => (fps, FrameRateSource::User),
+        (None, Some(source)) => (video_info.fps, source),
+        // check_video_params let an unreadable rate through only with --best-effort.
+        (None, None) if video_info.frame_rates.is_some() => (DEFAULT_FPS, FrameRateSource::Assumed),
+        (None, None) => (DEFAULT_FPS, FrameRateSource::Default),
+    };
+    if cli.debug {
+        log_line!("Frame rate: {:.3} ({})", fps, fps_source);
+    }
+    Ok(InputSetup {
+        ffmpeg,
+        video_info,
+        canvas_size,
+        dar,
+        output_canvas,
+        fps,
+        fps_source,
+        video_params_assumed,
+        probe_time,
+    })
+}
+
+/// Comments written inside each Event of the BDN XML, one per event.
+#[derive(Debug, Clone, Copy, Default)]
+struct EventComments<'a> {
+    /// --ocr-comments
+    ocr: Option<&'a [String]>,
+    /// --source-time-comments
+    source: Option<&'a [String]>,
+}
+
+impl<'a> EventComments<'a> {
+    /// The OCR and Source comments of event `i`; empty when it has none.
+    fn of(&self, i: usize) -> (&'a str, &'a str) {
+        let nth = |c: Option<&'a [String]>| c.and_then(|c| c.get(i)).map_or("", String::as_str);
+        (nth(self.ocr), nth(self.source))
+    }
+}
+
+/// The broadcast clock of `input_file` for --emit-source-times; without one, events get no
+/// wall-clock time.
+fn source_times(cli: &Cli, input_file: &Path) -> SourceTimes {
+    let clock = match read_broadcast_clock(input_file) {
+        Ok(clock) => clock,
+        Err(e) => {
+            log_line!("Cannot read TDT/TOT from {}: {}", input_file.display(), e);
+            None
+        }
+    };
+    match clock {
+        Some(c) if cli.debug => log_line!("Broadcast clock: TDT/TOT at {:.3}s", c.stream_time),
+        Some(_) => {}
+        None => log_line!(
+            "No TDT/TOT in the first {} MiB of the input; wall-clock times are left out.",
+            SCAN_LIMIT >> 20
+        ),
+    }
+    SourceTimes { clock }
+}
+
+/// Write every requested output format for the collected events. Files are written into the
+/// workspace and then moved into `output_dir`. Returns the written paths.
+fn write_outputs(
+    workspace: &TempWorkspace,
+    formats: &[OutputFormat],
+    bdn_info: &BdnInfo,
+    events: &[SubtitleEvent],
+    comments: EventComments,
+    output_dir: &Path,
+    base_name: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for format in formats {
+        match format {
+            OutputFormat::Bdn | OutputFormat::Tiff => {
+                let mut generator = BdnXmlGenerator::new(bdn_info.clone());
+                for (i, event) in events.iter().enumerate() {
+                    let (ocr, source) = comments.of(i);
+                    generator.add_event_with_comments(event, ocr, source);
+                }
+                let name = format!("{}.xml", base_name);
+                let xml_path = output_dir.join(&name);
+                generator.write_to_file(&workspace.file(&name))?;
+                workspace.persist(&name, &xml_path)?;
+                written.push(xml_path);
+            }
+            OutputFormat::Chapters => {
+                let mut generator = ChapterGenerator::new(bdn_info.clone());
+                for event in events {
+                    generator.add_event(event);
+                }
+                let name = format!("{}.chapters.txt", base_name);
+                let path = output_dir.join(&name);
+                generator.write_to_file(&workspace.file(&name))?;
+                workspace.persist(&name, &path)?;
+                written.push(path);
+            }
+            OutputFormat::Srt => {
+                let mut generator = SrtGenerator::new(bdn_info.clone());
+                for event in events {
+                    generator.add_event(event);
+                }
+                let name = format!("{}.srt", base_name);
+                let path = output_dir.join(&name);
+                generator.write_to_file(&workspace.file(&name))?;
+                workspace.persist(&name, &path)?;
+                written.push(path);
+            }
+            // Needs its own decode pass; written by write_stl.
+            OutputFormat::Stl => {}
+        }
+    }
+    Ok(written)
+}
+
+/// --start/--end in seconds of the output timeline; the end is infinite when only --start is
+/// given.
+fn time_range(cli: &Cli) -> anyhow::Result<Option<(f64, f64)>> {
+    if cli.start.is_none() && cli.end.is_none() {
+        return Ok(None);
+    }
+    let (start, end) = (cli.start.unwrap_or(0.0), cli.end.unwrap_or(f64::INFINITY));
+    if start < 0.0 {
+        anyhow::bail!("--start must not be negative.");
+    }
+    if end <= start {
+        anyhow::bail!("--end must be after --start.");
+    }
+    if cli.timestamp_base == TimestampBase::Subtitle {
+        anyhow::bail!("--timestamp-base subtitle is not known before decoding; use another base.");
+    }
+    Ok(Some((start, end)))
+}
+
+/// The --shard manifest of an output whose events come from packets with `pts`.
+fn shard_manifest(
+    shard: Shard,
+    start: f64,
+    end: f64,
+    xml_base: &str,
+    base_name: &str,
+    pts: Vec<Option<i64>>,
+) -> ShardManifest {
+    ShardManifest {
+        shard,
+        start,
+        end: end.is_finite().then_some(end),
+        xml: format!("{}.xml", xml_base),
+        base_name: base_name.to_string(),
+        pts,
+    }
+}
+
+/// --shard: `<base>.shard.json` in `dir`, read by `combine`.
+fn write_shard_manifest(
+    workspace: &TempWorkspace,
+    manifest: &ShardManifest,
+    dir: &Path,
+    base_name: &str,
+) -> anyhow::Result<PathBuf> {
+    let name = format!("{}{}", base_name, MANIFEST_SUFFIX);
+    let path = dir.join(&name);
+    std::fs::write(workspace.file(&name), manifest.to_json())
+        .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+    workspace.persist(&name, &path)?;
+    Ok(path)
+}
+
+/// --emit-bdsup2sub-cmd: `<base>.bdsup2sub.sh` (executable) and `.bat` in `dir`.
+fn write_bdsup2sub_scripts(
+    workspace: &TempWorkspace,
+    info: &BdnInfo,
+    dir: &Path,
+    base_name: &str,
+    tool: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let scripts = [
+        ("sh", render_sh_script(info, base_name, tool)),
+        ("bat", render_bat_script(info, base_name, tool)),
+    ];
+    let mut written = Vec::new();
+    for (extension, contents) in scripts {
+        let name = format!("{}.bdsup2sub.{}", base_name, extension);
+        let path = dir.join(&name);
+        let file = workspace.file(&name);
+        std::fs::write(&file, contents)
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+        #[cfg(unix)]
+        if extension == "sh" {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755))?;
+        }
+        workspace.persist(&name, &path)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// --forced-heuristic: keeps the events that look like signs or lyrics, marked forced, and returns
+/// them with the dropped ones, which `map` records.
+fn keep_forced(
+    cli: &Cli,
+    h: &ForcedHeuristic,
+    events: Vec<SubtitleEvent>,
+    colors: &HashMap<String, Option<Rgb>>,
+    (canvas_w, canvas_h): (i32, i32),
+    fps: f64,
+    map: &mut EventMap,
+) -> (Vec<SubtitleEvent>, Vec<SubtitleEvent>) {
+    let thresholds = cli.position_thresholds.unwrap_or_default();
+    let traits: Vec<EventTraits> = events
+        .iter()
+        .map(|e| {
+            // Both graphics of a split event count as one caption.
+            let right = e.second.as_ref().map_or(e.x + e.width, |g| g.x + g.width);
+            let seconds = |tc: &str| tc_to_seconds(tc, fps).unwrap_or(0.0);
+            EventTraits {
+                position: classify(
+                    e.x,
+                    e.y,
+                    right - e.x,
+                    e.height,
+                    canvas_w,
+                    canvas_h,
+                    &thresholds,
+                ),
+                duration: seconds(&e.out_tc) - seconds(&e.in_tc),
+                color: colors.get(&e.png_file).copied().flatten(),
+            }
+        })
+        .collect();
+    let signals = forced_signals(&traits, h);
+    log_line!("{}", forced_summary(&signals, h));
+    let keep: Vec<bool> = signals.iter().map(|s| s.score() >= h.min_score).collect();
+    map.retain(&keep, DropReason::ForcedHeuristic);
+    let (mut kept, mut dropped) = (Vec::new(), Vec::new());
+    for (mut event, keep) in events.into_iter().zip(keep) {
+        if keep {
+            event.forced = true;
+            kept.push(event);
+        } else {
+            dropped.push(event);
+        }
+    }
+    (kept, dropped)
+}
+
+/// Deletes the images of `dropped` events from `dirs`, except those `kept` events still use
+/// (--dedup-png).
+fn remove_unused_images(dropped: &[SubtitleEvent], kept: &[SubtitleEvent], dirs: &[&Path]) {
+    let files = |e: &SubtitleEvent| {
+        std::iter::once(e.png_file.clone()).chain(e.second.as_ref().map(|g| g.png_file.clone()))
+    };
+    let used: HashSet<String> = kept.iter().flat_map(files).collect();
+    let unused: HashSet<String> = dropped
+        .iter()
+        .flat_map(files)
+        .filter(|f| !used.contains(f))
+        .collect();
+    for dir in dirs {
+        for file in &unused {
+            let path = dir.join(file);
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    warning!("failed to remove image: {}: {}", path.display(), e)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Applies the (old, new) image renames of `renumber_images` in each of `dirs`. Goes through
+/// temporary names, as a new name can be the old name of a later image.
+fn rename_images(renames: &[(String, String)], dirs: &[&Path]) {
+    for dir in dirs {
+        let temporary = |file: &str| dir.join(format!("{}.renumber", file));
+        let mut moved = Vec::new();
+        for (old, new) in renames {
+            let path = dir.join(old);
+            match std::fs::rename(&path, temporary(old)) {
+                Ok(()) => moved.push((old, new)),
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    warning!("failed to rename image: {}: {}", path.display(), e)
+                }
+                _ => {}
+            }
+        }
+        for (old, new) in moved {
+            let path = dir.join(new);
+            if let Err(e) = std::fs::rename(temporary(old), &path) {
+                warning!("failed to rename image: {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Applies image renames in `image_dirs`, to the thumbnails (always PNG) in `thumbs_dir` and to
+/// the --preview-video timeline.
+fn rename_event_images(
+    renames: &[(String, String)],
+    image_dirs: &[&Path],
+    thumbs_dir: Option<&Path>,
+    video_timeline: Option<&mut PreviewTimeline>,
+) {
+    rename_images(renames, image_dirs);
+    if let Some(dir) = thumbs_dir {
+        let png = |f: &str| Path::new(f).with_extension("png").display().to_string();
+        let thumbs: Vec<(String, String)> = renames
+            .iter()
+            .map(|(old, new)| (png(old), png(new)))
+            .collect();
+        rename_images(&thumbs, &[dir]);
+    }
+    if let Some(timeline) = video_timeline {
+        timeline.rename(renames);
+    }
+}
+
+/// The --provenance record of this run; the input is named by its file name unless --full-paths.
+fn provenance(
+    cli: &Cli,
+    input_file: &Path,
+    fps: f64,
+    libaribcaption_opts: &BTreeMap<String, String>,
+) -> Provenance {
+    let path = Path::new(input_file);
+    let input = if cli.full_paths {
+        std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+    } else {
+        path.file_name().map_or(path.to_path_buf(), PathBuf::from)
+    };
+    Provenance {
+        tool: format!("arib2bdnxml {}", VERSION),
+        input: input.display().to_string(),
+        created: SystemTime::now(),
+        fps,
+        canvas: libaribcaption_opts
+            .get("canvas_size")
+            .cloned()
+            .unwrap_or_default(),
+        decoder_options: libaribcaption_opts
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        tone: (cli.brightness, cli.gamma),
+    }
+}
+
+/// The --stats-json `config` of this run; --redact-paths names the input by its file name.
+fn run_config(
+    cli: &Cli,
+    input_file: &Path,
+    fps: f64,
+    canvas: (i32, i32),
+    libaribcaption_opts: &BTreeMap<String, String>,
+) -> RunConfig {
+    RunConfig {
+        tool: format!("arib2bdnxml {}", VERSION),
+        input: match cli.redact_paths {
+            true => redact_path(&input_file.to_string_lossy()),
+            false => input_file.display().to_string(),
+        },
+        arguments: cli.arguments.clone(),
+        decoder_options: libaribcaption_opts.clone(),
+        canvas,
+        fps,
+        ffmpeg: crate::ffmpeg::library_versions(),
+    }
+}
+
+/// `event` with its images named as the BDN XML references them.
+fn reference_images(event: &SubtitleEvent, images: &ImageReference) -> SubtitleEvent {
+    let mut event = event.clone();
+    event.png_file = images.file(&event.png_file);
+    if let Some(second) = &mut event.second {
+        second.png_file = images.file(&second.png_file);
+    }
+    event
+}
+
+/// BDN settings of the --dual-output 1920x1080 set.
+fn square_bdn_info(info: &BdnInfo) -> BdnInfo {
+    let (width, height) = SQUARE_CANVAS;
+    BdnInfo {
+        video_format: video_format_from_canvas(&format!("{}x{}", width, height)).to_string(),
+        normalized: info.normalized.map(|_| SQUARE_CANVAS),
+        ..info.clone()
+    }
+}
+
+/// --ocr-cmd: recognizes every written image and returns the text of each event (its graphics'
+/// texts on separate lines). A failed image only leaves its text empty.
+fn recognize_events(
+    cli: &Cli,
+    template: &str,
+    events: &[SubtitleEvent],
+    output_dir: &Path,
+) -> anyhow::Result<Vec<String>> {
+    let words =
+        parse_ocr_command(template).map_err(|e| anyhow::anyhow!("Invalid --ocr-cmd: {}", e))?;
+    let mut files: Vec<&str> = Vec::new();
+    for event in events {
+        files.push(&event.png_file);
+        files.extend(event.second.as_ref().map(|g| g.png_file.as_str()));
+    }
+    files.sort_unstable();
+    files.dedup();
+    let images: Vec<PathBuf> = files.iter().map(|f| output_dir.join(f)).collect();
+    let jobs = cli.ocr_jobs.map_or_else(
+        || std::thread::available_parallelism().map_or(1, |n| n.get()),
+        |n| n as usize,
+    );
+    let results = run_ocr(
+        &words,
+        &images,
+        jobs,
+        std::time::Duration::from_secs(cli.ocr_timeout),
+    );
+    let mut texts: HashMap<&str, String> = HashMap::new();
+    let mut failures = 0;
+    for ((file, image), result) in files.iter().zip(&images).zip(results) {
+        let text = result.unwrap_or_else(|e| {
+            warning!("OCR failed: {}: {}", image.display(), e);
+            failures += 1;
+            String::new()
+        });
+        texts.insert(file, text);
+    }
+    let event_texts: Vec<String> = events
+        .iter()
+        .map(|event| {
+            std::iter::once(&event.png_file)
+                .chain(event.second.as_ref().map(|g| &g.png_file))
+                .map(|f| texts[f.as_str()].as_str())
+                .filter(|t| !t.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect();
+    log_line!("{}", ocr_summary(&event_texts, failures));
+    Ok(event_texts)
+}
+
+/// Write the forced-only output set (--split-forced) into `forced_dir`. PNGs already written to
+/// `output_dir` are hard-linked (or copied) rather than re-encoded.
+fn write_forced_outputs(
+    workspace: &TempWorkspace,
+    formats: &[OutputFormat],
+    bdn_info: &BdnInfo,
+    events: &[SubtitleEvent],
+    output_dir: &Path,
+    forced_dir: &Path,
+    base_name: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let forced: Vec<SubtitleEvent> = events.iter().filter(|e| e.forced).cloned().collect();
+    if formats.contains(&OutputFormat::Bdn) || formats.contains(&OutputFormat::Tiff) {
+        for event in &forced {
+            let files = std::iter::once(&event.png_file)
+                .chain(event.second.as_ref().map(|g| &g.png_file));
+            for file in files {
+                link_or_copy(&output_dir.join(file), &forced_dir.join(file))?;
+            }
+        }
+    }
+    let none = EventComments::default();
+    write_outputs(
+        workspace, formats, bdn_info, &forced, none, forced_dir, base_name,
+    )
+}
+
+/// Decodes the input again with text output (--format stl, --font-coverage).
+fn decode_text_events(
+    cli: &Cli,
+    input_file: &Path,
+    libaribcaption_opts: &BTreeMap<String, String>,
+    settings: &EventSettings,
+) -> anyhow::Result<Vec<TextEvent>> {
+    let mut ffmpeg = new_ffmpeg(cli);
+    open_input(&mut ffmpeg, cli, input_file)?;
+    ffmpeg.set_text_output(true);
+    ffmpeg.init_decoder(libaribcaption_opts)?;
+    let (_, canvas_h) = match libaribcaption_opts.get("canvas_size") {
+        Some(s) => parse_canvas_size(s)?,
+        None => anyhow::bail!("canvas_size not set."),
+    };
+    let frames = std::iter::from_fn(|| ffmpeg.get_next_subtitle_frame());
+    Ok(build_text_events(frames, settings, canvas_h))
+}
+
+/// --format stl: write the text events as `{base}.stl`. STL's Latin character table cannot hold
+/// Japanese, so unrepresentable characters are reported.
+fn write_stl(
+    workspace: &TempWorkspace,
+    cli: &Cli,
+    events: &[TextEvent],
+    settings: &EventSettings,
+    output_dir: &Path,
+    base_name: &str,
+) -> anyhow::Result<PathBuf> {
+    let (stl, replaced) = render_stl(events, settings.fps, base_name, SystemTime::now())?;
+    if replaced > 0 {
+        warning!(
+            "{} character(s) cannot be encoded in EBU STL (Latin) and were replaced with '?'.",
+            replaced
+        );
+    }
+
+    let name = format!("{}.stl", base_name);
+    let path = output_dir.join(&name);
+    std::fs::write(workspace.file(&name), stl)
+        .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+    workspace.persist(&name, &path)?;
+    if cli.debug {
+        log_line!("STL: {} caption(s)", events.len());
+    }
+    Ok(path)
+}
+
+/// --font-coverage: print the characters of the text events that the first font of
+/// libaribcaption's list has no glyph for, with the in-TCs of the events using them.
+fn report_font_coverage(events: &[TextEvent], libaribcaption_opts: &BTreeMap<String, String>) {
+    let fonts = libaribcaption_opts.get("font").map_or("", String::as_str);
+    let Some(font) = primary_font(fonts) else {
+        warning!("--font-coverage: no font configured");
+        return;
+    };
+    let report = match font_coverage(events, font, &FcMatch) {
+        Ok(report) => report,
+        Err(e) => {
+            warning!("--font-coverage: cannot query font {}: {}", font, e);
+            return;
+        }
+    };
+    if let Some(substitute) = &report.substitute {
+        warning!(
+            "--font-coverage: font {} is not installed; checked {}, which fontconfig uses instead",
+            font,
+            substitute
+        );
+    }
+    log_line!(
+        "Font coverage ({}): {} of {} distinct character(s) missing",
+        report.substitute.as_deref().unwrap_or(font),
+        report.missing.len(),
+        report.characters
+    );
+    if !report.missing.is_empty() {
+        warning!(
+            "{} character(s) are drawn from a fallback font; listed below.",
+            report.missing.len()
+        );
+    }
+    for line in report.missing_lines() {
+        log_line!("  {}", line);
+    }
+}
+
+/// A wrapper configured from the options shared by every decode pass.
+fn new_ffmpeg(cli: &Cli) -> FfmpegWrapper {
+    let mut ffmpeg = FfmpegWrapper::new();
+    ffmpeg.set_debug(cli.debug);
+    ffmpeg.set_rate_preference(cli.rate_source);
+    ffmpeg.set_open_retries(cli.open_retries);
+    ffmpeg.set_aggressive_stream_detect(cli.aggressive_stream_detect);
+    ffmpeg.set_threads(job_threads().or(cli.threads));
+    if let Some(RateLimit::BytesPerSecond(rate)) = cli.limit_rate {
+        ffmpeg.set_read_limit(Some(rate));
+    }
+    ffmpeg
+}
+
+/// Print the ARIB subtitle streams of one input with their types (--list-streams).
+fn list_streams(input_file: &Path, open_retries: u32) -> anyhow::Result<()> {
+    let mut ffmpeg = FfmpegWrapper::new();
+    ffmpeg.set_open_retries(open_retries);
+    let streams = ffmpeg.list_subtitle_streams(input_file)?;
+    println!("{}:", input_file.display());
+    if streams.is_empty() {
+        println!("  no ARIB subtitle streams");
+    }
+    for stream in &streams {
+        println!("  {}", stream);
+    }
+    Ok(())
+}
+
+/// Built-in styles merged with --style-file.
+fn load_styles(cli: &Cli) -> anyhow::Result<StyleSet> {
+    let mut styles = StyleSet::builtin();
+    if let Some(path) = &cli.style_file {
+        styles.merge_file(path)?;
+    }
+    Ok(styles)
+}
+
+/// Open the input honoring --stream-index/--pid/--fast-probe and the probe cache.
+/// A cached PID is tried with a fast probe first; if it no longer matches, falls back to a full probe.
+fn open_input(ffmpeg: &mut FfmpegWrapper, cli: &Cli, input_file: &Path) -> anyhow::Result<()> {
+    let explicit = match (cli.stream_index, cli.pid) {
+        (Some(idx), _) => Some(StreamSelection::Index(idx)),
+        (None, Some(pid)) => Some(StreamSelection::Pid(pid)),
+        (None, None) => None,
+    };
+    let cache = match &cli.probe_cache {
+        Some(path) => Some(ProbeCache::load(path)?),
+        None => None,
+    };
+    let dir_key = crate::probe_cache::directory_key(Path::new(input_file));
+    let cached = if explicit.is_none() {
+        cache
+            .as_ref()
+            .and_then(|c| c.lookup(&dir_key))
+            .map(|e| StreamSelection::Pid(e.pid))
+    } else {
+        None
+    };
+
+    let started = Instant::now();
+    ffmpeg.set_caption_type_filter(cli.caption_type.filter());
+    if let Some(selection) = cached {
+        ffmpeg.set_stream_selection(Some(selection));
+        ffmpeg.set_fast_probe(true);
+        if let Err(e) = ffmpeg.open_file(input_file) {
+            if cli.debug {
+                log_line!("Probe cache entry not usable ({}); running full probe.", e);
+            }
+            ffmpeg.set_stream_selection(None);
+            ffmpeg.set_fast_probe(false);
+            ffmpeg.open_file(input_file)?;
+        }
+    } else {
+        ffmpeg.set_stream_selection(explicit);
+        ffmpeg.set_fast_probe(cli.fast_probe);
+        ffmpeg.open_file(input_file)?;
+    }
+    if cli.debug {
+        log_line!("Probe time: {:.3}s", started.elapsed().as_secs_f64());
+        if let Some(t) = ffmpeg.caption_type() {
+            log_line!("Subtitle stream type: {}", t);
+        }
+    }
+
+    if let (Some(path), false) = (&cli.probe_cache, cli.dry_run) {
+        if let Some(pid) = ffmpeg.subtitle_stream_pid() {
+            let service = ffmpeg
+                .service_name()
+                .unwrap_or_else(|| crate::probe_cache::UNKNOWN_SERVICE.to_string());
+            ProbeCache::update(path, &dir_key, &service, pid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Advisory check of the events against the --target-tool limits; with --strict the limits a
+/// tool is likely to reject a file over are errors.
+fn check_target_tool(cli: &Cli, events: &[SubtitleEvent], fps: f64) -> anyhow::Result<()> {
+    let times: Vec<(f64, f64)> = events
+        .iter()
+        .map(|e| Ok((tc_to_seconds(&e.in_tc, fps)?, tc_to_seconds(&e.out_tc, fps)?)))
+        .collect::<anyhow::Result<_>>()?;
+    let tool = cli.target_tool;
+    let findings = check_limits(tool, &tool_limits(tool), &times);
+    for f in &findings {
+        warning!("{}; {}.", f.message, f.suggestion);
+    }
+    if cli.strict && findings.iter().any(|f| f.severe) {
+        anyhow::bail!("Output exceeds the {} limits (--strict).", tool);
+    }
+    Ok(())
+}
+
+/// Validate the written BDN XML files (--verify); fails listing the problems of each bad file.
+/// Valid files are also read back to check that the --fade-in/--fade-out declaration survived.
+/// Graphics outside an XML's directory are problems unless `allow_external`.
+fn verify_outputs(
+    written: &[PathBuf],
+    info: &BdnInfo,
+    extensions: bool,
+    allow_external: bool,
+) -> anyhow::Result<()> {
+    let mut failures = Vec::new();
+    for path in written.iter().filter(|p| p.extension().is_some_and(|e| e == "xml")) {
+        let mut problems = verify_file(path, extensions, allow_external)?;
+        if problems.is_empty() {
+            let xml = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))?;
+            let (read, _) = parse_bdn(&xml)?;
+            if read.fade != info.fade {
+                problems.push(format!(
+                    "fades read back as {:?}, expected {:?}",
+                    read.fade, info.fade
+                ));
+            }
+            if read.provenance.is_some() != info.provenance.is_some() {
+                problems.push("the --provenance comment did not read back".to_string());
+            }
+        }
+        if problems.is_empty() {
+            log_line!("Verified: {}", path.display());
+        } else {
+            failures.push(format!("{}:\n    {}", path.display(), problems.join("\n    ")));
+        }
+    }
+    if !failures.is_empty() {
+        anyhow::bail!("BDN XML verification failed:\n  {}", failures.join("\n  "));
+    }
+    Ok(())
+}
+
+/// `optimize` subcommand: shrink the images of an existing output directory and report the sizes.
+fn optimize(cli: &Cli, dir: &Path, trim: bool) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("Directory does not exist: {}", dir.display());
+    }
+    let options = OptimizeOptions {
+        trim,
+        png_auto_gray: cli.png_auto_gray,
+        allow_external_refs: cli.allow_external_refs,
+    };
+    let ws = TempWorkspace::create(cli.tmp_dir.as_deref().unwrap_or(dir), cli.keep_partial)?;
+    let summary = optimize_dir(dir, &ws, options)?;
+    ws.finish()?;
+    log_line!(
+        "Optimized {} XML file(s), {} image(s) ({} unchanged): {:.2} MB -> {:.2} MB ({:.1}% smaller)",
+        summary.xml_files.len(),
+        summary.images,
+        summary.unchanged,
+        summary.bytes_before as f64 / 1_000_000.0,
+        summary.bytes_after as f64 / 1_000_000.0,
+        if summary.bytes_before > 0 {
+            100.0 - summary.bytes_after as f64 * 100.0 / summary.bytes_before as f64
+        } else {
+            0.0
+        }
+    );
+    if cli.verify {
+        for (path, info) in &summary.xml_files {
+            let extensions = cli.bdn_extensions || info.fade.is_some();
+            verify_outputs(
+                std::slice::from_ref(path),
+                info,
+                extensions,
+                cli.allow_external_refs,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `retarget` subcommand: write an existing output directory scaled onto another canvas.
+fn retarget(cli: &Cli, dir: &Path, canvas: &str) -> anyhow::Result<()> {
+    let (width, height) = parse_canvas_size(canvas)?;
+    if width <= 0 || height <= 0 {
+        anyhow::bail!("invalid canvas_size: {}", canvas);
+    }
+    if !dir.is_dir() {
+        anyhow::bail!("Directory does not exist: {}", dir.display());
+    }
+    let Some(out_dir) = &cli.output else {
+        anyhow::bail!("retarget writes to a new directory; specify it with --output.");
+    };
+    crate::sink::create_dir_all(out_dir)?;
+    if dir.canonicalize()? == out_dir.canonicalize()? {
+        anyhow::bail!("--output must differ from the directory being retargeted.");
+    }
+    let ws = TempWorkspace::create(cli.tmp_dir.as_deref().unwrap_or(out_dir), cli.keep_partial)?;
+    let summary = retarget_dir(dir, out_dir, (width, height), cli.png_auto_gray, &ws)?;
+    ws.finish()?;
+    log_line!(
+        "Retargeted {} XML file(s), {} image(s) to {}x{}: {}",
+        summary.xml_files.len(),
+        summary.images,
+        width,
+        height,
+        out_dir.display()
+    );
+    if cli.verify {
+        for (path, info) in &summary.xml_files {
+            let extensions = cli.bdn_extensions || info.fade.is_some();
+            verify_outputs(
+                std::slice::from_ref(path),
+                info,
+                extensions,
+                cli.allow_external_refs,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `combine` subcommand: join the outputs of --shard runs into --output.
+fn combine(cli: &Cli, dirs: &[PathBuf]) -> anyhow::Result<()> {
+    for dir in dirs {
+        if !dir.is_dir() {
+            anyhow::bail!("Directory does not exist: {}", dir.display());
+        }
+    }
+    let Some(out_dir) = &cli.output else {
+        anyhow::bail!("combine writes to a new directory; specify it with --output.");
+    };
+    crate::sink::create_dir_all(out_dir)?;
+    for dir in dirs {
+        if dir.canonicalize()? == out_dir.canonicalize()? {
+            anyhow::bail!("--output must differ from the shard directories.");
+        }
+    }
+    let ws = TempWorkspace::create(cli.tmp_dir.as_deref().unwrap_or(out_dir), cli.keep_partial)?;
+    let summary = combine_dirs(dirs, out_dir, cli.start_index, &ws)?;
+    ws.finish()?;
+    for (path, _, events) in &summary.xml_files {
+        log_line!("Combined {} event(s): {}", events, path.display());
+    }
+    log_line!(
+        "{} image(s); {} boundary event(s) found in two shards kept once",
+        summary.images,
+        summary.duplicates
+    );
+    if cli.verify {
+        for (path, info, _) in &summary.xml_files {
+            let extensions = cli.bdn_extensions || info.fade.is_some();
+            verify_outputs(
+                std::slice::from_ref(path),
+                info,
+                extensions,
+                cli.allow_external_refs,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `corpus` subcommand: converts each sample listed in `expect` with the other options given,
+/// prints the pass/fail table and fails if any sample missed its expectations.
+fn corpus(
+    mut cli: Cli,
+    dir: &Path,
+    expect: &Path,
+    baseline_path: &Path,
+    update_baseline: bool,
+) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("Directory does not exist: {}", dir.display());
+    }
+    if !cli.benchmark && cli.output.is_none() {
+        anyhow::bail!(
+            "corpus writes the outputs of the samples to --output; specify it or use --timing-only."
+        );
+    }
+    OutputFormat::bdn_images(&cli.formats)?;
+    let contents = std::fs::read_to_string(expect)
+        .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", expect.display(), e))?;
+    let expectations = parse_expectations(&contents)
+        .map_err(|e| anyhow::anyhow!("Invalid expectations: {}: {}", expect.display(), e))?;
+    let mut baseline = Baseline::load(baseline_path)?;
+    let inputs: Vec<PathBuf> = expectations.iter().map(|e| dir.join(&e.file)).collect();
+    let layout = output_layout(&cli)?;
+    let plans = plan_outputs(&inputs, cli.output.as_deref(), cli.flat_output, &layout)?;
+    prepare_outputs(&cli, &plans)?;
+
+    let mut rows = Vec::new();
+    for ((expectation, input), plan) in expectations.iter().zip(&inputs).zip(&plans) {
+        log_line!("Converting: {}", input.display());
+        let sample = Arc::new(Mutex::new(SampleResult::default()));
+        let hook = Arc::clone(&sample);
+        cli.progress = Some(Box::new(move |events, in_tc| {
+            if let Ok(mut sample) = hook.lock() {
+                sample.record(events, in_tc);
+            }
+        }));
+        let warnings = warnings_printed();
+        let started = Instant::now();
+        let converted = if input.exists() {
+            convert(&cli, input, plan)
+        } else {
+            Err(anyhow::anyhow!(
+                "Input file does not exist: {}",
+                input.display()
+            ))
+        };
+        let result = converted.map_err(|e| e.to_string()).map(|_| SampleResult {
+            warnings: warnings_printed() - warnings,
+            seconds: started.elapsed().as_secs_f64(),
+            ..sample.lock().map(|s| s.clone()).unwrap_or_default()
+        });
+        rows.push(CorpusRow::new(expectation, result));
+    }
+    print!("{}", render_corpus_table(&rows, &baseline));
+    if update_baseline {
+        for row in &rows {
+            if let Ok(result) = &row.result {
+                baseline.set(&row.file, result.seconds);
+            }
+        }
+        baseline.save(baseline_path)?;
+        log_line!("Baseline updated: {}", baseline_path.display());
+    }
+    let failed = rows.iter().filter(|r| !r.passed()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} samples failed.", failed, rows.len());
+    }
+    Ok(())
+}
+
+/// Print --benchmark throughput for the decode loop started at `started`.
+fn report_benchmark(stats: &Stats, events: usize, started: Instant) {
+    log_line!("Benchmark:");
+    for line in stats.throughput_lines(events, started.elapsed()) {
+        log_line!("  {}", line);
+    }
+}
+
+/// Writes each sampled caption composited over its video frame (scaled to the canvas) as
+/// `{image stem}_preview.png`. Frames that cannot be decoded are skipped with a warning.
+fn write_previews(
+    ffmpeg: &mut FfmpegWrapper,
+    dir: &Path,
+    samples: &[PreviewSample],
+    fps: f64,
+    base_time: f64,
+    (canvas_w, canvas_h): (i32, i32),
+) -> anyhow::Result<Vec<PathBuf>> {
+    crate::sink::create_dir_all(dir)
+        .map_err(|e| anyhow::anyhow!("Failed to create directory: {}: {}", dir.display(), e))?;
+    let mut written = Vec::new();
+    for sample in samples {
+        let time = tc_to_seconds(&sample.in_tc, fps)? + base_time;
+        let video = match ffmpeg.decode_video_frame_at(time) {
+            Ok(video) => video,
+            Err(e) => {
+                warning!("no preview for event at {}: {}", sample.in_tc, e);
+                continue;
+            }
+        };
+        let mut still = scale_nearest(&video, canvas_w, canvas_h);
+        composite_over(&mut still, &sample.bitmap, sample.x, sample.y);
+        let stem = Path::new(&sample.image_file)
+            .file_stem()
+            .map_or_else(|| sample.image_file.clone(), |s| s.to_string_lossy().into_owned());
+        let path = dir.join(format!("{}_preview.png", stem));
+        save_bitmap_as_png(&still, &path)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn report_style(report: Option<&StyleReport>) {
+    if let Some(report) = report {
+        log_line!("Style: {}", report.to_json());
+    }
+}
+
+/// Extracts the input's font attachments where fontconfig finds them (see `AttachmentFonts`) and
+/// puts their families first in libaribcaption's font list.
+fn use_attachment_fonts(
+    ffmpeg: &FfmpegWrapper,
+    libaribcaption_opts: &mut BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let attachments = ffmpeg.attachments();
+    let has_fonts = (attachments.iter()).any(|a| is_font_attachment(&a.mimetype, &a.filename));
+    let Some(font_dir) = attachment_font_dir().filter(|_| has_fonts) else {
+        if has_fonts {
+            warning!("font attachments are used only by the arib2bdnxml command; not used.");
+        }
+        return Ok(());
+    };
+    let mut families = Vec::new();
+    for font in extract_fonts(&attachments, &font_dir)? {
+        if font.families.is_empty() {
+            warning!(
+                "attachment font {}: no family name found; not used",
+                font.filename
+            );
+            continue;
+        }
+        log_line!(
+            "Attachment font: {} ({})",
+            font.filename,
+            font.families.join(", ")
+        );
+        families.extend(font.families);
+    }
+    if families.is_empty() {
+        return Ok(());
+    }
+    let fonts = libaribcaption_opts.get("font").map_or("", String::as_str);
+    let fonts = prepend_fonts(fonts, &families);
+    log_line!("Fonts: {}", fonts);
+    libaribcaption_opts.insert("font".to_string(), fonts);
+    Ok(())
+}
+
+/// Sets up `AttachmentFonts` unless the command line turns them off; the binary calls this
+/// before starting any thread. A failure only costs the attached fonts.
+pub fn attachment_fonts(cli: &Cli) -> Option<AttachmentFonts> {
+    if cli.no_attachment_fonts {
+        return None;
+    }
+    AttachmentFonts::configure()
+        .map_err(|e| warning!("font attachments: {}; not used", e))
+        .ok()
+}
+
+/// Print the --drcs-report summary for one input.
+fn report_drcs(ffmpeg: &FfmpegWrapper, input_file: &Path) {
+    if let Some(stats) = ffmpeg.drcs_stats() {
+        log_line!(
+            "DRCS: {} glyph definition(s) in {} caption packet(s): {}",
+            stats.glyphs,
+            stats.packets_with_drcs,
+            input_file.display()
+        );
+        if stats.glyphs > 0 {
+            log_line!("  Consider --arib-params replace_drcs=1 if these glyphs render as blanks.");
+        }
+    }
+}
+
+/// Reads the --checkpoint file; a checkpoint made for another input or other settings is
+/// ignored with a warning (and overwritten by this run).
+fn load_checkpoint(
+    path: &Path,
+    input_file: &Path,
+    base_name: &str,
+    fps: f64,
+) -> anyhow::Result<Option<Checkpoint>> {
+    let Some(checkpoint) = Checkpoint::load(path)? else {
+        return Ok(None);
+    };
+    let input = InputStamp::of(Path::new(input_file))?;
+    match checkpoint.mismatch(input, base_name, fps) {
+        Some(reason) => {
+            warning!("ignoring checkpoint {}: {}.", path.display(), reason);
+            Ok(None)
+        }
+        None => Ok(Some(checkpoint)),
+    }
+}
+
+/// Write the --packet-log file and print its summary.
+fn report_packet_log(cli: &Cli, ffmpeg: &FfmpegWrapper) -> anyhow::Result<()> {
+    if let (Some(path), Some(log)) = (&cli.packet_log, ffmpeg.packet_log()) {
+        log.write_to_file(path)?;
+        log_line!("Subtitle packets: {}", log.stats());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        companion_mkv_base_candidates, parse_cli, saved_or_warn, time_range, Cli, Command,
+    };
+    use crate::bitmap::{save_bitmap, BitmapData, ImageFormat};
+    use crate::diagnostics::{with_handler, Collector, Level};
+    use clap::error::ErrorKind;
+    use clap::{CommandFactory, Parser};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    /// What clap does with a command line that does not convert: the error kind and whether the
+    /// message goes to stderr.
+    fn early_exit(args: &[&str]) -> (ErrorKind, bool, String) {
+        let e = Cli::try_parse_from(args).err().expect("parsed");
+        (e.kind(), e.use_stderr(), e.to_string())
+    }
+
+    #[test]
+    fn test_help_and_version() {
+        // -h is the short help, --help the long one.
+        let short = Cli::command().render_help().to_string();
+        let help = Cli::command().render_long_help().to_string();
+        for (args, help) in [
+            (&["arib2bdnxml", "--help"][..], &help),
+            (&["arib2bdnxml", "-h"], &short),
+            (&["arib2bdnxml", "--help", "a.ts"], &help),
+            (&["arib2bdnxml", "a.ts", "-h"], &short),
+            (
+                &["arib2bdnxml", "--debug", "--help", "--output", "out"],
+                &help,
+            ),
+        ] {
+            let (kind, stderr, text) = early_exit(args);
+            assert_eq!(
+                (kind, stderr),
+                (ErrorKind::DisplayHelp, false),
+                "{:?}",
+                args
+            );
+            assert_eq!(&text, help, "{:?}", args);
+        }
+        for args in [&["arib2bdnxml", "-V"][..], &["arib2bdnxml", "-v", "a.ts"]] {
+            let (kind, stderr, text) = early_exit(args);
+            assert_eq!((kind, stderr), (ErrorKind::DisplayVersion, false));
+            assert_eq!(text, format!("arib2bdnxml {}\n", super::VERSION));
+        }
+        let (kind, _, text) = early_exit(&["arib2bdnxml", "--version"]);
+        assert_eq!(kind, ErrorKind::DisplayVersion);
+        assert!(
+            text.contains("\nformats: bdn, chapters, tiff, stl, srt\n"),
+            "{}",
+            text
+        );
+        // Nothing at all: the help, on stdout, as with --help.
+        let e = parse_cli(["arib2bdnxml"]).err().expect("parsed");
+        assert_eq!((e.kind(), e.use_stderr()), (ErrorKind::DisplayHelp, false));
+        assert_eq!(e.to_string(), help);
+        // Subcommands have their own help.
+        let (kind, _, text) = early_exit(&["arib2bdnxml", "optimize", "--help"]);
+        assert_eq!(kind, ErrorKind::DisplayHelp);
+        assert!(
+            text.contains("Usage: arib2bdnxml optimize [OPTIONS] <DIR>"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_parse_inputs_and_commands() {
+        let cli = Cli::try_parse_from(["arib2bdnxml", "-d", "a.ts", "--anamorphic", "b.mks"]);
+        let cli = cli.unwrap();
+        assert_eq!(cli.input_files, [Path::new("a.ts"), Path::new("b.mks")]);
+        assert!(cli.debug && cli.anamorphic && cli.command.is_none());
+        // An input named like a command needs `--`.
+        let cli = Cli::try_parse_from(["arib2bdnxml", "--", "combine"]).unwrap();
+        assert_eq!(cli.input_files, [Path::new("combine")]);
+        assert!(cli.command.is_none());
+
+        let command = |args: &[&str]| Cli::try_parse_from(args).map(|cli| cli.command);
+        let optimize = command(&["arib2bdnxml", "optimize", "--trim", "out"]).unwrap();
+        assert!(matches!(
+            optimize,
+            Some(Command::Optimize { trim: true, .. })
+        ));
+        let args = [
+            "arib2bdnxml",
+            "-o",
+            "sd",
+            "retarget",
+            "--canvas",
+            "1280x720",
+            "out",
+        ];
+        let retarget = command(&args).unwrap();
+        assert!(matches!(retarget, Some(Command::Retarget { canvas, .. }) if canvas == "1280x720"));
+        let combine = command(&["arib2bdnxml", "-o", "all", "combine", "s1", "s2"]).unwrap();
+        assert!(matches!(combine, Some(Command::Combine { dirs }) if dirs.len() == 2));
+        let corpus = command(&["arib2bdnxml", "corpus", "--timing-only", "samples"]).unwrap();
+        assert!(matches!(
+            corpus,
+            Some(Command::Corpus {
+                timing_only: true,
+                ..
+            })
+        ));
+
+        let error = |args: &[&str]| Cli::try_parse_from(args).err().map(|e| e.kind());
+        // Options of the program go before the command; its own options after.
+        let late = error(&["arib2bdnxml", "optimize", "--debug", "out"]);
+        assert_eq!(late, Some(ErrorKind::UnknownArgument));
+        let missing = error(&["arib2bdnxml", "retarget", "out"]);
+        assert_eq!(missing, Some(ErrorKind::MissingRequiredArgument));
+        assert_eq!(
+            error(&["arib2bdnxml", "combine"]),
+            Some(ErrorKind::MissingRequiredArgument)
+        );
+        // After an input, a command name is another input.
+        let cli = Cli::try_parse_from(["arib2bdnxml", "a.ts", "optimize", "out"]).unwrap();
+        let inputs = ["a.ts", "optimize", "out"].map(Path::new);
+        assert_eq!(cli.input_files, inputs);
+    }
+
+    #[test]
+    fn test_help_describes_options() {
+        let command = Cli::command();
+        let undescribed: Vec<_> = (command.get_arguments())
+            .chain(command.get_subcommands().flat_map(|c| c.get_arguments()))
+            .filter(|a| a.get_help().is_none())
+            .map(|a| a.get_id().to_string())
+            .collect();
+        assert!(undescribed.is_empty(), "no help: {:?}", undescribed);
+        let help = Cli::command().render_help().to_string();
+        for line in ["  -a, --anamorphic", "  -o, --output <DIR>", "  combine "] {
+            assert!(help.contains(line), "{}", help);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_input_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let input = Path::new(OsStr::from_bytes(b"rec\xff/caf\xe9.ts"));
+        let cli = parse_cli([OsStr::new("arib2bdnxml"), input.as_os_str()]).unwrap();
+        assert_eq!(cli.input_files, [input]);
+        let layout = super::output_layout(&cli).unwrap();
+        let plans = super::plan_outputs(&cli.input_files, None, false, &layout).unwrap();
+        // The directory is kept byte for byte; only the base name of the outputs is lossy.
+        assert_eq!(plans[0].output_dir.parent(), input.parent());
+        assert_eq!(plans[0].base_name, "caf\u{fffd}");
+    }
+
+    #[test]
+    fn test_companion_mkv_base_candidates() {
+        assert!(companion_mkv_base_candidates("").is_empty());
+        let c = companion_mkv_base_candidates("MOVIE.jpn");
+        assert!(c.contains(&"MOVIE".to_string()));
+        assert!(c.contains(&"MOVIE.jpn".to_string()));
+        let c = companion_mkv_base_candidates("MOVIE.01.jpn");
+        assert!(c.contains(&"MOVIE".to_string()));
+        assert!(c.contains(&"MOVIE.01".to_string()));
+        assert!(c.contains(&"MOVIE.01.jpn".to_string()));
+        let c = companion_mkv_base_candidates("MOVIE.01.jpn.forced");
+        assert!(c.contains(&"MOVIE".to_string()));
+        assert!(c.contains(&"MOVIE.01.jpn".to_string()));
+        let c = companion_mkv_base_candidates("MOVIE.forced");
+        assert!(c.contains(&"MOVIE".to_string()));
+    }
+
+    #[test]
+    fn test_time_range() {
+        let range = |args: &[&str]| {
+            let cli = parse_cli([&["arib2bdnxml"], args, &["a.ts"]].concat()).unwrap();
+            time_range(&cli).map_err(|e| e.to_string())
+        };
+        assert_eq!(range(&[]), Ok(None));
+        assert_eq!(range(&["--start", "1:30"]), Ok(Some((90.0, f64::INFINITY))));
+        assert_eq!(range(&["--end", "00:01:00.5"]), Ok(Some((0.0, 60.5))));
+        let reversed = range(&["--start", "2:00", "--end", "1:00"]);
+        assert_eq!(reversed, Err("--end must be after --start.".to_string()));
+        assert!(range(&["--start=-5"]).is_err());
+        let subtitle_base = ["--start", "10", "--timestamp-base", "subtitle"];
+        assert!(range(&subtitle_base).is_err());
+        // --rebase needs --start.
+        assert!(parse_cli(["arib2bdnxml", "--rebase", "a.ts"]).is_err());
+    }
+
+    #[test]
+    fn test_save_failure_diagnostics() {
+        // A directory that does not exist: read-only permissions do not stop root.
+        let path = std::env::temp_dir()
+            .join(format!("arib2bdnxml-missing-{}", std::process::id()))
+            .join("00000001.png");
+        let bitmap = BitmapData {
+            data: vec![255; 4 * 4 * 2],
+            width: 4,
+            height: 2,
+            stride: 16,
+        };
+        let collector = Arc::new(Collector::default());
+        let saved = with_handler(Some(collector.clone()), || {
+            saved_or_warn(save_bitmap(&bitmap, &path, ImageFormat::Png, false), &path)
+        });
+        assert_eq!(saved, None);
+        assert_eq!(
+            collector.messages(),
+            [(
+                Level::Warning,
+                format!("failed to save image: {}", path.display())
+            )]
+        );
+    }
+}
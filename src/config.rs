@@ -1,14 +1,18 @@
-//! Output resolution (canvas_size), libaribcaption defaults.
+//! Output resolution (canvas_size), libaribcaption defaults and named styles (--style).
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::bitmap::{scale_bitmap, BitmapData};
+use crate::options::parse_libaribcaption_opts;
 
 /// Default output resolution.
-const DEFAULT_CANVAS: &str = "1920x1080";
+pub const DEFAULT_CANVAS: &str = "1920x1080";
 
 #[inline]
 fn debug_eprint(debug: bool, msg: &str) {
     if debug {
-        eprintln!("{}", msg);
+        log_line!("{}", msg);
     }
 }
 
@@ -46,7 +50,7 @@ pub fn determine_canvas_size(
         ),
     };
     if debug && canvas == DEFAULT_CANVAS && (video_width != 0 || video_height != 0) {
-        eprintln!("canvas_size: {}", canvas);
+        log_line!("canvas_size: {}", canvas);
     }
     Ok(canvas.to_string())
 }
@@ -61,6 +65,82 @@ pub fn video_format_from_canvas(canvas_size: &str) -> &'static str {
     }
 }
 
+/// Canvas of a BDN VideoFormat, for output written without a recorded canvas (retarget).
+pub fn canvas_from_video_format(video_format: &str) -> Option<(i32, i32)> {
+    match video_format {
+        "1080p" | "1080i" => Some((1920, 1080)),
+        "1440x1080" => Some((1440, 1080)),
+        "720p" => Some((1280, 720)),
+        "ntsc" | "480i" | "480p" => Some((720, 480)),
+        "pal" | "576i" | "576p" => Some((720, 576)),
+        _ => None,
+    }
+}
+
+/// Scale and offset mapping canvas pixels onto a square-pixel frame (--dar-correct).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DarCorrection {
+    pub scale_x: f64,
+    pub scale_y: f64,
+    pub offset_x: f64,
+    pub offset_y: f64,
+    pub target_width: i32,
+    pub target_height: i32,
+}
+
+/// Square-pixel BDN frame for a canvas height: 1080 lines -> 1920x1080, fewer -> 1280x720.
+fn square_pixel_target(canvas_height: i32) -> (i32, i32) {
+    if canvas_height >= 1080 {
+        (1920, 1080)
+    } else {
+        (1280, 720)
+    }
+}
+
+/// Correction that shows a canvas at `display_aspect` (source width * SAR / height) on a
+/// square-pixel frame, fitted and centered. None for an unknown aspect or empty canvas.
+pub fn dar_correction(
+    canvas_width: i32,
+    canvas_height: i32,
+    display_aspect: f64,
+) -> Option<DarCorrection> {
+    let aspect_known = display_aspect.is_finite() && display_aspect > 0.0;
+    if canvas_width <= 0 || canvas_height <= 0 || !aspect_known {
+        return None;
+    }
+    let (tw, th) = square_pixel_target(canvas_height);
+    // Width the canvas occupies on screen in square pixels, at its own height.
+    let display_width = canvas_height as f64 * display_aspect;
+    let fit = (tw as f64 / display_width).min(th as f64 / canvas_height as f64);
+    let scale_x = display_width * fit / canvas_width as f64;
+    let scale_y = fit;
+    Some(DarCorrection {
+        scale_x,
+        scale_y,
+        offset_x: (tw as f64 - canvas_width as f64 * scale_x) / 2.0,
+        offset_y: (th as f64 - canvas_height as f64 * scale_y) / 2.0,
+        target_width: tw,
+        target_height: th,
+    })
+}
+
+impl DarCorrection {
+    /// Target frame as a "WxH" canvas string.
+    pub fn target_canvas(&self) -> String {
+        format!("{}x{}", self.target_width, self.target_height)
+    }
+
+    /// Scales a bitmap and maps its position onto the target frame.
+    pub fn apply(&self, bitmap: &BitmapData, x: i32, y: i32) -> (BitmapData, i32, i32) {
+        let new_x = (x as f64 * self.scale_x + self.offset_x).round() as i32;
+        let new_y = (y as f64 * self.scale_y + self.offset_y).round() as i32;
+        let right = ((x + bitmap.width) as f64 * self.scale_x + self.offset_x).round() as i32;
+        let bottom = ((y + bitmap.height) as f64 * self.scale_y + self.offset_y).round() as i32;
+        let scaled = scale_bitmap(bitmap, right - new_x, bottom - new_y);
+        (scaled, new_x, new_y)
+    }
+}
+
 /// Parse a "WxH" string into (width, height).
 pub fn parse_canvas_size(s: &str) -> anyhow::Result<(i32, i32)> {
     let mut it = s.split('x');
@@ -80,6 +160,88 @@ pub fn parse_canvas_size(s: &str) -> anyhow::Result<(i32, i32)> {
     Ok((w, h))
 }
 
+/// Parses --canvas, the video resolution to use instead of the video stream's: one of those
+/// `determine_canvas_size` supports.
+pub fn parse_video_size(s: &str) -> Result<(i32, i32), String> {
+    let size = parse_canvas_size(s).map_err(|_| format!("expected WxH, got '{}'", s))?;
+    match size {
+        (1920, 1080) | (1440, 1080) | (1280, 720) | (720, 480) => Ok(size),
+        _ => Err(format!(
+            "unsupported video resolution: {} (1920x1080, 1440x1080, 1280x720, 720x480)",
+            s
+        )),
+    }
+}
+
+/// Part of the caption canvas that captions are rendered into (--region), for services whose
+/// captions belong to an inset picture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub width: i32,
+    pub height: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Parses a --region `WxH+X+Y`, e.g. `1440x810+480+0`.
+pub fn parse_region(s: &str) -> Result<Region, String> {
+    let invalid = || format!("expected WxH+X+Y, got '{}'", s);
+    let mut parts = s.trim().split('+');
+    let (size, x, y) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(size), Some(x), Some(y), None) => (size, x, y),
+        _ => return Err(invalid()),
+    };
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let number = |s: &str| s.trim().parse::<i32>().map_err(|_| invalid());
+    let region = Region {
+        width: number(width)?,
+        height: number(height)?,
+        x: number(x)?,
+        y: number(y)?,
+    };
+    if region.width <= 0 || region.height <= 0 {
+        return Err(format!("region size must be positive: {}", s));
+    }
+    Ok(region)
+}
+
+impl Region {
+    /// The canvas_size libaribcaption renders at.
+    pub fn canvas_size(&self) -> String {
+        format!("{}x{}", self.width, self.height)
+    }
+
+    /// Fails unless the region lies within a `canvas_width`x`canvas_height` canvas.
+    pub fn check(&self, canvas_width: i32, canvas_height: i32) -> anyhow::Result<()> {
+        if self.x + self.width > canvas_width || self.y + self.height > canvas_height {
+            anyhow::bail!(
+                "--region {}+{}+{} does not fit the {}x{} caption canvas",
+                self.canvas_size(),
+                self.x,
+                self.y,
+                canvas_width,
+                canvas_height
+            );
+        }
+        Ok(())
+    }
+
+    /// Canvas position of a `width`x`height` bitmap rendered at (x, y) in the region: offset by
+    /// the region's origin, then moved back inside the canvas where it would stick out.
+    pub fn place(
+        &self,
+        (x, y): (i32, i32),
+        (width, height): (i32, i32),
+        (canvas_width, canvas_height): (i32, i32),
+    ) -> (i32, i32) {
+        let clamp = |v: i32, size: i32, limit: i32| v.min(limit - size).max(0);
+        (
+            clamp(x + self.x, width, canvas_width),
+            clamp(y + self.y, height, canvas_height),
+        )
+    }
+}
+
 /// Default font for libaribcaption: Windows uses Rounded M+ only; others use Hiragino + Rounded M+.
 #[cfg(target_os = "windows")]
 fn default_arib_font() -> String {
@@ -91,7 +253,7 @@ fn default_arib_font() -> String {
 }
 
 /// Insert libaribcaption default options only for keys that are not already set.
-pub fn setup_libaribcaption_defaults(opts: &mut HashMap<String, String>) {
+pub fn setup_libaribcaption_defaults(opts: &mut BTreeMap<String, String>) {
     opts.entry("caption_encoding".to_string())
         .or_insert_with(|| "0".to_string());
     opts.entry("font".to_string())
@@ -114,3 +276,317 @@ pub fn setup_libaribcaption_defaults(opts: &mut HashMap<String, String>) {
         .or_insert_with(|| "0".to_string());
 }
 
+/// libaribcaption key/value pairs of a built-in style.
+type StyleOptions = &'static [(&'static str, &'static str)];
+
+/// Built-in named styles (--style): a description and libaribcaption options approximating the
+/// broadcaster's on-air look. Starting points; adjust with --arib-params or a --style-file.
+const BUILTIN_STYLES: &[(&str, &str, StyleOptions)] = &[
+    (
+        "nhk",
+        "NHK: outlined text without background boxes",
+        &[
+            ("outline_width", "1.5"),
+            ("ignore_background", "1"),
+            ("replace_msz_ascii", "1"),
+        ],
+    ),
+    (
+        "ntv",
+        "Nippon TV: thin outline, half-width MSZ characters",
+        &[
+            ("outline_width", "1.0"),
+            ("replace_msz_ascii", "1"),
+            ("replace_msz_japanese", "1"),
+        ],
+    ),
+    (
+        "tbs",
+        "TBS: thin outline, half-width MSZ alphanumerics",
+        &[("outline_width", "1.0"), ("replace_msz_ascii", "1")],
+    ),
+    (
+        "cx",
+        "Fuji TV: heavy outline without background boxes",
+        &[("outline_width", "2.0"), ("ignore_background", "1")],
+    ),
+    (
+        "ex",
+        "TV Asahi: thin outline, MSZ glyphs replaced",
+        &[("outline_width", "1.0"), ("replace_msz_glyph", "1")],
+    ),
+    (
+        "tx",
+        "TV Tokyo: medium outline, half-width MSZ characters",
+        &[
+            ("outline_width", "1.5"),
+            ("replace_msz_ascii", "1"),
+            ("replace_msz_japanese", "1"),
+        ],
+    ),
+];
+
+/// A named bundle of libaribcaption options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptionStyle {
+    pub name: String,
+    pub description: String,
+    pub options: BTreeMap<String, String>,
+}
+
+/// The styles --style can select: the built-ins plus those of a --style-file.
+#[derive(Debug, Clone)]
+pub struct StyleSet {
+    styles: Vec<CaptionStyle>,
+}
+
+impl StyleSet {
+    pub fn builtin() -> Self {
+        let styles = BUILTIN_STYLES
+            .iter()
+            .map(|(name, description, options)| CaptionStyle {
+                name: name.to_string(),
+                description: description.to_string(),
+                options: options
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            })
+            .collect();
+        StyleSet { styles }
+    }
+
+    /// Merges style definitions: one `name key=value,key=value` per line (values quoted as in
+    /// --arib-params); '#' starts a comment line. Options of a known style are layered over it,
+    /// key by key; other names add a style. Lines are applied in order.
+    pub fn merge(&mut self, contents: &str) -> Result<(), String> {
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            if !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!("line {}: invalid style name: {}", n + 1, name));
+            }
+            let options = parse_libaribcaption_opts(rest);
+            if options.is_empty() {
+                return Err(format!("line {}: no options for style {}", n + 1, name));
+            }
+            let index = match self.styles.iter().position(|s| s.name == name) {
+                Some(i) => i,
+                None => {
+                    self.styles.push(CaptionStyle {
+                        name: name.to_string(),
+                        description: "user style".to_string(),
+                        options: BTreeMap::new(),
+                    });
+                    self.styles.len() - 1
+                }
+            };
+            self.styles[index].options.extend(options);
+        }
+        Ok(())
+    }
+
+    /// Merges the style definitions in `path` (see `merge`).
+    pub fn merge_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))?;
+        self.merge(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid style file: {}: {}", path.display(), e))
+    }
+
+    pub fn get(&self, name: &str) -> anyhow::Result<&CaptionStyle> {
+        self.styles.iter().find(|s| s.name == name).ok_or_else(|| {
+            let names: Vec<&str> = self.styles.iter().map(|s| s.name.as_str()).collect();
+            anyhow::anyhow!("Unknown style: {} (available: {})", name, names.join(", "))
+        })
+    }
+
+    /// --list-styles output: one line per style with its options, then its description.
+    pub fn list_lines(&self) -> Vec<String> {
+        self.styles
+            .iter()
+            .map(|s| {
+                let options: Vec<String> = s
+                    .options
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                format!("{:<8} {}  # {}", s.name, options.join(","), s.description)
+            })
+            .collect()
+    }
+}
+
+/// Inserts a style's options for keys not already set, so --arib-params overrides the style and
+/// the style overrides `setup_libaribcaption_defaults` (applied afterwards).
+pub fn apply_style(style: &CaptionStyle, opts: &mut BTreeMap<String, String>) {
+    for (k, v) in &style.options {
+        opts.entry(k.clone()).or_insert_with(|| v.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn test_style_precedence() {
+        let styles = StyleSet::builtin();
+        let nhk = styles.get("nhk").unwrap();
+        // --arib-params first, then the style, then the defaults.
+        let mut opts = BTreeMap::from([("outline_width".to_string(), "3.0".to_string())]);
+        apply_style(nhk, &mut opts);
+        setup_libaribcaption_defaults(&mut opts);
+        assert_eq!(opts["outline_width"], "3.0");
+        assert_eq!(opts["ignore_background"], "1");
+        assert_eq!(opts["replace_msz_ascii"], "1");
+        assert_eq!(opts["replace_msz_japanese"], "0");
+        assert!(opts.keys().is_sorted());
+        let err = styles.get("bbc").unwrap_err().to_string();
+        assert!(
+            err.starts_with("Unknown style: bbc (available: nhk, ntv,"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_style_file_merge() {
+        let mut styles = StyleSet::builtin();
+        styles
+            .merge(
+                "# overrides\nnhk outline_width=1.0,replace_drcs=1\n\nsoft outline_width=0.5\nsoft font=\"A, B\"\n",
+            )
+            .unwrap();
+        let nhk = styles.get("nhk").unwrap();
+        assert_eq!(
+            nhk.description,
+            "NHK: outlined text without background boxes"
+        );
+        assert_eq!(nhk.options["outline_width"], "1.0");
+        assert_eq!(nhk.options["replace_drcs"], "1");
+        assert_eq!(nhk.options["ignore_background"], "1");
+        let soft = styles.get("soft").unwrap();
+        assert_eq!(soft.options.len(), 2);
+        assert_eq!(soft.options["font"], "A, B");
+        assert_eq!(
+            styles.list_lines().last().unwrap(),
+            "soft     font=A, B,outline_width=0.5  # user style"
+        );
+        assert_eq!(
+            styles.list_lines()[0],
+            "nhk      ignore_background=1,outline_width=1.0,replace_drcs=1,replace_msz_ascii=1  # NHK: outlined text without background boxes"
+        );
+
+        assert_eq!(
+            StyleSet::builtin().merge("ok outline_width=1\nbad/name x=1\n"),
+            Err("line 2: invalid style name: bad/name".to_string())
+        );
+        assert_eq!(
+            StyleSet::builtin().merge("empty\n"),
+            Err("line 1: no options for style empty".to_string())
+        );
+    }
+
+    #[test]
+    fn test_canvas_from_video_format() {
+        for canvas in ["720x480", "1280x720", "1440x1080", "1920x1080"] {
+            let (w, h) = canvas_from_video_format(video_format_from_canvas(canvas)).unwrap();
+            assert_eq!(format!("{}x{}", w, h), canvas);
+        }
+        assert_eq!(canvas_from_video_format("1080i"), Some((1920, 1080)));
+        assert_eq!(canvas_from_video_format("4k"), None);
+    }
+
+    #[test]
+    fn test_parse_video_size() {
+        assert_eq!(parse_video_size("1280x720"), Ok((1280, 720)));
+        assert_eq!(parse_video_size("720x480"), Ok((720, 480)));
+        assert!(parse_video_size("1280x").is_err());
+        assert!(parse_video_size("3840x2160")
+            .unwrap_err()
+            .starts_with("unsupported video resolution: 3840x2160"));
+    }
+
+    #[test]
+    fn test_dar_correction_anamorphic_1440() {
+        let c = dar_correction(1440, 1080, 16.0 / 9.0).unwrap();
+        assert!(approx(c.scale_x, 4.0 / 3.0));
+        assert!(approx(c.scale_y, 1.0));
+        assert!(approx(c.offset_x, 0.0) && approx(c.offset_y, 0.0));
+        assert_eq!(c.target_canvas(), "1920x1080");
+    }
+
+    #[test]
+    fn test_dar_correction_square_canvas_is_identity() {
+        let c = dar_correction(1920, 1080, 16.0 / 9.0).unwrap();
+        assert!(approx(c.scale_x, 1.0) && approx(c.scale_y, 1.0));
+    }
+
+    #[test]
+    fn test_dar_correction_sd() {
+        // 16:9 NTSC (SAR 32:27) fills 1280x720.
+        let c = dar_correction(720, 480, 720.0 * 32.0 / 27.0 / 480.0).unwrap();
+        assert!(approx(c.scale_x, 1280.0 / 720.0));
+        assert!(approx(c.scale_y, 1.5));
+        // 4:3 NTSC (SAR 8:9) is pillarboxed.
+        let c = dar_correction(720, 480, 4.0 / 3.0).unwrap();
+        assert!(approx(c.scale_x * 720.0, 960.0));
+        assert!(approx(c.offset_x, 160.0));
+        assert!(approx(c.offset_y, 0.0));
+        assert!(dar_correction(720, 480, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_parse_region() {
+        let region = Region {
+            width: 1440,
+            height: 810,
+            x: 480,
+            y: 0,
+        };
+        assert_eq!(parse_region("1440x810+480+0"), Ok(region));
+        assert_eq!(region.canvas_size(), "1440x810");
+        assert!(parse_region("1440x810").is_err());
+        assert!(parse_region("1440x810+480").is_err());
+        assert!(parse_region("1440x810+480+0+1").is_err());
+        assert!(parse_region("0x810+0+0").is_err());
+        assert!(parse_region("1440+810+0+0").is_err());
+    }
+
+    #[test]
+    fn test_region_place() {
+        let region = parse_region("1440x810+480+0").unwrap();
+        region.check(1920, 1080).unwrap();
+        assert!(region.check(1440, 1080).is_err());
+        let canvas = (1920, 1080);
+        assert_eq!(region.place((100, 700), (600, 80), canvas), (580, 700));
+        // A bitmap sticking out of the canvas is moved back in, never past the left/top edge.
+        assert_eq!(region.place((1000, 1050), (600, 80), canvas), (1320, 1000));
+        assert_eq!(region.place((-600, 0), (2000, 80), canvas), (0, 0));
+    }
+
+    #[test]
+    fn test_dar_correction_apply() {
+        let c = dar_correction(1440, 1080, 16.0 / 9.0).unwrap();
+        let bitmap = BitmapData {
+            data: vec![255; 30 * 10 * 4],
+            width: 30,
+            height: 10,
+            stride: 30 * 4,
+        };
+        let (scaled, x, y) = c.apply(&bitmap, 300, 900);
+        assert_eq!((x, y), (400, 900));
+        assert_eq!((scaled.width, scaled.height), (40, 10));
+    }
+}
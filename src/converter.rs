@@ -0,0 +1,144 @@
+//! Rust library API: `Converter` runs one conversion in-process, as the command line would, for
+//! Rust programs that do not want to run the binary. The C API (capi/) wraps it.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::cli::{convert_input, Converted};
+use crate::Cli;
+
+/// Options of a conversion; the rest are the command line defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConvertOptions {
+    /// --anamorphic.
+    pub anamorphic: bool,
+    /// libaribcaption options, `key=value,...` (--arib-params).
+    pub arib_params: Option<String>,
+    /// --debug: log the statistics and every output written.
+    pub debug: bool,
+}
+
+/// What a conversion wrote.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversionReport {
+    /// Events in the BDN XML.
+    pub events: usize,
+    pub xml_path: PathBuf,
+    /// Image files the events use, in order, relative to the directory of the XML.
+    pub images: Vec<String>,
+}
+
+/// Receives the number of events written so far and the in-TC of the latest one.
+type Progress = Arc<dyn Fn(u64, &str) + Send + Sync>;
+
+/// One input converted into BDN XML + PNG in an output directory.
+#[derive(Clone)]
+pub struct Converter {
+    input: PathBuf,
+    output_dir: PathBuf,
+    options: ConvertOptions,
+    progress: Option<Progress>,
+}
+
+impl std::fmt::Debug for Converter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Converter")
+            .field("input", &self.input)
+            .field("output_dir", &self.output_dir)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Converter {
+    pub fn new(
+        input: impl Into<PathBuf>,
+        output_dir: impl Into<PathBuf>,
+        options: ConvertOptions,
+    ) -> Self {
+        Converter {
+            input: input.into(),
+            output_dir: output_dir.into(),
+            options,
+            progress: None,
+        }
+    }
+
+    /// Calls `progress` after each written event with the number written so far and the in-TC
+    /// of the latest one, on the thread running the conversion.
+    pub fn on_progress(mut self, progress: impl Fn(u64, &str) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// The settings of this conversion: the command line defaults with the options set.
+    fn settings(&self) -> Cli {
+        let mut cli = Cli::defaults();
+        cli.anamorphic = self.options.anamorphic;
+        cli.arib_params.extend(self.options.arib_params.clone());
+        cli.debug = self.options.debug;
+        cli.output = Some(self.output_dir.clone());
+        if let Some(progress) = self.progress.clone() {
+            cli.progress = Some(Box::new(move |events, in_tc| progress(events, in_tc)));
+        }
+        cli
+    }
+
+    /// Converts the input. Log lines and warnings go to the diagnostics handler of the calling
+    /// thread (stderr unless one is set).
+    pub fn convert(&self) -> anyhow::Result<ConversionReport> {
+        let Converted { written, events } = convert_input(&self.settings(), &self.input)?;
+        let xml_path = written
+            .into_iter()
+            .find(|p| p.extension().is_some_and(|e| e == "xml"))
+            .ok_or_else(|| anyhow::anyhow!("The conversion wrote no BDN XML."))?;
+        let images = events
+            .iter()
+            .flat_map(|e| {
+                std::iter::once(&e.png_file).chain(e.second.as_ref().map(|g| &g.png_file))
+            })
+            .cloned()
+            .collect();
+        Ok(ConversionReport {
+            events: events.len(),
+            xml_path,
+            images,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings() {
+        let options = ConvertOptions {
+            anamorphic: true,
+            arib_params: Some("outline_width=2.0".to_string()),
+            debug: true,
+        };
+        let cli = Converter::new("rec/-a.ts", "out", options).settings();
+        assert!(cli.anamorphic && cli.debug);
+        assert_eq!(cli.arib_params, ["outline_width=2.0"]);
+        assert_eq!(cli.output, Some(PathBuf::from("out")));
+        assert!(cli.progress.is_none());
+        let plain = Converter::new("a.ts", "out", ConvertOptions::default())
+            .on_progress(|_, _| {})
+            .settings();
+        assert!(!plain.anamorphic && !plain.debug);
+        assert!(plain.arib_params.is_empty());
+        assert!(plain.progress.is_some());
+    }
+
+    #[test]
+    fn test_convert_missing_input() {
+        let missing = "/nonexistent/arib2bdnxml-library.ts";
+        let converter = Converter::new(missing, "/nonexistent", ConvertOptions::default());
+        let error = converter.convert().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Input file does not exist: {}", missing)
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! `corpus` subcommand: converts a directory of sample recordings (PTS wraps, discontinuities,
+//! bursts, ...) and checks each against an expectations file, with the time each took compared
+//! to a stored baseline, so a change that breaks a tricky capture shows up without eyeballing
+//! the outputs.
+//!
+//! Expectations file: one sample per line, its file name (in double quotes if it has spaces)
+//! followed by any of `events=N` or `events=MIN..MAX`, `first=<TC>` and `last=<TC>` (in-TCs of
+//! the first and last events) and `warnings=<MAX>`. `#` starts a comment.
+
+use std::path::Path;
+
+use crate::checkpoint::Json;
+use crate::dry_run::render_table;
+use crate::style::json_string;
+
+/// Expectations file read from the corpus directory when --expect is not given.
+pub const DEFAULT_EXPECTATIONS: &str = "corpus.txt";
+/// Baseline read from (and with --update-baseline written to) the corpus directory by default.
+pub const DEFAULT_BASELINE: &str = "corpus-baseline.json";
+/// Version written to and required from baseline files.
+pub const BASELINE_VERSION: u64 = 1;
+
+/// What one sample must produce; unset fields are not checked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expectation {
+    /// File name, relative to the corpus directory.
+    pub file: String,
+    /// Inclusive range of the event count.
+    pub events: Option<(usize, usize)>,
+    pub first_tc: Option<String>,
+    pub last_tc: Option<String>,
+    pub max_warnings: Option<usize>,
+}
+
+fn is_timecode(s: &str) -> bool {
+    let parts: Vec<&str> = s.split(':').collect();
+    parts.len() == 4
+        && parts
+            .iter()
+            .all(|p| p.len() == 2 && p.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn parse_count(s: &str) -> Option<usize> {
+    s.parse().ok()
+}
+
+fn parse_expectation(line: &str) -> Result<Expectation, String> {
+    let (file, rest) = match line.strip_prefix('"') {
+        Some(quoted) => quoted
+            .split_once('"')
+            .ok_or_else(|| "unterminated file name".to_string())?,
+        None => line.split_once(char::is_whitespace).unwrap_or((line, "")),
+    };
+    if file.is_empty() {
+        return Err("empty file name".to_string());
+    }
+    let mut expectation = Expectation {
+        file: file.to_string(),
+        ..Default::default()
+    };
+    for field in rest.split_whitespace() {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value: {}", field))?;
+        let invalid = || format!("invalid {}: {}", key, value);
+        match key {
+            "events" => {
+                let (min, max) = value.split_once("..").unwrap_or((value, value));
+                match (parse_count(min), parse_count(max)) {
+                    (Some(min), Some(max)) if min <= max => expectation.events = Some((min, max)),
+                    _ => return Err(invalid()),
+                }
+            }
+            "first" | "last" if is_timecode(value) => {
+                let tc = Some(value.to_string());
+                if key == "first" {
+                    expectation.first_tc = tc;
+                } else {
+                    expectation.last_tc = tc;
+                }
+            }
+            "warnings" => expectation.max_warnings = Some(parse_count(value).ok_or_else(invalid)?),
+            "first" | "last" => return Err(invalid()),
+            _ => return Err(format!("unknown key: {}", key)),
+        }
+    }
+    Ok(expectation)
+}
+
+/// Parses an expectations file; an error names the line.
+pub fn parse_expectations(contents: &str) -> Result<Vec<Expectation>, String> {
+    let mut expectations = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let expectation = parse_expectation(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        if expectations
+            .iter()
+            .any(|e: &Expectation| e.file == expectation.file)
+        {
+            return Err(format!(
+                "line {}: {} is listed twice",
+                i + 1,
+                expectation.file
+            ));
+        }
+        expectations.push(expectation);
+    }
+    if expectations.is_empty() {
+        return Err("no samples listed".to_string());
+    }
+    Ok(expectations)
+}
+
+/// What converting one sample produced.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleResult {
+    /// Events written (before filters that run after decoding, such as --forced-heuristic).
+    pub events: usize,
+    pub first_tc: Option<String>,
+    pub last_tc: Option<String>,
+    pub warnings: usize,
+    pub seconds: f64,
+}
+
+impl SampleResult {
+    /// Takes in one written event, as reported to the progress hook.
+    pub fn record(&mut self, events: u64, in_tc: &str) {
+        self.events = events as usize;
+        self.first_tc.get_or_insert_with(|| in_tc.to_string());
+        self.last_tc = Some(in_tc.to_string());
+    }
+}
+
+/// How `result` misses `expectation`; empty when it passes.
+pub fn check(expectation: &Expectation, result: &SampleResult) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some((min, max)) = expectation.events {
+        if !(min..=max).contains(&result.events) {
+            let expected = match min == max {
+                true => min.to_string(),
+                false => format!("{}..{}", min, max),
+            };
+            failures.push(format!("events {} (expected {})", result.events, expected));
+        }
+    }
+    for (name, expected, actual) in [
+        ("first", &expectation.first_tc, &result.first_tc),
+        ("last", &expectation.last_tc, &result.last_tc),
+    ] {
+        if let Some(expected) = expected.as_ref().filter(|e| Some(*e) != actual.as_ref()) {
+            failures.push(format!(
+                "{} {} (expected {})",
+                name,
+                actual.as_deref().unwrap_or("none"),
+                expected
+            ));
+        }
+    }
+    if let Some(max) = expectation.max_warnings.filter(|&m| result.warnings > m) {
+        failures.push(format!("warnings {} (at most {})", result.warnings, max));
+    }
+    failures
+}
+
+/// Seconds each sample took in the run recorded as the baseline.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Baseline {
+    pub samples: Vec<(String, f64)>,
+}
+
+impl Baseline {
+    pub fn to_json(&self) -> String {
+        let samples: Vec<String> = self
+            .samples
+            .iter()
+            .map(|(file, seconds)| {
+                format!(
+                    "  {{\"file\":{},\"seconds\":{:.3}}}",
+                    json_string(file),
+                    seconds
+                )
+            })
+            .collect();
+        format!(
+            "{{\"version\":{},\"samples\":[\n{}\n]}}\n",
+            BASELINE_VERSION,
+            samples.join(",\n")
+        )
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let root = Json::parse(contents)?;
+        let version = root.get("version")?.as_u64()?;
+        if version != BASELINE_VERSION {
+            return Err(format!(
+                "unsupported version {} (expected {})",
+                version, BASELINE_VERSION
+            ));
+        }
+        let samples = root
+            .get("samples")?
+            .as_array()?
+            .iter()
+            .map(|s| {
+                Ok((
+                    s.get("file")?.as_str()?.to_string(),
+                    s.get("seconds")?.as_f64()?,
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Baseline { samples })
+    }
+
+    /// Reads the baseline at `path`; empty when there is none yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Baseline::default()),
+            Err(e) => anyhow::bail!("Failed to read file: {}: {}", path.display(), e),
+        };
+        Baseline::parse(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid baseline: {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_json())
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))
+    }
+
+    pub fn seconds(&self, file: &str) -> Option<f64> {
+        self.samples.iter().find(|(f, _)| f == file).map(|s| s.1)
+    }
+
+    /// Records the time of `file`, keeping the samples that were not run.
+    pub fn set(&mut self, file: &str, seconds: f64) {
+        match self.samples.iter_mut().find(|(f, _)| f == file) {
+            Some(sample) => sample.1 = seconds,
+            None => self.samples.push((file.to_string(), seconds)),
+        }
+    }
+}
+
+/// One sample of a corpus run; `Err` holds why its conversion failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorpusRow {
+    pub file: String,
+    pub result: Result<SampleResult, String>,
+    pub failures: Vec<String>,
+}
+
+impl CorpusRow {
+    pub fn new(expectation: &Expectation, result: Result<SampleResult, String>) -> Self {
+        let failures = match &result {
+            Ok(r) => check(expectation, r),
+            Err(_) => Vec::new(),
+        };
+        CorpusRow {
+            file: expectation.file.clone(),
+            result,
+            failures,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.result.is_ok() && self.failures.is_empty()
+    }
+}
+
+/// `seconds` against the baseline time: `10.00s (+12.3%)`, or `-` without one.
+fn timing_delta(seconds: f64, baseline: Option<f64>) -> String {
+    match baseline {
+        Some(b) if b > 0.0 => format!("{:.2}s ({:+.1}%)", b, (seconds / b - 1.0) * 100.0),
+        Some(b) => format!("{:.2}s", b),
+        None => "-".to_string(),
+    }
+}
+
+/// The run as an aligned pass/fail table, one line per sample.
+pub fn render_corpus_table(rows: &[CorpusRow], baseline: &Baseline) -> String {
+    let header = [
+        "SAMPLE", "EVENTS", "FIRST", "LAST", "WARNINGS", "TIME", "BASELINE", "RESULT",
+    ];
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| match &row.result {
+            Ok(r) => vec![
+                row.file.clone(),
+                r.events.to_string(),
+                r.first_tc.clone().unwrap_or_else(|| "-".to_string()),
+                r.last_tc.clone().unwrap_or_else(|| "-".to_string()),
+                r.warnings.to_string(),
+                format!("{:.2}s", r.seconds),
+                timing_delta(r.seconds, baseline.seconds(&row.file)),
+                match row.failures.is_empty() {
+                    true => "ok".to_string(),
+                    false => format!("FAIL: {}", row.failures.join("; ")),
+                },
+            ],
+            Err(e) => vec![row.file.clone(), format!("FAILED: {}", e)],
+        })
+        .collect();
+    render_table(&header, &cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(events: usize, first: &str, last: &str, warnings: usize) -> SampleResult {
+        SampleResult {
+            events,
+            first_tc: Some(first.to_string()),
+            last_tc: Some(last.to_string()),
+            warnings,
+            seconds: 2.0,
+        }
+    }
+
+    #[test]
+    fn test_parse_expectations() {
+        let contents = "# tricky captures\n\
+                        pts_wrap.ts events=120..130 first=00:00:05:12 warnings=0\n\
+                        \n\
+                        \"burst 2.m2ts\" events=40 last=00:23:10:01  # dense\n\
+                        plain.ts\n";
+        let expectations = parse_expectations(contents).unwrap();
+        assert_eq!(
+            expectations,
+            [
+                Expectation {
+                    file: "pts_wrap.ts".to_string(),
+                    events: Some((120, 130)),
+                    first_tc: Some("00:00:05:12".to_string()),
+                    last_tc: None,
+                    max_warnings: Some(0),
+                },
+                Expectation {
+                    file: "burst 2.m2ts".to_string(),
+                    events: Some((40, 40)),
+                    last_tc: Some("00:23:10:01".to_string()),
+                    ..Default::default()
+                },
+                Expectation {
+                    file: "plain.ts".to_string(),
+                    ..Default::default()
+                },
+            ]
+        );
+        let error = |contents: &str| parse_expectations(contents).unwrap_err();
+        assert_eq!(error("a.ts events=9..3"), "line 1: invalid events: 9..3");
+        assert_eq!(
+            error("\na.ts first=0:00:05"),
+            "line 2: invalid first: 0:00:05"
+        );
+        assert_eq!(error("a.ts speed=1"), "line 1: unknown key: speed");
+        assert_eq!(
+            error("a.ts warnings"),
+            "line 1: expected key=value: warnings"
+        );
+        assert_eq!(error("\"a.ts events=1"), "line 1: unterminated file name");
+        assert_eq!(error("a.ts\na.ts"), "line 2: a.ts is listed twice");
+        assert_eq!(error("# nothing\n"), "no samples listed");
+    }
+
+    #[test]
+    fn test_check() {
+        let expectation = parse_expectations(
+            "a.ts events=100..120 first=00:00:05:12 last=00:23:10:01 warnings=1",
+        )
+        .unwrap()
+        .remove(0);
+        let passing = result(100, "00:00:05:12", "00:23:10:01", 1);
+        assert_eq!(check(&expectation, &passing), Vec::<String>::new());
+        let failing = SampleResult {
+            last_tc: None,
+            ..result(98, "00:00:05:11", "", 3)
+        };
+        assert_eq!(
+            check(&expectation, &failing),
+            [
+                "events 98 (expected 100..120)",
+                "first 00:00:05:11 (expected 00:00:05:12)",
+                "last none (expected 00:23:10:01)",
+                "warnings 3 (at most 1)",
+            ]
+        );
+        // Nothing expected, nothing checked.
+        let open = Expectation {
+            file: "a.ts".to_string(),
+            ..Default::default()
+        };
+        assert!(check(&open, &SampleResult::default()).is_empty());
+    }
+
+    #[test]
+    fn test_record() {
+        let mut result = SampleResult::default();
+        result.record(1, "00:00:01:00");
+        result.record(2, "00:00:03:00");
+        result.record(3, "00:00:07:15");
+        assert_eq!(result.events, 3);
+        assert_eq!(result.first_tc.as_deref(), Some("00:00:01:00"));
+        assert_eq!(result.last_tc.as_deref(), Some("00:00:07:15"));
+    }
+
+    #[test]
+    fn test_baseline() {
+        let mut baseline = Baseline::default();
+        baseline.set("a.ts", 12.3456);
+        baseline.set("say \"b\".ts", 4.0);
+        baseline.set("a.ts", 10.0);
+        let json = baseline.to_json();
+        assert_eq!(
+            json,
+            "{\"version\":1,\"samples\":[\n  {\"file\":\"a.ts\",\"seconds\":10.000},\n  {\"file\":\"say \\\"b\\\".ts\",\"seconds\":4.000}\n]}\n"
+        );
+        assert_eq!(Baseline::parse(&json), Ok(baseline.clone()));
+        assert_eq!(baseline.seconds("a.ts"), Some(10.0));
+        assert_eq!(baseline.seconds("c.ts"), None);
+        assert!(Baseline::parse("{\"version\":2,\"samples\":[]}").is_err());
+        assert!(Baseline::parse("{\"version\":1}").is_err());
+    }
+
+    #[test]
+    fn test_render_corpus_table() {
+        let expectations = parse_expectations("a.ts events=2\nb.ts warnings=0\nc.ts").unwrap();
+        let rows = [
+            CorpusRow::new(
+                &expectations[0],
+                Ok(result(2, "00:00:01:00", "00:00:09:00", 0)),
+            ),
+            CorpusRow::new(
+                &expectations[1],
+                Ok(result(5, "00:00:02:00", "00:01:00:00", 2)),
+            ),
+            CorpusRow::new(
+                &expectations[2],
+                Err("Input file does not exist".to_string()),
+            ),
+        ];
+        assert!(rows[0].passed());
+        assert!(!rows[1].passed());
+        assert!(!rows[2].passed());
+        let baseline = Baseline {
+            samples: vec![("a.ts".to_string(), 1.6)],
+        };
+        assert_eq!(
+            render_corpus_table(&rows, &baseline),
+            "SAMPLE  EVENTS  FIRST        LAST         WARNINGS  TIME   BASELINE        RESULT\n\
+             a.ts    2       00:00:01:00  00:00:09:00  0         2.00s  1.60s (+25.0%)  ok\n\
+             b.ts    5       00:00:02:00  00:01:00:00  2         2.00s  -               FAIL: warnings 2 (at most 0)\n\
+             c.ts    FAILED: Input file does not exist\n"
+        );
+    }
+}
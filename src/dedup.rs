@@ -0,0 +1,219 @@
+//! --dedup-png / --dedup-report: identical caption bitmaps share one image file.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::path::Path;
+
+use crate::bitmap::BitmapData;
+
+/// Identifies a bitmap by its size and two independent 64-bit hashes of its pixels, so pixels
+/// need not be kept for comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ContentKey {
+    width: i32,
+    height: i32,
+    fnv: u64,
+    sip: u64,
+}
+
+impl ContentKey {
+    fn of(bitmap: &BitmapData) -> Self {
+        let mut fnv: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut sip = DefaultHasher::new();
+        let row_bytes = bitmap.width.max(0) as usize * 4;
+        for y in 0..bitmap.height.max(0) as usize {
+            let start = y * bitmap.stride as usize;
+            let row = &bitmap.data[start..start + row_bytes];
+            for &b in row {
+                fnv = (fnv ^ b as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+            sip.write(row);
+        }
+        ContentKey {
+            width: bitmap.width,
+            height: bitmap.height,
+            fnv,
+            sip: sip.finish(),
+        }
+    }
+}
+
+/// One distinct caption image.
+#[derive(Debug)]
+struct Image {
+    file: String,
+    /// Events showing this image.
+    uses: usize,
+}
+
+/// Content-hash map of the caption images of one output, with reuse counters.
+#[derive(Debug, Default)]
+pub struct DedupIndex {
+    by_key: HashMap<ContentKey, usize>,
+    images: Vec<Image>,
+    events: usize,
+}
+
+impl DedupIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the bitmap of an event whose own image would be `file`. Returns the image of an
+    /// identical earlier event, or None when this is the first (`file` becomes its image).
+    pub fn record(&mut self, bitmap: &BitmapData, file: &str) -> Option<String> {
+        self.events += 1;
+        let key = ContentKey::of(bitmap);
+        if let Some(&i) = self.by_key.get(&key) {
+            self.images[i].uses += 1;
+            return Some(self.images[i].file.clone());
+        }
+        self.by_key.insert(key, self.images.len());
+        self.images.push(Image {
+            file: file.to_string(),
+            uses: 1,
+        });
+        None
+    }
+
+    /// Undoes the `record` that made `file` a new image (its event was dropped, e.g. because
+    /// the image could not be written).
+    pub fn forget(&mut self, file: &str) {
+        if self.images.last().is_some_and(|image| image.file == file) {
+            let index = self.images.len() - 1;
+            self.images.pop();
+            self.by_key.retain(|_, &mut i| i != index);
+            self.events -= 1;
+        }
+    }
+
+    /// Summary lines for --dedup-report. Bytes saved are the on-disk sizes in `dir` of the
+    /// images repeated events reuse (`applied`) or could reuse; 0 when nothing was written.
+    pub fn report_lines(&self, dir: &Path, applied: bool) -> Vec<String> {
+        let repeats = self.events - self.images.len();
+        let saved: u64 = self
+            .images
+            .iter()
+            .filter(|image| image.uses > 1)
+            .map(|image| {
+                let size = std::fs::metadata(dir.join(&image.file)).map_or(0, |m| m.len());
+                size * (image.uses - 1) as u64
+            })
+            .sum();
+        let mut lines = vec![format!(
+            "Dedup: {} unique image(s) for {} event(s), {} repeat(s) ({:.1}%), {:.2} MB {}",
+            self.images.len(),
+            self.events,
+            repeats,
+            if self.events > 0 {
+                repeats as f64 * 100.0 / self.events as f64
+            } else {
+                0.0
+            },
+            saved as f64 / 1_000_000.0,
+            if applied {
+                "saved"
+            } else {
+                "could be saved with --dedup-png"
+            }
+        )];
+        // First image wins ties, so the report is stable across runs.
+        let most = self
+            .images
+            .iter()
+            .rev()
+            .max_by_key(|image| image.uses)
+            .filter(|image| image.uses > 1);
+        if let Some(image) = most {
+            lines.push(format!(
+                "  Most repeated: {} ({} events)",
+                image.file, image.uses
+            ));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitmap(width: i32, height: i32, stride: i32, fill: u8) -> BitmapData {
+        BitmapData {
+            data: vec![fill; (stride * height) as usize],
+            width,
+            height,
+            stride,
+        }
+    }
+
+    #[test]
+    fn test_content_key() {
+        let a = bitmap(2, 2, 8, 7);
+        assert_eq!(ContentKey::of(&a), ContentKey::of(&a.clone()));
+        // Stride padding does not count; size and pixels do.
+        let mut padded = bitmap(2, 2, 12, 7);
+        padded.data[8..12].fill(99);
+        padded.data[20..24].fill(99);
+        assert_eq!(ContentKey::of(&a), ContentKey::of(&padded));
+        assert_ne!(ContentKey::of(&a), ContentKey::of(&bitmap(4, 1, 16, 7)));
+        let mut changed = a.clone();
+        changed.data[15] = 8;
+        assert_ne!(ContentKey::of(&a), ContentKey::of(&changed));
+    }
+
+    #[test]
+    fn test_record_and_report() {
+        let dir = crate::output::tests::scratch_dir("dedup");
+        let (a, b) = (bitmap(1, 1, 4, 1), bitmap(1, 1, 4, 2));
+        let mut index = DedupIndex::new();
+        assert_eq!(index.record(&a, "e_0000.png"), None);
+        assert_eq!(index.record(&b, "e_0001.png"), None);
+        assert_eq!(
+            index.record(&b, "e_0002.png"),
+            Some("e_0001.png".to_string())
+        );
+        assert_eq!(
+            index.record(&a, "e_0003.png"),
+            Some("e_0000.png".to_string())
+        );
+        assert_eq!(
+            index.record(&a, "e_0004.png"),
+            Some("e_0000.png".to_string())
+        );
+        std::fs::write(dir.join("e_0000.png"), vec![0; 500_000]).unwrap();
+        std::fs::write(dir.join("e_0001.png"), vec![0; 250_000]).unwrap();
+
+        assert_eq!(
+            index.report_lines(&dir, true),
+            [
+                "Dedup: 2 unique image(s) for 5 event(s), 3 repeat(s) (60.0%), 1.25 MB saved",
+                "  Most repeated: e_0000.png (3 events)",
+            ]
+        );
+        assert!(index.report_lines(&dir, false)[0].ends_with("could be saved with --dedup-png"));
+
+        let c = bitmap(1, 1, 4, 3);
+        assert_eq!(index.record(&c, "e_0005.png"), None);
+        index.forget("e_0005.png");
+        assert_eq!(index.record(&c, "e_0006.png"), None);
+        index.forget("e_0000.png");
+        assert_eq!(
+            index.record(&a, "e_0007.png"),
+            Some("e_0000.png".to_string())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_report_without_repeats() {
+        let mut index = DedupIndex::new();
+        assert_eq!(
+            index.report_lines(Path::new("."), true),
+            ["Dedup: 0 unique image(s) for 0 event(s), 0 repeat(s) (0.0%), 0.00 MB saved"]
+        );
+        index.record(&bitmap(1, 1, 4, 1), "e_0000.png");
+        assert_eq!(index.report_lines(Path::new("/nonexistent"), true).len(), 1);
+    }
+}
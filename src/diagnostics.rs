@@ -0,0 +1,138 @@
+//! Where log lines and warnings go. The command line prints them to stderr; a host embedding the
+//! conversion (the C API) can install its own handler to capture or drop them instead.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+/// How much a message matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Progress and what was detected.
+    Info,
+    /// Something was skipped or assumed; counted in `stats::warnings_printed`.
+    Warning,
+    /// One input of a batch failed.
+    Error,
+}
+
+/// Receives every message of the conversions run on the threads it is installed on.
+pub trait DiagnosticHandler: Send + Sync {
+    /// `text` has no `Warning: ` label or job prefix.
+    fn message(&self, level: Level, text: &str);
+}
+
+/// The command line's handler: one line on stderr per message, after the job prefix (--jobs).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrHandler;
+
+impl DiagnosticHandler for StderrHandler {
+    fn message(&self, level: Level, text: &str) {
+        let label = match level {
+            Level::Info => "",
+            Level::Warning => "Warning: ",
+            Level::Error => "Error: ",
+        };
+        eprintln!("{}{}{}", crate::jobs::log_prefix(), label, text);
+    }
+}
+
+thread_local! {
+    static HANDLER: RefCell<Option<Arc<dyn DiagnosticHandler>>> = const { RefCell::new(None) };
+}
+
+/// Installs the handler of the current thread (None: `StderrHandler`) and returns the previous
+/// one. Threads started for a batch (--jobs) get the handler of the thread that starts them.
+pub fn set_handler(
+    handler: Option<Arc<dyn DiagnosticHandler>>,
+) -> Option<Arc<dyn DiagnosticHandler>> {
+    HANDLER.with(|h| h.replace(handler))
+}
+
+/// The handler installed on the current thread, to hand on to the threads it starts.
+pub fn handler() -> Option<Arc<dyn DiagnosticHandler>> {
+    HANDLER.with(|h| h.borrow().clone())
+}
+
+/// Sends one message to the current thread's handler; warnings are counted first.
+pub fn emit(level: Level, args: fmt::Arguments) {
+    if level == Level::Warning {
+        crate::stats::count_warning();
+    }
+    let text = args.to_string();
+    match handler() {
+        Some(handler) => handler.message(level, &text),
+        None => StderrHandler.message(level, &text),
+    }
+}
+
+/// Keeps every message, for tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct Collector {
+    messages: std::sync::Mutex<Vec<(Level, String)>>,
+}
+
+#[cfg(test)]
+impl Collector {
+    /// The messages received so far, in order.
+    pub fn messages(&self) -> Vec<(Level, String)> {
+        self.messages
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+#[cfg(test)]
+impl DiagnosticHandler for Collector {
+    fn message(&self, level: Level, text: &str) {
+        self.messages
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((level, text.to_string()));
+    }
+}
+
+/// Puts the previous handler back when dropped, even on a panic.
+struct Restore(Option<Arc<dyn DiagnosticHandler>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        set_handler(self.0.take());
+    }
+}
+
+/// Runs `f` with `handler` installed on the current thread, then puts the previous one back.
+pub fn with_handler<R>(handler: Option<Arc<dyn DiagnosticHandler>>, f: impl FnOnce() -> R) -> R {
+    let _restore = Restore(set_handler(handler));
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collector() {
+        let collector = Arc::new(Collector::default());
+        let before = crate::stats::warnings_printed();
+        with_handler(Some(collector.clone()), || {
+            emit(Level::Info, format_args!("Probe: {}", 5));
+            emit(Level::Warning, format_args!("skipped"));
+            // Threads do not inherit it by themselves.
+            std::thread::spawn(|| assert!(handler().is_none()))
+                .join()
+                .unwrap();
+        });
+        assert!(handler().is_none());
+        assert_eq!(
+            collector.messages(),
+            [
+                (Level::Info, "Probe: 5".to_string()),
+                (Level::Warning, "skipped".to_string())
+            ]
+        );
+        assert!(crate::stats::warnings_printed() > before);
+    }
+}
@@ -0,0 +1,194 @@
+//! --dry-run: what a conversion would do for each input (selected stream, canvas, frame rate,
+//! outputs), worked out by the setup phase without decoding anything.
+
+use std::path::PathBuf;
+
+use crate::style::json_string;
+
+/// The setup decisions for one input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedInput {
+    /// PID of the selected subtitle stream, when the container has one.
+    pub pid: Option<i32>,
+    /// Caption or superimpose, when packets were seen while probing.
+    pub caption_type: Option<String>,
+    pub video: (i32, i32),
+    /// Frame the events are placed on.
+    pub canvas: String,
+    pub fps: f64,
+    pub fps_source: String,
+    pub output_dir: PathBuf,
+    pub base_name: String,
+    /// Files of a previous run for this base name in the output directory.
+    pub existing: usize,
+}
+
+/// One input of the plan; `Err` holds why it would fail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanRow {
+    pub input: String,
+    pub plan: Result<PlannedInput, String>,
+}
+
+fn or_dash<T: ToString>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
+
+/// The plan as an aligned text table, one line per input.
+pub fn render_plan_table(rows: &[PlanRow]) -> String {
+    let header = [
+        "INPUT", "PID", "TYPE", "VIDEO", "CANVAS", "FPS", "OUTPUT", "EXISTING",
+    ];
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| match &row.plan {
+            Ok(p) => vec![
+                row.input.clone(),
+                or_dash(p.pid),
+                or_dash(p.caption_type.as_ref()),
+                format!("{}x{}", p.video.0, p.video.1),
+                p.canvas.clone(),
+                format!("{:.3} ({})", p.fps, p.fps_source),
+                p.output_dir.join(&p.base_name).display().to_string(),
+                p.existing.to_string(),
+            ],
+            Err(e) => vec![row.input.clone(), format!("FAILED: {}", e)],
+        })
+        .collect();
+    render_table(&header, &cells)
+}
+
+/// `cells` as an aligned text table under `header`. Rows with fewer cells (a failure message)
+/// do not widen the columns.
+pub fn render_table(header: &[&str], cells: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for line in cells.iter().filter(|c| c.len() == header.len()) {
+        for (w, cell) in widths.iter_mut().zip(line) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let format_line = |cells: &[String]| {
+        let mut line = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            if i + 1 == cells.len() {
+                line.push_str(cell);
+            } else {
+                let pad = widths[i].saturating_sub(cell.chars().count());
+                line.push_str(&format!("{}{}  ", cell, " ".repeat(pad)));
+            }
+        }
+        line.push('\n');
+        line
+    };
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    let mut out = format_line(&header);
+    for line in cells {
+        out.push_str(&format_line(line));
+    }
+    out
+}
+
+/// The plan as a JSON array, one input per line (--dry-run --json).
+pub fn render_plan_json(rows: &[PlanRow]) -> String {
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|row| match &row.plan {
+            Ok(p) => format!(
+                "{{\"input\":{},\"ok\":true,\"pid\":{},\"caption_type\":{},\"video\":\"{}x{}\",\"canvas\":{},\"fps\":{},\"fps_source\":{},\"output_dir\":{},\"base_name\":{},\"existing\":{}}}",
+                json_string(&row.input),
+                or_null(p.pid),
+                p.caption_type.as_deref().map_or("null".to_string(), json_string),
+                p.video.0,
+                p.video.1,
+                json_string(&p.canvas),
+                p.fps,
+                json_string(&p.fps_source),
+                json_string(&p.output_dir.display().to_string()),
+                json_string(&p.base_name),
+                p.existing
+            ),
+            Err(e) => format!(
+                "{{\"input\":{},\"ok\":false,\"error\":{}}}",
+                json_string(&row.input),
+                json_string(e)
+            ),
+        })
+        .collect();
+    if lines.is_empty() {
+        return "[]\n".to_string();
+    }
+    format!("[\n{}\n]\n", lines.join(",\n"))
+}
+
+fn or_null<T: ToString>(value: Option<T>) -> String {
+    value.map_or("null".to_string(), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<PlanRow> {
+        vec![
+            PlanRow {
+                input: "rec/a.ts".to_string(),
+                plan: Ok(PlannedInput {
+                    pid: Some(0x130),
+                    caption_type: Some("caption".to_string()),
+                    video: (1440, 1080),
+                    canvas: "1920x1080".to_string(),
+                    fps: 30000.0 / 1001.0,
+                    fps_source: "avg_frame_rate".to_string(),
+                    output_dir: PathBuf::from("rec/a_bdnxml"),
+                    base_name: "a".to_string(),
+                    existing: 12,
+                }),
+            },
+            PlanRow {
+                input: "b.mkv".to_string(),
+                plan: Ok(PlannedInput {
+                    pid: None,
+                    caption_type: None,
+                    video: (1280, 720),
+                    canvas: "1280x720".to_string(),
+                    fps: 23.976,
+                    fps_source: "user".to_string(),
+                    output_dir: PathBuf::from("b_bdnxml"),
+                    base_name: "b".to_string(),
+                    existing: 0,
+                }),
+            },
+            PlanRow {
+                input: "broken.ts".to_string(),
+                plan: Err("No ARIB subtitle stream found.".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_render_plan_table() {
+        assert_eq!(
+            render_plan_table(&rows()),
+            "INPUT     PID  TYPE     VIDEO      CANVAS     FPS                      OUTPUT          EXISTING\n\
+             rec/a.ts  304  caption  1440x1080  1920x1080  29.970 (avg_frame_rate)  rec/a_bdnxml/a  12\n\
+             b.mkv     -    -        1280x720   1280x720   23.976 (user)            b_bdnxml/b      0\n\
+             broken.ts  FAILED: No ARIB subtitle stream found.\n"
+        );
+    }
+
+    #[test]
+    fn test_render_plan_json() {
+        let json = render_plan_json(&rows());
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[2],
+            r#"{"input":"b.mkv","ok":true,"pid":null,"caption_type":null,"video":"1280x720","canvas":"1280x720","fps":23.976,"fps_source":"user","output_dir":"b_bdnxml","base_name":"b","existing":0},"#
+        );
+        assert_eq!(
+            lines[3],
+            r#"{"input":"broken.ts","ok":false,"error":"No ARIB subtitle stream found."}"#
+        );
+        assert_eq!(render_plan_json(&[]), "[]\n");
+    }
+}
@@ -0,0 +1,101 @@
+//! --dual-output: the square-pixel 1920x1080 set made from the 1440x1080 anamorphic events, so
+//! both sets come out of one decode.
+
+use crate::bdn::{Graphic, SubtitleEvent};
+use crate::bitmap::{scale_bitmap, BitmapData};
+
+/// Canvas the events are decoded on.
+pub const ANAMORPHIC_CANVAS: &str = "1440x1080";
+/// Frame of the second set.
+pub const SQUARE_CANVAS: (i32, i32) = (1920, 1080);
+const SCALE_X: f64 = 4.0 / 3.0;
+
+/// Columns `x..x + width` of the anamorphic canvas on the square frame. The width depends only on
+/// `width`, so identical images scale to identical files wherever they sit (--dedup-png).
+pub fn widen_columns(x: i32, width: i32) -> (i32, i32) {
+    let width = (width as f64 * SCALE_X).round() as i32;
+    let x = (x as f64 * SCALE_X).round() as i32;
+    (x.clamp(0, (SQUARE_CANVAS.0 - width).max(0)), width)
+}
+
+/// A bitmap stretched horizontally for the square frame.
+pub fn widen_bitmap(bitmap: &BitmapData) -> BitmapData {
+    let (_, width) = widen_columns(0, bitmap.width);
+    scale_bitmap(bitmap, width, bitmap.height)
+}
+
+/// An event placed on the square frame; image files keep their names.
+pub fn widen_event(event: &SubtitleEvent) -> SubtitleEvent {
+    let (x, width) = widen_columns(event.x, event.width);
+    SubtitleEvent {
+        x,
+        width,
+        second: event.second.as_ref().map(|g| {
+            let (x, width) = widen_columns(g.x, g.width);
+            Graphic {
+                png_file: g.png_file.clone(),
+                x,
+                width,
+                ..*g
+            }
+        }),
+        ..event.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_widen_columns() {
+        assert_eq!(widen_columns(0, 1440), (0, 1920));
+        assert_eq!(widen_columns(240, 960), (320, 1280));
+        assert_eq!(widen_columns(361, 100), (481, 133));
+        assert_eq!(widen_columns(362, 100), (483, 133));
+        // Rounding never pushes an image past the right edge.
+        assert_eq!(widen_columns(1439, 1), (1919, 1));
+        assert_eq!(widen_columns(1, 1439), (1, 1919));
+    }
+
+    #[test]
+    fn test_widen_event() {
+        let event = SubtitleEvent {
+            in_tc: "00:00:01:00".to_string(),
+            out_tc: "00:00:02:00".to_string(),
+            png_file: "a00000.png".to_string(),
+            x: 240,
+            y: 900,
+            width: 300,
+            height: 120,
+            forced: true,
+            second: Some(Graphic {
+                png_file: "a00000_2.png".to_string(),
+                x: 900,
+                y: 900,
+                width: 300,
+                height: 120,
+            }),
+        };
+        let wide = widen_event(&event);
+        assert_eq!(
+            (wide.x, wide.y, wide.width, wide.height),
+            (320, 900, 400, 120)
+        );
+        let second = wide.second.as_ref().unwrap();
+        assert_eq!(
+            (second.x, second.width, second.png_file.as_str()),
+            (1200, 400, "a00000_2.png")
+        );
+        assert_eq!((wide.png_file.as_str(), wide.forced), ("a00000.png", true));
+
+        let bitmap = BitmapData {
+            data: vec![255; 300 * 4 * 2],
+            width: 300,
+            height: 2,
+            stride: 300 * 4,
+        };
+        let widened = widen_bitmap(&bitmap);
+        assert_eq!((widened.width, widened.height), (wide.width, 2));
+    }
+}
@@ -0,0 +1,189 @@
+//! Cut lists (--edl): drop captions inside removed ranges and shift later ones earlier.
+//!
+//! Accepted inputs:
+//! - "start end" per line (times via parse_time_string), optionally followed by an MPlayer/comskip
+//!   EDL action field (0 = cut; other actions are ignored)
+//! - Avidemux project scripts (`adm.addSegment(ref, start_us, duration_us)` lines), whose kept
+//!   segments are converted to the complementary cuts
+
+use std::path::Path;
+
+use crate::options::parse_time_string;
+
+/// Sorted, non-overlapping cut ranges in seconds. A range may extend to infinity.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CutList {
+    cuts: Vec<(f64, f64)>,
+}
+
+/// Parses the numeric arguments of an `adm.addSegment(...)` call.
+fn parse_avidemux_segment(line: &str) -> Option<(f64, f64)> {
+    let args = line
+        .trim()
+        .strip_prefix("adm.addSegment(")?
+        .split(')')
+        .next()?;
+    let fields: Vec<&str> = args.split(',').map(str::trim).collect();
+    if fields.len() != 3 {
+        return None;
+    }
+    let start_us: f64 = fields[1].parse().ok()?;
+    let duration_us: f64 = fields[2].parse().ok()?;
+    Some((start_us / 1e6, (start_us + duration_us) / 1e6))
+}
+
+impl CutList {
+    /// Builds a cut list from raw ranges: drops empty ranges, sorts, and merges overlapping or
+    /// adjacent ones.
+    pub fn new(mut ranges: Vec<(f64, f64)>) -> Self {
+        ranges.retain(|(s, e)| e > s);
+        ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut cuts: Vec<(f64, f64)> = Vec::with_capacity(ranges.len());
+        for (s, e) in ranges {
+            match cuts.last_mut() {
+                Some(last) if s <= last.1 => last.1 = last.1.max(e),
+                _ => cuts.push((s, e)),
+            }
+        }
+        CutList { cuts }
+    }
+
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let segments: Vec<(f64, f64)> = contents
+            .lines()
+            .filter(|l| l.trim_start().starts_with("adm.addSegment("))
+            .map(|l| {
+                parse_avidemux_segment(l)
+                    .ok_or_else(|| anyhow::anyhow!("edl: invalid avidemux segment: {}", l.trim()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        if !segments.is_empty() {
+            return Ok(Self::from_kept_segments(segments));
+        }
+
+        let mut ranges = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 2 || fields.len() > 3 {
+                anyhow::bail!("edl line {}: expected \"start end [action]\": {}", n + 1, line);
+            }
+            if fields.len() == 3 && fields[2] != "0" {
+                continue;
+            }
+            let start = parse_time_string(fields[0])
+                .map_err(|e| anyhow::anyhow!("edl line {}: {}", n + 1, e))?;
+            let end = parse_time_string(fields[1])
+                .map_err(|e| anyhow::anyhow!("edl line {}: {}", n + 1, e))?;
+            if end < start {
+                anyhow::bail!("edl line {}: end before start: {}", n + 1, line);
+            }
+            ranges.push((start, end));
+        }
+        Ok(Self::new(ranges))
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read EDL: {}: {}", path.display(), e))?;
+        Self::parse(&contents)
+    }
+
+    /// Cuts that remove everything outside the kept segments.
+    fn from_kept_segments(segments: Vec<(f64, f64)>) -> Self {
+        let kept = CutList::new(segments).cuts;
+        let mut cuts = Vec::new();
+        let mut pos = 0.0;
+        for (s, e) in kept {
+            if s > pos {
+                cuts.push((pos, s));
+            }
+            pos = e;
+        }
+        cuts.push((pos, f64::INFINITY));
+        CutList::new(cuts)
+    }
+
+    /// Maps a source time onto the cut timeline. Times inside a cut collapse to the cut point.
+    pub fn map_time(&self, t: f64) -> f64 {
+        let mut removed = 0.0;
+        for &(s, e) in &self.cuts {
+            if t <= s {
+                break;
+            }
+            removed += t.min(e) - s;
+        }
+        t - removed
+    }
+
+    /// Maps an event onto the cut timeline. Events entirely inside cuts are dropped (None);
+    /// events straddling a cut are clipped to the part that remains.
+    pub fn map_event(&self, start: f64, end: f64) -> Option<(f64, f64)> {
+        let (s, e) = (self.map_time(start), self.map_time(end));
+        (e > s).then_some((s, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_merges_overlapping_and_adjacent() {
+        let c = CutList::new(vec![(50.0, 60.0), (10.0, 20.0), (15.0, 30.0), (30.0, 40.0), (5.0, 5.0)]);
+        assert_eq!(c.cuts, vec![(10.0, 40.0), (50.0, 60.0)]);
+    }
+
+    #[test]
+    fn test_map_event_before_inside_after() {
+        let c = CutList::new(vec![(10.0, 20.0)]);
+        assert_eq!(c.map_event(1.0, 5.0), Some((1.0, 5.0)));
+        assert_eq!(c.map_event(12.0, 18.0), None);
+        assert_eq!(c.map_event(25.0, 27.0), Some((15.0, 17.0)));
+    }
+
+    #[test]
+    fn test_map_event_at_boundaries() {
+        let c = CutList::new(vec![(10.0, 20.0)]);
+        // Ends exactly where the cut starts: untouched.
+        assert_eq!(c.map_event(8.0, 10.0), Some((8.0, 10.0)));
+        // Starts exactly where the cut ends: shifted by the full cut.
+        assert_eq!(c.map_event(20.0, 22.0), Some((10.0, 12.0)));
+        // Exactly the cut: dropped.
+        assert_eq!(c.map_event(10.0, 20.0), None);
+    }
+
+    #[test]
+    fn test_map_event_straddling_and_spanning() {
+        let c = CutList::new(vec![(10.0, 20.0), (30.0, 40.0)]);
+        assert_eq!(c.map_event(8.0, 12.0), Some((8.0, 10.0)));
+        assert_eq!(c.map_event(18.0, 22.0), Some((10.0, 12.0)));
+        // Spans a whole cut: the cut portion is removed.
+        assert_eq!(c.map_event(5.0, 25.0), Some((5.0, 15.0)));
+        // After both cuts: shifted by 20s.
+        assert_eq!(c.map_event(45.0, 46.0), Some((25.0, 26.0)));
+    }
+
+    #[test]
+    fn test_parse_plain_and_mplayer_edl() {
+        let c = CutList::parse("# cuts\n00:10 00:20\n30.0\t40.0\t0\n50 60 1\n").unwrap();
+        assert_eq!(c.cuts, vec![(10.0, 20.0), (30.0, 40.0)]);
+        assert!(CutList::parse("10").is_err());
+        assert!(CutList::parse("20 10").is_err());
+    }
+
+    #[test]
+    fn test_parse_avidemux_segments() {
+        let script = "adm.clearSegments()\n\
+                      adm.addSegment(0, 0, 10000000)\n\
+                      adm.addSegment(0, 20000000, 10000000)\n\
+                      adm.markerA = 0\n";
+        let c = CutList::parse(script).unwrap();
+        assert_eq!(c.cuts, vec![(10.0, 20.0), (30.0, f64::INFINITY)]);
+        assert_eq!(c.map_event(25.0, 26.0), Some((15.0, 16.0)));
+        assert_eq!(c.map_event(35.0, 36.0), None);
+    }
+}
@@ -0,0 +1,327 @@
+//! --event-map: which decoded subtitle frame each event came from, and why the other frames
+//! produced no event, so tools comparing runs can follow a caption to its output image.
+
+use std::collections::HashMap;
+
+use crate::bdn::SubtitleEvent;
+use crate::bitmap::{generate_image_filename, second_image_filename, ImageFormat};
+use crate::source_time::SourceTimes;
+use crate::style::json_string;
+
+/// Why a decoded frame has no event of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Only text/ASS rects.
+    NonBitmap,
+    /// A bitmap of zero width or height.
+    EmptyBitmap,
+    /// Inside an --edl cut.
+    Cut,
+    /// Ends where it starts once timed.
+    ZeroLength,
+    /// Its image could not be written.
+    WriteFailed,
+    /// Replaced by a corrected re-send (--correction-window).
+    Corrected,
+    /// Replaced by a later caption of a burst (--max-events-per-second).
+    Coalesced,
+    /// Not kept by --forced-heuristic.
+    ForcedHeuristic,
+    /// Merged into an event with the same timecodes (--merge-colliding).
+    Merged,
+    /// Not shown between --start and --end.
+    OutOfRange,
+    /// Ends before 00:00:00:00 once moved by --offset or --global-delay.
+    BeforeZero,
+}
+
+impl DropReason {
+    pub fn code(self) -> &'static str {
+        match self {
+            DropReason::NonBitmap => "non_bitmap",
+            DropReason::EmptyBitmap => "empty_bitmap",
+            DropReason::Cut => "cut",
+            DropReason::ZeroLength => "zero_length",
+            DropReason::WriteFailed => "write_failed",
+            DropReason::Corrected => "corrected",
+            DropReason::Coalesced => "coalesced",
+            DropReason::ForcedHeuristic => "forced_heuristic",
+            DropReason::Merged => "merged",
+            DropReason::OutOfRange => "out_of_range",
+            DropReason::BeforeZero => "before_zero",
+        }
+    }
+}
+
+/// Where an event's OutTC came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndReason {
+    /// A clear frame sent by the broadcaster.
+    Clear,
+    /// The display time carried by the caption itself.
+    DisplayTime,
+    /// The start of the next caption.
+    NextCaption,
+    /// --default-duration: nothing else ended it.
+    Default,
+}
+
+impl EndReason {
+    pub fn code(self) -> &'static str {
+        match self {
+            EndReason::Clear => "clear",
+            EndReason::DisplayTime => "display_time",
+            EndReason::NextCaption => "next_caption",
+            EndReason::Default => "default",
+        }
+    }
+}
+
+/// A decoded subtitle frame: its index in decode order (clear frames included) and timestamp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceFrame {
+    pub index: usize,
+    pub timestamp: f64,
+    /// Packet pts in the stream time base.
+    pub pts: Option<i64>,
+    /// Seconds from the packet time to the display start (`SubtitleFrame::display_latency`).
+    pub latency: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroppedFrame {
+    pub frame: SourceFrame,
+    pub reason: DropReason,
+}
+
+/// Source frame of each event, parallel to the events, and the frames dropped on the way. Clear
+/// frames end a caption rather than being dropped, so they are in neither.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventMap {
+    /// Number of the first image (--start-index).
+    pub start_index: usize,
+    /// None for events resumed from a --checkpoint.
+    pub sources: Vec<Option<SourceFrame>>,
+    /// Where each event's OutTC came from, parallel to `sources`.
+    pub ends: Vec<Option<EndReason>>,
+    pub dropped: Vec<DroppedFrame>,
+    /// Clear frames that were not ignored, with the timecode they map to
+    /// (--include-clear-events).
+    pub clears: Vec<(SourceFrame, String)>,
+    /// Adds the broadcast `wall_clock` of each event (--emit-source-times).
+    pub source_times: Option<SourceTimes>,
+}
+
+impl EventMap {
+    /// Records a frame that produced no event.
+    pub fn drop_frame(&mut self, frame: SourceFrame, reason: DropReason) {
+        self.dropped.push(DroppedFrame { frame, reason });
+    }
+
+    /// Records a new event, ended for `end` so far.
+    pub fn event_added(&mut self, frame: SourceFrame, end: EndReason) {
+        self.sources.push(Some(frame));
+        self.ends.push(Some(end));
+    }
+
+    /// Records that the newest event now ends for `end`.
+    pub fn event_ended(&mut self, end: EndReason) {
+        if let Some(last) = self.ends.last_mut() {
+            *last = Some(end);
+        }
+    }
+
+    /// Records the newest event being taken out (replaced by the next one).
+    pub fn event_removed(&mut self, reason: DropReason) {
+        self.ends.pop();
+        if let Some(frame) = self.sources.pop().flatten() {
+            self.drop_frame(frame, reason);
+        }
+    }
+
+    /// Keeps the events whose `keep` entry is true; the others are dropped for `reason`.
+    pub fn retain(&mut self, keep: &[bool], reason: DropReason) {
+        let sources = std::mem::take(&mut self.sources);
+        let ends = std::mem::take(&mut self.ends);
+        for ((source, end), &keep) in sources.into_iter().zip(ends).zip(keep) {
+            if keep {
+                self.sources.push(source);
+                self.ends.push(end);
+            } else if let Some(frame) = source {
+                self.drop_frame(frame, reason);
+            }
+        }
+    }
+
+    /// The map as JSON: each event with its image, what ended it and its source frame (with its
+    /// packet pts and display latency, and with `source_times` its wall-clock time), and the
+    /// dropped frames in decode order; with `include_clears` also the clear frames.
+    pub fn to_json(&self, events: &[SubtitleEvent], include_clears: bool) -> String {
+        let frame = |f: &Option<SourceFrame>| match f {
+            Some(f) => format!(
+                "\"decode_index\":{},\"timestamp\":{:.3},\"pts\":{},\"latency_ms\":{:.0}",
+                f.index,
+                f.timestamp,
+                f.pts.map_or("null".to_string(), |p| p.to_string()),
+                f.latency * 1000.0
+            ),
+            None => "\"decode_index\":null,\"timestamp\":null,\"pts\":null,\"latency_ms\":null"
+                .to_string(),
+        };
+        let wall_clock = |f: &Option<SourceFrame>| match &self.source_times {
+            Some(times) => format!(",{}", times.json_field(f.map(|f| f.timestamp))),
+            None => String::new(),
+        };
+        let events: Vec<String> = events
+            .iter()
+            .zip(&self.sources)
+            .zip(&self.ends)
+            .enumerate()
+            .map(|(i, ((e, source), end))| {
+                format!(
+                    "    {{\"index\":{},\"png_file\":{},\"in_tc\":{},\"out_tc\":{},\"end\":{},{}{}}}",
+                    i,
+                    json_string(&e.png_file),
+                    json_string(&e.in_tc),
+                    json_string(&e.out_tc),
+                    end.map_or("null".to_string(), |r| json_string(r.code())),
+                    frame(source),
+                    wall_clock(source)
+                )
+            })
+            .collect();
+        let mut dropped = self.dropped.clone();
+        dropped.sort_by_key(|d| d.frame.index);
+        let dropped: Vec<String> = dropped
+            .iter()
+            .map(|d| {
+                format!(
+                    "    {{\"index\":{},\"reason\":\"{}\",\"timestamp\":{:.3}}}",
+                    d.frame.index,
+                    d.reason.code(),
+                    d.frame.timestamp
+                )
+            })
+            .collect();
+        let list = |lines: &[String]| match lines.is_empty() {
+            true => "[]".to_string(),
+            false => format!("[\n{}\n  ]", lines.join(",\n")),
+        };
+        let clears = match include_clears {
+            true => {
+                let clears: Vec<String> = self
+                    .clears
+                    .iter()
+                    .map(|(f, tc)| {
+                        format!(
+                            "    {{\"decode_index\":{},\"timestamp\":{:.3},\"tc\":{}}}",
+                            f.index,
+                            f.timestamp,
+                            json_string(tc)
+                        )
+                    })
+                    .collect();
+                format!(",\n  \"clears\": {}", list(&clears))
+            }
+            false => String::new(),
+        };
+        format!(
+            "{{\n  \"start_index\": {},\n  \"events\": {},\n  \"dropped\": {}{}\n}}\n",
+            self.start_index,
+            list(&events),
+            list(&dropped),
+            clears
+        )
+    }
+}
+
+/// Names the images of `events` by their position after `start_index` (--start-index), so
+/// dropping events leaves no gaps, and returns the (old, new) file renames in event order. Events
+/// sharing an image (--dedup-png) keep sharing it.
+pub fn renumber_images(
+    events: &mut [SubtitleEvent],
+    start_index: usize,
+    base_name: &str,
+    format: ImageFormat,
+) -> Vec<(String, String)> {
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut renames = Vec::new();
+    for (i, event) in events.iter_mut().enumerate() {
+        let old = event.png_file.clone();
+        let new = names.entry(old.clone()).or_insert_with(|| {
+            let new = generate_image_filename(start_index + i, base_name, format);
+            if new != old {
+                renames.push((old.clone(), new.clone()));
+                if event.second.is_some() {
+                    renames.push((second_image_filename(&old), second_image_filename(&new)));
+                }
+            }
+            new
+        });
+        if let Some(second) = &mut event.second {
+            second.png_file = second_image_filename(new);
+        }
+        event.png_file = new.clone();
+    }
+    renames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdn::Graphic;
+
+    fn event(png_file: &str) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: "00:00:01:00".to_string(),
+            out_tc: "00:00:02:00".to_string(),
+            png_file: png_file.to_string(),
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 10,
+            forced: false,
+            second: None,
+        }
+    }
+
+    #[test]
+    fn test_renumber_images() {
+        let mut split = event("a00003.png");
+        split.second = Some(Graphic {
+            png_file: "a00003_2.png".to_string(),
+            x: 20,
+            y: 0,
+            width: 10,
+            height: 10,
+        });
+        // Events 1, 2 and 4 were dropped; the last one reuses the image of the second.
+        let mut events = [
+            event("a00000.png"),
+            split,
+            event("a00005.png"),
+            event("a00003.png"),
+        ];
+        let renames = renumber_images(&mut events, 0, "a", ImageFormat::Png);
+        assert_eq!(
+            renames,
+            [
+                ("a00003.png".to_string(), "a00001.png".to_string()),
+                ("a00003_2.png".to_string(), "a00001_2.png".to_string()),
+                ("a00005.png".to_string(), "a00002.png".to_string()),
+            ]
+        );
+        let files: Vec<&str> = events.iter().map(|e| e.png_file.as_str()).collect();
+        assert_eq!(
+            files,
+            ["a00000.png", "a00001.png", "a00002.png", "a00001.png"]
+        );
+        assert_eq!(events[1].second.as_ref().unwrap().png_file, "a00001_2.png");
+        assert_eq!(events[3].second, None);
+
+        // A second part numbered on from the first (--start-index).
+        let mut events = [event("b00000.png"), event("b00002.png")];
+        renumber_images(&mut events, 40, "b", ImageFormat::Png);
+        assert_eq!(events[1].png_file, "b00041.png");
+    }
+}
@@ -1,44 +1,258 @@
 //! FFmpeg wrapper: open file, detect ARIB subtitle stream, init decoder, composite AVSubtitle to RGBA.
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::ffi::{CStr, CString};
-use std::os::raw::c_int;
+use std::io::ErrorKind;
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
 use std::ptr;
+use std::time::{Duration, Instant};
 
+use crate::arib::{caption_type, CaptionType, DrcsStats};
 use crate::bitmap::BitmapData;
 use crate::config;
+use crate::diagnostics::Level;
 use crate::ffmpeg_sys::*;
+use crate::packet_log::{PacketLog, PacketRecord};
+use crate::throttle::TokenBucket;
+use crate::timing::{select_frame_rate, FrameRateCandidates, FrameRateSource, RatePreference};
 
 const AV_NOPTS_VALUE: i64 = i64::MIN;
 const INVALID_DISPLAY_TIME: u32 = 0xFFFF_FFFF;
+/// Packets read to detect caption types (a few minutes of a broadcast TS; caption management
+/// data is repeated well within that).
+const CAPTION_TYPE_PROBE_PACKETS: usize = 30_000;
 
 /// Video stream info (resolution, FPS, start time).
 #[derive(Debug, Clone)]
 pub struct VideoInfo {
     pub width: i32,
     pub height: i32,
+    /// Selected frame rate; 0 when no credible rate was found.
     pub fps: f64,
+    /// Where `fps` came from; None when it is 0.
+    pub fps_source: Option<FrameRateSource>,
+    /// Rates reported by the video stream; None without a video stream.
+    pub frame_rates: Option<FrameRateCandidates>,
+    /// Video sample aspect ratio; 0 when unknown.
+    pub sample_aspect_ratio: f64,
+    /// Container start_time in seconds.
     pub start_time: f64,
+    /// First video pts in seconds (video stream start_time), if known.
+    pub video_start_time: Option<f64>,
+    /// Container duration in seconds, if known.
+    pub duration: Option<f64>,
+}
+
+/// Something the video stream's parameters lack, typically because a damaged video PID could
+/// not be analyzed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoProbeProblem {
+    /// Width or height is 0.
+    NoSize,
+    /// None of the reported frame rates is credible.
+    NoFrameRate,
+}
+
+impl VideoInfo {
+    /// What is unusable in the parameters of the video stream; empty when they look sound or
+    /// there is no video stream.
+    pub fn probe_problems(&self) -> Vec<VideoProbeProblem> {
+        let mut problems = Vec::new();
+        if self.frame_rates.is_none() {
+            return problems;
+        }
+        if self.width <= 0 || self.height <= 0 {
+            problems.push(VideoProbeProblem::NoSize);
+        }
+        if self.fps_source.is_none() {
+            problems.push(VideoProbeProblem::NoFrameRate);
+        }
+        problems
+    }
+
+    /// One-line summary of the video stream's parameters for diagnostics.
+    pub fn describe(&self) -> String {
+        match &self.frame_rates {
+            Some(rates) => format!("{}x{}, {}", self.width, self.height, rates.describe()),
+            None => "no video stream".to_string(),
+        }
+    }
 }
 
 /// A single subtitle frame (bitmap or clear command).
 #[derive(Debug)]
-#[allow(dead_code)] // pts used internally for timestamp calculation
 pub struct SubtitleFrame {
     pub bitmap: Option<BitmapData>,
+    /// Packet pts in the stream time base; `timestamp` is it in seconds.
     pub pts: i64,
     pub timestamp: f64,
     pub start_time: f64,
     pub end_time: f64,
     pub x: i32,
     pub y: i32,
+    /// Rect types in the decoded AVSubtitle.
+    pub rects: RectCounts,
+    /// Text of SUBTITLE_TEXT / SUBTITLE_ASS rects, one per line; None when there were none.
+    pub text: Option<String>,
+}
+
+/// Number of rects of each type in one AVSubtitle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RectCounts {
+    pub bitmap: usize,
+    pub text: usize,
+    pub ass: usize,
+    /// SUBTITLE_NONE or unknown types.
+    pub other: usize,
+}
+
+/// What a subtitle frame means for the event list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// No rects: ends the displayed caption.
+    Clear,
+    /// At least one bitmap rect (other rects are ignored).
+    Bitmap,
+    /// Only text/ASS/unknown rects: nothing to render, and not a clear either.
+    NonBitmap,
+}
+
+impl RectCounts {
+    /// Counts rect types (null rects are not passed in).
+    pub fn from_types(types: impl IntoIterator<Item = AVSubtitleType>) -> Self {
+        let mut counts = RectCounts::default();
+        for t in types {
+            if t == AVSubtitleType_SUBTITLE_BITMAP {
+                counts.bitmap += 1;
+            } else if t == AVSubtitleType_SUBTITLE_TEXT {
+                counts.text += 1;
+            } else if t == AVSubtitleType_SUBTITLE_ASS {
+                counts.ass += 1;
+            } else {
+                counts.other += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.bitmap + self.text + self.ass + self.other
+    }
+
+    pub fn kind(&self) -> FrameKind {
+        if self.bitmap > 0 {
+            FrameKind::Bitmap
+        } else if self.total() == 0 {
+            FrameKind::Clear
+        } else {
+            FrameKind::NonBitmap
+        }
+    }
+}
+
+impl std::fmt::Display for RectCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bitmap {}, text {}, ass {}, other {}",
+            self.bitmap, self.text, self.ass, self.other
+        )
+    }
+}
+
+impl SubtitleFrame {
+    /// Classification from the rect counts.
+    pub fn kind(&self) -> FrameKind {
+        self.rects.kind()
+    }
+
+    /// The packet pts, if the packet had one.
+    pub fn packet_pts(&self) -> Option<i64> {
+        (self.pts != AV_NOPTS_VALUE).then_some(self.pts)
+    }
+
+    /// Seconds from the packet time to the display start the caption asks for; 0 without a
+    /// display window (the caption starts at the packet).
+    pub fn display_latency(&self) -> f64 {
+        if self.start_time > 0.0 && self.end_time > self.start_time {
+            self.start_time - self.timestamp
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Explicit subtitle stream selection (instead of the first ARIB subtitle stream).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamSelection {
+    /// Container stream index.
+    Index(u32),
+    /// Stream id; for MPEG-TS this is the PID.
+    Pid(i32),
+}
+
+/// A file attached to the input (MKV/MKS attachment streams).
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub mimetype: String,
+    pub data: Vec<u8>,
+}
+
+/// An ARIB subtitle stream of the input (--list-streams).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubtitleStreamInfo {
+    pub index: u32,
+    /// Stream id; for MPEG-TS this is the PID.
+    pub id: i32,
+    /// None when no packet of the stream was seen while probing.
+    pub caption_type: Option<CaptionType>,
+}
+
+impl std::fmt::Display for SubtitleStreamInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "index {}, PID {} (0x{:X}): ",
+            self.index, self.id, self.id
+        )?;
+        match self.caption_type {
+            Some(t) => write!(f, "{}", t),
+            None => f.write_str("unknown (no packets while probing)"),
+        }
+    }
 }
 
 pub struct FfmpegWrapper {
     debug: bool,
+    stream_selection: Option<StreamSelection>,
+    /// Only select streams of this type (--caption-type); None takes any.
+    caption_type_filter: Option<CaptionType>,
+    /// Type of the selected stream, once known.
+    subtitle_caption_type: Option<CaptionType>,
+    fast_probe: bool,
+    open_retries: u32,
+    /// Also consider data/unknown streams that look like ARIB captions (--aggressive-stream-detect).
+    aggressive_stream_detect: bool,
+    /// The selected stream is such a data stream; it is decoded with libaribcaption by name.
+    subtitle_from_data_stream: bool,
+    /// Decode captions as text instead of bitmaps (--format stl).
+    text_output: bool,
+    /// Decoder thread count (--threads); None leaves FFmpeg's default.
+    threads: Option<u32>,
+    /// Paces input packets by size (--limit-rate MB/s).
+    read_limit: Option<TokenBucket>,
+    rate_preference: RatePreference,
+    drcs_stats: Option<DrcsStats>,
+    /// Every subtitle packet read (--packet-log).
+    packet_log: Option<PacketLog>,
     format_ctx: *mut AVFormatContext,
     codec_ctx: *mut AVCodecContext,
     codec: *const AVCodec,
+    /// Video decoder, opened on first use by --preview-on-video.
+    video_codec_ctx: *mut AVCodecContext,
     subtitle_stream_index: c_int,
     video_stream_index: c_int,
     video_info: VideoInfo,
@@ -46,24 +260,114 @@ pub struct FfmpegWrapper {
 
 unsafe impl Send for FfmpegWrapper {}
 
+/// Microsecond time base used by AVSubtitle.pts (AV_TIME_BASE_Q).
+const AV_TIME_BASE_Q: AVRational = AVRational {
+    num: 1,
+    den: AV_TIME_BASE as c_int,
+};
+
+/// Converts seconds to a pts in the given time base (inverse of `pts_to_seconds`).
+fn seconds_to_pts(seconds: f64, time_base: AVRational) -> i64 {
+    if time_base.num <= 0 || time_base.den <= 0 {
+        return 0;
+    }
+    (seconds * time_base.den as f64 / time_base.num as f64).round() as i64
+}
+
+/// Converts a pts in the given time base to seconds. Returns 0.0 for AV_NOPTS_VALUE
+/// or a time base with a non-positive numerator/denominator.
 fn pts_to_seconds(pts: i64, time_base: AVRational) -> f64 {
-    if pts == AV_NOPTS_VALUE {
+    if pts == AV_NOPTS_VALUE || time_base.num <= 0 || time_base.den <= 0 {
         return 0.0;
     }
-    let num = time_base.num as f64;
-    let den = time_base.den as f64;
-    if den == 0.0 {
+    // Multiply before dividing so 1/90000 and 1/1000 stay exact for whole-ms values.
+    pts as f64 * time_base.num as f64 / time_base.den as f64
+}
+
+/// Rational to f64; 0.0 for unset (0/0) or non-positive rationals.
+fn rational_to_f64(r: AVRational) -> f64 {
+    if r.num <= 0 || r.den <= 0 {
         return 0.0;
     }
-    pts as f64 * (num / den)
+    r.num as f64 / r.den as f64
+}
+
+/// Base timestamp (seconds) of a decoded subtitle. Packet pts is in the stream time base;
+/// AVSubtitle.pts (the fallback) is always in AV_TIME_BASE units.
+fn subtitle_base_seconds(packet_pts: i64, subtitle_pts: i64, time_base: AVRational) -> f64 {
+    if packet_pts != AV_NOPTS_VALUE {
+        pts_to_seconds(packet_pts, time_base)
+    } else {
+        pts_to_seconds(subtitle_pts, AV_TIME_BASE_Q)
+    }
+}
+
+/// Display window (start, end) in seconds from AVSubtitle display times (ms relative to base).
+/// Unset display times yield (base, base); end never precedes start.
+fn display_window(base: f64, start_display_ms: u32, end_display_ms: u32) -> (f64, f64) {
+    if start_display_ms == INVALID_DISPLAY_TIME || end_display_ms == INVALID_DISPLAY_TIME {
+        return (base, base);
+    }
+    let start = base + start_display_ms as f64 / 1000.0;
+    let end = base + end_display_ms.max(start_display_ms) as f64 / 1000.0;
+    (start, end)
 }
 
 fn ffmpeg_strerror(err: c_int) -> String {
-    let mut buf = [0i8; 64];
+    // AV_ERROR_MAX_STRING_SIZE is 64, but strerror() text for OS errors can be longer.
+    let mut buf: [c_char; 256] = [0; 256];
     unsafe {
         av_strerror(err, buf.as_mut_ptr(), buf.len());
-        CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
     }
+    // av_strerror always terminates within the buffer; keep the last byte as a guard anyway.
+    buf[buf.len() - 1] = 0;
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Converts `value` for FFmpeg. `what` names the value in the error: an interior NUL can come
+/// from a config file or a command-line value.
+fn c_string(what: &str, value: &str) -> anyhow::Result<CString> {
+    CString::new(value)
+        .map_err(|_| anyhow::anyhow!("Invalid {}: {:?} contains a NUL character.", what, value))
+}
+
+/// Converts a file name for FFmpeg, byte for byte where file names are bytes (Unix) and as
+/// UTF-8 elsewhere, so that names which are not UTF-8 still open.
+fn path_c_string(path: &Path) -> anyhow::Result<CString> {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str());
+    #[cfg(not(unix))]
+    let bytes = (path.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid path: {} is not UTF-8.", path.display()))?
+        .as_bytes();
+    CString::new(bytes)
+        .map_err(|_| anyhow::anyhow!("Invalid path: {:?} contains a NUL character.", path))
+}
+
+/// Converts option pairs for av_dict_set, failing on the first key or value with a NUL.
+fn option_c_strings<'a>(
+    options: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> anyhow::Result<Vec<(CString, CString)>> {
+    options
+        .into_iter()
+        .map(|(key, value)| {
+            Ok((
+                c_string("option name", key)?,
+                c_string(&format!("value for option {}", key), value)?,
+            ))
+        })
+        .collect()
+}
+
+/// The --debug line naming every option the decoder is opened with, sorted by name.
+fn decoder_options_line(options: &BTreeMap<&str, String>) -> String {
+    let options: Vec<String> = options
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    format!("Decoder options: {}", options.join(", "))
 }
 
 fn codec_name_has_arib(name: *const std::ffi::c_char) -> bool {
@@ -74,42 +378,161 @@ fn codec_name_has_arib(name: *const std::ffi::c_char) -> bool {
     s.contains("arib") || s.contains("libaribcaption")
 }
 
-/// Probes a file for video stream resolution. Returns (width, height) or error if no video stream.
-/// Used for .mks companion .mkv resolution when --anamorphic is set.
-pub fn probe_video_resolution(filename: &str) -> anyhow::Result<(i32, i32)> {
-    let c_path = CString::new(filename).map_err(|e| anyhow::anyhow!("path: {}", e))?;
-    unsafe {
-        let mut format_opts: *mut AVDictionary = ptr::null_mut();
-        let k1 = CString::new("analyzeduration").unwrap();
-        let v1 = CString::new("5000000").unwrap();
-        av_dict_set(&mut format_opts, k1.as_ptr(), v1.as_ptr(), 0);
-        let k2 = CString::new("probesize").unwrap();
-        let v2 = CString::new("5000000").unwrap();
-        av_dict_set(&mut format_opts, k2.as_ptr(), v2.as_ptr(), 0);
+/// Decoder opened explicitly on ARIB data streams (--aggressive-stream-detect).
+const ARIB_DECODER: &CStr = c"libaribcaption";
+
+/// Data component ID of ARIB captions (data_component_descriptor), which some demuxers leave in
+/// the codec tag of a caption stream they could not identify.
+const ARIB_CAPTION_COMPONENT_ID: u32 = 0x0008;
+
+/// --aggressive-stream-detect: whether a data or unknown stream may carry ARIB captions, going
+/// by its codec id, the name of the decoder for that id, or its codec tag.
+fn is_arib_data_stream(
+    codec_type: AVMediaType,
+    codec_id: AVCodecID,
+    codec_tag: u32,
+    decoder: Option<&str>,
+) -> bool {
+    if codec_type != AVMediaType_AVMEDIA_TYPE_DATA && codec_type != AVMediaType_AVMEDIA_TYPE_UNKNOWN
+    {
+        return false;
+    }
+    codec_id == AVCodecID_AV_CODEC_ID_ARIB_CAPTION
+        || decoder.is_some_and(|name| name.contains("arib"))
+        || codec_tag == ARIB_CAPTION_COMPONENT_ID
+}
+
+/// First delay between --open-retries attempts; doubled per attempt up to OPEN_RETRY_MAX_DELAY.
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(500);
+const OPEN_RETRY_MAX_DELAY: Duration = Duration::from_secs(8);
 
+/// True if an FFmpeg error from opening or probing a file may go away on retry: an OS I/O error
+/// other than the file being missing or inaccessible. FFmpeg's own codes (invalid data, EOF,
+/// unknown format) are permanent.
+fn is_transient_error(err: c_int) -> bool {
+    if !(-4095..0).contains(&err) {
+        return false;
+    }
+    !matches!(
+        std::io::Error::from_raw_os_error(-err).kind(),
+        ErrorKind::NotFound
+            | ErrorKind::PermissionDenied
+            | ErrorKind::NotADirectory
+            | ErrorKind::IsADirectory
+            | ErrorKind::InvalidInput
+            | ErrorKind::Unsupported
+    )
+}
+
+/// Delay before retry number `attempt` (0-based).
+fn retry_delay(attempt: u32) -> Duration {
+    OPEN_RETRY_DELAY
+        .saturating_mul(1 << attempt.min(16))
+        .min(OPEN_RETRY_MAX_DELAY)
+}
+
+/// Opens `filename` with the given format options and reads its stream info. Transient I/O
+/// failures of either step are retried up to `retries` times with a doubling delay.
+unsafe fn open_format_context(
+    filename: &Path,
+    options: &[(&str, &str)],
+    retries: u32,
+) -> anyhow::Result<*mut AVFormatContext> {
+    let c_path = path_c_string(filename)?;
+    let options = option_c_strings(options.iter().copied())?;
+    let mut attempt = 0;
+    loop {
+        let mut format_opts: *mut AVDictionary = ptr::null_mut();
+        for (k, v) in &options {
+            av_dict_set(&mut format_opts, k.as_ptr(), v.as_ptr(), 0);
+        }
         let mut ctx: *mut AVFormatContext = ptr::null_mut();
-        let ret = avformat_open_input(
-            &mut ctx,
-            c_path.as_ptr(),
-            ptr::null(),
-            &mut format_opts,
-        );
+        let mut ret = avformat_open_input(&mut ctx, c_path.as_ptr(), ptr::null(), &mut format_opts);
         if !format_opts.is_null() {
             av_dict_free(&mut format_opts);
         }
-        if ret < 0 {
-            anyhow::bail!(
-                "Failed to open file: {} ({})",
-                filename,
-                ffmpeg_strerror(ret)
-            );
+        let opened = ret >= 0;
+        if opened {
+            ret = avformat_find_stream_info(ctx, ptr::null_mut());
+            if ret >= 0 {
+                return Ok(ctx);
+            }
+            avformat_close_input(&mut ctx);
         }
 
-        let ret = avformat_find_stream_info(ctx, ptr::null_mut());
-        if ret < 0 {
-            avformat_close_input(&mut ctx);
+        if attempt < retries && is_transient_error(ret) {
+            let delay = retry_delay(attempt);
+            attempt += 1;
+            warning!(
+                "{}: {} ({}); retrying in {:.1}s ({}/{})",
+                filename.display(),
+                if opened { "reading stream info failed" } else { "open failed" },
+                ffmpeg_strerror(ret),
+                delay.as_secs_f64(),
+                attempt,
+                retries
+            );
+            std::thread::sleep(delay);
+            continue;
+        }
+        if opened {
             anyhow::bail!("Failed to get stream info: {}", ffmpeg_strerror(ret));
         }
+        anyhow::bail!(
+            "Failed to open file: {} ({})",
+            filename.display(),
+            ffmpeg_strerror(ret)
+        );
+    }
+}
+
+/// Whether the FFmpeg libraries have the libaribcaption decoder (--capabilities).
+pub fn has_arib_decoder() -> bool {
+    unsafe { !avcodec_find_decoder_by_name(ARIB_DECODER.as_ptr()).is_null() }
+}
+
+/// Whether the FFmpeg libraries have an encoder called `name` (--capabilities).
+pub fn has_encoder(name: &str) -> bool {
+    let Ok(name) = CString::new(name) else {
+        return false;
+    };
+    unsafe { !avcodec_find_encoder_by_name(name.as_ptr()).is_null() }
+}
+
+/// `major.minor.micro` of an FFmpeg library version number (`AV_VERSION_INT`).
+fn library_version(version: u32) -> String {
+    let (major, minor, micro) = (version >> 16, version >> 8 & 0xff, version & 0xff);
+    format!("{}.{}.{}", major, minor, micro)
+}
+
+/// The FFmpeg release and the versions of the libraries loaded at run time (--stats-json).
+pub fn library_versions() -> Vec<(&'static str, String)> {
+    unsafe {
+        let release = av_version_info();
+        let release = if release.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(release).to_string_lossy().into_owned()
+        };
+        vec![
+            ("ffmpeg", release),
+            ("libavcodec", library_version(avcodec_version())),
+            ("libavformat", library_version(avformat_version())),
+            ("libavutil", library_version(avutil_version())),
+        ]
+    }
+}
+
+/// Probes a file for video stream resolution. Returns (width, height) or error if no video stream.
+/// Used for .mks companion .mkv resolution when --anamorphic is set.
+pub fn probe_video_resolution(filename: &Path, open_retries: u32) -> anyhow::Result<(i32, i32)> {
+    install_log_callback();
+    unsafe {
+        let mut ctx = open_format_context(
+            filename,
+            &[("analyzeduration", "5000000"), ("probesize", "5000000")],
+            open_retries,
+        )?;
 
         let nb_streams = (*ctx).nb_streams;
         let mut width = 0i32;
@@ -133,33 +556,91 @@ pub fn probe_video_resolution(filename: &str) -> anyhow::Result<(i32, i32)> {
         avformat_close_input(&mut ctx);
 
         if width <= 0 || height <= 0 {
-            anyhow::bail!("No video stream found in {}", filename);
+            anyhow::bail!("No video stream found in {}", filename.display());
         }
         Ok((width, height))
     }
 }
 
+thread_local! {
+    /// FFmpeg log text of this thread not yet ended by a newline, and whether the next message
+    /// starts a line (and so gets the `[component @ address]` prefix).
+    static LOG_LINE: RefCell<(String, c_int)> = const { RefCell::new((String::new(), 1)) };
+}
+
+/// Replaces FFmpeg's default log callback, which writes to stderr, with one that sends each line
+/// to the diagnostics handler of the logging thread. Installed once per process.
+fn install_log_callback() {
+    static INSTALLED: std::sync::Once = std::sync::Once::new();
+    INSTALLED.call_once(|| unsafe { av_log_set_callback(Some(log_callback)) });
+}
+
+unsafe extern "C" fn log_callback(
+    avcl: *mut c_void,
+    level: c_int,
+    fmt: *const c_char,
+    vl: va_list,
+) {
+    if level > av_log_get_level() {
+        return;
+    }
+    let mut buf = [0 as c_char; 1024];
+    let size = buf.len() as c_int;
+    let text = LOG_LINE.with(|line| {
+        let (pending, print_prefix) = &mut *line.borrow_mut();
+        av_log_format_line2(avcl, level, fmt, vl, buf.as_mut_ptr(), size, print_prefix);
+        pending.push_str(&CStr::from_ptr(buf.as_ptr()).to_string_lossy());
+        // FFmpeg builds some lines from several calls; they are sent once complete.
+        pending.ends_with('\n').then(|| std::mem::take(pending))
+    });
+    if let Some(text) = text.as_deref().map(str::trim_end).filter(|t| !t.is_empty()) {
+        crate::diagnostics::emit(Level::Info, format_args!("{}", text));
+    }
+}
+
 impl FfmpegWrapper {
     pub fn new() -> Self {
+        install_log_callback();
         unsafe {
             av_log_set_level(AV_LOG_FATAL as c_int);
         }
         FfmpegWrapper {
             debug: false,
+            stream_selection: None,
+            caption_type_filter: None,
+            subtitle_caption_type: None,
+            fast_probe: false,
+            open_retries: 0,
+            aggressive_stream_detect: false,
+            subtitle_from_data_stream: false,
+            text_output: false,
+            threads: None,
+            read_limit: None,
+            rate_preference: RatePreference::Guess,
+            drcs_stats: None,
+            packet_log: None,
             format_ctx: ptr::null_mut(),
             codec_ctx: ptr::null_mut(),
             codec: ptr::null(),
+            video_codec_ctx: ptr::null_mut(),
             subtitle_stream_index: -1,
             video_stream_index: -1,
             video_info: VideoInfo {
                 width: 0,
                 height: 0,
                 fps: 0.0,
+                fps_source: None,
+                frame_rates: None,
+                sample_aspect_ratio: 0.0,
                 start_time: 0.0,
+                video_start_time: None,
+                duration: None,
             },
         }
     }
 
+    /// With `debug`, FFmpeg's own messages up to INFO are logged too, through the diagnostics
+    /// handler (`log_callback`).
     pub fn set_debug(&mut self, debug: bool) {
         self.debug = debug;
         unsafe {
@@ -171,77 +652,358 @@ impl FfmpegWrapper {
         }
     }
 
-    pub fn open_file(&mut self, filename: &str) -> anyhow::Result<()> {
-        let c_path = CString::new(filename).map_err(|e| anyhow::anyhow!("path: {}", e))?;
+    /// Select the subtitle stream explicitly instead of taking the first ARIB subtitle stream.
+    pub fn set_stream_selection(&mut self, selection: Option<StreamSelection>) {
+        self.stream_selection = selection;
+    }
 
-        let mut format_opts: *mut AVDictionary = ptr::null_mut();
+    /// Only select caption streams of the given type. The type is read from the stream's first
+    /// packets, so the input is probed and reopened when this is set.
+    pub fn set_caption_type_filter(&mut self, filter: Option<CaptionType>) {
+        self.caption_type_filter = filter;
+    }
+
+    /// Type of the selected subtitle stream: known after `open_file` with a filter, otherwise
+    /// after its first packet was decoded.
+    pub fn caption_type(&self) -> Option<CaptionType> {
+        self.subtitle_caption_type
+    }
+
+    /// Use a small analyzeduration/probesize. Only takes effect with an explicit stream selection,
+    /// since full stream analysis is not needed to find a known stream.
+    pub fn set_fast_probe(&mut self, fast_probe: bool) {
+        self.fast_probe = fast_probe;
+    }
+
+    /// When no subtitle stream is ARIB, try data/unknown streams that look like ARIB captions.
+    pub fn set_aggressive_stream_detect(&mut self, aggressive: bool) {
+        self.aggressive_stream_detect = aggressive;
+    }
+
+    /// Retry transient failures opening the input this many times (--open-retries).
+    pub fn set_open_retries(&mut self, retries: u32) {
+        self.open_retries = retries;
+    }
+
+    /// Decoder thread count for the caption and video decoders (--threads).
+    pub fn set_threads(&mut self, threads: Option<u32>) {
+        self.threads = threads;
+    }
+
+    /// Limit input reading to `bytes_per_second` (--limit-rate).
+    pub fn set_read_limit(&mut self, bytes_per_second: Option<f64>) {
+        self.read_limit = bytes_per_second.map(|rate| TokenBucket::new(rate, Instant::now()));
+    }
+
+    /// Decode captions as text (libaribcaption sub_type "text"); must be set before init_decoder.
+    pub fn set_text_output(&mut self, text_output: bool) {
+        self.text_output = text_output;
+    }
+
+    /// Which reported video frame rate to use.
+    pub fn set_rate_preference(&mut self, preference: RatePreference) {
+        self.rate_preference = preference;
+    }
+
+    /// Count DRCS glyph definitions in the caption packets read from now on.
+    pub fn enable_drcs_report(&mut self) {
+        self.drcs_stats = Some(DrcsStats::default());
+    }
+
+    pub fn drcs_stats(&self) -> Option<&DrcsStats> {
+        self.drcs_stats.as_ref()
+    }
+
+    /// Record every subtitle packet read from now on.
+    pub fn enable_packet_log(&mut self) {
+        self.packet_log = Some(PacketLog::default());
+    }
+
+    pub fn packet_log(&self) -> Option<&PacketLog> {
+        self.packet_log.as_ref()
+    }
+
+    /// Opens the container and reads stream info, without selecting streams.
+    fn open_format(&mut self, filename: &Path) -> anyhow::Result<()> {
+        let probe_limit = if self.fast_probe && self.stream_selection.is_some() {
+            "5000000"
+        } else {
+            "150000000"
+        };
+        if self.debug {
+            log_line!("Probe: analyzeduration/probesize {}", probe_limit);
+        }
+        let options = [
+            ("analyzeduration", probe_limit),
+            ("probesize", probe_limit),
+            ("fflags", "+genpts+igndts"),
+        ];
+        self.format_ctx = unsafe { open_format_context(filename, &options, self.open_retries)? };
+        Ok(())
+    }
+
+    /// ARIB subtitle streams of the open input, with their caption types left unknown.
+    fn arib_subtitle_streams(&self) -> Vec<SubtitleStreamInfo> {
+        let mut found = Vec::new();
         unsafe {
-            let k1 = CString::new("analyzeduration").unwrap();
-            let v1 = CString::new("150000000").unwrap();
-            av_dict_set(&mut format_opts, k1.as_ptr(), v1.as_ptr(), 0);
-            let k2 = CString::new("probesize").unwrap();
-            let v2 = CString::new("150000000").unwrap();
-            av_dict_set(&mut format_opts, k2.as_ptr(), v2.as_ptr(), 0);
-            let k3 = CString::new("fflags").unwrap();
-            let v3 = CString::new("+genpts+igndts").unwrap();
-            av_dict_set(&mut format_opts, k3.as_ptr(), v3.as_ptr(), 0);
-
-            let mut ctx: *mut AVFormatContext = ptr::null_mut();
-            let ret = avformat_open_input(
-                &mut ctx,
-                c_path.as_ptr(),
-                ptr::null(),
-                &mut format_opts,
-            );
-            if !format_opts.is_null() {
-                av_dict_free(&mut format_opts);
+            for i in 0..(*self.format_ctx).nb_streams {
+                let stream = *(*self.format_ctx).streams.add(i as usize);
+                if stream.is_null() || (*stream).codecpar.is_null() {
+                    continue;
+                }
+                let codecpar = (*stream).codecpar;
+                if (*codecpar).codec_type != AVMediaType_AVMEDIA_TYPE_SUBTITLE {
+                    continue;
+                }
+                let codec = avcodec_find_decoder((*codecpar).codec_id);
+                if !codec.is_null() && codec_name_has_arib((*codec).name) {
+                    found.push(SubtitleStreamInfo {
+                        index: i,
+                        id: (*stream).id,
+                        caption_type: None,
+                    });
+                }
             }
-            if ret < 0 {
-                anyhow::bail!(
-                    "Failed to open file: {} ({})",
-                    filename,
-                    ffmpeg_strerror(ret)
-                );
+        }
+        found
+    }
+
+    /// Data/unknown streams of the open input that may carry ARIB captions.
+    fn arib_data_streams(&self) -> Vec<SubtitleStreamInfo> {
+        let mut found = Vec::new();
+        unsafe {
+            for i in 0..(*self.format_ctx).nb_streams {
+                let stream = *(*self.format_ctx).streams.add(i as usize);
+                if stream.is_null() || (*stream).codecpar.is_null() {
+                    continue;
+                }
+                let codecpar = (*stream).codecpar;
+                let codec = avcodec_find_decoder((*codecpar).codec_id);
+                let decoder = (!codec.is_null() && !(*codec).name.is_null())
+                    .then(|| CStr::from_ptr((*codec).name).to_string_lossy());
+                if is_arib_data_stream(
+                    (*codecpar).codec_type,
+                    (*codecpar).codec_id,
+                    (*codecpar).codec_tag,
+                    decoder.as_deref(),
+                ) {
+                    found.push(SubtitleStreamInfo {
+                        index: i,
+                        id: (*stream).id,
+                        caption_type: None,
+                    });
+                }
             }
-            self.format_ctx = ctx;
         }
+        found
+    }
 
+    /// Whether `stream` is the one --stream-index/--pid asks for (any, without a selection).
+    fn is_selected(&self, stream: &SubtitleStreamInfo) -> bool {
+        match self.stream_selection {
+            None => true,
+            Some(StreamSelection::Index(idx)) => idx == stream.index,
+            Some(StreamSelection::Pid(pid)) => stream.id == pid,
+        }
+    }
+
+    /// Opens libaribcaption on stream `index` and decodes its first non-empty packet. Leaves the
+    /// input mid-file; reopen it before decoding.
+    unsafe fn decodes_as_arib(&mut self, index: u32) -> bool {
+        let codec = avcodec_find_decoder_by_name(ARIB_DECODER.as_ptr());
+        if codec.is_null() {
+            return false;
+        }
+        let mut ctx = avcodec_alloc_context3(codec);
+        if ctx.is_null() {
+            return false;
+        }
+        let stream = *(*self.format_ctx).streams.add(index as usize);
+        (*ctx).time_base = (*stream).time_base;
+        (*ctx).width = 1920;
+        (*ctx).height = 1080;
+        (*ctx).pix_fmt = AVPixelFormat_AV_PIX_FMT_RGBA;
+        let mut opts: *mut AVDictionary = ptr::null_mut();
+        av_dict_set(&mut opts, c"sub_type".as_ptr(), c"bitmap".as_ptr(), 0);
+        av_dict_set(&mut opts, c"canvas_size".as_ptr(), c"1920x1080".as_ptr(), 0);
+        let opened = avcodec_open2(ctx, codec, &mut opts) >= 0;
+        av_dict_free(&mut opts);
+        let mut decoded = false;
+        let mut packet = if opened {
+            av_packet_alloc()
+        } else {
+            ptr::null_mut()
+        };
+        if !packet.is_null() {
+            let mut read = 0;
+            while read < CAPTION_TYPE_PROBE_PACKETS && av_read_frame(self.format_ctx, packet) >= 0 {
+                read += 1;
+                if (*packet).stream_index as u32 == index && (*packet).size > 0 {
+                    let mut subtitle = std::mem::zeroed::<AVSubtitle>();
+                    let mut got_subtitle: c_int = 0;
+                    decoded =
+                        avcodec_decode_subtitle2(ctx, &mut subtitle, &mut got_subtitle, packet)
+                            >= 0;
+                    if got_subtitle != 0 {
+                        avsubtitle_free(&mut subtitle);
+                    }
+                    av_packet_unref(packet);
+                    break;
+                }
+                av_packet_unref(packet);
+            }
+            av_packet_free(&mut packet);
+        }
+        avcodec_free_context(&mut ctx);
+        decoded
+    }
+
+    /// The first selected data stream whose first packet libaribcaption decodes
+    /// (--aggressive-stream-detect). Reopens the input after each try.
+    fn find_arib_data_stream(
+        &mut self,
+        filename: &Path,
+    ) -> anyhow::Result<Option<SubtitleStreamInfo>> {
+        let streams: Vec<SubtitleStreamInfo> = self
+            .arib_data_streams()
+            .into_iter()
+            .filter(|s| self.is_selected(s))
+            .collect();
+        for stream in streams {
+            let decoded = unsafe { self.decodes_as_arib(stream.index) };
+            // The try consumed packets; start over from the beginning of the file.
+            unsafe { avformat_close_input(&mut self.format_ctx) };
+            self.format_ctx = ptr::null_mut();
+            self.open_format(filename)?;
+            if self.debug {
+                let verdict = if decoded {
+                    "decodes as ARIB captions"
+                } else {
+                    "not ARIB captions"
+                };
+                log_line!("Data stream {}: {}", stream, verdict);
+            }
+            if decoded {
+                return Ok(Some(stream));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads packets from the current position to fill in the caption types of `streams`.
+    /// Leaves the input mid-file; reopen it before decoding.
+    fn probe_caption_types(&mut self, streams: &mut [SubtitleStreamInfo]) -> anyhow::Result<()> {
         unsafe {
-            let ret = avformat_find_stream_info(self.format_ctx, ptr::null_mut());
-            if ret < 0 {
-                self.close();
-                anyhow::bail!("Failed to get stream info: {}", ffmpeg_strerror(ret));
+            let mut packet = av_packet_alloc();
+            if packet.is_null() {
+                anyhow::bail!("Failed to allocate packet.");
+            }
+            let mut read = 0;
+            while read < CAPTION_TYPE_PROBE_PACKETS
+                && streams.iter().any(|s| s.caption_type.is_none())
+                && av_read_frame(self.format_ctx, packet) >= 0
+            {
+                read += 1;
+                let index = (*packet).stream_index as u32;
+                if let Some(s) = streams.iter_mut().find(|s| s.index == index) {
+                    if s.caption_type.is_none() && !(*packet).data.is_null() && (*packet).size > 0 {
+                        s.caption_type = caption_type(std::slice::from_raw_parts(
+                            (*packet).data,
+                            (*packet).size as usize,
+                        ));
+                    }
+                }
+                av_packet_unref(packet);
             }
+            av_packet_free(&mut packet);
+            if self.debug {
+                log_line!("Caption type probe: {} packets read", read);
+            }
+        }
+        Ok(())
+    }
 
+    /// ARIB subtitle streams of `filename` with their caption types (--list-streams).
+    pub fn list_subtitle_streams(
+        &mut self,
+        filename: &Path,
+    ) -> anyhow::Result<Vec<SubtitleStreamInfo>> {
+        self.open_format(filename)?;
+        let mut streams = self.arib_subtitle_streams();
+        let result = self.probe_caption_types(&mut streams);
+        self.close();
+        result.map(|()| streams)
+    }
+
+    pub fn open_file(&mut self, filename: &Path) -> anyhow::Result<()> {
+        self.open_format(filename)?;
+        self.subtitle_from_data_stream = false;
+        unsafe {
             let nb_streams = (*self.format_ctx).nb_streams;
             if self.debug {
-                eprintln!("Searching for subtitle stream... (total streams: {})", nb_streams);
+                log_line!("Searching for subtitle stream... (total streams: {})", nb_streams);
             }
 
-            for i in 0..nb_streams {
-                let stream = *(*self.format_ctx).streams.add(i as usize);
-                if stream.is_null() {
-                    continue;
-                }
-                let codecpar = (*stream).codecpar;
-                if codecpar.is_null() {
-                    continue;
+            let mut candidates: Vec<SubtitleStreamInfo> = self
+                .arib_subtitle_streams()
+                .into_iter()
+                .filter(|s| self.is_selected(s))
+                .collect();
+            if candidates.is_empty() && self.aggressive_stream_detect {
+                match self.find_arib_data_stream(filename) {
+                    Ok(Some(stream)) => {
+                        candidates.push(stream);
+                        self.subtitle_from_data_stream = true;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        self.close();
+                        return Err(e);
+                    }
                 }
-                if (*codecpar).codec_type == AVMediaType_AVMEDIA_TYPE_SUBTITLE {
-                    let codec = avcodec_find_decoder((*codecpar).codec_id);
-                    if !codec.is_null() && codec_name_has_arib((*codec).name) {
-                        self.subtitle_stream_index = i as c_int;
-                        if self.debug {
-                            eprintln!("Subtitle stream found: index {}", i);
-                        }
-                        break;
+            }
+            if candidates.is_empty() {
+                self.close();
+                match self.stream_selection {
+                    Some(StreamSelection::Index(idx)) => {
+                        anyhow::bail!("Stream index {} is not an ARIB subtitle stream.", idx)
+                    }
+                    Some(StreamSelection::Pid(pid)) => {
+                        anyhow::bail!("PID {} (0x{:X}) is not an ARIB subtitle stream.", pid, pid)
                     }
+                    None => anyhow::bail!("ARIB subtitle stream not found."),
                 }
             }
 
-            if self.subtitle_stream_index < 0 {
-                self.close();
-                anyhow::bail!("ARIB subtitle stream not found.");
+            if let Some(wanted) = self.caption_type_filter {
+                if let Err(e) = self.probe_caption_types(&mut candidates) {
+                    self.close();
+                    return Err(e);
+                }
+                // Probing consumed packets; start over from the beginning of the file.
+                avformat_close_input(&mut self.format_ctx);
+                self.format_ctx = ptr::null_mut();
+                self.open_format(filename)?;
+                if !candidates.iter().any(|s| s.caption_type == Some(wanted)) {
+                    self.close();
+                    let found: Vec<String> = candidates.iter().map(|s| s.to_string()).collect();
+                    anyhow::bail!("No ARIB {} stream found ({}).", wanted, found.join("; "));
+                }
+                candidates.retain(|s| s.caption_type == Some(wanted));
+            }
+
+            let chosen = candidates[0];
+            self.subtitle_stream_index = chosen.index as c_int;
+            self.subtitle_caption_type = chosen.caption_type;
+            if self.debug {
+                let stream = *(*self.format_ctx).streams.add(chosen.index as usize);
+                let tb = (*stream).time_base;
+                log_line!(
+                    "Subtitle stream found: index {} (time_base {}/{})",
+                    chosen.index,
+                    tb.num,
+                    tb.den
+                );
             }
 
             for i in 0..nb_streams {
@@ -262,13 +1024,29 @@ impl FfmpegWrapper {
                 let par = (*stream).codecpar;
                 self.video_info.width = (*par).width;
                 self.video_info.height = (*par).height;
-                let avg = (*stream).avg_frame_rate;
-                let r = (*stream).r_frame_rate;
-                if avg.num > 0 && avg.den > 0 {
-                    self.video_info.fps =
-                        (avg.num as f64) / (avg.den as f64);
-                } else if r.num > 0 && r.den > 0 {
-                    self.video_info.fps = (r.num as f64) / (r.den as f64);
+                let guessed = av_guess_frame_rate(self.format_ctx, stream, ptr::null_mut());
+                let rates = FrameRateCandidates {
+                    avg: rational_to_f64((*stream).avg_frame_rate),
+                    r: rational_to_f64((*stream).r_frame_rate),
+                    guessed: rational_to_f64(guessed),
+                };
+                if let Some((fps, source)) = select_frame_rate(&rates, self.rate_preference) {
+                    self.video_info.fps = fps;
+                    self.video_info.fps_source = Some(source);
+                }
+                if self.debug {
+                    log_line!("Video frame rates: {}", rates.describe());
+                }
+                self.video_info.frame_rates = Some(rates);
+                self.video_info.sample_aspect_ratio = rational_to_f64(av_guess_sample_aspect_ratio(
+                    self.format_ctx,
+                    stream,
+                    ptr::null_mut(),
+                ));
+                let video_start = (*stream).start_time;
+                if video_start != AV_NOPTS_VALUE {
+                    self.video_info.video_start_time =
+                        Some(pts_to_seconds(video_start, (*stream).time_base));
                 }
             }
 
@@ -278,6 +1056,10 @@ impl FfmpegWrapper {
             } else {
                 0.0
             };
+            let duration = (*self.format_ctx).duration;
+            if duration != AV_NOPTS_VALUE && duration > 0 {
+                self.video_info.duration = Some(duration as f64 / AV_TIME_BASE as f64);
+            }
         }
 
         Ok(())
@@ -287,19 +1069,125 @@ impl FfmpegWrapper {
         self.video_info.clone()
     }
 
+    /// Stream id (PID for MPEG-TS) of the selected subtitle stream.
+    pub fn subtitle_stream_pid(&self) -> Option<i32> {
+        if self.format_ctx.is_null() || self.subtitle_stream_index < 0 {
+            return None;
+        }
+        unsafe {
+            let stream = *(*self.format_ctx)
+                .streams
+                .add(self.subtitle_stream_index as usize);
+            Some((*stream).id)
+        }
+    }
+
+    /// Attachment streams of the open input with their contents.
+    pub fn attachments(&self) -> Vec<Attachment> {
+        let mut found = Vec::new();
+        if self.format_ctx.is_null() {
+            return found;
+        }
+        unsafe {
+            let tag = |stream: *mut AVStream, key: &CStr| {
+                let entry = av_dict_get((*stream).metadata, key.as_ptr(), ptr::null(), 0);
+                if entry.is_null() || (*entry).value.is_null() {
+                    String::new()
+                } else {
+                    CStr::from_ptr((*entry).value).to_string_lossy().into_owned()
+                }
+            };
+            for i in 0..(*self.format_ctx).nb_streams as usize {
+                let stream = *(*self.format_ctx).streams.add(i);
+                if stream.is_null() || (*stream).codecpar.is_null() {
+                    continue;
+                }
+                let codecpar = &*(*stream).codecpar;
+                if codecpar.codec_type != AVMediaType_AVMEDIA_TYPE_ATTACHMENT
+                    || codecpar.extradata.is_null()
+                    || codecpar.extradata_size <= 0
+                {
+                    continue;
+                }
+                found.push(Attachment {
+                    filename: tag(stream, c"filename"),
+                    mimetype: tag(stream, c"mimetype"),
+                    data: std::slice::from_raw_parts(
+                        codecpar.extradata,
+                        codecpar.extradata_size as usize,
+                    )
+                    .to_vec(),
+                });
+            }
+        }
+        found
+    }
+
+    /// service_name metadata of the program containing the subtitle stream (MPEG-TS), if any.
+    pub fn service_name(&self) -> Option<String> {
+        if self.format_ctx.is_null() || self.subtitle_stream_index < 0 {
+            return None;
+        }
+        unsafe {
+            let ctx = &*self.format_ctx;
+            let key = c"service_name";
+            for p in 0..ctx.nb_programs as usize {
+                let program = *ctx.programs.add(p);
+                if program.is_null() {
+                    continue;
+                }
+                let program = &*program;
+                let contains = (0..program.nb_stream_indexes as usize).any(|j| {
+                    *program.stream_index.add(j) == self.subtitle_stream_index as u32
+                });
+                if !contains {
+                    continue;
+                }
+                let entry = av_dict_get(program.metadata, key.as_ptr(), ptr::null(), 0);
+                if !entry.is_null() && !(*entry).value.is_null() {
+                    return Some(CStr::from_ptr((*entry).value).to_string_lossy().into_owned());
+                }
+            }
+        }
+        None
+    }
+
     pub fn init_decoder(
         &mut self,
-        libaribcaption_opts: &HashMap<String, String>,
+        libaribcaption_opts: &BTreeMap<String, String>,
     ) -> anyhow::Result<()> {
         if self.subtitle_stream_index < 0 {
             anyhow::bail!("Subtitle stream not configured.");
         }
+        let canvas_size = libaribcaption_opts.get("canvas_size");
+        let c_canvas = canvas_size
+            .map(|s| c_string("canvas_size", s))
+            .transpose()?;
+        let decoder_opts = option_c_strings(
+            libaribcaption_opts
+                .iter()
+                .filter(|(k, _)| *k != "sub_type" && *k != "canvas_size")
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        )?;
+        // Everything the decoder is opened with, for the --debug line.
+        let mut logged: BTreeMap<&str, String> = libaribcaption_opts
+            .iter()
+            .filter(|(k, _)| *k != "sub_type")
+            .map(|(k, v)| (k.as_str(), v.clone()))
+            .collect();
+        if let Some(threads) = self.threads {
+            logged.insert("threads", threads.to_string());
+        }
 
         unsafe {
             let stream = *(*self.format_ctx)
                 .streams
                 .add(self.subtitle_stream_index as usize);
-            self.codec = avcodec_find_decoder((*stream).codecpar.as_ref().unwrap().codec_id);
+            self.codec = if self.subtitle_from_data_stream {
+                avcodec_find_decoder_by_name(ARIB_DECODER.as_ptr())
+            } else {
+                avcodec_find_decoder((*stream).codecpar.as_ref().unwrap().codec_id)
+            };
             if self.codec.is_null() {
                 anyhow::bail!("Decoder not found.");
             }
@@ -319,19 +1207,26 @@ impl FfmpegWrapper {
             }
 
             (*self.codec_ctx).time_base = (*stream).time_base;
+            if self.subtitle_from_data_stream {
+                // The parameters say data; the decoder only opens on its own type and id.
+                (*self.codec_ctx).codec_type = AVMediaType_AVMEDIA_TYPE_SUBTITLE;
+                (*self.codec_ctx).codec_id = (*self.codec).id;
+            }
 
             let mut opts_dict: *mut AVDictionary = ptr::null_mut();
             if codec_name_has_arib((*self.codec).name) {
-                let k_st = CString::new("sub_type").unwrap();
-                let v_st = CString::new("bitmap").unwrap();
-                av_dict_set(&mut opts_dict, k_st.as_ptr(), v_st.as_ptr(), 0);
-                let canvas_size = match libaribcaption_opts.get("canvas_size") {
-                    Some(s) => s.as_str(),
-                    None => anyhow::bail!("canvas_size not set."),
+                let v_st = if self.text_output { c"text" } else { c"bitmap" };
+                av_dict_set(&mut opts_dict, c"sub_type".as_ptr(), v_st.as_ptr(), 0);
+                logged.insert("sub_type", v_st.to_string_lossy().into_owned());
+                let (Some(canvas_size), Some(c_canvas)) = (canvas_size, &c_canvas) else {
+                    anyhow::bail!("canvas_size not set.");
                 };
-                let c_canvas = CString::new(canvas_size).unwrap();
-                let k_canvas = CString::new("canvas_size").unwrap();
-                av_dict_set(&mut opts_dict, k_canvas.as_ptr(), c_canvas.as_ptr(), 0);
+                av_dict_set(
+                    &mut opts_dict,
+                    c"canvas_size".as_ptr(),
+                    c_canvas.as_ptr(),
+                    0,
+                );
                 if let Ok((w, h)) = config::parse_canvas_size(canvas_size) {
                     (*self.codec_ctx).width = w;
                     (*self.codec_ctx).height = h;
@@ -343,13 +1238,12 @@ impl FfmpegWrapper {
                 }
             }
 
-            for (k, v) in libaribcaption_opts {
-                if k == "sub_type" || k == "canvas_size" {
-                    continue;
-                }
-                let ck = CString::new(k.as_str()).unwrap();
-                let cv = CString::new(v.as_str()).unwrap();
-                av_dict_set(&mut opts_dict, ck.as_ptr(), cv.as_ptr(), 0);
+            for (k, v) in &decoder_opts {
+                av_dict_set(&mut opts_dict, k.as_ptr(), v.as_ptr(), 0);
+            }
+            self.set_threads_option(&mut opts_dict);
+            if self.debug {
+                log_line!("{}", decoder_options_line(&logged));
             }
 
             let ret = avcodec_open2(
@@ -369,7 +1263,40 @@ impl FfmpegWrapper {
         Ok(())
     }
 
-    pub fn get_next_subtitle_frame(&self) -> Option<SubtitleFrame> {
+    /// Adds the --threads decoder option to `dict`.
+    unsafe fn set_threads_option(&self, dict: &mut *mut AVDictionary) {
+        if let Some(threads) = self.threads {
+            // A number never contains a NUL.
+            let v = CString::new(threads.to_string()).unwrap_or_default();
+            av_dict_set(dict, c"threads".as_ptr(), v.as_ptr(), 0);
+        }
+    }
+
+    /// Seeks the input so subtitle decoding restarts at or before `seconds` (stream time), for
+    /// --checkpoint. Frames before `seconds` may still come out; the caller skips them.
+    pub fn seek_subtitles(&mut self, seconds: f64) -> anyhow::Result<()> {
+        if self.format_ctx.is_null() || self.codec_ctx.is_null() {
+            anyhow::bail!("Subtitle decoder not initialized.");
+        }
+        unsafe {
+            let stream = *(*self.format_ctx)
+                .streams
+                .add(self.subtitle_stream_index as usize);
+            let ret = av_seek_frame(
+                self.format_ctx,
+                self.subtitle_stream_index,
+                seconds_to_pts(seconds, (*stream).time_base),
+                AVSEEK_FLAG_BACKWARD as c_int,
+            );
+            if ret < 0 {
+                anyhow::bail!("Failed to seek to {:.3}s: {}", seconds, ffmpeg_strerror(ret));
+            }
+            avcodec_flush_buffers(self.codec_ctx);
+        }
+        Ok(())
+    }
+
+    pub fn get_next_subtitle_frame(&mut self) -> Option<SubtitleFrame> {
         if self.codec_ctx.is_null() || self.format_ctx.is_null() {
             return None;
         }
@@ -386,14 +1313,31 @@ impl FfmpegWrapper {
         result
     }
 
-    fn get_next_subtitle_frame_inner(&self, packet: *mut AVPacket) -> Option<SubtitleFrame> {
+    fn get_next_subtitle_frame_inner(&mut self, packet: *mut AVPacket) -> Option<SubtitleFrame> {
         unsafe {
             while av_read_frame(self.format_ctx, packet) >= 0 {
+                if let Some(limit) = self.read_limit.as_mut() {
+                    limit.pace((*packet).size.max(0) as f64);
+                }
                 if (*packet).stream_index != self.subtitle_stream_index {
                     av_packet_unref(packet);
                     continue;
                 }
 
+                if !(*packet).data.is_null() && (*packet).size > 0 {
+                    let payload =
+                        std::slice::from_raw_parts((*packet).data, (*packet).size as usize);
+                    if let Some(stats) = self.drcs_stats.as_mut() {
+                        stats.add_packet(payload);
+                    }
+                    if self.subtitle_caption_type.is_none() {
+                        self.subtitle_caption_type = caption_type(payload);
+                        if let (true, Some(t)) = (self.debug, self.subtitle_caption_type) {
+                            log_line!("Subtitle stream type: {}", t);
+                        }
+                    }
+                }
+
                 let mut subtitle = std::mem::zeroed::<AVSubtitle>();
                 let mut got_subtitle: c_int = 0;
                 let ret = avcodec_decode_subtitle2(
@@ -403,8 +1347,22 @@ impl FfmpegWrapper {
                     packet,
                 );
 
+                if let Some(log) = self.packet_log.as_mut() {
+                    let pts = (*packet).pts;
+                    let pts = (pts != AV_NOPTS_VALUE).then_some(pts);
+                    let stream = *(*self.format_ctx)
+                        .streams
+                        .add(self.subtitle_stream_index as usize);
+                    log.push(PacketRecord {
+                        pts,
+                        seconds: pts.map(|p| pts_to_seconds(p, (*stream).time_base)),
+                        size: (*packet).size.max(0) as usize,
+                        decoded: ret >= 0 && got_subtitle != 0,
+                    });
+                }
+
                 if ret < 0 {
-                    eprintln!("Warning: subtitle decode error: {}", ffmpeg_strerror(ret));
+                    warning!("subtitle decode error: {}", ffmpeg_strerror(ret));
                     av_packet_unref(packet);
                     continue;
                 }
@@ -419,28 +1377,37 @@ impl FfmpegWrapper {
                     .streams
                     .add(self.subtitle_stream_index as usize);
                 let time_base = (*stream).time_base;
-                let pts = if (*packet).pts != AV_NOPTS_VALUE {
-                    (*packet).pts
-                } else {
-                    subtitle.pts
-                };
-                let base_timestamp = pts_to_seconds(pts, time_base);
-                let start_time = if subtitle.start_display_time != INVALID_DISPLAY_TIME
-                    && subtitle.end_display_time != INVALID_DISPLAY_TIME
-                {
-                    base_timestamp + (subtitle.start_display_time as f64 / 1000.0)
-                } else {
-                    base_timestamp
-                };
-                let end_time = if subtitle.start_display_time != INVALID_DISPLAY_TIME
-                    && subtitle.end_display_time != INVALID_DISPLAY_TIME
-                {
-                    base_timestamp + (subtitle.end_display_time as f64 / 1000.0)
-                } else {
-                    base_timestamp
-                };
+                let pts = (*packet).pts;
+                let base_timestamp = subtitle_base_seconds(pts, subtitle.pts, time_base);
+                let (start_time, end_time) = display_window(
+                    base_timestamp,
+                    subtitle.start_display_time,
+                    subtitle.end_display_time,
+                );
 
-                if subtitle.num_rects == 0 {
+                let rects: Vec<&AVSubtitleRect> = (0..subtitle.num_rects as usize)
+                    .filter_map(|i| (*subtitle.rects.add(i)).as_ref())
+                    .collect();
+                let rect_counts = RectCounts::from_types(rects.iter().map(|r| r.type_));
+                let texts: Vec<String> = rects
+                    .iter()
+                    .filter_map(|r| {
+                        let p = if r.type_ == AVSubtitleType_SUBTITLE_TEXT {
+                            r.text
+                        } else if r.type_ == AVSubtitleType_SUBTITLE_ASS {
+                            r.ass
+                        } else {
+                            ptr::null_mut()
+                        };
+                        (!p.is_null()).then(|| CStr::from_ptr(p).to_string_lossy().into_owned())
+                    })
+                    .collect();
+                let text = (!texts.is_empty()).then(|| texts.join("\n"));
+
+                if rect_counts.kind() != FrameKind::Bitmap {
+                    // Text rects carry the caption position when the decoder provides one.
+                    let x = rects.iter().map(|r| r.x).min().unwrap_or(0);
+                    let y = rects.iter().map(|r| r.y).min().unwrap_or(0);
                     avsubtitle_free(&mut subtitle);
                     av_packet_unref(packet);
                     return Some(SubtitleFrame {
@@ -449,8 +1416,10 @@ impl FfmpegWrapper {
                         timestamp: base_timestamp,
                         start_time,
                         end_time,
-                        x: 0,
-                        y: 0,
+                        x,
+                        y,
+                        rects: rect_counts,
+                        text,
                     });
                 }
 
@@ -458,7 +1427,6 @@ impl FfmpegWrapper {
                 let mut min_y = i32::MAX;
                 let mut max_x = i32::MIN;
                 let mut max_y = i32::MIN;
-                let mut has_bitmap = false;
 
                 for i in 0..(subtitle.num_rects as usize) {
                     let rect_ptr = *subtitle.rects.add(i);
@@ -467,7 +1435,6 @@ impl FfmpegWrapper {
                     }
                     let rect = &*rect_ptr;
                     if rect.type_ == AVSubtitleType_SUBTITLE_BITMAP {
-                        has_bitmap = true;
                         min_x = min_x.min(rect.x);
                         min_y = min_y.min(rect.y);
                         max_x = max_x.max(rect.x + rect.w);
@@ -475,12 +1442,6 @@ impl FfmpegWrapper {
                     }
                 }
 
-                if !has_bitmap {
-                    avsubtitle_free(&mut subtitle);
-                    av_packet_unref(packet);
-                    continue;
-                }
-
                 let composite_width = max_x - min_x;
                 let composite_height = max_y - min_y;
                 let stride = composite_width * 4;
@@ -572,24 +1533,130 @@ impl FfmpegWrapper {
                     end_time,
                     x: min_x,
                     y: min_y,
+                    rects: rect_counts,
+                    text,
                 });
             }
         }
         None
     }
 
+    /// Decodes the first video frame at or after `seconds` (stream time) as RGBA at the video's
+    /// size. This seeks the input, so call it only after subtitle decoding has finished.
+    pub fn decode_video_frame_at(&mut self, seconds: f64) -> anyhow::Result<BitmapData> {
+        if self.format_ctx.is_null() || self.video_stream_index < 0 {
+            anyhow::bail!("No video stream.");
+        }
+        unsafe {
+            let stream = *(*self.format_ctx)
+                .streams
+                .add(self.video_stream_index as usize);
+            if self.video_codec_ctx.is_null() {
+                let codec = avcodec_find_decoder((*(*stream).codecpar).codec_id);
+                if codec.is_null() {
+                    anyhow::bail!("Video decoder not found.");
+                }
+                self.video_codec_ctx = avcodec_alloc_context3(codec);
+                if self.video_codec_ctx.is_null() {
+                    anyhow::bail!("Failed to create video decoder context.");
+                }
+                if avcodec_parameters_to_context(self.video_codec_ctx, (*stream).codecpar) < 0 {
+                    avcodec_free_context(&mut self.video_codec_ctx);
+                    anyhow::bail!("Failed to copy video decoder parameters.");
+                }
+                (*self.video_codec_ctx).pkt_timebase = (*stream).time_base;
+                let mut opts_dict: *mut AVDictionary = ptr::null_mut();
+                self.set_threads_option(&mut opts_dict);
+                let ret = avcodec_open2(self.video_codec_ctx, codec, &mut opts_dict);
+                if !opts_dict.is_null() {
+                    av_dict_free(&mut opts_dict);
+                }
+                if ret < 0 {
+                    avcodec_free_context(&mut self.video_codec_ctx);
+                    anyhow::bail!("Failed to open video decoder: {}", ffmpeg_strerror(ret));
+                }
+            }
+
+            let target = seconds_to_pts(seconds, (*stream).time_base);
+            let ret = av_seek_frame(
+                self.format_ctx,
+                self.video_stream_index,
+                target,
+                AVSEEK_FLAG_BACKWARD as c_int,
+            );
+            if ret < 0 {
+                anyhow::bail!("Failed to seek to {:.3}s: {}", seconds, ffmpeg_strerror(ret));
+            }
+            avcodec_flush_buffers(self.video_codec_ctx);
+
+            let mut packet = av_packet_alloc();
+            let mut frame = av_frame_alloc();
+            let result = if packet.is_null() || frame.is_null() {
+                Err(anyhow::anyhow!("Failed to allocate video frame."))
+            } else {
+                self.decode_video_until(packet, frame, target)
+                    .ok_or_else(|| anyhow::anyhow!("No video frame at {:.3}s.", seconds))
+                    .and_then(|()| frame_to_rgba(&*frame))
+            };
+            av_frame_free(&mut frame);
+            av_packet_free(&mut packet);
+            result
+        }
+    }
+
+    /// Reads and decodes video packets until `frame` holds a frame at or after `target`.
+    unsafe fn decode_video_until(
+        &mut self,
+        packet: *mut AVPacket,
+        frame: *mut AVFrame,
+        target: i64,
+    ) -> Option<()> {
+        while av_read_frame(self.format_ctx, packet) >= 0 {
+            if let Some(limit) = self.read_limit.as_mut() {
+                limit.pace((*packet).size.max(0) as f64);
+            }
+            if (*packet).stream_index != self.video_stream_index {
+                av_packet_unref(packet);
+                continue;
+            }
+            let sent = avcodec_send_packet(self.video_codec_ctx, packet);
+            av_packet_unref(packet);
+            if sent < 0 {
+                continue;
+            }
+            while avcodec_receive_frame(self.video_codec_ctx, frame) >= 0 {
+                let pts = (*frame).best_effort_timestamp;
+                if pts == AV_NOPTS_VALUE || pts >= target {
+                    return Some(());
+                }
+            }
+        }
+        None
+    }
+
     pub fn close(&mut self) {
         unsafe {
             if !self.codec_ctx.is_null() {
                 avcodec_free_context(&mut self.codec_ctx);
                 self.codec_ctx = ptr::null_mut();
             }
+            if !self.video_codec_ctx.is_null() {
+                avcodec_free_context(&mut self.video_codec_ctx);
+                self.video_codec_ctx = ptr::null_mut();
+            }
             if !self.format_ctx.is_null() {
                 avformat_close_input(&mut self.format_ctx);
                 self.format_ctx = ptr::null_mut();
             }
         }
         self.subtitle_stream_index = -1;
+        self.subtitle_caption_type = None;
+    }
+}
+
+impl Default for FfmpegWrapper {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -598,3 +1665,452 @@ impl Drop for FfmpegWrapper {
         self.close();
     }
 }
+
+/// Encoders tried for --preview-video, in order of preference.
+pub const PREVIEW_ENCODERS: [&str; 3] = ["libx264", "mpeg4", "ffv1"];
+
+/// Encodes YUV 4:2:0 frames at a constant frame rate into a file (--preview-video). The
+/// container is chosen from the file extension.
+#[cfg(feature = "preview-video")]
+pub struct VideoEncoder {
+    format_ctx: *mut AVFormatContext,
+    codec_ctx: *mut AVCodecContext,
+    stream: *mut AVStream,
+    frame: *mut AVFrame,
+    packet: *mut AVPacket,
+    /// Whether `format_ctx.pb` was opened by us (and must be closed).
+    opened_io: bool,
+    next_pts: i64,
+}
+
+#[cfg(feature = "preview-video")]
+impl VideoEncoder {
+    pub fn create(path: &Path, width: i32, height: i32, fps: i32) -> anyhow::Result<Self> {
+        let c_path = path_c_string(path)?;
+        let mut encoder = VideoEncoder {
+            format_ctx: ptr::null_mut(),
+            codec_ctx: ptr::null_mut(),
+            stream: ptr::null_mut(),
+            frame: ptr::null_mut(),
+            packet: ptr::null_mut(),
+            opened_io: false,
+            next_pts: 0,
+        };
+        unsafe {
+            let ret = avformat_alloc_output_context2(
+                &mut encoder.format_ctx,
+                ptr::null(),
+                ptr::null(),
+                c_path.as_ptr(),
+            );
+            if ret < 0 || encoder.format_ctx.is_null() {
+                anyhow::bail!(
+                    "Failed to create output: {}: {}",
+                    path.display(),
+                    ffmpeg_strerror(ret)
+                );
+            }
+            let codec = PREVIEW_ENCODERS
+                .iter()
+                .map(|name| {
+                    let name = CString::new(*name).unwrap();
+                    avcodec_find_encoder_by_name(name.as_ptr())
+                })
+                .find(|codec| !codec.is_null())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "No video encoder available (tried {}).",
+                        PREVIEW_ENCODERS.join(", ")
+                    )
+                })?;
+            encoder.codec_ctx = avcodec_alloc_context3(codec);
+            if encoder.codec_ctx.is_null() {
+                anyhow::bail!("Failed to create video encoder context.");
+            }
+            let ctx = &mut *encoder.codec_ctx;
+            ctx.width = width;
+            ctx.height = height;
+            ctx.time_base = AVRational { num: 1, den: fps };
+            ctx.framerate = AVRational { num: fps, den: 1 };
+            ctx.pix_fmt = AVPixelFormat_AV_PIX_FMT_YUV420P;
+            ctx.gop_size = fps * 10;
+            let oformat = (*encoder.format_ctx).oformat;
+            if (*oformat).flags & AVFMT_GLOBALHEADER as c_int != 0 {
+                ctx.flags |= AV_CODEC_FLAG_GLOBAL_HEADER as c_int;
+            }
+            let ret = avcodec_open2(encoder.codec_ctx, codec, ptr::null_mut());
+            if ret < 0 {
+                anyhow::bail!("Failed to open video encoder: {}", ffmpeg_strerror(ret));
+            }
+
+            encoder.stream = avformat_new_stream(encoder.format_ctx, ptr::null());
+            if encoder.stream.is_null() {
+                anyhow::bail!("Failed to create video stream.");
+            }
+            (*encoder.stream).time_base = ctx.time_base;
+            if avcodec_parameters_from_context((*encoder.stream).codecpar, encoder.codec_ctx) < 0 {
+                anyhow::bail!("Failed to copy video encoder parameters.");
+            }
+            if (*oformat).flags & AVFMT_NOFILE as c_int == 0 {
+                let ret = avio_open(
+                    &mut (*encoder.format_ctx).pb,
+                    c_path.as_ptr(),
+                    AVIO_FLAG_WRITE as c_int,
+                );
+                if ret < 0 {
+                    anyhow::bail!(
+                        "Failed to write file: {}: {}",
+                        path.display(),
+                        ffmpeg_strerror(ret)
+                    );
+                }
+                encoder.opened_io = true;
+            }
+            let ret = avformat_write_header(encoder.format_ctx, ptr::null_mut());
+            if ret < 0 {
+                anyhow::bail!("Failed to write header: {}", ffmpeg_strerror(ret));
+            }
+
+            encoder.frame = av_frame_alloc();
+            encoder.packet = av_packet_alloc();
+            if encoder.frame.is_null() || encoder.packet.is_null() {
+                anyhow::bail!("Failed to allocate video frame.");
+            }
+            (*encoder.frame).format = AVPixelFormat_AV_PIX_FMT_YUV420P;
+            (*encoder.frame).width = width;
+            (*encoder.frame).height = height;
+            if av_frame_get_buffer(encoder.frame, 0) < 0 {
+                anyhow::bail!("Failed to allocate video frame.");
+            }
+        }
+        Ok(encoder)
+    }
+
+    /// Encodes the next frame from Y, U and V planes (tightly packed, chroma at half size).
+    pub fn write_frame(&mut self, planes: &[Vec<u8>; 3]) -> anyhow::Result<()> {
+        unsafe {
+            if av_frame_make_writable(self.frame) < 0 {
+                anyhow::bail!("Failed to allocate video frame.");
+            }
+            let frame = &mut *self.frame;
+            for (i, plane) in planes.iter().enumerate() {
+                let (w, h) = if i == 0 {
+                    (frame.width as usize, frame.height as usize)
+                } else {
+                    (frame.width as usize / 2, frame.height as usize / 2)
+                };
+                for row in 0..h.min(plane.len() / w.max(1)) {
+                    ptr::copy_nonoverlapping(
+                        plane[row * w..].as_ptr(),
+                        frame.data[i].add(row * frame.linesize[i] as usize),
+                        w,
+                    );
+                }
+            }
+            frame.pts = self.next_pts;
+            self.next_pts += 1;
+            let ret = avcodec_send_frame(self.codec_ctx, self.frame);
+            if ret < 0 {
+                anyhow::bail!("Failed to encode video frame: {}", ffmpeg_strerror(ret));
+            }
+            self.write_packets()
+        }
+    }
+
+    /// Flushes the encoder and finishes the file.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        unsafe {
+            avcodec_send_frame(self.codec_ctx, ptr::null());
+            self.write_packets()?;
+            let ret = av_write_trailer(self.format_ctx);
+            if ret < 0 {
+                anyhow::bail!("Failed to write trailer: {}", ffmpeg_strerror(ret));
+            }
+        }
+        Ok(())
+    }
+
+    /// Muxes every packet the encoder has ready.
+    unsafe fn write_packets(&mut self) -> anyhow::Result<()> {
+        while avcodec_receive_packet(self.codec_ctx, self.packet) >= 0 {
+            av_packet_rescale_ts(
+                self.packet,
+                (*self.codec_ctx).time_base,
+                (*self.stream).time_base,
+            );
+            (*self.packet).stream_index = (*self.stream).index;
+            let ret = av_interleaved_write_frame(self.format_ctx, self.packet);
+            if ret < 0 {
+                anyhow::bail!("Failed to write video packet: {}", ffmpeg_strerror(ret));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "preview-video")]
+impl Drop for VideoEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            av_packet_free(&mut self.packet);
+            av_frame_free(&mut self.frame);
+            avcodec_free_context(&mut self.codec_ctx);
+            if !self.format_ctx.is_null() {
+                if self.opened_io {
+                    avio_closep(&mut (*self.format_ctx).pb);
+                }
+                avformat_free_context(self.format_ctx);
+            }
+        }
+    }
+}
+
+/// Converts a decoded 8-bit 4:2:0 video frame to RGBA.
+unsafe fn frame_to_rgba(frame: &AVFrame) -> anyhow::Result<BitmapData> {
+    let full_range = frame.format == AVPixelFormat_AV_PIX_FMT_YUVJ420P;
+    if frame.format != AVPixelFormat_AV_PIX_FMT_YUV420P && !full_range {
+        anyhow::bail!("Unsupported video pixel format for preview: {}", frame.format);
+    }
+    if frame.width <= 0 || frame.height <= 0 || frame.linesize[..3].iter().any(|&l| l <= 0) {
+        anyhow::bail!("Invalid video frame.");
+    }
+    let h = frame.height as usize;
+    let plane = |i: usize, rows: usize| {
+        std::slice::from_raw_parts(frame.data[i], frame.linesize[i] as usize * rows)
+    };
+    let chroma_rows = h.div_ceil(2);
+    Ok(crate::preview::yuv420_to_rgba(
+        [plane(0, h), plane(1, chroma_rows), plane(2, chroma_rows)],
+        [
+            frame.linesize[0] as usize,
+            frame.linesize[1] as usize,
+            frame.linesize[2] as usize,
+        ],
+        frame.width,
+        frame.height,
+        full_range,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video_info(width: i32, height: i32, rates: Option<FrameRateCandidates>) -> VideoInfo {
+        let selected = rates.and_then(|r| select_frame_rate(&r, RatePreference::Guess));
+        VideoInfo {
+            width,
+            height,
+            fps: selected.map_or(0.0, |(fps, _)| fps),
+            fps_source: selected.map(|(_, source)| source),
+            frame_rates: rates,
+            sample_aspect_ratio: 0.0,
+            start_time: 0.0,
+            video_start_time: None,
+            duration: None,
+        }
+    }
+
+    #[test]
+    fn test_video_probe_problems() {
+        let rates = |fps: f64| FrameRateCandidates {
+            avg: fps,
+            r: fps,
+            guessed: fps,
+        };
+        assert_eq!(
+            video_info(1280, 720, Some(rates(59.94))).probe_problems(),
+            []
+        );
+        // No video stream: nothing to distrust.
+        assert_eq!(video_info(0, 0, None).probe_problems(), []);
+        // A corrupt video PID: no size and a bogus rate.
+        let broken = video_info(0, 0, Some(rates(90000.0)));
+        assert_eq!(
+            broken.probe_problems(),
+            [VideoProbeProblem::NoSize, VideoProbeProblem::NoFrameRate]
+        );
+        assert_eq!(
+            broken.describe(),
+            "0x0, avg_frame_rate=90000.000 r_frame_rate=90000.000 guessed=90000.000"
+        );
+        assert_eq!(
+            video_info(1920, 0, Some(rates(29.97))).probe_problems(),
+            [VideoProbeProblem::NoSize]
+        );
+        assert_eq!(
+            video_info(1920, 1080, Some(rates(0.0))).probe_problems(),
+            [VideoProbeProblem::NoFrameRate]
+        );
+    }
+
+    #[test]
+    fn test_option_c_strings_reject_nul() {
+        let ok = option_c_strings([("font", "Rounded M+ 1m"), ("outline_width", "1.5")]).unwrap();
+        assert_eq!(ok[0].0.as_bytes(), b"font");
+        assert_eq!(ok[1].1.as_bytes(), b"1.5");
+
+        let err = option_c_strings([("font", "A\0B")])
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "Invalid value for option font: \"A\\0B\" contains a NUL character."
+        );
+        let err = option_c_strings([("ok", "1"), ("bad\0key", "1")])
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Invalid option name: "), "{}", err);
+        assert!(path_c_string(Path::new("a\0.ts")).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let path = Path::new(std::ffi::OsStr::from_bytes(b"caf\xe9.ts"));
+            assert_eq!(path_c_string(path).unwrap().as_bytes(), b"caf\xe9.ts");
+        }
+    }
+
+    #[test]
+    fn test_decoder_options_line() {
+        let options = BTreeMap::from([
+            ("sub_type", "bitmap".to_string()),
+            ("canvas_size", "1920x1080".to_string()),
+            ("font", "Rounded M+ 1m".to_string()),
+        ]);
+        assert_eq!(
+            decoder_options_line(&options),
+            "Decoder options: canvas_size=1920x1080, font=Rounded M+ 1m, sub_type=bitmap"
+        );
+    }
+
+    #[test]
+    fn test_is_transient_error() {
+        // AVERROR(EIO), AVERROR(EAGAIN), AVERROR(ETIMEDOUT) on Linux.
+        assert!(is_transient_error(-5));
+        assert!(is_transient_error(-11));
+        assert!(is_transient_error(-110));
+        // AVERROR(ENOENT), AVERROR(EACCES), AVERROR(EISDIR).
+        assert!(!is_transient_error(-2));
+        assert!(!is_transient_error(-13));
+        assert!(!is_transient_error(-21));
+        // AVERROR_INVALIDDATA and AVERROR_EOF are FFmpeg tags, not errno values.
+        assert!(!is_transient_error(-0x4144_4E49));
+        assert!(!is_transient_error(-0x2046_4F45));
+        assert!(!is_transient_error(0));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_millis(500));
+        assert_eq!(retry_delay(2), Duration::from_secs(2));
+        assert_eq!(retry_delay(5), OPEN_RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(u32::MAX), OPEN_RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_seconds_to_pts() {
+        assert_eq!(seconds_to_pts(1.0, tb(1, 90_000)), 90_000);
+        assert_eq!(seconds_to_pts(0.1001, tb(1001, 30_000)), 3);
+        assert_eq!(seconds_to_pts(1.0, tb(1, 0)), 0);
+        let t = 12.345;
+        assert_eq!(pts_to_seconds(seconds_to_pts(t, tb(1, 90_000)), tb(1, 90_000)), t);
+    }
+
+    fn tb(num: c_int, den: c_int) -> AVRational {
+        AVRational { num, den }
+    }
+
+    #[test]
+    fn test_pts_to_seconds_common_time_bases() {
+        assert_eq!(pts_to_seconds(90_000, tb(1, 90_000)), 1.0);
+        assert_eq!(pts_to_seconds(5_400_000_000, tb(1, 90_000)), 60_000.0);
+        assert_eq!(pts_to_seconds(1_500, tb(1, 1_000)), 1.5);
+        assert_eq!(pts_to_seconds(-500, tb(1, 1_000)), -0.5);
+    }
+
+    #[test]
+    fn test_pts_to_seconds_pathological_time_bases() {
+        assert_eq!(pts_to_seconds(AV_NOPTS_VALUE, tb(1, 1_000)), 0.0);
+        assert_eq!(pts_to_seconds(100, tb(1, 0)), 0.0);
+        assert_eq!(pts_to_seconds(100, tb(0, 1)), 0.0);
+        assert_eq!(pts_to_seconds(100, tb(1, -1_000)), 0.0);
+        assert_eq!(pts_to_seconds(3, tb(1001, 30_000)), 0.1001);
+        assert!(pts_to_seconds(i64::MAX, tb(c_int::MAX, 1)).is_finite());
+    }
+
+    #[test]
+    fn test_rect_counts_kind() {
+        use AVSubtitleType_SUBTITLE_ASS as ASS;
+        use AVSubtitleType_SUBTITLE_BITMAP as BITMAP;
+        use AVSubtitleType_SUBTITLE_TEXT as TEXT;
+
+        assert_eq!(RectCounts::from_types([]).kind(), FrameKind::Clear);
+        assert_eq!(RectCounts::from_types([BITMAP]).kind(), FrameKind::Bitmap);
+        assert_eq!(RectCounts::from_types([TEXT, BITMAP, ASS]).kind(), FrameKind::Bitmap);
+        assert_eq!(RectCounts::from_types([TEXT]).kind(), FrameKind::NonBitmap);
+        assert_eq!(RectCounts::from_types([ASS, 99]).kind(), FrameKind::NonBitmap);
+        let counts = RectCounts::from_types([BITMAP, BITMAP, TEXT, ASS, 0]);
+        assert_eq!(
+            counts,
+            RectCounts {
+                bitmap: 2,
+                text: 1,
+                ass: 1,
+                other: 1
+            }
+        );
+        assert_eq!(counts.to_string(), "bitmap 2, text 1, ass 1, other 1");
+    }
+
+    #[test]
+    fn test_library_version() {
+        assert_eq!(library_version(61 << 16 | 19 << 8 | 100), "61.19.100");
+    }
+
+    #[test]
+    fn test_rational_to_f64() {
+        assert_eq!(rational_to_f64(tb(30_000, 1_001)), 30_000.0 / 1_001.0);
+        assert_eq!(rational_to_f64(tb(0, 0)), 0.0);
+        assert_eq!(rational_to_f64(tb(1, 0)), 0.0);
+    }
+
+    #[test]
+    fn test_subtitle_base_seconds_fallback_uses_av_time_base() {
+        assert_eq!(subtitle_base_seconds(2_000, 0, tb(1, 1_000)), 2.0);
+        // AVSubtitle.pts is in microseconds regardless of the stream time base.
+        assert_eq!(subtitle_base_seconds(AV_NOPTS_VALUE, 2_000_000, tb(1, 1_000)), 2.0);
+        assert_eq!(subtitle_base_seconds(AV_NOPTS_VALUE, 2_000_000, tb(1, 90_000)), 2.0);
+        assert_eq!(subtitle_base_seconds(AV_NOPTS_VALUE, AV_NOPTS_VALUE, tb(1, 90_000)), 0.0);
+    }
+
+    #[test]
+    fn test_display_window() {
+        assert_eq!(display_window(10.0, 0, 1_500), (10.0, 11.5));
+        assert_eq!(display_window(10.0, INVALID_DISPLAY_TIME, 1_500), (10.0, 10.0));
+        assert_eq!(display_window(10.0, 0, INVALID_DISPLAY_TIME), (10.0, 10.0));
+        // End before start saturates to start.
+        assert_eq!(display_window(10.0, 2_000, 1_000), (12.0, 12.0));
+        // Long holds near the top of the u32 range do not wrap.
+        let (s, e) = display_window(0.0, 0, INVALID_DISPLAY_TIME - 1);
+        assert_eq!(s, 0.0);
+        assert_eq!(e, (u32::MAX - 1) as f64 / 1000.0);
+    }
+
+    #[test]
+    fn test_is_arib_data_stream() {
+        let data = AVMediaType_AVMEDIA_TYPE_DATA;
+        let unknown = AVMediaType_AVMEDIA_TYPE_UNKNOWN;
+        let arib = AVCodecID_AV_CODEC_ID_ARIB_CAPTION;
+        let none = AVCodecID_AV_CODEC_ID_NONE;
+        assert!(is_arib_data_stream(data, arib, 0, None));
+        assert!(is_arib_data_stream(data, none, 0, Some("libaribcaption")));
+        assert!(is_arib_data_stream(unknown, none, 0x0008, None));
+        assert!(!is_arib_data_stream(data, none, 0, None));
+        assert!(!is_arib_data_stream(data, none, 0x0009, Some("bin_data")));
+        // Streams FFmpeg typed are left to the regular search.
+        let subtitle = AVMediaType_AVMEDIA_TYPE_SUBTITLE;
+        let audio = AVMediaType_AVMEDIA_TYPE_AUDIO;
+        assert!(!is_arib_data_stream(subtitle, arib, 0, None));
+        assert!(!is_arib_data_stream(audio, none, 0x0008, None));
+    }
+}
@@ -0,0 +1,127 @@
+//! Companion .mkv probing through an `ffprobe` on PATH, for files our own FFmpeg libraries cannot
+//! open (e.g. muxed by a newer FFmpeg).
+
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::checkpoint::Json;
+use crate::ocr::run_command;
+use crate::options::parse_frame_rate;
+
+/// Time ffprobe may take before it is killed.
+pub const FFPROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One video stream of `ffprobe -show_streams`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbedStream {
+    pub index: u64,
+    pub width: i32,
+    pub height: i32,
+    /// avg_frame_rate, or r_frame_rate when that is unset ("0/0").
+    pub fps: Option<f64>,
+    /// Cover art or another still picture attached to the file.
+    pub attached_pic: bool,
+}
+
+/// The video streams of ffprobe's `-print_format json -show_streams` output.
+pub fn parse_ffprobe_streams(json: &str) -> Result<Vec<ProbedStream>, String> {
+    let root = Json::parse(json)?;
+    // ffprobe leaves "streams" out when the file has none.
+    let Ok(items) = root.get("streams") else {
+        return Ok(Vec::new());
+    };
+    let mut streams = Vec::new();
+    for s in items.as_array()? {
+        if s.get("codec_type").and_then(Json::as_str) != Ok("video") {
+            continue;
+        }
+        let rate = |key: &str| {
+            let rate = s.get(key).and_then(Json::as_str).ok()?;
+            parse_frame_rate(rate).ok()
+        };
+        let attached_pic = s
+            .get("disposition")
+            .and_then(|d| d.get("attached_pic"))
+            .and_then(Json::as_u64);
+        streams.push(ProbedStream {
+            index: s.get("index")?.as_u64()?,
+            width: s.get("width").and_then(Json::as_i32).unwrap_or(0),
+            height: s.get("height").and_then(Json::as_i32).unwrap_or(0),
+            fps: rate("avg_frame_rate").or_else(|| rate("r_frame_rate")),
+            attached_pic: attached_pic == Ok(1),
+        });
+    }
+    Ok(streams)
+}
+
+/// The first video stream with a size that is not an attached picture.
+pub fn select_video_stream(streams: &[ProbedStream]) -> Option<&ProbedStream> {
+    streams
+        .iter()
+        .find(|s| !s.attached_pic && s.width > 0 && s.height > 0)
+}
+
+/// Runs `ffprobe -v quiet -print_format json -show_streams` on `path` and returns its video
+/// stream (`select_video_stream`).
+pub fn ffprobe_video_stream(path: &Path, timeout: Duration) -> Result<ProbedStream, String> {
+    let mut args = [
+        "ffprobe",
+        "-v",
+        "quiet",
+        "-print_format",
+        "json",
+        "-show_streams",
+    ]
+    .map(OsString::from)
+    .to_vec();
+    args.push(path.as_os_str().to_owned());
+    let streams = parse_ffprobe_streams(&run_command(&args, timeout)?)?;
+    select_video_stream(&streams)
+        .cloned()
+        .ok_or_else(|| "no video stream".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANAMORPHIC: &str = include_str!("../tests/ffprobe/mkv_opus_anamorphic.json");
+    const COVER_ART: &str = include_str!("../tests/ffprobe/cover_art_first.json");
+
+    #[test]
+    fn test_parse_ffprobe_streams() {
+        // H.264 with Opus audio and a font attachment: only the video stream is kept.
+        let streams = parse_ffprobe_streams(ANAMORPHIC).unwrap();
+        assert_eq!(streams.len(), 1);
+        let video = select_video_stream(&streams).unwrap();
+        assert_eq!((video.index, video.width, video.height), (0, 1440, 1080));
+        assert_eq!(video.fps, Some(30000.0 / 1001.0));
+
+        // Cover art comes first; the picture stream has no avg_frame_rate.
+        let streams = parse_ffprobe_streams(COVER_ART).unwrap();
+        assert_eq!(streams.len(), 2);
+        assert!(streams[0].attached_pic);
+        let video = select_video_stream(&streams).unwrap();
+        assert_eq!((video.index, video.width, video.height), (1, 1280, 720));
+        assert_eq!(video.fps, Some(60000.0 / 1001.0));
+    }
+
+    #[test]
+    fn test_no_video_stream() {
+        assert_eq!(parse_ffprobe_streams("{\n\n}\n"), Ok(Vec::new()));
+        let audio = r#"{"streams": [{"index": 0, "codec_type": "audio"}]}"#;
+        assert_eq!(parse_ffprobe_streams(audio), Ok(Vec::new()));
+        let sizeless = r#"{"streams": [{"index": 0, "codec_type": "video"}]}"#;
+        let streams = parse_ffprobe_streams(sizeless).unwrap();
+        assert_eq!(select_video_stream(&streams), None);
+        assert!(parse_ffprobe_streams("not json").is_err());
+    }
+
+    #[test]
+    fn test_ffprobe_missing() {
+        // Fails cleanly when the file cannot be probed or ffprobe is not installed.
+        let missing = Path::new("/nonexistent/companion.mkv");
+        assert!(ffprobe_video_stream(missing, Duration::from_secs(5)).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Keeps every caption graphic within the canvas and --max-graphic-size: a composite larger than
+//! that (e.g. rects slightly off the canvas) is cropped to the canvas and, if still too large,
+//! scaled down; with --strict it is an error instead.
+
+use crate::bitmap::{scale_bitmap, BitmapData};
+use crate::config::parse_canvas_size;
+
+/// What to do with a graphic of some size at some position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicFit {
+    /// Within the limits.
+    Keep,
+    /// Keep only the part on the canvas: `(x, y, width, height)` of the bitmap.
+    Crop((i32, i32, i32, i32)),
+    /// The part on the canvas (`crop`), scaled down to `size` keeping its aspect ratio.
+    Scale {
+        crop: (i32, i32, i32, i32),
+        size: (i32, i32),
+    },
+    /// Scaling would be needed (--strict).
+    Reject,
+}
+
+/// Parses --max-graphic-size (`WxH`).
+pub fn parse_max_graphic_size(s: &str) -> Result<(i32, i32), String> {
+    parse_canvas_size(s).map_err(|_| format!("expected WxH, got '{}'", s))
+}
+
+/// Decides how a `size` graphic at `pos` fits a `canvas`, no larger than `limit` when given:
+/// cropping to the canvas first, then scaling down unless `strict`.
+pub fn fit_graphic(
+    pos: (i32, i32),
+    size: (i32, i32),
+    canvas: (i32, i32),
+    limit: Option<(i32, i32)>,
+    strict: bool,
+) -> GraphicFit {
+    let (max_w, max_h) = limit.map_or(canvas, |(w, h)| (w.min(canvas.0), h.min(canvas.1)));
+    if size.0 <= max_w && size.1 <= max_h {
+        return GraphicFit::Keep;
+    }
+    // The part of the bitmap on the canvas.
+    let (left, top) = ((-pos.0).clamp(0, size.0), (-pos.1).clamp(0, size.1));
+    let right = (canvas.0 - pos.0).clamp(left, size.0);
+    let bottom = (canvas.1 - pos.1).clamp(top, size.1);
+    let crop = (left, top, right - left, bottom - top);
+    if crop.2 <= max_w && crop.3 <= max_h {
+        return GraphicFit::Crop(crop);
+    }
+    if strict {
+        return GraphicFit::Reject;
+    }
+    // Whichever side hits its limit first sets the scale; the other is rounded down.
+    let (w, h) = (crop.2 as i64, crop.3 as i64);
+    let size = if w * max_h as i64 >= h * max_w as i64 {
+        (max_w, (h * max_w as i64 / w) as i32)
+    } else {
+        ((w * max_h as i64 / h) as i32, max_h)
+    };
+    GraphicFit::Scale {
+        crop,
+        size: (size.0.max(1), size.1.max(1)),
+    }
+}
+
+/// The `(x, y, width, height)` part of `bitmap`, which must lie inside it.
+fn crop_bitmap(bitmap: &BitmapData, (x, y, width, height): (i32, i32, i32, i32)) -> BitmapData {
+    let stride = bitmap.stride as usize;
+    let (start, len) = (x as usize * 4, width as usize * 4);
+    let mut data = Vec::with_capacity(len * height as usize);
+    for row in y as usize..(y + height) as usize {
+        data.extend_from_slice(&bitmap.data[row * stride + start..row * stride + start + len]);
+    }
+    BitmapData {
+        data,
+        width,
+        height,
+        stride: width * 4,
+    }
+}
+
+/// Applies a `Crop` or `Scale` to `bitmap` at `pos`; returns the new bitmap and position, moved
+/// only as far as the crop clamps it onto the canvas. None for `Keep` and `Reject`.
+pub fn apply_fit(
+    bitmap: &BitmapData,
+    pos: (i32, i32),
+    fit: GraphicFit,
+) -> Option<(BitmapData, (i32, i32))> {
+    let (crop, size) = match fit {
+        GraphicFit::Keep | GraphicFit::Reject => return None,
+        GraphicFit::Crop(crop) => (crop, None),
+        GraphicFit::Scale { crop, size } => (crop, Some(size)),
+    };
+    let mut fitted = crop_bitmap(bitmap, crop);
+    if let Some((width, height)) = size {
+        fitted = scale_bitmap(&fitted, width, height);
+    }
+    Some((fitted, (pos.0 + crop.0, pos.1 + crop.1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANVAS: (i32, i32) = (1920, 1080);
+
+    #[test]
+    fn test_fit_graphic() {
+        // Within the canvas.
+        let keep = fit_graphic((100, 900), (1200, 120), CANVAS, None, false);
+        assert_eq!(keep, GraphicFit::Keep);
+        // 1952 wide, 16 px off each side: the canvas part is kept.
+        let wide = fit_graphic((-16, 900), (1952, 120), CANVAS, None, true);
+        assert_eq!(wide, GraphicFit::Crop((16, 0, 1920, 120)));
+        // Off the right and bottom edges.
+        let corner = fit_graphic((1000, 1000), (2000, 200), CANVAS, None, false);
+        assert_eq!(corner, GraphicFit::Crop((0, 0, 920, 80)));
+    }
+
+    #[test]
+    fn test_fit_graphic_scale_or_reject() {
+        // On the canvas but over the limit: scaled keeping the aspect ratio.
+        let limit = Some((1280, 720));
+        let scale = fit_graphic((0, 800), (1600, 200), CANVAS, limit, false);
+        let expected = GraphicFit::Scale {
+            crop: (0, 0, 1600, 200),
+            size: (1280, 160),
+        };
+        assert_eq!(scale, expected);
+        // Cropping first, then scaling what is left.
+        let both = fit_graphic((-100, 0), (1800, 1200), CANVAS, limit, false);
+        let expected = GraphicFit::Scale {
+            crop: (100, 0, 1700, 1080),
+            size: (1133, 720),
+        };
+        assert_eq!(both, expected);
+        // --strict: no scaling.
+        let strict = fit_graphic((0, 800), (1600, 200), CANVAS, limit, true);
+        assert_eq!(strict, GraphicFit::Reject);
+        // A limit larger than the canvas does not raise it.
+        let big = fit_graphic((-8, 0), (1936, 100), CANVAS, Some((4096, 2160)), false);
+        assert_eq!(big, GraphicFit::Crop((8, 0, 1920, 100)));
+    }
+
+    #[test]
+    fn test_apply_fit() {
+        let bitmap = BitmapData {
+            data: (0..6 * 2 * 4).map(|i| i as u8).collect(),
+            width: 6,
+            height: 2,
+            stride: 24,
+        };
+        let (cropped, pos) = apply_fit(&bitmap, (-2, 5), GraphicFit::Crop((2, 1, 3, 1))).unwrap();
+        assert_eq!(pos, (0, 6));
+        assert_eq!((cropped.width, cropped.height), (3, 1));
+        assert_eq!(cropped.data[..4], [32, 33, 34, 35]);
+        let scale = GraphicFit::Scale {
+            crop: (0, 0, 6, 2),
+            size: (3, 1),
+        };
+        let (scaled, pos) = apply_fit(&bitmap, (10, 20), scale).unwrap();
+        assert_eq!(pos, (10, 20));
+        assert_eq!((scaled.width, scaled.height), (3, 1));
+        assert!(apply_fit(&bitmap, (0, 0), GraphicFit::Keep).is_none());
+        assert_eq!(parse_max_graphic_size("1280x720"), Ok((1280, 720)));
+        assert!(parse_max_graphic_size("1280").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! --font-coverage: the characters of a text decode pass that the primary caption font has no
+//! glyph for. libaribcaption draws those from the next font in its list, so single characters
+//! (usually rare kanji) end up in another style.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::time::Duration;
+
+use crate::ocr::run_command;
+use crate::stl::TextEvent;
+
+/// Time fc-match may take before it is killed.
+pub const FONT_QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// In-TCs listed per missing character; the rest are counted.
+pub const MAX_LISTED_TCS: usize = 5;
+
+/// Code points a font has glyphs for, as sorted inclusive ranges.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Charset(Vec<(u32, u32)>);
+
+impl Charset {
+    pub fn contains(&self, c: char) -> bool {
+        let c = c as u32;
+        let i = self.0.partition_point(|&(_, end)| end < c);
+        self.0.get(i).is_some_and(|&(start, _)| start <= c)
+    }
+}
+
+/// Parses a fontconfig charset as `fc-match --format=%{charset}` prints it: space-separated
+/// hex code points and `first-last` ranges.
+pub fn parse_fc_charset(s: &str) -> Result<Charset, String> {
+    let hex = |v: &str| u32::from_str_radix(v, 16).map_err(|_| format!("invalid charset: {}", v));
+    let mut ranges = s
+        .split_whitespace()
+        .map(|item| match item.split_once('-') {
+            Some((start, end)) => Ok((hex(start)?, hex(end)?)),
+            None => hex(item).map(|c| (c, c)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    ranges.sort_unstable();
+    Ok(Charset(ranges))
+}
+
+/// The font a family name resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontMatch {
+    /// Family of the font found, which is another one when the requested font is not installed.
+    pub family: String,
+    pub charset: Charset,
+}
+
+/// Looks fonts up the way libaribcaption's font provider does.
+pub trait FontQuery {
+    fn query(&self, family: &str) -> Result<FontMatch, String>;
+}
+
+/// fontconfig, through `fc-match` on PATH.
+pub struct FcMatch;
+
+impl FontQuery for FcMatch {
+    fn query(&self, family: &str) -> Result<FontMatch, String> {
+        let args = ["fc-match", "--format=%{family}\\n%{charset}", family].map(OsString::from);
+        let out = run_command(&args, FONT_QUERY_TIMEOUT)?;
+        let (families, charset) = out.split_once('\n').unwrap_or((&out, ""));
+        Ok(FontMatch {
+            // Fonts with localized names list them all, comma-separated.
+            family: families.split(',').next().unwrap_or_default().to_string(),
+            charset: parse_fc_charset(charset)?,
+        })
+    }
+}
+
+/// The first family of libaribcaption's comma-separated `font` option.
+pub fn primary_font(font_list: &str) -> Option<&str> {
+    font_list.split(',').map(str::trim).find(|f| !f.is_empty())
+}
+
+/// A character the primary font lacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingChar {
+    pub ch: char,
+    /// In-TCs of the events containing it, in order.
+    pub in_tcs: Vec<String>,
+}
+
+/// Result of `font_coverage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageReport {
+    pub font: String,
+    /// The family fontconfig matched instead, when `font` is not installed.
+    pub substitute: Option<String>,
+    /// Distinct characters of the program.
+    pub characters: usize,
+    pub missing: Vec<MissingChar>,
+}
+
+/// The distinct printable characters of `events`, each with the in-TCs of the events using it.
+pub fn collect_characters(events: &[TextEvent]) -> BTreeMap<char, Vec<String>> {
+    let mut chars: BTreeMap<char, Vec<String>> = BTreeMap::new();
+    for event in events {
+        for c in event
+            .text
+            .chars()
+            .filter(|c| !c.is_whitespace() && !c.is_control())
+        {
+            let tcs = chars.entry(c).or_default();
+            if tcs.last() != Some(&event.in_tc) {
+                tcs.push(event.in_tc.clone());
+            }
+        }
+    }
+    chars
+}
+
+/// Checks the characters of `events` against `font` as `query` resolves it.
+pub fn font_coverage(
+    events: &[TextEvent],
+    font: &str,
+    query: &dyn FontQuery,
+) -> Result<CoverageReport, String> {
+    let matched = query.query(font)?;
+    let chars = collect_characters(events);
+    Ok(CoverageReport {
+        font: font.to_string(),
+        substitute: (!matched.family.eq_ignore_ascii_case(font)).then_some(matched.family),
+        characters: chars.len(),
+        missing: chars
+            .into_iter()
+            .filter(|(c, _)| !matched.charset.contains(*c))
+            .map(|(ch, in_tcs)| MissingChar { ch, in_tcs })
+            .collect(),
+    })
+}
+
+impl CoverageReport {
+    /// One line per missing character: the character, its code point and up to
+    /// `MAX_LISTED_TCS` in-TCs.
+    pub fn missing_lines(&self) -> Vec<String> {
+        self.missing
+            .iter()
+            .map(|m| {
+                let listed = m.in_tcs.len().min(MAX_LISTED_TCS);
+                let more = match m.in_tcs.len() - listed {
+                    0 => String::new(),
+                    n => format!(" (+{} more)", n),
+                };
+                format!(
+                    "{} U+{:04X}: {}{}",
+                    m.ch,
+                    m.ch as u32,
+                    m.in_tcs[..listed].join(", "),
+                    more
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Covers ASCII and the hiragana block, under whatever name it is asked for.
+    struct Kana;
+
+    impl FontQuery for Kana {
+        fn query(&self, family: &str) -> Result<FontMatch, String> {
+            Ok(FontMatch {
+                family: family.to_string(),
+                charset: parse_fc_charset("20-7e 3041-3096")?,
+            })
+        }
+    }
+
+    fn event(in_tc: &str, text: &str) -> TextEvent {
+        TextEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: in_tc.to_string(),
+            text: text.to_string(),
+            y: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_fc_charset() {
+        let charset = parse_fc_charset("20-7e a0-2e9 3000 3041-3096\n").unwrap();
+        assert!(charset.contains('A'));
+        assert!(charset.contains('\u{3000}'));
+        assert!(charset.contains('あ'));
+        assert!(!charset.contains('\u{9f}'));
+        assert!(!charset.contains('漢'));
+        assert_eq!(parse_fc_charset(""), Ok(Charset::default()));
+        assert!(parse_fc_charset("20-zz").is_err());
+    }
+
+    #[test]
+    fn test_font_coverage() {
+        let events = [
+            event("00:00:01:00", "あの 髙橋さん"),
+            event("00:00:03:00", "髙い"),
+            event("00:00:05:00", "ok"),
+        ];
+        let chars = collect_characters(&events);
+        assert_eq!(chars.len(), 9);
+        assert_eq!(chars[&'い'], ["00:00:03:00"]);
+        let report = font_coverage(&events, "Rounded M+ 1m for ARIB", &Kana).unwrap();
+        assert_eq!(report.substitute, None);
+        let missing: Vec<char> = report.missing.iter().map(|m| m.ch).collect();
+        assert_eq!(missing, ['橋', '髙']);
+        assert_eq!(
+            report.missing_lines()[1],
+            "髙 U+9AD9: 00:00:01:00, 00:00:03:00"
+        );
+    }
+
+    #[test]
+    fn test_missing_lines_cap() {
+        let events: Vec<TextEvent> = (0..7)
+            .map(|i| event(&format!("00:00:0{}:00", i), "髙"))
+            .collect();
+        let report = font_coverage(&events, "A", &Kana).unwrap();
+        let line = &report.missing_lines()[0];
+        assert!(line.ends_with("00:00:04:00 (+2 more)"), "{}", line);
+        assert_eq!(
+            primary_font(" Hiragino Maru Gothic ProN, Rounded M+ 1m for ARIB"),
+            Some("Hiragino Maru Gothic ProN")
+        );
+        assert_eq!(primary_font(""), None);
+    }
+}
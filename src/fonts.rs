@@ -0,0 +1,388 @@
+//! Fonts attached to the input (MKV/MKS attachments): extracted to the workspace and put first
+//! in libaribcaption's font list, so captions render with the font the file was made with.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use crate::ffmpeg::Attachment;
+
+/// Attachment MIME types used for fonts besides `font/*`.
+const FONT_MIMETYPES: [&str; 5] = [
+    "application/x-truetype-font",
+    "application/x-font-ttf",
+    "application/x-font-otf",
+    "application/vnd.ms-opentype",
+    "application/font-sfnt",
+];
+const FONT_EXTENSIONS: [&str; 4] = ["ttf", "otf", "ttc", "otc"];
+
+/// Name IDs of the family name: typographic (16) is preferred over the legacy one (1).
+const NAME_ID_FAMILY: u16 = 1;
+const NAME_ID_TYPOGRAPHIC_FAMILY: u16 = 16;
+
+/// True if an attachment with this MIME type and file name is a font.
+pub fn is_font_attachment(mimetype: &str, filename: &str) -> bool {
+    let mimetype = mimetype.to_ascii_lowercase();
+    mimetype.starts_with("font/")
+        || FONT_MIMETYPES.contains(&mimetype.as_str())
+        || Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| FONT_EXTENSIONS.iter().any(|f| e.eq_ignore_ascii_case(f)))
+}
+
+fn read_u16(b: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*b.get(pos)?, *b.get(pos + 1)?]))
+}
+
+fn read_u32(b: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(b.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+/// Family names of the font at `offset` (an sfnt table directory), English names first.
+fn sfnt_family_names(data: &[u8], offset: usize) -> Option<Vec<String>> {
+    let num_tables = read_u16(data, offset + 4)? as usize;
+    let name = (0..num_tables)
+        .map(|i| offset + 12 + i * 16)
+        .find(|&record| data.get(record..record + 4) == Some(b"name"))
+        .and_then(|record| read_u32(data, record + 8))? as usize;
+    let count = read_u16(data, name + 2)? as usize;
+    let strings = name + read_u16(data, name + 4)? as usize;
+
+    // (name id, English?, name)
+    let mut found: Vec<(u16, bool, String)> = Vec::new();
+    for i in 0..count {
+        let record = name + 6 + i * 12;
+        let (platform, encoding, language, name_id) = (
+            read_u16(data, record)?,
+            read_u16(data, record + 2)?,
+            read_u16(data, record + 4)?,
+            read_u16(data, record + 6)?,
+        );
+        if name_id != NAME_ID_FAMILY && name_id != NAME_ID_TYPOGRAPHIC_FAMILY {
+            continue;
+        }
+        let start = strings + read_u16(data, record + 10)? as usize;
+        let Some(bytes) = data.get(start..start + read_u16(data, record + 8)? as usize) else {
+            continue;
+        };
+        let (text, english) = match (platform, encoding) {
+            // Unicode and Windows: UTF-16BE.
+            (0, _) | (3, 1) | (3, 10) => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                (
+                    String::from_utf16_lossy(&units),
+                    platform == 0 || language == 0x0409,
+                )
+            }
+            // Macintosh Roman; only its ASCII range is taken.
+            (1, 0) if bytes.is_ascii() => {
+                (String::from_utf8_lossy(bytes).into_owned(), language == 0)
+            }
+            _ => continue,
+        };
+        if !text.trim().is_empty() {
+            found.push((name_id, english, text.trim().to_string()));
+        }
+    }
+    let preferred = if found.iter().any(|f| f.0 == NAME_ID_TYPOGRAPHIC_FAMILY) {
+        NAME_ID_TYPOGRAPHIC_FAMILY
+    } else {
+        NAME_ID_FAMILY
+    };
+    found.retain(|f| f.0 == preferred);
+    found.sort_by_key(|f| !f.1);
+    Some(found.into_iter().map(|f| f.2).collect())
+}
+
+/// Family names in a TrueType/OpenType font or collection (deduplicated, English first).
+/// Data that is not a font gives no names.
+pub fn font_family_names(data: &[u8]) -> Vec<String> {
+    let offsets: Vec<usize> = if data.starts_with(b"ttcf") {
+        let count = read_u32(data, 8).unwrap_or(0) as usize;
+        (0..count)
+            .map_while(|i| read_u32(data, 12 + i * 4).map(|o| o as usize))
+            .collect()
+    } else {
+        vec![0]
+    };
+    let mut names: Vec<String> = Vec::new();
+    for offset in offsets {
+        for name in sfnt_family_names(data, offset).unwrap_or_default() {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// A font attachment written to disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedFont {
+    /// File name in the attachment.
+    pub filename: String,
+    pub path: PathBuf,
+    /// Empty when no family name could be read; such fonts are not used.
+    pub families: Vec<String>,
+}
+
+/// Writes the font attachments into `dir` (numbered, so equal names cannot collide) and reads
+/// their family names.
+pub fn extract_fonts(attachments: &[Attachment], dir: &Path) -> anyhow::Result<Vec<ExtractedFont>> {
+    let mut fonts = Vec::new();
+    for (i, attachment) in attachments.iter().enumerate() {
+        if !is_font_attachment(&attachment.mimetype, &attachment.filename) {
+            continue;
+        }
+        if fonts.is_empty() {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                anyhow::anyhow!("Failed to create directory: {}: {}", dir.display(), e)
+            })?;
+        }
+        // Only the last component of the stored name, never a path.
+        let name = Path::new(&attachment.filename)
+            .file_name()
+            .map_or_else(|| "font".to_string(), |n| n.to_string_lossy().into_owned());
+        let path = dir.join(format!("{:02}-{}", i, name));
+        std::fs::write(&path, &attachment.data)
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))?;
+        fonts.push(ExtractedFont {
+            filename: attachment.filename.clone(),
+            path,
+            families: font_family_names(&attachment.data),
+        });
+    }
+    Ok(fonts)
+}
+
+/// `families` put in front of a libaribcaption font list (comma-separated), without repeats.
+pub fn prepend_fonts(font_list: &str, families: &[String]) -> String {
+    let mut names: Vec<&str> = families.iter().map(String::as_str).collect();
+    for name in font_list
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+    {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names.join(", ")
+}
+
+/// The directory fontconfig loads attachment fonts from, once `AttachmentFonts::configure` ran.
+static FONTS_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// A directory of this process that fontconfig loads on top of the system configuration, for the
+/// fonts attached to the inputs. Removed when dropped.
+pub struct AttachmentFonts {
+    root: PathBuf,
+}
+
+impl AttachmentFonts {
+    /// Creates the directory and points FONTCONFIG_FILE at a configuration that adds it.
+    /// libaribcaption loads its own fontconfig configuration from that variable, so no other
+    /// setting reaches it; and setting a variable races with any thread reading the environment,
+    /// so the binary calls this in `main` before starting threads. The library never does: there,
+    /// attached fonts are not used.
+    pub fn configure() -> anyhow::Result<AttachmentFonts> {
+        let root = std::env::temp_dir().join(format!("arib2bdnxml-fonts-{}", std::process::id()));
+        let fonts = root.join("fonts");
+        std::fs::create_dir_all(&fonts).map_err(|e| {
+            anyhow::anyhow!("Failed to create directory: {}: {}", fonts.display(), e)
+        })?;
+        let base = std::env::var_os("FONTCONFIG_FILE").map_or_else(
+            || "/etc/fonts/fonts.conf".to_string(),
+            |f| f.to_string_lossy().into_owned(),
+        );
+        let config = root.join("fonts.conf");
+        std::fs::write(&config, fontconfig_config(&base, &fonts))
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", config.display(), e))?;
+        std::env::set_var("FONTCONFIG_FILE", &config);
+        let _ = FONTS_DIR.set(fonts);
+        Ok(AttachmentFonts { root })
+    }
+}
+
+impl Drop for AttachmentFonts {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// A new directory for one input's fonts where fontconfig finds them, after removing those of
+/// earlier inputs; None when `AttachmentFonts::configure` did not run. A fresh name each time
+/// keeps fontconfig from reusing its cache of an earlier input's directory.
+pub fn attachment_font_dir() -> Option<PathBuf> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let fonts = FONTS_DIR.get()?;
+    for entry in std::fs::read_dir(fonts).into_iter().flatten().flatten() {
+        let _ = std::fs::remove_dir_all(entry.path());
+    }
+    Some(fonts.join(NEXT.fetch_add(1, Ordering::Relaxed).to_string()))
+}
+
+/// A fontconfig configuration that loads `base` (the system configuration) and adds `font_dir`.
+pub fn fontconfig_config(base: &str, font_dir: &Path) -> String {
+    let escape = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    format!(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE fontconfig SYSTEM \"fonts.dtd\">\n<fontconfig>\n  <include ignore_missing=\"yes\">{}</include>\n  <dir>{}</dir>\n</fontconfig>\n",
+        escape(base),
+        escape(&font_dir.to_string_lossy())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal sfnt with only a `name` table holding `names` as (platform, encoding,
+    /// language, name id, text) records.
+    fn sfnt(names: &[(u16, u16, u16, u16, &str)]) -> Vec<u8> {
+        let mut strings = Vec::new();
+        let mut records = Vec::new();
+        for &(platform, encoding, language, name_id, text) in names {
+            let bytes: Vec<u8> = if platform == 1 {
+                text.as_bytes().to_vec()
+            } else {
+                text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+            };
+            for v in [platform, encoding, language, name_id, bytes.len() as u16] {
+                records.extend_from_slice(&v.to_be_bytes());
+            }
+            records.extend_from_slice(&(strings.len() as u16).to_be_bytes());
+            strings.extend_from_slice(&bytes);
+        }
+        let mut name = Vec::new();
+        for v in [0, names.len() as u16, 6 + records.len() as u16] {
+            name.extend_from_slice(&v.to_be_bytes());
+        }
+        name.extend(records);
+        name.extend(strings);
+
+        let mut font = vec![0, 1, 0, 0, 0, 1, 0, 16, 0, 0, 0, 0];
+        font.extend_from_slice(b"name");
+        font.extend_from_slice(&0u32.to_be_bytes());
+        font.extend_from_slice(&28u32.to_be_bytes());
+        font.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        font.extend(name);
+        font
+    }
+
+    #[test]
+    fn test_font_family_names() {
+        let font = sfnt(&[
+            (3, 1, 0x0411, 1, "ＭＳ ゴシック"),
+            (3, 1, 0x0409, 2, "Regular"),
+            (3, 1, 0x0409, 1, "MS Gothic"),
+            (1, 0, 0, 1, "MS Gothic"),
+        ]);
+        assert_eq!(font_family_names(&font), ["MS Gothic", "ＭＳ ゴシック"]);
+
+        // The typographic family wins over the legacy (style-specific) one.
+        let font = sfnt(&[
+            (3, 1, 0x0409, 1, "Rounded M+ 1m for ARIB Medium"),
+            (3, 1, 0x0409, 16, "Rounded M+ 1m for ARIB"),
+        ]);
+        assert_eq!(font_family_names(&font), ["Rounded M+ 1m for ARIB"]);
+
+        assert!(font_family_names(b"not a font").is_empty());
+        assert!(font_family_names(&font[..40]).is_empty());
+    }
+
+    #[test]
+    fn test_font_collection() {
+        let (a, b) = (sfnt(&[(3, 1, 0x0409, 1, "A")]), sfnt(&[(0, 3, 0, 1, "B")]));
+        let mut ttc = b"ttcf\x00\x01\x00\x00".to_vec();
+        ttc.extend_from_slice(&2u32.to_be_bytes());
+        ttc.extend_from_slice(&20u32.to_be_bytes());
+        ttc.extend_from_slice(&(20 + a.len() as u32).to_be_bytes());
+        // Table offsets in a collection are from the start of the file.
+        let shift = |font: &[u8], by: u32| {
+            let mut font = font.to_vec();
+            font[20..24].copy_from_slice(&(28 + by).to_be_bytes());
+            font
+        };
+        ttc.extend(shift(&a, 20));
+        ttc.extend(shift(&b, 20 + a.len() as u32));
+        assert_eq!(font_family_names(&ttc), ["A", "B"]);
+    }
+
+    #[test]
+    fn test_is_font_attachment() {
+        assert!(is_font_attachment("font/ttf", "x.bin"));
+        assert!(is_font_attachment("application/x-truetype-font", "x"));
+        assert!(is_font_attachment("application/octet-stream", "Maru.OTF"));
+        assert!(!is_font_attachment("image/jpeg", "cover.jpg"));
+    }
+
+    #[test]
+    fn test_extract_fonts() {
+        let dir = crate::output::tests::scratch_dir("fonts");
+        let font = sfnt(&[(3, 1, 0x0409, 1, "Maru")]);
+        let attachments = [
+            Attachment {
+                filename: "cover.jpg".to_string(),
+                mimetype: "image/jpeg".to_string(),
+                data: vec![0xFF, 0xD8],
+            },
+            Attachment {
+                filename: "../maru.ttf".to_string(),
+                mimetype: "font/ttf".to_string(),
+                data: font.clone(),
+            },
+            Attachment {
+                filename: "broken.otf".to_string(),
+                mimetype: "font/otf".to_string(),
+                data: vec![1, 2, 3],
+            },
+        ];
+        let fonts = extract_fonts(&attachments, &dir.join("fonts")).unwrap();
+        assert_eq!(
+            fonts,
+            [
+                ExtractedFont {
+                    filename: "../maru.ttf".to_string(),
+                    path: dir.join("fonts").join("01-maru.ttf"),
+                    families: vec!["Maru".to_string()],
+                },
+                ExtractedFont {
+                    filename: "broken.otf".to_string(),
+                    path: dir.join("fonts").join("02-broken.otf"),
+                    families: Vec::new(),
+                },
+            ]
+        );
+        assert_eq!(std::fs::read(&fonts[0].path).unwrap(), font);
+        assert!(extract_fonts(&attachments[..1], &dir.join("none"))
+            .unwrap()
+            .is_empty());
+        assert!(!dir.join("none").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prepend_fonts_and_config() {
+        let families = ["Maru".to_string(), "Rounded M+ 1m for ARIB".to_string()];
+        assert_eq!(
+            prepend_fonts(
+                "Hiragino Maru Gothic ProN, Rounded M+ 1m for ARIB",
+                &families
+            ),
+            "Maru, Rounded M+ 1m for ARIB, Hiragino Maru Gothic ProN"
+        );
+        assert_eq!(prepend_fonts("", &families[..1]), "Maru");
+        let config = fontconfig_config("/etc/fonts/fonts.conf", Path::new("/tmp/a&b"));
+        assert!(config.contains("<include ignore_missing=\"yes\">/etc/fonts/fonts.conf</include>"));
+        assert!(config.contains("<dir>/tmp/a&amp;b</dir>"));
+    }
+}
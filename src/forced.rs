@@ -0,0 +1,253 @@
+//! --forced-heuristic: keeps the events that look like signs or song lyrics (placed at the top or
+//! side of the screen, short, or in another color than the dialogue) for a forced-only track.
+
+use std::collections::HashMap;
+
+use crate::position::{HPos, Position, VPos};
+use crate::style::Rgb;
+
+/// Score of a caption at the top, or at the left/right edge (vertical lyrics), of the screen.
+const PLACEMENT_SCORE: u32 = 2;
+/// Score of a short caption, and of one in another color than the dialogue.
+const HINT_SCORE: u32 = 1;
+
+/// Thresholds of --forced-heuristic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForcedHeuristic {
+    /// Captions shown at most this long (seconds) are short; 0 disables the signal.
+    pub short: f64,
+    /// RGB distance from the dialogue color above which a caption is differently colored; 0
+    /// disables the signal.
+    pub color_distance: f64,
+    /// Score an event needs to be kept.
+    pub min_score: u32,
+}
+
+impl Default for ForcedHeuristic {
+    fn default() -> Self {
+        Self {
+            short: 1.5,
+            color_distance: 64.0,
+            min_score: 2,
+        }
+    }
+}
+
+/// Parses --forced-heuristic sub-options: comma-separated `short=SECS`, `color=DIST` and
+/// `min=N`; empty for the defaults.
+pub fn parse_forced_heuristic(s: &str) -> Result<ForcedHeuristic, String> {
+    let mut h = ForcedHeuristic::default();
+    for option in s.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got '{}'", option))?;
+        let number = || {
+            value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .ok_or_else(|| format!("{}: expected a non-negative number, got '{}'", key, value))
+        };
+        match key.trim() {
+            "short" => h.short = number()?,
+            "color" => h.color_distance = number()?,
+            "min" => {
+                h.min_score = value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|&n| n >= 1)
+                    .ok_or_else(|| format!("min: expected a score of 1 or more, got '{}'", value))?
+            }
+            other => return Err(format!("unknown option '{}' (short, color, min)", other)),
+        }
+    }
+    Ok(h)
+}
+
+/// What the classifier knows about one event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventTraits {
+    pub position: Position,
+    /// Display time in seconds.
+    pub duration: f64,
+    /// Dominant color of the bitmap.
+    pub color: Option<Rgb>,
+}
+
+/// The signals that matched one event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ForcedSignals {
+    pub top: bool,
+    pub side: bool,
+    pub short: bool,
+    pub colored: bool,
+}
+
+impl ForcedSignals {
+    pub fn score(&self) -> u32 {
+        let placement = if self.top || self.side {
+            PLACEMENT_SCORE
+        } else {
+            0
+        };
+        placement + HINT_SCORE * (self.short as u32 + self.colored as u32)
+    }
+}
+
+/// The color most events have, taken as the dialogue color.
+fn dialogue_color(events: &[EventTraits]) -> Option<Rgb> {
+    let mut counts: HashMap<Rgb, usize> = HashMap::new();
+    for color in events.iter().filter_map(|e| e.color) {
+        *counts.entry(color).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(color, n)| (n, color))
+        .map(|(color, _)| color)
+}
+
+fn distance(a: Rgb, b: Rgb) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(&x, y)| (x as f64 - y as f64).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Signals of every event. Top: the bitmap's center is in the top class. Side: it is in the
+/// left or right class and not at the bottom. The dialogue color is the most common one of the
+/// whole run, so `events` must be all of them.
+pub fn forced_signals(events: &[EventTraits], h: &ForcedHeuristic) -> Vec<ForcedSignals> {
+    let dialogue = dialogue_color(events);
+    events
+        .iter()
+        .map(|e| ForcedSignals {
+            top: e.position.v == VPos::Top,
+            side: e.position.h != HPos::Center && e.position.v != VPos::Bottom,
+            short: h.short > 0.0 && e.duration <= h.short,
+            colored: match (e.color, dialogue) {
+                (Some(c), Some(d)) => h.color_distance > 0.0 && distance(c, d) > h.color_distance,
+                _ => false,
+            },
+        })
+        .collect()
+}
+
+/// Counts of kept events and of each signal among them, for the summary.
+pub fn forced_summary(signals: &[ForcedSignals], h: &ForcedHeuristic) -> String {
+    let kept: Vec<&ForcedSignals> = signals
+        .iter()
+        .filter(|s| s.score() >= h.min_score)
+        .collect();
+    let count = |f: fn(&ForcedSignals) -> bool| kept.iter().filter(|s| f(s)).count();
+    format!(
+        "Forced heuristic: kept {} of {} event(s) (top {}, side {}, short {}, colored {})",
+        kept.len(),
+        signals.len(),
+        count(|s| s.top),
+        count(|s| s.side),
+        count(|s| s.short),
+        count(|s| s.colored)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{classify, PositionThresholds};
+
+    const WHITE: Rgb = [255, 255, 255];
+    const YELLOW: Rgb = [255, 255, 0];
+
+    /// A 1920x1080 event at (x, y) of the given size.
+    fn traits(x: i32, y: i32, w: i32, h: i32, duration: f64, color: Rgb) -> EventTraits {
+        EventTraits {
+            position: classify(x, y, w, h, 1920, 1080, &PositionThresholds::default()),
+            duration,
+            color: Some(color),
+        }
+    }
+
+    #[test]
+    fn test_parse_forced_heuristic() {
+        assert_eq!(parse_forced_heuristic(""), Ok(ForcedHeuristic::default()));
+        assert_eq!(
+            parse_forced_heuristic("short=2.5, color=0,min=1"),
+            Ok(ForcedHeuristic {
+                short: 2.5,
+                color_distance: 0.0,
+                min_score: 1,
+            })
+        );
+        assert!(parse_forced_heuristic("short").is_err());
+        assert!(parse_forced_heuristic("short=-1").is_err());
+        assert!(parse_forced_heuristic("min=0").is_err());
+        assert!(parse_forced_heuristic("size=3").is_err());
+    }
+
+    #[test]
+    fn test_labeled_examples() {
+        // (event, should be kept)
+        let labeled = [
+            // Dialogue: bottom center, white, several seconds.
+            (traits(360, 900, 1200, 120, 4.0, WHITE), false),
+            (traits(400, 900, 1100, 120, 3.0, WHITE), false),
+            (traits(300, 840, 1300, 180, 5.5, WHITE), false),
+            // A quick bottom line of dialogue is only short.
+            (traits(700, 900, 500, 120, 1.0, WHITE), false),
+            // Sign at the top.
+            (traits(600, 60, 700, 100, 3.0, WHITE), true),
+            // Vertical lyrics along the right edge.
+            (traits(1750, 150, 120, 700, 6.0, WHITE), true),
+            // Song lyrics at the bottom in another color, shown briefly.
+            (traits(500, 900, 900, 120, 1.2, YELLOW), true),
+            // Colored but long bottom line: not enough on its own.
+            (traits(500, 900, 900, 120, 4.0, YELLOW), false),
+        ];
+        let events: Vec<EventTraits> = labeled.iter().map(|(e, _)| *e).collect();
+        let h = ForcedHeuristic::default();
+        let kept: Vec<bool> = forced_signals(&events, &h)
+            .iter()
+            .map(|s| s.score() >= h.min_score)
+            .collect();
+        let expected: Vec<bool> = labeled.iter().map(|(_, keep)| *keep).collect();
+        assert_eq!(kept, expected);
+
+        let signals = forced_signals(&events, &h);
+        assert_eq!(
+            forced_summary(&signals, &h),
+            "Forced heuristic: kept 3 of 8 event(s) (top 1, side 1, short 1, colored 1)"
+        );
+        // Stricter: placement plus a hint.
+        let strict = ForcedHeuristic { min_score: 3, ..h };
+        assert!(forced_signals(&events, &strict)
+            .iter()
+            .all(|s| s.score() < strict.min_score));
+    }
+
+    #[test]
+    fn test_disabled_signals() {
+        let events = [
+            traits(360, 900, 1200, 120, 1.0, WHITE),
+            traits(360, 900, 1200, 120, 4.0, WHITE),
+            traits(360, 900, 1200, 120, 4.0, YELLOW),
+        ];
+        let off = ForcedHeuristic {
+            short: 0.0,
+            color_distance: 0.0,
+            min_score: 1,
+        };
+        assert!(forced_signals(&events, &off)
+            .iter()
+            .all(|s| *s == ForcedSignals::default()));
+        let on = ForcedHeuristic {
+            min_score: 1,
+            ..ForcedHeuristic::default()
+        };
+        let signals = forced_signals(&events, &on);
+        assert!(signals[0].short && !signals[0].colored);
+        assert!(signals[2].colored && !signals[2].short);
+    }
+}
@@ -0,0 +1,202 @@
+//! --jobs: converts several inputs of a batch at once. Workers take the inputs in order; the
+//! decoder threads (--threads) are a budget all workers share, and each log line of a worker
+//! starts with the base name of the input it converts.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+/// What the worker thread converting one input needs to know beyond the options.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobContext {
+    /// Written before each log line, e.g. `[ep01] `.
+    pub prefix: String,
+    /// Decoder threads granted from the budget; None leaves --threads as it is.
+    pub threads: Option<u32>,
+}
+
+thread_local! {
+    static JOB: RefCell<JobContext> = RefCell::new(JobContext::default());
+}
+
+/// Sets the job of the current thread; `JobContext::default()` clears it.
+pub fn set_job(job: JobContext) {
+    JOB.with(|j| *j.borrow_mut() = job);
+}
+
+/// Prefix of the log lines of the current thread; empty outside a parallel batch.
+pub fn log_prefix() -> String {
+    JOB.with(|j| j.borrow().prefix.clone())
+}
+
+/// Decoder threads granted to the current thread's job, if it is one.
+pub fn job_threads() -> Option<u32> {
+    JOB.with(|j| j.borrow().threads)
+}
+
+/// Whether the current thread converts one input of a parallel batch.
+pub fn in_parallel_job() -> bool {
+    JOB.with(|j| !j.borrow().prefix.is_empty())
+}
+
+/// Decoder threads shared by every worker of a batch.
+#[derive(Debug)]
+pub struct ThreadBudget {
+    free: Mutex<u32>,
+    released: Condvar,
+}
+
+/// Threads taken from a `ThreadBudget`; they go back when this is dropped.
+#[derive(Debug)]
+pub struct ThreadLease<'a> {
+    budget: &'a ThreadBudget,
+    pub threads: u32,
+}
+
+impl ThreadBudget {
+    pub fn new(total: u32) -> Self {
+        ThreadBudget {
+            free: Mutex::new(total.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Takes up to `want` threads, at least one; waits while none is free.
+    pub fn acquire(&self, want: u32) -> ThreadLease<'_> {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        while *free == 0 {
+            free = self.released.wait(free).unwrap_or_else(|e| e.into_inner());
+        }
+        let threads = want.clamp(1, *free);
+        *free -= threads;
+        ThreadLease {
+            budget: self,
+            threads,
+        }
+    }
+
+    #[cfg(test)]
+    fn free(&self) -> u32 {
+        *self.free.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for ThreadLease<'_> {
+    fn drop(&mut self) {
+        *self.budget.free.lock().unwrap_or_else(|e| e.into_inner()) += self.threads;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Decoder threads each of `jobs` workers asks for out of `total`: an even share, at least one.
+pub fn thread_share(total: u32, jobs: usize) -> u32 {
+    (total / jobs.max(1) as u32).max(1)
+}
+
+/// Runs `work` on every item with at most `jobs` running at once, starting them in order.
+/// Returns the results in item order.
+pub fn run_parallel<T, R, F>(items: &[T], jobs: usize, work: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = work(item);
+                results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .map(|r| r.expect("every item is run"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_thread_share() {
+        assert_eq!(thread_share(8, 4), 2);
+        assert_eq!(thread_share(8, 3), 2);
+        assert_eq!(thread_share(2, 4), 1);
+        assert_eq!(thread_share(16, 0), 16);
+    }
+
+    #[test]
+    fn test_thread_budget() {
+        let budget = ThreadBudget::new(5);
+        let a = budget.acquire(2);
+        let b = budget.acquire(2);
+        // Only one left: granted less than asked.
+        let c = budget.acquire(2);
+        assert_eq!((a.threads, b.threads, c.threads), (2, 2, 1));
+        assert_eq!(budget.free(), 0);
+        drop(b);
+        assert_eq!(budget.free(), 2);
+        drop((a, c));
+        assert_eq!(budget.free(), 5);
+    }
+
+    #[test]
+    fn test_run_parallel() {
+        // Stubbed per-file work: each takes two threads of a budget of four for a moment, so at
+        // most two run at once even with four workers.
+        let budget = ThreadBudget::new(4);
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let inputs: Vec<u32> = (0..12).collect();
+        let results = run_parallel(&inputs, 4, |&n| {
+            let lease = budget.acquire(thread_share(4, 2));
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(5));
+            running.fetch_sub(1, Ordering::SeqCst);
+            drop(lease);
+            if n % 5 == 4 {
+                Err(n)
+            } else {
+                Ok(n * 10)
+            }
+        });
+        assert_eq!(results.len(), 12);
+        assert_eq!(results[3], Ok(30));
+        assert_eq!(results[4], Err(4));
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 2);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert_eq!(budget.free(), 4);
+        assert!(run_parallel(&[] as &[u32], 4, |&n| n).is_empty());
+    }
+
+    #[test]
+    fn test_job_context() {
+        assert_eq!(log_prefix(), "");
+        assert!(!in_parallel_job());
+        let handle = std::thread::spawn(|| {
+            set_job(JobContext {
+                prefix: "[ep01] ".to_string(),
+                threads: Some(2),
+            });
+            (log_prefix(), job_threads(), in_parallel_job())
+        });
+        assert_eq!(
+            handle.join().unwrap(),
+            ("[ep01] ".to_string(), Some(2), true)
+        );
+        // The context is per thread.
+        assert_eq!(job_threads(), None);
+    }
+}
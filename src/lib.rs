@@ -0,0 +1,87 @@
+//! arib2bdnxml as a library: `Converter` runs one conversion from a Rust program, and
+//! `parse_cli`/`run_with` are the command line the binary runs. The C API (capi/) is built on top
+//! of this crate.
+
+/// A log line for the current thread's diagnostics handler (stderr on the command line).
+macro_rules! log_line {
+    ($($arg:tt)*) => {
+        $crate::diagnostics::emit($crate::diagnostics::Level::Info, format_args!($($arg)*))
+    };
+}
+
+/// A warning for the current thread's diagnostics handler, counted (`stats::warnings_printed`).
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::diagnostics::emit($crate::diagnostics::Level::Warning, format_args!($($arg)*))
+    };
+}
+
+mod arib;
+mod bdn;
+mod bdsup2sub;
+mod bitmap;
+mod capabilities;
+mod chapters;
+mod checkpoint;
+mod cli;
+mod config;
+mod converter;
+mod corpus;
+mod dedup;
+mod diagnostics;
+mod dry_run;
+mod dual;
+mod edl;
+mod event_map;
+mod ffmpeg;
+mod ffmpeg_sys;
+mod ffprobe;
+mod fit;
+mod font_coverage;
+mod fonts;
+mod forced;
+mod jobs;
+mod limits;
+mod margin;
+mod min_gap;
+mod ocr;
+mod optimize;
+mod options;
+mod output;
+mod packet_log;
+mod pipeline;
+mod plate;
+mod png_optimize;
+mod position;
+mod preflight;
+mod preview;
+#[cfg_attr(not(feature = "preview-video"), allow(dead_code))]
+mod preview_video;
+mod probe_cache;
+mod reference;
+mod retarget;
+mod run_config;
+mod shard;
+mod sink;
+mod source_time;
+mod srt;
+mod stats;
+mod stl;
+mod style;
+mod throttle;
+mod timing;
+mod validate;
+mod watch;
+mod workspace;
+
+pub use cli::{attachment_fonts, parse_cli, run_with, Cli};
+pub use converter::{ConversionReport, ConvertOptions, Converter};
+pub use diagnostics::{handler, with_handler, DiagnosticHandler, Level};
+pub use fonts::AttachmentFonts;
+
+/// The building blocks of a conversion, for programs that compose their own.
+pub mod parts {
+    pub use crate::bdn::{BdnInfo, BdnXmlGenerator, SubtitleEvent};
+    pub use crate::bitmap::BitmapData;
+    pub use crate::ffmpeg::FfmpegWrapper;
+}
@@ -0,0 +1,228 @@
+//! --target-tool: advisory check of the written events against the practical limits of common
+//! authoring tools, so a file that will fail late in the authoring chain is caught here.
+
+/// Authoring tool whose limits the events are checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetTool {
+    Tsmuxer,
+    Scenarist,
+    /// Conservative limits for an unknown tool.
+    Generic,
+}
+
+impl std::fmt::Display for TargetTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TargetTool::Tsmuxer => "tsMuxeR",
+            TargetTool::Scenarist => "Scenarist",
+            TargetTool::Generic => "generic",
+        })
+    }
+}
+
+pub fn parse_target_tool(s: &str) -> Result<TargetTool, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "tsmuxer" => Ok(TargetTool::Tsmuxer),
+        "scenarist" => Ok(TargetTool::Scenarist),
+        "generic" => Ok(TargetTool::Generic),
+        _ => Err(format!(
+            "unknown target tool: {} (tsmuxer, scenarist, generic)",
+            s
+        )),
+    }
+}
+
+/// Practical limits of one tool, per PG stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToolLimits {
+    pub max_events: usize,
+    /// First in-TC to last out-TC, in seconds.
+    pub max_duration: f64,
+    /// Events starting within any one minute.
+    pub max_events_per_minute: usize,
+}
+
+/// Limits reported by users of each tool; not hard specifications. Generic takes the strictest.
+pub fn tool_limits(tool: TargetTool) -> ToolLimits {
+    match tool {
+        TargetTool::Tsmuxer => ToolLimits {
+            max_events: 9_999,
+            max_duration: 6.0 * 3600.0,
+            max_events_per_minute: 240,
+        },
+        TargetTool::Scenarist | TargetTool::Generic => ToolLimits {
+            max_events: 4_000,
+            max_duration: 2.0 * 3600.0,
+            max_events_per_minute: 120,
+        },
+    }
+}
+
+/// One limit the events exceed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// The file is likely to be rejected (an error with --strict); otherwise only slow or risky.
+    pub severe: bool,
+    pub message: String,
+    pub suggestion: String,
+}
+
+/// `seconds` as HH:MM:SS, rounded down.
+fn hms(seconds: f64) -> String {
+    let s = seconds.max(0.0) as u64;
+    format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+/// Most events starting within one minute, and where that minute starts. `starts` is sorted.
+fn densest_minute(starts: &[f64]) -> (usize, f64) {
+    let mut best = (0, 0.0);
+    let mut first = 0;
+    for (last, &start) in starts.iter().enumerate() {
+        while start - starts[first] >= 60.0 {
+            first += 1;
+        }
+        if last - first + 1 > best.0 {
+            best = (last - first + 1, starts[first]);
+        }
+    }
+    best
+}
+
+/// Checks events, given as (start, end) seconds in output order, against `limits` of `tool`.
+pub fn check_limits(tool: TargetTool, limits: &ToolLimits, events: &[(f64, f64)]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let (Some(first), Some(last)) = (events.first(), events.last()) else {
+        return findings;
+    };
+    if events.len() > limits.max_events {
+        let parts = events.len().div_ceil(limits.max_events);
+        findings.push(Finding {
+            severe: true,
+            message: format!(
+                "{} events; {} handles at most {} per file",
+                events.len(),
+                tool,
+                limits.max_events
+            ),
+            suggestion: format!(
+                "split the input at {} (and every {} events after) to stay under {} events per file ({} files)",
+                hms(events[limits.max_events].0),
+                limits.max_events,
+                limits.max_events,
+                parts
+            ),
+        });
+    }
+    let duration = last.1 - first.0;
+    if duration > limits.max_duration {
+        findings.push(Finding {
+            severe: true,
+            message: format!(
+                "events span {}; {} handles at most {} per file",
+                hms(duration),
+                tool,
+                hms(limits.max_duration)
+            ),
+            suggestion: format!(
+                "split the input at {} to keep each part under {}",
+                hms(first.0 + limits.max_duration),
+                hms(limits.max_duration)
+            ),
+        });
+    }
+    let mut starts: Vec<f64> = events.iter().map(|e| e.0).collect();
+    starts.sort_by(f64::total_cmp);
+    let (count, at) = densest_minute(&starts);
+    if count > limits.max_events_per_minute {
+        findings.push(Finding {
+            severe: false,
+            message: format!(
+                "{} events start within the minute from {}; {} handles about {}",
+                count,
+                hms(at),
+                tool,
+                limits.max_events_per_minute
+            ),
+            suggestion:
+                "lower --max-events-per-second or raise --correction-window to merge rapid re-sends"
+                    .to_string(),
+        });
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `n` one-second events, `gap` seconds apart, from 10 s.
+    fn events(n: usize, gap: f64) -> Vec<(f64, f64)> {
+        (0..n)
+            .map(|i| {
+                let start = 10.0 + i as f64 * gap;
+                (start, start + 1.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_target_tool() {
+        assert_eq!(parse_target_tool("tsMuxeR"), Ok(TargetTool::Tsmuxer));
+        assert_eq!(parse_target_tool("scenarist"), Ok(TargetTool::Scenarist));
+        assert_eq!(parse_target_tool("generic"), Ok(TargetTool::Generic));
+        assert!(parse_target_tool("encore").is_err());
+    }
+
+    #[test]
+    fn test_within_limits() {
+        let limits = tool_limits(TargetTool::Generic);
+        assert_eq!(check_limits(TargetTool::Generic, &limits, &[]), []);
+        // A two-hour-less-a-bit film with a caption every four seconds.
+        let film = events(1_700, 4.0);
+        assert_eq!(check_limits(TargetTool::Generic, &limits, &film), []);
+    }
+
+    #[test]
+    fn test_exceeded_limits() {
+        let limits = ToolLimits {
+            max_events: 100,
+            max_duration: 600.0,
+            max_events_per_minute: 20,
+        };
+        let findings = check_limits(TargetTool::Scenarist, &limits, &events(250, 2.0));
+        assert_eq!(findings.len(), 2);
+        assert!(findings[0].severe);
+        assert_eq!(
+            findings[0].message,
+            "250 events; Scenarist handles at most 100 per file"
+        );
+        assert_eq!(
+            findings[0].suggestion,
+            "split the input at 00:03:30 (and every 100 events after) to stay under 100 events per file (3 files)"
+        );
+        // Every two seconds is 30 a minute.
+        assert!(!findings[1].severe);
+        assert_eq!(
+            findings[1].message,
+            "30 events start within the minute from 00:00:10; Scenarist handles about 20"
+        );
+
+        let long = [(0.0, 1.0), (700.0, 701.5)];
+        let findings = check_limits(TargetTool::Scenarist, &limits, &long);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].message,
+            "events span 00:11:41; Scenarist handles at most 00:10:00 per file"
+        );
+        assert_eq!(
+            findings[0].suggestion,
+            "split the input at 00:10:00 to keep each part under 00:10:00"
+        );
+    }
+
+    #[test]
+    fn test_densest_minute() {
+        assert_eq!(densest_minute(&[]), (0, 0.0));
+        assert_eq!(densest_minute(&[0.0, 30.0, 59.9, 60.0, 61.0]), (4, 30.0));
+    }
+}
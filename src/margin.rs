@@ -0,0 +1,320 @@
+//! --bottom-margin and the other edges: keeps captions out of the bands along the canvas edges
+//! where players draw their OSD. An event inside a band is moved just far enough to clear it, and
+//! events shown at the same time are moved together so none comes to overlap another.
+
+use crate::bdn::{tc_to_seconds, SubtitleEvent};
+
+/// Bands along each edge of the canvas, in pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Margins {
+    pub top: i32,
+    pub bottom: i32,
+    pub left: i32,
+    pub right: i32,
+}
+
+impl Margins {
+    pub fn is_empty(&self) -> bool {
+        *self == Margins::default()
+    }
+}
+
+/// A box on the canvas: position and size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bounds {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Bounds {
+    /// The box around every graphic of `event`.
+    fn of(event: &SubtitleEvent) -> Self {
+        let (mut left, mut top) = (event.x, event.y);
+        let (mut right, mut bottom) = (event.x + event.width, event.y + event.height);
+        if let Some(g) = &event.second {
+            (left, top) = (left.min(g.x), top.min(g.y));
+            (right, bottom) = (right.max(g.x + g.width), bottom.max(g.y + g.height));
+        }
+        Bounds {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+
+    fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    fn overlaps_x(&self, other: &Bounds) -> bool {
+        self.x < other.right() && other.x < self.right()
+    }
+
+    fn overlaps_y(&self, other: &Bounds) -> bool {
+        self.y < other.bottom() && other.y < self.bottom()
+    }
+}
+
+/// Start of a `len` long span moved into `[low, high)`; when it does not fit, the `high` edge is
+/// kept clear, but the span never starts before 0.
+fn clamp_span(start: i32, len: i32, low: i32, high: i32) -> i32 {
+    start.max(low).min(high - len).max(0)
+}
+
+/// Starts of `bounds` on one axis, moved into `span`. `near` and `len` give a box's start and
+/// length on the axis, `overlaps_other_axis` whether two boxes share a row or column. Boxes are
+/// placed from the far edge in (the lowest first): each ends no further than the start of the
+/// boxes it was stacked on that are shown with it, so a box moved in pushes those before it
+/// along and the stacking order is kept. Boxes that already overlapped are left to overlap.
+fn place_axis(
+    bounds: &[Bounds],
+    together: &dyn Fn(usize, usize) -> bool,
+    span: (i32, i32),
+    near: fn(&Bounds) -> i32,
+    len: fn(&Bounds) -> i32,
+    overlaps_other_axis: fn(&Bounds, &Bounds) -> bool,
+) -> Vec<i32> {
+    let mut order: Vec<usize> = (0..bounds.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(near(&bounds[i]) + len(&bounds[i])));
+    let mut placed: Vec<Option<i32>> = vec![None; bounds.len()];
+    for &i in &order {
+        let b = &bounds[i];
+        let mut start = clamp_span(near(b), len(b), span.0, span.1);
+        for (j, other) in bounds.iter().enumerate() {
+            let Some(other_start) = placed[j] else {
+                continue;
+            };
+            // Only boxes that were apart on this axis, with `b` before, are kept apart.
+            let stacked = near(b) + len(b) <= near(other) && overlaps_other_axis(b, other);
+            if stacked && together(i, j) {
+                start = start.min(other_start - len(b));
+            }
+        }
+        placed[i] = Some(start.max(0));
+    }
+    placed.into_iter().map(|p| p.unwrap_or_default()).collect()
+}
+
+/// Moves the events of `group` (indices into `events`) out of `margins` of a `canvas`; the events
+/// are connected by being shown at the same time, `together` telling which pairs are.
+fn place_group(
+    events: &mut [SubtitleEvent],
+    group: &[usize],
+    together: &dyn Fn(usize, usize) -> bool,
+    margins: Margins,
+    canvas: (i32, i32),
+) -> usize {
+    let bounds: Vec<Bounds> = group.iter().map(|&i| Bounds::of(&events[i])).collect();
+    let together = |a: usize, b: usize| together(group[a], group[b]);
+    let xs = place_axis(
+        &bounds,
+        &together,
+        (margins.left, canvas.0 - margins.right),
+        |b| b.x,
+        |b| b.width,
+        Bounds::overlaps_y,
+    );
+    // Horizontal moves decide which boxes stack vertically.
+    let moved: Vec<Bounds> = bounds
+        .iter()
+        .zip(&xs)
+        .map(|(b, &x)| Bounds { x, ..*b })
+        .collect();
+    let ys = place_axis(
+        &moved,
+        &together,
+        (margins.top, canvas.1 - margins.bottom),
+        |b| b.y,
+        |b| b.height,
+        Bounds::overlaps_x,
+    );
+    let mut shifted = 0;
+    for (k, &i) in group.iter().enumerate() {
+        let (dx, dy) = (xs[k] - bounds[k].x, ys[k] - bounds[k].y);
+        if (dx, dy) == (0, 0) {
+            continue;
+        }
+        let event = &mut events[i];
+        (event.x, event.y) = (event.x + dx, event.y + dy);
+        if let Some(g) = &mut event.second {
+            (g.x, g.y) = (g.x + dx, g.y + dy);
+        }
+        shifted += 1;
+    }
+    shifted
+}
+
+/// Moves every event inside `margins` of a `canvas` out of them, keeping events shown at the
+/// same time from overlapping. When not everything fits between the bands, the top and left
+/// bands give way first. Returns the number of events moved.
+pub fn apply_margins(
+    events: &mut [SubtitleEvent],
+    margins: Margins,
+    canvas: (i32, i32),
+    fps: f64,
+) -> anyhow::Result<usize> {
+    let times = events
+        .iter()
+        .map(|e| {
+            Ok((
+                tc_to_seconds(&e.in_tc, fps)?,
+                tc_to_seconds(&e.out_tc, fps)?,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<(f64, f64)>>>()?;
+    let together = |a: usize, b: usize| times[a].0 < times[b].1 && times[b].0 < times[a].1;
+    let mut order: Vec<usize> = (0..events.len()).collect();
+    order.sort_by(|&a, &b| times[a].0.total_cmp(&times[b].0));
+    let mut shifted = 0;
+    // Runs of events each shown while an earlier one of the run still is.
+    let mut group: Vec<usize> = Vec::new();
+    let mut group_end = f64::NEG_INFINITY;
+    for i in order {
+        if times[i].0 >= group_end && !group.is_empty() {
+            shifted += place_group(events, &group, &together, margins, canvas);
+            group.clear();
+        }
+        group_end = if group.is_empty() {
+            times[i].1
+        } else {
+            group_end.max(times[i].1)
+        };
+        group.push(i);
+    }
+    if !group.is_empty() {
+        shifted += place_group(events, &group, &together, margins, canvas);
+    }
+    Ok(shifted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdn::Graphic;
+
+    const CANVAS: (i32, i32) = (1920, 1080);
+    const OSD: Margins = Margins {
+        top: 0,
+        bottom: 40,
+        left: 0,
+        right: 0,
+    };
+
+    fn event(
+        in_tc: &str,
+        out_tc: &str,
+        (x, y): (i32, i32),
+        (width, height): (i32, i32),
+    ) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: format!("a{}.png", y),
+            x,
+            y,
+            width,
+            height,
+            forced: false,
+            second: None,
+        }
+    }
+
+    fn positions(events: &[SubtitleEvent]) -> Vec<(i32, i32)> {
+        events.iter().map(|e| (e.x, e.y)).collect()
+    }
+
+    #[test]
+    fn test_single_event() {
+        let mut events = [
+            // Bottom edge at 1060: moved up 20 px.
+            event("00:00:01:00", "00:00:02:00", (400, 940), (1000, 120)),
+            // Already clear.
+            event("00:00:03:00", "00:00:04:00", (400, 900), (1000, 100)),
+            // Off the left edge and into the right band.
+            event("00:00:05:00", "00:00:06:00", (-10, 100), (200, 50)),
+            event("00:00:07:00", "00:00:08:00", (1800, 100), (100, 50)),
+        ];
+        let margins = Margins {
+            left: 30,
+            right: 60,
+            ..OSD
+        };
+        assert_eq!(
+            apply_margins(&mut events, margins, CANVAS, 30.0).unwrap(),
+            3
+        );
+        let expected = [(400, 920), (400, 900), (30, 100), (1760, 100)];
+        assert_eq!(positions(&events), expected);
+        // Taller than the space between the bands: kept on the canvas.
+        let mut tall = [event("00:00:01:00", "00:00:02:00", (0, 0), (100, 1060))];
+        apply_margins(&mut tall, Margins { top: 30, ..OSD }, CANVAS, 30.0).unwrap();
+        assert_eq!(positions(&tall), [(0, 0)]);
+        assert!(Margins::default().is_empty());
+    }
+
+    #[test]
+    fn test_stacked_events() {
+        let mut events = [
+            // Two lines shown together; the lower one is in the band.
+            event("00:00:01:00", "00:00:04:00", (400, 870), (1000, 90)),
+            event("00:00:01:00", "00:00:04:00", (400, 960), (1000, 100)),
+            // Shown with both, higher up and clear of them: not moved.
+            event("00:00:02:00", "00:00:03:00", (400, 100), (1000, 90)),
+        ];
+        assert_eq!(apply_margins(&mut events, OSD, CANVAS, 30.0).unwrap(), 2);
+        // The lower one moved up 20 px, pushing the upper one as far.
+        assert_eq!(positions(&events), [(400, 850), (400, 940), (400, 100)]);
+
+        // A gap absorbs part of the move: only as far as needed.
+        let mut events = [
+            event("00:00:01:00", "00:00:04:00", (400, 850), (1000, 90)),
+            event("00:00:01:00", "00:00:04:00", (400, 960), (1000, 100)),
+        ];
+        apply_margins(&mut events, OSD, CANVAS, 30.0).unwrap();
+        assert_eq!(positions(&events), [(400, 850), (400, 940)]);
+    }
+
+    #[test]
+    fn test_stacking_needs_simultaneous_events() {
+        let mut events = [
+            // Gone before the lower one shows: not pushed.
+            event("00:00:01:00", "00:00:02:00", (400, 860), (1000, 90)),
+            event("00:00:02:00", "00:00:03:00", (400, 960), (1000, 100)),
+            // Side by side: each moved up on its own, no horizontal push.
+            event("00:00:05:00", "00:00:06:00", (100, 1000), (600, 60)),
+            event("00:00:05:00", "00:00:06:00", (900, 900), (600, 60)),
+        ];
+        assert_eq!(apply_margins(&mut events, OSD, CANVAS, 30.0).unwrap(), 2);
+        let expected = [(400, 860), (400, 940), (100, 980), (900, 900)];
+        assert_eq!(positions(&events), expected);
+    }
+
+    #[test]
+    fn test_overlapping_and_split_events() {
+        let mut split = event("00:00:01:00", "00:00:02:00", (100, 980), (400, 60));
+        split.second = Some(Graphic {
+            png_file: "a_2.png".to_string(),
+            x: 1200,
+            y: 990,
+            width: 400,
+            height: 70,
+        });
+        let mut events = [
+            split,
+            // Already overlapping the box of both graphics, and clear of the band: left alone.
+            event("00:00:01:00", "00:00:02:00", (200, 1000), (100, 40)),
+        ];
+        apply_margins(&mut events, OSD, CANVAS, 30.0).unwrap();
+        // The box of both graphics ends at 1060: both move up 20 px.
+        assert_eq!(positions(&events), [(100, 960), (200, 1000)]);
+        let second = events[0].second.as_ref().unwrap();
+        assert_eq!((second.x, second.y), (1200, 970));
+    }
+}
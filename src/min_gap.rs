@@ -0,0 +1,247 @@
+//! --min-gap: keeps consecutive captions within the timing budget of the PG decoder. A
+//! presentation graphics object has to be decoded before its composition is shown, at a nominal
+//! rate, so a large caption right after another one can miss its time on hardware players, which
+//! then drop it. Too short gaps are widened by starting the later caption later or ending the
+//! earlier one earlier.
+
+use crate::bdn::{frames_to_tc, tc_to_frames, SubtitleEvent};
+
+/// Nominal object decode rate of the PG decoder model (128 Mbit/s), in bytes per second.
+pub const PG_DECODE_RATE: f64 = 16_000_000.0;
+
+/// Gap required between the end of an event and the start of the next (--min-gap).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MinGap {
+    /// The same number of frames for every transition.
+    Frames(u32),
+    /// Per transition, the frames needed to decode the objects of the next event.
+    Auto,
+}
+
+/// Parses --min-gap: a number of frames, or `auto`.
+pub fn parse_min_gap(s: &str) -> Result<MinGap, String> {
+    match s.trim() {
+        "auto" => Ok(MinGap::Auto),
+        frames => frames.parse().map(MinGap::Frames).map_err(|_| {
+            format!(
+                "invalid minimum gap: {} (expected a number of frames or auto)",
+                s
+            )
+        }),
+    }
+}
+
+/// Which event a too short gap is taken from first (--min-gap-side).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GapSide {
+    /// The later event starts later.
+    #[default]
+    Next,
+    /// The earlier event ends earlier.
+    Previous,
+}
+
+pub fn parse_gap_side(s: &str) -> Result<GapSide, String> {
+    match s.trim() {
+        "next" => Ok(GapSide::Next),
+        "previous" => Ok(GapSide::Previous),
+        _ => Err(format!(
+            "invalid gap side: {} (expected next or previous)",
+            s
+        )),
+    }
+}
+
+/// Bytes of the decoded objects of `event`: one palette index per pixel of each graphic.
+pub fn object_bytes(event: &SubtitleEvent) -> u64 {
+    let area = |width: i32, height: i32| width.max(0) as u64 * height.max(0) as u64;
+    let second = event.second.as_ref().map_or(0, |g| area(g.width, g.height));
+    area(event.width, event.height) + second
+}
+
+/// Frames needed between an event and `next`.
+pub fn required_gap(min_gap: MinGap, next: &SubtitleEvent, fps: f64) -> i64 {
+    match min_gap {
+        MinGap::Frames(frames) => frames as i64,
+        MinGap::Auto => (object_bytes(next) as f64 / PG_DECODE_RATE * fps).ceil() as i64,
+    }
+}
+
+/// A gap widened by `enforce_min_gap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GapAdjustment {
+    /// Images of the earlier and the later event.
+    pub previous: String,
+    pub next: String,
+    /// Frames the earlier event was ended earlier and the later one started later.
+    pub trimmed: i64,
+    pub delayed: i64,
+    /// Gap before and after, and the gap required, in frames.
+    pub gap: i64,
+    pub widened: i64,
+    pub required: i64,
+}
+
+/// Widens the gaps between consecutive events that do not overlap to what `min_gap` requires,
+/// taking the frames from the event `side` names first and the rest from the other one. Every
+/// event keeps at least one frame, so a gap can stay short. Works on exclusive OutTCs, after
+/// `snap_gaps` and before `apply_outtc_semantics`. Returns every widened gap.
+pub fn enforce_min_gap(
+    events: &mut [SubtitleEvent],
+    min_gap: MinGap,
+    side: GapSide,
+    fps: f64,
+) -> Vec<GapAdjustment> {
+    let mut adjustments = Vec::new();
+    for i in 1..events.len() {
+        let (Some(prev_in), Some(prev_out), Some(next_in), Some(next_out)) = (
+            tc_to_frames(&events[i - 1].in_tc, fps),
+            tc_to_frames(&events[i - 1].out_tc, fps),
+            tc_to_frames(&events[i].in_tc, fps),
+            tc_to_frames(&events[i].out_tc, fps),
+        ) else {
+            continue;
+        };
+        let gap = next_in - prev_out;
+        let required = required_gap(min_gap, &events[i], fps);
+        // Overlapping events are shown together, not one after the other.
+        if gap < 0 || gap >= required {
+            continue;
+        }
+        let missing = required - gap;
+        let (prev_room, next_room) = (
+            (prev_out - prev_in - 1).max(0),
+            (next_out - next_in - 1).max(0),
+        );
+        let (trimmed, delayed) = match side {
+            GapSide::Next => {
+                let delayed = missing.min(next_room);
+                ((missing - delayed).min(prev_room), delayed)
+            }
+            GapSide::Previous => {
+                let trimmed = missing.min(prev_room);
+                (trimmed, (missing - trimmed).min(next_room))
+            }
+        };
+        if trimmed + delayed == 0 {
+            continue;
+        }
+        events[i - 1].out_tc = frames_to_tc(prev_out - trimmed, fps);
+        events[i].in_tc = frames_to_tc(next_in + delayed, fps);
+        adjustments.push(GapAdjustment {
+            previous: events[i - 1].png_file.clone(),
+            next: events[i].png_file.clone(),
+            trimmed,
+            delayed,
+            gap,
+            widened: gap + trimmed + delayed,
+            required,
+        });
+    }
+    adjustments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(in_tc: &str, out_tc: &str, (width, height): (i32, i32)) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: format!("{}.png", in_tc.replace(':', "")),
+            x: 0,
+            y: 0,
+            width,
+            height,
+            forced: false,
+            second: None,
+        }
+    }
+
+    fn timecodes(events: &[SubtitleEvent]) -> Vec<(&str, &str)> {
+        events
+            .iter()
+            .map(|e| (e.in_tc.as_str(), e.out_tc.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_min_gap() {
+        assert_eq!(parse_min_gap("2"), Ok(MinGap::Frames(2)));
+        assert_eq!(parse_min_gap("auto"), Ok(MinGap::Auto));
+        assert!(parse_min_gap("-1").is_err());
+        assert_eq!(parse_gap_side("previous"), Ok(GapSide::Previous));
+        assert!(parse_gap_side("both").is_err());
+    }
+
+    #[test]
+    fn test_required_gap_auto() {
+        let fps = 30.0;
+        // Full-frame: 2073600 bytes take 0.1296 s, 3.9 frames.
+        let full = event("00:00:01:00", "00:00:02:00", (1920, 1080));
+        assert_eq!(required_gap(MinGap::Auto, &full, fps), 4);
+        // Two lines of text: under one frame, still one.
+        let line = event("00:00:01:00", "00:00:02:00", (1400, 120));
+        assert_eq!(required_gap(MinGap::Auto, &line, fps), 1);
+        let mut split = line.clone();
+        split.second = Some(crate::bdn::Graphic {
+            png_file: "b.png".to_string(),
+            x: 0,
+            y: 900,
+            width: 1920,
+            height: 1080,
+        });
+        assert_eq!(object_bytes(&split), 1400 * 120 + 1920 * 1080);
+        assert_eq!(required_gap(MinGap::Frames(2), &full, fps), 2);
+    }
+
+    #[test]
+    fn test_enforce_min_gap() {
+        let fps = 30.0;
+        let sequence = || {
+            vec![
+                event("00:00:01:00", "00:00:02:00", (1400, 120)),
+                // One frame after, full-width and tall: needs 4.
+                event("00:00:02:01", "00:00:03:00", (1920, 1080)),
+                // Back to back with a small one: needs 1.
+                event("00:00:03:00", "00:00:04:00", (1400, 120)),
+                // Shown together with the previous one: left alone.
+                event("00:00:03:15", "00:00:05:00", (1920, 1080)),
+            ]
+        };
+        let mut events = sequence();
+        let adjustments = enforce_min_gap(&mut events, MinGap::Auto, GapSide::Next, fps);
+        let expected = [
+            ("00:00:01:00", "00:00:02:00"),
+            ("00:00:02:04", "00:00:03:00"),
+            ("00:00:03:01", "00:00:04:00"),
+            ("00:00:03:15", "00:00:05:00"),
+        ];
+        assert_eq!(timecodes(&events), expected);
+        assert_eq!(adjustments.len(), 2);
+        assert_eq!(
+            (
+                adjustments[0].gap,
+                adjustments[0].widened,
+                adjustments[0].delayed
+            ),
+            (1, 4, 3)
+        );
+        assert_eq!(adjustments[0].next, "00000201.png");
+
+        let mut events = sequence();
+        enforce_min_gap(&mut events, MinGap::Auto, GapSide::Previous, fps);
+        assert_eq!(events[0].out_tc, "00:00:01:27");
+        assert_eq!(events[1].out_tc, "00:00:02:29");
+
+        // A one-frame event cannot give any: the earlier one gives all of it.
+        let mut events = vec![
+            event("00:00:01:00", "00:00:02:00", (100, 100)),
+            event("00:00:02:00", "00:00:02:01", (100, 100)),
+        ];
+        enforce_min_gap(&mut events, MinGap::Frames(2), GapSide::Next, fps);
+        assert_eq!(timecodes(&events)[0], ("00:00:01:00", "00:00:01:28"));
+        assert_eq!(events[1].in_tc, "00:00:02:00");
+    }
+}
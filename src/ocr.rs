@@ -0,0 +1,302 @@
+//! --ocr-cmd: runs an external OCR command (e.g. tesseract) on every written caption image, so
+//! archived bitmap captions get searchable text. No OCR engine is bundled.
+
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::bdn::SubtitleEvent;
+use crate::style::json_string;
+
+/// Replaced by the image path in an --ocr-cmd template.
+pub const OCR_PLACEHOLDER: &str = "{png}";
+/// Default limit on one OCR run (--ocr-timeout).
+pub const DEFAULT_OCR_TIMEOUT_SECS: u64 = 60;
+
+/// Splits an --ocr-cmd template into program and arguments. Whitespace separates words; single
+/// or double quotes group them. No shell is involved, so image paths are never re-parsed.
+pub fn parse_ocr_command(template: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for c in template.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(format!("unterminated quote in: {}", template));
+    }
+    words.extend(word);
+    if words.is_empty() {
+        return Err("empty command".to_string());
+    }
+    Ok(words)
+}
+
+/// Arguments for one image: `{png}` in any word is replaced by the path; without a
+/// placeholder the path is appended.
+pub fn ocr_args(words: &[String], image: &Path) -> Vec<OsString> {
+    if !words.iter().any(|w| w.contains(OCR_PLACEHOLDER)) {
+        let mut args: Vec<OsString> = words.iter().map(OsString::from).collect();
+        args.push(image.as_os_str().to_owned());
+        return args;
+    }
+    words
+        .iter()
+        .map(|w| match w.split_once(OCR_PLACEHOLDER) {
+            Some(_) if w == OCR_PLACEHOLDER => image.as_os_str().to_owned(),
+            Some(_) => OsString::from(w.replace(OCR_PLACEHOLDER, &image.to_string_lossy())),
+            None => OsString::from(w),
+        })
+        .collect()
+}
+
+/// Recognized text cleaned up: lines trimmed, blank lines and form feeds (tesseract ends pages
+/// with one) dropped.
+pub fn clean_ocr_text(raw: &str) -> String {
+    raw.lines()
+        .map(|l| l.trim_matches(|c: char| c.is_whitespace() || c == '\u{c}'))
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs `args` and returns its cleaned stdout; see `run_command`.
+pub fn run_ocr_command(args: &[OsString], timeout: Duration) -> Result<String, String> {
+    run_command(args, timeout).map(|out| clean_ocr_text(&out))
+}
+
+/// Runs `args` and returns its stdout. Fails on a spawn error, a non-zero exit (with the first
+/// line of stderr) or when it runs longer than `timeout` (the process is killed).
+pub fn run_command(args: &[OsString], timeout: Duration) -> Result<String, String> {
+    let (program, rest) = args.split_first().ok_or("empty command")?;
+    let mut child = Command::new(program)
+        .args(rest)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{}: {}", program.to_string_lossy(), e))?;
+    // Read both pipes while waiting so a chatty command cannot block on a full pipe.
+    let read_all = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut out = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut out);
+            }
+            out
+        })
+    };
+    let stdout = read_all(
+        child
+            .stdout
+            .take()
+            .map(|p| Box::new(p) as Box<dyn Read + Send>),
+    );
+    let stderr = read_all(
+        child
+            .stderr
+            .take()
+            .map(|p| Box::new(p) as Box<dyn Read + Send>),
+    );
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {}s", timeout.as_secs_f64()));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&stderr);
+        return Err(
+            match stderr.lines().map(str::trim).find(|l| !l.is_empty()) {
+                Some(line) => format!("{}: {}", status, line),
+                None => status.to_string(),
+            },
+        );
+    }
+    Ok(String::from_utf8_lossy(&stdout).into_owned())
+}
+
+/// OCRs `images` with at most `jobs` commands running at once; results are in input order.
+pub fn run_ocr(
+    words: &[String],
+    images: &[PathBuf],
+    jobs: usize,
+    timeout: Duration,
+) -> Vec<Result<String, String>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![Ok(String::new()); images.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, images.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(image) = images.get(i) else {
+                    break;
+                };
+                let result = run_ocr_command(&ocr_args(words, image), timeout);
+                results.lock().unwrap()[i] = result;
+            });
+        }
+    });
+    results.into_inner().unwrap()
+}
+
+/// OCR coverage: events with recognized text, and failed commands.
+pub fn ocr_summary(texts: &[String], failures: usize) -> String {
+    let recognized = texts.iter().filter(|t| !t.is_empty()).count();
+    let percent = if texts.is_empty() {
+        0.0
+    } else {
+        recognized as f64 * 100.0 / texts.len() as f64
+    };
+    format!(
+        "OCR: text for {} of {} event(s) ({:.1}%), {} failed image(s)",
+        recognized,
+        texts.len(),
+        percent,
+        failures
+    )
+}
+
+/// Events with their recognized text as a JSON array, one event per line.
+pub fn render_ocr_json(events: &[SubtitleEvent], texts: &[String]) -> String {
+    let lines: Vec<String> = events
+        .iter()
+        .zip(texts)
+        .map(|(e, text)| {
+            format!(
+                "{{\"in_tc\":{},\"out_tc\":{},\"png_file\":{},\"text\":{}}}",
+                json_string(&e.in_tc),
+                json_string(&e.out_tc),
+                json_string(&e.png_file),
+                json_string(text)
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        return "[]\n".to_string();
+    }
+    format!("[\n{}\n]\n", lines.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(template: &str) -> Vec<String> {
+        parse_ocr_command(template).unwrap()
+    }
+
+    #[test]
+    fn test_parse_ocr_command() {
+        assert_eq!(
+            words("tesseract {png} - -l jpn"),
+            ["tesseract", "{png}", "-", "-l", "jpn"]
+        );
+        assert_eq!(
+            words(r#"  "/opt/my ocr/run" --in='{png}' ''  "#),
+            ["/opt/my ocr/run", "--in={png}", ""]
+        );
+        assert!(parse_ocr_command("ocr 'x").is_err());
+        assert!(parse_ocr_command("   ").is_err());
+    }
+
+    #[test]
+    fn test_ocr_args() {
+        let image = Path::new("out dir/a 00001.png");
+        assert_eq!(
+            ocr_args(&words("tesseract {png} - --psm 6"), image),
+            ["tesseract", "out dir/a 00001.png", "-", "--psm", "6"]
+        );
+        assert_eq!(
+            ocr_args(&words("ocr --input={png}"), image),
+            ["ocr", "--input=out dir/a 00001.png"]
+        );
+        assert_eq!(
+            ocr_args(&words("ocr -j"), image),
+            ["ocr", "-j", "out dir/a 00001.png"]
+        );
+    }
+
+    #[test]
+    fn test_clean_ocr_text() {
+        assert_eq!(clean_ocr_text("  字幕 \n\n次の行\n\u{c}"), "字幕\n次の行");
+        assert_eq!(clean_ocr_text("\n \u{c}\n"), "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_ocr_command() {
+        let sh = |script: &str| {
+            let args = ocr_args(&["sh".into(), "-c".into(), script.into()], Path::new("x"));
+            run_ocr_command(&args[..3], Duration::from_secs(5))
+        };
+        assert_eq!(sh("printf ' a \\n\\nb\\n'"), Ok("a\nb".to_string()));
+        let err = sh("echo 'no model' >&2; exit 3").unwrap_err();
+        assert!(err.ends_with(": no model") && err.contains('3'), "{}", err);
+        let started = Instant::now();
+        let args: Vec<OsString> = ["sh", "-c", "sleep 5"].iter().map(OsString::from).collect();
+        assert_eq!(
+            run_ocr_command(&args, Duration::from_millis(200)),
+            Err("timed out after 0.2s".to_string())
+        );
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert!(run_ocr_command(&["/nonexistent/ocr".into()], Duration::from_secs(1)).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_ocr_keeps_order() {
+        let images: Vec<PathBuf> = (0..7).map(|i| PathBuf::from(format!("img{}", i))).collect();
+        let results = run_ocr(&words("echo {png}"), &images, 3, Duration::from_secs(5));
+        let expected: Vec<Result<String, String>> =
+            (0..7).map(|i| Ok(format!("img{}", i))).collect();
+        assert_eq!(results, expected);
+        assert!(run_ocr(&words("echo"), &[], 4, Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn test_summary_and_json() {
+        let texts = ["字幕".to_string(), String::new()];
+        assert_eq!(
+            ocr_summary(&texts, 1),
+            "OCR: text for 1 of 2 event(s) (50.0%), 1 failed image(s)"
+        );
+        let event = SubtitleEvent {
+            in_tc: "00:00:01:00".to_string(),
+            out_tc: "00:00:02:00".to_string(),
+            png_file: "a00000.png".to_string(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            forced: false,
+            second: None,
+        };
+        assert_eq!(
+            render_ocr_json(&[event], &["「はい」\n2行目".to_string()]),
+            "[\n{\"in_tc\":\"00:00:01:00\",\"out_tc\":\"00:00:02:00\",\"png_file\":\"a00000.png\",\"text\":\"「はい」\\u000a2行目\"}\n]\n"
+        );
+    }
+}
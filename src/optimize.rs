@@ -0,0 +1,398 @@
+//! `optimize` subcommand: shrinks the images of existing BDN output in place, without the
+//! source recording.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::bdn::{graphic_ref, parse_bdn, resolve_graphic, BdnInfo, BdnXmlGenerator, GraphicRef};
+use crate::bitmap::{load_png, write_rgba_png};
+use crate::workspace::TempWorkspace;
+
+/// What `optimize_dir` may change besides the PNG encoding.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OptimizeOptions {
+    /// Crop fully transparent borders and move the Graphic to match (--trim).
+    pub trim: bool,
+    /// Write monochrome images as grayscale + alpha (--png-auto-gray).
+    pub png_auto_gray: bool,
+    /// Leave images outside the XML's directory as they are instead of failing
+    /// (--allow-external-refs). They are never rewritten.
+    pub allow_external_refs: bool,
+}
+
+/// Result of `optimize_dir`.
+#[derive(Debug, Default)]
+pub struct OptimizeSummary {
+    /// Rewritten XML files and the info each was read with.
+    pub xml_files: Vec<(PathBuf, BdnInfo)>,
+    pub images: usize,
+    /// Images left as they were: not PNG, or not smaller when re-encoded.
+    pub unchanged: usize,
+    /// Sizes of the XML files and their images.
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Offset and size of a cropped image relative to its original Graphic.
+#[derive(Debug, Clone, Copy)]
+struct Crop {
+    dx: i32,
+    dy: i32,
+    width: i32,
+    height: i32,
+}
+
+fn file_size(path: &Path) -> anyhow::Result<u64> {
+    std::fs::metadata(path)
+        .map(|m| m.len())
+        .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))
+}
+
+/// Re-encodes one image into the workspace. Returns whether it was rewritten and its crop.
+fn optimize_image(
+    dir: &Path,
+    file: &str,
+    workspace: &TempWorkspace,
+    options: OptimizeOptions,
+    summary: &mut OptimizeSummary,
+) -> anyhow::Result<(bool, Option<Crop>)> {
+    let path = dir.join(file);
+    let before = file_size(&path)?;
+    summary.images += 1;
+    summary.bytes_before += before;
+    if !Path::new(file)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+    {
+        summary.unchanged += 1;
+        summary.bytes_after += before;
+        return Ok((false, None));
+    }
+
+    let mut image = load_png(&path)?;
+    // Color under fully transparent pixels is invisible but still costs bytes.
+    for px in image.data.chunks_exact_mut(4).filter(|px| px[3] == 0) {
+        px.fill(0);
+    }
+    let mut crop = None;
+    if let Some((x, y, width, height)) = image.content_bounds().filter(|_| options.trim) {
+        if (width, height) != (image.width, image.height) {
+            image = image.crop(x, y, width, height);
+            crop = Some(Crop {
+                dx: x as i32,
+                dy: y as i32,
+                width: width as i32,
+                height: height as i32,
+            });
+        }
+    }
+
+    let scratch = workspace.file(file);
+    write_rgba_png(&image, &scratch, options.png_auto_gray, true)?;
+    let after = file_size(&scratch)?;
+    if crop.is_none() && after >= before {
+        let _ = std::fs::remove_file(&scratch);
+        summary.unchanged += 1;
+        summary.bytes_after += before;
+        return Ok((false, None));
+    }
+    summary.bytes_after += after;
+    Ok((true, crop))
+}
+
+/// Optimizes every BDN XML file in `dir` and the images it references. Everything is written to
+/// `workspace` first; files in `dir` are replaced only once all of them were written. Images
+/// referenced by several events (or XML files) are processed once.
+pub fn optimize_dir(
+    dir: &Path,
+    workspace: &TempWorkspace,
+    options: OptimizeOptions,
+) -> anyhow::Result<OptimizeSummary> {
+    let mut xml_paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("Failed to read directory: {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "xml"))
+        .collect();
+    xml_paths.sort();
+    if xml_paths.is_empty() {
+        anyhow::bail!("No BDN XML files in {}", dir.display());
+    }
+
+    let mut summary = OptimizeSummary::default();
+    let mut images: HashMap<String, Option<Crop>> = HashMap::new();
+    let mut rewritten: Vec<String> = Vec::new();
+    let mut xml_names = Vec::new();
+    for path in xml_paths {
+        let xml = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))?;
+        let (info, mut events) =
+            parse_bdn(&xml).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        summary.bytes_before += xml.len() as u64;
+
+        for event in &mut events {
+            let second = event.second.as_mut();
+            let graphics = std::iter::once((
+                &event.png_file,
+                &mut event.x,
+                &mut event.y,
+                &mut event.width,
+                &mut event.height,
+            ))
+            .chain(second.map(|g| (&g.png_file, &mut g.x, &mut g.y, &mut g.width, &mut g.height)));
+            for (file, x, y, width, height) in graphics {
+                let crop = match images.get(file) {
+                    Some(&crop) => crop,
+                    None if graphic_ref(file) == GraphicRef::External => {
+                        resolve_graphic(dir, file, options.allow_external_refs)
+                            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+                        warning!(
+                            "{}: graphic {} is outside the XML's directory; left as it is",
+                            path.display(),
+                            file
+                        );
+                        images.insert(file.clone(), None);
+                        None
+                    }
+                    None => {
+                        let (changed, crop) =
+                            optimize_image(dir, file, workspace, options, &mut summary)?;
+                        if changed {
+                            rewritten.push(file.clone());
+                        }
+                        images.insert(file.clone(), crop);
+                        crop
+                    }
+                };
+                if let Some(c) = crop {
+                    *x += c.dx;
+                    *y += c.dy;
+                    *width = c.width;
+                    *height = c.height;
+                }
+            }
+        }
+
+        let mut generator = BdnXmlGenerator::new(info.clone());
+        for event in &events {
+            generator.add_event(event);
+        }
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid file name: {}", path.display()))?
+            .to_string();
+        generator.write_to_file(&workspace.file(&name))?;
+        summary.bytes_after += file_size(&workspace.file(&name))?;
+        xml_names.push(name);
+        summary.xml_files.push((path, info));
+    }
+
+    for file in rewritten.iter().chain(&xml_names) {
+        workspace.persist(file, &dir.join(file))?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdn::{Fade, SubtitleEvent, XmlStyle};
+    use crate::bitmap::{save_bitmap_as_png, BitmapData, RgbaImage};
+    use crate::validate::verify_file;
+
+    /// Opaque white 2x1 block at (3, 2) of a transparent 8x5 bitmap.
+    fn padded() -> BitmapData {
+        let mut data = vec![0; 8 * 5 * 4];
+        data[(2 * 8 + 3) * 4..(2 * 8 + 5) * 4].fill(255);
+        BitmapData {
+            data,
+            width: 8,
+            height: 5,
+            stride: 32,
+        }
+    }
+
+    fn event(in_tc: &str, png_file: &str) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: "00:00:09:00".to_string(),
+            png_file: png_file.to_string(),
+            x: 100,
+            y: 900,
+            width: 8,
+            height: 5,
+            forced: false,
+            second: None,
+        }
+    }
+
+    fn write_bdn(dir: &Path, name: &str, info: BdnInfo, events: &[SubtitleEvent]) {
+        let mut generator = BdnXmlGenerator::new(info);
+        for e in events {
+            generator.add_event(e);
+        }
+        generator.write_to_file(&dir.join(name)).unwrap();
+    }
+
+    #[test]
+    fn test_optimize_dir_trims_and_keeps_structure() {
+        let dir = crate::output::tests::scratch_dir("optimize");
+        save_bitmap_as_png(&padded(), &dir.join("a00000.png")).unwrap();
+        let info = BdnInfo {
+            fps: 29.97,
+            video_format: "1080i".to_string(),
+            fade: Some(Fade {
+                in_frames: 2,
+                out_frames: 3,
+            }),
+            normalized: None,
+            provenance: None,
+            xml_style: XmlStyle::default(),
+        };
+        // Two events share one image (--dedup-png output).
+        let events = [
+            event("00:00:01:00", "a00000.png"),
+            event("00:00:05:00", "a00000.png"),
+        ];
+        write_bdn(&dir, "a.xml", info.clone(), &events);
+
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let options = OptimizeOptions {
+            trim: true,
+            png_auto_gray: true,
+            ..Default::default()
+        };
+        let summary = optimize_dir(&dir, &ws, options).unwrap();
+        ws.finish().unwrap();
+        assert_eq!((summary.images, summary.unchanged), (1, 0));
+        assert_eq!(summary.xml_files, [(dir.join("a.xml"), info.clone())]);
+        assert!(summary.bytes_after < summary.bytes_before);
+
+        let xml_path = dir.join("a.xml");
+        assert_eq!(
+            verify_file(&xml_path, true, false).unwrap(),
+            Vec::<String>::new()
+        );
+        let (read_info, read) = parse_bdn(&std::fs::read_to_string(&xml_path).unwrap()).unwrap();
+        assert_eq!(read_info, info);
+        for e in &read {
+            assert_eq!((e.x, e.y, e.width, e.height), (103, 902, 2, 1));
+        }
+        let image = load_png(&dir.join("a00000.png")).unwrap();
+        assert_eq!(
+            image,
+            RgbaImage {
+                data: vec![255; 8],
+                width: 2,
+                height: 1,
+            }
+        );
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(leftovers, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_optimize_dir_without_trim_keeps_geometry() {
+        let dir = crate::output::tests::scratch_dir("optimize-notrim");
+        save_bitmap_as_png(&padded(), &dir.join("b00000.png")).unwrap();
+        std::fs::write(dir.join("b00001.tif"), b"tiff").unwrap();
+        let info = BdnInfo {
+            fps: 25.0,
+            video_format: "1080p".to_string(),
+            fade: None,
+            normalized: None,
+            provenance: None,
+            xml_style: XmlStyle::default(),
+        };
+        let events = [
+            event("00:00:01:00", "b00000.png"),
+            event("00:00:05:00", "b00001.tif"),
+        ];
+        write_bdn(&dir, "b.xml", info, &events);
+
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let summary = optimize_dir(&dir, &ws, OptimizeOptions::default()).unwrap();
+        ws.finish().unwrap();
+        assert_eq!(summary.images, 2);
+        assert!(summary.unchanged >= 1);
+        let (_, read) = parse_bdn(&std::fs::read_to_string(dir.join("b.xml")).unwrap()).unwrap();
+        assert_eq!(read, events);
+        assert_eq!(load_png(&dir.join("b00000.png")).unwrap().width, 8);
+        assert_eq!(std::fs::read(dir.join("b00001.tif")).unwrap(), b"tiff");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_optimize_dir_errors() {
+        let dir = crate::output::tests::scratch_dir("optimize-errors");
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let err = optimize_dir(&dir, &ws, OptimizeOptions::default()).unwrap_err();
+        assert!(err.to_string().starts_with("No BDN XML files"), "{}", err);
+
+        let info = BdnInfo {
+            fps: 25.0,
+            video_format: "1080p".to_string(),
+            fade: None,
+            normalized: None,
+            provenance: None,
+            xml_style: XmlStyle::default(),
+        };
+        write_bdn(&dir, "c.xml", info, &[event("00:00:01:00", "missing.png")]);
+        let before = std::fs::read(dir.join("c.xml")).unwrap();
+        let err = optimize_dir(&dir, &ws, OptimizeOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("missing.png"), "{}", err);
+        assert_eq!(std::fs::read(dir.join("c.xml")).unwrap(), before);
+        drop(ws);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_optimize_dir_external_refs() {
+        let root = crate::output::tests::scratch_dir("optimize-external");
+        let dir = root.join("xml");
+        std::fs::create_dir_all(&dir).unwrap();
+        save_bitmap_as_png(&padded(), &root.join("outside.png")).unwrap();
+        let outside = std::fs::read(root.join("outside.png")).unwrap();
+        let info = BdnInfo {
+            fps: 25.0,
+            video_format: "1080p".to_string(),
+            fade: None,
+            normalized: None,
+            provenance: None,
+            xml_style: XmlStyle::default(),
+        };
+        let events = [event("00:00:01:00", "../outside.png")];
+        write_bdn(&dir, "d.xml", info, &events);
+        let before = std::fs::read(dir.join("d.xml")).unwrap();
+
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let options = OptimizeOptions {
+            trim: true,
+            ..Default::default()
+        };
+        let err = optimize_dir(&dir, &ws, options).unwrap_err().to_string();
+        assert!(err.contains("outside the XML's directory"), "{}", err);
+        assert_eq!(std::fs::read(dir.join("d.xml")).unwrap(), before);
+        assert!(!verify_file(&dir.join("d.xml"), false, false)
+            .unwrap()
+            .is_empty());
+
+        // Allowed, the image is read by --verify but never rewritten or trimmed.
+        let options = OptimizeOptions {
+            allow_external_refs: true,
+            ..options
+        };
+        let summary = optimize_dir(&dir, &ws, options).unwrap();
+        ws.finish().unwrap();
+        assert_eq!(summary.images, 0);
+        assert_eq!(std::fs::read(root.join("outside.png")).unwrap(), outside);
+        let (_, read) = parse_bdn(&std::fs::read_to_string(dir.join("d.xml")).unwrap()).unwrap();
+        assert_eq!(read, events);
+        assert_eq!(
+            verify_file(&dir.join("d.xml"), false, true).unwrap(),
+            Vec::<String>::new()
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 /// Excluded libaribcaption option keys (handled internally or not supported).
 const EXCLUDED_OPTS: &[&str] = &["sub_type", "ass_single_rect", "canvas_size"];
@@ -8,8 +8,9 @@ fn is_excluded_opt(key: &str) -> bool {
 }
 
 /// Parses libaribcaption option string (key=value,key=value). Values may be quoted; commas inside quotes are not separators.
-pub fn parse_libaribcaption_opts(opts_str: &str) -> HashMap<String, String> {
-    let mut result = HashMap::new();
+/// Keys come out sorted; a key given twice keeps its last value.
+pub fn parse_libaribcaption_opts(opts_str: &str) -> BTreeMap<String, String> {
+    let mut result = BTreeMap::new();
     let mut remaining = opts_str.trim();
 
     while !remaining.is_empty() {
@@ -20,7 +21,7 @@ pub fn parse_libaribcaption_opts(opts_str: &str) -> HashMap<String, String> {
         let eq_pos = match remaining.find('=') {
             Some(p) => p,
             None => {
-                eprintln!("Warning: libaribcaption option '{}' is not key=value format, skipping", remaining);
+                warning!("libaribcaption option '{}' is not key=value format, skipping", remaining);
                 break;
             }
         };
@@ -73,7 +74,7 @@ pub fn parse_libaribcaption_opts(opts_str: &str) -> HashMap<String, String> {
         };
 
         if is_excluded_opt(&key) {
-            eprintln!("Warning: libaribcaption option '{}' is not supported, skipping", key);
+            warning!("libaribcaption option '{}' is not supported, skipping", key);
         } else {
             result.insert(key, value);
         }
@@ -87,6 +88,123 @@ pub fn parse_libaribcaption_opts(opts_str: &str) -> HashMap<String, String> {
     result
 }
 
+/// Parses a PID given as decimal ("336") or hexadecimal ("0x150").
+pub fn parse_pid(s: &str) -> Result<i32, String> {
+    let s = s.trim();
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => i32::from_str_radix(hex, 16),
+        None => s.parse::<i32>(),
+    };
+    match parsed {
+        Ok(pid) if (0..=0x1FFF).contains(&pid) => Ok(pid),
+        Ok(pid) => Err(format!("PID out of range (0-0x1FFF): {}", pid)),
+        Err(_) => Err(format!("invalid PID: {}", s)),
+    }
+}
+
+/// Parses a time given as seconds ("90", "90.5"), MM:SS(.mmm) or HH:MM:SS(.mmm).
+/// A leading '-' makes the time negative.
+pub fn parse_time_string(s: &str) -> Result<f64, String> {
+    let t = s.trim();
+    let (sign, body) = match t.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, t),
+    };
+    let parts: Vec<&str> = body.split(':').collect();
+    if parts.is_empty() || parts.len() > 3 || parts.iter().any(|p| p.is_empty()) {
+        return Err(format!("invalid time: {}", s));
+    }
+    let (last, fields) = parts.split_last().unwrap();
+    let secs: f64 = last
+        .parse()
+        .ok()
+        .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+        .ok_or_else(|| format!("invalid time: {}", s))?;
+    if !fields.is_empty() && secs >= 60.0 {
+        return Err(format!("invalid time (seconds must be < 60): {}", s));
+    }
+    let mut minutes = 0.0;
+    for (i, f) in fields.iter().enumerate() {
+        let v: u32 = f.parse().map_err(|_| format!("invalid time: {}", s))?;
+        // In HH:MM:SS the minutes field is bounded; a leading MM in MM:SS is not.
+        if fields.len() == 2 && i == 1 && v >= 60 {
+            return Err(format!("invalid time (minutes must be < 60): {}", s));
+        }
+        minutes = minutes * 60.0 + v as f64;
+    }
+    Ok(sign * (minutes * 60.0 + secs))
+}
+
+/// Parses an RGB color given as "#RRGGBB" or "RRGGBB".
+pub fn parse_rgb_color(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.trim();
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid color (expected #RRGGBB): {}", s));
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok([channel(0), channel(2), channel(4)])
+}
+
+/// Parses a frame rate given as a decimal ("29.97") or a fraction ("30000/1001").
+pub fn parse_frame_rate(s: &str) -> Result<f64, String> {
+    let t = s.trim();
+    let fps = match t.split_once('/') {
+        Some((num, den)) => {
+            let num: f64 = num.trim().parse().map_err(|_| format!("invalid frame rate: {}", s))?;
+            let den: f64 = den.trim().parse().map_err(|_| format!("invalid frame rate: {}", s))?;
+            if den == 0.0 {
+                return Err(format!("invalid frame rate: {}", s));
+            }
+            num / den
+        }
+        None => t.parse().map_err(|_| format!("invalid frame rate: {}", s))?,
+    };
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(format!("frame rate must be positive: {}", s));
+    }
+    Ok(fps)
+}
+
+/// Parses a --brightness factor between 0.0 and 1.0.
+pub fn parse_brightness(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(b) if (0.0..=1.0).contains(&b) => Ok(b),
+        _ => Err(format!("brightness must be between 0.0 and 1.0: {}", s)),
+    }
+}
+
+/// Parses a positive --gamma.
+pub fn parse_gamma(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(g) if g.is_finite() && g > 0.0 => Ok(g),
+        _ => Err(format!("gamma must be a positive number: {}", s)),
+    }
+}
+
+/// Parses "start end" time ranges, one per line (times via parse_time_string).
+/// Blank lines and lines starting with '#' are ignored.
+pub fn parse_time_ranges(contents: &str) -> Result<Vec<(f64, f64)>, String> {
+    let mut ranges = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            return Err(format!("line {}: expected \"start end\": {}", n + 1, line));
+        }
+        let start = parse_time_string(fields[0]).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        let end = parse_time_string(fields[1]).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        if end < start {
+            return Err(format!("line {}: end before start: {}", n + 1, line));
+        }
+        ranges.push((start, end));
+    }
+    Ok(ranges)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,15 +217,119 @@ mod tests {
     }
 
     #[test]
+    fn test_parse_libaribcaption_opts_order() {
+        // Sorted by key whatever the input order; the last of a repeated key wins.
+        let m = parse_libaribcaption_opts("replace_drcs=1,font=A,ignore_ruby=1,font=B");
+        let keys: Vec<&str> = m.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["font", "ignore_ruby", "replace_drcs"]);
+        assert_eq!(m["font"], "B");
+    }
+
+    #[test]
+    #[allow(clippy::unnecessary_get_then_check)]
     fn test_parse_libaribcaption_opts_excluded() {
         let m = parse_libaribcaption_opts("sub_type=bitmap,outline_width=0.0");
         assert!(m.get("sub_type").is_none());
         assert_eq!(m.get("outline_width"), Some(&"0.0".to_string()));
     }
 
+    #[test]
+    fn test_parse_libaribcaption_opts_diagnostics() {
+        use crate::diagnostics::{with_handler, Collector, Level};
+        use std::sync::Arc;
+
+        let collector = Arc::new(Collector::default());
+        let (m, malformed) = with_handler(Some(collector.clone()), || {
+            (
+                parse_libaribcaption_opts("canvas_size=1920x1080,outline_width=1.0"),
+                parse_libaribcaption_opts("bold"),
+            )
+        });
+        assert_eq!(m.len(), 1);
+        assert!(malformed.is_empty());
+        assert_eq!(
+            collector.messages(),
+            [
+                (
+                    Level::Warning,
+                    "libaribcaption option 'canvas_size' is not supported, skipping".to_string()
+                ),
+                (
+                    Level::Warning,
+                    "libaribcaption option 'bold' is not key=value format, skipping".to_string()
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn test_parse_libaribcaption_opts_quoted() {
         let m = parse_libaribcaption_opts(r#"font="Hiragino Maru Gothic ProN""#);
         assert_eq!(m.get("font"), Some(&"Hiragino Maru Gothic ProN".to_string()));
     }
+
+    #[test]
+    fn test_parse_pid() {
+        assert_eq!(parse_pid("336"), Ok(336));
+        assert_eq!(parse_pid("0x150"), Ok(0x150));
+        assert_eq!(parse_pid("0X1FFF"), Ok(0x1FFF));
+        assert!(parse_pid("0x2000").is_err());
+        assert!(parse_pid("-1").is_err());
+        assert!(parse_pid("pid").is_err());
+    }
+
+    #[test]
+    fn test_parse_time_string() {
+        assert_eq!(parse_time_string("90"), Ok(90.0));
+        assert_eq!(parse_time_string("1.5"), Ok(1.5));
+        assert_eq!(parse_time_string("01:30"), Ok(90.0));
+        assert_eq!(parse_time_string("01:02:03.250"), Ok(3723.25));
+        assert_eq!(parse_time_string("-00:00:01.5"), Ok(-1.5));
+        assert_eq!(parse_time_string("100:00"), Ok(6000.0));
+        assert!(parse_time_string("").is_err());
+        assert!(parse_time_string("1:2:3:4").is_err());
+        assert!(parse_time_string("00:60").is_err());
+        assert!(parse_time_string("01:60:00").is_err());
+        assert!(parse_time_string("abc").is_err());
+        assert!(parse_time_string("1::2").is_err());
+        assert!(parse_time_string("--1").is_err());
+    }
+
+    #[test]
+    fn test_parse_rgb_color() {
+        assert_eq!(parse_rgb_color("#102030"), Ok([0x10, 0x20, 0x30]));
+        assert_eq!(parse_rgb_color("ffFFff"), Ok([255, 255, 255]));
+        assert!(parse_rgb_color("#12345").is_err());
+        assert!(parse_rgb_color("#12345g").is_err());
+    }
+
+    #[test]
+    fn test_parse_time_ranges() {
+        let r = parse_time_ranges("# signs\n00:01:00 00:01:05.5\n\n120 130\n125 140\n").unwrap();
+        assert_eq!(r, vec![(60.0, 65.5), (120.0, 130.0), (125.0, 140.0)]);
+        assert!(parse_time_ranges("10").is_err());
+        assert!(parse_time_ranges("10 5").is_err());
+        assert!(parse_time_ranges("10 x").is_err());
+    }
+
+    #[test]
+    fn test_parse_frame_rate() {
+        assert_eq!(parse_frame_rate("25"), Ok(25.0));
+        assert_eq!(parse_frame_rate("29.97"), Ok(29.97));
+        assert_eq!(parse_frame_rate("30000/1001"), Ok(30000.0 / 1001.0));
+        assert!(parse_frame_rate("0").is_err());
+        assert!(parse_frame_rate("30/0").is_err());
+        assert!(parse_frame_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_brightness_and_gamma() {
+        assert_eq!(parse_brightness("0.9"), Ok(0.9));
+        assert_eq!(parse_brightness("1"), Ok(1.0));
+        assert!(parse_brightness("1.1").is_err());
+        assert!(parse_brightness("-0.1").is_err());
+        assert_eq!(parse_gamma("2.2"), Ok(2.2));
+        assert!(parse_gamma("0").is_err());
+        assert!(parse_gamma("inf").is_err());
+    }
 }
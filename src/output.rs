@@ -0,0 +1,777 @@
+//! Output directory handling: where each input's files go (--layout), and detecting and cleaning
+//! files left by a previous run.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::validate::is_xml_char;
+
+/// What to do when the output directory already has files (--clean / --no-clobber).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingOutputPolicy {
+    /// Warn about files from a previous run and overwrite.
+    Warn,
+    /// Remove this input's previous outputs before writing.
+    Clean,
+    /// Fail if the directory is not empty.
+    NoClobber,
+}
+
+/// Base name for output files: the input's file stem, or "output" when the stem is empty.
+/// Non-UTF-8 stems are converted lossily, and control characters (which cannot appear in the
+/// BDN XML) are removed with a warning.
+pub fn output_base_name(input_file: &Path) -> String {
+    let stem = match input_file.file_stem() {
+        Some(stem) if !stem.is_empty() => stem.to_string_lossy().into_owned(),
+        _ => return "output".to_string(),
+    };
+    let cleaned: String = stem
+        .chars()
+        .filter(|&c| !c.is_control() && is_xml_char(c))
+        .collect();
+    if cleaned != stem {
+        warning!(
+            "removed control characters from the output base name: {:?} -> {:?}",
+            stem,
+            cleaned
+        );
+    }
+    if cleaned.is_empty() {
+        "output".to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Directory next to `dir` with `suffix` appended to its name (e.g. "out" -> "out.forced").
+pub fn sibling_dir(dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = dir.components().as_path().as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Language of an input for `{lang}`: the last dot-separated part of its base name when it is a
+/// 2-3 letter code (`ep01.jpn.ts`), otherwise this.
+pub const DEFAULT_LANGUAGE: &str = "jpn";
+
+/// Where the BDN XML and images of each input go (--layout).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// `{base}_bdnxml/` next to the input, or --output.
+    Default,
+    /// XML and images directly in the input's directory (or --output).
+    Flat,
+    /// `{dir}/{base}.xml` with the images in `{dir}/{base}/`.
+    Siblings,
+    /// Templates (--xml-path, --png-dir) with `{base}`, `{dir}` and `{lang}`.
+    Custom { xml_path: String, png_dir: String },
+}
+
+/// Checks a --xml-path/--png-dir template: only `{base}`, `{dir}` and `{lang}` placeholders.
+pub fn parse_path_template(s: &str) -> Result<String, String> {
+    if s.is_empty() {
+        return Err("empty template".to_string());
+    }
+    let mut rest = s;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}') else {
+            return Err(format!("unclosed '{{' in '{}'", s));
+        };
+        let name = &rest[open + 1..open + close];
+        if !matches!(name, "base" | "dir" | "lang") {
+            return Err(format!(
+                "unknown placeholder '{{{}}}' (base, dir, lang)",
+                name
+            ));
+        }
+        rest = &rest[open + close + 1..];
+    }
+    Ok(s.to_string())
+}
+
+/// `template` with its placeholders replaced.
+fn expand_template(template: &str, base: &str, dir: &Path, lang: &str) -> PathBuf {
+    PathBuf::from(
+        template
+            .replace("{base}", base)
+            .replace("{lang}", lang)
+            .replace("{dir}", &dir.to_string_lossy()),
+    )
+}
+
+/// `{lang}` of a base name; see `DEFAULT_LANGUAGE`.
+fn input_language(base_name: &str) -> &str {
+    match base_name.rsplit_once('.') {
+        Some((_, code))
+            if (2..=3).contains(&code.len()) && code.chars().all(|c| c.is_ascii_alphabetic()) =>
+        {
+            code
+        }
+        _ => DEFAULT_LANGUAGE,
+    }
+}
+
+/// Where one input's outputs go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputPlan {
+    pub base_name: String,
+    /// Directory of the images (and thumbnails); the BDN XML's too unless the layout separates
+    /// them.
+    pub output_dir: PathBuf,
+    /// Directory of the BDN XML and the files written next to it (chapters, scripts, .sup).
+    pub xml_dir: PathBuf,
+    /// File name of the BDN XML without `.xml`; also names the files next to it.
+    pub xml_base: String,
+    /// The directories hold only this input's outputs (the default layout). Otherwise they are
+    /// shared with other files, and --no-clobber only refuses this input's own outputs.
+    pub exclusive: bool,
+    /// Directory for the --split-forced set.
+    pub forced_dir: PathBuf,
+    /// Directory for the 1920x1080 set of --dual-output.
+    pub square_dir: PathBuf,
+}
+
+/// Plans output directories for all inputs before any of them is opened.
+///
+/// Without `output`, each input gets `{base}_bdnxml` next to it. A single input with `output`
+/// writes there directly. Several inputs with `output` get one subdirectory each, named after
+/// the base name (prefixed with the parent directory name when base names repeat); with `flat`
+/// they share `output`, and repeated base names are an error listing the conflicting inputs.
+///
+/// The other layouts place the files by `{dir}`, the input's directory or `output`, and fail
+/// when two inputs would write the same XML or images.
+pub fn plan_outputs<P: AsRef<Path>>(
+    inputs: &[P],
+    output: Option<&Path>,
+    flat: bool,
+    layout: &Layout,
+) -> anyhow::Result<Vec<OutputPlan>> {
+    let bases: Vec<String> = inputs.iter().map(|p| output_base_name(p.as_ref())).collect();
+    if *layout != Layout::Default {
+        let plans: Vec<OutputPlan> = inputs
+            .iter()
+            .zip(bases)
+            .map(|(input, base_name)| {
+                let dir = output.or(input.as_ref().parent()).unwrap_or(Path::new("."));
+                let dir = if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                };
+                layout_plan(layout, base_name, dir)
+            })
+            .collect();
+        check_collisions(inputs, &plans)?;
+        return Ok(plans);
+    }
+    let mut by_base: HashMap<&str, Vec<&Path>> = HashMap::new();
+    for (input, base) in inputs.iter().zip(&bases) {
+        by_base.entry(base).or_default().push(input.as_ref());
+    }
+
+    let Some(out) = output else {
+        return Ok(inputs
+            .iter()
+            .zip(bases)
+            .map(|(input, base_name)| {
+                let parent = input.as_ref().parent().unwrap_or(Path::new("."));
+                let output_dir = parent.join(format!("{}_bdnxml", base_name));
+                OutputPlan {
+                    xml_dir: output_dir.clone(),
+                    xml_base: base_name.clone(),
+                    exclusive: true,
+                    output_dir,
+                    forced_dir: parent.join(format!("{}.forced_bdnxml", base_name)),
+                    square_dir: parent.join(format!("{}_bdnxml_1920", base_name)),
+                    base_name,
+                }
+            })
+            .collect());
+    };
+
+    if inputs.len() <= 1 || flat {
+        let mut conflicts: Vec<String> = by_base
+            .iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(base, paths)| {
+                let list: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                format!("{}: {}", base, list.join(", "))
+            })
+            .collect();
+        if !conflicts.is_empty() {
+            conflicts.sort();
+            anyhow::bail!(
+                "Inputs share a base name and would overwrite each other in {} (--flat-output):\n  {}",
+                out.display(),
+                conflicts.join("\n  ")
+            );
+        }
+        return Ok(bases
+            .into_iter()
+            .map(|base_name| OutputPlan {
+                xml_dir: out.to_path_buf(),
+                xml_base: base_name.clone(),
+                exclusive: true,
+                base_name,
+                output_dir: out.to_path_buf(),
+                forced_dir: sibling_dir(out, ".forced"),
+                square_dir: sibling_dir(out, "_1920"),
+            })
+            .collect());
+    }
+
+    let mut used: HashMap<String, usize> = HashMap::new();
+    let mut plans = Vec::new();
+    for (input, base_name) in inputs.iter().zip(bases.iter()) {
+        let mut subdir = base_name.clone();
+        if by_base[base_name.as_str()].len() > 1 {
+            if let Some(parent) = input.as_ref().parent().and_then(|p| p.file_name()) {
+                subdir = format!("{}_{}", parent.to_string_lossy(), base_name);
+            }
+        }
+        // Same parent name too (e.g. a/x/01.ts and b/x/01.ts): number the repeats.
+        let n = used.entry(subdir.clone()).or_insert(0);
+        *n += 1;
+        if *n > 1 {
+            subdir = format!("{}_{}", subdir, n);
+        }
+        let output_dir = out.join(&subdir);
+        plans.push(OutputPlan {
+            base_name: base_name.clone(),
+            xml_dir: output_dir.clone(),
+            xml_base: base_name.clone(),
+            exclusive: true,
+            forced_dir: sibling_dir(&output_dir, ".forced"),
+            square_dir: sibling_dir(&output_dir, "_1920"),
+            output_dir,
+        });
+    }
+    Ok(plans)
+}
+
+/// Plan of one input under a layout other than the default; `dir` is `{dir}`. The --split-forced
+/// and --dual-output sets go next to the image directory.
+fn layout_plan(layout: &Layout, base_name: String, dir: &Path) -> OutputPlan {
+    let (xml_path, output_dir) = match layout {
+        Layout::Default | Layout::Flat => (dir.join(format!("{}.xml", base_name)), dir.to_path_buf()),
+        Layout::Siblings => (dir.join(format!("{}.xml", base_name)), dir.join(&base_name)),
+        Layout::Custom { xml_path, png_dir } => {
+            let lang = input_language(&base_name);
+            (
+                expand_template(xml_path, &base_name, dir, lang),
+                expand_template(png_dir, &base_name, dir, lang),
+            )
+        }
+    };
+    let xml_dir = match xml_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = xml_path.file_name().map(|n| n.to_string_lossy().into_owned());
+    let name = name.unwrap_or_default();
+    let xml_base = name.strip_suffix(".xml").unwrap_or(&name).to_string();
+    OutputPlan {
+        xml_dir,
+        xml_base,
+        exclusive: false,
+        forced_dir: sibling_dir(&output_dir, ".forced"),
+        square_dir: sibling_dir(&output_dir, "_1920"),
+        output_dir,
+        base_name,
+    }
+}
+
+/// Fails when two inputs would write the same BDN XML, or images of the same name into the same
+/// directory, listing the inputs.
+fn check_collisions<P: AsRef<Path>>(inputs: &[P], plans: &[OutputPlan]) -> anyhow::Result<()> {
+    let mut xml: HashMap<PathBuf, Vec<&Path>> = HashMap::new();
+    let mut images: HashMap<PathBuf, Vec<&Path>> = HashMap::new();
+    for (input, plan) in inputs.iter().zip(plans) {
+        let xml_path = normalize(&plan.xml_dir.join(format!("{}.xml", plan.xml_base)));
+        xml.entry(xml_path).or_default().push(input.as_ref());
+        let images_of = normalize(&plan.output_dir.join(&plan.base_name));
+        images.entry(images_of).or_default().push(input.as_ref());
+    }
+    let mut conflicts = Vec::new();
+    for (what, map) in [("XML", &xml), ("images", &images)] {
+        for (path, inputs) in map.iter().filter(|(_, inputs)| inputs.len() > 1) {
+            let list: Vec<String> = inputs.iter().map(|p| p.display().to_string()).collect();
+            conflicts.push(format!("{} {}: {}", what, path.display(), list.join(", ")));
+        }
+    }
+    if !conflicts.is_empty() {
+        conflicts.sort();
+        anyhow::bail!(
+            "Inputs would overwrite each other's output (--layout):\n  {}",
+            conflicts.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// `path` made absolute, with `.` and `..` resolved lexically.
+fn normalize(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push(component);
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Relative path from directory `from` to `to`, both absolute and normalized; None when there is
+/// none (different drives or UNC shares on Windows).
+fn relative_path(from: &Path, to: &Path) -> Option<PathBuf> {
+    let (mut from, mut to) = (from.components().peekable(), to.components().peekable());
+    let root = |c: Option<&Component>| matches!(c, Some(Component::Prefix(_) | Component::RootDir));
+    while let (Some(a), Some(b)) = (from.peek(), to.peek()) {
+        if a != b {
+            break;
+        }
+        from.next();
+        to.next();
+    }
+    // A differing prefix or root has no relative path.
+    if root(from.peek()) || root(to.peek()) {
+        return None;
+    }
+    let mut path: PathBuf = from.map(|_| Component::ParentDir).collect();
+    path.extend(to);
+    Some(path)
+}
+
+/// How the BDN XML names its images.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageReference {
+    /// The images are next to the XML: bare file names.
+    SameDir,
+    /// Relative path from the XML's directory, `/`-separated.
+    Relative(String),
+    /// The image directory has no relative path from the XML's (another drive on Windows).
+    Absolute(PathBuf),
+}
+
+impl ImageReference {
+    /// Reference from an XML in `xml_dir` to images in `png_dir`.
+    pub fn new(xml_dir: &Path, png_dir: &Path) -> Self {
+        let (xml_dir, png_dir) = (normalize(xml_dir), normalize(png_dir));
+        match relative_path(&xml_dir, &png_dir) {
+            Some(path) if path.as_os_str().is_empty() => ImageReference::SameDir,
+            Some(path) => {
+                let parts: Vec<String> = path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                ImageReference::Relative(parts.join("/"))
+            }
+            None => ImageReference::Absolute(png_dir),
+        }
+    }
+
+    /// What the XML writes for the image `file`.
+    pub fn file(&self, file: &str) -> String {
+        match self {
+            ImageReference::SameDir => file.to_string(),
+            ImageReference::Relative(dir) => format!("{}/{}", dir, file),
+            ImageReference::Absolute(dir) => dir.join(file).display().to_string(),
+        }
+    }
+}
+
+/// True if `name` is an output this tool writes for `base_name`: `{base}NNNNN.png`/`.tif`,
+/// `{base}.xml`, `{base}.chapters.txt` or `{base}.srt`.
+fn is_own_output(name: &str, base_name: &str) -> bool {
+    let Some(rest) = name.strip_prefix(base_name) else {
+        return false;
+    };
+    if matches!(
+        rest,
+        ".xml"
+            | ".chapters.txt"
+            | ".srt"
+            | ".ocr.json"
+            | ".eventmap.json"
+            | ".bdsup2sub.sh"
+            | ".bdsup2sub.bat"
+            | ".sup"
+    ) {
+        return true;
+    }
+    rest.strip_suffix(".png")
+        .or_else(|| rest.strip_suffix(".tif"))
+        .is_some_and(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Previous outputs for `base_name` in `dir`, sorted.
+pub fn existing_outputs(dir: &Path, base_name: &str) -> std::io::Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            if is_own_output(name, base_name) {
+                out.push(entry.path());
+            }
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Applies the policy to an existing output directory before anything is written.
+pub fn prepare_output_dir(
+    dir: &Path,
+    base_name: &str,
+    policy: ExistingOutputPolicy,
+) -> anyhow::Result<()> {
+    match policy {
+        ExistingOutputPolicy::NoClobber => {
+            if fs::read_dir(dir)?.next().is_some() {
+                anyhow::bail!(
+                    "Output directory is not empty: {} (--no-clobber)",
+                    dir.display()
+                );
+            }
+        }
+        ExistingOutputPolicy::Clean => {
+            for path in existing_outputs(dir, base_name)? {
+                fs::remove_file(&path).map_err(|e| {
+                    anyhow::anyhow!("Failed to remove {}: {}", path.display(), e)
+                })?;
+            }
+        }
+        ExistingOutputPolicy::Warn => {
+            let existing = existing_outputs(dir, base_name)?;
+            if !existing.is_empty() {
+                warning!(
+                    "{} file(s) from a previous run in {} will be overwritten or left stale (use --clean to remove them).",
+                    existing.len(),
+                    dir.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `prepare_output_dir` for a directory shared with other files: --no-clobber fails only when
+/// outputs of `base_name` are there.
+pub fn prepare_shared_dir(
+    dir: &Path,
+    base_name: &str,
+    policy: ExistingOutputPolicy,
+) -> anyhow::Result<()> {
+    if policy != ExistingOutputPolicy::NoClobber {
+        return prepare_output_dir(dir, base_name, policy);
+    }
+    match existing_outputs(dir, base_name)?.first() {
+        Some(path) => anyhow::bail!("Output file exists: {} (--no-clobber)", path.display()),
+        None => Ok(()),
+    }
+}
+
+/// Hard-links `src` to `dst` so two output sets can share a PNG, copying when linking is not
+/// possible (e.g. across filesystems). An existing `dst` is replaced.
+pub fn link_or_copy(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    if dst.exists() {
+        fs::remove_file(dst)
+            .map_err(|e| anyhow::anyhow!("Failed to remove {}: {}", dst.display(), e))?;
+    }
+    if fs::hard_link(src, dst).is_err() {
+        fs::copy(src, dst).map_err(|e| {
+            anyhow::anyhow!("Failed to copy {} to {}: {}", src.display(), dst.display(), e)
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Creates an empty, unique scratch directory under the system temp dir.
+    pub(crate) fn scratch_dir(tag: &str) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "arib2bdnxml-test-{}-{}-{}",
+            tag,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_output_base_name() {
+        assert_eq!(output_base_name(Path::new("/rec/ep01.ts")), "ep01");
+        assert_eq!(output_base_name(Path::new("ep01.jpn.mks")), "ep01.jpn");
+        assert_eq!(output_base_name(Path::new("/")), "output");
+        assert_eq!(output_base_name(Path::new("")), "output");
+        assert_eq!(output_base_name(Path::new("ep\u{1}01\t.ts")), "ep01");
+        assert_eq!(output_base_name(Path::new("\u{7}.ts")), "output");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_base_name_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let input = Path::new(OsStr::from_bytes(b"/rec/ep\xff01.ts"));
+        assert_eq!(output_base_name(input), "ep\u{FFFD}01");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_png_to_non_utf8_path() {
+        use crate::bitmap::{save_bitmap_as_png, BitmapData};
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let dir = scratch_dir("nonutf8");
+        let bitmap = BitmapData {
+            data: vec![255; 4],
+            width: 1,
+            height: 1,
+            stride: 4,
+        };
+        let path = dir.join(OsStr::from_bytes(b"caption\xff.png"));
+        save_bitmap_as_png(&bitmap, &path).unwrap();
+        assert!(path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sibling_dir() {
+        assert_eq!(sibling_dir(Path::new("out"), ".forced"), PathBuf::from("out.forced"));
+        assert_eq!(sibling_dir(Path::new("/a/out/"), ".forced"), PathBuf::from("/a/out.forced"));
+    }
+
+    fn dirs(plans: &[OutputPlan]) -> Vec<String> {
+        plans.iter().map(|p| p.output_dir.display().to_string()).collect()
+    }
+
+    #[test]
+    fn test_plan_outputs_default_dirs() {
+        let plans = plan_outputs(&["Show/01.ts", "Show2/01.ts"], None, false, &Layout::Default).unwrap();
+        assert_eq!(dirs(&plans), ["Show/01_bdnxml", "Show2/01_bdnxml"]);
+        assert_eq!(plans[0].forced_dir, Path::new("Show/01.forced_bdnxml"));
+        assert_eq!(plans[0].square_dir, Path::new("Show/01_bdnxml_1920"));
+        assert_eq!(plans[1].base_name, "01");
+    }
+
+    #[test]
+    fn test_plan_outputs_single_input_uses_output_dir() {
+        let plans = plan_outputs(&["Show/01.ts"], Some(Path::new("/out")), false, &Layout::Default).unwrap();
+        assert_eq!(dirs(&plans), ["/out"]);
+        assert_eq!(plans[0].forced_dir, Path::new("/out.forced"));
+        assert_eq!(plans[0].square_dir, Path::new("/out_1920"));
+    }
+
+    #[test]
+    fn test_plan_outputs_batch_subdirectories() {
+        let inputs = ["Show/01.ts", "Show2/01.ts", "Show/02.ts", "a/x/03.ts", "b/x/03.ts"];
+        let plans = plan_outputs(&inputs, Some(Path::new("/out")), false, &Layout::Default).unwrap();
+        assert_eq!(
+            dirs(&plans),
+            ["/out/Show_01", "/out/Show2_01", "/out/02", "/out/x_03", "/out/x_03_2"]
+        );
+        assert!(plans.iter().all(|p| p.base_name != "Show_01"));
+        assert_eq!(plans[2].forced_dir, Path::new("/out/02.forced"));
+    }
+
+    #[test]
+    fn test_plan_outputs_flat() {
+        let out = Some(Path::new("/out"));
+        let plans = plan_outputs(&["Show/01.ts", "Show/02.ts"], out, true, &Layout::Default).unwrap();
+        assert_eq!(dirs(&plans), ["/out", "/out"]);
+
+        let err = plan_outputs(
+            &["Show/01.ts", "Show2/01.ts", "Show/02.ts"],
+            Some(Path::new("/out")),
+            true,
+            &Layout::Default,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("01: Show/01.ts, Show2/01.ts"), "{}", err);
+        assert!(!err.contains("02"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_path_template() {
+        let template = "{dir}/subs/{base}.{lang}.xml";
+        assert_eq!(parse_path_template(template), Ok(template.to_string()));
+        assert!(parse_path_template("").is_err());
+        assert!(parse_path_template("{dir}/{name}.xml").is_err());
+        assert!(parse_path_template("{dir/x.xml").is_err());
+        assert_eq!(input_language("ep01.jpn"), "jpn");
+        assert_eq!(input_language("ep01.en"), "en");
+        assert_eq!(input_language("ep01"), DEFAULT_LANGUAGE);
+        assert_eq!(input_language("ep.01"), DEFAULT_LANGUAGE);
+    }
+
+    #[test]
+    fn test_plan_outputs_layouts() {
+        let plan = |layout: &Layout, output: Option<&str>| {
+            let out = output.map(Path::new);
+            plan_outputs(&["rec/ep01.ts"], out, false, layout).unwrap().remove(0)
+        };
+        let flat = plan(&Layout::Flat, None);
+        assert_eq!(flat.output_dir, Path::new("rec"));
+        assert_eq!((flat.xml_dir.as_path(), flat.xml_base.as_str()), (Path::new("rec"), "ep01"));
+        assert!(!flat.exclusive);
+
+        let siblings = plan(&Layout::Siblings, Some("/out"));
+        assert_eq!(siblings.output_dir, Path::new("/out/ep01"));
+        assert_eq!(siblings.xml_dir, Path::new("/out"));
+        assert_eq!(siblings.forced_dir, Path::new("/out/ep01.forced"));
+
+        let custom = Layout::Custom {
+            xml_path: "{dir}/xml/{base}.{lang}.xml".to_string(),
+            png_dir: "/png/{base}".to_string(),
+        };
+        let custom = plan(&custom, None);
+        assert_eq!(custom.xml_dir, Path::new("rec/xml"));
+        assert_eq!(custom.xml_base, "ep01.jpn");
+        assert_eq!(custom.output_dir, Path::new("/png/ep01"));
+        assert_eq!(custom.base_name, "ep01");
+    }
+
+    #[test]
+    fn test_plan_outputs_layout_collisions() {
+        let inputs = ["a/ep01.ts", "a/ep01.m2ts", "b/ep01.ts"];
+        let err = plan_outputs(&inputs, None, false, &Layout::Siblings)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("a/ep01.ts, a/ep01.m2ts"), "{}", err);
+        assert!(!err.contains("b/ep01.ts"), "{}", err);
+
+        // One image directory for all is fine while the base names differ.
+        let shared = Layout::Custom {
+            xml_path: "{dir}/{base}.xml".to_string(),
+            png_dir: "/png".to_string(),
+        };
+        assert!(plan_outputs(&["a/ep01.ts", "a/ep02.ts"], None, false, &shared).is_ok());
+        let err = plan_outputs(&["a/ep01.ts", "b/ep01.ts"], None, false, &shared)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("images /png/ep01: a/ep01.ts, b/ep01.ts"), "{}", err);
+        assert!(!err.contains("XML"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_image_reference() {
+        let reference = |xml: &str, png: &str| ImageReference::new(Path::new(xml), Path::new(png));
+        assert_eq!(reference("/out", "/out/"), ImageReference::SameDir);
+        assert_eq!(reference("/out/x/..", "/out/./"), ImageReference::SameDir);
+        let sub = reference("/rec", "/rec/ep01");
+        assert_eq!(sub, ImageReference::Relative("ep01".to_string()));
+        assert_eq!(sub.file("ep0100000.png"), "ep01/ep0100000.png");
+        assert_eq!(
+            reference("/rec/xml", "/png/ep01"),
+            ImageReference::Relative("../../png/ep01".to_string())
+        );
+        assert_eq!(
+            reference("/rec", "/"),
+            ImageReference::Relative("..".to_string())
+        );
+        // Relative directories are taken from the current one.
+        assert_eq!(reference("rec", "rec/ep01"), sub);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_image_reference_other_drive() {
+        let reference = |xml: &str, png: &str| ImageReference::new(Path::new(xml), Path::new(png));
+        assert_eq!(
+            reference(r"C:\rec", r"C:\rec\ep01"),
+            ImageReference::Relative("ep01".to_string())
+        );
+        let other = reference(r"C:\rec", r"D:\png\ep01");
+        assert_eq!(other, ImageReference::Absolute(PathBuf::from(r"D:\png\ep01")));
+        assert_eq!(other.file("ep0100000.png"), r"D:\png\ep01\ep0100000.png");
+        assert!(matches!(
+            reference(r"C:\rec", r"\\server\share\png"),
+            ImageReference::Absolute(_)
+        ));
+    }
+
+    #[test]
+    fn test_is_own_output() {
+        assert!(is_own_output("ep0100000.png", "ep01"));
+        assert!(is_own_output("ep01.xml", "ep01"));
+        assert!(is_own_output("ep01.chapters.txt", "ep01"));
+        assert!(is_own_output("ep01.srt", "ep01"));
+        assert!(is_own_output("ep01.ocr.json", "ep01"));
+        assert!(is_own_output("ep01.eventmap.json", "ep01"));
+        assert!(is_own_output("ep01.bdsup2sub.sh", "ep01"));
+        assert!(is_own_output("ep01.sup", "ep01"));
+        assert!(is_own_output("ep0100000.tif", "ep01"));
+        assert!(!is_own_output("ep01.png", "ep01"));
+        assert!(!is_own_output("ep0100000.txt", "ep01"));
+        assert!(!is_own_output("ep01_cover.png", "ep01"));
+        assert!(!is_own_output("ep0200000.png", "ep01"));
+    }
+
+    #[test]
+    fn test_clean_removes_only_own_outputs() {
+        let dir = scratch_dir("clean");
+        for name in ["ep0100000.png", "ep0100001.png", "ep01.xml", "notes.txt", "ep0200000.png"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+        assert_eq!(existing_outputs(&dir, "ep01").unwrap().len(), 3);
+        prepare_output_dir(&dir, "ep01", ExistingOutputPolicy::Clean).unwrap();
+        assert!(existing_outputs(&dir, "ep01").unwrap().is_empty());
+        assert!(dir.join("notes.txt").exists());
+        assert!(dir.join("ep0200000.png").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_clobber() {
+        let dir = scratch_dir("noclobber");
+        prepare_output_dir(&dir, "ep01", ExistingOutputPolicy::NoClobber).unwrap();
+        fs::write(dir.join("other.txt"), b"x").unwrap();
+        assert!(prepare_output_dir(&dir, "ep01", ExistingOutputPolicy::NoClobber).is_err());
+        prepare_output_dir(&dir, "ep01", ExistingOutputPolicy::Warn).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prepare_shared_dir() {
+        let dir = scratch_dir("shared");
+        fs::write(dir.join("ep01.ts"), b"x").unwrap();
+        prepare_shared_dir(&dir, "ep01", ExistingOutputPolicy::NoClobber).unwrap();
+        fs::write(dir.join("ep01.xml"), b"x").unwrap();
+        assert!(prepare_shared_dir(&dir, "ep01", ExistingOutputPolicy::NoClobber).is_err());
+        prepare_shared_dir(&dir, "ep01", ExistingOutputPolicy::Clean).unwrap();
+        assert!(!dir.join("ep01.xml").exists());
+        assert!(dir.join("ep01.ts").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_link_or_copy_replaces_existing() {
+        let dir = scratch_dir("link");
+        let (src, dst) = (dir.join("a.png"), dir.join("b.png"));
+        fs::write(&src, b"new").unwrap();
+        fs::write(&dst, b"old").unwrap();
+        link_or_copy(&src, &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), b"new");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+//! --packet-log: a timeline of every subtitle packet read, for debugging sync problems and
+//! remuxes that lose caption packets.
+
+use std::path::Path;
+
+/// One subtitle packet as read from the container.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketRecord {
+    /// Packet pts in the stream time base; None when the container gave none.
+    pub pts: Option<i64>,
+    /// `pts` in seconds.
+    pub seconds: Option<f64>,
+    pub size: usize,
+    /// Whether decoding the packet produced a subtitle.
+    pub decoded: bool,
+}
+
+/// Aggregates over a packet log. Gaps are between consecutive packets with a pts, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketStats {
+    pub packets: usize,
+    pub bytes: u64,
+    pub decoded: usize,
+    /// Minimum, median and maximum gap; None with fewer than two timed packets.
+    pub gaps: Option<(f64, f64, f64)>,
+}
+
+impl std::fmt::Display for PacketStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} packet(s), {} byte(s), {} decoded",
+            self.packets, self.bytes, self.decoded
+        )?;
+        if let Some((min, median, max)) = self.gaps {
+            write!(
+                f,
+                "; gap min {:.3}s, median {:.3}s, max {:.3}s",
+                min, median, max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Subtitle packets in read order.
+#[derive(Debug, Default, Clone)]
+pub struct PacketLog {
+    pub records: Vec<PacketRecord>,
+}
+
+impl PacketLog {
+    pub fn push(&mut self, record: PacketRecord) {
+        self.records.push(record);
+    }
+
+    pub fn stats(&self) -> PacketStats {
+        let times: Vec<f64> = self.records.iter().filter_map(|r| r.seconds).collect();
+        let mut gaps: Vec<f64> = times.windows(2).map(|w| w[1] - w[0]).collect();
+        gaps.sort_by(f64::total_cmp);
+        PacketStats {
+            packets: self.records.len(),
+            bytes: self.records.iter().map(|r| r.size as u64).sum(),
+            decoded: self.records.iter().filter(|r| r.decoded).count(),
+            gaps: (!gaps.is_empty()).then(|| {
+                let mid = gaps.len() / 2;
+                let median = if gaps.len().is_multiple_of(2) {
+                    (gaps[mid - 1] + gaps[mid]) / 2.0
+                } else {
+                    gaps[mid]
+                };
+                (gaps[0], median, gaps[gaps.len() - 1])
+            }),
+        }
+    }
+
+    /// CSV with a header line: `index,pts,seconds,size,decoded`. A missing pts is an empty field.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("index,pts,seconds,size,decoded\n");
+        for (i, r) in self.records.iter().enumerate() {
+            out.push_str(&format!(
+                "{},{},{},{},{}\n",
+                i,
+                r.pts.map(|p| p.to_string()).unwrap_or_default(),
+                r.seconds.map(|s| format!("{:.6}", s)).unwrap_or_default(),
+                r.size,
+                r.decoded
+            ));
+        }
+        out
+    }
+
+    /// One JSON object per line; a missing pts is `null`.
+    pub fn to_ndjson(&self) -> String {
+        let mut out = String::new();
+        for (i, r) in self.records.iter().enumerate() {
+            out.push_str(&format!(
+                "{{\"index\":{},\"pts\":{},\"seconds\":{},\"size\":{},\"decoded\":{}}}\n",
+                i,
+                r.pts.map_or("null".to_string(), |p| p.to_string()),
+                r.seconds
+                    .map_or("null".to_string(), |s| format!("{:.6}", s)),
+                r.size,
+                r.decoded
+            ));
+        }
+        out
+    }
+
+    /// Writes the log to `path`: NDJSON for `.ndjson`/`.jsonl`, CSV otherwise.
+    pub fn write_to_file(&self, path: &Path) -> anyhow::Result<()> {
+        let ndjson = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("ndjson") || e.eq_ignore_ascii_case("jsonl"));
+        let contents = if ndjson {
+            self.to_ndjson()
+        } else {
+            self.to_csv()
+        };
+        crate::sink::write(path, contents)
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log() -> PacketLog {
+        let mut log = PacketLog::default();
+        for (pts, size, decoded) in [
+            (Some(1000), 120, true),
+            (Some(1500), 80, false),
+            (None, 10, false),
+            (Some(3500), 200, true),
+            (Some(3750), 40, true),
+        ] {
+            log.push(PacketRecord {
+                pts,
+                seconds: pts.map(|p| p as f64 / 1000.0),
+                size,
+                decoded,
+            });
+        }
+        log
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = log().stats();
+        // Gaps 0.5s, 2.0s, 0.25s.
+        assert_eq!(
+            stats,
+            PacketStats {
+                packets: 5,
+                bytes: 450,
+                decoded: 3,
+                gaps: Some((0.25, 0.5, 2.0)),
+            }
+        );
+        assert_eq!(
+            stats.to_string(),
+            "5 packet(s), 450 byte(s), 3 decoded; gap min 0.250s, median 0.500s, max 2.000s"
+        );
+        let empty = PacketLog::default().stats();
+        assert_eq!(empty.gaps, None);
+        assert_eq!(empty.to_string(), "0 packet(s), 0 byte(s), 0 decoded");
+    }
+
+    #[test]
+    fn test_csv_and_ndjson() {
+        let log = log();
+        let csv = log.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "index,pts,seconds,size,decoded");
+        assert_eq!(lines[1], "0,1000,1.000000,120,true");
+        assert_eq!(lines[3], "2,,,10,false");
+        assert_eq!(lines.len(), 6);
+
+        let ndjson = log.to_ndjson();
+        let lines: Vec<&str> = ndjson.lines().collect();
+        assert_eq!(
+            lines[1],
+            r#"{"index":1,"pts":1500,"seconds":1.500000,"size":80,"decoded":false}"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"{"index":2,"pts":null,"seconds":null,"size":10,"decoded":false}"#
+        );
+    }
+
+    #[test]
+    fn test_write_to_file_picks_format_by_extension() {
+        let dir = crate::output::tests::scratch_dir("packet-log");
+        let log = log();
+        log.write_to_file(&dir.join("p.csv")).unwrap();
+        log.write_to_file(&dir.join("p.NDJSON")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("p.csv")).unwrap(),
+            log.to_csv()
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("p.NDJSON")).unwrap(),
+            log.to_ndjson()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1320 @@
+//! Event building: turns decoded subtitle frames into timed BDN events. Everything except the
+//! per-event callback (PNG writing etc.) is pure, so it also runs over in-memory frames.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::bdn::{
+    adjust_timestamp, apply_outtc_semantics, midpoint_in_ranges, outtc_with_semantics,
+    resolve_collisions, snap_gaps, time_to_tc, Collision, Graphic, OutTcSemantics, SubtitleEvent,
+};
+use crate::bitmap::{
+    count_opaque_pixels, find_vertical_gap, generate_image_filename, place_on_canvas,
+    second_image_filename, BitmapData, ImageFormat,
+};
+use crate::edl::CutList;
+use crate::event_map::{DropReason, EndReason, SourceFrame};
+use crate::ffmpeg::{FrameKind, SubtitleFrame};
+use crate::min_gap::{enforce_min_gap, GapAdjustment, GapSide, MinGap};
+use crate::stats::Stats;
+use crate::stl::TextEvent;
+use crate::timing::{
+    auto_default_duration, auto_text_duration, plausible_duration, Burst, BurstLimiter,
+    ClearTracker, Correction, CorrectionTracker, DefaultDuration, TcMap,
+};
+
+/// Settings that decide event timing and naming.
+pub struct EventSettings<'a> {
+    pub fps: f64,
+    /// Seconds treated as 00:00:00:00.
+    pub base_time: f64,
+    pub default_duration: DefaultDuration,
+    /// Canvas width * height (for --default-duration auto).
+    pub canvas_area: usize,
+    pub tc_map: Option<&'a TcMap>,
+    pub cut_list: Option<&'a CutList>,
+    pub forced_ranges: Option<&'a [(f64, f64)]>,
+    pub base_name: &'a str,
+    /// Format of the per-event image files the events reference.
+    pub image_format: ImageFormat,
+    /// --start-index: number of the first image.
+    pub start_index: usize,
+    pub outtc_semantics: OutTcSemantics,
+    /// --split-horizontal: minimum transparent gap (pixels) at which a caption becomes two
+    /// graphics.
+    pub split_min_gap: Option<usize>,
+    /// --full-frame-pngs: canvas size every image is expanded to, with the caption at its
+    /// position and the Graphic at 0,0.
+    pub full_frame: Option<(i32, i32)>,
+    /// --correction-window: seconds within which a different caption re-sends the previous one
+    /// corrected; 0 disables.
+    pub correction_window: f64,
+    /// --keep-corrections: end the corrected event instead of replacing it.
+    pub keep_corrections: bool,
+    /// --max-events-per-second: events starting within one second beyond which captions are
+    /// coalesced into the newest event; 0 disables.
+    pub max_events_per_second: usize,
+    /// --snap-gaps: transitions of at most this many frames are made seamless; 0 disables.
+    pub snap_gaps: u32,
+    /// --merge-colliding: an event with the same timecodes as the previous one becomes its second
+    /// graphic where possible instead of starting a frame later.
+    pub merge_colliding: bool,
+    /// --min-gap: gap required between consecutive events, and --min-gap-side.
+    pub min_gap: Option<MinGap>,
+    pub min_gap_side: GapSide,
+    /// --global-delay: seconds added to every caption time before --tc-map and --edl.
+    pub global_delay: f64,
+    /// --offset: seconds added to every caption time after --tc-map and --edl.
+    pub offset: f64,
+    /// --max-display-duration: display times of a caption lasting longer are ignored; 0
+    /// disables.
+    pub max_display_duration: f64,
+    pub debug: bool,
+}
+
+impl EventSettings<'_> {
+    /// Applies --global-delay and --tc-map to an already base-adjusted time.
+    fn map_time(&self, t: f64) -> f64 {
+        let t = t + self.global_delay;
+        self.tc_map.map_or(t, |m| m.apply(t))
+    }
+
+    /// Output time of a clear frame's timestamp.
+    fn clear_time(&self, timestamp: f64) -> f64 {
+        let t = self.map_time(adjust_timestamp(timestamp, self.base_time));
+        self.cut_list.map_or(t, |cuts| cuts.map_time(t)) + self.offset
+    }
+
+    /// Applies --tc-map, --edl and then --offset to an event window; None when it falls inside
+    /// a cut.
+    fn map_window(&self, start: f64, end: f64) -> Option<(f64, f64)> {
+        let (start, end) = (self.map_time(start), self.map_time(end));
+        let mapped = match self.cut_list {
+            Some(cuts) => {
+                let mapped = cuts.map_event(start, end);
+                if mapped.is_none() && self.debug {
+                    log_line!("Dropped (inside EDL cut): {:.3}s", start);
+                }
+                mapped
+            }
+            None => Some((start, end)),
+        };
+        mapped.map(|(start, end)| (start + self.offset, end + self.offset))
+    }
+}
+
+/// Whether the frame carries display times (start and end) of its own.
+fn has_display_window(frame: &SubtitleFrame) -> bool {
+    frame.start_time > 0.0 && frame.end_time > frame.start_time
+}
+
+/// The frame's own display window (start, end), unless it has none or the window lasts longer
+/// than `max_duration` (--max-display-duration), in which case it is treated as absent.
+fn display_window(frame: &SubtitleFrame, max_duration: f64) -> Option<(f64, f64)> {
+    (has_display_window(frame)
+        && plausible_duration(frame.start_time, frame.end_time, max_duration))
+    .then_some((frame.start_time, frame.end_time))
+}
+
+/// Compute (adjusted_start, adjusted_end, end reason) for a subtitle frame using next frame or
+/// fallback: its own display time, the next caption, a following clear frame or
+/// `default_duration`. Display windows over `max_duration` are ignored.
+pub fn subtitle_timing(
+    frame: &SubtitleFrame,
+    next_frame: Option<&SubtitleFrame>,
+    start_time: f64,
+    default_duration: f64,
+    max_duration: f64,
+) -> (f64, f64, EndReason) {
+    let window = display_window(frame, max_duration);
+    let adjusted_start = match window {
+        Some((start, _)) => adjust_timestamp(start, start_time),
+        None => adjust_timestamp(frame.timestamp, start_time),
+    };
+    let (end, reason) = if let Some((_, end)) = window {
+        (end, EndReason::DisplayTime)
+    } else if let Some(next) = next_frame {
+        if next.bitmap.is_some() {
+            match display_window(next, max_duration) {
+                Some((start, _)) => (start, EndReason::NextCaption),
+                None => (next.timestamp, EndReason::NextCaption),
+            }
+        } else {
+            (next.timestamp, EndReason::Clear)
+        }
+    } else {
+        let end = adjusted_start + default_duration;
+        return (adjusted_start, end, EndReason::Default);
+    };
+    (adjusted_start, adjust_timestamp(end, start_time), reason)
+}
+
+/// Records a frame that has rects but no bitmap; it neither creates nor ends an event.
+fn skip_non_bitmap(frame: &SubtitleFrame, settings: &EventSettings, stats: &mut Stats) {
+    stats.non_bitmap_frames += 1;
+    let source = SourceFrame {
+        index: stats.subtitle_frames - 1,
+        timestamp: frame.timestamp,
+        pts: frame.packet_pts(),
+        latency: frame.display_latency(),
+    };
+    stats.event_map.drop_frame(source, DropReason::NonBitmap);
+    if settings.debug {
+        log_line!(
+            "Skipped frame without bitmap rects at {:.3}s ({}){}",
+            frame.timestamp,
+            frame.rects,
+            frame.text.as_deref().map_or(String::new(), |t| format!(": {:?}", t))
+        );
+    }
+}
+
+/// Hash of a caption image and its position, to tell a corrected re-send from a repeat.
+fn image_hash(bitmap: &BitmapData, x: i32, y: i32) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (x, y, bitmap.width, bitmap.height, bitmap.stride).hash(&mut hasher);
+    bitmap.data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// `build_events_from` with nothing to resume.
+#[cfg(test)]
+pub fn build_events<I, F>(
+    frames: I,
+    settings: &EventSettings,
+    stats: &mut Stats,
+    on_event: F,
+) -> Vec<SubtitleEvent>
+where
+    I: IntoIterator<Item = SubtitleFrame>,
+    F: FnMut(&mut SubtitleEvent, &BitmapData, f64) -> bool,
+{
+    build_events_from(Vec::new(), frames, settings, stats, on_event, |_, _| {})
+}
+
+/// Builds events from a frame source. `on_event` receives each candidate event, its bitmap and
+/// display duration in seconds (PNG writing, thumbnails, poster); it may point the event at
+/// another image file (--dedup-png), and returning false drops it. Split events (`second` set)
+/// cover columns `0..width` and `second.x - x..` of the bitmap.
+///
+/// New events are numbered after `resumed`, the events of a --checkpoint. After each new event,
+/// `on_settled` receives the events whose timing can no longer change (all but the newest) and
+/// the timestamp of the frame the newest came from, where decoding restarts to rebuild it.
+pub fn build_events_from<I, F, S>(
+    resumed: Vec<SubtitleEvent>,
+    frames: I,
+    settings: &EventSettings,
+    stats: &mut Stats,
+    mut on_event: F,
+    mut on_settled: S,
+) -> Vec<SubtitleEvent>
+where
+    I: IntoIterator<Item = SubtitleFrame>,
+    F: FnMut(&mut SubtitleEvent, &BitmapData, f64) -> bool,
+    S: FnMut(&[SubtitleEvent], f64),
+{
+    stats.event_map.start_index = settings.start_index;
+    stats.event_map.sources = vec![None; resumed.len()];
+    stats.event_map.ends = vec![None; resumed.len()];
+    let mut events = resumed;
+    let mut frames = frames.into_iter().peekable();
+    // Resumed events are settled; a clear after the resume point cannot end them.
+    let mut clears = ClearTracker::default();
+    let mut corrections = CorrectionTracker::default();
+    let mut bursts = BurstLimiter::new(settings.max_events_per_second);
+
+    while let Some(subtitle_frame) = frames.next() {
+        if settings.debug {
+            log_line!("Subtitle frame: index {}", events.len());
+        }
+        let source = SourceFrame {
+            index: stats.subtitle_frames,
+            timestamp: subtitle_frame.timestamp,
+            pts: subtitle_frame.packet_pts(),
+            latency: subtitle_frame.display_latency(),
+        };
+        stats.subtitle_frames += 1;
+        if subtitle_frame.kind() == FrameKind::NonBitmap {
+            skip_non_bitmap(&subtitle_frame, settings, stats);
+            continue;
+        }
+
+        let bitmap = match &subtitle_frame.bitmap {
+            Some(b) => b,
+            None => {
+                if !clears.clear() {
+                    stats.ignored_clears += 1;
+                } else if subtitle_frame.timestamp > 0.0 {
+                    let clear_tc =
+                        time_to_tc(settings.clear_time(subtitle_frame.timestamp), settings.fps);
+                    stats.event_map.clears.push((source, clear_tc.clone()));
+                    if let Some(last) = events.last_mut() {
+                        last.out_tc = clear_tc;
+                        stats.event_map.event_ended(EndReason::Clear);
+                    }
+                }
+                continue;
+            }
+        };
+        clears.caption();
+        stats.rgba_bytes += bitmap.data.len() as u64;
+        if settings.debug && subtitle_frame.rects.bitmap < subtitle_frame.rects.total() {
+            log_line!("Ignored non-bitmap rects ({})", subtitle_frame.rects);
+        }
+        if bitmap.width == 0 || bitmap.height == 0 {
+            stats.event_map.drop_frame(source, DropReason::EmptyBitmap);
+            continue;
+        }
+        // Text-only frames must not end this caption through the next-frame fallback.
+        while let Some(skipped) = frames.next_if(|f| f.kind() == FrameKind::NonBitmap) {
+            stats.subtitle_frames += 1;
+            skip_non_bitmap(&skipped, settings, stats);
+        }
+
+        let default_duration = match settings.default_duration {
+            DefaultDuration::Fixed(d) => d,
+            DefaultDuration::Auto => {
+                auto_default_duration(count_opaque_pixels(bitmap), settings.canvas_area)
+            }
+        };
+        if has_display_window(&subtitle_frame)
+            && display_window(&subtitle_frame, settings.max_display_duration).is_none()
+        {
+            stats.implausible_durations += 1;
+            if settings.debug {
+                log_line!(
+                    "Ignored implausible display time at {:.3}s: {:.3}s",
+                    subtitle_frame.timestamp,
+                    subtitle_frame.end_time - subtitle_frame.start_time
+                );
+            }
+        }
+        let (adjusted_start, adjusted_end, end_reason) = subtitle_timing(
+            &subtitle_frame,
+            frames.peek(),
+            settings.base_time,
+            default_duration,
+            settings.max_display_duration,
+        );
+        let Some((adjusted_start, adjusted_end)) =
+            settings.map_window(adjusted_start, adjusted_end)
+        else {
+            stats.event_map.drop_frame(source, DropReason::Cut);
+            continue;
+        };
+        // Moved before the start of the timeline: gone when it ends there, cut at 0 otherwise.
+        if adjusted_end <= 0.0 {
+            if settings.debug {
+                log_line!(
+                    "Dropped (ends before 00:00:00:00 after --offset/--global-delay): {:.3}s",
+                    subtitle_frame.timestamp
+                );
+            }
+            stats.event_map.drop_frame(source, DropReason::BeforeZero);
+            continue;
+        }
+        let adjusted_start = adjusted_start.max(0.0);
+
+        if adjusted_start >= adjusted_end {
+            stats.event_map.drop_frame(source, DropReason::ZeroLength);
+            continue;
+        }
+
+        // A different caption right after the newest event is the broadcaster re-sending it
+        // corrected: it takes that event's place, or ends it with --keep-corrections.
+        let image = image_hash(bitmap, subtitle_frame.x, subtitle_frame.y);
+        let mut in_tc = time_to_tc(adjusted_start, settings.fps);
+        let correction = corrections
+            .check(
+                adjusted_start,
+                image,
+                settings.correction_window,
+                settings.keep_corrections,
+            )
+            .and_then(|c| {
+                // An event cut to end where it starts would be empty.
+                let same_start = events.last()?.in_tc == in_tc;
+                Some(if same_start { Correction::Replace } else { c })
+            });
+        // Over --max-events-per-second (a decoder emitting a frame per packet): the caption
+        // replaces the newest event, so a burst keeps its first events and its last caption.
+        let coalesce = correction.is_none() && bursts.check(adjusted_start);
+        let mut index = events.len();
+        if correction == Some(Correction::Replace) || coalesce {
+            index -= 1;
+            in_tc = events[index].in_tc.clone();
+        }
+
+        let forced = settings
+            .forced_ranges
+            .is_some_and(|r| midpoint_in_ranges(adjusted_start, adjusted_end, r));
+        let full_frame = settings
+            .full_frame
+            .map(|(w, h)| place_on_canvas(bitmap, subtitle_frame.x, subtitle_frame.y, w, h));
+        let (bitmap, x, y) = match &full_frame {
+            Some(canvas) => (canvas, 0, 0),
+            None => (bitmap, subtitle_frame.x, subtitle_frame.y),
+        };
+        let mut event = SubtitleEvent {
+            in_tc,
+            out_tc: time_to_tc(adjusted_end, settings.fps),
+            // A replacing event rewrites the image file of the event it replaces.
+            png_file: generate_image_filename(
+                settings.start_index + index,
+                settings.base_name,
+                settings.image_format,
+            ),
+            x,
+            y,
+            width: bitmap.width,
+            height: bitmap.height,
+            forced,
+            second: None,
+        };
+        if let Some((gap_start, gap_end)) = settings
+            .split_min_gap
+            .and_then(|min_gap| find_vertical_gap(bitmap, min_gap))
+        {
+            event.width = gap_start;
+            event.second = Some(Graphic {
+                png_file: second_image_filename(&event.png_file),
+                x: event.x + gap_end,
+                y: event.y,
+                width: bitmap.width - gap_end,
+                height: bitmap.height,
+            });
+        }
+        if !on_event(&mut event, bitmap, adjusted_end - adjusted_start) {
+            stats.event_map.drop_frame(source, DropReason::WriteFailed);
+            continue;
+        }
+        if end_reason == EndReason::Default {
+            stats.defaulted_durations += 1;
+        }
+        if let Some(correction) = correction {
+            stats.corrections += 1;
+            if settings.debug {
+                log_line!(
+                    "Corrected caption ({:?}): {:.3}s",
+                    correction,
+                    adjusted_start
+                );
+            }
+            match (correction, events.last_mut()) {
+                (Correction::Replace, _) => {
+                    events.pop();
+                    stats.event_map.event_removed(DropReason::Corrected);
+                }
+                // Timecodes are zero-padded, so they compare as strings.
+                (Correction::Shorten, Some(last)) if last.out_tc > event.in_tc => {
+                    last.out_tc = event.in_tc.clone();
+                    stats.event_map.event_ended(EndReason::NextCaption);
+                }
+                _ => {}
+            }
+        }
+        if coalesce {
+            stats.coalesced += 1;
+            events.pop();
+            stats.event_map.event_removed(DropReason::Coalesced);
+        }
+        // A corrected re-send stands in for an event the limiter already counted.
+        if correction != Some(Correction::Replace) {
+            if let Some(burst) = bursts.event_added(adjusted_start, coalesce) {
+                warn_burst(&burst, settings);
+            }
+        }
+        events.push(event);
+        stats.event_map.event_added(source, end_reason);
+        clears.event_added();
+        corrections.event_added(adjusted_start, image);
+        on_settled(&events[..events.len() - 1], subtitle_frame.timestamp);
+    }
+    if let Some(burst) = bursts.finish() {
+        warn_burst(&burst, settings);
+    }
+    stats.snapped_gaps += snap_gaps(&mut events, settings.snap_gaps, settings.fps);
+    let mut keep = vec![true; events.len()];
+    for collision in resolve_collisions(&mut events, settings.merge_colliding, settings.fps) {
+        warn_collision(&collision);
+        stats.collisions += 1;
+        if collision.shifted_to.is_none() {
+            keep[collision.index] = false;
+            stats.merged_images.push((
+                collision.second.clone(),
+                second_image_filename(&collision.first),
+            ));
+        }
+    }
+    stats.event_map.retain(&keep, DropReason::Merged);
+    if let Some(min_gap) = settings.min_gap {
+        let side = settings.min_gap_side;
+        for adjustment in enforce_min_gap(&mut events, min_gap, side, settings.fps) {
+            report_gap(&adjustment);
+            stats.widened_gaps += 1;
+        }
+    }
+    apply_outtc_semantics(&mut events, settings.outtc_semantics, settings.fps);
+    events
+}
+
+fn warn_collision(collision: &Collision) {
+    let resolution = match &collision.shifted_to {
+        Some(in_tc) => format!("{} now starts at {}", collision.second, in_tc),
+        None => "merged into one event".to_string(),
+    };
+    warning!(
+        "{} and {} both show {}-{}; {}.",
+        collision.first,
+        collision.second,
+        collision.in_tc,
+        collision.out_tc,
+        resolution
+    );
+}
+
+fn report_gap(adjustment: &GapAdjustment) {
+    log_line!(
+        "Gap before {} widened from {} to {} frame(s) (--min-gap {}): {} frame(s) later, {} ended {} frame(s) earlier",
+        adjustment.next,
+        adjustment.gap,
+        adjustment.widened,
+        adjustment.required,
+        adjustment.delayed,
+        adjustment.previous,
+        adjustment.trimmed
+    );
+}
+
+fn warn_burst(burst: &Burst, settings: &EventSettings) {
+    warning!(
+        "more than {} captions per second at {}-{}; {} coalesced into the newest event.",
+        settings.max_events_per_second,
+        time_to_tc(burst.start, settings.fps),
+        time_to_tc(burst.end, settings.fps),
+        burst.coalesced
+    );
+}
+
+/// Builds text events from a text decode pass (--format stl), timed like `build_events_from`: frames
+/// with text start a caption, clears end the previous one. `canvas_height` turns the caption
+/// position into a fraction of the canvas.
+pub fn build_text_events<I>(
+    frames: I,
+    settings: &EventSettings,
+    canvas_height: i32,
+) -> Vec<TextEvent>
+where
+    I: IntoIterator<Item = SubtitleFrame>,
+{
+    let mut events: Vec<TextEvent> = Vec::new();
+    let mut frames = frames.into_iter().peekable();
+    let mut clears = ClearTracker::default();
+
+    while let Some(frame) = frames.next() {
+        let text = frame.text.as_deref().map(str::trim).unwrap_or_default();
+        if text.is_empty() {
+            if frame.kind() == FrameKind::Clear && clears.clear() && frame.timestamp > 0.0 {
+                if let Some(last) = events.last_mut() {
+                    last.out_tc = time_to_tc(settings.clear_time(frame.timestamp), settings.fps);
+                }
+            }
+            continue;
+        }
+        clears.caption();
+
+        let default_duration = match settings.default_duration {
+            DefaultDuration::Fixed(d) => d,
+            DefaultDuration::Auto => auto_text_duration(text.chars().count()),
+        };
+        let (start, end, _) = subtitle_timing(
+            &frame,
+            frames.peek(),
+            settings.base_time,
+            default_duration,
+            settings.max_display_duration,
+        );
+        let Some((start, end)) = settings.map_window(start, end) else {
+            continue;
+        };
+        if end <= 0.0 || start >= end {
+            continue;
+        }
+        events.push(TextEvent {
+            in_tc: time_to_tc(start, settings.fps),
+            out_tc: time_to_tc(end, settings.fps),
+            text: text.to_string(),
+            y: (frame.y > 0 && canvas_height > 0).then(|| frame.y as f64 / canvas_height as f64),
+        });
+        clears.event_added();
+    }
+    for event in &mut events {
+        if let Some(out_tc) = outtc_with_semantics(
+            &event.in_tc,
+            &event.out_tc,
+            settings.outtc_semantics,
+            settings.fps,
+        ) {
+            event.out_tc = out_tc;
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    //! Snapshot tests: each `tests/snapshots/<name>.frames` manifest is run through
+    //! `build_events` and the BDN XML writer and compared with `<name>.xml`.
+    //! Run with `UPDATE_SNAPSHOTS=1 cargo test` to rewrite the golden files after an
+    //! intentional output change. Golden files are compared byte for byte (.gitattributes keeps
+    //! their line endings as written).
+    //!
+    //! Manifest lines (`#` starts a comment):
+    //! - `fps <RATE>`, `base <SECONDS>`, `default-duration <SECONDS|auto>`, `canvas <W>x<H>`,
+    //!   `forced <START> <END>`, `outtc <exclusive|inclusive>`, `xml-line-endings <lf|crlf>`,
+    //!   `xml-indent <N|tab>`
+    //! - `frame <TIMESTAMP> <START> <END> <X> <Y> <W>x<H> <BASE64 RGBA|->` (START/END 0 = unset)
+    //! - `clear <TIMESTAMP>`
+    //! - `text <TIMESTAMP> <TEXT...>` (a frame with one SUBTITLE_TEXT rect and no bitmap)
+    //!
+    //! Every frame gets the packet pts of its timestamp on a 90 kHz clock, as in MPEG-TS.
+
+    use super::*;
+    use crate::bdn::{
+        parse_indent, parse_line_ending, parse_outtc_semantics, BdnInfo, BdnXmlGenerator, XmlStyle,
+        DEFAULT_SNAP_GAPS,
+    };
+    use crate::event_map::renumber_images;
+    use crate::ffmpeg::RectCounts;
+    use crate::source_time::{BroadcastClock, SourceTimes};
+    use crate::timing::{
+        parse_default_duration, DEFAULT_CORRECTION_WINDOW, DEFAULT_MAX_DISPLAY_DURATION,
+        DEFAULT_MAX_EVENTS_PER_SECOND,
+    };
+    use std::path::Path;
+
+    fn decode_base64(s: &str) -> Vec<u8> {
+        const ALPHABET: &[u8] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = Vec::new();
+        let (mut acc, mut bits) = (0u32, 0);
+        for c in s.bytes().filter(|&c| c != b'=') {
+            let v = ALPHABET.iter().position(|&a| a == c).expect("invalid base64") as u32;
+            acc = ((acc << 6) | v) & 0xFFF;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((acc >> bits) as u8);
+            }
+        }
+        out
+    }
+
+    struct Manifest {
+        fps: f64,
+        base_time: f64,
+        default_duration: DefaultDuration,
+        canvas_area: usize,
+        forced_ranges: Vec<(f64, f64)>,
+        outtc_semantics: OutTcSemantics,
+        xml_style: XmlStyle,
+        frames: Vec<SubtitleFrame>,
+    }
+
+    fn parse_manifest(contents: &str) -> Manifest {
+        let mut m = Manifest {
+            fps: 29.97,
+            base_time: 0.0,
+            default_duration: DefaultDuration::Fixed(1.0),
+            canvas_area: 1920 * 1080,
+            forced_ranges: Vec::new(),
+            outtc_semantics: OutTcSemantics::Exclusive,
+            xml_style: XmlStyle::default(),
+            frames: Vec::new(),
+        };
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let f: Vec<&str> = line.split_whitespace().collect();
+            let num = |i: usize| -> f64 { f[i].parse().unwrap() };
+            let pts = |timestamp: f64| (timestamp * 90_000.0).round() as i64;
+            let size = |s: &str| -> (i32, i32) {
+                let (w, h) = s.split_once('x').unwrap();
+                (w.parse().unwrap(), h.parse().unwrap())
+            };
+            match f[0] {
+                "fps" => m.fps = num(1),
+                "base" => m.base_time = num(1),
+                "default-duration" => m.default_duration = parse_default_duration(f[1]).unwrap(),
+                "canvas" => {
+                    let (w, h) = size(f[1]);
+                    m.canvas_area = (w * h) as usize;
+                }
+                "forced" => m.forced_ranges.push((num(1), num(2))),
+                "outtc" => m.outtc_semantics = parse_outtc_semantics(f[1]).unwrap(),
+                "xml-line-endings" => m.xml_style.line_ending = parse_line_ending(f[1]).unwrap(),
+                "xml-indent" => m.xml_style.indent = parse_indent(f[1]).unwrap(),
+                "clear" => m.frames.push(SubtitleFrame {
+                    bitmap: None,
+                    pts: pts(num(1)),
+                    timestamp: num(1),
+                    start_time: 0.0,
+                    end_time: 0.0,
+                    x: 0,
+                    y: 0,
+                    rects: RectCounts::default(),
+                    text: None,
+                }),
+                "text" => m.frames.push(SubtitleFrame {
+                    bitmap: None,
+                    pts: pts(num(1)),
+                    timestamp: num(1),
+                    start_time: 0.0,
+                    end_time: 0.0,
+                    x: 0,
+                    y: 0,
+                    rects: RectCounts {
+                        text: 1,
+                        ..Default::default()
+                    },
+                    text: Some(f[2..].join(" ")),
+                }),
+                "frame" => {
+                    let (width, height) = size(f[6]);
+                    let data = if f[7] == "-" { Vec::new() } else { decode_base64(f[7]) };
+                    assert_eq!(data.len(), (width * height * 4) as usize, "{}", line);
+                    m.frames.push(SubtitleFrame {
+                        bitmap: Some(BitmapData {
+                            data,
+                            width,
+                            height,
+                            stride: width * 4,
+                        }),
+                        pts: pts(num(1)),
+                        timestamp: num(1),
+                        start_time: num(2),
+                        end_time: num(3),
+                        x: f[4].parse().unwrap(),
+                        y: f[5].parse().unwrap(),
+                        rects: RectCounts {
+                            bitmap: 1,
+                            ..Default::default()
+                        },
+                        text: None,
+                    });
+                }
+                other => panic!("unknown manifest line: {}", other),
+            }
+        }
+        m
+    }
+
+    /// Settings of a manifest run; tests override single fields with `..settings(&m)`.
+    fn settings(m: &Manifest) -> EventSettings<'static> {
+        EventSettings {
+            fps: m.fps,
+            base_time: m.base_time,
+            default_duration: m.default_duration,
+            canvas_area: m.canvas_area,
+            tc_map: None,
+            cut_list: None,
+            forced_ranges: None,
+            base_name: "t",
+            image_format: ImageFormat::Png,
+            start_index: 0,
+            outtc_semantics: m.outtc_semantics,
+            split_min_gap: None,
+            full_frame: None,
+            correction_window: DEFAULT_CORRECTION_WINDOW,
+            keep_corrections: false,
+            max_events_per_second: DEFAULT_MAX_EVENTS_PER_SECOND,
+            snap_gaps: DEFAULT_SNAP_GAPS,
+            merge_colliding: false,
+            min_gap: None,
+            min_gap_side: GapSide::Next,
+            global_delay: 0.0,
+            offset: 0.0,
+            max_display_duration: DEFAULT_MAX_DISPLAY_DURATION,
+            debug: false,
+        }
+    }
+
+    fn render(m: Manifest) -> String {
+        let settings = EventSettings {
+            forced_ranges: (!m.forced_ranges.is_empty()).then_some(m.forced_ranges.as_slice()),
+            base_name: "snapshot",
+            ..settings(&m)
+        };
+        let events = build_events(m.frames, &settings, &mut Stats::default(), |_, _, _| true);
+        let mut generator = BdnXmlGenerator::new(BdnInfo {
+            fps: m.fps,
+            video_format: "1080i".to_string(),
+            fade: None,
+            normalized: None,
+            provenance: None,
+            xml_style: m.xml_style,
+        });
+        for event in &events {
+            generator.add_event(event);
+        }
+        generator.to_xml().unwrap()
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64("/wAA/w=="), vec![255, 0, 0, 255]);
+        assert_eq!(decode_base64(""), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_text_only_frames_are_skipped() {
+        let m = parse_manifest("frame 1.0 0 0 0 0 1x1 /wAA/w==\ntext 2.0 hello\nclear 3.0\n");
+        let mut stats = Stats::default();
+        let settings = EventSettings {
+            fps: 30.0,
+            ..settings(&m)
+        };
+        let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].out_tc, "00:00:03:00");
+        assert_eq!(stats.subtitle_frames, 3);
+        assert_eq!(stats.non_bitmap_frames, 1);
+    }
+
+    #[test]
+    fn test_only_first_clear_ends_an_event() {
+        let m = parse_manifest(
+            "clear 0.5\nframe 1.0 0 0 0 0 1x1 /wAA/w==\nclear 2.0\nclear 5.0\nframe 6.0 0 0 0 0 1x1 /wAA/w==\nclear 7.0\n",
+        );
+        let mut stats = Stats::default();
+        let settings = EventSettings {
+            fps: 30.0,
+            ..settings(&m)
+        };
+        let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+        let times: Vec<(&str, &str)> = events
+            .iter()
+            .map(|e| (e.in_tc.as_str(), e.out_tc.as_str()))
+            .collect();
+        assert_eq!(
+            times,
+            [("00:00:01:00", "00:00:02:00"), ("00:00:06:00", "00:00:07:00")]
+        );
+        assert_eq!(stats.ignored_clears, 2);
+    }
+
+    #[test]
+    fn test_corrected_captions() {
+        // Red then green 0.1s later, both with the same display time, and a legitimate change
+        // 0.5s later.
+        let manifest = "frame 1.0 0 0 0 0 1x1 /wAA/w==\nframe 1.1 0 0 0 0 1x1 AP8A/w==\nclear 3.0\n\
+                        frame 4.0 4.0 5.0 0 0 1x1 /wAA/w==\nframe 4.0 4.0 5.0 0 0 1x1 AP8A/w==\n\
+                        frame 6.0 0 0 0 0 1x1 /wAA/w==\nframe 6.5 0 0 0 0 1x1 AP8A/w==\nclear 7.0\n";
+        let build = |keep_corrections| {
+            let m = parse_manifest(manifest);
+            let settings = EventSettings {
+                fps: 30.0,
+                keep_corrections,
+                ..settings(&m)
+            };
+            let mut stats = Stats::default();
+            let mut written = Vec::new();
+            let events = build_events(m.frames, &settings, &mut stats, |e, b, _| {
+                written.push((e.png_file.clone(), b.data[1] == 255));
+                true
+            });
+            assert_eq!(stats.corrections, 2);
+            let events: Vec<(String, String, String)> = events
+                .into_iter()
+                .map(|e| (e.in_tc, e.out_tc, e.png_file))
+                .collect();
+            (events, written)
+        };
+        let row = |in_tc: &str, out_tc: &str, file: &str| {
+            (in_tc.to_string(), out_tc.to_string(), file.to_string())
+        };
+
+        let (events, written) = build(false);
+        assert_eq!(
+            events,
+            [
+                row("00:00:01:00", "00:00:03:00", "t00000.png"),
+                row("00:00:04:00", "00:00:05:00", "t00001.png"),
+                row("00:00:06:00", "00:00:06:15", "t00002.png"),
+                row("00:00:06:15", "00:00:07:00", "t00003.png"),
+            ]
+        );
+        // The green correction overwrites the red image of the event it replaces.
+        let green = |file: &str| (file.to_string(), true);
+        let red = |file: &str| (file.to_string(), false);
+        assert_eq!(
+            written,
+            [
+                red("t00000.png"),
+                green("t00000.png"),
+                red("t00001.png"),
+                green("t00001.png"),
+                red("t00002.png"),
+                green("t00003.png"),
+            ]
+        );
+
+        // --keep-corrections: the stale caption ends where the correction starts, unless both
+        // start on the same frame.
+        let (events, _) = build(true);
+        assert_eq!(
+            events,
+            [
+                row("00:00:01:00", "00:00:01:03", "t00000.png"),
+                row("00:00:01:03", "00:00:03:00", "t00001.png"),
+                row("00:00:04:00", "00:00:05:00", "t00002.png"),
+                row("00:00:06:00", "00:00:06:15", "t00003.png"),
+                row("00:00:06:15", "00:00:07:00", "t00004.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_caption_burst() {
+        // Twelve captions 50 ms apart (a decoder emitting one per packet), then a normal one.
+        let mut manifest: String = (0..12)
+            .map(|i| format!("frame {:.2} 0 0 0 0 1x1 /wAA/w==\n", 1.0 + i as f64 * 0.05))
+            .collect();
+        manifest.push_str("frame 3.0 0 0 0 0 1x1 AP8A/w==\nclear 4.0\n");
+        let m = parse_manifest(&manifest);
+        let settings = EventSettings {
+            fps: 30.0,
+            correction_window: 0.0,
+            max_events_per_second: 3,
+            ..settings(&m)
+        };
+        let mut stats = Stats::default();
+        let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+        let rows: Vec<(&str, &str, &str)> = events
+            .iter()
+            .map(|e| (e.in_tc.as_str(), e.out_tc.as_str(), e.png_file.as_str()))
+            .collect();
+        // The third event spans the coalesced captions up to the next one.
+        assert_eq!(
+            rows,
+            [
+                ("00:00:01:00", "00:00:01:02", "t00000.png"),
+                ("00:00:01:02", "00:00:01:03", "t00001.png"),
+                ("00:00:01:03", "00:00:03:00", "t00002.png"),
+                ("00:00:03:00", "00:00:04:00", "t00003.png"),
+            ]
+        );
+        assert_eq!(stats.coalesced, 9);
+    }
+
+    #[test]
+    fn test_display_latency_and_global_delay() {
+        // Two captions shown half a second after their packet, one at it.
+        let manifest = "frame 1.0 1.5 2.5 0 0 1x1 /wAA/w==\nframe 3.0 3.5 4.5 0 0 1x1 AP8A/w==\n\
+                        frame 6.0 0 0 0 0 1x1 /wAA/w==\nclear 7.0\n";
+        let run = |global_delay: f64| {
+            let m = parse_manifest(manifest);
+            let settings = EventSettings {
+                fps: 30.0,
+                global_delay,
+                ..settings(&m)
+            };
+            let mut stats = Stats::default();
+            let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+            let tcs: Vec<(String, String)> =
+                events.into_iter().map(|e| (e.in_tc, e.out_tc)).collect();
+            (tcs, stats)
+        };
+
+        let (tcs, stats) = run(0.0);
+        assert_eq!(
+            tcs[0],
+            ("00:00:01:15".to_string(), "00:00:02:15".to_string())
+        );
+        let latency = stats.display_latency().unwrap();
+        assert_eq!(latency.events, 3);
+        assert_eq!((latency.min, latency.median, latency.max), (0.0, 0.5, 0.5));
+        let sources: Vec<_> = stats.event_map.sources.iter().flatten().collect();
+        assert_eq!(sources[1].pts, Some(270_000));
+
+        // -500 ms: every time moves, clears included; the latency measured stays the same.
+        let (delayed, stats) = run(-0.5);
+        assert_eq!(
+            delayed,
+            [
+                ("00:00:01:00".to_string(), "00:00:02:00".to_string()),
+                ("00:00:03:00".to_string(), "00:00:04:00".to_string()),
+                ("00:00:05:15".to_string(), "00:00:06:15".to_string()),
+            ]
+        );
+        assert_eq!(stats.display_latency(), Some(latency));
+    }
+
+    #[test]
+    fn test_offset_before_zero() {
+        // A recording starting at 10 s; --offset -2 moves the first two captions before 0.
+        let manifest = "frame 10.5 10.5 11.5 0 0 1x1 /wAA/w==\n\
+                        frame 11.0 11.0 13.0 0 0 1x1 AP8A/w==\n\
+                        frame 14.0 14.0 15.0 0 0 1x1 /wAA/w==\n";
+        let m = parse_manifest(manifest);
+        let settings = EventSettings {
+            fps: 30.0,
+            base_time: 10.0,
+            offset: -2.0,
+            ..settings(&m)
+        };
+        let mut stats = Stats::default();
+        let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+        let tcs: Vec<(&str, &str, &str)> = events
+            .iter()
+            .map(|e| (e.in_tc.as_str(), e.out_tc.as_str(), e.png_file.as_str()))
+            .collect();
+        assert_eq!(
+            tcs,
+            [
+                // -1.0 s to 1.0 s: clamped to start at 0.
+                ("00:00:00:00", "00:00:01:00", "t00000.png"),
+                ("00:00:02:00", "00:00:03:00", "t00001.png"),
+            ]
+        );
+        // -1.5 s to -0.5 s: dropped.
+        let dropped = &stats.event_map.dropped;
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].reason, DropReason::BeforeZero);
+        assert_eq!(dropped[0].frame.timestamp, 10.5);
+    }
+
+    #[test]
+    fn test_offset_after_cuts() {
+        let manifest = "frame 1.0 1.0 2.0 0 0 1x1 /wAA/w==\n\
+                        frame 3.2 3.2 3.8 0 0 1x1 AP8A/w==\n\
+                        frame 5.0 5.0 6.0 0 0 1x1 /wAA/w==\n";
+        let m = parse_manifest(manifest);
+        // The cut is on the recording's timeline; --offset moves what is left after it.
+        let cuts = CutList::new(vec![(3.0, 4.0)]);
+        let settings = EventSettings {
+            fps: 30.0,
+            cut_list: Some(&cuts),
+            offset: -1.5,
+            ..settings(&m)
+        };
+        let mut stats = Stats::default();
+        let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+        let tcs: Vec<(&str, &str)> = events
+            .iter()
+            .map(|e| (e.in_tc.as_str(), e.out_tc.as_str()))
+            .collect();
+        assert_eq!(
+            tcs,
+            [
+                ("00:00:00:00", "00:00:00:15"),
+                ("00:00:02:15", "00:00:03:15"),
+            ]
+        );
+        let reasons: Vec<_> = stats.event_map.dropped.iter().map(|d| d.reason).collect();
+        assert_eq!(reasons, [DropReason::Cut]);
+    }
+
+    #[test]
+    fn test_event_map() {
+        let manifest = "frame 1.0 0 0 0 0 1x1 /wAA/w==\nframe 1.1 0 0 0 0 1x1 AP8A/w==\n\
+                        text 1.5 hello\nclear 2.0\nframe 3.0 0 0 0 0 0x0 -\n\
+                        frame 4.0 0 0 0 0 1x1 /wAA/w==\nframe 5.0 0 0 0 0 1x1 AP8A/w==\nclear 6.0\n\
+                        frame 7.0 0 0 0 0 1x1 /wAA/w==\nclear 8.0\n";
+        let m = parse_manifest(manifest);
+        let settings = EventSettings {
+            fps: 30.0,
+            ..settings(&m)
+        };
+        let mut stats = Stats::default();
+        // The image of the caption at 4 s cannot be written.
+        let mut events = build_events(m.frames, &settings, &mut stats, |e, _, _| {
+            e.in_tc != "00:00:04:00"
+        });
+        assert_eq!(events.len(), 3);
+        // A filter after decoding (--forced-heuristic) drops the caption at 5 s.
+        stats
+            .event_map
+            .retain(&[true, false, true], DropReason::ForcedHeuristic);
+        events.remove(1);
+        renumber_images(&mut events, 0, "t", ImageFormat::Png);
+        assert_eq!(
+            stats.event_map.to_json(&events, true),
+            r#"{
+  "start_index": 0,
+  "events": [
+    {"index":0,"png_file":"t00000.png","in_tc":"00:00:01:00","out_tc":"00:00:02:00","end":"clear","decode_index":1,"timestamp":1.100,"pts":99000,"latency_ms":0},
+    {"index":1,"png_file":"t00001.png","in_tc":"00:00:07:00","out_tc":"00:00:08:00","end":"clear","decode_index":8,"timestamp":7.000,"pts":630000,"latency_ms":0}
+  ],
+  "dropped": [
+    {"index":0,"reason":"corrected","timestamp":1.000},
+    {"index":2,"reason":"non_bitmap","timestamp":1.500},
+    {"index":4,"reason":"empty_bitmap","timestamp":3.000},
+    {"index":5,"reason":"write_failed","timestamp":4.000},
+    {"index":6,"reason":"forced_heuristic","timestamp":5.000}
+  ],
+  "clears": [
+    {"decode_index":3,"timestamp":2.000,"tc":"00:00:02:00"},
+    {"decode_index":7,"timestamp":6.000,"tc":"00:00:06:00"},
+    {"decode_index":9,"timestamp":8.000,"tc":"00:00:08:00"}
+  ]
+}
+"#
+        );
+        assert!(!stats.event_map.to_json(&events, false).contains("clears"));
+        // --emit-source-times: the broadcast time of each event's packet.
+        let clock = BroadcastClock {
+            stream_time: 1.0,
+            jst: 1_792_184_405,
+        };
+        stats.event_map.source_times = Some(SourceTimes { clock: Some(clock) });
+        let json = stats.event_map.to_json(&events, false);
+        let first = r#""latency_ms":0,"wall_clock":"2026-10-16T21:00:05.100+09:00"}"#;
+        assert!(json.contains(first), "{}", json);
+    }
+
+    #[test]
+    fn test_implausible_display_time() {
+        // An end display time of 0x7FFFFFFF ms; the next caption ends it instead.
+        let manifest = "frame 1.0 1.0 2147484.647 0 0 1x1 /wAA/w==\n\
+                        frame 3.0 0 0 0 0 1x1 AP8A/w==\nclear 4.0\n";
+        let run = |max_display_duration: f64| {
+            let m = parse_manifest(manifest);
+            let settings = EventSettings {
+                fps: 30.0,
+                max_display_duration,
+                ..settings(&m)
+            };
+            let mut stats = Stats::default();
+            let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+            (events[0].out_tc.clone(), stats.implausible_durations)
+        };
+        assert_eq!(
+            run(DEFAULT_MAX_DISPLAY_DURATION),
+            ("00:00:03:00".to_string(), 1)
+        );
+        // Without the bound the caption runs for 24 days.
+        assert_eq!(run(0.0), ("596:31:24:19".to_string(), 0));
+    }
+
+    #[test]
+    fn test_start_index_continues_parts() {
+        // One program converted as two parts into the same directory.
+        let parts = [
+            "frame 1.0 0 0 0 0 1x1 /wAA/w==\nframe 2.0 0 0 0 0 1x1 AP8A/w==\nclear 3.0\n",
+            "frame 1.0 0 0 0 0 1x1 AP8A/w==\nclear 2.0\nframe 4.0 0 0 0 0 1x1 /wAA/w==\n",
+        ];
+        let mut names = Vec::new();
+        for manifest in parts {
+            let m = parse_manifest(manifest);
+            let settings = EventSettings {
+                fps: 30.0,
+                start_index: names.len(),
+                ..settings(&m)
+            };
+            let mut stats = Stats::default();
+            let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+            assert_eq!(stats.event_map.start_index, settings.start_index);
+            names.extend(events.into_iter().map(|e| e.png_file));
+        }
+        assert_eq!(
+            names,
+            ["t00000.png", "t00001.png", "t00002.png", "t00003.png"]
+        );
+    }
+
+    #[test]
+    fn test_end_reasons() {
+        // Own display time; the next caption; a clear after a caption with display time; a
+        // clear; nothing (the default duration).
+        let manifest = "frame 1.0 1.0 1.5 0 0 1x1 /wAA/w==\nframe 2.0 0 0 0 0 1x1 AP8A/w==\n\
+                        frame 3.0 3.0 3.5 0 0 1x1 /wAA/w==\nclear 3.2\n\
+                        frame 4.0 0 0 0 0 1x1 AP8A/w==\nclear 4.5\n\
+                        frame 6.0 0 0 0 0 1x1 /wAA/w==\n";
+        let m = parse_manifest(manifest);
+        let settings = EventSettings {
+            fps: 30.0,
+            ..settings(&m)
+        };
+        let mut stats = Stats::default();
+        let events = build_events(m.frames, &settings, &mut stats, |_, _, _| true);
+        let ends: Vec<(&str, &str)> = events
+            .iter()
+            .zip(&stats.event_map.ends)
+            .map(|(e, end)| (e.out_tc.as_str(), end.unwrap().code()))
+            .collect();
+        assert_eq!(
+            ends,
+            [
+                ("00:00:01:15", "display_time"),
+                ("00:00:03:00", "next_caption"),
+                ("00:00:03:06", "clear"),
+                ("00:00:04:15", "clear"),
+                ("00:00:07:00", "default"),
+            ]
+        );
+        assert_eq!(stats.defaulted_durations, 1);
+        let clears = &stats.event_map.clears;
+        assert_eq!(clears.len(), 2);
+        assert_eq!(clears[0].0.index, 3);
+        assert_eq!(clears[0].1, "00:00:03:06");
+        assert_eq!(clears[1].1, "00:00:04:15");
+    }
+
+    #[test]
+    fn test_split_horizontal() {
+        // Two opaque columns with a two-column transparent gap between them.
+        let manifest = "frame 1.0 0 0 100 900 4x1 /wAA/wAAAAAAAAAA/wAA/w==\nclear 2.0\n";
+        let split = |min_gap| {
+            let m = parse_manifest(manifest);
+            let settings = EventSettings {
+                fps: 30.0,
+                split_min_gap: min_gap,
+                ..settings(&m)
+            };
+            build_events(m.frames, &settings, &mut Stats::default(), |_, _, _| true).remove(0)
+        };
+        let event = split(Some(2));
+        assert_eq!((event.png_file.as_str(), event.x, event.width), ("t00000.png", 100, 1));
+        assert_eq!(
+            event.second,
+            Some(Graphic {
+                png_file: "t00000_2.png".to_string(),
+                x: 103,
+                y: 900,
+                width: 1,
+                height: 1,
+            })
+        );
+        assert_eq!(split(Some(3)).second, None);
+        assert_eq!(split(None).width, 4);
+    }
+
+    #[test]
+    fn test_full_frame() {
+        let manifest = "frame 1.0 0 0 2 1 2x1 /wAA/wAAAAA=\nclear 2.0\n";
+        let build = |full_frame| {
+            let m = parse_manifest(manifest);
+            let settings = EventSettings {
+                fps: 30.0,
+                canvas_area: 4 * 3,
+                full_frame,
+                ..settings(&m)
+            };
+            let mut images = Vec::new();
+            let events = build_events(m.frames, &settings, &mut Stats::default(), |_, b, _| {
+                images.push(b.clone());
+                true
+            });
+            (events.into_iter().next().unwrap(), images.remove(0))
+        };
+        // Bounding mode: the Graphic is the caption's own box.
+        let (event, image) = build(None);
+        assert_eq!((event.x, event.y, event.width, event.height), (2, 1, 2, 1));
+        assert_eq!((image.width, image.height), (2, 1));
+        // Full frame: the Graphic is the canvas and the caption sits at its position.
+        let (event, image) = build(Some((4, 3)));
+        assert_eq!((event.x, event.y, event.width, event.height), (0, 0, 4, 3));
+        assert_eq!((image.width, image.height), (4, 3));
+        assert_eq!(&image.data[(4 + 2) * 4..(4 + 3) * 4], &[255, 0, 0, 255]);
+        assert_eq!(count_opaque_pixels(&image), 1);
+    }
+
+    #[test]
+    fn test_resume_matches_full_run() {
+        let manifest = "frame 1.0 0 0 0 0 2x1 /wAA/wAAAAA=\nclear 1.5\nframe 2.0 0 0 0 0 2x1 /wAA/wAAAAA=\nframe 3.0 0 0 0 0 2x1 /wAA/wAAAAA=\nclear 3.4\nframe 4.0 0 0 0 0 2x1 /wAA/wAAAAA=\n";
+        let settings = EventSettings {
+            fps: 30.0,
+            canvas_area: 2,
+            outtc_semantics: OutTcSemantics::Inclusive,
+            ..settings(&parse_manifest(manifest))
+        };
+        let frames = || parse_manifest(manifest).frames.into_iter();
+        let mut checkpoints = Vec::new();
+        let full = build_events_from(
+            Vec::new(),
+            frames(),
+            &settings,
+            &mut Stats::default(),
+            |_, _, _| true,
+            |settled, resume_at| checkpoints.push((settled.to_vec(), resume_at)),
+        );
+        assert_eq!(full.len(), 4);
+        assert_eq!(checkpoints.len(), 4);
+        assert_eq!(checkpoints[2].0.len(), 2);
+        assert_eq!(checkpoints[2].1, 3.0);
+        // Resuming from any checkpoint with the frames from its timestamp on gives the same events.
+        for (settled, resume_at) in checkpoints {
+            let rest = frames().filter(|f| f.timestamp >= resume_at);
+            let resumed = build_events_from(
+                settled,
+                rest,
+                &settings,
+                &mut Stats::default(),
+                |_, _, _| true,
+                |_, _| {},
+            );
+            assert_eq!(resumed, full);
+        }
+    }
+
+    #[test]
+    fn test_build_text_events() {
+        let mut m = parse_manifest(
+            "text 1.0 Hello\ntext 2.5 World\nclear 4.0\ntext 5.0 Last\nclear 5.2\ntext 9.0 Tail\n",
+        );
+        m.frames[1].y = 540;
+        let settings = EventSettings {
+            fps: 30.0,
+            default_duration: DefaultDuration::Fixed(2.0),
+            ..settings(&m)
+        };
+        let events = build_text_events(m.frames, &settings, 1080);
+        let windows: Vec<(&str, &str, &str, Option<f64>)> = events
+            .iter()
+            .map(|e| (e.in_tc.as_str(), e.out_tc.as_str(), e.text.as_str(), e.y))
+            .collect();
+        assert_eq!(
+            windows,
+            [
+                ("00:00:01:00", "00:00:02:15", "Hello", None),
+                ("00:00:02:15", "00:00:04:00", "World", Some(0.5)),
+                ("00:00:05:00", "00:00:05:06", "Last", None),
+                ("00:00:09:00", "00:00:11:00", "Tail", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+        let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+        let mut manifests: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "frames"))
+            .collect();
+        manifests.sort();
+        assert!(!manifests.is_empty(), "no manifests in {}", dir.display());
+
+        let mut failures = Vec::new();
+        for manifest in manifests {
+            let actual = render(parse_manifest(&std::fs::read_to_string(&manifest).unwrap()));
+            let golden = manifest.with_extension("xml");
+            if update {
+                std::fs::write(&golden, &actual).unwrap();
+                continue;
+            }
+            let expected = std::fs::read_to_string(&golden).unwrap_or_default();
+            if actual != expected {
+                failures.push(format!("{}:\n{}", golden.display(), actual));
+            }
+        }
+        assert!(
+            failures.is_empty(),
+            "snapshot mismatch (rerun with UPDATE_SNAPSHOTS=1 if intended):\n{}",
+            failures.join("\n")
+        );
+    }
+}
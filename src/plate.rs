@@ -0,0 +1,221 @@
+//! --trim-background-plate: with ignore_background=0 some services get a caption rect covering
+//! the whole canvas, filled with a translucent background even around a single line of text.
+//! This finds that plate, clears it away from the text and crops to what is left.
+
+use std::collections::HashMap;
+
+use crate::bitmap::{BitmapData, RgbaImage};
+
+/// Share of all pixels the plate color must cover.
+pub const PLATE_MIN_COVERAGE: f64 = 0.5;
+
+/// Share of the edge pixels the plate color must cover: a plate runs to the edges of the rect.
+pub const PLATE_MIN_EDGE_COVERAGE: f64 = 0.9;
+
+/// Plate pixels kept around the text, so captions keep their own background box.
+pub const PLATE_KEEP_MARGIN: usize = 6;
+
+/// A caption bitmap with its background plate removed.
+#[derive(Debug, Clone)]
+pub struct TrimmedPlate {
+    pub bitmap: BitmapData,
+    /// Position of `bitmap` in the original one.
+    pub x: i32,
+    pub y: i32,
+    /// The plate color, premultiplied like the bitmap.
+    pub color: [u8; 4],
+}
+
+fn pixels(bitmap: &BitmapData) -> impl Iterator<Item = [u8; 4]> + '_ {
+    let row = bitmap.width.max(0) as usize * 4;
+    (0..bitmap.height.max(0) as usize).flat_map(move |y| {
+        let start = y * bitmap.stride as usize;
+        bitmap.data[start..start + row]
+            .chunks_exact(4)
+            .map(|px| [px[0], px[1], px[2], px[3]])
+    })
+}
+
+/// Color of the background plate filling `bitmap`, if it has one. Conservative: None unless one
+/// non-transparent color covers `PLATE_MIN_COVERAGE` of the pixels and `PLATE_MIN_EDGE_COVERAGE`
+/// of the edges, and something other than it (the text) is left.
+pub fn detect_plate(bitmap: &BitmapData) -> Option<[u8; 4]> {
+    let (w, h) = (bitmap.width.max(0) as usize, bitmap.height.max(0) as usize);
+    if w == 0 || h == 0 {
+        return None;
+    }
+    let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+    for px in pixels(bitmap) {
+        *counts.entry(px).or_default() += 1;
+    }
+    let (&color, &count) = counts.iter().max_by_key(|&(color, &n)| (n, *color))?;
+    let total = w * h;
+    if color[3] == 0 || count == total || (count as f64) < PLATE_MIN_COVERAGE * total as f64 {
+        return None;
+    }
+    let at = |x: usize, y: usize| {
+        let i = y * bitmap.stride as usize + x * 4;
+        bitmap.data[i..i + 4] == color
+    };
+    let edges: Vec<bool> = (0..w)
+        .flat_map(|x| [at(x, 0), at(x, h - 1)])
+        .chain((0..h).flat_map(|y| [at(0, y), at(w - 1, y)]))
+        .collect();
+    let on_plate = edges.iter().filter(|&&p| p).count();
+    (on_plate as f64 >= PLATE_MIN_EDGE_COVERAGE * edges.len() as f64).then_some(color)
+}
+
+/// `mask` (`w` x `h`) grown by `margin` pixels in every direction.
+fn dilate(mask: &[bool], w: usize, h: usize, margin: usize) -> Vec<bool> {
+    // Any set value within `margin` of each position, along one line of `len` values.
+    let grow = |len: usize, get: &dyn Fn(usize) -> bool| -> Vec<bool> {
+        let mut prefix = vec![0usize; len + 1];
+        for i in 0..len {
+            prefix[i + 1] = prefix[i] + get(i) as usize;
+        }
+        (0..len)
+            .map(|i| prefix[(i + margin + 1).min(len)] > prefix[i.saturating_sub(margin)])
+            .collect()
+    };
+    let mut rows = vec![false; w * h];
+    for y in 0..h {
+        rows[y * w..(y + 1) * w].copy_from_slice(&grow(w, &|x| mask[y * w + x]));
+    }
+    let mut out = vec![false; w * h];
+    for x in 0..w {
+        for (y, set) in grow(h, &|y| rows[y * w + x]).into_iter().enumerate() {
+            out[y * w + x] = set;
+        }
+    }
+    out
+}
+
+/// `bitmap` with its background plate (`detect_plate`) made transparent except within
+/// `PLATE_KEEP_MARGIN` of the text, cropped to what is left. None leaves the frame as it is.
+pub fn trim_background_plate(bitmap: &BitmapData) -> Option<TrimmedPlate> {
+    let color = detect_plate(bitmap)?;
+    let (w, h) = (bitmap.width as usize, bitmap.height as usize);
+    let mut data: Vec<u8> = pixels(bitmap).flatten().collect();
+    let text: Vec<bool> = data
+        .chunks_exact(4)
+        .map(|px| px[3] > 0 && px != color)
+        .collect();
+    let keep = dilate(&text, w, h, PLATE_KEEP_MARGIN);
+    for (px, keep) in data.chunks_exact_mut(4).zip(keep) {
+        if !keep && *px == color {
+            px.fill(0);
+        }
+    }
+    let image = RgbaImage {
+        data,
+        width: w as u32,
+        height: h as u32,
+    };
+    let (x, y, width, height) = image.content_bounds()?;
+    let cropped = image.crop(x, y, width, height);
+    Some(TrimmedPlate {
+        bitmap: BitmapData {
+            data: cropped.data,
+            width: width as i32,
+            height: height as i32,
+            stride: width as i32 * 4,
+        },
+        x: x as i32,
+        y: y as i32,
+        color,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::count_opaque_pixels;
+
+    const PLATE: [u8; 4] = [0, 0, 0, 128];
+    const TEXT: [u8; 4] = [255, 255, 255, 255];
+
+    /// A `w` x `h` bitmap of `fill`, with `TEXT` in each of `boxes` (x, y, width, height).
+    /// Rows are padded to show the stride is honored.
+    fn bitmap(w: i32, h: i32, fill: [u8; 4], boxes: &[(i32, i32, i32, i32)]) -> BitmapData {
+        let stride = w * 4 + 8;
+        let mut data = vec![7u8; (stride * h) as usize];
+        for y in 0..h {
+            for x in 0..w {
+                let inside = boxes.iter().any(|&(bx, by, bw, bh)| {
+                    (bx..bx + bw).contains(&x) && (by..by + bh).contains(&y)
+                });
+                let i = (y * stride + x * 4) as usize;
+                data[i..i + 4].copy_from_slice(if inside { &TEXT } else { &fill });
+            }
+        }
+        BitmapData {
+            data,
+            width: w,
+            height: h,
+            stride,
+        }
+    }
+
+    #[test]
+    fn test_trim_full_plate() {
+        // One line of text on a plate covering the whole canvas.
+        let plate = bitmap(240, 135, PLATE, &[(50, 100, 40, 10)]);
+        let trimmed = trim_background_plate(&plate).unwrap();
+        assert_eq!(trimmed.color, PLATE);
+        let margin = PLATE_KEEP_MARGIN as i32;
+        assert_eq!((trimmed.x, trimmed.y), (50 - margin, 100 - margin));
+        let (w, h) = (40 + 2 * margin, 10 + 2 * margin);
+        assert_eq!((trimmed.bitmap.width, trimmed.bitmap.height), (w, h));
+        // The text keeps its background box: every pixel left is text or plate.
+        assert_eq!(count_opaque_pixels(&trimmed.bitmap), (w * h) as usize);
+        let corner = &trimmed.bitmap.data[..4];
+        assert_eq!(corner, PLATE);
+        let text = ((margin * w + margin) * 4) as usize;
+        assert_eq!(&trimmed.bitmap.data[text..text + 4], TEXT);
+
+        // Two lines far apart: the plate between them goes, the crop spans both.
+        let plate = bitmap(240, 135, PLATE, &[(20, 10, 30, 8), (150, 110, 60, 8)]);
+        let trimmed = trim_background_plate(&plate).unwrap();
+        assert_eq!((trimmed.x, trimmed.y), (20 - margin, 10 - margin));
+        let between = ((40 * trimmed.bitmap.stride + 100 * 4) as usize) + 3;
+        assert_eq!(trimmed.bitmap.data[between], 0);
+    }
+
+    #[test]
+    fn test_low_confidence_is_left_alone() {
+        // A usual caption: transparent around the text.
+        let clear = bitmap(120, 40, [0; 4], &[(10, 10, 50, 10)]);
+        assert_eq!(detect_plate(&clear), None);
+        // A background box around the text only, not running to the edges.
+        let mut boxed = bitmap(120, 40, [0; 4], &[(10, 10, 50, 10)]);
+        for y in 2..38 {
+            for x in 2..118 {
+                let i = (y * boxed.stride + x * 4) as usize;
+                if boxed.data[i + 3] == 0 {
+                    boxed.data[i..i + 4].copy_from_slice(&PLATE);
+                }
+            }
+        }
+        assert_eq!(detect_plate(&boxed), None);
+        // Mostly text: no color covers enough of the rect.
+        let busy = bitmap(40, 20, PLATE, &[(0, 0, 40, 12)]);
+        assert_eq!(detect_plate(&busy), None);
+        // A plate with nothing on it.
+        assert!(trim_background_plate(&bitmap(40, 20, PLATE, &[])).is_none());
+        // A gradient has no dominant color.
+        let mut gradient = bitmap(64, 16, PLATE, &[]);
+        for (i, px) in gradient.data.chunks_exact_mut(4).enumerate() {
+            px.copy_from_slice(&[i as u8, 0, 0, 200]);
+        }
+        assert!(trim_background_plate(&gradient).is_none());
+    }
+
+    #[test]
+    fn test_dilate() {
+        let mut mask = vec![false; 7 * 5];
+        mask[2 * 7 + 3] = true;
+        let grown = dilate(&mask, 7, 5, 1);
+        let set: Vec<usize> = (0..mask.len()).filter(|&i| grown[i]).collect();
+        assert_eq!(set, [9, 10, 11, 16, 17, 18, 23, 24, 25]);
+    }
+}
@@ -0,0 +1,296 @@
+//! --png-optimize: encodes each caption PNG with several row filters, as it is and as a palette
+//! image when that is lossless, and keeps the smallest.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::bitmap::{straight_rgba_image, write_png_bytes, BitmapData, PngPixels, RgbaImage};
+
+/// Time one image's trials may take. A trial is not interrupted, so the budget is checked before
+/// each one and the smallest result so far is kept when it runs out.
+pub const PNG_OPTIMIZE_BUDGET: Duration = Duration::from_millis(250);
+
+/// One encoding tried by --png-optimize (at the maximum deflate level).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Strategy {
+    pub filter: png::FilterType,
+    /// Indexed colors instead of RGBA (or grayscale + alpha).
+    pub palette: bool,
+}
+
+const fn strategy(filter: png::FilterType, palette: bool) -> Strategy {
+    Strategy { filter, palette }
+}
+
+/// Encodings tried after the default one, in order. Palette images come first: sparse captions
+/// with few colors usually end up smallest that way.
+pub const STRATEGIES: [Strategy; 8] = [
+    strategy(png::FilterType::NoFilter, true),
+    strategy(png::FilterType::Sub, true),
+    strategy(png::FilterType::Up, true),
+    strategy(png::FilterType::Paeth, true),
+    strategy(png::FilterType::NoFilter, false),
+    strategy(png::FilterType::Sub, false),
+    strategy(png::FilterType::Up, false),
+    strategy(png::FilterType::Paeth, false),
+];
+
+/// The smallest encoding found for one image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizedPng {
+    pub png: Vec<u8>,
+    /// Size of the default encoding.
+    pub baseline: u64,
+    /// Encoding of `png`; None when the default one stayed smallest.
+    pub strategy: Option<Strategy>,
+    /// The budget ran out before every strategy was tried.
+    pub timed_out: bool,
+}
+
+impl OptimizedPng {
+    /// Bytes saved over the default encoding.
+    pub fn saved(&self) -> u64 {
+        self.baseline - self.png.len() as u64
+    }
+}
+
+/// Packs 8-bit indices into rows of `bits`-bit samples, most significant first.
+fn pack_indices(indices: &[u8], width: usize, bits: usize) -> Vec<u8> {
+    if bits == 8 {
+        return indices.to_vec();
+    }
+    let row_bytes = (width * bits).div_ceil(8);
+    let mut out = Vec::with_capacity(row_bytes * indices.len() / width.max(1));
+    for row in indices.chunks(width.max(1)) {
+        let mut packed = vec![0u8; row_bytes];
+        for (x, &index) in row.iter().enumerate() {
+            let bit = x * bits;
+            packed[bit / 8] |= index << (8 - bits - bit % 8);
+        }
+        out.extend_from_slice(&packed);
+    }
+    out
+}
+
+/// The image as indexed pixels at the smallest bit depth, when it has at most 256 colors.
+/// Translucent entries come first so tRNS can leave out the opaque ones.
+pub fn palette_pixels(image: &RgbaImage) -> Option<PngPixels> {
+    let mut colors: Vec<[u8; 4]> = Vec::new();
+    let mut lookup: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut indices = Vec::with_capacity(image.data.len() / 4);
+    for px in image.data.chunks_exact(4) {
+        let px = [px[0], px[1], px[2], px[3]];
+        let index = match lookup.get(&px) {
+            Some(&index) => index,
+            None if colors.len() == 256 => return None,
+            None => {
+                let index = colors.len() as u8;
+                colors.push(px);
+                lookup.insert(px, index);
+                index
+            }
+        };
+        indices.push(index);
+    }
+
+    let mut order: Vec<usize> = (0..colors.len()).collect();
+    order.sort_by_key(|&i| colors[i][3] == 255);
+    let mut remap = vec![0u8; colors.len()];
+    for (new, &old) in order.iter().enumerate() {
+        remap[old] = new as u8;
+    }
+    for index in &mut indices {
+        *index = remap[*index as usize];
+    }
+    let colors: Vec<[u8; 4]> = order.iter().map(|&i| colors[i]).collect();
+
+    let (depth, bits) = match colors.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    let plte = colors.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+    let trns = colors
+        .iter()
+        .map(|c| c[3])
+        .take_while(|&a| a < 255)
+        .collect();
+    Some(PngPixels {
+        data: pack_indices(&indices, image.width as usize, bits),
+        width: image.width,
+        height: image.height,
+        color: png::ColorType::Indexed,
+        depth,
+        palette: Some((plte, trns)),
+    })
+}
+
+/// Encodes `image` the default way (RGBA, or grayscale + alpha with `auto_gray`), then with each
+/// of `STRATEGIES` while `budget` lasts, and returns the smallest result.
+pub fn optimize_png(
+    image: &RgbaImage,
+    auto_gray: bool,
+    budget: Duration,
+) -> anyhow::Result<OptimizedPng> {
+    let started = Instant::now();
+    let direct = PngPixels::of(image, auto_gray);
+    let mut best = OptimizedPng {
+        png: direct.encode(|_| {})?,
+        baseline: 0,
+        strategy: None,
+        timed_out: false,
+    };
+    best.baseline = best.png.len() as u64;
+    let palette = palette_pixels(image);
+    for candidate in STRATEGIES {
+        let pixels = match (candidate.palette, &palette) {
+            (false, _) => &direct,
+            (true, Some(palette)) => palette,
+            (true, None) => continue,
+        };
+        if started.elapsed() >= budget {
+            best.timed_out = true;
+            break;
+        }
+        let png = pixels.encode(|encoder| {
+            encoder.set_compression(png::Compression::Best);
+            encoder.set_filter(candidate.filter);
+            encoder.set_adaptive_filter(png::AdaptiveFilterType::NonAdaptive);
+        })?;
+        if png.len() < best.png.len() {
+            best.png = png;
+            best.strategy = Some(candidate);
+        }
+    }
+    Ok(best)
+}
+
+/// Writes `bitmap` to `path` as the smallest PNG `optimize_png` finds.
+pub fn save_bitmap_optimized(
+    bitmap: &BitmapData,
+    path: &Path,
+    auto_gray: bool,
+    budget: Duration,
+) -> anyhow::Result<OptimizedPng> {
+    let optimized = optimize_png(&straight_rgba_image(bitmap)?, auto_gray, budget)?;
+    write_png_bytes(&optimized.png, path)?;
+    Ok(optimized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(png: &[u8]) -> Vec<u8> {
+        let mut decoder = png::Decoder::new(png);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        buf.truncate(info.buffer_size());
+        match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            other => panic!("unexpected color type {:?}", other),
+        }
+    }
+
+    /// A caption-like image: transparent, with a band of `colors` colors (the last translucent).
+    fn caption(width: u32, height: u32, colors: usize) -> RgbaImage {
+        let mut data = vec![0u8; (width * height * 4) as usize];
+        for y in height / 3..height * 2 / 3 {
+            for x in 2..width - 2 {
+                let c = (x as usize * 7 + y as usize) % colors;
+                let alpha = if c + 1 == colors { 128 } else { 255 };
+                let i = ((y * width + x) * 4) as usize;
+                data[i..i + 4].copy_from_slice(&[c as u8, (c >> 8) as u8, 40, alpha]);
+            }
+        }
+        RgbaImage {
+            data,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_palette_pixels_round_trip() {
+        // 1, 2, 4 and 8-bit palettes, with widths that do not fill the last byte of a row.
+        for (colors, depth) in [
+            (1, png::BitDepth::One),
+            (3, png::BitDepth::Two),
+            (12, png::BitDepth::Four),
+            (200, png::BitDepth::Eight),
+        ] {
+            let image = caption(37, 11, colors);
+            let pixels = palette_pixels(&image).unwrap();
+            assert_eq!(pixels.depth, depth, "{} colors", colors);
+            assert_eq!(decode(&pixels.encode(|_| {}).unwrap()), image.data);
+        }
+        let (_, trns) = palette_pixels(&caption(37, 11, 3))
+            .unwrap()
+            .palette
+            .unwrap();
+        // Transparent and translucent entries only.
+        assert_eq!(trns, [0, 128]);
+        assert_eq!(palette_pixels(&caption(300, 4, 257)), None);
+    }
+
+    #[test]
+    fn test_every_strategy_is_lossless() {
+        let image = caption(64, 24, 5);
+        let palette = palette_pixels(&image).unwrap();
+        for candidate in STRATEGIES {
+            let pixels = match candidate.palette {
+                true => palette.clone(),
+                false => PngPixels::of(&image, false),
+            };
+            let png = pixels
+                .encode(|encoder| encoder.set_filter(candidate.filter))
+                .unwrap();
+            assert_eq!(decode(&png), image.data, "{:?}", candidate);
+        }
+    }
+
+    #[test]
+    fn test_optimize_png() {
+        let image = caption(200, 60, 4);
+        let optimized = optimize_png(&image, false, Duration::from_secs(60)).unwrap();
+        assert!(!optimized.timed_out);
+        assert!(optimized.strategy.is_some_and(|s| s.palette));
+        assert!(optimized.saved() > 0);
+        assert_eq!(decode(&optimized.png), image.data);
+
+        // A gray caption keeps its pixels through grayscale + alpha too.
+        let gray = RgbaImage {
+            data: image
+                .data
+                .chunks_exact(4)
+                .flat_map(|p| [p[1], p[1], p[1], p[3]])
+                .collect(),
+            ..image
+        };
+        let optimized = optimize_png(&gray, true, Duration::from_secs(60)).unwrap();
+        assert_eq!(decode(&optimized.png), gray.data);
+    }
+
+    #[test]
+    fn test_optimize_png_budget() {
+        // No time for trials: the default encoding is kept.
+        let image = caption(40, 20, 3);
+        let optimized = optimize_png(&image, false, Duration::ZERO).unwrap();
+        assert!(optimized.timed_out);
+        assert_eq!(optimized.strategy, None);
+        assert_eq!(optimized.saved(), 0);
+        assert_eq!(decode(&optimized.png), image.data);
+    }
+}
@@ -0,0 +1,343 @@
+//! On-screen position classes for events (top/middle/bottom, left/center/right) and recurring
+//! caption regions.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bdn::SubtitleEvent;
+
+/// Vertical position class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VPos {
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// Horizontal position class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HPos {
+    Left,
+    Center,
+    Right,
+}
+
+impl fmt::Display for VPos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VPos::Top => "top",
+            VPos::Middle => "middle",
+            VPos::Bottom => "bottom",
+        })
+    }
+}
+
+impl fmt::Display for HPos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HPos::Left => "left",
+            HPos::Center => "center",
+            HPos::Right => "right",
+        })
+    }
+}
+
+/// Boundaries between classes as fractions of the canvas, applied to the bitmap's center:
+/// center < first is top/left, center >= second is bottom/right, otherwise middle/center.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionThresholds {
+    pub vertical: (f64, f64),
+    pub horizontal: (f64, f64),
+}
+
+impl Default for PositionThresholds {
+    fn default() -> Self {
+        Self {
+            vertical: (1.0 / 3.0, 2.0 / 3.0),
+            horizontal: (1.0 / 3.0, 2.0 / 3.0),
+        }
+    }
+}
+
+/// Parses --position-thresholds: `V1,V2` (vertical only) or `V1,V2,H1,H2`, each in 0.0–1.0 with
+/// the first of a pair below the second.
+pub fn parse_position_thresholds(s: &str) -> Result<PositionThresholds, String> {
+    let values = s
+        .split(',')
+        .map(|v| {
+            v.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|x| (0.0..=1.0).contains(x))
+                .ok_or_else(|| format!("expected a fraction between 0 and 1, got '{}'", v.trim()))
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    let pair = |a: f64, b: f64| {
+        if a < b {
+            Ok((a, b))
+        } else {
+            Err(format!("threshold {} must be below {}", a, b))
+        }
+    };
+    let mut t = PositionThresholds::default();
+    match values[..] {
+        [v1, v2] => t.vertical = pair(v1, v2)?,
+        [v1, v2, h1, h2] => {
+            t.vertical = pair(v1, v2)?;
+            t.horizontal = pair(h1, h2)?;
+        }
+        _ => return Err("expected V1,V2 or V1,V2,H1,H2".to_string()),
+    }
+    Ok(t)
+}
+
+/// Position of one event: classes plus geometry normalized to the canvas (0.0–1.0).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub v: VPos,
+    pub h: HPos,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+fn normalize(value: i32, extent: i32) -> f64 {
+    if extent > 0 {
+        (value as f64 / extent as f64).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Classifies a bitmap placed at (x, y) with the given size on a canvas.
+pub fn classify(
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    canvas_width: i32,
+    canvas_height: i32,
+    thresholds: &PositionThresholds,
+) -> Position {
+    let (nx, ny) = (normalize(x, canvas_width), normalize(y, canvas_height));
+    let nw = normalize(width, canvas_width).min(1.0 - nx);
+    let nh = normalize(height, canvas_height).min(1.0 - ny);
+    let (cx, cy) = (nx + nw / 2.0, ny + nh / 2.0);
+    let v = if cy < thresholds.vertical.0 {
+        VPos::Top
+    } else if cy >= thresholds.vertical.1 {
+        VPos::Bottom
+    } else {
+        VPos::Middle
+    };
+    let h = if cx < thresholds.horizontal.0 {
+        HPos::Left
+    } else if cx >= thresholds.horizontal.1 {
+        HPos::Right
+    } else {
+        HPos::Center
+    };
+    Position {
+        v,
+        h,
+        x: nx,
+        y: ny,
+        width: nw,
+        height: nh,
+    }
+}
+
+/// Origins within this fraction of the canvas (on both axes) of a region's center join it.
+const REGION_RADIUS: f64 = 0.05;
+
+/// Groups bitmap origins into recurring regions over the whole run and returns the region id
+/// of each origin. Frequent origins seed regions first; ids are then numbered top to bottom,
+/// left to right, so they are stable for a given input.
+pub fn assign_regions(origins: &[(i32, i32)], canvas_width: i32, canvas_height: i32) -> Vec<usize> {
+    let scale = |(x, y): (i32, i32)| {
+        (
+            x as f64 / canvas_width.max(1) as f64,
+            y as f64 / canvas_height.max(1) as f64,
+        )
+    };
+    let mut counts: HashMap<(i32, i32), usize> = HashMap::new();
+    for &origin in origins {
+        *counts.entry(origin).or_default() += 1;
+    }
+    let mut distinct: Vec<((i32, i32), usize)> = counts.into_iter().collect();
+    distinct.sort_by_key(|&((x, y), n)| (std::cmp::Reverse(n), y, x));
+
+    // Weighted sums (x, y, count) per region.
+    let mut regions: Vec<(f64, f64, usize)> = Vec::new();
+    let center = |r: &(f64, f64, usize)| (r.0 / r.2 as f64, r.1 / r.2 as f64);
+    let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+    for (origin, n) in distinct {
+        let p = scale(origin);
+        let near = regions
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (i, center(r)))
+            .filter(|(_, c)| {
+                (p.0 - c.0).abs() <= REGION_RADIUS && (p.1 - c.1).abs() <= REGION_RADIUS
+            })
+            .min_by(|a, b| distance(p, a.1).total_cmp(&distance(p, b.1)));
+        match near {
+            Some((i, _)) => {
+                let r = &mut regions[i];
+                r.0 += p.0 * n as f64;
+                r.1 += p.1 * n as f64;
+                r.2 += n;
+            }
+            None => regions.push((p.0 * n as f64, p.1 * n as f64, n)),
+        }
+    }
+    let mut centers: Vec<(f64, f64)> = regions.iter().map(center).collect();
+    centers.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.total_cmp(&b.0)));
+
+    origins
+        .iter()
+        .map(|&origin| {
+            let p = scale(origin);
+            (0..centers.len())
+                .min_by(|&a, &b| distance(p, centers[a]).total_cmp(&distance(p, centers[b])))
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+/// `{png} {v} {h} {x} {y} {width} {height} r{region}` per event, one per line (--positions),
+/// with the geometry normalized to the canvas and the region from `assign_regions`.
+pub fn render_positions(
+    events: &[SubtitleEvent],
+    canvas_width: i32,
+    canvas_height: i32,
+    thresholds: &PositionThresholds,
+) -> String {
+    let origins: Vec<(i32, i32)> = events.iter().map(|e| (e.x, e.y)).collect();
+    let regions = assign_regions(&origins, canvas_width, canvas_height);
+    events
+        .iter()
+        .zip(regions)
+        .map(|(e, region)| {
+            let p = classify(e.x, e.y, e.width, e.height, canvas_width, canvas_height, thresholds);
+            format!(
+                "{} {} {} {:.4} {:.4} {:.4} {:.4} r{}\n",
+                e.png_file, p.v, p.h, p.x, p.y, p.width, p.height, region
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(x: i32, y: i32, w: i32, h: i32) -> (VPos, HPos) {
+        let p = classify(x, y, w, h, 1920, 1080, &PositionThresholds::default());
+        (p.v, p.h)
+    }
+
+    #[test]
+    fn test_classify_grid() {
+        // 200x100 boxes centered in each ninth of a 1920x1080 canvas.
+        let rows = [(180, VPos::Top), (540, VPos::Middle), (900, VPos::Bottom)];
+        let cols = [(320, HPos::Left), (960, HPos::Center), (1600, HPos::Right)];
+        for (cy, v) in rows {
+            for (cx, h) in cols {
+                assert_eq!(class(cx - 100, cy - 50, 200, 100), (v, h), "center {},{}", cx, cy);
+            }
+        }
+    }
+
+    #[test]
+    fn test_classify_spanning_captions() {
+        // Full-width dialogue line at the bottom.
+        assert_eq!(class(0, 900, 1920, 120), (VPos::Bottom, HPos::Center));
+        // Nearly full-screen caption.
+        assert_eq!(class(40, 40, 1840, 1000), (VPos::Middle, HPos::Center));
+        // Vertical text down the right edge.
+        assert_eq!(class(1760, 60, 120, 960), (VPos::Middle, HPos::Right));
+        // Overhanging the canvas: clamped before classification.
+        let p = classify(1800, 1000, 400, 200, 1920, 1080, &PositionThresholds::default());
+        assert_eq!((p.v, p.h), (VPos::Bottom, HPos::Right));
+        assert!(p.x + p.width <= 1.0 + 1e-9 && p.y + p.height <= 1.0 + 1e-9);
+    }
+
+    #[test]
+    fn test_classify_normalized_and_degenerate() {
+        let p = classify(480, 270, 960, 540, 1920, 1080, &PositionThresholds::default());
+        assert_eq!((p.x, p.y, p.width, p.height), (0.25, 0.25, 0.5, 0.5));
+        let p = classify(10, 10, 10, 10, 0, 0, &PositionThresholds::default());
+        assert_eq!((p.v, p.h, p.x, p.width), (VPos::Top, HPos::Left, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_custom_thresholds() {
+        // Only the bottom 20% counts as bottom.
+        let t = parse_position_thresholds("0.2,0.8").unwrap();
+        assert_eq!(t.horizontal, PositionThresholds::default().horizontal);
+        let p = classify(0, 700, 1920, 100, 1920, 1080, &t);
+        assert_eq!(p.v, VPos::Middle);
+        let p = classify(0, 900, 1920, 100, 1920, 1080, &t);
+        assert_eq!(p.v, VPos::Bottom);
+    }
+
+    #[test]
+    fn test_parse_position_thresholds() {
+        let t = parse_position_thresholds("0.25, 0.75, 0.1, 0.9").unwrap();
+        assert_eq!(t.vertical, (0.25, 0.75));
+        assert_eq!(t.horizontal, (0.1, 0.9));
+        assert!(parse_position_thresholds("0.5").is_err());
+        assert!(parse_position_thresholds("0.7,0.3").is_err());
+        assert!(parse_position_thresholds("0.2,1.5").is_err());
+        assert!(parse_position_thresholds("a,b").is_err());
+    }
+
+    #[test]
+    fn test_render_positions() {
+        let event = SubtitleEvent {
+            in_tc: "00:00:01:00".to_string(),
+            out_tc: "00:00:02:00".to_string(),
+            png_file: "ep0100000.png".to_string(),
+            x: 480,
+            y: 810,
+            width: 960,
+            height: 135,
+            forced: false,
+            second: None,
+        };
+        assert_eq!(
+            render_positions(&[event], 1920, 1080, &PositionThresholds::default()),
+            "ep0100000.png bottom center 0.2500 0.7500 0.5000 0.1250 r0\n"
+        );
+    }
+
+    #[test]
+    fn test_assign_regions() {
+        // Bottom dialogue with jittered origins, a top caption and vertical text on the right.
+        let origins = [
+            (480, 900),
+            (484, 904),
+            (1600, 60),
+            (476, 896),
+            (300, 40),
+            (480, 900),
+            (1604, 64),
+        ];
+        let regions = assign_regions(&origins, 1920, 1080);
+        assert_eq!(regions, [2, 2, 1, 2, 0, 2, 1]);
+        // Same input, same ids, whatever the order of first appearance.
+        let mut reversed = origins;
+        reversed.reverse();
+        let mut expected = regions.clone();
+        expected.reverse();
+        assert_eq!(assign_regions(&reversed, 1920, 1080), expected);
+        assert_eq!(assign_regions(&[], 1920, 1080), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_assign_regions_degenerate_canvas() {
+        assert_eq!(assign_regions(&[(0, 0), (5, 5)], 0, 0), [0, 1]);
+    }
+}
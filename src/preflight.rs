@@ -0,0 +1,227 @@
+//! Checks, before decoding, that each output directory takes the files a run writes: that files
+//! can be created there, that the names the run uses are allowed, and that a file renamed onto
+//! another replaces it, as the workspace moves outputs into place. Filesystems such as exFAT
+//! refuse some of these, which would otherwise fail a run halfway with a bare I/O error.
+
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+/// Prefix of the probe files; they are removed again right away.
+const PROBE_PREFIX: &str = ".arib2bdnxml-probe-";
+
+/// The file operations the probe needs, so tests can stand in for a filesystem.
+pub trait ProbeFs {
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove(&self, path: &Path) -> io::Result<()>;
+}
+
+/// The real filesystem.
+pub struct RealFs;
+
+impl ProbeFs for RealFs {
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Something an output directory does not allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Limitation {
+    /// No file can be created there.
+    NotWritable(String),
+    /// A file name the run writes is refused.
+    NameRejected { name: String, reason: String },
+    /// Renaming a file onto another fails or keeps the old one; outputs are then copied into
+    /// place, and an interrupted run can leave a partial file.
+    NoReplacingRename,
+}
+
+impl Limitation {
+    /// Whether the run cannot write its outputs at all.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, Limitation::NoReplacingRename)
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Limitation::NotWritable(reason) => format!("files cannot be created: {}", reason),
+            Limitation::NameRejected { name, reason } => {
+                format!("the file name {} is refused: {}", name, reason)
+            }
+            Limitation::NoReplacingRename => "renaming a file onto an existing one does not replace it; outputs are copied into place instead".to_string(),
+        }
+    }
+}
+
+/// What an error creating a file says about the filesystem.
+pub fn explain(e: &io::Error) -> String {
+    let limitation = match e.kind() {
+        ErrorKind::PermissionDenied => "no write permission",
+        ErrorKind::ReadOnlyFilesystem => "the filesystem is read-only",
+        ErrorKind::StorageFull | ErrorKind::QuotaExceeded => "no space left",
+        ErrorKind::NotFound => "the directory does not exist",
+        // Linux exFAT/FAT drivers answer EINVAL, Windows ERROR_INVALID_NAME.
+        ErrorKind::InvalidFilename | ErrorKind::InvalidInput => {
+            "too long, or has characters this filesystem does not allow (exFAT and FAT refuse \\ : * ? \" < > |)"
+        }
+        _ => return e.to_string(),
+    };
+    format!("{} ({})", limitation, e)
+}
+
+/// Probes `dir` with the file `names` a run writes there and returns what it does not allow.
+/// Stops at the first check when no file can be created at all.
+pub fn probe_dir(fs: &dyn ProbeFs, dir: &Path, names: &[String]) -> Vec<Limitation> {
+    let probe =
+        |suffix: &str| dir.join(format!("{}{}-{}", PROBE_PREFIX, std::process::id(), suffix));
+    let (first, second) = (probe("a"), probe("b"));
+    if let Err(e) = fs.write(&first, b"a") {
+        return vec![Limitation::NotWritable(explain(&e))];
+    }
+    let mut found = Vec::new();
+    for name in names {
+        // The name itself might be an earlier output, so a prefixed copy is created.
+        let path = dir.join(format!("{}{}", PROBE_PREFIX, name));
+        match fs.write(&path, b"") {
+            Ok(()) => {
+                let _ = fs.remove(&path);
+            }
+            Err(e) => found.push(Limitation::NameRejected {
+                name: name.clone(),
+                reason: explain(&e),
+            }),
+        }
+    }
+    let replaced = fs.write(&second, b"b").is_ok()
+        && fs.rename(&second, &first).is_ok()
+        && fs.read(&first).is_ok_and(|c| c == b"b");
+    if !replaced {
+        found.push(Limitation::NoReplacingRename);
+    }
+    let _ = fs.remove(&first);
+    let _ = fs.remove(&second);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    /// An in-memory directory that refuses what the options say.
+    #[derive(Default)]
+    struct MockFs {
+        files: RefCell<HashMap<PathBuf, Vec<u8>>>,
+        read_only: bool,
+        /// Refuses names with ':' like exFAT.
+        fat_names: bool,
+        /// Renaming onto an existing file fails.
+        no_replace: bool,
+    }
+
+    impl ProbeFs for MockFs {
+        fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            if self.read_only {
+                return Err(ErrorKind::ReadOnlyFilesystem.into());
+            }
+            let name = path.file_name().unwrap().to_string_lossy();
+            if self.fat_names && name.contains(':') {
+                return Err(ErrorKind::InvalidInput.into());
+            }
+            self.files
+                .borrow_mut()
+                .insert(path.to_path_buf(), contents.to_vec());
+            Ok(())
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            let files = self.files.borrow();
+            files.get(path).cloned().ok_or(ErrorKind::NotFound.into())
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            let mut files = self.files.borrow_mut();
+            if self.no_replace && files.contains_key(to) {
+                return Err(ErrorKind::AlreadyExists.into());
+            }
+            let contents = files
+                .remove(from)
+                .ok_or(io::Error::from(ErrorKind::NotFound))?;
+            files.insert(to.to_path_buf(), contents);
+            Ok(())
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            let removed = self.files.borrow_mut().remove(path);
+            removed.map(|_| ()).ok_or(ErrorKind::NotFound.into())
+        }
+    }
+
+    fn names() -> Vec<String> {
+        vec!["ep 12:30.xml".to_string(), "ep 12:3000000.png".to_string()]
+    }
+
+    #[test]
+    fn test_probe_dir() {
+        let dir = Path::new("/media/sd/out");
+        let fs = MockFs::default();
+        assert_eq!(probe_dir(&fs, dir, &names()), []);
+        // Every probe file is gone again.
+        assert!(fs.files.borrow().is_empty());
+
+        let exfat = MockFs {
+            fat_names: true,
+            no_replace: true,
+            ..MockFs::default()
+        };
+        let found = probe_dir(&exfat, dir, &names());
+        assert_eq!(found.len(), 3);
+        assert!(
+            matches!(&found[0], Limitation::NameRejected { name, .. } if name == "ep 12:30.xml")
+        );
+        assert!(
+            found[0].message().contains("exFAT and FAT refuse"),
+            "{:?}",
+            found[0]
+        );
+        assert_eq!(found[2], Limitation::NoReplacingRename);
+        assert!(found[0].is_fatal() && !found[2].is_fatal());
+        assert!(exfat.files.borrow().is_empty());
+
+        let read_only = MockFs {
+            read_only: true,
+            ..MockFs::default()
+        };
+        let found = probe_dir(&read_only, dir, &names());
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message().contains("read-only"), "{:?}", found[0]);
+    }
+
+    #[test]
+    fn test_probe_real_dir() {
+        let dir = crate::output::tests::scratch_dir("preflight");
+        let names = ["ep01.xml".to_string(), "ep0100000.png".to_string()];
+        assert_eq!(probe_dir(&RealFs, &dir, &names), []);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        let missing = dir.join("missing");
+        let found = probe_dir(&RealFs, &missing, &names);
+        assert!(found[0].message().contains("does not exist"), "{:?}", found);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+//! --preview-on-video: captions composited over the video frame they appear on, for checking
+//! placement against real content.
+
+use crate::bitmap::BitmapData;
+
+/// Number of stills written by --preview-on-video.
+pub const PREVIEW_STILLS: usize = 5;
+
+/// A caption kept for a preview still.
+#[derive(Debug, Clone)]
+pub struct PreviewSample {
+    /// Event image file name; the still is named after it.
+    pub image_file: String,
+    pub in_tc: String,
+    pub bitmap: BitmapData,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Keeps an evenly spaced subset of a stream of unknown length. At most `2 * target` items are
+/// held: when full, every other item is dropped and the keep interval doubles.
+#[derive(Debug)]
+pub struct EvenSampler<T> {
+    target: usize,
+    step: usize,
+    seen: usize,
+    items: Vec<T>,
+}
+
+impl<T> EvenSampler<T> {
+    pub fn new(target: usize) -> Self {
+        Self {
+            target: target.max(1),
+            step: 1,
+            seen: 0,
+            items: Vec::new(),
+        }
+    }
+
+    /// Offers the next item; `make` is only called when the item is kept.
+    pub fn offer(&mut self, make: impl FnOnce() -> T) {
+        let index = self.seen;
+        self.seen += 1;
+        if !index.is_multiple_of(self.step) {
+            return;
+        }
+        self.items.push(make());
+        if self.items.len() >= 2 * self.target {
+            let mut position = 0;
+            self.items.retain(|_| {
+                position += 1;
+                position % 2 == 1
+            });
+            self.step *= 2;
+        }
+    }
+
+    /// Up to `target` of the kept items, spread over the whole stream.
+    pub fn into_items(self) -> Vec<T> {
+        let len = self.items.len();
+        if len <= self.target {
+            return self.items;
+        }
+        let picks: Vec<usize> = (0..self.target)
+            .map(|i| i * (len - 1) / (self.target - 1).max(1))
+            .collect();
+        self.items
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| picks.contains(i))
+            .map(|(_, item)| item)
+            .collect()
+    }
+}
+
+/// Converts 8-bit planar 4:2:0 YUV to opaque RGBA. HD sizes (height > 576) use BT.709
+/// coefficients, SD uses BT.601; `full_range` is JPEG-style 0–255 luma.
+pub fn yuv420_to_rgba(
+    planes: [&[u8]; 3],
+    strides: [usize; 3],
+    width: i32,
+    height: i32,
+    full_range: bool,
+) -> BitmapData {
+    let (w, h) = (width.max(0) as usize, height.max(0) as usize);
+    let (kr, kb) = if height > 576 { (0.2126, 0.0722) } else { (0.299, 0.114) };
+    let kg = 1.0 - kr - kb;
+    let (y_off, y_scale, c_scale) = if full_range {
+        (0.0, 1.0, 1.0)
+    } else {
+        (16.0, 255.0 / 219.0, 255.0 / 224.0)
+    };
+    let clamp = |v: f64| v.round().clamp(0.0, 255.0) as u8;
+    let mut data = Vec::with_capacity(w * h * 4);
+    for row in 0..h {
+        for col in 0..w {
+            let y = (planes[0][row * strides[0] + col] as f64 - y_off) * y_scale;
+            let u = (planes[1][(row / 2) * strides[1] + col / 2] as f64 - 128.0) * c_scale;
+            let v = (planes[2][(row / 2) * strides[2] + col / 2] as f64 - 128.0) * c_scale;
+            let r = y + 2.0 * (1.0 - kr) * v;
+            let b = y + 2.0 * (1.0 - kb) * u;
+            let g = (y - kr * r - kb * b) / kg;
+            data.extend_from_slice(&[clamp(r), clamp(g), clamp(b), 255]);
+        }
+    }
+    BitmapData {
+        data,
+        width,
+        height,
+        stride: width * 4,
+    }
+}
+
+/// Nearest-neighbour resize (for stretching anamorphic video to the caption canvas).
+pub fn scale_nearest(bitmap: &BitmapData, new_width: i32, new_height: i32) -> BitmapData {
+    let (w, h) = (new_width.max(0) as usize, new_height.max(0) as usize);
+    let (src_w, src_h) = (bitmap.width.max(1) as usize, bitmap.height.max(1) as usize);
+    let mut data = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        let sy = y * src_h / h;
+        for x in 0..w {
+            let o = sy * bitmap.stride as usize + (x * src_w / w) * 4;
+            data.extend_from_slice(&bitmap.data[o..o + 4]);
+        }
+    }
+    BitmapData {
+        data,
+        width: new_width,
+        height: new_height,
+        stride: new_width * 4,
+    }
+}
+
+/// Draws a premultiplied caption over `frame` with its top-left at (x, y), clipped to the frame.
+pub fn composite_over(frame: &mut BitmapData, caption: &BitmapData, x: i32, y: i32) {
+    for cy in 0..caption.height.max(0) {
+        let fy = y + cy;
+        if fy < 0 || fy >= frame.height {
+            continue;
+        }
+        for cx in 0..caption.width.max(0) {
+            let fx = x + cx;
+            if fx < 0 || fx >= frame.width {
+                continue;
+            }
+            let s = (cy * caption.stride + cx * 4) as usize;
+            let d = (fy * frame.stride + fx * 4) as usize;
+            let inv = 255 - caption.data[s + 3] as u16;
+            for i in 0..4 {
+                let below = (frame.data[d + i] as u16 * inv + 127) / 255;
+                frame.data[d + i] = (caption.data[s + i] as u16 + below).min(255) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(n: usize, target: usize) -> Vec<usize> {
+        let mut sampler = EvenSampler::new(target);
+        for i in 0..n {
+            sampler.offer(|| i);
+        }
+        sampler.into_items()
+    }
+
+    #[test]
+    fn test_even_sampler() {
+        assert_eq!(sample(0, 5), Vec::<usize>::new());
+        assert_eq!(sample(3, 5), [0, 1, 2]);
+        assert_eq!(sample(9, 5), [0, 2, 4, 6, 8]);
+        let picks = sample(1000, 5);
+        assert_eq!(picks.len(), 5);
+        assert_eq!(picks[0], 0);
+        assert!(picks[4] >= 750, "{:?}", picks);
+        assert!(picks.windows(2).all(|w| w[1] - w[0] >= 128), "{:?}", picks);
+    }
+
+    #[test]
+    fn test_even_sampler_only_builds_kept_items() {
+        let mut built = 0;
+        let mut sampler = EvenSampler::new(2);
+        for i in 0..100 {
+            sampler.offer(|| {
+                built += 1;
+                i
+            });
+        }
+        assert!(built < 20, "{}", built);
+    }
+
+    #[test]
+    fn test_yuv420_to_rgba() {
+        // 2x2 limited-range frame: white, black, grey, grey luma with neutral chroma.
+        let y = [235, 16, 126, 126];
+        let rgba = yuv420_to_rgba([&y, &[128], &[128]], [2, 1, 1], 2, 2, false);
+        assert_eq!(&rgba.data[0..4], &[255, 255, 255, 255]);
+        assert_eq!(&rgba.data[4..8], &[0, 0, 0, 255]);
+        assert_eq!(&rgba.data[8..12], &[128, 128, 128, 255]);
+
+        // Full-range SD red (BT.601: Y=76, U=85, V=255).
+        let red = yuv420_to_rgba([&[76], &[85], &[255]], [1, 1, 1], 1, 1, true);
+        assert!(red.data[0] >= 250 && red.data[1] <= 5 && red.data[2] <= 5, "{:?}", red.data);
+    }
+
+    #[test]
+    fn test_scale_nearest() {
+        let b = BitmapData {
+            data: vec![1, 1, 1, 1, 2, 2, 2, 2],
+            width: 2,
+            height: 1,
+            stride: 8,
+        };
+        let s = scale_nearest(&b, 4, 2);
+        assert_eq!((s.width, s.height, s.data.len()), (4, 2, 32));
+        assert_eq!(s.data[..16], [1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(s.data[16..], s.data[..16]);
+    }
+
+    #[test]
+    fn test_composite_over() {
+        let mut frame = BitmapData {
+            data: [100, 100, 100, 255].repeat(4),
+            width: 2,
+            height: 2,
+            stride: 8,
+        };
+        // Opaque white and premultiplied half-transparent black, partly off the frame.
+        let caption = BitmapData {
+            data: vec![255, 255, 255, 255, 0, 0, 0, 128],
+            width: 2,
+            height: 1,
+            stride: 8,
+        };
+        composite_over(&mut frame, &caption, 1, 1);
+        assert_eq!(&frame.data[0..12], &[100, 100, 100, 255].repeat(3)[..]);
+        assert_eq!(&frame.data[12..16], &[255, 255, 255, 255]);
+        composite_over(&mut frame, &caption, -1, 0);
+        assert_eq!(&frame.data[0..4], &[50, 50, 50, 255]);
+    }
+}
@@ -0,0 +1,280 @@
+//! --preview-video: a low-resolution QC video of the caption track, each caption drawn over a
+//! gray background while it is shown. Encoding needs the `preview-video` cargo feature.
+
+use std::collections::HashMap;
+
+use crate::bdn::{tc_to_seconds, SubtitleEvent};
+use crate::bitmap::{scale_bitmap, BitmapData};
+use crate::preview::composite_over;
+
+/// Height of the preview video; the width follows the canvas aspect.
+pub const PREVIEW_VIDEO_HEIGHT: i32 = 480;
+/// Frame rate of the preview video; caption changes land on the nearest earlier frame.
+pub const PREVIEW_VIDEO_FPS: i32 = 5;
+/// Background drawn where no caption is shown (opaque mid gray, premultiplied RGBA).
+const BACKGROUND: [u8; 4] = [128, 128, 128, 255];
+
+/// A caption placed on the preview timeline, in preview pixels.
+#[derive(Debug, Clone, Copy)]
+struct Placed<'a> {
+    start: f64,
+    end: f64,
+    image: &'a BitmapData,
+    x: i32,
+    y: i32,
+}
+
+/// Caption images scaled down for the preview, keyed by image file name.
+#[derive(Debug)]
+pub struct PreviewTimeline {
+    scale: f64,
+    /// Preview frame size (even, as 4:2:0 video needs).
+    pub size: (i32, i32),
+    images: HashMap<String, BitmapData>,
+}
+
+impl PreviewTimeline {
+    pub fn new((canvas_w, canvas_h): (i32, i32)) -> Self {
+        let scale = PREVIEW_VIDEO_HEIGHT as f64 / canvas_h.max(1) as f64;
+        let width = ((canvas_w.max(1) as f64 * scale / 2.0).round() as i32 * 2).max(2);
+        PreviewTimeline {
+            scale,
+            size: (width, PREVIEW_VIDEO_HEIGHT),
+            images: HashMap::new(),
+        }
+    }
+
+    /// Keeps a scaled copy of the bitmap saved as `file` (once per file; --dedup-png reuses).
+    pub fn add(&mut self, file: &str, bitmap: &BitmapData) {
+        if self.images.contains_key(file) {
+            return;
+        }
+        let scaled = scale_bitmap(
+            bitmap,
+            (bitmap.width as f64 * self.scale).round() as i32,
+            (bitmap.height as f64 * self.scale).round() as i32,
+        );
+        self.images.insert(file.to_string(), scaled);
+    }
+
+    /// Follows the (old, new) image renames of `renumber_images`.
+    pub fn rename(&mut self, renames: &[(String, String)]) {
+        let moved: Vec<(String, BitmapData)> = renames
+            .iter()
+            .filter_map(|(old, new)| Some((new.clone(), self.images.remove(old)?)))
+            .collect();
+        self.images.extend(moved);
+    }
+
+    /// Events with a kept image, on the preview timeline (seconds from 00:00:00:00).
+    fn place<'a>(&'a self, events: &[SubtitleEvent], fps: f64) -> anyhow::Result<Vec<Placed<'a>>> {
+        let mut placed = Vec::new();
+        for event in events {
+            if let Some(image) = self.images.get(&event.png_file) {
+                placed.push(Placed {
+                    start: tc_to_seconds(&event.in_tc, fps)?,
+                    end: tc_to_seconds(&event.out_tc, fps)?,
+                    image,
+                    x: (event.x as f64 * self.scale).round() as i32,
+                    y: (event.y as f64 * self.scale).round() as i32,
+                });
+            }
+        }
+        Ok(placed)
+    }
+
+    /// Passes every frame of the preview, in order, to `emit` as YUV 4:2:0 planes. The video
+    /// runs until the last caption ends; a frame is only rendered when the captions shown change.
+    pub fn render(
+        &self,
+        events: &[SubtitleEvent],
+        fps: f64,
+        mut emit: impl FnMut(&[Vec<u8>; 3]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let placed = self.place(events, fps)?;
+        let duration = placed.iter().map(|p| p.end).fold(0.0, f64::max);
+        let count = ((duration * PREVIEW_VIDEO_FPS as f64).ceil() as usize).max(1);
+        let mut shown: Option<(Vec<usize>, [Vec<u8>; 3])> = None;
+        for i in 0..count {
+            let time = i as f64 / PREVIEW_VIDEO_FPS as f64;
+            let active: Vec<usize> = (0..placed.len())
+                .filter(|&n| placed[n].start <= time && time < placed[n].end)
+                .collect();
+            if shown.as_ref().is_none_or(|(last, _)| *last != active) {
+                let mut frame = BitmapData {
+                    data: BACKGROUND.repeat((self.size.0 * self.size.1) as usize),
+                    width: self.size.0,
+                    height: self.size.1,
+                    stride: self.size.0 * 4,
+                };
+                for &n in &active {
+                    composite_over(&mut frame, placed[n].image, placed[n].x, placed[n].y);
+                }
+                shown = Some((active, rgba_to_yuv420(&frame)));
+            }
+            if let Some((_, planes)) = &shown {
+                emit(planes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts opaque RGBA to limited-range BT.601 planar 4:2:0 (the inverse of
+/// `preview::yuv420_to_rgba` for SD). Chroma is the average of each 2x2 block; the size must be
+/// even.
+pub fn rgba_to_yuv420(frame: &BitmapData) -> [Vec<u8>; 3] {
+    let (w, h) = (frame.width.max(0) as usize, frame.height.max(0) as usize);
+    let (kr, kb) = (0.299, 0.114);
+    let kg = 1.0 - kr - kb;
+    let px = |x: usize, y: usize| {
+        let o = y * frame.stride as usize + x * 4;
+        let d = &frame.data[o..o + 3];
+        (d[0] as f64, d[1] as f64, d[2] as f64)
+    };
+    let luma = |(r, g, b): (f64, f64, f64)| kr * r + kg * g + kb * b;
+    let clamp = |v: f64| v.round().clamp(0.0, 255.0) as u8;
+    let mut y_plane = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            y_plane.push(clamp(16.0 + luma(px(x, y)) * 219.0 / 255.0));
+        }
+    }
+    let (cw, ch) = (w / 2, h / 2);
+    let mut u_plane = Vec::with_capacity(cw * ch);
+    let mut v_plane = Vec::with_capacity(cw * ch);
+    for cy in 0..ch {
+        for cx in 0..cw {
+            let mut sum = (0.0, 0.0, 0.0);
+            for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (r, g, b) = px(cx * 2 + x, cy * 2 + y);
+                sum = (sum.0 + r / 4.0, sum.1 + g / 4.0, sum.2 + b / 4.0);
+            }
+            let l = luma(sum);
+            let u = (sum.2 - l) / (2.0 * (1.0 - kb));
+            let v = (sum.0 - l) / (2.0 * (1.0 - kr));
+            u_plane.push(clamp(128.0 + u * 224.0 / 255.0));
+            v_plane.push(clamp(128.0 + v * 224.0 / 255.0));
+        }
+    }
+    [y_plane, u_plane, v_plane]
+}
+
+/// Encodes the preview of `events` to `path` (container from the extension, e.g. `.mkv`).
+#[cfg(feature = "preview-video")]
+pub fn write_preview_video(
+    path: &std::path::Path,
+    timeline: &PreviewTimeline,
+    events: &[SubtitleEvent],
+    fps: f64,
+) -> anyhow::Result<()> {
+    let (width, height) = timeline.size;
+    let mut encoder = crate::ffmpeg::VideoEncoder::create(path, width, height, PREVIEW_VIDEO_FPS)?;
+    timeline.render(events, fps, |planes| encoder.write_frame(planes))?;
+    encoder.finish()?;
+    crate::sink::apply_file_mode(path)
+        .map_err(|e| anyhow::anyhow!("Failed to set permissions: {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preview::yuv420_to_rgba;
+
+    fn solid(rgba: [u8; 4], width: i32, height: i32) -> BitmapData {
+        BitmapData {
+            data: rgba.repeat((width * height) as usize),
+            width,
+            height,
+            stride: width * 4,
+        }
+    }
+
+    fn event(in_tc: &str, out_tc: &str, png_file: &str, x: i32, y: i32) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: png_file.to_string(),
+            x,
+            y,
+            width: 0,
+            height: 0,
+            forced: false,
+            second: None,
+        }
+    }
+
+    #[test]
+    fn test_rgba_to_yuv420_round_trips() {
+        for rgba in [
+            BACKGROUND,
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+            [200, 40, 90, 255],
+        ] {
+            let frame = solid(rgba, 4, 2);
+            let [y, u, v] = rgba_to_yuv420(&frame);
+            assert_eq!((y.len(), u.len(), v.len()), (8, 2, 2));
+            let back = yuv420_to_rgba([&y, &u, &v], [4, 2, 2], 4, 2, false);
+            for (a, b) in back.data[..4].iter().zip(rgba) {
+                assert!(
+                    (*a as i32 - b as i32).abs() <= 2,
+                    "{:?} -> {:?}",
+                    rgba,
+                    &back.data[..4]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_timeline_size_and_scaling() {
+        let mut timeline = PreviewTimeline::new((1920, 1080));
+        assert_eq!(timeline.size, (854, 480));
+        assert_eq!(PreviewTimeline::new((720, 480)).size, (720, 480));
+        timeline.add("a.png", &solid([255; 4], 90, 45));
+        timeline.add("a.png", &solid([0; 4], 9, 9));
+        assert_eq!(
+            (
+                timeline.images["a.png"].width,
+                timeline.images["a.png"].height
+            ),
+            (40, 20)
+        );
+    }
+
+    /// Renders `events` into runs of identical frames: (Y at top-left, Y at bottom-right, count).
+    fn runs(timeline: &PreviewTimeline, events: &[SubtitleEvent]) -> Vec<(u8, u8, usize)> {
+        let mut runs: Vec<(u8, u8, usize)> = Vec::new();
+        timeline
+            .render(events, 30.0, |[y, _, _]| {
+                let corners = (y[0], y[y.len() - 1]);
+                match runs.last_mut() {
+                    Some(run) if (run.0, run.1) == corners => run.2 += 1,
+                    _ => runs.push((corners.0, corners.1, 1)),
+                }
+                Ok(())
+            })
+            .unwrap();
+        runs
+    }
+
+    #[test]
+    fn test_render_follows_captions() {
+        let mut timeline = PreviewTimeline::new((1920, 1080));
+        timeline.add("a.png", &solid([255; 4], 9, 9));
+        timeline.add("b.png", &solid([255; 4], 9, 9));
+        let events = [
+            event("00:00:01:00", "00:00:02:00", "a.png", 0, 0),
+            event("00:00:02:00", "00:00:02:15", "b.png", 1916, 1076),
+        ];
+        // 2.5s at 5 fps: 13 frames, gray until 1s, then a (top left), then b (bottom right).
+        let (gray, white) = (126, 235);
+        assert_eq!(
+            runs(&timeline, &events),
+            [(gray, gray, 5), (white, gray, 5), (gray, white, 3)]
+        );
+        // No captions: one gray frame.
+        assert_eq!(runs(&timeline, &[]), [(gray, gray, 1)]);
+    }
+}
@@ -0,0 +1,203 @@
+//! Probe cache: remembers the ARIB subtitle PID chosen per (directory, service) so later files
+//! of the same batch can skip the full stream analysis.
+//!
+//! File format: one entry per line, tab-separated `directory<TAB>service<TAB>pid`.
+//! Lines starting with '#' and malformed lines are ignored.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Service label used when the container does not expose a service name.
+pub const UNKNOWN_SERVICE: &str = "-";
+
+/// A single remembered stream selection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeCacheEntry {
+    pub directory: String,
+    pub service: String,
+    pub pid: i32,
+}
+
+#[derive(Debug, Default)]
+pub struct ProbeCache {
+    entries: Vec<ProbeCacheEntry>,
+}
+
+/// Cache key for an input file: its (canonicalized when possible) parent directory.
+pub fn directory_key(input_file: &Path) -> String {
+    let parent = match input_file.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    parent
+        .canonicalize()
+        .unwrap_or_else(|_| parent.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// Replaces characters that would break the line format.
+fn sanitize_field(s: &str) -> String {
+    let s: String = s
+        .chars()
+        .map(|c| if c == '\t' || c == '\n' || c == '\r' { ' ' } else { c })
+        .collect();
+    if s.trim().is_empty() {
+        UNKNOWN_SERVICE.to_string()
+    } else {
+        s
+    }
+}
+
+impl ProbeCache {
+    /// Parses cache file contents.
+    pub fn parse(contents: &str) -> Self {
+        let entries = contents
+            .lines()
+            .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+            .filter_map(|l| {
+                let mut it = l.split('\t');
+                let directory = it.next()?.to_string();
+                let service = it.next()?.to_string();
+                let pid = it.next()?.trim().parse().ok()?;
+                if it.next().is_some() {
+                    return None;
+                }
+                Some(ProbeCacheEntry {
+                    directory,
+                    service,
+                    pid,
+                })
+            })
+            .collect();
+        ProbeCache { entries }
+    }
+
+    /// Loads a cache file. A missing file yields an empty cache.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(s) => Ok(Self::parse(&s)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => anyhow::bail!("Failed to read probe cache: {}: {}", path.display(), e),
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = String::from("# arib2bdnxml probe cache: directory\tservice\tpid\n");
+        for e in &self.entries {
+            out.push_str(&format!("{}\t{}\t{}\n", e.directory, e.service, e.pid));
+        }
+        out
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, self.serialize())
+            .map_err(|e| anyhow::anyhow!("Failed to write probe cache: {}: {}", path.display(), e))
+    }
+
+    /// Records a selection into the cache file at `path`, re-reading it first so entries other
+    /// inputs of a --jobs batch recorded meanwhile are kept.
+    pub fn update(path: &Path, directory: &str, service: &str, pid: i32) -> anyhow::Result<()> {
+        static FILE: Mutex<()> = Mutex::new(());
+        let _guard = FILE.lock().unwrap_or_else(|e| e.into_inner());
+        let mut cache = Self::load(path)?;
+        cache.record(directory, service, pid);
+        cache.save(path)
+    }
+
+    /// Most recently recorded entry for a directory (any service).
+    pub fn lookup(&self, directory: &str) -> Option<&ProbeCacheEntry> {
+        self.entries.iter().rev().find(|e| e.directory == directory)
+    }
+
+    /// Records a selection, replacing any entry with the same (directory, service) and moving it
+    /// to the end so it is preferred by `lookup`.
+    pub fn record(&mut self, directory: &str, service: &str, pid: i32) {
+        let directory = sanitize_field(directory);
+        let service = sanitize_field(service);
+        self.entries
+            .retain(|e| !(e.directory == directory && e.service == service));
+        self.entries.push(ProbeCacheEntry {
+            directory,
+            service,
+            pid,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_skips_comments_and_malformed_lines() {
+        let c = ProbeCache::parse("# header\n/rec\tNHK\t336\nbroken line\n/rec\tBS\tx\n\n/a\t-\t48\textra\n");
+        assert_eq!(c.entries.len(), 1);
+        assert_eq!(
+            c.lookup("/rec"),
+            Some(&ProbeCacheEntry {
+                directory: "/rec".to_string(),
+                service: "NHK".to_string(),
+                pid: 336,
+            })
+        );
+        assert!(c.lookup("/a").is_none());
+    }
+
+    #[test]
+    fn test_record_replaces_same_key_and_prefers_latest() {
+        let mut c = ProbeCache::default();
+        c.record("/rec", "NHK", 336);
+        c.record("/rec", "BS", 400);
+        assert_eq!(c.lookup("/rec").unwrap().pid, 400);
+        c.record("/rec", "NHK", 337);
+        assert_eq!(c.entries.len(), 2);
+        assert_eq!(c.lookup("/rec").unwrap().pid, 337);
+        assert!(c.lookup("/other").is_none());
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut c = ProbeCache::default();
+        c.record("/rec dir", "Service\tName", 336);
+        c.record("/x", "", 48);
+        let parsed = ProbeCache::parse(&c.serialize());
+        assert_eq!(parsed.entries, c.entries);
+        assert_eq!(parsed.entries[0].service, "Service Name");
+        assert_eq!(parsed.entries[1].service, UNKNOWN_SERVICE);
+    }
+
+    #[test]
+    fn test_load_missing_file_is_empty() {
+        let c = ProbeCache::load(Path::new("/nonexistent/arib2bdnxml-probe-cache")).unwrap();
+        assert!(c.entries.is_empty());
+    }
+
+    #[test]
+    fn test_update_from_several_threads() {
+        let path =
+            std::env::temp_dir().join(format!("arib2bdnxml-probe-cache-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        std::thread::scope(|scope| {
+            for pid in 0..8 {
+                let path = &path;
+                scope.spawn(move || {
+                    ProbeCache::update(path, &format!("/rec{}", pid), "NHK", pid).unwrap()
+                });
+            }
+        });
+        let c = ProbeCache::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(c.entries.len(), 8);
+        assert_eq!(c.lookup("/rec5").unwrap().pid, 5);
+    }
+
+    #[test]
+    fn test_directory_key_bare_filename() {
+        assert_eq!(
+            directory_key(Path::new("input.ts")),
+            directory_key(Path::new("./input.ts"))
+        );
+    }
+}
@@ -0,0 +1,373 @@
+//! --reference-subs: cross-checks event timing against a separately timed .srt or .ass of the same
+//! program; only the cue times are read. Each event is aligned to the reference cue starting
+//! nearest to it, and events far from every cue or off by much point at PTS problems. With
+//! --snap-to-reference, small offsets are corrected to the reference times.
+
+use std::path::Path;
+
+use crate::bdn::{
+    outtc_with_semantics, tc_to_frames, tc_to_seconds, time_to_tc, OutTcSemantics, SubtitleEvent,
+};
+
+/// An event with no reference cue starting within this many seconds is reported as unmatched.
+pub const MATCH_WINDOW: f64 = 2.0;
+/// A matched event off by more than this many seconds is listed.
+pub const LARGE_OFFSET: f64 = 0.5;
+
+/// Display time of a reference cue, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Seconds of `H:MM:SS<sep>fraction`: SRT writes `00:01:02,345`, ASS `0:01:02.34`.
+fn parse_cue_time(s: &str) -> Option<f64> {
+    let (clock, fraction) = s.trim().split_once([',', '.'])?;
+    let fields: Vec<u64> = clock
+        .split(':')
+        .map(|f| f.parse().ok())
+        .collect::<Option<_>>()?;
+    let [h, m, sec] = fields[..] else {
+        return None;
+    };
+    if m >= 60 || sec >= 60 || fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let fraction: f64 = format!("0.{}", fraction).parse().ok()?;
+    Some(((h * 60 + m) * 60 + sec) as f64 + fraction)
+}
+
+/// Cues of an SRT file: every `start --> end` line (text and any position after the end time are
+/// ignored).
+pub fn parse_srt(contents: &str) -> anyhow::Result<Vec<Cue>> {
+    let mut cues = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let Some((start, rest)) = line.split_once("-->") else {
+            continue;
+        };
+        let end = rest.split_whitespace().next().unwrap_or_default();
+        match (parse_cue_time(start), parse_cue_time(end)) {
+            (Some(start), Some(end)) => cues.push(Cue { start, end }),
+            _ => anyhow::bail!("srt line {}: invalid cue time: {}", n + 1, line.trim()),
+        }
+    }
+    Ok(cues)
+}
+
+/// Cues of an ASS/SSA file: the Start and End of each `Dialogue:` line of [Events], found through
+/// its `Format:` line (comments are skipped).
+pub fn parse_ass(contents: &str) -> anyhow::Result<Vec<Cue>> {
+    let mut in_events = false;
+    // Field positions of Start and End; the order of the ASS specification until a Format line.
+    let (mut start_at, mut end_at, mut fields) = (1, 2, 10);
+    let mut cues = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(format) = line.strip_prefix("Format:") {
+            let names: Vec<&str> = format.split(',').map(str::trim).collect();
+            let position = |name: &str| names.iter().position(|f| f.eq_ignore_ascii_case(name));
+            let (Some(start), Some(end)) = (position("Start"), position("End")) else {
+                anyhow::bail!("ass line {}: Format without Start and End", n + 1);
+            };
+            (start_at, end_at, fields) = (start, end, names.len());
+        } else if let Some(dialogue) = line.strip_prefix("Dialogue:") {
+            // The last field (Text) may contain commas.
+            let values: Vec<&str> = dialogue.splitn(fields, ',').collect();
+            let time = |i: usize| values.get(i).and_then(|v| parse_cue_time(v));
+            match (time(start_at), time(end_at)) {
+                (Some(start), Some(end)) => cues.push(Cue { start, end }),
+                _ => anyhow::bail!("ass line {}: invalid cue time: {}", n + 1, line),
+            }
+        }
+    }
+    Ok(cues)
+}
+
+/// Reads a reference subtitle file: ASS/SSA by its extension or [Script Info] header, SRT
+/// otherwise. The cues are sorted by start.
+pub fn load_reference(path: &Path) -> anyhow::Result<Vec<Cue>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to read reference subtitles: {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    let contents = contents.trim_start_matches('\u{feff}');
+    let ass = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("ass") || e.eq_ignore_ascii_case("ssa"))
+        || contents.trim_start().starts_with("[Script Info]");
+    let mut cues = if ass {
+        parse_ass(contents)
+    } else {
+        parse_srt(contents)
+    }
+    .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    if cues.is_empty() {
+        anyhow::bail!("No cues in reference subtitles: {}", path.display());
+    }
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(cues)
+}
+
+/// The cue (index into `cues`, sorted by start) starting nearest to `start`, within `window`
+/// seconds, and the event's offset from it (positive: the event is later).
+pub fn nearest_cue(start: f64, cues: &[Cue], window: f64) -> Option<(usize, f64)> {
+    let after = cues.partition_point(|c| c.start < start);
+    [after.checked_sub(1), Some(after)]
+        .into_iter()
+        .flatten()
+        .filter(|&i| i < cues.len())
+        .map(|i| (i, start - cues[i].start))
+        .filter(|(_, offset)| offset.abs() <= window)
+        .min_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+}
+
+/// Offsets of the events from their nearest cues.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReferenceReport {
+    /// Offset in seconds of each matched event.
+    pub offsets: Vec<f64>,
+    /// Image and InTC of each event with no cue within `MATCH_WINDOW`.
+    pub unmatched: Vec<(String, String)>,
+    /// Image, InTC and offset of each event off by more than `LARGE_OFFSET`.
+    pub large: Vec<(String, String, f64)>,
+    /// Events moved onto their cue (--snap-to-reference).
+    pub snapped: usize,
+}
+
+impl ReferenceReport {
+    fn median(&self) -> Option<f64> {
+        let mut sorted = self.offsets.clone();
+        sorted.sort_by(f64::total_cmp);
+        let mid = sorted.len() / 2;
+        match sorted.len() {
+            0 => None,
+            n if n % 2 == 0 => Some((sorted[mid - 1] + sorted[mid]) / 2.0),
+            _ => Some(sorted[mid]),
+        }
+    }
+
+    /// The lines printed after the conversion.
+    pub fn lines(&self) -> Vec<String> {
+        let matched = self.offsets.len();
+        let mut lines = vec![format!(
+            "Reference timing: {} of {} events near a cue",
+            matched,
+            matched + self.unmatched.len()
+        )];
+        if let Some(median) = self.median() {
+            let mean = self.offsets.iter().sum::<f64>() / matched as f64;
+            let max = self.offsets.iter().fold(0.0f64, |m, o| m.max(o.abs()));
+            lines.push(format!(
+                "  offset: mean {:+.3}s, median {:+.3}s, largest {:.3}s",
+                mean, median, max
+            ));
+        }
+        for (image, in_tc, offset) in &self.large {
+            lines.push(format!("  {} at {}: off by {:+.3}s", image, in_tc, offset));
+        }
+        for (image, in_tc) in &self.unmatched {
+            lines.push(format!(
+                "  {} at {}: no reference cue within {:.0}s",
+                image, in_tc, MATCH_WINDOW
+            ));
+        }
+        if self.snapped > 0 {
+            lines.push(format!(
+                "  snapped to the reference: {} events",
+                self.snapped
+            ));
+        }
+        lines
+    }
+}
+
+/// Aligns each event to its nearest cue and, with `snap` (seconds), moves its InTC and OutTC
+/// onto the cue's times where each is off by at most `snap`. OutTCs are read and written with
+/// `semantics`; an event always keeps at least one frame.
+pub fn compare_with_reference(
+    events: &mut [SubtitleEvent],
+    cues: &[Cue],
+    snap: Option<f64>,
+    semantics: OutTcSemantics,
+    fps: f64,
+) -> anyhow::Result<ReferenceReport> {
+    let mut report = ReferenceReport::default();
+    // One frame after an inclusive OutTC is where the caption is gone.
+    let inclusive = if semantics == OutTcSemantics::Inclusive {
+        1.0 / fps
+    } else {
+        0.0
+    };
+    for event in events.iter_mut() {
+        let start = tc_to_seconds(&event.in_tc, fps)?;
+        let end = tc_to_seconds(&event.out_tc, fps)? + inclusive;
+        let Some((i, offset)) = nearest_cue(start, cues, MATCH_WINDOW) else {
+            report
+                .unmatched
+                .push((event.png_file.clone(), event.in_tc.clone()));
+            continue;
+        };
+        report.offsets.push(offset);
+        if offset.abs() > LARGE_OFFSET {
+            let large = (event.png_file.clone(), event.in_tc.clone(), offset);
+            report.large.push(large);
+        }
+        let Some(snap) = snap else {
+            continue;
+        };
+        let cue = cues[i];
+        let in_tc = match offset.abs() <= snap {
+            true => time_to_tc(cue.start, fps),
+            false => event.in_tc.clone(),
+        };
+        let out_tc = match (end - cue.end).abs() <= snap {
+            true => time_to_tc(cue.end, fps),
+            false => time_to_tc(end, fps),
+        };
+        let (Some(in_frame), Some(out_frame)) =
+            (tc_to_frames(&in_tc, fps), tc_to_frames(&out_tc, fps))
+        else {
+            continue;
+        };
+        if out_frame <= in_frame {
+            continue;
+        }
+        let out_tc = outtc_with_semantics(&in_tc, &out_tc, semantics, fps).unwrap_or(out_tc);
+        if (&in_tc, &out_tc) != (&event.in_tc, &event.out_tc) {
+            (event.in_tc, event.out_tc) = (in_tc, out_tc);
+            report.snapped += 1;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str = include_str!("../tests/reference/sample.srt");
+    const ASS: &str = include_str!("../tests/reference/sample.ass");
+
+    fn event(in_tc: &str, out_tc: &str) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: format!("{}.png", in_tc.replace(':', "")),
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 50,
+            forced: false,
+            second: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_reference() {
+        let expected = [
+            Cue {
+                start: 1.0,
+                end: 3.5,
+            },
+            Cue {
+                start: 4.0,
+                end: 6.0,
+            },
+            Cue {
+                start: 60.25,
+                end: 62.0,
+            },
+        ];
+        assert_eq!(parse_srt(SRT).unwrap(), expected);
+        // The Comment line is skipped; sorting is left to load_reference.
+        let mut cues = parse_ass(ASS).unwrap();
+        cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+        assert_eq!(cues, expected);
+        assert_eq!(parse_cue_time("0:01:02.5"), Some(62.5));
+        assert_eq!(parse_cue_time("00:61:00,000"), None);
+        assert!(parse_srt("1\n00:00:01 --> 00:00:02,000\n").is_err());
+        let no_times = "[Events]\nFormat: Layer, Style, Text\n";
+        assert!(parse_ass(no_times).is_err());
+    }
+
+    #[test]
+    fn test_nearest_cue() {
+        let cues = parse_srt(SRT).unwrap();
+        assert_eq!(
+            nearest_cue(1.1, &cues, MATCH_WINDOW).map(|(i, _)| i),
+            Some(0)
+        );
+        // Between two cues: the nearer one, before or after.
+        assert_eq!(
+            nearest_cue(3.7, &cues, MATCH_WINDOW).map(|(i, _)| i),
+            Some(1)
+        );
+        let (i, offset) = nearest_cue(2.0, &cues, MATCH_WINDOW).unwrap();
+        assert_eq!(i, 0);
+        assert!((offset - 1.0).abs() < 1e-9);
+        assert_eq!(nearest_cue(30.0, &cues, MATCH_WINDOW), None);
+        assert_eq!(nearest_cue(1.0, &[], MATCH_WINDOW), None);
+    }
+
+    #[test]
+    fn test_compare_with_reference() {
+        let cues = parse_srt(SRT).unwrap();
+        let fps = 30.0;
+        let sequence = || {
+            vec![
+                // 3 frames late; the OutTC on the cue's end.
+                event("00:00:01:03", "00:00:03:15"),
+                // 0.6 s late, ending 0.4 s late.
+                event("00:00:04:18", "00:00:06:12"),
+                // No cue near.
+                event("00:00:30:00", "00:00:32:00"),
+            ]
+        };
+        let exclusive = OutTcSemantics::Exclusive;
+        let mut events = sequence();
+        let report = compare_with_reference(&mut events, &cues, None, exclusive, fps).unwrap();
+        assert_eq!(events, sequence());
+        assert_eq!(report.offsets.len(), 2);
+        assert_eq!(report.large.len(), 1);
+        assert_eq!(report.large[0].0, "00000418.png");
+        assert_eq!(
+            report.unmatched,
+            [("00003000.png".to_string(), "00:00:30:00".to_string())]
+        );
+        let lines = report.lines();
+        assert_eq!(lines[0], "Reference timing: 2 of 3 events near a cue");
+        assert_eq!(
+            lines[1],
+            "  offset: mean +0.350s, median +0.350s, largest 0.600s"
+        );
+
+        // Snapping within 0.5 s: the first InTC, and the second OutTC only.
+        let snap = Some(0.5);
+        let report = compare_with_reference(&mut events, &cues, snap, exclusive, fps).unwrap();
+        assert_eq!(report.snapped, 2);
+        assert_eq!(events[0].in_tc, "00:00:01:00");
+        assert_eq!(events[0].out_tc, "00:00:03:15");
+        assert_eq!(events[1].in_tc, "00:00:04:18");
+        assert_eq!(events[1].out_tc, "00:00:06:00");
+
+        // Inclusive OutTCs stay the last frame shown.
+        let mut events = vec![event("00:00:01:02", "00:00:03:16")];
+        let inclusive = OutTcSemantics::Inclusive;
+        compare_with_reference(&mut events, &cues, snap, inclusive, fps).unwrap();
+        assert_eq!(events[0].in_tc, "00:00:01:00");
+        assert_eq!(events[0].out_tc, "00:00:03:14");
+    }
+}
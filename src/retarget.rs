@@ -0,0 +1,261 @@
+//! `retarget` subcommand: maps existing BDN output onto another canvas (e.g. 1080 to 720),
+//! scaling each image and placing it by its normalized position.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::bdn::{
+    graphic_ref, parse_bdn, parse_normalized, BdnInfo, BdnXmlGenerator, GraphicRef, Normalized,
+};
+use crate::bitmap::{load_png, save_bitmap, scale_bitmap, ImageFormat};
+use crate::config::{canvas_from_video_format, video_format_from_canvas};
+use crate::workspace::TempWorkspace;
+
+/// Pixel rectangle `(x, y, width, height)` of a normalized Graphic on `canvas`. Sizes round to
+/// at least one pixel and the rectangle is clamped inside the canvas. Captions in the lower half
+/// are placed by their bottom edge, so rounding the top and the height separately cannot push
+/// them down (or past the bottom of the canvas).
+pub fn retarget_rect(n: Normalized, canvas: (i32, i32)) -> (i32, i32, i32, i32) {
+    let (cw, ch) = (canvas.0.max(1), canvas.1.max(1));
+    let scale = |v: f64, size: i32| (v * size as f64).round() as i32;
+    let width = scale(n.width, cw).clamp(1, cw);
+    let height = scale(n.height, ch).clamp(1, ch);
+    let x = scale(n.x, cw).clamp(0, cw - width);
+    let y = if n.y + n.height / 2.0 > 0.5 {
+        scale(n.y + n.height, ch) - height
+    } else {
+        scale(n.y, ch)
+    };
+    (x, y.clamp(0, ch - height), width, height)
+}
+
+/// Result of `retarget_dir`.
+#[derive(Debug, Default)]
+pub struct RetargetSummary {
+    /// Written XML files and their info.
+    pub xml_files: Vec<(PathBuf, BdnInfo)>,
+    pub images: usize,
+}
+
+/// Retargets every BDN XML file in `dir` and its PNGs onto `canvas`, writing them to `out_dir`
+/// (through `workspace`, like `optimize_dir`). Positions come from the --emit-normalized
+/// comments; output without them is normalized against its recorded canvas or VideoFormat.
+pub fn retarget_dir(
+    dir: &Path,
+    out_dir: &Path,
+    canvas: (i32, i32),
+    png_auto_gray: bool,
+    workspace: &TempWorkspace,
+) -> anyhow::Result<RetargetSummary> {
+    let mut xml_paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("Failed to read directory: {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "xml"))
+        .collect();
+    xml_paths.sort();
+    if xml_paths.is_empty() {
+        anyhow::bail!("No BDN XML files in {}", dir.display());
+    }
+
+    let mut summary = RetargetSummary::default();
+    let mut images: HashMap<String, (i32, i32)> = HashMap::new();
+    let mut written = Vec::new();
+    for path in xml_paths {
+        let xml = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))?;
+        let context = |e: anyhow::Error| anyhow::anyhow!("{}: {}", path.display(), e);
+        let (info, mut events) = parse_bdn(&xml).map_err(context)?;
+        let normalized = parse_normalized(&xml).map_err(context)?;
+        let source = info
+            .normalized
+            .or_else(|| canvas_from_video_format(&info.video_format));
+
+        for (event, recorded) in events.iter_mut().zip(normalized) {
+            let second = event.second.as_mut();
+            let graphics = std::iter::once((
+                &event.png_file,
+                &mut event.x,
+                &mut event.y,
+                &mut event.width,
+                &mut event.height,
+            ))
+            .chain(second.map(|g| (&g.png_file, &mut g.x, &mut g.y, &mut g.width, &mut g.height)));
+            for (i, (file, x, y, width, height)) in graphics.enumerate() {
+                if graphic_ref(file) == GraphicRef::External {
+                    anyhow::bail!(
+                        "{}: graphic {} is outside the XML's directory; retarget writes every image next to the new XML",
+                        path.display(),
+                        file
+                    );
+                }
+                let n = match (recorded.get(i), source) {
+                    (Some(&n), _) => n,
+                    (None, Some(source)) => Normalized::of(*x, *y, *width, *height, source),
+                    (None, None) => anyhow::bail!(
+                        "{}: unknown canvas for VideoFormat {}; write it with --emit-normalized",
+                        path.display(),
+                        info.video_format
+                    ),
+                };
+                (*x, *y, *width, *height) = retarget_rect(n, canvas);
+                if images.contains_key(file) {
+                    continue;
+                }
+                if !Path::new(file)
+                    .extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+                {
+                    anyhow::bail!("Only PNG images can be retargeted: {}", file);
+                }
+                let image = load_png(&dir.join(file))?.premultiplied();
+                let scaled = scale_bitmap(&image, *width, *height);
+                save_bitmap(
+                    &scaled,
+                    &workspace.file(file),
+                    ImageFormat::Png,
+                    png_auto_gray,
+                )?;
+                images.insert(file.clone(), (*width, *height));
+                written.push(file.clone());
+            }
+        }
+
+        let info = BdnInfo {
+            video_format: video_format_from_canvas(&format!("{}x{}", canvas.0, canvas.1))
+                .to_string(),
+            normalized: info.normalized.map(|_| canvas),
+            ..info
+        };
+        let mut generator = BdnXmlGenerator::new(info.clone());
+        for event in &events {
+            generator.add_event(event);
+        }
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid file name: {}", path.display()))?
+            .to_string();
+        generator.write_to_file(&workspace.file(&name))?;
+        written.push(name.clone());
+        summary.xml_files.push((out_dir.join(&name), info));
+    }
+
+    summary.images = images.len();
+    for file in &written {
+        workspace.persist(file, &out_dir.join(file))?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdn::{SubtitleEvent, XmlStyle};
+    use crate::bitmap::{save_bitmap_as_png, BitmapData};
+
+    fn rect(
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        from: (i32, i32),
+        to: (i32, i32),
+    ) -> (i32, i32, i32, i32) {
+        retarget_rect(Normalized::of(x, y, width, height, from), to)
+    }
+
+    #[test]
+    fn test_retarget_1080_to_720() {
+        let (from, to) = ((1920, 1080), (1280, 720));
+        assert_eq!(rect(360, 930, 1200, 120, from, to), (240, 620, 800, 80));
+        // One pixel above the bottom edge stays one pixel above it (top-first rounding would
+        // give y 641 and end on the edge).
+        assert_eq!(rect(300, 961, 1320, 118, from, to), (200, 640, 880, 79));
+        // Upper captions keep their top edge.
+        assert_eq!(rect(0, 31, 1920, 118, from, to), (0, 21, 1280, 79));
+        // Off-canvas and oversized sources are clamped inside the canvas.
+        assert_eq!(rect(1800, 1000, 300, 200, from, to), (1080, 587, 200, 133));
+        assert_eq!(rect(-10, 0, 4000, 10, from, to), (0, 0, 1280, 7));
+        assert_eq!(rect(10, 10, 1, 1, from, to), (7, 7, 1, 1));
+    }
+
+    #[test]
+    fn test_retarget_480_to_1080() {
+        let (from, to) = ((720, 480), (1920, 1080));
+        assert_eq!(rect(60, 400, 600, 60, from, to), (160, 900, 1600, 135));
+        // Ending on the bottom edge: top-first rounding would give y 905 + height 176 = 1081.
+        assert_eq!(rect(60, 402, 600, 78, from, to), (160, 904, 1600, 176));
+        assert_eq!(rect(0, 20, 720, 60, from, to), (0, 45, 1920, 135));
+    }
+
+    #[test]
+    fn test_retarget_dir() {
+        let dir = crate::output::tests::scratch_dir("retarget");
+        let out = dir.join("720");
+        std::fs::create_dir(&out).unwrap();
+        save_bitmap_as_png(
+            &BitmapData {
+                data: vec![255; 8 * 4 * 4],
+                width: 8,
+                height: 4,
+                stride: 32,
+            },
+            &dir.join("r00000.png"),
+        )
+        .unwrap();
+        let event = SubtitleEvent {
+            in_tc: "00:00:01:00".to_string(),
+            out_tc: "00:00:02:00".to_string(),
+            png_file: "r00000.png".to_string(),
+            x: 960,
+            y: 1000,
+            width: 8,
+            height: 4,
+            forced: false,
+            second: None,
+        };
+        for (name, normalized) in [("plain.xml", None), ("normalized.xml", Some((1920, 1080)))] {
+            let mut g = BdnXmlGenerator::new(BdnInfo {
+                fps: 29.97,
+                video_format: "1080i".to_string(),
+                fade: None,
+                normalized,
+                provenance: None,
+                xml_style: XmlStyle::default(),
+            });
+            g.add_event(&event);
+            g.write_to_file(&dir.join(name)).unwrap();
+        }
+
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let summary = retarget_dir(&dir, &out, (960, 540), false, &ws).unwrap();
+        ws.finish().unwrap();
+        assert_eq!(summary.images, 1);
+        let infos: Vec<_> = summary
+            .xml_files
+            .iter()
+            .map(|(_, i)| i.normalized)
+            .collect();
+        assert_eq!(infos, [Some((960, 540)), None]);
+        for (path, _) in &summary.xml_files {
+            let xml = std::fs::read_to_string(path).unwrap();
+            let (info, events) = parse_bdn(&xml).unwrap();
+            assert_eq!(info.video_format, "1080p");
+            let e = &events[0];
+            assert_eq!((e.x, e.y, e.width, e.height), (480, 500, 4, 2));
+        }
+        let image = load_png(&out.join("r00000.png")).unwrap();
+        assert_eq!((image.width, image.height), (4, 2));
+        assert!(dir.join("r00000.png").exists());
+
+        let mut unknown = std::fs::read_to_string(dir.join("plain.xml")).unwrap();
+        unknown = unknown.replace("1080i", "4k");
+        std::fs::remove_file(dir.join("normalized.xml")).unwrap();
+        std::fs::write(dir.join("plain.xml"), unknown).unwrap();
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let err = retarget_dir(&dir, &out, (960, 540), false, &ws).unwrap_err();
+        assert!(err.to_string().contains("--emit-normalized"), "{}", err);
+        drop(ws);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
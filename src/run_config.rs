@@ -0,0 +1,224 @@
+//! The resolved configuration of one conversion, written as the `config` object of --stats-json
+//! so that whoever has the outputs can tell which options made them and run it again.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+
+use crate::style::json_string;
+
+/// Value names of options that take a file or directory (--redact-paths).
+const PATH_VALUE_NAMES: [&str; 3] = ["FILE", "PATH", "DIR"];
+
+/// Everything needed to reproduce one conversion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunConfig {
+    /// Tool name and version.
+    pub tool: String,
+    pub input: String,
+    /// The command line as clap resolved it (`given_arguments`).
+    pub arguments: Vec<String>,
+    /// Effective libaribcaption options, defaults and --style included.
+    pub decoder_options: BTreeMap<String, String>,
+    /// Output canvas.
+    pub canvas: (i32, i32),
+    pub fps: f64,
+    /// FFmpeg libraries loaded at run time and their versions.
+    pub ffmpeg: Vec<(&'static str, String)>,
+}
+
+impl RunConfig {
+    /// The `config` object, indented to sit at the top level of --stats-json.
+    pub fn to_json(&self) -> String {
+        let list = |items: Vec<String>| format!("[{}]", items.join(", "));
+        let object = |items: Vec<String>| format!("{{{}}}", items.join(", "));
+        let (num, den) = fps_rational(self.fps);
+        format!(
+            "{{\n    \"tool\": {},\n    \"input\": {},\n    \"arguments\": {},\n    \"decoder_options\": {},\n    \"canvas\": {{\"width\": {}, \"height\": {}}},\n    \"fps\": {{\"num\": {}, \"den\": {}}},\n    \"ffmpeg\": {}\n  }}",
+            json_string(&self.tool),
+            json_string(&self.input),
+            list(self.arguments.iter().map(|a| json_string(a)).collect()),
+            object(
+                self.decoder_options
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", json_string(k), json_string(v)))
+                    .collect()
+            ),
+            self.canvas.0,
+            self.canvas.1,
+            num,
+            den,
+            object(
+                self.ffmpeg
+                    .iter()
+                    .map(|(lib, version)| format!("{}: {}", json_string(lib), json_string(version)))
+                    .collect()
+            )
+        )
+    }
+}
+
+/// `fps` as a fraction: whole rates over 1, NTSC rates over 1001, anything else in thousandths.
+pub fn fps_rational(fps: f64) -> (u64, u64) {
+    let close = |num: f64, den: f64| (num / den - fps).abs() < 1e-6;
+    let whole = fps.round();
+    if close(whole, 1.0) {
+        return (whole as u64, 1);
+    }
+    let ntsc = (fps * 1001.0 / 1000.0).round() * 1000.0;
+    if close(ntsc, 1001.0) {
+        return (ntsc as u64, 1001);
+    }
+    let (mut a, mut b) = ((fps * 1000.0).round().max(0.0) as u64, 1000);
+    let (num, den) = (a, b);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    (num / a, den / a)
+}
+
+/// File name of `path`, for --redact-paths; `path` itself when it has none.
+pub fn redact_path(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map_or(path.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+/// The options given on the command line, in the order `command` declares them, as `--name` or
+/// `--name=value` (once per value), then the positional values. Defaults are left out. With
+/// `redact`, file and directory values keep only their file name.
+pub fn given_arguments(command: &Command, matches: &ArgMatches, redact: bool) -> Vec<String> {
+    let mut options = Vec::new();
+    let mut positionals = Vec::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let is_path = arg.is_positional()
+            || arg
+                .get_value_names()
+                .is_some_and(|names| names.iter().any(|n| PATH_VALUE_NAMES.contains(&n.as_str())));
+        let values: Vec<String> = match matches.get_raw(id) {
+            Some(raw) if arg.get_action().takes_values() => raw
+                .map(|v| v.to_string_lossy().into_owned())
+                .map(|v| {
+                    if redact && is_path {
+                        redact_path(&v)
+                    } else {
+                        v
+                    }
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        match arg.get_long() {
+            Some(long) if values.is_empty() => options.push(format!("--{}", long)),
+            Some(long) => options.extend(values.iter().map(|v| format!("--{}={}", long, v))),
+            None => positionals.extend(values),
+        }
+    }
+    options.extend(positionals);
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::Json;
+    use clap::{Arg, ArgAction};
+
+    fn command() -> Command {
+        Command::new("t")
+            .arg(Arg::new("flag").long("flag").action(ArgAction::SetTrue))
+            .arg(Arg::new("rate").long("frame-rate").default_value("auto"))
+            .arg(
+                Arg::new("delay")
+                    .long("global-delay")
+                    .allow_hyphen_values(true),
+            )
+            .arg(Arg::new("out").long("output").value_name("DIR"))
+            .arg(
+                Arg::new("params")
+                    .long("arib-params")
+                    .action(ArgAction::Append),
+            )
+            .arg(Arg::new("inputs").num_args(1..))
+    }
+
+    #[test]
+    fn test_given_arguments() {
+        let argv = [
+            "t",
+            "/media/rec/ep01.ts",
+            "--arib-params",
+            "a=1",
+            "--output=/home/me/out",
+            "--global-delay",
+            "-250",
+            "--flag",
+            "--arib-params=b=2",
+        ];
+        let matches = command().try_get_matches_from(argv).unwrap();
+        // Declaration order, defaults left out, inputs last.
+        let expected = [
+            "--flag",
+            "--global-delay=-250",
+            "--output=/home/me/out",
+            "--arib-params=a=1",
+            "--arib-params=b=2",
+            "/media/rec/ep01.ts",
+        ];
+        assert_eq!(given_arguments(&command(), &matches, false), expected);
+        let redacted = given_arguments(&command(), &matches, true);
+        assert_eq!(redacted[2], "--output=out");
+        assert_eq!(redacted[5], "ep01.ts");
+
+        // The normalized form parses back to the same options.
+        let mut again = vec!["t".to_string()];
+        again.extend(expected.map(String::from));
+        let reparsed = command().try_get_matches_from(again).unwrap();
+        assert_eq!(given_arguments(&command(), &reparsed, false), expected);
+    }
+
+    #[test]
+    fn test_fps_rational() {
+        assert_eq!(fps_rational(30000.0 / 1001.0), (30000, 1001));
+        assert_eq!(fps_rational(24000.0 / 1001.0), (24000, 1001));
+        assert_eq!(fps_rational(25.0), (25, 1));
+        assert_eq!(fps_rational(12.5), (25, 2));
+    }
+
+    #[test]
+    fn test_run_config_json() {
+        let config = RunConfig {
+            tool: "arib2bdnxml 0.2.2".to_string(),
+            input: "ep01.ts".to_string(),
+            arguments: vec!["--dedup-png".to_string(), "ep01.ts".to_string()],
+            decoder_options: [("canvas_size", "1920x1080"), ("font", "A \"B\"")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into(),
+            canvas: (1920, 1080),
+            fps: 30000.0 / 1001.0,
+            ffmpeg: vec![
+                ("ffmpeg", "7.1".to_string()),
+                ("libavcodec", "61.19.100".to_string()),
+            ],
+        };
+        let json = Json::parse(&config.to_json()).unwrap();
+        assert_eq!(json.get("tool").unwrap().as_str(), Ok("arib2bdnxml 0.2.2"));
+        let arguments = json.get("arguments").unwrap().as_array().unwrap();
+        assert_eq!(arguments[0].as_str(), Ok("--dedup-png"));
+        let options = json.get("decoder_options").unwrap();
+        assert_eq!(options.get("font").unwrap().as_str(), Ok("A \"B\""));
+        let fps = json.get("fps").unwrap();
+        assert_eq!(fps.get("num").unwrap().as_u64(), Ok(30000));
+        assert_eq!(fps.get("den").unwrap().as_u64(), Ok(1001));
+        let canvas = json.get("canvas").unwrap();
+        assert_eq!(canvas.get("height").unwrap().as_u64(), Ok(1080));
+        let ffmpeg = json.get("ffmpeg").unwrap();
+        assert_eq!(ffmpeg.get("libavcodec").unwrap().as_str(), Ok("61.19.100"));
+    }
+}
@@ -0,0 +1,526 @@
+//! --shard N/M: converts one time slice of a long input, so M processes can share its demux and
+//! decode, and the `combine` subcommand joining their outputs into one BDN XML.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::bdn::{graphic_ref, parse_bdn, BdnInfo, BdnXmlGenerator, GraphicRef, SubtitleEvent};
+use crate::bitmap::{ImageFormat, MAX_IMAGE_INDEX};
+use crate::checkpoint::Json;
+use crate::event_map::renumber_images;
+use crate::style::json_string;
+use crate::workspace::TempWorkspace;
+
+/// Seconds decoded before a slice and discarded, so the decoder has seen the caption management
+/// data and DRCS patterns in use when the slice starts.
+pub const SHARD_PREROLL: f64 = 10.0;
+
+/// Images of shard N are numbered from N times this, so shards never share a file name.
+pub const SHARD_INDEX_STRIDE: usize = 10_000;
+
+/// Most shards whose image numbers stay within 5 digits.
+pub const MAX_SHARDS: u32 = ((MAX_IMAGE_INDEX + 1) / SHARD_INDEX_STRIDE) as u32;
+
+/// Suffix of the partial manifest written next to the BDN XML of a shard.
+pub const MANIFEST_SUFFIX: &str = ".shard.json";
+
+/// Slice `index` (from 0) of `count` equal slices of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+/// Parses --shard (`N/M`, 0 <= N < M).
+pub fn parse_shard(s: &str) -> Result<Shard, String> {
+    let (n, m) = s
+        .split_once('/')
+        .ok_or_else(|| format!("expected N/M, got '{}'", s))?;
+    let number = |v: &str| {
+        v.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("expected N/M, got '{}'", s))
+    };
+    let (index, count) = (number(n)?, number(m)?);
+    if count == 0 || count > MAX_SHARDS {
+        return Err(format!("M must be 1-{}, got {}", MAX_SHARDS, count));
+    }
+    if index >= count {
+        return Err(format!(
+            "N must be below M (0-{}), got {}",
+            count - 1,
+            index
+        ));
+    }
+    Ok(Shard { index, count })
+}
+
+impl Shard {
+    /// Stream time range `[start, end)` of this slice of an input starting at `start` and lasting
+    /// `duration` seconds. The last slice is open-ended, so nothing past the duration is lost.
+    pub fn range(&self, start: f64, duration: f64) -> (f64, f64) {
+        let at = |i: u32| start + duration * i as f64 / self.count as f64;
+        let end = if self.index + 1 == self.count {
+            f64::INFINITY
+        } else {
+            at(self.index + 1)
+        };
+        (at(self.index), end)
+    }
+
+    /// Number of this shard's first image.
+    pub fn start_index(&self) -> usize {
+        self.index as usize * SHARD_INDEX_STRIDE
+    }
+}
+
+/// What a shard wrote, read by `combine`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardManifest {
+    pub shard: Shard,
+    /// Stream time range of the slice; `end` is None for the last one.
+    pub start: f64,
+    pub end: Option<f64>,
+    /// File name of the BDN XML, next to the manifest.
+    pub xml: String,
+    /// Base name of the images.
+    pub base_name: String,
+    /// Packet pts of the source frame of each event, in XML order.
+    pub pts: Vec<Option<i64>>,
+}
+
+impl ShardManifest {
+    pub fn to_json(&self) -> String {
+        let pts: Vec<String> = (self.pts.iter())
+            .map(|p| p.map_or("null".to_string(), |p| p.to_string()))
+            .collect();
+        format!(
+            "{{\n  \"shard\": {},\n  \"count\": {},\n  \"start\": {},\n  \"end\": {},\n  \"xml\": {},\n  \"base_name\": {},\n  \"pts\": [{}]\n}}\n",
+            self.shard.index,
+            self.shard.count,
+            self.start,
+            self.end.map_or("null".to_string(), |e| e.to_string()),
+            json_string(&self.xml),
+            json_string(&self.base_name),
+            pts.join(", ")
+        )
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let root = Json::parse(contents)?;
+        let (index, count) = (root.get("shard")?.as_u64()?, root.get("count")?.as_u64()?);
+        let shard = parse_shard(&format!("{}/{}", index, count))?;
+        let end = match root.get("end")? {
+            Json::Null => None,
+            end => Some(end.as_f64()?),
+        };
+        let pts = (root.get("pts")?.as_array()?.iter())
+            .map(|p| match p {
+                Json::Null => Ok(None),
+                p => p.as_i64().map(Some),
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(ShardManifest {
+            shard,
+            start: root.get("start")?.as_f64()?,
+            end,
+            xml: root.get("xml")?.as_str()?.to_string(),
+            base_name: root.get("base_name")?.as_str()?.to_string(),
+            pts,
+        })
+    }
+}
+
+/// Joins the events of every shard, in shard order, into one list. A shard decodes up to the
+/// first caption after its slice so its last event gets a proper end; that caption is also the
+/// first of the next shard. Events are matched by the packet pts of their source frame, given
+/// with the events: one whose pts is also in a later shard is dropped, so such boundary events
+/// are kept once, from the shard that starts with them. Events without a pts are always kept.
+/// Returns the kept events with the index of their shard, and the number dropped.
+pub fn merge_shard_events(
+    shards: Vec<(Vec<SubtitleEvent>, Vec<Option<i64>>)>,
+) -> (Vec<(usize, SubtitleEvent)>, usize) {
+    let mut later = HashSet::new();
+    let mut kept = Vec::new();
+    let mut dropped = 0;
+    for (i, (events, pts)) in shards.into_iter().enumerate().rev() {
+        let mut shard = Vec::new();
+        for (event, p) in events.into_iter().zip(&pts) {
+            if p.is_some_and(|p| later.contains(&p)) {
+                dropped += 1;
+            } else {
+                shard.push((i, event));
+            }
+        }
+        later.extend(pts.into_iter().flatten());
+        kept.push(shard);
+    }
+    (kept.into_iter().rev().flatten().collect(), dropped)
+}
+
+/// Result of `combine_dirs`.
+#[derive(Debug, Default)]
+pub struct CombineSummary {
+    /// Written XML files, their info and their event count.
+    pub xml_files: Vec<(PathBuf, BdnInfo, usize)>,
+    pub images: usize,
+    /// Boundary events found in two shards.
+    pub duplicates: usize,
+}
+
+/// Reads the shard manifests in `dirs` and writes each complete set of shards to `out_dir` as one
+/// BDN XML, its images numbered from `start_index` (through `workspace`, like `retarget_dir`).
+pub fn combine_dirs(
+    dirs: &[PathBuf],
+    out_dir: &Path,
+    start_index: usize,
+    workspace: &TempWorkspace,
+) -> anyhow::Result<CombineSummary> {
+    // Shards of the same output share the XML name.
+    let mut sets: BTreeMap<String, Vec<(PathBuf, ShardManifest)>> = BTreeMap::new();
+    for dir in dirs {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to read directory: {}: {}", dir.display(), e))?;
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            if !path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.ends_with(MANIFEST_SUFFIX))
+            {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))?;
+            let manifest = ShardManifest::parse(&contents).map_err(|e| {
+                anyhow::anyhow!("Invalid shard manifest: {}: {}", path.display(), e)
+            })?;
+            let set = sets.entry(manifest.xml.clone()).or_default();
+            set.push((dir.clone(), manifest));
+        }
+    }
+    if sets.is_empty() {
+        anyhow::bail!("No shard manifests (*{}) found.", MANIFEST_SUFFIX);
+    }
+
+    let mut summary = CombineSummary::default();
+    let mut written = Vec::new();
+    for (xml_name, mut set) in sets {
+        set.sort_by_key(|(_, m)| m.shard.index);
+        let count = set[0].1.shard.count;
+        let indices: Vec<u32> = set.iter().map(|(_, m)| m.shard.index).collect();
+        if set.iter().any(|(_, m)| m.shard.count != count)
+            || indices != (0..count).collect::<Vec<_>>()
+        {
+            anyhow::bail!(
+                "{}: expected shards 0-{} of {}, found {:?}",
+                xml_name,
+                count - 1,
+                count,
+                indices
+            );
+        }
+
+        let mut info: Option<BdnInfo> = None;
+        let mut shards = Vec::new();
+        for (dir, manifest) in &set {
+            let path = dir.join(&manifest.xml);
+            let xml = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))?;
+            let (shard_info, events) =
+                parse_bdn(&xml).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+            if events.len() != manifest.pts.len() {
+                anyhow::bail!(
+                    "{}: {} event(s), but the shard manifest lists {}",
+                    path.display(),
+                    events.len(),
+                    manifest.pts.len()
+                );
+            }
+            match &info {
+                Some(first)
+                    if first.fps != shard_info.fps
+                        || first.video_format != shard_info.video_format =>
+                {
+                    anyhow::bail!(
+                        "{}: frame rate or video format differs from shard 0",
+                        path.display()
+                    );
+                }
+                Some(_) => {}
+                None => info = Some(shard_info),
+            }
+            shards.push((events, manifest.pts.clone()));
+        }
+        let info = info.unwrap_or_else(|| unreachable!("a set has at least one shard"));
+        let (merged, duplicates) = merge_shard_events(shards);
+        summary.duplicates += duplicates;
+
+        // Source directory of every image, by its name in the shard output.
+        let mut sources: HashMap<String, &Path> = HashMap::new();
+        for (shard, event) in &merged {
+            let files =
+                std::iter::once(&event.png_file).chain(event.second.iter().map(|g| &g.png_file));
+            for file in files {
+                if graphic_ref(file) == GraphicRef::External {
+                    anyhow::bail!(
+                        "{}: graphic {} is outside the XML's directory; combine writes every image next to the new XML",
+                        xml_name,
+                        file
+                    );
+                }
+                sources.insert(file.clone(), &set[*shard].0);
+            }
+        }
+        let mut events: Vec<SubtitleEvent> = merged.into_iter().map(|(_, e)| e).collect();
+        let format = match Path::new(&events.first().map_or("", |e| e.png_file.as_str()))
+            .extension()
+            .and_then(|e| e.to_str())
+        {
+            Some("tif") => ImageFormat::Tiff,
+            _ => ImageFormat::Png,
+        };
+        let base_name = &set[0].1.base_name;
+        let renames: HashMap<String, String> =
+            renumber_images(&mut events, start_index, base_name, format)
+                .into_iter()
+                .collect();
+        for (old, dir) in &sources {
+            let new = renames.get(old).unwrap_or(old);
+            let source = dir.join(old);
+            std::fs::copy(&source, workspace.file(new))
+                .map_err(|e| anyhow::anyhow!("Failed to copy {}: {}", source.display(), e))?;
+            written.push(new.clone());
+        }
+        summary.images += sources.len();
+
+        let mut generator = BdnXmlGenerator::new(info.clone());
+        for event in &events {
+            generator.add_event(event);
+        }
+        generator.write_to_file(&workspace.file(&xml_name))?;
+        written.push(xml_name.clone());
+        summary
+            .xml_files
+            .push((out_dir.join(&xml_name), info, events.len()));
+    }
+
+    for file in &written {
+        workspace.persist(file, &out_dir.join(file))?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdn::XmlStyle;
+    use crate::bitmap::{save_bitmap_as_png, BitmapData};
+
+    fn event(in_tc: &str, out_tc: &str, png_file: &str) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: png_file.to_string(),
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 10,
+            forced: false,
+            second: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_shard() {
+        assert_eq!(parse_shard("1/4"), Ok(Shard { index: 1, count: 4 }));
+        assert_eq!(parse_shard("0/1"), Ok(Shard { index: 0, count: 1 }));
+        assert!(parse_shard("4/4").is_err());
+        assert!(parse_shard("0/0").is_err());
+        assert!(parse_shard("0/11").is_err());
+        assert!(parse_shard("1").is_err());
+        assert!(parse_shard("a/2").is_err());
+    }
+
+    #[test]
+    fn test_shard_range() {
+        // A 6-hour recording starting at 1.4s, in 4 slices.
+        let duration = 6.0 * 3600.0;
+        let ranges: Vec<(f64, f64)> = (0..4)
+            .map(|index| Shard { index, count: 4 }.range(1.4, duration))
+            .collect();
+        assert_eq!(ranges[0], (1.4, 1.4 + 5400.0));
+        // Slices meet exactly, and the last one takes everything after its start.
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].1, pair[1].0);
+        }
+        assert_eq!(ranges[3], (1.4 + 16200.0, f64::INFINITY));
+        assert_eq!(Shard { index: 3, count: 4 }.start_index(), 30_000);
+    }
+
+    #[test]
+    fn test_merge_shard_events() {
+        let shards = vec![
+            (
+                vec![
+                    event("00:00:01:00", "00:00:03:00", "a00000.png"),
+                    // Still shown when the slice ends; the next shard starts after it.
+                    event("00:29:58:00", "00:30:02:00", "a00001.png"),
+                    // Decoded past the slice to end the one before: the next shard's first event.
+                    event("00:30:05:00", "00:30:06:00", "a00002.png"),
+                ],
+                vec![Some(90_000), Some(161_820_000), Some(162_450_000)],
+            ),
+            // Nothing started in this slice.
+            (vec![], vec![]),
+            (
+                vec![
+                    event("00:30:05:00", "00:30:09:00", "a20000.png"),
+                    event("00:45:00:00", "00:45:02:00", "a20001.png"),
+                ],
+                vec![Some(162_450_000), Some(243_000_000)],
+            ),
+        ];
+        let (merged, dropped) = merge_shard_events(shards);
+        assert_eq!(dropped, 1);
+        let kept: Vec<(usize, &str)> = merged
+            .iter()
+            .map(|(shard, e)| (*shard, e.png_file.as_str()))
+            .collect();
+        let expected = [
+            (0, "a00000.png"),
+            (0, "a00001.png"),
+            (2, "a20000.png"),
+            (2, "a20001.png"),
+        ];
+        assert_eq!(kept, expected);
+        // The kept copy of the boundary event has its full display time.
+        assert_eq!(merged[2].1.out_tc, "00:30:09:00");
+    }
+
+    #[test]
+    fn test_merge_shard_events_same_in_tc() {
+        // Two captions in the same frame, or a later one mapped before the next shard's first
+        // by --tc-map: only the same source packet is a duplicate.
+        let shards = vec![
+            (
+                vec![
+                    event("00:10:00:00", "00:10:01:00", "a00000.png"),
+                    event("00:10:02:00", "00:10:03:00", "a00001.png"),
+                    event("00:10:00:00", "00:10:04:00", "a00002.png"),
+                ],
+                vec![Some(1_000), Some(2_000), None],
+            ),
+            (
+                vec![event("00:10:00:00", "00:10:05:00", "a10000.png")],
+                vec![Some(3_000)],
+            ),
+        ];
+        let (merged, dropped) = merge_shard_events(shards);
+        assert_eq!((merged.len(), dropped), (4, 0));
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let manifest = ShardManifest {
+            shard: Shard { index: 3, count: 4 },
+            start: 16201.4,
+            end: None,
+            xml: "ep01.xml".to_string(),
+            base_name: "ep01".to_string(),
+            pts: vec![Some(1_458_126_000), None, Some(8_589_934_592)],
+        };
+        assert_eq!(
+            ShardManifest::parse(&manifest.to_json()),
+            Ok(manifest.clone())
+        );
+        let first = ShardManifest {
+            shard: Shard { index: 0, count: 4 },
+            end: Some(5401.4),
+            pts: Vec::new(),
+            ..manifest
+        };
+        assert_eq!(ShardManifest::parse(&first.to_json()), Ok(first));
+        assert!(ShardManifest::parse("{\"shard\": 4, \"count\": 4}").is_err());
+    }
+
+    #[test]
+    fn test_combine_dirs() {
+        let dir = crate::output::tests::scratch_dir("combine");
+        let bitmap = BitmapData {
+            data: vec![255; 4 * 2 * 4],
+            width: 4,
+            height: 2,
+            stride: 16,
+        };
+        let shards = [
+            vec![
+                event("00:00:01:00", "00:00:02:00", "ep00000.png"),
+                event("00:00:05:00", "00:00:06:00", "ep00001.png"),
+            ],
+            vec![event("00:00:05:00", "00:00:08:00", "ep10000.png")],
+        ];
+        let pts = [vec![Some(90_000), Some(450_000)], vec![Some(450_000)]];
+        let mut dirs = Vec::new();
+        for (index, events) in shards.iter().enumerate() {
+            let shard_dir = dir.join(format!("shard{}", index));
+            std::fs::create_dir(&shard_dir).unwrap();
+            let mut g = BdnXmlGenerator::new(BdnInfo {
+                fps: 30.0,
+                video_format: "1080p".to_string(),
+                fade: None,
+                normalized: None,
+                provenance: None,
+                xml_style: XmlStyle::default(),
+            });
+            for event in events {
+                save_bitmap_as_png(&bitmap, &shard_dir.join(&event.png_file)).unwrap();
+                g.add_event(event);
+            }
+            g.write_to_file(&shard_dir.join("ep.xml")).unwrap();
+            let manifest = ShardManifest {
+                shard: Shard {
+                    index: index as u32,
+                    count: 2,
+                },
+                start: index as f64 * 4.0,
+                end: (index == 0).then_some(4.0),
+                xml: "ep.xml".to_string(),
+                base_name: "ep".to_string(),
+                pts: pts[index].clone(),
+            };
+            std::fs::write(shard_dir.join("ep.shard.json"), manifest.to_json()).unwrap();
+            dirs.push(shard_dir);
+        }
+        let out = dir.join("out");
+        std::fs::create_dir(&out).unwrap();
+
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let summary = combine_dirs(&dirs, &out, 0, &ws).unwrap();
+        ws.finish().unwrap();
+        assert_eq!((summary.images, summary.duplicates), (2, 1));
+        let xml = std::fs::read_to_string(out.join("ep.xml")).unwrap();
+        let (_, events) = parse_bdn(&xml).unwrap();
+        let files: Vec<&str> = events.iter().map(|e| e.png_file.as_str()).collect();
+        assert_eq!(files, ["ep00000.png", "ep00001.png"]);
+        assert_eq!(events[1].out_tc, "00:00:08:00");
+        assert!(out.join("ep00001.png").exists());
+        assert!(!out.join("ep10000.png").exists());
+
+        // So is a manifest that does not match its XML.
+        let manifest = std::fs::read_to_string(dirs[1].join("ep.shard.json")).unwrap();
+        let manifest = manifest.replace("[450000]", "[]");
+        std::fs::write(dirs[1].join("ep.shard.json"), manifest).unwrap();
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let err = combine_dirs(&dirs, &out, 0, &ws).unwrap_err();
+        assert!(err.to_string().contains("manifest lists 0"), "{}", err);
+        drop(ws);
+
+        // A missing shard is an error.
+        std::fs::remove_file(dirs[1].join("ep.shard.json")).unwrap();
+        let ws = TempWorkspace::create(&dir, false).unwrap();
+        let err = combine_dirs(&dirs, &out, 0, &ws).unwrap_err();
+        assert!(err.to_string().contains("expected shards 0-1"), "{}", err);
+        drop(ws);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! Creating output files and directories with the --output-mode permissions. Without the option
+//! the umask decides, as for any other program; with it every output file and directory gets
+//! the given mode right after it is created, whichever thread writes it.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Stored when --output-mode is not given.
+const NO_MODE: u32 = u32::MAX;
+
+/// The --output-mode of the running conversion, shared by the encoder threads.
+static OUTPUT_MODE: AtomicU32 = AtomicU32::new(NO_MODE);
+
+/// Parses --output-mode: an octal mode such as `0644` or `664`.
+pub fn parse_output_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if !digits.is_empty() && mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("expected an octal mode such as 0644, got '{}'", s)),
+    }
+}
+
+/// Sets the mode applied from now on; None leaves permissions to the umask.
+pub fn set_output_mode(mode: Option<u32>) {
+    OUTPUT_MODE.store(mode.unwrap_or(NO_MODE), Ordering::Relaxed);
+}
+
+pub fn output_mode() -> Option<u32> {
+    Some(OUTPUT_MODE.load(Ordering::Relaxed)).filter(|&m| m != NO_MODE)
+}
+
+/// `mode` with execute permission for whoever may read (`chmod +X` style): the mode of
+/// directories and of executable files.
+pub fn with_execute(mode: u32) -> u32 {
+    mode | (mode & 0o444) >> 2
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, directory: bool) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let Some(mode) = output_mode() else {
+        return Ok(());
+    };
+    let executable = directory || std::fs::metadata(path)?.permissions().mode() & 0o111 != 0;
+    let mode = if executable { with_execute(mode) } else { mode };
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _directory: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// Applies the output mode to an output file written some other way (e.g. moved into place).
+/// Executable files keep execute permission.
+pub fn apply_file_mode(path: &Path) -> io::Result<()> {
+    set_mode(path, false)
+}
+
+/// `File::create` with the output mode.
+pub fn create(path: &Path) -> io::Result<File> {
+    let file = File::create(path)?;
+    set_mode(path, false)?;
+    Ok(file)
+}
+
+/// `std::fs::write` with the output mode.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    std::fs::write(path, contents)?;
+    set_mode(path, false)
+}
+
+/// `std::fs::create_dir_all` with the output mode (plus execute) on each directory it creates;
+/// existing directories are left as they are.
+pub fn create_dir_all(path: &Path) -> io::Result<()> {
+    if path.as_os_str().is_empty() || path.is_dir() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    match std::fs::create_dir(path) {
+        Ok(()) => set_mode(path, true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists && path.is_dir() => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output_mode() {
+        assert_eq!(parse_output_mode("0644"), Ok(0o644));
+        assert_eq!(parse_output_mode("664"), Ok(0o664));
+        assert_eq!(parse_output_mode("0o2775"), Ok(0o2775));
+        assert!(parse_output_mode("0888").is_err());
+        assert!(parse_output_mode("17777").is_err());
+        assert!(parse_output_mode("").is_err());
+        assert_eq!(with_execute(0o644), 0o755);
+        assert_eq!(with_execute(0o640), 0o750);
+        assert_eq!(with_execute(0o2664), 0o2775);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_mode_applied() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        let dir = crate::output::tests::scratch_dir("sink");
+        // The mode is process-wide; files other tests write meanwhile stay owner-writable.
+        set_output_mode(Some(0o640));
+        let nested = dir.join("a/b");
+        create_dir_all(&nested).unwrap();
+        write(&nested.join("x.xml"), "<BDN/>").unwrap();
+        drop(create(&nested.join("x.png")).unwrap());
+        let script = nested.join("x.sh");
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o700)).unwrap();
+        apply_file_mode(&script).unwrap();
+        set_output_mode(None);
+        assert_eq!(mode(&dir.join("a")), 0o750);
+        assert_eq!(mode(&nested), 0o750);
+        assert_eq!(mode(&nested.join("x.xml")), 0o640);
+        assert_eq!(mode(&nested.join("x.png")), 0o640);
+        assert_eq!(mode(&script), 0o750);
+        // Without the option nothing is changed.
+        let plain = dir.join("plain");
+        std::fs::write(&plain, "").unwrap();
+        std::fs::set_permissions(&plain, std::fs::Permissions::from_mode(0o600)).unwrap();
+        apply_file_mode(&plain).unwrap();
+        assert_eq!(mode(&plain), 0o600);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
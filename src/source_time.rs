@@ -0,0 +1,283 @@
+//! --emit-source-times: the broadcast wall-clock time of each event, from the TDT/TOT (time and
+//! date tables) of the input. FFmpeg does not parse those, so the start of the file is scanned
+//! for them directly and the first one found is tied to the program clock (PCR) sent before it.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use crate::stl::civil_from_days;
+use crate::style::json_string;
+
+/// Bytes of the input searched for a TDT/TOT; broadcasters send one every few seconds.
+pub const SCAN_LIMIT: u64 = 64 << 20;
+
+const TS_PACKET: usize = 188;
+/// PID carrying the TDT and TOT.
+const TIME_PID: u16 = 0x0014;
+const TDT_TABLE_ID: u8 = 0x70;
+const TOT_TABLE_ID: u8 = 0x73;
+/// Modified Julian Date of 1970-01-01.
+const MJD_UNIX_EPOCH: i64 = 40_587;
+/// Seconds of one 33-bit cycle of the 90 kHz PCR and PTS.
+const CLOCK_WRAP: f64 = (1u64 << 33) as f64 / 90_000.0;
+
+/// A TDT/TOT time and the stream time it was sent at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BroadcastClock {
+    /// PCR in seconds of the last packet before the table.
+    pub stream_time: f64,
+    /// JST (UTC+9, as ARIB sends it) in seconds since 1970-01-01 00:00 JST.
+    pub jst: i64,
+}
+
+impl BroadcastClock {
+    /// JST seconds at `stream_time` (a packet time in seconds), across a PCR wrap.
+    pub fn wall_clock(&self, stream_time: f64) -> f64 {
+        let delta = (stream_time - self.stream_time + CLOCK_WRAP / 2.0).rem_euclid(CLOCK_WRAP);
+        self.jst as f64 + delta - CLOCK_WRAP / 2.0
+    }
+}
+
+/// JST seconds of a TDT or TOT section (table id, section length, then the 40-bit
+/// MJD + BCD time); None for other tables and malformed times.
+pub fn parse_time_section(section: &[u8]) -> Option<i64> {
+    if !matches!(section.first(), Some(&TDT_TABLE_ID | &TOT_TABLE_ID)) || section.len() < 8 {
+        return None;
+    }
+    let bcd = |b: u8, max: i64| {
+        let v = (b >> 4) as i64 * 10 + (b & 0x0f) as i64;
+        (b >> 4 < 10 && b & 0x0f < 10 && v <= max).then_some(v)
+    };
+    let mjd = u16::from_be_bytes([section[3], section[4]]) as i64;
+    let (h, m, s) = (
+        bcd(section[5], 23)?,
+        bcd(section[6], 59)?,
+        bcd(section[7], 59)?,
+    );
+    Some((mjd - MJD_UNIX_EPOCH) * 86_400 + h * 3600 + m * 60 + s)
+}
+
+/// 33-bit PCR base of a TS packet, in 90 kHz units.
+fn packet_pcr(packet: &[u8]) -> Option<u64> {
+    let has_pcr = packet[3] & 0x20 != 0 && packet[4] >= 7 && packet[5] & 0x10 != 0;
+    has_pcr.then(|| {
+        let b = |i: usize| packet[i] as u64;
+        b(6) << 25 | b(7) << 17 | b(8) << 9 | b(9) << 1 | b(10) >> 7
+    })
+}
+
+/// The section starting in a TS packet, after the pointer field.
+fn packet_section(packet: &[u8]) -> Option<&[u8]> {
+    if packet[1] & 0x40 == 0 || packet[3] & 0x10 == 0 {
+        return None;
+    }
+    let start = match packet[3] & 0x20 {
+        0 => 4,
+        _ => 5 + packet[4] as usize,
+    };
+    let pointer = *packet.get(start)? as usize;
+    packet.get(start + 1 + pointer..)
+}
+
+/// The first TDT/TOT of a transport stream (188-byte packets, or 192-byte BDAV packets), with the
+/// PCR of the first PCR PID sent before it. None when there is none, or no PCR before it.
+pub fn scan_broadcast_clock(reader: impl Read) -> io::Result<Option<BroadcastClock>> {
+    let mut reader = BufReader::new(reader);
+    // Up to the second sync byte of either packet size.
+    let mut head = [0u8; 197];
+    match reader.read_exact(&mut head) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    // BDAV packets have a 4-byte timestamp before the sync byte.
+    let prefix = match (head[0], head[4]) {
+        (0x47, _) if head[188] == 0x47 => 0,
+        (_, 0x47) if head[196] == 0x47 => 4,
+        _ => return Ok(None),
+    };
+    let mut reader = io::Cursor::new(head).chain(reader);
+    let mut packet = vec![0u8; prefix + TS_PACKET];
+    let (mut pcr_pid, mut pcr) = (None, None);
+    loop {
+        match reader.read_exact(&mut packet) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let packet = &packet[prefix..];
+        if packet[0] != 0x47 {
+            return Ok(None);
+        }
+        let pid = u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff;
+        if let Some(base) = packet_pcr(packet).filter(|_| *pcr_pid.get_or_insert(pid) == pid) {
+            pcr = Some(base as f64 / 90_000.0);
+        }
+        if pid != TIME_PID {
+            continue;
+        }
+        let jst = packet_section(packet).and_then(parse_time_section);
+        if let (Some(jst), Some(stream_time)) = (jst, pcr) {
+            return Ok(Some(BroadcastClock { stream_time, jst }));
+        }
+    }
+}
+
+/// `scan_broadcast_clock` of the first `SCAN_LIMIT` bytes of `path`.
+pub fn read_broadcast_clock(path: &Path) -> io::Result<Option<BroadcastClock>> {
+    scan_broadcast_clock(File::open(path)?.take(SCAN_LIMIT))
+}
+
+/// JST seconds as ISO 8601 with milliseconds, e.g. `2026-10-16T21:00:05.250+09:00`.
+pub fn format_jst(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as i64;
+    let (secs, ms) = (millis.div_euclid(1000), millis.rem_euclid(1000));
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let time = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}+09:00",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        ms
+    )
+}
+
+/// Source times of one conversion; `clock` is None when the input has no TDT/TOT.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SourceTimes {
+    pub clock: Option<BroadcastClock>,
+}
+
+impl SourceTimes {
+    fn wall_clock(&self, stream_time: f64) -> Option<String> {
+        self.clock.map(|c| format_jst(c.wall_clock(stream_time)))
+    }
+
+    /// The `wall_clock` field of an event in --event-map.
+    pub fn json_field(&self, stream_time: Option<f64>) -> String {
+        let value = stream_time.and_then(|t| self.wall_clock(t));
+        format!(
+            "\"wall_clock\":{}",
+            value.map_or("null".to_string(), |v| json_string(&v))
+        )
+    }
+
+    /// Text of the Source comment of an event (--source-time-comments).
+    pub fn comment(&self, stream_time: f64) -> String {
+        let wall_clock = self.wall_clock(stream_time);
+        format!(
+            "Source PTS=\"{:.3}\"{}",
+            stream_time,
+            wall_clock.map_or(String::new(), |w| format!(" WallClock=\"{}\"", w))
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-16 21:00:05 JST as MJD + BCD.
+    const TOT_TIME: [u8; 5] = [0xEF, 0x91, 0x21, 0x00, 0x05];
+
+    fn packet(pid: u16, pcr: Option<u64>, section: Option<&[u8]>) -> Vec<u8> {
+        let mut p = vec![0x47, (pid >> 8) as u8, pid as u8, 0x10];
+        if section.is_some() {
+            p[1] |= 0x40;
+        }
+        if let Some(pcr) = pcr {
+            p[3] |= 0x20;
+            let b = [
+                (pcr >> 25) as u8,
+                (pcr >> 17) as u8,
+                (pcr >> 9) as u8,
+                (pcr >> 1) as u8,
+            ];
+            p.extend([7, 0x10]);
+            p.extend(b);
+            p.extend([((pcr & 1) << 7) as u8 | 0x7e, 0]);
+        }
+        if let Some(section) = section {
+            p.push(0);
+            p.extend(section);
+        }
+        p.resize(TS_PACKET, 0xff);
+        p
+    }
+
+    #[test]
+    fn test_parse_time_section() {
+        let mut tdt = vec![TDT_TABLE_ID, 0x70, 0x05];
+        tdt.extend(TOT_TIME);
+        let jst = parse_time_section(&tdt).unwrap();
+        assert_eq!(format_jst(jst as f64), "2026-10-16T21:00:05.000+09:00");
+        tdt[0] = TOT_TABLE_ID;
+        assert_eq!(parse_time_section(&tdt), Some(jst));
+        // Not a time table, and an hour that is not BCD.
+        tdt[0] = 0x42;
+        assert_eq!(parse_time_section(&tdt), None);
+        tdt[0] = TDT_TABLE_ID;
+        tdt[5] = 0x2a;
+        assert_eq!(parse_time_section(&tdt), None);
+    }
+
+    #[test]
+    fn test_scan_broadcast_clock() {
+        let mut tot = vec![TOT_TABLE_ID, 0xb0, 0x0a];
+        tot.extend(TOT_TIME);
+        // 1000.5 s, and a later PCR of another program that is ignored.
+        let mut ts = packet(0x1ff, Some(90_045_000), None);
+        ts.extend(packet(0x100, None, None));
+        ts.extend(packet(0x2ff, Some(1), None));
+        ts.extend(packet(TIME_PID, None, Some(&tot)));
+        let clock = scan_broadcast_clock(ts.as_slice()).unwrap().unwrap();
+        assert_eq!(clock.stream_time, 1000.5);
+        assert_eq!(
+            format_jst(clock.wall_clock(1012.75)),
+            "2026-10-16T21:00:17.250+09:00"
+        );
+        // Just after the PCR wrapped.
+        assert_eq!(
+            format_jst(clock.wall_clock(1000.5 - CLOCK_WRAP + 3.0)),
+            "2026-10-16T21:00:08.000+09:00"
+        );
+
+        // BDAV packets; the same clock.
+        let m2ts: Vec<u8> = ts
+            .chunks(TS_PACKET)
+            .flat_map(|p| [&[0; 4], p].concat())
+            .collect();
+        assert_eq!(scan_broadcast_clock(m2ts.as_slice()).unwrap(), Some(clock));
+        // No PCR before the table.
+        let late = [&ts[3 * TS_PACKET..], &ts[..3 * TS_PACKET]].concat();
+        assert_eq!(scan_broadcast_clock(late.as_slice()).unwrap(), None);
+        assert_eq!(scan_broadcast_clock(&b"not a stream"[..]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_source_times_output() {
+        let times = SourceTimes {
+            clock: Some(BroadcastClock {
+                stream_time: 10.0,
+                jst: 1_792_184_405,
+            }),
+        };
+        assert_eq!(
+            times.comment(12.3456),
+            "Source PTS=\"12.346\" WallClock=\"2026-10-16T21:00:07.346+09:00\""
+        );
+        assert_eq!(
+            times.json_field(Some(10.0)),
+            "\"wall_clock\":\"2026-10-16T21:00:05.000+09:00\""
+        );
+        assert_eq!(times.json_field(None), "\"wall_clock\":null");
+        let unknown = SourceTimes::default();
+        assert_eq!(unknown.comment(12.0), "Source PTS=\"12.000\"");
+        assert_eq!(unknown.json_field(Some(12.0)), "\"wall_clock\":null");
+    }
+}
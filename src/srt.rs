@@ -0,0 +1,133 @@
+//! SRT sidecar (--format srt): one cue per event whose text is its image file name, timed like
+//! the BDN XML, to preview the extraction timeline in players that cannot read BDN XML.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::bdn::{tc_to_seconds, BdnInfo, SubtitleEvent};
+
+/// Formats seconds as HH:MM:SS,mmm.
+fn format_srt_time(seconds: f64) -> String {
+    let total_ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        total_ms / 3_600_000,
+        (total_ms / 60_000) % 60,
+        (total_ms / 1000) % 60,
+        total_ms % 1000
+    )
+}
+
+/// Writes one cue per event from its InTC to its OutTC, converted at the BDN frame rate.
+pub struct SrtGenerator {
+    info: BdnInfo,
+    events: Vec<SubtitleEvent>,
+}
+
+impl SrtGenerator {
+    pub fn new(info: BdnInfo) -> Self {
+        SrtGenerator {
+            info,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn add_event(&mut self, event: &SubtitleEvent) {
+        self.events.push(event.clone());
+    }
+
+    fn render(&self) -> anyhow::Result<String> {
+        let mut out = String::new();
+        for (i, event) in self.events.iter().enumerate() {
+            let start = tc_to_seconds(&event.in_tc, self.info.fps)?;
+            let end = tc_to_seconds(&event.out_tc, self.info.fps)?;
+            out.push_str(&format!(
+                "{}\n{} --> {}\n{}\n",
+                i + 1,
+                format_srt_time(start),
+                format_srt_time(end),
+                event.png_file
+            ));
+            // --split-horizontal: the second graphic on a line of its own.
+            if let Some(second) = &event.second {
+                out.push_str(&format!("{}\n", second.png_file));
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> anyhow::Result<()> {
+        let f = crate::sink::create(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open file: {}: {}", path.display(), e))?;
+        let mut w = BufWriter::new(f);
+        w.write_all(self.render()?.as_bytes())?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdn::{Graphic, XmlStyle};
+
+    fn info(fps: f64) -> BdnInfo {
+        BdnInfo {
+            fps,
+            video_format: "1080i".to_string(),
+            fade: None,
+            normalized: None,
+            provenance: None,
+            xml_style: XmlStyle::default(),
+        }
+    }
+
+    fn event(in_tc: &str, out_tc: &str, png_file: &str) -> SubtitleEvent {
+        SubtitleEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            png_file: png_file.to_string(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            forced: false,
+            second: None,
+        }
+    }
+
+    #[test]
+    fn test_format_srt_time() {
+        assert_eq!(format_srt_time(0.0), "00:00:00,000");
+        assert_eq!(format_srt_time(3723.5), "01:02:03,500");
+        assert_eq!(format_srt_time(-1.0), "00:00:00,000");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut g = SrtGenerator::new(info(30000.0 / 1001.0));
+        g.add_event(&event("00:00:01:00", "00:00:02:10", "ep0100000.png"));
+        let mut split = event("00:10:00:00", "00:10:01:00", "ep0100001.png");
+        split.second = Some(Graphic {
+            png_file: "ep0100001_2.png".to_string(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        });
+        g.add_event(&split);
+        // Frames at 29.97 are 1001/30000 s: 00:10:00:00 is 18000 frames, 600.6 s.
+        assert_eq!(
+            g.render().unwrap(),
+            "1\n00:00:01,001 --> 00:00:02,336\nep0100000.png\n\n\
+             2\n00:10:00,600 --> 00:10:01,601\nep0100001.png\nep0100001_2.png\n\n"
+        );
+        let mut g = SrtGenerator::new(info(25.0));
+        g.add_event(&event("00:00:01:12", "00:00:02:00", "a.png"));
+        assert!(g
+            .render()
+            .unwrap()
+            .contains("00:00:01,480 --> 00:00:02,000"));
+    }
+}
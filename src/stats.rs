@@ -0,0 +1,578 @@
+//! Run statistics collected while converting one input.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::bdn::SubtitleEvent;
+use crate::event_map::EventMap;
+use crate::run_config::RunConfig;
+use crate::style::json_string;
+use crate::timing::FrameRateSource;
+
+/// Windows with the largest decoded size listed in the summary and --stats-json.
+pub const PEAK_WINDOWS_REPORTED: usize = 3;
+
+/// Warnings printed by `warning!` since the program started.
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn count_warning() {
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Warnings printed so far; the `corpus` subcommand counts them per sample.
+pub fn warnings_printed() -> usize {
+    WARNINGS.load(Ordering::Relaxed)
+}
+
+/// Image sizes of one event, both graphics of a split event together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventImageSize {
+    pub in_tc: String,
+    pub out_tc: String,
+    pub png_file: String,
+    /// Bytes of the written image files.
+    pub encoded: u64,
+    /// Bytes of the images decoded to RGBA, as a player's decoder buffer holds them.
+    pub decoded: u64,
+}
+
+/// Adjacent events (`first..=last`) and their summed decoded size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakWindow {
+    pub first: usize,
+    pub last: usize,
+    pub decoded: u64,
+}
+
+/// Sizes of each event's images; `encoded` gives the size of a written image file.
+pub fn event_image_sizes(
+    events: &[SubtitleEvent],
+    encoded: impl Fn(&str) -> u64,
+) -> Vec<EventImageSize> {
+    events
+        .iter()
+        .map(|e| {
+            let mut graphics = vec![(e.png_file.as_str(), e.width, e.height)];
+            graphics.extend(
+                e.second
+                    .as_ref()
+                    .map(|g| (g.png_file.as_str(), g.width, g.height)),
+            );
+            EventImageSize {
+                in_tc: e.in_tc.clone(),
+                out_tc: e.out_tc.clone(),
+                png_file: e.png_file.clone(),
+                encoded: graphics.iter().map(|(file, _, _)| encoded(file)).sum(),
+                decoded: graphics
+                    .iter()
+                    .map(|&(_, w, h)| w.max(0) as u64 * h.max(0) as u64 * 4)
+                    .sum(),
+            }
+        })
+        .collect()
+}
+
+/// The `top` windows of two adjacent events with the largest decoded size, largest first (earlier
+/// first on ties). A single event is its own window.
+pub fn peak_windows(sizes: &[EventImageSize], top: usize) -> Vec<PeakWindow> {
+    let mut windows: Vec<PeakWindow> = match sizes.len() {
+        0 => Vec::new(),
+        1 => vec![PeakWindow {
+            first: 0,
+            last: 0,
+            decoded: sizes[0].decoded,
+        }],
+        n => (0..n - 1)
+            .map(|i| PeakWindow {
+                first: i,
+                last: i + 1,
+                decoded: sizes[i].decoded + sizes[i + 1].decoded,
+            })
+            .collect(),
+    };
+    windows.sort_by(|a, b| b.decoded.cmp(&a.decoded).then(a.first.cmp(&b.first)));
+    windows.truncate(top);
+    windows
+}
+
+/// Display latency of the events (display start minus packet time), in seconds. Percentiles are
+/// nearest-rank.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySummary {
+    pub events: usize,
+    pub min: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    /// None without any latency.
+    pub fn of(latencies: impl IntoIterator<Item = f64>) -> Option<Self> {
+        let mut sorted: Vec<f64> = latencies.into_iter().collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+        Some(LatencySummary {
+            events: sorted.len(),
+            min: sorted[0],
+            median: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// Counters reported at the end of a run (with --debug).
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    /// Events whose end time came from the default duration (no display time, no next caption).
+    pub defaulted_durations: usize,
+    /// Subtitle frames returned by the decoder, including clear frames.
+    pub subtitle_frames: usize,
+    /// Bytes of RGBA bitmap composited by the decoder.
+    pub rgba_bytes: u64,
+    /// Frames with only text/ASS rects, skipped without ending the previous event.
+    pub non_bitmap_frames: usize,
+    /// Clear frames with no open event to end (a repeated clear, or one before any event).
+    pub ignored_clears: usize,
+    /// Captions that re-sent the previous event corrected (--correction-window).
+    pub corrections: usize,
+    /// Captions coalesced into the newest event over --max-events-per-second.
+    pub coalesced: usize,
+    /// Transitions between events made seamless by --snap-gaps.
+    pub snapped_gaps: usize,
+    /// Display times over --max-display-duration, ignored as implausible.
+    pub implausible_durations: usize,
+    /// Gaps between events widened to --min-gap.
+    pub widened_gaps: usize,
+    /// Events with the same InTC and OutTC as the previous one, moved or merged.
+    pub collisions: usize,
+    /// Graphics over the canvas or --max-graphic-size cropped to the canvas, and those scaled down.
+    pub graphics_cropped: usize,
+    pub graphics_scaled: usize,
+    /// Events moved out of the --bottom-margin and other edge bands.
+    pub margin_shifts: usize,
+    /// Events moved onto their --reference-subs cue (--snap-to-reference).
+    pub reference_snaps: usize,
+    /// (old, new) names of the images merged into a colliding event as its second graphic.
+    pub merged_images: Vec<(String, String)>,
+    /// Frame rate used for timecodes and where it came from.
+    pub frame_rate: Option<(f64, FrameRateSource)>,
+    /// --best-effort assumed a canvas or frame rate the video stream did not provide.
+    pub video_params_assumed: bool,
+    /// Bytes --png-optimize saved over the default PNG encoding.
+    pub png_bytes_saved: u64,
+    /// Images whose --png-optimize trials stopped at the time limit.
+    pub png_optimize_timeouts: usize,
+    /// Image sizes of every event, in event order.
+    pub image_sizes: Vec<EventImageSize>,
+    /// Source frame of each event and the frames dropped (--event-map).
+    pub event_map: EventMap,
+    /// The options and libraries of this run, written with --stats-json.
+    pub config: Option<RunConfig>,
+    /// Time spent opening and probing the input (what --fast-probe and --probe-cache shorten).
+    pub probe_time: Option<Duration>,
+}
+
+impl Stats {
+    /// Display latency of the events decoded in this run (not those resumed from a checkpoint).
+    pub fn display_latency(&self) -> Option<LatencySummary> {
+        LatencySummary::of(self.event_map.sources.iter().flatten().map(|s| s.latency))
+    }
+
+    /// Summary lines for the end-of-run report.
+    pub fn summary_lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Events with default duration: {}",
+            self.defaulted_durations
+        )];
+        if self.non_bitmap_frames > 0 {
+            lines.push(format!(
+                "Frames without bitmap rects (skipped): {}",
+                self.non_bitmap_frames
+            ));
+        }
+        if self.ignored_clears > 0 {
+            lines.push(format!(
+                "Clear frames without an open event (ignored): {}",
+                self.ignored_clears
+            ));
+        }
+        if self.corrections > 0 {
+            lines.push(format!(
+                "Corrected captions (re-sent within the correction window): {}",
+                self.corrections
+            ));
+        }
+        if self.coalesced > 0 {
+            lines.push(format!(
+                "Coalesced captions (over --max-events-per-second): {}",
+                self.coalesced
+            ));
+        }
+        if self.snapped_gaps > 0 {
+            lines.push(format!(
+                "Snapped transitions (within --snap-gaps frames): {}",
+                self.snapped_gaps
+            ));
+        }
+        if self.widened_gaps > 0 {
+            lines.push(format!(
+                "Widened gaps (under --min-gap): {}",
+                self.widened_gaps
+            ));
+        }
+        if self.implausible_durations > 0 {
+            lines.push(format!(
+                "Implausible display durations (over --max-display-duration, ignored): {}",
+                self.implausible_durations
+            ));
+        }
+        if self.collisions > 0 {
+            lines.push(format!(
+                "Colliding events (same InTC and OutTC): {} ({} merged)",
+                self.collisions,
+                self.merged_images.len()
+            ));
+        }
+        if self.graphics_cropped + self.graphics_scaled > 0 {
+            lines.push(format!(
+                "Oversized graphics (canvas or --max-graphic-size): {} cropped, {} scaled",
+                self.graphics_cropped, self.graphics_scaled
+            ));
+        }
+        if self.margin_shifts > 0 {
+            lines.push(format!(
+                "Moved out of the screen margins: {} events",
+                self.margin_shifts
+            ));
+        }
+        if self.reference_snaps > 0 {
+            lines.push(format!(
+                "Snapped to the reference subtitles: {} events",
+                self.reference_snaps
+            ));
+        }
+        if let Some((fps, source)) = self.frame_rate {
+            lines.push(format!("Frame rate: {:.3} ({})", fps, source));
+        }
+        if self.video_params_assumed {
+            lines.push("Video parameters: assumed (--best-effort)".to_string());
+        }
+        if let Some(l) = self.display_latency() {
+            let ms = |s: f64| (s * 1000.0).round();
+            lines.push(format!(
+                "Display latency (display start - packet time): median {} ms, p90 {} ms, p99 {} ms, range {}..{} ms ({} events)",
+                ms(l.median),
+                ms(l.p90),
+                ms(l.p99),
+                ms(l.min),
+                ms(l.max),
+                l.events
+            ));
+        }
+        if self.png_bytes_saved > 0 || self.png_optimize_timeouts > 0 {
+            lines.push(format!(
+                "PNG bytes saved by --png-optimize: {} ({} image(s) stopped at the time limit)",
+                self.png_bytes_saved, self.png_optimize_timeouts
+            ));
+        }
+        let sizes = &self.image_sizes;
+        if !sizes.is_empty() {
+            lines.push(format!(
+                "Image bytes: {} encoded, {} decoded",
+                sizes.iter().map(|s| s.encoded).sum::<u64>(),
+                sizes.iter().map(|s| s.decoded).sum::<u64>()
+            ));
+            lines.push("Largest decoded size of two adjacent events:".to_string());
+            for w in peak_windows(sizes, PEAK_WINDOWS_REPORTED) {
+                lines.push(format!(
+                    "  {} bytes: {}-{} ({}..{})",
+                    w.decoded,
+                    sizes[w.first].in_tc,
+                    sizes[w.last].out_tc,
+                    sizes[w.first].png_file,
+                    sizes[w.last].png_file
+                ));
+            }
+        }
+        lines
+    }
+
+    /// The statistics as a JSON object (--stats-json).
+    pub fn to_json(&self) -> String {
+        let sizes = &self.image_sizes;
+        let (fps, source) = match self.frame_rate {
+            Some((fps, source)) => (fps.to_string(), json_string(&source.to_string())),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        let images: Vec<String> = sizes
+            .iter()
+            .map(|s| {
+                format!(
+                    "    {{\"in_tc\":{},\"out_tc\":{},\"png_file\":{},\"encoded_bytes\":{},\"decoded_bytes\":{}}}",
+                    json_string(&s.in_tc),
+                    json_string(&s.out_tc),
+                    json_string(&s.png_file),
+                    s.encoded,
+                    s.decoded
+                )
+            })
+            .collect();
+        let peaks: Vec<String> = peak_windows(sizes, PEAK_WINDOWS_REPORTED)
+            .iter()
+            .map(|w| {
+                format!(
+                    "    {{\"in_tc\":{},\"out_tc\":{},\"first_png_file\":{},\"last_png_file\":{},\"decoded_bytes\":{}}}",
+                    json_string(&sizes[w.first].in_tc),
+                    json_string(&sizes[w.last].out_tc),
+                    json_string(&sizes[w.first].png_file),
+                    json_string(&sizes[w.last].png_file),
+                    w.decoded
+                )
+            })
+            .collect();
+        let latency = self.display_latency().map_or("null".to_string(), |l| {
+            let ms = |s: f64| (s * 1000.0).round();
+            format!(
+                "{{\"events\": {}, \"min\": {}, \"median\": {}, \"p90\": {}, \"p99\": {}, \"max\": {}}}",
+                l.events,
+                ms(l.min),
+                ms(l.median),
+                ms(l.p90),
+                ms(l.p99),
+                ms(l.max)
+            )
+        });
+        let array = |items: Vec<String>| {
+            if items.is_empty() {
+                "[]".to_string()
+            } else {
+                format!("[\n{}\n  ]", items.join(",\n"))
+            }
+        };
+        format!(
+            "{{\n  \"subtitle_frames\": {},\n  \"non_bitmap_frames\": {},\n  \"ignored_clears\": {},\n  \"corrections\": {},\n  \"coalesced\": {},\n  \"snapped_gaps\": {},\n  \"collisions\": {},\n  \"widened_gaps\": {},\n  \"implausible_durations\": {},\n  \"graphics_cropped\": {},\n  \"graphics_scaled\": {},\n  \"margin_shifts\": {},\n  \"reference_snaps\": {},\n  \"defaulted_durations\": {},\n  \"rgba_bytes\": {},\n  \"frame_rate\": {},\n  \"frame_rate_source\": {},\n  \"video_params_assumed\": {},\n  \"display_latency_ms\": {},\n  \"png_bytes_saved\": {},\n  \"encoded_bytes\": {},\n  \"decoded_bytes\": {},\n  \"images\": {},\n  \"peak_windows\": {},\n  \"config\": {}\n}}\n",
+            self.subtitle_frames,
+            self.non_bitmap_frames,
+            self.ignored_clears,
+            self.corrections,
+            self.coalesced,
+            self.snapped_gaps,
+            self.collisions,
+            self.widened_gaps,
+            self.implausible_durations,
+            self.graphics_cropped,
+            self.graphics_scaled,
+            self.margin_shifts,
+            self.reference_snaps,
+            self.defaulted_durations,
+            self.rgba_bytes,
+            fps,
+            source,
+            self.video_params_assumed,
+            latency,
+            self.png_bytes_saved,
+            sizes.iter().map(|s| s.encoded).sum::<u64>(),
+            sizes.iter().map(|s| s.decoded).sum::<u64>(),
+            array(images),
+            array(peaks),
+            self.config
+                .as_ref()
+                .map_or("null".to_string(), RunConfig::to_json)
+        )
+    }
+
+    /// Throughput lines for --benchmark over the decode loop's wall time, after the probe time.
+    pub fn throughput_lines(&self, events: usize, elapsed: Duration) -> Vec<String> {
+        let secs = elapsed.as_secs_f64();
+        let rate = |n: f64| if secs > 0.0 { n / secs } else { 0.0 };
+        let mb = self.rgba_bytes as f64 / (1024.0 * 1024.0);
+        let probe = (self.probe_time).map(|t| format!("Probe: {:.3}s", t.as_secs_f64()));
+        let mut lines: Vec<String> = probe.into_iter().collect();
+        lines.extend([
+            format!("Elapsed: {:.3}s", secs),
+            format!(
+                "Subtitle frames: {} ({:.1} frames/s)",
+                self.subtitle_frames,
+                rate(self.subtitle_frames as f64)
+            ),
+            format!("Events: {} ({:.1} events/s)", events, rate(events as f64)),
+            format!("RGBA composited: {:.1} MB ({:.1} MB/s)", mb, rate(mb)),
+        ]);
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput_lines() {
+        let stats = Stats {
+            subtitle_frames: 100,
+            rgba_bytes: 4 * 1024 * 1024,
+            ..Default::default()
+        };
+        let lines = stats.throughput_lines(50, Duration::from_secs(2));
+        assert_eq!(lines[0], "Elapsed: 2.000s");
+        assert_eq!(lines[1], "Subtitle frames: 100 (50.0 frames/s)");
+        assert_eq!(lines[2], "Events: 50 (25.0 events/s)");
+        assert_eq!(lines[3], "RGBA composited: 4.0 MB (2.0 MB/s)");
+        let probed = Stats {
+            probe_time: Some(Duration::from_millis(1250)),
+            ..stats
+        };
+        let lines = probed.throughput_lines(50, Duration::from_secs(2));
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "Probe: 1.250s");
+        assert_eq!(lines[1], "Elapsed: 2.000s");
+    }
+
+    fn sizes(decoded: &[u64]) -> Vec<EventImageSize> {
+        decoded
+            .iter()
+            .enumerate()
+            .map(|(i, &d)| EventImageSize {
+                in_tc: format!("00:00:{:02}:00", i * 2),
+                out_tc: format!("00:00:{:02}:00", i * 2 + 1),
+                png_file: format!("a{:05}.png", i),
+                encoded: d / 10,
+                decoded: d,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_event_image_sizes() {
+        let mut split = SubtitleEvent {
+            in_tc: "00:00:01:00".to_string(),
+            out_tc: "00:00:02:00".to_string(),
+            png_file: "a00000.png".to_string(),
+            x: 0,
+            y: 900,
+            width: 600,
+            height: 100,
+            forced: false,
+            second: None,
+        };
+        split.second = Some(crate::bdn::Graphic {
+            png_file: "a00000_2.png".to_string(),
+            x: 1000,
+            y: 900,
+            width: 400,
+            height: 100,
+        });
+        let encoded = |file: &str| if file == "a00000.png" { 700 } else { 300 };
+        let sizes = event_image_sizes(&[split], encoded);
+        assert_eq!(sizes[0].encoded, 1000);
+        assert_eq!(sizes[0].decoded, 1000 * 100 * 4);
+    }
+
+    #[test]
+    fn test_peak_windows() {
+        let s = sizes(&[100, 500, 50, 400, 200]);
+        assert_eq!(
+            peak_windows(&s, 2),
+            [
+                PeakWindow {
+                    first: 0,
+                    last: 1,
+                    decoded: 600,
+                },
+                PeakWindow {
+                    first: 3,
+                    last: 4,
+                    decoded: 600,
+                },
+            ]
+        );
+        assert_eq!(peak_windows(&s, 10).len(), 4);
+        assert_eq!(peak_windows(&s[..1], 3)[0].decoded, 100);
+        assert!(peak_windows(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn test_summary_and_json_report_peaks() {
+        let stats = Stats {
+            image_sizes: sizes(&[100, 500, 50]),
+            ..Default::default()
+        };
+        let lines = stats.summary_lines();
+        assert_eq!(lines[1], "Image bytes: 65 encoded, 650 decoded");
+        assert_eq!(
+            lines[3],
+            "  600 bytes: 00:00:00:00-00:00:03:00 (a00000.png..a00001.png)"
+        );
+        assert_eq!(
+            lines[4],
+            "  550 bytes: 00:00:02:00-00:00:05:00 (a00001.png..a00002.png)"
+        );
+
+        let json = stats.to_json();
+        assert!(json.contains("\"frame_rate\": null,"), "{}", json);
+        assert!(json.contains(
+            r#"    {"in_tc":"00:00:02:00","out_tc":"00:00:03:00","png_file":"a00001.png","encoded_bytes":50,"decoded_bytes":500},"#
+        ), "{}", json);
+        assert!(json.contains(
+            r#"  "peak_windows": [
+    {"in_tc":"00:00:00:00","out_tc":"00:00:03:00","first_png_file":"a00000.png","last_png_file":"a00001.png","decoded_bytes":600},"#
+        ), "{}", json);
+        let empty = Stats::default().to_json();
+        assert!(empty.contains("\"images\": [],"));
+        assert!(empty.ends_with("\"config\": null\n}\n"));
+    }
+
+    #[test]
+    fn test_display_latency() {
+        let mut latencies = vec![0.0; 90];
+        latencies.extend([0.5; 9]);
+        latencies.push(-0.02);
+        let l = LatencySummary::of(latencies).unwrap();
+        assert_eq!(
+            (l.events, l.min, l.median, l.p90, l.p99, l.max),
+            (100, -0.02, 0.0, 0.0, 0.5, 0.5)
+        );
+        assert_eq!(LatencySummary::of([]), None);
+
+        let mut stats = Stats::default();
+        for (i, latency) in [0.5, 0.0, 0.5].into_iter().enumerate() {
+            stats
+                .event_map
+                .sources
+                .push(Some(crate::event_map::SourceFrame {
+                    index: i,
+                    timestamp: i as f64,
+                    pts: None,
+                    latency,
+                }));
+        }
+        // Resumed events have no source.
+        stats.event_map.sources.push(None);
+        assert!(stats.summary_lines().contains(
+            &"Display latency (display start - packet time): median 500 ms, p90 500 ms, p99 500 ms, range 0..500 ms (3 events)".to_string()
+        ));
+        let json = stats.to_json();
+        assert!(json.contains(
+            "  \"display_latency_ms\": {\"events\": 3, \"min\": 0, \"median\": 500, \"p90\": 500, \"p99\": 500, \"max\": 500},\n"
+        ), "{}", json);
+        assert!(Stats::default()
+            .to_json()
+            .contains("\"display_latency_ms\": null,"));
+    }
+
+    #[test]
+    fn test_throughput_lines_zero_elapsed() {
+        let lines = Stats::default().throughput_lines(0, Duration::ZERO);
+        assert_eq!(lines[1], "Subtitle frames: 0 (0.0 frames/s)");
+    }
+}
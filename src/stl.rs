@@ -0,0 +1,283 @@
+//! EBU Tech 3264 STL export (--format stl): a GSI block followed by TTI blocks, one subtitle per
+//! caption from the text decode pass.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const GSI_SIZE: usize = 1024;
+const TTI_SIZE: usize = 128;
+const TEXT_FIELD_SIZE: usize = 112;
+/// Teletext CR/LF inside the text field.
+const NEWLINE: u8 = 0x8A;
+/// Unused space at the end of the text field.
+const FILLER: u8 = 0x8F;
+/// Teletext rows available for subtitles.
+const FIRST_ROW: u8 = 1;
+const LAST_ROW: u8 = 23;
+
+/// A caption as text, timed like the BDN events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEvent {
+    pub in_tc: String,
+    pub out_tc: String,
+    pub text: String,
+    /// Top of the caption as a fraction of the canvas height; None when unknown.
+    pub y: Option<f64>,
+}
+
+/// Encodes text for character code table 00 (Latin). Full-width ASCII (common in ARIB text) is
+/// folded to ASCII; other characters the table cannot hold become '?'. Returns the bytes and the
+/// number of replaced characters.
+pub fn encode_text(text: &str) -> (Vec<u8>, usize) {
+    let mut out = Vec::with_capacity(text.len());
+    let mut replaced = 0;
+    for (i, line) in text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .enumerate()
+    {
+        if i > 0 {
+            out.push(NEWLINE);
+        }
+        for c in line.chars() {
+            let c = match c {
+                '\u{3000}' => ' ',
+                '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or('?'),
+                c => c,
+            };
+            if (' '..='~').contains(&c) {
+                out.push(c as u8);
+            } else {
+                out.push(b'?');
+                replaced += 1;
+            }
+        }
+    }
+    (out, replaced)
+}
+
+/// STL disk format code for a frame rate: STL25.01 or STL30.01 (29.97 uses 30 fps timecodes).
+fn disk_format(fps: f64) -> anyhow::Result<&'static str> {
+    match fps.round() as i64 {
+        25 => Ok("STL25.01"),
+        30 => Ok("STL30.01"),
+        _ => anyhow::bail!("EBU STL supports 25 and 30 fps only (got {:.3}).", fps),
+    }
+}
+
+/// Timecode HH:MM:SS:FF as the 4 binary bytes of a TTI block.
+fn tc_bytes(tc: &str) -> anyhow::Result<[u8; 4]> {
+    let mut out = [0u8; 4];
+    let fields: Vec<&str> = tc.split(':').collect();
+    if fields.len() != 4 {
+        anyhow::bail!("invalid timecode: {}", tc);
+    }
+    for (b, f) in out.iter_mut().zip(&fields) {
+        *b = f
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid timecode: {}", tc))?;
+    }
+    Ok(out)
+}
+
+/// Teletext row for a caption: from its position when known, otherwise the bottom rows.
+fn vertical_position(y: Option<f64>, lines: usize) -> u8 {
+    let bottom = LAST_ROW
+        .saturating_sub(lines.saturating_sub(1) as u8)
+        .max(FIRST_ROW);
+    match y {
+        Some(y) if y > 0.0 => {
+            let span = (LAST_ROW - FIRST_ROW) as f64;
+            (FIRST_ROW + (y.clamp(0.0, 1.0) * span).round() as u8).min(bottom)
+        }
+        _ => bottom,
+    }
+}
+
+/// (year, month, day) of a day count since 1970-01-01 (proleptic Gregorian).
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// YYMMDD (UTC) for GSI dates.
+fn yymmdd(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:02}{:02}{:02}", year % 100, month, day)
+}
+
+/// Writes `value` left-aligned into `field`, padded with spaces and truncated to fit.
+fn put(field: &mut [u8], value: &str) {
+    field.fill(b' ');
+    let (bytes, _) = encode_text(value);
+    let n = bytes.len().min(field.len());
+    field[..n].copy_from_slice(&bytes[..n]);
+}
+
+/// Renders an STL file for `events`. Text longer than one TTI block continues in extension
+/// blocks. Returns the file and the number of characters replaced by `encode_text`.
+pub fn render_stl(
+    events: &[TextEvent],
+    fps: f64,
+    title: &str,
+    created: SystemTime,
+) -> anyhow::Result<(Vec<u8>, usize)> {
+    if events.len() > u16::MAX as usize + 1 {
+        anyhow::bail!("EBU STL holds at most {} subtitles.", u16::MAX as usize + 1);
+    }
+    let mut replaced = 0;
+    let mut tti = Vec::new();
+    let mut blocks = 0;
+    for (number, event) in events.iter().enumerate() {
+        let (text, n) = encode_text(&event.text);
+        replaced += n;
+        let tci = tc_bytes(&event.in_tc)?;
+        let tco = tc_bytes(&event.out_tc)?;
+        let lines = text.iter().filter(|&&b| b == NEWLINE).count() + 1;
+        let chunks: Vec<&[u8]> = if text.is_empty() {
+            vec![&[]]
+        } else {
+            text.chunks(TEXT_FIELD_SIZE).collect()
+        };
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut block = [0u8; TTI_SIZE];
+            // SGN 0, SN, EBN (0xFF on the last block), CS 0.
+            block[1..3].copy_from_slice(&(number as u16).to_le_bytes());
+            block[3] = if i + 1 == chunks.len() { 0xFF } else { i as u8 };
+            block[5..9].copy_from_slice(&tci);
+            block[9..13].copy_from_slice(&tco);
+            block[13] = vertical_position(event.y, lines);
+            // Centered, not a comment.
+            block[14] = 2;
+            block[15] = 0;
+            block[16..].fill(FILLER);
+            block[16..16 + chunk.len()].copy_from_slice(chunk);
+            tti.extend_from_slice(&block);
+            blocks += 1;
+        }
+    }
+
+    let mut gsi = [b' '; GSI_SIZE];
+    put(&mut gsi[0..3], "850");
+    put(&mut gsi[3..11], disk_format(fps)?);
+    // Display standard: level-1 teletext; character table 00 (Latin); language 69 (Japanese).
+    put(&mut gsi[11..12], "1");
+    put(&mut gsi[12..14], "00");
+    put(&mut gsi[14..16], "69");
+    put(&mut gsi[16..48], title);
+    let date = yymmdd(created);
+    put(&mut gsi[224..230], &date);
+    put(&mut gsi[230..236], &date);
+    put(&mut gsi[236..238], "00");
+    put(&mut gsi[238..243], &format!("{:05}", blocks));
+    put(&mut gsi[243..248], &format!("{:05}", events.len()));
+    put(&mut gsi[248..251], "001");
+    // Max characters per row and rows.
+    put(&mut gsi[251..253], "40");
+    put(&mut gsi[253..255], "23");
+    // Timecodes are intended for use; programme starts at 00:00:00:00.
+    put(&mut gsi[255..256], "1");
+    put(&mut gsi[256..264], "00000000");
+    let first = events.first().map_or("00:00:00:00", |e| e.in_tc.as_str());
+    put(&mut gsi[264..272], &first.replace(':', ""));
+    put(&mut gsi[272..273], "1");
+    put(&mut gsi[273..274], "1");
+    put(&mut gsi[274..277], "JPN");
+
+    let mut out = gsi.to_vec();
+    out.extend_from_slice(&tti);
+    Ok((out, replaced))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn event(in_tc: &str, out_tc: &str, text: &str) -> TextEvent {
+        TextEvent {
+            in_tc: in_tc.to_string(),
+            out_tc: out_tc.to_string(),
+            text: text.to_string(),
+            y: None,
+        }
+    }
+
+    #[test]
+    fn test_encode_text() {
+        assert_eq!(encode_text("Hello\nworld"), (b"Hello\x8Aworld".to_vec(), 0));
+        // Full-width ASCII and ideographic space fold to ASCII; kana cannot be encoded.
+        assert_eq!(encode_text("ＡＢＣ１\u{3000}!"), (b"ABC1 !".to_vec(), 0));
+        assert_eq!(encode_text("あa"), (b"?a".to_vec(), 1));
+        assert_eq!(encode_text("\n a \n\n"), (b"a".to_vec(), 0));
+    }
+
+    #[test]
+    fn test_yymmdd() {
+        assert_eq!(yymmdd(UNIX_EPOCH), "700101");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_164_800);
+        assert_eq!(yymmdd(leap_day), "240229");
+        let new_year = UNIX_EPOCH + Duration::from_secs(946_684_799);
+        assert_eq!(yymmdd(new_year), "991231");
+    }
+
+    #[test]
+    fn test_vertical_position() {
+        assert_eq!(vertical_position(None, 1), 23);
+        assert_eq!(vertical_position(None, 2), 22);
+        assert_eq!(vertical_position(Some(0.05), 1), 2);
+        assert_eq!(vertical_position(Some(0.95), 2), 22);
+    }
+
+    #[test]
+    fn test_render_stl() {
+        let events = [
+            event("00:00:01:00", "00:00:02:15", "Hello"),
+            event("01:02:03:04", "01:02:05:00", &"x".repeat(150)),
+        ];
+        let (stl, replaced) = render_stl(&events, 29.97, "Title", UNIX_EPOCH).unwrap();
+        assert_eq!(replaced, 0);
+        assert_eq!(stl.len(), GSI_SIZE + 3 * TTI_SIZE);
+        assert_eq!(&stl[0..11], b"850STL30.01");
+        assert_eq!(&stl[16..21], b"Title");
+        assert_eq!(&stl[224..230], b"700101");
+        // Three TTI blocks (one extension), two subtitles.
+        assert_eq!(&stl[238..248], b"0000300002");
+        assert_eq!(&stl[264..272], b"00000100");
+
+        let tti = &stl[GSI_SIZE..];
+        assert_eq!(
+            &tti[0..16],
+            &[0, 0, 0, 0xFF, 0, 0, 0, 1, 0, 0, 0, 2, 15, 23, 2, 0]
+        );
+        assert_eq!(&tti[16..21], b"Hello");
+        assert!(tti[21..TTI_SIZE].iter().all(|&b| b == FILLER));
+        let second = &tti[TTI_SIZE..];
+        assert_eq!(&second[1..4], &[1, 0, 0]);
+        assert_eq!(&second[5..9], &[1, 2, 3, 4]);
+        assert!(second[16..TTI_SIZE].iter().all(|&b| b == b'x'));
+        let third = &tti[2 * TTI_SIZE..];
+        assert_eq!(&third[1..4], &[1, 0, 0xFF]);
+        assert_eq!(&third[16..54], "x".repeat(38).as_bytes());
+        assert_eq!(third[54], FILLER);
+    }
+
+    #[test]
+    fn test_render_stl_rates() {
+        let (stl, _) = render_stl(&[], 25.0, "", UNIX_EPOCH).unwrap();
+        assert_eq!(&stl[3..11], b"STL25.01");
+        assert_eq!(stl.len(), GSI_SIZE);
+        assert!(render_stl(&[], 23.976, "", UNIX_EPOCH).is_err());
+    }
+}
@@ -0,0 +1,395 @@
+//! Caption style fingerprint (--style-report): text, background and outline colors of the
+//! first bitmaps, e.g. to tell broadcasters apart or to pick ignore_background settings.
+//! Also per-event dominant colors (--color-analysis).
+
+use std::collections::HashMap;
+
+use crate::bdn::SubtitleEvent;
+use crate::bitmap::{straight_pixel, BitmapData};
+
+/// Pixels at or above this alpha can be text.
+const OPAQUE_ALPHA: u8 = 224;
+/// The most common color is a background box when it covers at least this share of the bitmap.
+const BACKGROUND_COVERAGE: f64 = 0.4;
+/// Text has an outline when at least this share of its edge pixels border a third color.
+const OUTLINE_SHARE: f64 = 0.5;
+
+pub type Rgb = [u8; 3];
+
+/// Style of one bitmap. Colors are straight (not premultiplied).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BitmapStyle {
+    pub text: Option<Rgb>,
+    /// Background box color and alpha; None when the text is on a transparent background.
+    pub background: Option<(Rgb, u8)>,
+    pub outline: Option<Rgb>,
+}
+
+/// Colors are grouped by their top 4 bits per channel so anti-aliasing stays in one group.
+fn bucket(px: [u8; 4]) -> Rgb {
+    [px[0] >> 4, px[1] >> 4, px[2] >> 4]
+}
+
+#[derive(Debug, Default)]
+struct ColorStats {
+    count: usize,
+    sum: [u64; 4],
+}
+
+impl ColorStats {
+    fn add(&mut self, px: [u8; 4]) {
+        self.count += 1;
+        for (s, c) in self.sum.iter_mut().zip(px) {
+            *s += c as u64;
+        }
+    }
+
+    fn mean(&self) -> (Rgb, u8) {
+        let avg = |i: usize| (self.sum[i] as f64 / self.count.max(1) as f64).round() as u8;
+        ([avg(0), avg(1), avg(2)], avg(3))
+    }
+}
+
+/// Most common bucket in `hist` (ties go to the larger bucket key, for determinism).
+fn most_common(hist: &HashMap<Rgb, ColorStats>) -> Option<(Rgb, &ColorStats)> {
+    hist.iter()
+        .max_by_key(|(key, stats)| (stats.count, **key))
+        .map(|(key, stats)| (*key, stats))
+}
+
+/// Analyzes one (premultiplied) bitmap.
+pub fn analyze(bitmap: &BitmapData) -> BitmapStyle {
+    let (w, h) = (bitmap.width.max(0) as usize, bitmap.height.max(0) as usize);
+    let stride = bitmap.stride as usize;
+    let pixels: Vec<[u8; 4]> = (0..h)
+        .flat_map(|y| {
+            (0..w).map(move |x| {
+                let o = y * stride + x * 4;
+                let d = &bitmap.data;
+                straight_pixel([d[o], d[o + 1], d[o + 2], d[o + 3]])
+            })
+        })
+        .collect();
+
+    let mut visible: HashMap<Rgb, ColorStats> = HashMap::new();
+    for &px in pixels.iter().filter(|px| px[3] > 0) {
+        visible.entry(bucket(px)).or_default().add(px);
+    }
+    let background = most_common(&visible)
+        .filter(|(_, stats)| stats.count as f64 >= BACKGROUND_COVERAGE * (w * h) as f64)
+        .map(|(key, stats)| (key, stats.mean()));
+    let bg_key = background.map(|(key, _)| key);
+
+    let mut opaque: HashMap<Rgb, ColorStats> = HashMap::new();
+    for &px in pixels.iter().filter(|px| px[3] >= OPAQUE_ALPHA) {
+        if Some(bucket(px)) != bg_key {
+            opaque.entry(bucket(px)).or_default().add(px);
+        }
+    }
+    let Some((text_key, text_stats)) = most_common(&opaque) else {
+        return BitmapStyle {
+            text: None,
+            background: background.map(|(_, mean)| mean),
+            outline: None,
+        };
+    };
+
+    // Edge pixels of the text and the third colors (not text, not background) they touch.
+    let mut edges = 0;
+    let mut third: HashMap<Rgb, ColorStats> = HashMap::new();
+    for y in 0..h {
+        for x in 0..w {
+            if pixels[y * w + x][3] == 0 || bucket(pixels[y * w + x]) != text_key {
+                continue;
+            }
+            let neighbors = [
+                (x > 0).then(|| pixels[y * w + x - 1]),
+                (x + 1 < w).then(|| pixels[y * w + x + 1]),
+                (y > 0).then(|| pixels[(y - 1) * w + x]),
+                (y + 1 < h).then(|| pixels[(y + 1) * w + x]),
+            ];
+            let mut is_edge = false;
+            let mut third_px = None;
+            for n in neighbors.into_iter().flatten() {
+                if n[3] > 0 && bucket(n) == text_key {
+                    continue;
+                }
+                is_edge = true;
+                if n[3] > 0 && Some(bucket(n)) != bg_key {
+                    third_px = Some(n);
+                }
+            }
+            if is_edge {
+                edges += 1;
+                if let Some(n) = third_px {
+                    third.entry(bucket(n)).or_default().add(n);
+                }
+            }
+        }
+    }
+    let third_count: usize = third.values().map(|s| s.count).sum();
+    let outline = (edges > 0 && third_count as f64 >= OUTLINE_SHARE * edges as f64)
+        .then(|| most_common(&third).map(|(_, stats)| stats.mean().0))
+        .flatten();
+
+    BitmapStyle {
+        text: Some(text_stats.mean().0),
+        background: background.map(|(_, mean)| mean),
+        outline,
+    }
+}
+
+/// Most frequent exact color among the opaque pixels of a bitmap (straight RGB); None when it
+/// has no opaque pixels.
+pub fn dominant_color(bitmap: &BitmapData) -> Option<Rgb> {
+    let mut counts: HashMap<Rgb, usize> = HashMap::new();
+    for y in 0..bitmap.height.max(0) as usize {
+        let row = &bitmap.data[y * bitmap.stride as usize..];
+        for px in row[..bitmap.width.max(0) as usize * 4].chunks_exact(4) {
+            if px[3] >= OPAQUE_ALPHA {
+                let s = straight_pixel([px[0], px[1], px[2], px[3]]);
+                *counts.entry([s[0], s[1], s[2]]).or_default() += 1;
+            }
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(color, n)| (n, color))
+        .map(|(color, _)| color)
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Events with their dominant colors as a JSON array, one event per line (--color-analysis).
+pub fn render_color_analysis(events: &[SubtitleEvent], colors: &[Option<Rgb>]) -> String {
+    let lines: Vec<String> = events
+        .iter()
+        .zip(colors)
+        .map(|(e, color)| {
+            format!(
+                "  {{\"image\":{},\"in_tc\":\"{}\",\"out_tc\":\"{}\",\"dominant_color\":{}}}",
+                json_string(&e.png_file),
+                e.in_tc,
+                e.out_tc,
+                color.map_or("null".to_string(), hex)
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        return "[]\n".to_string();
+    }
+    format!("[\n{}\n]\n", lines.join(",\n"))
+}
+
+/// Most frequent value; ties go to the value seen first.
+fn mode<T: PartialEq + Copy>(values: impl IntoIterator<Item = T>) -> Option<T> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for v in values {
+        match counts.iter_mut().find(|(seen, _)| *seen == v) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((v, 1)),
+        }
+    }
+    let best = counts.iter().map(|(_, n)| *n).max()?;
+    counts.into_iter().find(|(_, n)| *n == best).map(|(v, _)| v)
+}
+
+fn hex(c: Rgb) -> String {
+    format!("\"#{:02X}{:02X}{:02X}\"", c[0], c[1], c[2])
+}
+
+/// Styles of the first `limit` bitmaps and their combined fingerprint.
+#[derive(Debug)]
+pub struct StyleReport {
+    limit: usize,
+    samples: Vec<BitmapStyle>,
+}
+
+impl StyleReport {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Analyzes `bitmap` unless `limit` bitmaps were already sampled.
+    pub fn add(&mut self, bitmap: &BitmapData) {
+        if self.samples.len() < self.limit {
+            self.samples.push(analyze(bitmap));
+        }
+    }
+
+    /// Fingerprint as one-line JSON: the most frequent text color, background and outline
+    /// across the samples (null when absent).
+    pub fn to_json(&self) -> String {
+        let text = mode(self.samples.iter().filter_map(|s| s.text));
+        let background = mode(self.samples.iter().map(|s| s.background)).flatten();
+        let outline = mode(self.samples.iter().map(|s| s.outline)).flatten();
+        format!(
+            "{{\"samples\":{},\"text_color\":{},\"background\":{},\"outline_color\":{}}}",
+            self.samples.len(),
+            text.map_or("null".to_string(), hex),
+            background.map_or("null".to_string(), |(c, a)| format!(
+                "{{\"color\":{},\"alpha\":{}}}",
+                hex(c),
+                a
+            )),
+            outline.map_or("null".to_string(), hex)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+    /// (x, y, w, h) and the premultiplied pixel to fill it with.
+    type Rect = ((i32, i32, i32, i32), [u8; 4]);
+
+    /// `width` x `height` bitmap of `fill` with the rects drawn in order.
+    fn bitmap(width: i32, height: i32, fill: [u8; 4], rects: &[Rect]) -> BitmapData {
+        let mut data = fill.repeat((width * height) as usize);
+        for &((rx, ry, rw, rh), px) in rects {
+            for y in ry..ry + rh {
+                for x in rx..rx + rw {
+                    let o = ((y * width + x) * 4) as usize;
+                    data[o..o + 4].copy_from_slice(&px);
+                }
+            }
+        }
+        BitmapData {
+            data,
+            width,
+            height,
+            stride: width * 4,
+        }
+    }
+
+    #[test]
+    fn test_text_on_background_box() {
+        // Premultiplied half-transparent black box with white text.
+        let b = bitmap(20, 10, [0, 0, 0, 128], &[((7, 3, 6, 4), WHITE)]);
+        assert_eq!(
+            analyze(&b),
+            BitmapStyle {
+                text: Some([255, 255, 255]),
+                background: Some(([0, 0, 0], 128)),
+                outline: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_outlined_text_on_transparent_background() {
+        // 8x4 yellow text inside a 1px black outline.
+        let b = bitmap(
+            20,
+            10,
+            [0, 0, 0, 0],
+            &[((5, 2, 10, 6), BLACK), ((6, 3, 8, 4), [255, 255, 0, 255])],
+        );
+        assert_eq!(
+            analyze(&b),
+            BitmapStyle {
+                text: Some([255, 255, 0]),
+                background: None,
+                outline: Some([0, 0, 0]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_premultiplied_background_color() {
+        let b = bitmap(20, 10, [64, 0, 0, 64], &[((7, 3, 6, 4), WHITE)]);
+        assert_eq!(analyze(&b).background, Some(([255, 0, 0], 64)));
+    }
+
+    #[test]
+    fn test_empty_and_transparent_bitmaps() {
+        assert_eq!(analyze(&bitmap(0, 0, WHITE, &[])), BitmapStyle::default());
+        assert_eq!(analyze(&bitmap(4, 4, [0, 0, 0, 0], &[])), BitmapStyle::default());
+    }
+
+    #[test]
+    fn test_dominant_color() {
+        // Yellow text outlined in black; the half-transparent shadow is not counted.
+        let b = bitmap(
+            20,
+            10,
+            [0, 0, 0, 64],
+            &[((5, 2, 10, 6), BLACK), ((6, 3, 8, 4), [255, 255, 0, 255])],
+        );
+        assert_eq!(dominant_color(&b), Some([255, 255, 0]));
+        assert_eq!(dominant_color(&bitmap(4, 4, [0, 0, 0, 128], &[])), None);
+        assert_eq!(dominant_color(&bitmap(0, 0, WHITE, &[])), None);
+        // Stride wider than the visible row: padding is ignored.
+        let mut padded = bitmap(3, 1, WHITE, &[]);
+        padded.width = 1;
+        padded.data[4..].copy_from_slice(&[BLACK, BLACK].concat());
+        assert_eq!(dominant_color(&padded), Some([255, 255, 255]));
+    }
+
+    #[test]
+    fn test_render_color_analysis() {
+        let event = |png: &str| SubtitleEvent {
+            in_tc: "00:00:01:00".to_string(),
+            out_tc: "00:00:02:00".to_string(),
+            png_file: png.to_string(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            forced: false,
+            second: None,
+        };
+        let json = render_color_analysis(
+            &[event("a00000.png"), event("b\"00001.png")],
+            &[Some([255, 255, 0]), None],
+        );
+        assert_eq!(
+            json,
+            concat!(
+                "[\n",
+                r##"  {"image":"a00000.png","in_tc":"00:00:01:00","out_tc":"00:00:02:00","dominant_color":"#FFFF00"},"##,
+                "\n",
+                r##"  {"image":"b\"00001.png","in_tc":"00:00:01:00","out_tc":"00:00:02:00","dominant_color":null}"##,
+                "\n]\n"
+            )
+        );
+        assert_eq!(render_color_analysis(&[], &[]), "[]\n");
+    }
+
+    #[test]
+    fn test_report_json_and_limit() {
+        let boxed = bitmap(20, 10, [0, 0, 0, 128], &[((7, 3, 6, 4), WHITE)]);
+        let plain = bitmap(20, 10, [0, 0, 0, 0], &[((7, 3, 6, 4), [0, 255, 255, 255])]);
+        let mut report = StyleReport::new(3);
+        for b in [&boxed, &plain, &boxed, &plain, &plain] {
+            report.add(b);
+        }
+        assert_eq!(
+            report.to_json(),
+            r##"{"samples":3,"text_color":"#FFFFFF","background":{"color":"#000000","alpha":128},"outline_color":null}"##
+        );
+        assert_eq!(
+            StyleReport::new(5).to_json(),
+            r#"{"samples":0,"text_color":null,"background":null,"outline_color":null}"#
+        );
+    }
+}
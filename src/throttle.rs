@@ -0,0 +1,178 @@
+//! Running politely next to other work: --limit-rate pacing and --nice process priority.
+
+use std::time::{Duration, Instant};
+
+/// Shorter waits are carried over as debt instead of sleeping, so small reads don't each sleep.
+const MIN_PACE_SLEEP: Duration = Duration::from_millis(10);
+/// Niceness set by --nice.
+#[cfg(unix)]
+const NICE_LEVEL: i32 = 10;
+
+/// What --limit-rate paces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimit {
+    /// Captions per second.
+    EventsPerSecond(f64),
+    /// Input bytes read per second.
+    BytesPerSecond(f64),
+}
+
+/// Parses "<N>" or "<N>/s" (captions per second) or "<N>MB/s" (input megabytes per second,
+/// 1 MB = 1,000,000 bytes).
+pub fn parse_rate_limit(s: &str) -> Result<RateLimit, String> {
+    let lower = s.trim().to_ascii_lowercase();
+    let value = lower.strip_suffix("/s").unwrap_or(&lower);
+    let (number, bytes) = match value.strip_suffix("mb") {
+        Some(n) => (n, true),
+        None => (value, false),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(n) if n.is_finite() && n > 0.0 => Ok(if bytes {
+            RateLimit::BytesPerSecond(n * 1_000_000.0)
+        } else {
+            RateLimit::EventsPerSecond(n)
+        }),
+        _ => Err(format!(
+            "invalid rate limit: {} (expected captions per second, e.g. 5, or MB/s, e.g. 20MB/s)",
+            s
+        )),
+    }
+}
+
+/// Token bucket holding up to one second of `rate`. Taking more than is available leaves a debt
+/// that is paid back by waiting.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, now: Instant) -> Self {
+        TokenBucket {
+            rate,
+            tokens: rate,
+            last: now,
+        }
+    }
+
+    /// Takes `amount` tokens at `now`; returns how long to wait until the debt is paid.
+    pub fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// Takes `amount` tokens, sleeping once the debt is worth a sleep.
+    pub fn pace(&mut self, amount: f64) {
+        let wait = self.take(amount, Instant::now());
+        if wait >= MIN_PACE_SLEEP {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Lowers CPU priority to nice 10 and, on Linux, sets the idle I/O class (--nice). Returns a
+/// message for each step that failed; on platforms without these calls it does nothing.
+pub fn lower_priority() -> Vec<String> {
+    let mut failures = Vec::new();
+    #[cfg(unix)]
+    unsafe {
+        let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+        if current < NICE_LEVEL && libc::setpriority(libc::PRIO_PROCESS, 0, NICE_LEVEL) != 0 {
+            failures.push(format!(
+                "could not lower CPU priority: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    #[cfg(target_os = "linux")]
+    unsafe {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        let prio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio) != 0 {
+            failures.push(format!(
+                "could not set idle I/O priority: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(parse_rate_limit("5"), Ok(RateLimit::EventsPerSecond(5.0)));
+        assert_eq!(
+            parse_rate_limit("0.5/s"),
+            Ok(RateLimit::EventsPerSecond(0.5))
+        );
+        assert_eq!(
+            parse_rate_limit("20MB/s"),
+            Ok(RateLimit::BytesPerSecond(20e6))
+        );
+        assert_eq!(
+            parse_rate_limit(" 1.5 mb "),
+            Ok(RateLimit::BytesPerSecond(1.5e6))
+        );
+        assert!(parse_rate_limit("0").is_err());
+        assert!(parse_rate_limit("-1MB/s").is_err());
+        assert!(parse_rate_limit("fast").is_err());
+        assert!(parse_rate_limit("inf").is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_allows_one_second_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, start);
+        for _ in 0..10 {
+            assert_eq!(bucket.take(1.0, start), Duration::ZERO);
+        }
+        assert_eq!(bucket.take(1.0, start), Duration::from_millis(100));
+        // Debt accumulates until it is waited off.
+        assert_eq!(bucket.take(1.0, start), Duration::from_millis(200));
+        assert_eq!(
+            bucket.take(0.0, start + Duration::from_millis(200)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_refill_is_capped() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000.0, start);
+        assert_eq!(bucket.take(1000.0, start), Duration::ZERO);
+        // A long idle period refills at most one second's worth.
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.take(1000.0, later), Duration::ZERO);
+        assert_eq!(bucket.take(500.0, later), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_token_bucket_sustained_rate() {
+        // 2 MB/s with 188-byte TS packets, waiting as told for 10 simulated seconds: apart from
+        // the initial one-second burst, the average is the configured rate.
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2e6, start);
+        let mut now = start;
+        let mut bytes = 0.0;
+        while now < start + Duration::from_secs(10) {
+            now += bucket.take(188.0, now);
+            bytes += 188.0;
+        }
+        let rate = (bytes - 2e6) / (now - start).as_secs_f64();
+        assert!((rate - 2e6).abs() < 1e4, "{}", rate);
+    }
+}
@@ -0,0 +1,758 @@
+//! Timing decisions: which timestamp is treated as 00:00:00:00, and timestamp corrections.
+
+use std::collections::VecDeque;
+
+use crate::options::parse_time_string;
+
+/// Reference point subtracted from every subtitle timestamp (--timestamp-base).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampBase {
+    /// Container start_time (default).
+    Container,
+    /// First video pts.
+    Video,
+    /// First caption pts.
+    Subtitle,
+    /// User-provided anchor in seconds.
+    Absolute(f64),
+}
+
+/// Parses "container", "video", "subtitle" or "absolute:<TIME>".
+pub fn parse_timestamp_base(s: &str) -> Result<TimestampBase, String> {
+    match s.trim() {
+        "container" => Ok(TimestampBase::Container),
+        "video" => Ok(TimestampBase::Video),
+        "subtitle" => Ok(TimestampBase::Subtitle),
+        other => match other.strip_prefix("absolute:") {
+            Some(t) => parse_time_string(t).map(TimestampBase::Absolute),
+            None => Err(format!(
+                "invalid timestamp base: {} (expected container, video, subtitle or absolute:<TIME>)",
+                s
+            )),
+        },
+    }
+}
+
+/// Candidate base times (seconds) observed in the input. None when not available.
+#[derive(Debug, Clone, Default)]
+pub struct TimestampBaseCandidates {
+    pub container: Option<f64>,
+    pub video: Option<f64>,
+    pub subtitle: Option<f64>,
+}
+
+impl TimestampBaseCandidates {
+    /// One-line summary of all candidates for debug output.
+    pub fn describe(&self) -> String {
+        let fmt = |v: Option<f64>| v.map_or("n/a".to_string(), |t| format!("{:.3}s", t));
+        format!(
+            "container={} video={} subtitle={}",
+            fmt(self.container),
+            fmt(self.video),
+            fmt(self.subtitle)
+        )
+    }
+}
+
+/// Resolves the base time to subtract. An unknown container start counts as 0; the other bases
+/// are an error when the input does not provide them.
+pub fn select_timestamp_base(
+    base: TimestampBase,
+    candidates: &TimestampBaseCandidates,
+) -> anyhow::Result<f64> {
+    let chosen = match base {
+        TimestampBase::Container => Some(candidates.container.unwrap_or(0.0)),
+        TimestampBase::Video => candidates.video,
+        TimestampBase::Subtitle => candidates.subtitle,
+        TimestampBase::Absolute(t) => Some(t),
+    };
+    chosen.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Timestamp base {:?} is not available for this input ({}).",
+            base,
+            candidates.describe()
+        )
+    })
+}
+
+/// Duration used when a caption has neither a display time nor a following caption (--default-duration).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefaultDuration {
+    /// Constant duration in seconds.
+    Fixed(f64),
+    /// Reading-speed heuristic from the bitmap's non-transparent pixel count.
+    Auto,
+}
+
+/// Parses "auto" or a positive time (parse_time_string formats).
+pub fn parse_default_duration(s: &str) -> Result<DefaultDuration, String> {
+    if s.trim() == "auto" {
+        return Ok(DefaultDuration::Auto);
+    }
+    match parse_time_string(s)? {
+        d if d > 0.0 => Ok(DefaultDuration::Fixed(d)),
+        _ => Err(format!("default duration must be positive: {}", s)),
+    }
+}
+
+/// Non-transparent pixels per character on a 1920x1080 canvas (about a 60x60 cell, ~1/3 ink),
+/// expressed as a fraction of the canvas area so it applies to any canvas size.
+const INK_FRACTION_PER_CHAR: f64 = 1200.0 / (1920.0 * 1080.0);
+/// Comfortable reading speed for Japanese captions.
+const CHARS_PER_SECOND: f64 = 4.0;
+const AUTO_DURATION_MIN: f64 = 1.0;
+const AUTO_DURATION_MAX: f64 = 10.0;
+
+/// Reading-time estimate from a caption's non-transparent pixel count, clamped to [1s, 10s].
+pub fn auto_default_duration(opaque_pixels: usize, canvas_area: usize) -> f64 {
+    if canvas_area == 0 {
+        return AUTO_DURATION_MIN;
+    }
+    let chars = opaque_pixels as f64 / (canvas_area as f64 * INK_FRACTION_PER_CHAR);
+    (chars / CHARS_PER_SECOND).clamp(AUTO_DURATION_MIN, AUTO_DURATION_MAX)
+}
+
+/// Reading-time estimate for a text caption of `chars` characters, clamped like
+/// `auto_default_duration`.
+pub fn auto_text_duration(chars: usize) -> f64 {
+    (chars as f64 / CHARS_PER_SECOND).clamp(AUTO_DURATION_MIN, AUTO_DURATION_MAX)
+}
+
+/// Frame rates below or above these are treated as bogus (e.g. VFR remuxes reporting 1000/1).
+const MIN_CREDIBLE_FPS: f64 = 10.0;
+const MAX_CREDIBLE_FPS: f64 = 120.0;
+
+/// Frame rate of inputs without a video stream, and assumed by --best-effort.
+pub const DEFAULT_FPS: f64 = 29.97;
+
+/// Where the frame rate used for timecodes came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameRateSource {
+    /// Stream avg_frame_rate.
+    Average,
+    /// Stream r_frame_rate.
+    Real,
+    /// av_guess_frame_rate (container/codec level guess).
+    Guessed,
+    /// --fps.
+    User,
+    /// No video stream: DEFAULT_FPS.
+    Default,
+    /// The video stream has no credible rate and --best-effort assumed DEFAULT_FPS.
+    Assumed,
+}
+
+impl std::fmt::Display for FrameRateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FrameRateSource::Average => "avg_frame_rate",
+            FrameRateSource::Real => "r_frame_rate",
+            FrameRateSource::Guessed => "guessed",
+            FrameRateSource::User => "--fps",
+            FrameRateSource::Default => "default (no video stream)",
+            FrameRateSource::Assumed => "assumed (--best-effort)",
+        })
+    }
+}
+
+/// Frame rates reported for the video stream, in fps (0 when unset or invalid).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameRateCandidates {
+    pub avg: f64,
+    pub r: f64,
+    pub guessed: f64,
+}
+
+impl FrameRateCandidates {
+    /// One-line summary for error and debug output.
+    pub fn describe(&self) -> String {
+        format!(
+            "avg_frame_rate={:.3} r_frame_rate={:.3} guessed={:.3}",
+            self.avg, self.r, self.guessed
+        )
+    }
+}
+
+/// Which reported rate frame rate detection uses (--rate-source).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RatePreference {
+    /// avg_frame_rate only.
+    Avg,
+    /// r_frame_rate only (e.g. telecined sources with an odd average).
+    R,
+    /// First credible of avg -> r -> guessed (default).
+    Guess,
+}
+
+impl std::fmt::Display for RatePreference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RatePreference::Avg => "avg",
+            RatePreference::R => "r",
+            RatePreference::Guess => "guess",
+        })
+    }
+}
+
+/// Parses "avg", "r" or "guess".
+pub fn parse_rate_preference(s: &str) -> Result<RatePreference, String> {
+    match s.trim() {
+        "avg" => Ok(RatePreference::Avg),
+        "r" => Ok(RatePreference::R),
+        "guess" => Ok(RatePreference::Guess),
+        _ => Err(format!("invalid rate source: {} (expected avg, r or guess)", s)),
+    }
+}
+
+/// Picks the first credible rate allowed by `preference`. None if all allowed rates are bogus.
+pub fn select_frame_rate(
+    c: &FrameRateCandidates,
+    preference: RatePreference,
+) -> Option<(f64, FrameRateSource)> {
+    let order: &[(f64, FrameRateSource)] = match preference {
+        RatePreference::Avg => &[(c.avg, FrameRateSource::Average)],
+        RatePreference::R => &[(c.r, FrameRateSource::Real)],
+        RatePreference::Guess => &[
+            (c.avg, FrameRateSource::Average),
+            (c.r, FrameRateSource::Real),
+            (c.guessed, FrameRateSource::Guessed),
+        ],
+    };
+    order
+        .iter()
+        .copied()
+        .find(|(fps, _)| (MIN_CREDIBLE_FPS..=MAX_CREDIBLE_FPS).contains(fps))
+}
+
+/// Piecewise-linear timestamp correction from (source, target) breakpoints (--tc-map).
+/// The correction (target - source) is interpolated between breakpoints and held constant
+/// before the first and after the last one.
+#[derive(Debug, Clone)]
+pub struct TcMap {
+    points: Vec<(f64, f64)>,
+}
+
+impl TcMap {
+    /// Parses one "source target" pair per line (whitespace or comma separated, times via
+    /// parse_time_string). Blank lines and lines starting with '#' are ignored.
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut points = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|f| !f.is_empty())
+                .collect();
+            if fields.len() != 2 {
+                anyhow::bail!("tc-map line {}: expected \"source target\": {}", n + 1, line);
+            }
+            let source = parse_time_string(fields[0])
+                .map_err(|e| anyhow::anyhow!("tc-map line {}: {}", n + 1, e))?;
+            let target = parse_time_string(fields[1])
+                .map_err(|e| anyhow::anyhow!("tc-map line {}: {}", n + 1, e))?;
+            points.push((source, target));
+        }
+        if points.is_empty() {
+            anyhow::bail!("tc-map has no breakpoints");
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if points.windows(2).any(|w| w[0].0 == w[1].0) {
+            anyhow::bail!("tc-map has duplicate source times");
+        }
+        Ok(TcMap { points })
+    }
+
+    pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read tc-map: {}: {}", path.display(), e))?;
+        Self::parse(&contents)
+    }
+
+    /// Maps a source time to the corrected time.
+    pub fn apply(&self, t: f64) -> f64 {
+        let delta = |p: &(f64, f64)| p.1 - p.0;
+        let first = &self.points[0];
+        let last = &self.points[self.points.len() - 1];
+        if t <= first.0 {
+            return t + delta(first);
+        }
+        if t >= last.0 {
+            return t + delta(last);
+        }
+        let i = self.points.partition_point(|p| p.0 <= t);
+        let (a, b) = (&self.points[i - 1], &self.points[i]);
+        let f = (t - a.0) / (b.0 - a.0);
+        t + delta(a) + (delta(b) - delta(a)) * f
+    }
+}
+
+/// Whether the newest event is still open: nothing has ended it since it was added. Only the
+/// first clear after an event sets its end; later clears, and clears before any event (a capture
+/// joined mid-caption), must not move an end that is already known.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClearTracker {
+    open: bool,
+}
+
+impl ClearTracker {
+    /// An event was added.
+    pub fn event_added(&mut self) {
+        self.open = true;
+    }
+
+    /// A caption frame arrived, ending the newest event whether or not it becomes an event
+    /// itself.
+    pub fn caption(&mut self) {
+        self.open = false;
+    }
+
+    /// A clear frame arrived. True when it ends the open event; false when it is to be ignored.
+    pub fn clear(&mut self) -> bool {
+        std::mem::take(&mut self.open)
+    }
+}
+
+/// Default --correction-window: a caption starting this many seconds or less after the previous
+/// one, with a different image, is a corrected re-send of it.
+pub const DEFAULT_CORRECTION_WINDOW: f64 = 0.2;
+
+/// Parses a --correction-window time (parse_time_string formats); 0 disables detection.
+pub fn parse_correction_window(s: &str) -> Result<f64, String> {
+    match parse_time_string(s)? {
+        w if w >= 0.0 => Ok(w),
+        _ => Err(format!("correction window must not be negative: {}", s)),
+    }
+}
+
+/// Parses a --global-delay in milliseconds (negative moves captions earlier) into seconds.
+pub fn parse_global_delay(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(ms) if ms.is_finite() && ms.abs() <= 3_600_000.0 => Ok(ms / 1000.0),
+        _ => Err(format!(
+            "invalid delay: {} (expected milliseconds, at most one hour either way)",
+            s
+        )),
+    }
+}
+
+/// Default --max-display-duration: seconds a caption's own display time may last.
+pub const DEFAULT_MAX_DISPLAY_DURATION: f64 = 60.0;
+
+/// Parses a --max-display-duration time (parse_time_string formats); 0 disables the bound.
+pub fn parse_max_display_duration(s: &str) -> Result<f64, String> {
+    match parse_time_string(s)? {
+        d if d >= 0.0 => Ok(d),
+        _ => Err(format!("display duration must not be negative: {}", s)),
+    }
+}
+
+/// Whether a display time from `start` to `end` (seconds) is believable: broken encoders send
+/// end display times such as 0x7FFFFFFF ms. `max` 0 accepts any duration.
+pub fn plausible_duration(start: f64, end: f64, max: f64) -> bool {
+    max <= 0.0 || end - start <= max
+}
+
+/// What a corrected re-send does to the event it corrects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Correction {
+    /// The new caption takes the previous event's place (its start and image file).
+    Replace,
+    /// The previous event ends where the new caption starts (--keep-corrections).
+    Shorten,
+}
+
+/// Start time and image of the newest event, to tell a broadcaster's corrected re-send of it
+/// from the next caption. Only events added through this tracker count, so resumed events are
+/// never replaced.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CorrectionTracker {
+    last: Option<(f64, u64)>,
+}
+
+impl CorrectionTracker {
+    /// An event starting at `start` with image hash `image` was added.
+    pub fn event_added(&mut self, start: f64, image: u64) {
+        self.last = Some((start, image));
+    }
+
+    /// Whether a caption starting at `start` with image hash `image` corrects the newest event:
+    /// it starts at most `window` seconds after it (0 disables) and shows something else. The
+    /// same image again is a repeat, not a correction.
+    pub fn check(&self, start: f64, image: u64, window: f64, keep: bool) -> Option<Correction> {
+        let (last_start, last_image) = self.last?;
+        let gap = start - last_start;
+        if window <= 0.0 || !(0.0..=window + 1e-9).contains(&gap) || image == last_image {
+            return None;
+        }
+        Some(if keep {
+            Correction::Shorten
+        } else {
+            Correction::Replace
+        })
+    }
+}
+
+/// Default --max-events-per-second.
+pub const DEFAULT_MAX_EVENTS_PER_SECOND: usize = 30;
+
+/// Captions coalesced by a `BurstLimiter`, for the diagnostic: the starts of the first and last
+/// and how many there were.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Burst {
+    pub start: f64,
+    pub end: f64,
+    pub coalesced: usize,
+}
+
+/// --max-events-per-second: caps the events starting within any one second. Once the cap is
+/// reached, each further caption replaces the newest event instead of adding one, so a burst
+/// keeps its first events and its last caption rather than one image per decoded frame.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BurstLimiter {
+    /// 0 disables the cap.
+    limit: usize,
+    /// Starts of the events added within the last second.
+    starts: VecDeque<f64>,
+    burst: Option<Burst>,
+}
+
+impl BurstLimiter {
+    pub fn new(limit: usize) -> Self {
+        BurstLimiter {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Whether a caption starting at `start` is over the cap and replaces the newest event.
+    pub fn check(&self, start: f64) -> bool {
+        self.limit > 0 && self.starts.iter().filter(|&&s| start - s < 1.0).count() >= self.limit
+    }
+
+    /// An event starting at `start` was added, or replaced the newest one when `coalesced`.
+    /// Returns the burst that ended when an event is added normally again.
+    pub fn event_added(&mut self, start: f64, coalesced: bool) -> Option<Burst> {
+        if coalesced {
+            let burst = self.burst.get_or_insert(Burst {
+                start,
+                end: start,
+                coalesced: 0,
+            });
+            burst.end = start;
+            burst.coalesced += 1;
+            return None;
+        }
+        while self.starts.front().is_some_and(|&s| start - s >= 1.0) {
+            self.starts.pop_front();
+        }
+        self.starts.push_back(start);
+        self.burst.take()
+    }
+
+    /// The burst still open when the input ends.
+    pub fn finish(&mut self) -> Option<Burst> {
+        self.burst.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp_base() {
+        assert_eq!(parse_timestamp_base("container"), Ok(TimestampBase::Container));
+        assert_eq!(parse_timestamp_base("video"), Ok(TimestampBase::Video));
+        assert_eq!(parse_timestamp_base("subtitle"), Ok(TimestampBase::Subtitle));
+        assert_eq!(
+            parse_timestamp_base("absolute:16:40:00"),
+            Ok(TimestampBase::Absolute(60000.0))
+        );
+        assert!(parse_timestamp_base("absolute:").is_err());
+        assert!(parse_timestamp_base("absolute").is_err());
+        assert!(parse_timestamp_base("audio").is_err());
+    }
+
+    #[test]
+    fn test_select_timestamp_base() {
+        let c = TimestampBaseCandidates {
+            container: Some(60000.0),
+            video: Some(60000.5),
+            subtitle: Some(60003.0),
+        };
+        assert_eq!(select_timestamp_base(TimestampBase::Container, &c).unwrap(), 60000.0);
+        assert_eq!(select_timestamp_base(TimestampBase::Video, &c).unwrap(), 60000.5);
+        assert_eq!(select_timestamp_base(TimestampBase::Subtitle, &c).unwrap(), 60003.0);
+        assert_eq!(select_timestamp_base(TimestampBase::Absolute(1.0), &c).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_select_timestamp_base_missing_candidates() {
+        let c = TimestampBaseCandidates::default();
+        assert_eq!(select_timestamp_base(TimestampBase::Container, &c).unwrap(), 0.0);
+        assert!(select_timestamp_base(TimestampBase::Video, &c).is_err());
+        assert!(select_timestamp_base(TimestampBase::Subtitle, &c).is_err());
+        assert_eq!(select_timestamp_base(TimestampBase::Absolute(5.0), &c).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn test_tc_map_interpolates_between_breakpoints() {
+        let m = TcMap::parse("# source target\n00:00:00 00:00:00\n00:10:00 00:10:01\n").unwrap();
+        assert_eq!(m.apply(0.0), 0.0);
+        assert_eq!(m.apply(300.0), 300.5);
+        assert_eq!(m.apply(600.0), 601.0);
+        // Held constant outside the table.
+        assert_eq!(m.apply(1200.0), 1201.0);
+        assert_eq!(m.apply(-1.0), -1.0);
+    }
+
+    #[test]
+    fn test_tc_map_unsorted_and_comma_separated() {
+        let m = TcMap::parse("100,102\n0, 1\n").unwrap();
+        assert_eq!(m.apply(0.0), 1.0);
+        assert_eq!(m.apply(50.0), 51.5);
+        assert_eq!(m.apply(100.0), 102.0);
+    }
+
+    #[test]
+    fn test_tc_map_single_point_is_constant_offset() {
+        let m = TcMap::parse("10 9.5").unwrap();
+        assert_eq!(m.apply(0.0), -0.5);
+        assert_eq!(m.apply(100.0), 99.5);
+    }
+
+    #[test]
+    fn test_tc_map_errors() {
+        assert!(TcMap::parse("").is_err());
+        assert!(TcMap::parse("1 2 3").is_err());
+        assert!(TcMap::parse("1 x").is_err());
+        assert!(TcMap::parse("1 2\n1 3").is_err());
+    }
+
+    #[test]
+    fn test_select_frame_rate_explicit_preference() {
+        let telecined = FrameRateCandidates {
+            avg: 25.4,
+            r: 23.976,
+            guessed: 25.4,
+        };
+        assert_eq!(
+            select_frame_rate(&telecined, RatePreference::R),
+            Some((23.976, FrameRateSource::Real))
+        );
+        assert_eq!(
+            select_frame_rate(&telecined, RatePreference::Avg),
+            Some((25.4, FrameRateSource::Average))
+        );
+        // An explicit source does not fall back to the others.
+        let vfr = FrameRateCandidates {
+            avg: 0.0,
+            r: 1000.0,
+            guessed: 23.976,
+        };
+        assert_eq!(select_frame_rate(&vfr, RatePreference::Avg), None);
+        assert_eq!(select_frame_rate(&vfr, RatePreference::R), None);
+    }
+
+    #[test]
+    fn test_parse_rate_preference() {
+        assert_eq!(parse_rate_preference("avg"), Ok(RatePreference::Avg));
+        assert_eq!(parse_rate_preference("r"), Ok(RatePreference::R));
+        assert_eq!(parse_rate_preference("guess"), Ok(RatePreference::Guess));
+        assert!(parse_rate_preference("real").is_err());
+    }
+
+    #[test]
+    fn test_select_frame_rate() {
+        let c = |avg, r, guessed| FrameRateCandidates { avg, r, guessed };
+        let select_frame_rate = |c: &FrameRateCandidates| select_frame_rate(c, RatePreference::Guess);
+        let ntsc = 30000.0 / 1001.0;
+        assert_eq!(
+            select_frame_rate(&c(ntsc, 59.94, ntsc)),
+            Some((ntsc, FrameRateSource::Average))
+        );
+        // avg 0/0, r 1000/1 (VFR MKV): falls through to the guess.
+        assert_eq!(
+            select_frame_rate(&c(0.0, 1000.0, 23.976)),
+            Some((23.976, FrameRateSource::Guessed))
+        );
+        assert_eq!(
+            select_frame_rate(&c(0.0, 25.0, 0.0)),
+            Some((25.0, FrameRateSource::Real))
+        );
+        // Bounds are inclusive.
+        assert_eq!(
+            select_frame_rate(&c(120.0, 0.0, 0.0)),
+            Some((120.0, FrameRateSource::Average))
+        );
+        assert_eq!(
+            select_frame_rate(&c(5.0, 10.0, 0.0)),
+            Some((10.0, FrameRateSource::Real))
+        );
+        assert_eq!(select_frame_rate(&c(0.0, 1000.0, 90000.0)), None);
+        assert_eq!(select_frame_rate(&FrameRateCandidates::default()), None);
+    }
+
+    /// Runs a frame sequence ("clear", "show" for a kept caption, "drop" for a dropped one)
+    /// through a tracker and returns what each clear did.
+    fn clear_results(frames: &[&str]) -> Vec<bool> {
+        let mut tracker = ClearTracker::default();
+        let mut results = Vec::new();
+        for frame in frames {
+            match *frame {
+                "clear" => results.push(tracker.clear()),
+                "show" => {
+                    tracker.caption();
+                    tracker.event_added();
+                }
+                "drop" => tracker.caption(),
+                other => panic!("unknown frame: {}", other),
+            }
+        }
+        results
+    }
+
+    #[test]
+    fn test_parse_correction_window() {
+        assert_eq!(parse_correction_window("0.5"), Ok(0.5));
+        assert_eq!(parse_correction_window("0"), Ok(0.0));
+        assert!(parse_correction_window("-0.2").is_err());
+    }
+
+    #[test]
+    fn test_parse_global_delay() {
+        assert_eq!(parse_global_delay("-500"), Ok(-0.5));
+        assert_eq!(parse_global_delay(" 1500 "), Ok(1.5));
+        assert_eq!(parse_global_delay("0"), Ok(0.0));
+        assert!(parse_global_delay("0.5s").is_err());
+        assert!(parse_global_delay("inf").is_err());
+    }
+
+    #[test]
+    fn test_max_display_duration() {
+        assert_eq!(parse_max_display_duration("60"), Ok(60.0));
+        assert_eq!(parse_max_display_duration("0"), Ok(0.0));
+        assert!(parse_max_display_duration("-1").is_err());
+
+        let max = DEFAULT_MAX_DISPLAY_DURATION;
+        assert!(plausible_duration(10.0, 70.0, max));
+        assert!(!plausible_duration(10.0, 70.001, max));
+        assert!(plausible_duration(0.5, 0.5, max));
+        // 0x7FFFFFFF ms, about 24 days.
+        let end = 10.0 + 0x7FFF_FFFF as f64 / 1000.0;
+        assert!(!plausible_duration(10.0, end, max));
+        assert!(plausible_duration(10.0, end, 0.0));
+    }
+
+    #[test]
+    fn test_correction_tracker() {
+        let window = DEFAULT_CORRECTION_WINDOW;
+        let mut tracker = CorrectionTracker::default();
+        // Nothing to correct before the first event.
+        assert_eq!(tracker.check(1.0, 1, window, false), None);
+        tracker.event_added(1.0, 1);
+        // Same start, another image.
+        assert_eq!(tracker.check(1.0, 2, window, false), Some(Correction::Replace));
+        assert_eq!(tracker.check(1.0, 2, window, true), Some(Correction::Shorten));
+        // Near start, up to the window.
+        assert_eq!(tracker.check(1.1, 2, window, false), Some(Correction::Replace));
+        assert_eq!(tracker.check(1.2, 2, window, false), Some(Correction::Replace));
+        // A repeat of the same image is not a correction.
+        assert_eq!(tracker.check(1.1, 1, window, false), None);
+        // Legitimate rapid succession, and a disabled window.
+        assert_eq!(tracker.check(1.25, 2, window, false), None);
+        assert_eq!(tracker.check(1.5, 2, window, false), None);
+        assert_eq!(tracker.check(1.1, 2, 0.0, false), None);
+        assert_eq!(tracker.check(1.4, 2, 0.5, false), Some(Correction::Replace));
+        // A correction is measured from the newest event.
+        tracker.event_added(1.15, 2);
+        assert_eq!(tracker.check(1.3, 3, window, false), Some(Correction::Replace));
+    }
+
+    #[test]
+    fn test_burst_limiter() {
+        // Captions 10 ms apart: from the fourth within a second on, each replaces the newest.
+        let mut limiter = BurstLimiter::new(3);
+        let mut kept = Vec::new();
+        let mut ended = Vec::new();
+        for i in 0..10 {
+            let start = 5.0 + i as f64 * 0.01;
+            let coalesced = limiter.check(start);
+            if coalesced {
+                kept.pop();
+            }
+            kept.push(start);
+            ended.extend(limiter.event_added(start, coalesced));
+        }
+        // First two and the last are left.
+        assert_eq!(kept, [5.0, 5.01, 5.09]);
+        assert!(ended.is_empty());
+        // A second later the window has moved on: the burst is reported and events are added.
+        assert!(!limiter.check(6.05));
+        assert_eq!(
+            limiter.event_added(6.05, false),
+            Some(Burst {
+                start: 5.03,
+                end: 5.09,
+                coalesced: 7,
+            })
+        );
+        limiter.event_added(6.06, false);
+        limiter.event_added(6.07, false);
+        assert!(limiter.check(6.08));
+        limiter.event_added(6.08, true);
+        assert_eq!(limiter.finish().map(|b| b.coalesced), Some(1));
+        assert_eq!(limiter.finish(), None);
+
+        // Captions at the normal pace never hit the cap; 0 disables it.
+        let mut normal = BurstLimiter::new(DEFAULT_MAX_EVENTS_PER_SECOND);
+        for i in 0..100 {
+            let start = i as f64 * 0.5;
+            assert!(!normal.check(start));
+            normal.event_added(start, false);
+        }
+        let off = BurstLimiter::new(0);
+        assert!(!off.check(0.0));
+    }
+
+    #[test]
+    fn test_clear_tracker() {
+        assert_eq!(
+            clear_results(&["clear", "show", "clear", "clear", "show"]),
+            [false, true, false]
+        );
+        assert_eq!(
+            clear_results(&["show", "show", "clear", "show", "clear"]),
+            [true, true]
+        );
+        // A dropped caption still ended the event before it.
+        assert_eq!(clear_results(&["show", "drop", "clear"]), [false]);
+        assert_eq!(clear_results(&["clear", "clear"]), [false, false]);
+    }
+
+    #[test]
+    fn test_parse_default_duration() {
+        assert_eq!(parse_default_duration("auto"), Ok(DefaultDuration::Auto));
+        assert_eq!(parse_default_duration("1.0"), Ok(DefaultDuration::Fixed(1.0)));
+        assert_eq!(parse_default_duration("00:03"), Ok(DefaultDuration::Fixed(3.0)));
+        assert!(parse_default_duration("0").is_err());
+        assert!(parse_default_duration("-1").is_err());
+    }
+
+    #[test]
+    fn test_auto_default_duration() {
+        let area_1080 = 1920 * 1080;
+        assert_eq!(auto_default_duration(0, area_1080), 1.0);
+        // 20 characters at ~1200 ink pixels each -> 5 seconds.
+        assert!((auto_default_duration(24_000, area_1080) - 5.0).abs() < 1e-9);
+        assert_eq!(auto_default_duration(area_1080, area_1080), 10.0);
+        // Same relative coverage on a 720x480 canvas gives the same duration.
+        let area_480 = 720 * 480;
+        let scaled = 24_000 * area_480 / area_1080;
+        assert!((auto_default_duration(scaled, area_480) - 5.0).abs() < 0.01);
+        assert_eq!(auto_default_duration(100, 0), 1.0);
+    }
+}
@@ -0,0 +1,599 @@
+//! Structural check of BDN XML against a hard-coded model of the BDN 0.93 schema (--verify):
+//! element order, required attributes, timecode and number formats, and XML characters.
+
+use std::path::Path;
+
+use crate::bdn::resolve_graphic;
+
+/// True for characters allowed in XML 1.0 documents.
+pub fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Occurs {
+    One,
+    OneOrMore,
+    ZeroOrMore,
+}
+
+/// Allowed content of one element in the schema.
+struct Model {
+    required: &'static [&'static str],
+    optional: &'static [&'static str],
+    /// Attributes outside strict 0.93, allowed with `extensions` (--bdn-extensions).
+    extensions: &'static [&'static str],
+    /// Child elements in schema order.
+    children: &'static [(&'static str, Occurs, &'static Model)],
+    /// Whether the element holds text (a file name) instead of children.
+    text: bool,
+}
+
+static GRAPHIC: Model = Model {
+    required: &["Width", "Height", "X", "Y"],
+    optional: &[],
+    extensions: &[],
+    children: &[],
+    text: true,
+};
+static EVENT: Model = Model {
+    required: &["InTC", "OutTC"],
+    optional: &["Forced"],
+    extensions: &[],
+    children: &[("Graphic", Occurs::OneOrMore, &GRAPHIC)],
+    text: false,
+};
+static EVENTS: Model = Model {
+    required: &[],
+    optional: &[],
+    extensions: &[],
+    children: &[("Event", Occurs::ZeroOrMore, &EVENT)],
+    text: false,
+};
+static NAME: Model = Model {
+    required: &["Title"],
+    optional: &["Content"],
+    extensions: &[],
+    children: &[],
+    text: false,
+};
+static LANGUAGE: Model = Model {
+    required: &["Code"],
+    optional: &[],
+    extensions: &[],
+    children: &[],
+    text: false,
+};
+static FORMAT: Model = Model {
+    required: &["VideoFormat", "FrameRate", "DropFrame"],
+    optional: &[],
+    extensions: &[],
+    children: &[],
+    text: false,
+};
+static EVENTS_SUMMARY: Model = Model {
+    required: &["Type", "FirstEventInTC", "LastEventOutTC", "NumberofEvents"],
+    optional: &[],
+    extensions: &["DefaultFadeIn", "DefaultFadeOut"],
+    children: &[],
+    text: false,
+};
+static DESCRIPTION: Model = Model {
+    required: &[],
+    optional: &[],
+    extensions: &[],
+    children: &[
+        ("Name", Occurs::One, &NAME),
+        ("Language", Occurs::One, &LANGUAGE),
+        ("Format", Occurs::One, &FORMAT),
+        ("Events", Occurs::One, &EVENTS_SUMMARY),
+    ],
+    text: false,
+};
+static BDN: Model = Model {
+    required: &["Version"],
+    optional: &["xmlns:xsi", "xsi:noNamespaceSchemaLocation"],
+    extensions: &[],
+    children: &[
+        ("Description", Occurs::One, &DESCRIPTION),
+        ("Events", Occurs::One, &EVENTS),
+    ],
+    text: false,
+};
+
+/// Checks an attribute value's lexical form; returns the expected form when it does not match.
+fn check_value(attr: &str, value: &str) -> Result<(), &'static str> {
+    let ok = match attr {
+        "Version" => value == "0.93",
+        "Type" => value == "Graphic",
+        "InTC" | "OutTC" | "FirstEventInTC" | "LastEventOutTC" => is_timecode(value),
+        "Width" | "Height" | "X" | "Y" | "NumberofEvents" | "DefaultFadeIn" | "DefaultFadeOut" => {
+            !value.is_empty() && value.chars().all(|c| c.is_ascii_digit())
+        }
+        "Forced" | "DropFrame" => value == "True" || value == "False",
+        "FrameRate" => value.parse::<f64>().is_ok_and(|f| f > 0.0 && f.is_finite()),
+        _ => true,
+    };
+    if ok {
+        return Ok(());
+    }
+    Err(match attr {
+        "Version" => "0.93",
+        "Type" => "Graphic",
+        "Forced" | "DropFrame" => "True or False",
+        "FrameRate" => "a positive number",
+        "Width" | "Height" | "X" | "Y" | "NumberofEvents" | "DefaultFadeIn" | "DefaultFadeOut" => {
+            "a non-negative integer"
+        }
+        _ => "HH:MM:SS:FF",
+    })
+}
+
+/// HH:MM:SS:FF with two digits each and minutes/seconds below 60.
+fn is_timecode(s: &str) -> bool {
+    let fields: Vec<&str> = s.split(':').collect();
+    fields.len() == 4
+        && fields
+            .iter()
+            .all(|f| f.len() == 2 && f.bytes().all(|b| b.is_ascii_digit()))
+        && fields[1..3]
+            .iter()
+            .all(|f| f.parse::<u32>().is_ok_and(|v| v < 60))
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Element {
+    pub(crate) name: String,
+    pub(crate) attrs: Vec<(String, String)>,
+    pub(crate) children: Vec<Element>,
+    pub(crate) text: String,
+    /// Text of the comments directly inside the element, trimmed.
+    pub(crate) comments: Vec<String>,
+    pub(crate) line: usize,
+}
+
+/// Parser for the XML subset BDN files use: a declaration, comments, elements, attributes,
+/// text and the predefined/numeric entities. No DTDs, CDATA or processing instructions.
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn line(&self) -> usize {
+        self.s[..self.pos].matches('\n').count() + 1
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line(), message)
+    }
+
+    fn rest(&self) -> &str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start_matches([' ', '\t', '\r', '\n']);
+        self.pos = self.s.len() - trimmed.len();
+    }
+
+    /// Skips whitespace, comments and (before the root) the XML declaration.
+    fn skip_misc(&mut self, allow_decl: bool) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            let (open, close) = if self.rest().starts_with("<!--") {
+                ("<!--", "-->")
+            } else if allow_decl && self.pos == 0 && self.rest().starts_with("<?xml") {
+                ("<?xml", "?>")
+            } else {
+                return Ok(());
+            };
+            match self.rest()[open.len()..].find(close) {
+                Some(end) => self.pos += open.len() + end + close.len(),
+                None => return Err(self.error(&format!("unterminated {}", open))),
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.')))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Ok(name)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", token)))
+        }
+    }
+
+    /// Replaces entity references in raw character data.
+    fn unescape(&self, raw: &str) -> Result<String, String> {
+        let mut out = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            let end = rest[amp..]
+                .find(';')
+                .ok_or_else(|| self.error("unterminated entity reference"))?;
+            let entity = &rest[amp + 1..amp + end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse::<u32>()))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32)
+                    .filter(|&c| is_xml_char(c)),
+            };
+            out.push(c.ok_or_else(|| self.error(&format!("invalid entity '&{};'", entity)))?);
+            rest = &rest[amp + end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn element(&mut self) -> Result<Element, String> {
+        let line = self.line();
+        self.expect("<")?;
+        let mut element = Element {
+            name: self.name()?,
+            line,
+            ..Default::default()
+        };
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(element);
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let attr = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = self
+                .rest()
+                .chars()
+                .next()
+                .filter(|c| matches!(c, '"' | '\''));
+            let Some(quote) = quote else {
+                return Err(self.error(&format!("attribute {} value is not quoted", attr)));
+            };
+            self.pos += 1;
+            let end = self
+                .rest()
+                .find(quote)
+                .ok_or_else(|| self.error(&format!("unterminated attribute {}", attr)))?;
+            let raw = &self.rest()[..end];
+            if raw.contains('<') {
+                return Err(self.error(&format!("'<' in attribute {}", attr)));
+            }
+            let value = self.unescape(raw)?;
+            self.pos += end + 1;
+            if element.attrs.iter().any(|(a, _)| *a == attr) {
+                return Err(self.error(&format!("duplicate attribute {}", attr)));
+            }
+            element.attrs.push((attr, value));
+        }
+        loop {
+            let end = self.rest().find('<').unwrap_or(self.rest().len());
+            let raw = &self.rest()[..end];
+            element.text.push_str(&self.unescape(raw)?);
+            self.pos += end;
+            if self.rest().is_empty() {
+                return Err(self.error(&format!("<{}> is not closed", element.name)));
+            }
+            if let Some(comment) = self.rest().strip_prefix("<!--") {
+                let end = comment
+                    .find("-->")
+                    .ok_or_else(|| self.error("unterminated <!--"))?;
+                element.comments.push(comment[..end].trim().to_string());
+                self.pos += 4 + end + 3;
+            } else if self.rest().starts_with("</") {
+                self.pos += 2;
+                let name = self.name()?;
+                if name != element.name {
+                    return Err(self.error(&format!(
+                        "</{}> does not close <{}> (line {})",
+                        name, element.name, element.line
+                    )));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                return Ok(element);
+            } else {
+                element.children.push(self.element()?);
+            }
+        }
+    }
+}
+
+pub(crate) fn parse(xml: &str) -> Result<Element, String> {
+    let mut parser = Parser { s: xml, pos: 0 };
+    parser.skip_misc(true)?;
+    let root = parser.element()?;
+    parser.skip_misc(false)?;
+    if !parser.rest().is_empty() {
+        return Err(parser.error("content after the root element"));
+    }
+    Ok(root)
+}
+
+/// Checks `element` and its subtree against `model`, appending problems to `errors`.
+fn check(element: &Element, model: &Model, extensions: bool, errors: &mut Vec<String>) {
+    let at = format!("line {}: <{}>", element.line, element.name);
+    for attr in model.required {
+        if !element.attrs.iter().any(|(a, _)| a == attr) {
+            errors.push(format!("{} is missing attribute {}", at, attr));
+        }
+    }
+    for (attr, value) in &element.attrs {
+        let extension = model.extensions.contains(&attr.as_str());
+        if extension && !extensions {
+            errors.push(format!("{} has attribute {}, a BDN extension", at, attr));
+        } else if !extension
+            && !model.required.contains(&attr.as_str())
+            && !model.optional.contains(&attr.as_str())
+        {
+            errors.push(format!("{} has unexpected attribute {}", at, attr));
+        } else if let Err(expected) = check_value(attr, value) {
+            errors.push(format!("{} {}=\"{}\" is not {}", at, attr, value, expected));
+        }
+    }
+    if model.text {
+        if element.text.trim().is_empty() {
+            errors.push(format!("{} has no file name", at));
+        }
+    } else if !element.text.trim().is_empty() {
+        errors.push(format!("{} has unexpected text", at));
+    }
+
+    // Match the children against the model's sequence in order.
+    let mut children = element.children.iter().peekable();
+    for (name, occurs, child_model) in model.children {
+        let mut count = 0;
+        while let Some(child) = children.next_if(|c| c.name == *name) {
+            check(child, child_model, extensions, errors);
+            count += 1;
+            if *occurs == Occurs::One {
+                break;
+            }
+        }
+        if count == 0 && *occurs != Occurs::ZeroOrMore {
+            errors.push(format!("{} is missing <{}>", at, name));
+        }
+    }
+    if let Some(child) = children.next() {
+        errors.push(format!(
+            "line {}: <{}> is not allowed here in <{}>",
+            child.line, child.name, element.name
+        ));
+    }
+}
+
+pub(crate) fn attr<'a>(element: &'a Element, name: &str) -> Option<&'a str> {
+    element
+        .attrs
+        .iter()
+        .find(|(a, _)| a == name)
+        .map(|(_, v)| v.as_str())
+}
+
+pub(crate) fn child<'a>(element: &'a Element, name: &str) -> Option<&'a Element> {
+    element.children.iter().find(|c| c.name == name)
+}
+
+/// Validates a BDN XML document; returns the problems found (empty when valid). `extensions`
+/// also accepts the attributes --bdn-extensions writes.
+pub fn validate_bdn(xml: &str, extensions: bool) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (i, line) in xml.lines().enumerate() {
+        if let Some(c) = line.chars().find(|&c| !is_xml_char(c)) {
+            errors.push(format!(
+                "line {}: invalid XML character U+{:04X}",
+                i + 1,
+                c as u32
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        return errors;
+    }
+    let root = match parse(xml) {
+        Ok(root) => root,
+        Err(e) => return vec![e],
+    };
+    if root.name != "BDN" {
+        return vec![format!("root element is <{}>, not <BDN>", root.name)];
+    }
+    check(&root, &BDN, extensions, &mut errors);
+
+    let summary = child(&root, "Description").and_then(|d| child(d, "Events"));
+    let count = child(&root, "Events").map_or(0, |e| e.children.len());
+    if let Some(declared) = summary.and_then(|s| attr(s, "NumberofEvents")) {
+        if declared.parse::<usize>().is_ok_and(|n| n != count) {
+            errors.push(format!(
+                "NumberofEvents=\"{}\" but the document has {} event(s)",
+                declared, count
+            ));
+        }
+    }
+    errors
+}
+
+/// Validates a written BDN XML file and checks that the graphics it references exist next to
+/// it; references outside its directory are problems unless `allow_external`. Returns the
+/// problems found.
+pub fn verify_file(
+    path: &Path,
+    extensions: bool,
+    allow_external: bool,
+) -> anyhow::Result<Vec<String>> {
+    let xml = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read file: {}: {}", path.display(), e))?;
+    let mut errors = validate_bdn(&xml, extensions);
+    if !errors.is_empty() {
+        return Ok(errors);
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    if let Some(events) = parse(&xml).ok().as_ref().and_then(|r| child(r, "Events")) {
+        for graphic in events.children.iter().flat_map(|e| &e.children) {
+            let file = graphic.text.trim();
+            match resolve_graphic(dir, file, allow_external) {
+                Ok(path) if path.is_file() => {}
+                Ok(_) => errors.push(format!("line {}: graphic {} not found", graphic.line, file)),
+                Err(e) => errors.push(format!("line {}: {}", graphic.line, e)),
+            }
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<BDN Version="0.93" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:noNamespaceSchemaLocation="BD-03-006-0093b BDN File Format.xsd">
+  <Description>
+    <Name Title="BDN Subtitle" Content=""/>
+    <Language Code="und"/>
+    <Format VideoFormat="1080p" FrameRate="29.97" DropFrame="False"/>
+    <Events Type="Graphic" FirstEventInTC="00:00:01:00" LastEventOutTC="00:00:02:00" NumberofEvents="1"/>
+  </Description>
+  <Events>
+    <Event InTC="00:00:01:00" OutTC="00:00:02:00" Forced="False">
+      <Graphic Width="10" Height="10" X="0" Y="0">a&amp;b00001.png</Graphic>
+    </Event>
+  </Events>
+</BDN>
+"#;
+
+    fn problems(from: &str, to: &str) -> Vec<String> {
+        let xml = VALID.replacen(from, to, 1);
+        assert_ne!(xml, VALID, "{} not found", from);
+        validate_bdn(&xml, false)
+    }
+
+    #[test]
+    fn test_valid_document() {
+        assert_eq!(validate_bdn(VALID, false), Vec::<String>::new());
+        let root = parse(VALID).unwrap();
+        let graphic = &child(&root, "Events").unwrap().children[0].children[0];
+        assert_eq!(graphic.text, "a&b00001.png");
+    }
+
+    #[test]
+    fn test_comments_are_kept() {
+        let xml = VALID.replacen(
+            "\n      <Graphic",
+            "\n      <!-- one -->\n      <!--two-->\n      <Graphic",
+            1,
+        );
+        assert_eq!(validate_bdn(&xml, false), Vec::<String>::new());
+        let root = parse(&xml).unwrap();
+        let event = &child(&root, "Events").unwrap().children[0];
+        assert_eq!(event.comments, ["one", "two"]);
+        assert_eq!(event.children.len(), 1);
+        let e = problems("</Event>", "<!-- open </Event>");
+        assert_eq!(e, ["line 12: unterminated <!--"]);
+    }
+
+    #[test]
+    fn test_attribute_problems() {
+        let e = problems(r#" Forced="False""#, "");
+        assert!(e.is_empty(), "{:?}", e);
+        let e = problems(r#"X="0" "#, "");
+        assert_eq!(e, ["line 11: <Graphic> is missing attribute X"]);
+        let e = problems(r#"Y="0""#, r#"Y="-4""#);
+        assert_eq!(
+            e,
+            [r#"line 11: <Graphic> Y="-4" is not a non-negative integer"#]
+        );
+        let e = problems(r#" InTC="00:00:01:00""#, r#" InTC="0:00:01:00""#);
+        assert_eq!(
+            e,
+            [r#"line 10: <Event> InTC="0:00:01:00" is not HH:MM:SS:FF"#]
+        );
+        let e = problems(r#"OutTC="00:00:02:00""#, r#"OutTC="00:61:02:00""#);
+        assert_eq!(e.len(), 1, "{:?}", e);
+        let e = problems(r#"DropFrame="False""#, r#"DropFrame="false" Extra="1""#);
+        assert_eq!(e.len(), 2, "{:?}", e);
+    }
+
+    #[test]
+    fn test_structure_problems() {
+        // Description children out of order.
+        let e = problems(
+            "<Name Title=\"BDN Subtitle\" Content=\"\"/>\n    <Language Code=\"und\"/>",
+            "<Language Code=\"und\"/>\n    <Name Title=\"BDN Subtitle\" Content=\"\"/>",
+        );
+        assert!(
+            e.contains(&"line 3: <Description> is missing <Name>".to_string()),
+            "{:?}",
+            e
+        );
+        let e = problems("a&amp;b00001.png", " ");
+        assert_eq!(e, ["line 11: <Graphic> has no file name"]);
+        let e = problems(r#"NumberofEvents="1""#, r#"NumberofEvents="2""#);
+        assert_eq!(e, [r#"NumberofEvents="2" but the document has 1 event(s)"#]);
+        let e = problems("    </Event>\n", "");
+        assert_eq!(e, ["line 12: </Events> does not close <Event> (line 10)"]);
+        assert!(!validate_bdn("<BDN Version=\"0.93\"/>", false).is_empty());
+        assert!(!validate_bdn("", false).is_empty());
+    }
+
+    #[test]
+    fn test_extension_attributes() {
+        let fades = r#"NumberofEvents="1" DefaultFadeIn="2" DefaultFadeOut="0""#;
+        let xml = VALID.replacen(r#"NumberofEvents="1""#, fades, 1);
+        assert_eq!(validate_bdn(&xml, true), Vec::<String>::new());
+        assert_eq!(
+            validate_bdn(&xml, false),
+            [
+                "line 7: <Events> has attribute DefaultFadeIn, a BDN extension",
+                "line 7: <Events> has attribute DefaultFadeOut, a BDN extension",
+            ]
+        );
+        let bad = xml.replace(r#"DefaultFadeIn="2""#, r#"DefaultFadeIn="x""#);
+        assert_eq!(
+            validate_bdn(&bad, true),
+            [r#"line 7: <Events> DefaultFadeIn="x" is not a non-negative integer"#]
+        );
+        // Extensions are only accepted where --bdn-extensions writes them.
+        let misplaced = VALID.replacen(r#"Forced="False""#, r#"DefaultFadeIn="2""#, 1);
+        assert_eq!(
+            validate_bdn(&misplaced, true),
+            ["line 10: <Event> has unexpected attribute DefaultFadeIn"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_characters() {
+        let e = problems("a&amp;b00001.png", "a\u{1}b00001.png");
+        assert_eq!(e, ["line 11: invalid XML character U+0001"]);
+        let e = problems("a&amp;b00001.png", "a&#1;b00001.png");
+        assert_eq!(e, ["line 11: invalid entity '&#1;'"]);
+        assert!(is_xml_char('\u{3042}') && is_xml_char('\n'));
+        assert!(!is_xml_char('\u{1B}') && !is_xml_char('\u{FFFE}'));
+    }
+}
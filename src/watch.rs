@@ -0,0 +1,250 @@
+//! --watch: converts recordings as they appear in a directory. Files are converted once their
+//! size has stopped changing; a state file in the directory remembers what was done.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// State file name inside the watched directory.
+pub const WATCH_STATE_FILE: &str = ".arib2bdnxml-watch";
+/// Conversions of one file are attempted at most this many times.
+pub const MAX_WATCH_ATTEMPTS: u32 = 3;
+/// How often the directory is listed.
+pub const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Inputs --watch picks up.
+const WATCH_EXTENSIONS: [&str; 4] = ["ts", "m2ts", "mkv", "mks"];
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    // A second Ctrl-C aborts the conversion in flight.
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Makes Ctrl-C request a stop after the current conversion instead of killing the process (a
+/// second Ctrl-C still aborts; its workspace is swept by a later run). Only on unix; elsewhere
+/// Ctrl-C keeps its default effect.
+pub fn install_interrupt_handler() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// True once Ctrl-C was pressed.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Sleeps for `duration`, returning early on Ctrl-C.
+pub fn sleep_unless_interrupted(duration: Duration) {
+    let until = Instant::now() + duration;
+    while !interrupted() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(Duration::from_millis(100)));
+    }
+}
+
+/// Decides when a file has stopped growing: its size must stay the same for `stable_for`.
+#[derive(Debug)]
+pub struct StabilityTracker {
+    stable_for: Duration,
+    /// Last seen size and when the file was first seen at that size.
+    seen: HashMap<PathBuf, (u64, Instant)>,
+}
+
+impl StabilityTracker {
+    pub fn new(stable_for: Duration) -> Self {
+        StabilityTracker {
+            stable_for,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Records `size` for `path` at `now`; true when the size has not changed for `stable_for`.
+    pub fn observe(&mut self, path: &Path, size: u64, now: Instant) -> bool {
+        let entry = self.seen.entry(path.to_path_buf()).or_insert((size, now));
+        if entry.0 != size {
+            *entry = (size, now);
+        }
+        now.saturating_duration_since(entry.1) >= self.stable_for
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Done,
+    /// Failed this many times.
+    Failed(u32),
+}
+
+/// Inputs already handled, kept in `WATCH_STATE_FILE` as `done<TAB>name` and
+/// `failed<TAB>attempts<TAB>name` lines so a restart does not convert them again.
+#[derive(Debug)]
+pub struct WatchState {
+    path: PathBuf,
+    entries: BTreeMap<String, Outcome>,
+}
+
+impl WatchState {
+    /// Loads the state file at `path`; a missing file is an empty state.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut entries = BTreeMap::new();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => anyhow::bail!("Failed to read file: {}: {}", path.display(), e),
+        };
+        for (n, line) in contents.lines().enumerate() {
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            let entry = match fields[..] {
+                ["done", name] => (name, Outcome::Done),
+                ["failed", attempts, name] => match attempts.parse() {
+                    Ok(a) => (name, Outcome::Failed(a)),
+                    Err(_) => anyhow::bail!("{}:{}: invalid attempt count", path.display(), n + 1),
+                },
+                [""] => continue,
+                _ => anyhow::bail!("{}:{}: invalid line: {}", path.display(), n + 1, line),
+            };
+            entries.insert(entry.0.to_string(), entry.1);
+        }
+        Ok(WatchState {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// Writes the state file (via a temporary file, so a crash never leaves it half-written).
+    pub fn save(&self) -> anyhow::Result<()> {
+        let mut out = String::new();
+        for (name, outcome) in &self.entries {
+            match outcome {
+                Outcome::Done => out.push_str(&format!("done\t{}\n", name)),
+                Outcome::Failed(a) => out.push_str(&format!("failed\t{}\t{}\n", a, name)),
+            }
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, out)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}: {}", self.path.display(), e))
+    }
+
+    /// True unless `name` was converted or has used up its attempts.
+    pub fn pending(&self, name: &str) -> bool {
+        match self.entries.get(name) {
+            None => true,
+            Some(Outcome::Done) => false,
+            Some(Outcome::Failed(a)) => *a < MAX_WATCH_ATTEMPTS,
+        }
+    }
+
+    pub fn mark_done(&mut self, name: &str) {
+        self.entries.insert(name.to_string(), Outcome::Done);
+    }
+
+    /// Records a failed attempt; returns the number of attempts so far.
+    pub fn mark_failed(&mut self, name: &str) -> u32 {
+        let attempts = match self.entries.get(name) {
+            Some(Outcome::Failed(a)) => a + 1,
+            _ => 1,
+        };
+        self.entries
+            .insert(name.to_string(), Outcome::Failed(attempts));
+        attempts
+    }
+}
+
+/// Input files directly inside `dir`, sorted by name.
+pub fn watch_candidates(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("Failed to read directory: {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.is_file()
+                && p.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| WATCH_EXTENSIONS.iter().any(|w| e.eq_ignore_ascii_case(w)))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stability_tracker() {
+        let start = Instant::now();
+        let at = |s: u64| start + Duration::from_secs(s);
+        let mut tracker = StabilityTracker::new(Duration::from_secs(10));
+        let (a, b) = (Path::new("a.ts"), Path::new("b.ts"));
+        assert!(!tracker.observe(a, 100, at(0)));
+        assert!(!tracker.observe(a, 200, at(5)));
+        // Unchanged since 5s: stable from 15s.
+        assert!(!tracker.observe(a, 200, at(14)));
+        assert!(tracker.observe(a, 200, at(15)));
+        assert!(!tracker.observe(a, 300, at(16)));
+        // Files are tracked independently.
+        assert!(!tracker.observe(b, 1, at(16)));
+        assert!(tracker.observe(b, 1, at(26)));
+        assert!(StabilityTracker::new(Duration::ZERO).observe(a, 1, at(0)));
+    }
+
+    #[test]
+    fn test_watch_state_round_trip() {
+        let dir = crate::output::tests::scratch_dir("watch-state");
+        let path = dir.join(WATCH_STATE_FILE);
+        let mut state = WatchState::load(&path).unwrap();
+        assert!(state.pending("a.ts"));
+        state.mark_done("a.ts");
+        assert_eq!(state.mark_failed("b c.ts"), 1);
+        assert_eq!(state.mark_failed("b c.ts"), 2);
+        state.save().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "done\ta.ts\nfailed\t2\tb c.ts\n"
+        );
+
+        // A restart keeps what was done and the attempts made.
+        let mut state = WatchState::load(&path).unwrap();
+        assert!(!state.pending("a.ts"));
+        assert!(state.pending("b c.ts"));
+        assert_eq!(state.mark_failed("b c.ts"), MAX_WATCH_ATTEMPTS);
+        assert!(!state.pending("b c.ts"));
+        assert!(state.pending("new.ts"));
+        assert!(!dir.join(".arib2bdnxml-watch.tmp").exists());
+
+        std::fs::write(&path, "done\ta.ts\nbogus\n").unwrap();
+        let err = WatchState::load(&path).unwrap_err().to_string();
+        assert!(err.ends_with(":2: invalid line: bogus"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watch_candidates() {
+        let dir = crate::output::tests::scratch_dir("watch-candidates");
+        for name in ["b.m2ts", "a.TS", "c.mkv", "notes.txt", WATCH_STATE_FILE] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        std::fs::create_dir(dir.join("d.ts")).unwrap();
+        let names: Vec<_> = watch_candidates(&dir)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["a.TS", "b.m2ts", "c.mkv"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//! Per-run scratch directory for intermediate files.
+//!
+//! Files are written into the workspace and moved to their final place with `persist`, so an
+//! interrupted run never leaves half-written outputs. The workspace is removed on success and,
+//! unless kept with --keep-partial, on failure. Workspaces left behind by crashed runs are
+//! removed by `sweep_stale` once they are old enough.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Directory name prefix; `sweep_stale` only ever touches names starting with it.
+const WORKSPACE_PREFIX: &str = ".arib2bdnxml-tmp-";
+
+/// Workspaces older than this are considered leftovers of crashed runs.
+pub const STALE_WORKSPACE_AGE: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+pub struct TempWorkspace {
+    path: PathBuf,
+    keep_on_failure: bool,
+    finished: bool,
+}
+
+/// True if `name` looks like a workspace directory: prefix followed by `<pid>-<nanos>[-<n>]`.
+fn is_workspace_name(name: &str) -> bool {
+    name.strip_prefix(WORKSPACE_PREFIX).is_some_and(|rest| {
+        let parts: Vec<&str> = rest.split('-').collect();
+        (2..=3).contains(&parts.len())
+            && parts
+                .iter()
+                .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+impl TempWorkspace {
+    /// Creates a uniquely named workspace under `parent`.
+    pub fn create(parent: &Path, keep_on_failure: bool) -> anyhow::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let stem = format!("{}{}-{}", WORKSPACE_PREFIX, std::process::id(), nanos);
+        for attempt in 0..100 {
+            let name = if attempt == 0 {
+                stem.clone()
+            } else {
+                format!("{}-{}", stem, attempt)
+            };
+            let path = parent.join(name);
+            match fs::create_dir(&path) {
+                Ok(()) => {
+                    return Ok(TempWorkspace {
+                        path,
+                        keep_on_failure,
+                        finished: false,
+                    })
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => anyhow::bail!(
+                    "Failed to create temporary directory: {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        anyhow::bail!("Failed to create temporary directory under {}", parent.display())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of a scratch file inside the workspace.
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Moves a finished scratch file to `dest`, replacing it, and gives it the --output-mode.
+    /// Falls back to copy + remove when `dest` is on another filesystem.
+    pub fn persist(&self, name: &str, dest: &Path) -> anyhow::Result<()> {
+        let src = self.file(name);
+        if fs::rename(&src, dest).is_err() {
+            fs::copy(&src, dest).map_err(|e| {
+                anyhow::anyhow!("Failed to write file: {}: {}", dest.display(), e)
+            })?;
+            let _ = fs::remove_file(&src);
+        }
+        crate::sink::apply_file_mode(dest)
+            .map_err(|e| anyhow::anyhow!("Failed to set permissions: {}: {}", dest.display(), e))
+    }
+
+    /// Marks the run as successful and removes the workspace.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.finished = true;
+        fs::remove_dir_all(&self.path).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to remove temporary directory: {}: {}",
+                self.path.display(),
+                e
+            )
+        })
+    }
+}
+
+impl Drop for TempWorkspace {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if self.keep_on_failure {
+            log_line!("Partial files kept in: {}", self.path.display());
+        } else {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Removes workspaces under `parent` last modified more than `max_age` before `now`.
+/// Returns the removed directories.
+pub fn sweep_stale(parent: &Path, max_age: Duration, now: SystemTime) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(parent) else {
+        return Vec::new();
+    };
+    let mut removed = Vec::new();
+    for entry in entries.flatten() {
+        let is_workspace = entry.file_name().to_str().is_some_and(is_workspace_name);
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if !is_workspace || !meta.is_dir() {
+            continue;
+        }
+        let stale = meta
+            .modified()
+            .ok()
+            .and_then(|m| now.duration_since(m).ok())
+            .is_some_and(|age| age > max_age);
+        if stale && fs::remove_dir_all(entry.path()).is_ok() {
+            removed.push(entry.path());
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::tests::scratch_dir;
+
+    #[test]
+    fn test_is_workspace_name() {
+        assert!(is_workspace_name(".arib2bdnxml-tmp-123-456"));
+        assert!(is_workspace_name(".arib2bdnxml-tmp-123-456-2"));
+        assert!(!is_workspace_name(".arib2bdnxml-tmp-"));
+        assert!(!is_workspace_name(".arib2bdnxml-tmp-123"));
+        assert!(!is_workspace_name(".arib2bdnxml-tmp-123-abc"));
+        assert!(!is_workspace_name(".arib2bdnxml-tmp-1-2-3-4"));
+        assert!(!is_workspace_name("arib2bdnxml-tmp-123-456"));
+        assert!(!is_workspace_name("ep01_bdnxml"));
+    }
+
+    #[test]
+    fn test_finish_removes_workspace() {
+        let parent = scratch_dir("ws-finish");
+        let ws = TempWorkspace::create(&parent, false).unwrap();
+        fs::write(ws.file("a.xml"), b"<BDN/>").unwrap();
+        ws.persist("a.xml", &parent.join("a.xml")).unwrap();
+        let path = ws.path().to_path_buf();
+        ws.finish().unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read(parent.join("a.xml")).unwrap(), b"<BDN/>");
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn test_failure_removes_or_keeps() {
+        let parent = scratch_dir("ws-fail");
+        let dropped = TempWorkspace::create(&parent, false).unwrap();
+        let kept = TempWorkspace::create(&parent, true).unwrap();
+        assert_ne!(dropped.path(), kept.path());
+        let (dropped_path, kept_path) = (dropped.path().to_path_buf(), kept.path().to_path_buf());
+        drop(dropped);
+        drop(kept);
+        assert!(!dropped_path.exists());
+        assert!(kept_path.exists());
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn test_sweep_stale_only_removes_old_workspaces() {
+        let parent = scratch_dir("ws-sweep");
+        let ws = TempWorkspace::create(&parent, true).unwrap();
+        let ws_path = ws.path().to_path_buf();
+        drop(ws);
+        fs::create_dir(parent.join("ep01_bdnxml")).unwrap();
+
+        let now = SystemTime::now();
+        assert!(sweep_stale(&parent, STALE_WORKSPACE_AGE, now).is_empty());
+        let later = now + STALE_WORKSPACE_AGE + Duration::from_secs(60);
+        assert_eq!(sweep_stale(&parent, STALE_WORKSPACE_AGE, later), vec![ws_path]);
+        assert!(parent.join("ep01_bdnxml").exists());
+        fs::remove_dir_all(&parent).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+/* Links against the C API and checks its error paths; with an input file it also converts it.
+ * Built and run by tests/capi/run.sh. */
+#include <stdio.h>
+#include <string.h>
+
+#include "arib2bdnxml.h"
+
+static void progress(void *user_data, uint64_t events, const char *in_tc) {
+  *(uint64_t *)user_data = events;
+  printf("event %llu at %s\n", (unsigned long long)events, in_tc);
+}
+
+static void message(void *user_data, int32_t level, const char *text) {
+  (void)user_data;
+  printf("%s%s\n", level == ARIB2BDNXML_LEVEL_WARNING ? "warning: " : "", text);
+}
+
+static int check(int ok, const char *what) {
+  if (!ok) {
+    fprintf(stderr, "FAILED: %s\n", what);
+  }
+  return ok ? 0 : 1;
+}
+
+int main(int argc, char **argv) {
+  int failures = 0;
+  Arib2bdnxmlReport report = {0};
+
+  failures += check(arib2bdnxml_convert(NULL, &report) == ARIB2BDNXML_INVALID, "NULL options");
+  failures += check(arib2bdnxml_last_error() != NULL, "error after NULL options");
+
+  Arib2bdnxmlOptions options = {0};
+  options.input = "a.ts";
+  failures += check(arib2bdnxml_convert(&options, NULL) == ARIB2BDNXML_INVALID, "no output");
+
+  options.input = "/nonexistent/arib2bdnxml-link-test.ts";
+  options.output = "/nonexistent";
+  failures += check(arib2bdnxml_convert(&options, &report) == ARIB2BDNXML_FAILED, "missing input");
+  failures += check(strstr(arib2bdnxml_last_error(), "does not exist") != NULL, "missing input error");
+
+  if (argc > 1) {
+    uint64_t seen = 0;
+    options.input = argv[1];
+    options.output = argc > 2 ? argv[2] : ".";
+    options.progress = progress;
+    options.message = message;
+    options.user_data = &seen;
+    int code = arib2bdnxml_convert(&options, &report);
+    if (code != ARIB2BDNXML_OK) {
+      fprintf(stderr, "conversion failed (%d): %s\n", code, arib2bdnxml_last_error());
+    }
+    failures += check(code == ARIB2BDNXML_OK, "conversion");
+    /* Progress counts every event written; filters such as --forced-heuristic run after. */
+    failures += check(report.events <= seen, "progress and report agree");
+    printf("%llu event(s) in %.2fs\n", (unsigned long long)report.events, report.elapsed_secs);
+  }
+
+  if (failures == 0) {
+    printf("ok\n");
+  }
+  return failures == 0 ? 0 : 1;
+}
@@ -0,0 +1,11 @@
+#!/bin/sh
+# Builds the C API library, compiles link_test.c against it and runs it. Optional arguments
+# (input file, output directory, default .) add a real conversion.
+#   tests/capi/run.sh [INPUT [OUTPUT_DIR]]
+set -e
+cd "$(dirname "$0")/../.."
+cargo build --release -p arib2bdnxml-capi
+out=target/release
+cc -Wall -Wextra -std=c99 -Iinclude tests/capi/link_test.c -L"$out" -larib2bdnxml_capi \
+    -Wl,-rpath,"$PWD/$out" -o "$out/capi_link_test"
+"$out/capi_link_test" "$@"